@@ -11,6 +11,8 @@
 extern crate rebalancer;
 
 use std::env;
+use std::panic;
+use std::process;
 
 use rebalancer::libagent::Agent;
 use rebalancer::util;
@@ -38,9 +40,42 @@ fn usage() {
     println!(" The options are:");
     println!("  -h, --help       Display this information");
     println!("  -V, --version    Display the program's version number");
+    println!("  --validate-config <file>");
+    println!("                   Check a config file and exit");
+}
+
+// Check a configuration file, printing whatever is wrong with it, and return
+// the status that the agent should exit with.
+fn validate_config(path: &str) -> i32 {
+    match Agent::check_config(path) {
+        Ok(notices) => {
+            for notice in notices.iter() {
+                println!("{}", notice);
+            }
+            println!("{} is valid", path);
+            0
+        }
+        Err(errors) => {
+            println!("Invalid configuration:");
+            for e in errors.iter() {
+                println!("  {}", e);
+            }
+            1
+        }
+    }
 }
 
 fn main() {
+    // The workspace is built to unwind on panic, for the sake of the manager
+    // (see Cargo.toml), but nothing in the agent recovers from one.  A worker
+    // thread that went away would leave its assignment unfinished, so abort
+    // instead, as before, and let the agent be restarted.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        process::abort();
+    }));
+
     let _guard = util::init_global_logger(None);
     let args: Vec<String> = env::args().collect();
     let len = args.len();
@@ -56,6 +91,9 @@ fn main() {
                 print_version();
                 return;
             }
+            "--validate-config" if i + 1 < len => {
+                std::process::exit(validate_config(&args[i + 1]));
+            }
             _ => {
                 println!("{:?}: illegal option -- {:?}", args[0], args[i]);
                 usage();
@@ -87,14 +125,25 @@ pub mod agenttests {
     use rebalancer::agent_test_util::{
         self, get_progress, send_assignment_impl,
     };
-    use rebalancer::common::{ObjectSkippedReason, Task, TaskStatus};
+    use rebalancer::common::{
+        object_generation, ContentEncoding, ObjectSkippedReason, Task,
+        TaskAction, TaskAutopsy, TaskStatus,
+    };
     use rebalancer::libagent::{
-        process_task, router, AgentAssignmentState, AgentConfig, Assignment,
+        process_task, router, AgentAssignmentList, AgentAssignmentState,
+        AgentAssignmentStats, AgentConfig, Assignment,
+    };
+    use rebalancer::reaper::{ConfigReaper, Reaped, Reaper};
+    use rebalancer::sampler::SamplerReport;
+    use rebalancer::scrubber::{ConfigScrubber, Scrubbed, Scrubber};
+    use rebalancer::transfer::{
+        ConfigTransfer, HttpTransfer, PipelinedTransfer, Transfer,
+        TransferBackend,
     };
     use rebalancer::util;
     use reqwest::StatusCode;
     use std::path::Path;
-    use std::sync::Mutex;
+    use std::sync::{Arc, Mutex};
     use std::{mem, thread, time};
     use uuid::Uuid;
     use walkdir::WalkDir;
@@ -246,6 +295,14 @@ pub mod agenttests {
                 manta_storage_id: "localhost:8080".to_owned(),
             },
             status: TaskStatus::Pending,
+            content_length: None,
+            download: None,
+            autopsy: None,
+            action: TaskAction::Copy,
+            generation: None,
+            unchecked: false,
+            direct: false,
+            encodings: vec![],
         }
     }
 
@@ -339,15 +396,37 @@ pub mod agenttests {
         );
     }
 
+    // Test name:   Unchecked download.
+    // Description: Download files whose tasks have no usable checksum, which
+    //              the manager marks as unchecked.
+    // Expected:    TaskStatus for all tasks in the assignment should appear
+    //              as "Complete", since the checksum is not checked.
+    #[test]
+    fn unchecked_checksum() {
+        unit_test_init();
+        let mut assignment = create_assignment(MANTA_SRC_DIR);
+
+        for task in assignment.iter_mut() {
+            task.md5sum = String::new();
+            task.unchecked = true;
+        }
+        let uuid = send_assignment(&assignment);
+        monitor_assignment(&uuid, TaskStatus::Complete);
+    }
+
     // Test name:   Duplicate assignment
     // Description: First, successfully process an assignment.  Upon completion
     //              reissue the exact same assignment (including the uuid) to
-    //              the agent.  Any time that an agent receives an assignment
-    //              uuid that it knows it has already received -- regardless of
-    //              the state of that assignment (i.e. complete or not) -- it
-    //              the request should be rejected.
-    // Expected:    When we send the assignment for the second time, the server
-    //              should return a response of 409 (CONFLICT).
+    //              the agent.  This is what the manager does when it times out
+    //              waiting for the response to a post, so the agent should
+    //              recognize it as the assignment it already has and neither
+    //              reject it nor process it again.  Then reissue the uuid with
+    //              a different set of tasks, which should be rejected.
+    // Expected:    When we send the same assignment for the second time, the
+    //              server should return a response of 200 (OK) and the
+    //              assignment should still be complete.  When we send a
+    //              different assignment with the same uuid, the server should
+    //              return a response of 409 (CONFLICT).
     #[test]
     fn duplicate_assignment() {
         // Download a file once.
@@ -356,17 +435,50 @@ pub mod agenttests {
         let uuid = send_assignment(&assignment);
         monitor_assignment(&uuid, TaskStatus::Complete);
 
-        // Send the exact same assignment again and send it, although this time,
-        // we will reuse our first uuid. We expect to receive a status code of
-        // StatusCode::CONFLICT (409) from the server this time.
+        // Send the exact same assignment again, although this time, we will
+        // reuse our first uuid.
         send_assignment_impl(
             &assignment,
             &uuid,
             &TEST_SERVER.lock().unwrap(),
+            StatusCode::OK,
+        );
+        monitor_assignment(&uuid, TaskStatus::Complete);
+
+        // Change the tasks, but keep the uuid.  We expect to receive a status
+        // code of StatusCode::CONFLICT (409) from the server this time.
+        let mut different = assignment.clone();
+        different[0].object_id = "abc".to_string();
+        send_assignment_impl(
+            &different,
+            &uuid,
+            &TEST_SERVER.lock().unwrap(),
             StatusCode::CONFLICT,
         );
     }
 
+    // Test name:   Insufficient space
+    // Description: Post an assignment whose objects are larger than the free
+    //              space on the agent.
+    // Expected:    The agent turns the assignment down with a response of 507
+    //              (INSUFFICIENT_STORAGE) rather than accepting it.
+    #[test]
+    fn insufficient_space() {
+        unit_test_init();
+        let mut assignment = create_assignment(MANTA_SRC_DIR);
+        for task in assignment.iter_mut() {
+            task.content_length = Some(u64::max_value() / 2);
+        }
+
+        let uuid = Uuid::new_v4().to_hyphenated().to_string();
+        send_assignment_impl(
+            &assignment,
+            &uuid,
+            &TEST_SERVER.lock().unwrap(),
+            StatusCode::INSUFFICIENT_STORAGE,
+        );
+    }
+
     // Test name:   Delete assignment
     // Description: First generate an assignment and post it to the agent.  Once
     //              it has been observed that the assignment has been completely
@@ -388,4 +500,518 @@ pub mod agenttests {
         // Issue a request to the agent to delete it.
         agent_test_util::delete_assignment(&uuid, &TEST_SERVER.lock().unwrap());
     }
+
+    // Test name:   Delete object
+    // Description: Send a delete task for the agent's copy of an object with a
+    //              generation that does not match it, then one that does, then
+    //              the same delete task again.
+    // Expected:    The first task fails with "generation_mismatch" and leaves
+    //              the object in place, the second is "Complete" and removes
+    //              it, and the third fails with "agent_object_not_found".
+    #[test]
+    fn delete_object() {
+        unit_test_init();
+        let object_id = Uuid::new_v4().to_string();
+        let path = format!("/manta/rebalancer/{}", object_id);
+        std::fs::create_dir_all("/manta/rebalancer").unwrap();
+        std::fs::write(&path, b"extra copy").unwrap();
+
+        let task = Task {
+            object_id,
+            owner: "rebalancer".to_owned(),
+            md5sum: calculate_md5(&path),
+            source: MantaObjectShark {
+                datacenter: "dc".to_owned(),
+                manta_storage_id: "localhost:8080".to_owned(),
+            },
+            status: TaskStatus::Pending,
+            content_length: Some(10),
+            download: None,
+            autopsy: None,
+            action: TaskAction::Delete,
+            generation: Some(object_generation("not the md5sum", 10)),
+            unchecked: false,
+            direct: false,
+            encodings: vec![],
+        };
+
+        let uuid = send_assignment(&vec![task.clone()]);
+        monitor_assignment(
+            &uuid,
+            TaskStatus::Failed(ObjectSkippedReason::GenerationMismatch),
+        );
+        assert!(Path::new(&path).exists());
+
+        let task = Task {
+            generation: Some(object_generation(&calculate_md5(&path), 10)),
+            ..task
+        };
+
+        let uuid = send_assignment(&vec![task.clone()]);
+        monitor_assignment(&uuid, TaskStatus::Complete);
+        assert!(!Path::new(&path).exists());
+
+        let uuid = send_assignment(&vec![task]);
+        monitor_assignment(
+            &uuid,
+            TaskStatus::Failed(ObjectSkippedReason::AgentObjectNotFound),
+        );
+    }
+
+    // Test name:   Get config
+    // Description: Request the effective configuration of the agent.
+    // Expected:    The agent responds with 200 and a JSON representation of
+    //              its configuration, including any defaults.
+    #[test]
+    fn get_config() {
+        unit_test_init();
+        let response = TEST_SERVER
+            .lock()
+            .unwrap()
+            .client()
+            .get("http://localhost/config")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().unwrap();
+        let config: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(config["server"]["port"], 7878);
+        assert_eq!(config["server"]["workers_per_assignment"], 1);
+        assert!(config["server"]["max_workers_per_assignment"].is_null());
+        assert_eq!(config["server"]["zfs_quota_aware"], false);
+        assert_eq!(config["server"]["space_headroom_percent"], 10);
+        assert!(config["server"]["slow_task_secs"].is_null());
+        assert_eq!(config["sampler"]["sample_percent"], 0.0);
+        assert_eq!(config["transfer"]["backend"], "http");
+        assert_eq!(config["reaper"]["interval_secs"], 3600);
+    }
+
+    // Test name:   Get samples
+    // Description: Request the report of the sampler, which is disabled by
+    //              default.
+    // Expected:    The agent responds with 200 and a report of no samples.
+    #[test]
+    fn get_samples() {
+        unit_test_init();
+        let response = TEST_SERVER
+            .lock()
+            .unwrap()
+            .client()
+            .get("http://localhost/samples")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().unwrap();
+        let report: SamplerReport = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(report.pending, 0);
+        assert_eq!(report.matched + report.mismatched, 0);
+        assert!(report.mismatches.is_empty());
+    }
+
+    // Test name:   Pipelined transfer
+    // Description: Fetch every test object at once down a single connection
+    //              with the pipelined transfer backend, along with an object
+    //              that the source does not have.
+    // Expected:    Each object that the source has is fetched intact, and the
+    //              one that it does not have fails with a 404 without
+    //              getting in the way of those requested after it.
+    #[test]
+    fn pipelined_transfer() {
+        unit_test_init();
+        let config = ConfigTransfer {
+            backend: TransferBackend::Pipelined,
+            connections_per_source: 1,
+            pipeline_depth: 4,
+            ..Default::default()
+        };
+        let transfer = Arc::new(PipelinedTransfer::new(&config));
+        let dir = "/var/tmp/rebalancer/pipelined";
+        std::fs::create_dir_all(dir).unwrap();
+
+        let mut objects: Vec<(String, Option<String>)> =
+            create_assignment(MANTA_SRC_DIR)
+                .into_iter()
+                .map(|t| (t.object_id, Some(t.md5sum)))
+                .collect();
+        objects.insert(0, (Uuid::new_v4().to_string(), None));
+
+        let fetches: Vec<_> = objects
+            .into_iter()
+            .map(|(object_id, md5sum)| {
+                let transfer = Arc::clone(&transfer);
+                thread::spawn(move || {
+                    let uri = format!(
+                        "http://localhost:8080/rebalancer/{}",
+                        object_id
+                    );
+                    let path = format!("{}/{}", dir, object_id);
+                    let mut autopsy = TaskAutopsy::default();
+                    let res = transfer.fetch(&uri, &path, &[], &mut autopsy);
+                    (path, md5sum, res)
+                })
+            })
+            .collect();
+
+        for fetch in fetches {
+            let (path, md5sum, res) = fetch.join().unwrap();
+            match md5sum {
+                Some(md5sum) => {
+                    assert!(res.is_ok());
+                    assert_eq!(calculate_md5(&path), md5sum);
+                }
+                None => assert_eq!(
+                    res,
+                    Err(ObjectSkippedReason::HTTPStatusCode(404))
+                ),
+            }
+        }
+    }
+
+    // Test name:   Offered encodings
+    // Description: Fetch each test object with the http transfer backend,
+    //              offering the source every content encoding.  The test
+    //              server only compresses objects that it has a .gz of.
+    // Expected:    Each object is fetched intact, and its autopsy says that
+    //              it was sent as it is.
+    #[test]
+    fn offered_encodings() {
+        unit_test_init();
+        let transfer = HttpTransfer::new();
+        let encodings = [ContentEncoding::Zstd, ContentEncoding::Gzip];
+        let dir = "/var/tmp/rebalancer/encodings";
+        std::fs::create_dir_all(dir).unwrap();
+
+        for task in create_assignment(MANTA_SRC_DIR) {
+            let uri =
+                format!("http://localhost:8080/rebalancer/{}", task.object_id);
+            let path = format!("{}/{}", dir, task.object_id);
+            let mut autopsy = TaskAutopsy::default();
+
+            let bytes = transfer
+                .fetch(&uri, &path, &encodings, &mut autopsy)
+                .expect("fetch");
+            assert_eq!(calculate_md5(&path), task.md5sum);
+            assert_eq!(autopsy.content_encoding, None);
+            assert_eq!(autopsy.decoded_bytes, None);
+            assert_eq!(autopsy.bytes, bytes);
+        }
+    }
+
+    // Test name:   Injected download faults
+    // Description: With the faults feature, fetch the test objects through a
+    //              transfer that drops the first download and corrupts every
+    //              second one, with a delay on every download.
+    // Expected:    The first fetch fails for a transient reason, every second
+    //              one after it is fetched but does not match its checksum,
+    //              and the rest are fetched intact.  Each rule counts what
+    //              it saw.
+    #[cfg(feature = "faults")]
+    #[test]
+    fn injected_download_faults() {
+        use rebalancer::faults::{self, FaultInjector, FaultyTransfer};
+
+        unit_test_init();
+        let injector = Arc::new(FaultInjector::new());
+        let rules = faults::parse_rules(
+            r#"[
+                {"point": "agent_download", "fault": "delay", "delay_ms": 1},
+                {"point": "agent_download", "fault": "drop", "times": 1},
+                {"point": "agent_download", "fault": "corrupt", "every": 2}
+            ]"#,
+        )
+        .expect("fault rules");
+        injector.set(rules);
+
+        let transfer = FaultyTransfer::new(
+            Arc::new(HttpTransfer::new()),
+            Arc::clone(&injector),
+        );
+        let dir = "/var/tmp/rebalancer/faults";
+        std::fs::create_dir_all(dir).unwrap();
+
+        let tasks = create_assignment(MANTA_SRC_DIR);
+        assert!(tasks.len() > 2);
+        for (i, task) in tasks.iter().enumerate() {
+            let uri =
+                format!("http://localhost:8080/rebalancer/{}", task.object_id);
+            let path = format!("{}/{}", dir, task.object_id);
+            let mut autopsy = TaskAutopsy::default();
+            let res = transfer.fetch(&uri, &path, &[], &mut autopsy);
+
+            if i == 0 {
+                assert_eq!(res, Err(ObjectSkippedReason::NetworkError));
+            } else if i % 2 == 1 {
+                res.expect("fetch");
+                assert_ne!(calculate_md5(&path), task.md5sum);
+            } else {
+                res.expect("fetch");
+                assert_eq!(calculate_md5(&path), task.md5sum);
+            }
+        }
+
+        let status = injector.status();
+        let n = tasks.len() as u64;
+        assert_eq!(status.iter().map(|s| s.hits).collect::<Vec<_>>(), [n; 3]);
+        assert_eq!(
+            status.iter().map(|s| s.injected).collect::<Vec<_>>(),
+            [n, 1, n / 2]
+        );
+
+        // A fault that makes no sense where it is to be injected is refused.
+        assert!(faults::parse_rules(
+            r#"[{"point": "agent_download", "fault": "error"}]"#
+        )
+        .is_err());
+    }
+
+    // Test name:   Held assignments
+    // Description: Ask the agent for the assignments that it holds, without
+    //              a boot id, with the one that it gave, and with another.
+    // Expected:    The agent gives the same boot id each time, and says that
+    //              it has restarted unless it was given that boot id.
+    #[test]
+    fn held_assignments() {
+        unit_test_init();
+        let held = |query: &str| {
+            let response = TEST_SERVER
+                .lock()
+                .unwrap()
+                .client()
+                .get(format!("http://localhost/assignments{}", query))
+                .perform()
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.read_utf8_body().unwrap();
+            serde_json::from_str::<AgentAssignmentList>(&body).unwrap()
+        };
+
+        let list = held("");
+        assert!(list.restarted);
+        assert!(!list.boot_id.is_empty());
+
+        let since = held(&format!("?since={}", list.boot_id));
+        assert!(!since.restarted);
+        assert_eq!(since.boot_id, list.boot_id);
+
+        let other = held(&format!("?since={}", Uuid::new_v4()));
+        assert!(other.restarted);
+        assert_eq!(other.boot_id, list.boot_id);
+    }
+
+    #[test]
+    fn healthcheck() {
+        unit_test_init();
+        let response = TEST_SERVER
+            .lock()
+            .unwrap()
+            .client()
+            .get("http://localhost/ping")
+            .perform()
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = TEST_SERVER
+            .lock()
+            .unwrap()
+            .client()
+            .get("http://localhost/healthcheck")
+            .perform()
+            .unwrap();
+
+        // Whether the agent is healthy depends on the free space of the file
+        // system that the tests are run on.
+        let body = response.read_utf8_body().unwrap();
+        let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert!(health["healthy"].is_boolean());
+        assert_eq!(health["min_staging_free_mb"], 1024);
+    }
+
+    // Test name:   Reap
+    // Description: Reap a directory of completed assignments, one of which
+    //              has been reported, and a staging directory, with no
+    //              retention and no tolerance for stale downloads.
+    // Expected:    Only the reported assignment is removed from the completed
+    //              directory, and everything is removed from the staging
+    //              directory.
+    #[test]
+    fn reap() {
+        let base = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let finished = base.join("completed");
+        let staging = base.join("staging");
+        std::fs::create_dir_all(&finished).unwrap();
+        std::fs::create_dir_all(&staging).unwrap();
+
+        let reported = Uuid::new_v4().to_string();
+        let unreported = Uuid::new_v4().to_string();
+        std::fs::write(finished.join(&reported), b"done").unwrap();
+        std::fs::write(finished.join(&unreported), b"done").unwrap();
+        std::fs::write(staging.join("owner.object.1"), b"partial").unwrap();
+
+        let reaper = Reaper::new(
+            ConfigReaper {
+                retention_secs: 0,
+                stale_download_secs: 0,
+                ..ConfigReaper::default()
+            },
+            &finished.to_string_lossy(),
+            &staging.to_string_lossy(),
+        );
+        reaper.reported(&reported);
+
+        let (assignments, downloads) = reaper.reap(&None);
+        assert_eq!(assignments, Reaped { files: 1, bytes: 4 });
+        assert_eq!(downloads, Reaped { files: 1, bytes: 7 });
+        assert!(!finished.join(&reported).exists());
+        assert!(finished.join(&unreported).exists());
+
+        // Nothing more is reaped until the other assignment is reported.
+        assert_eq!(reaper.reap(&None).0, Reaped::default());
+        reaper.reported(&unreported);
+        assert_eq!(reaper.reap(&None).0.files, 1);
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    // Test name:   Scrub
+    // Description: Record three objects in the scrub ledger, then corrupt
+    //              one of them and remove another before scrubbing.
+    // Expected:    One object matches, one is reported as corrupt and the
+    //              removed one is dropped from the ledger, so that the next
+    //              pass only finds the other two.
+    #[test]
+    fn scrub() {
+        let base = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let owner = Uuid::new_v4().to_string();
+        std::fs::create_dir_all(base.join(&owner)).unwrap();
+
+        let scrubber = Scrubber::new(
+            ConfigScrubber {
+                interval_secs: 1,
+                max_iops: 0,
+                read_bytes: 3,
+            },
+            &base.to_string_lossy(),
+            &base.join("scrub.db").to_string_lossy(),
+            None,
+        );
+        assert!(scrubber.enabled());
+
+        let mut objects = vec![];
+        for _ in 0..3 {
+            let object_id = Uuid::new_v4().to_string();
+            let path = base.join(&owner).join(&object_id);
+            std::fs::write(&path, b"rebalancer").unwrap();
+
+            let task = Task {
+                owner: owner.clone(),
+                ..object_to_task(&path)
+            };
+            scrubber.record(&task);
+            objects.push(path);
+        }
+
+        std::fs::write(&objects[1], b"rebalanced").unwrap();
+        std::fs::remove_file(&objects[2]).unwrap();
+
+        let client = reqwest::Client::new();
+        let expected = Scrubbed {
+            matched: 1,
+            corrupt: 1,
+            missing: 1,
+            unreadable: 0,
+        };
+        assert_eq!(scrubber.scrub(&client, &None), expected);
+
+        let report = scrubber.report();
+        assert_eq!(report.passes, 1);
+        assert_eq!(report.corrupt_objects.len(), 1);
+        assert_eq!(report.corrupt_objects[0].size, 10);
+        assert!(objects[1].ends_with(&report.corrupt_objects[0].object_id));
+
+        let expected = Scrubbed {
+            missing: 0,
+            ..expected
+        };
+        assert_eq!(scrubber.scrub(&client, &None), expected);
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    // Test name:   Old assignment
+    // Description: Ask for a completed assignment that was saved by an agent
+    //              that predates task actions and content encodings, and so
+    //              migrations.
+    // Expected:    The assignment is brought up to date when it is opened,
+    //              with every migration recorded, and is reported as usual.
+    #[test]
+    fn old_assignment() {
+        let finished = Path::new("/var/tmp/rebalancer/completed");
+        std::fs::create_dir_all(finished).unwrap();
+
+        let uuid = Uuid::new_v4().to_string();
+        let path = finished.join(&uuid);
+        let mut stats = AgentAssignmentStats::new(1);
+        stats.state = AgentAssignmentState::Complete(None);
+        stats.complete = 1;
+
+        {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE tasks (
+                    object_id text primary key not null unique,
+                    owner text not null,
+                    md5sum text not null,
+                    datacenter text not null,
+                    manta_storage_id text not null,
+                    status text not null
+                );
+                CREATE TABLE stats (stats text not null);",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO tasks values (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    Uuid::new_v4().to_string(),
+                    "rebalancer",
+                    "1B2M2Y8AsgTpgAmY7PhCfg==",
+                    "dc",
+                    "localhost:8080",
+                    serde_json::to_vec(&TaskStatus::Complete).unwrap()
+                ],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO stats values (?1)",
+                rusqlite::params![serde_json::to_vec(&stats).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let assignment = get_progress(&uuid, &TEST_SERVER.lock().unwrap());
+        assert_eq!(assignment.stats.total, 1);
+        assert_eq!(assignment.stats.complete, 1);
+        assert!(assignment.encodings.is_empty());
+
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let applied: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM schema_migrations",
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(applied, 6);
+        assert!(conn.prepare("SELECT action, direct FROM tasks").is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
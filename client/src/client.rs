@@ -0,0 +1,278 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// A typed client for the manager's HTTP API.
+//
+// Each request returns a future that resolves to the type that the manager
+// answers with, so that a user of the client finds out that a response has
+// changed shape when it builds against a newer client, rather than when its
+// own parsing of the response quietly stops matching.  The futures are those
+// of reqwest's async client, and need a tokio runtime to be run on.
+
+use crate::compat::VersionInfo;
+use crate::error::{ApiError, RebalancerError};
+use crate::jobs::{ConfirmJobPayload, JobListEntry, JobPayload};
+use crate::status::{JobConfirmation, JobStatus};
+
+use std::fmt;
+
+use futures::{future, Future, Stream};
+use reqwest::r#async::{Client, Decoder, RequestBuilder, Response};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+
+pub static DEFAULT_URL: &str = "http://localhost";
+
+pub type ClientFuture<T> =
+    Box<dyn Future<Item = T, Error = ClientError> + Send>;
+
+#[derive(Debug)]
+pub enum ClientError {
+    // The request could not be sent, or its response could not be read.
+    Request(String),
+
+    // The manager refused the request, with why in the body of its response.
+    Api(u16, ApiError),
+
+    // The manager refused the request, with a body that is not an ApiError,
+    // as a manager that predates them answers with.
+    Status(u16, String),
+
+    // The response was not what the manager is expected to answer with.
+    Decode(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Request(msg) => write!(f, "Request failed: {}", msg),
+            ClientError::Api(status, error) => {
+                write!(f, "Server response: {}: {}", status, error)
+            }
+            ClientError::Status(status, msg) if msg.is_empty() => {
+                write!(f, "Server response: {}", status)
+            }
+            ClientError::Status(status, msg) => {
+                write!(f, "Server response: {}: {}", status, msg.trim())
+            }
+            ClientError::Decode(msg) => {
+                write!(f, "Failed to parse response: {}", msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl ClientError {
+    /// What went wrong, if it is known.  A request that could not be sent at
+    /// all is taken to be for a manager that could not be reached.
+    pub fn error(&self) -> Option<RebalancerError> {
+        match self {
+            ClientError::Request(_) => Some(RebalancerError::Unreachable),
+            ClientError::Api(_, error) => Some(error.error),
+            ClientError::Status(..) | ClientError::Decode(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        ClientError::Request(error.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct RebalancerClient {
+    base_url: String,
+    client: Client,
+}
+
+impl RebalancerClient {
+    /// A client of the manager at `base_url`, e.g. `http://localhost`.  There
+    /// is no timeout on requests: getting the status of a large job counts
+    /// its objects, which can take a while.
+    pub fn new(base_url: &str) -> Result<RebalancerClient, ClientError> {
+        let client = Client::builder().build()?;
+
+        Ok(RebalancerClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    /// The full URL of `path` on the manager.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// The version of the manager, and of the API that it speaks.  A manager
+    /// that predates GET /version is reported as `VersionInfo::legacy()`.
+    pub fn version(&self) -> ClientFuture<VersionInfo> {
+        let request = self.client.get(&self.url("/version"));
+
+        Box::new(send(request).and_then(|response| {
+            if response.status() == StatusCode::NOT_FOUND {
+                future::Either::A(future::ok(VersionInfo::legacy()))
+            } else {
+                future::Either::B(json(response))
+            }
+        }))
+    }
+
+    /// Create a job, returning its uuid.
+    pub fn create_job(&self, payload: &JobPayload) -> ClientFuture<String> {
+        let request = self.client.post(&self.url("/jobs")).json(payload);
+
+        Box::new(send(request).and_then(job_uuid))
+    }
+
+    /// Retry the job `uuid`, returning the uuid of the new job.
+    pub fn retry_job(&self, uuid: &str) -> ClientFuture<String> {
+        let path = format!("/jobs/{}/retry", uuid);
+        let request = self.client.post(&self.url(&path));
+
+        Box::new(send(request).and_then(job_uuid))
+    }
+
+    /// Every job that the manager knows of.
+    pub fn list_jobs(&self) -> ClientFuture<Vec<JobListEntry>> {
+        let request = self.client.get(&self.url("/jobs"));
+
+        Box::new(send(request).and_then(json))
+    }
+
+    /// The status of the job `uuid`.
+    pub fn get_job(&self, uuid: &str) -> ClientFuture<JobStatus> {
+        let path = format!("/jobs/{}", uuid);
+        let request = self.client.get(&self.url(&path));
+
+        Box::new(send(request).and_then(json))
+    }
+
+    /// Sign off the job `uuid`, which is awaiting confirmation.
+    pub fn confirm_job(
+        &self,
+        uuid: &str,
+        payload: &ConfirmJobPayload,
+    ) -> ClientFuture<JobConfirmation> {
+        let path = format!("/jobs/{}/confirm", uuid);
+        let request = self.client.post(&self.url(&path)).json(payload);
+
+        Box::new(send(request).and_then(json))
+    }
+}
+
+// Send `request`, and read the body of the response if the manager refused
+// it.  A 404 from GET /version is left for the caller, as it is how a manager
+// too old to have a version is recognized.
+fn send(
+    request: RequestBuilder,
+) -> impl Future<Item = Response, Error = ClientError> {
+    request
+        .send()
+        .map_err(ClientError::from)
+        .and_then(|response| {
+            let status = response.status();
+
+            if status.is_success() || status == StatusCode::NOT_FOUND {
+                return future::Either::A(future::ok(response));
+            }
+
+            future::Either::B(body(response).then(move |b| {
+                Err::<Response, _>(status_error(status, &b.unwrap_or_default()))
+            }))
+        })
+}
+
+// The error for a refused request, from the body of the manager's response.
+fn status_error(status: StatusCode, body: &[u8]) -> ClientError {
+    match serde_json::from_slice::<ApiError>(body) {
+        Ok(error) => ClientError::Api(status.as_u16(), error),
+        Err(_) => ClientError::Status(
+            status.as_u16(),
+            String::from_utf8_lossy(body).to_string(),
+        ),
+    }
+}
+
+fn body(
+    mut response: Response,
+) -> impl Future<Item = Vec<u8>, Error = ClientError> {
+    let body = std::mem::replace(response.body_mut(), Decoder::empty());
+
+    body.concat2()
+        .map(|chunk| chunk.to_vec())
+        .map_err(ClientError::from)
+}
+
+// The body of a successful response, parsed as JSON.
+fn json<T>(response: Response) -> impl Future<Item = T, Error = ClientError>
+where
+    T: DeserializeOwned,
+{
+    let status = response.status();
+
+    body(response).and_then(move |b| {
+        if !status.is_success() {
+            return Err(status_error(status, &b));
+        }
+
+        serde_json::from_slice(&b)
+            .map_err(|e| ClientError::Decode(e.to_string()))
+    })
+}
+
+// The uuid of a job that the manager has created, which it answers with on a
+// line of its own.
+fn job_uuid(
+    response: Response,
+) -> impl Future<Item = String, Error = ClientError> {
+    let status = response.status();
+
+    body(response).and_then(move |b| {
+        if !status.is_success() {
+            return Err(status_error(status, &b));
+        }
+
+        Ok(String::from_utf8_lossy(&b).trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_url() {
+        let client = RebalancerClient::new("http://localhost/").unwrap();
+        assert_eq!(client.url("/jobs"), "http://localhost/jobs");
+
+        let client = RebalancerClient::new(DEFAULT_URL).unwrap();
+        assert_eq!(client.url("/version"), "http://localhost/version");
+    }
+
+    #[test]
+    fn client_status_error() {
+        let body = br#"{"error": "not_found", "handling": "skip",
+                        "message": "Could not find job UUID: 1234"}"#;
+        let error = status_error(StatusCode::NOT_FOUND, body);
+        assert_eq!(error.error(), Some(RebalancerError::NotFound));
+        assert_eq!(
+            error.to_string(),
+            "Server response: 404: Could not find job UUID: 1234 (not_found)"
+        );
+
+        // Older managers answer with plain text.
+        let error = status_error(StatusCode::BAD_REQUEST, b"Invalid UUID");
+        assert_eq!(error.error(), None);
+        assert_eq!(error.to_string(), "Server response: 400: Invalid UUID");
+    }
+}
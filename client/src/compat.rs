@@ -0,0 +1,160 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Compatibility between rebalancer-adm and the manager.
+//
+// rebalancer-adm is often run from a different image than the manager that it
+// talks to, and the two can drift apart.  Rather than leave an operator to
+// make sense of whatever an old client makes of a new manager's responses (or
+// the other way around), the manager reports, with GET /version:
+//
+//  * api_version: bumped for any change to the API that existing clients can
+//    not cope with, such as a route being removed or a field changing type.
+//    A client refuses to talk to a manager with a different API version.
+//  * schema_revision: bumped for changes that existing clients can cope with,
+//    such as a new field in a response or a new route.  A client warns when
+//    the manager's revision differs from its own, since either the client
+//    will not know about some of what the manager reports, or the manager
+//    will not support some of what the client offers.
+//
+// Managers older than GET /version are treated as API version 1, schema
+// revision 0.
+
+use serde::{Deserialize, Serialize};
+
+pub static API_VERSION: u32 = 1;
+pub static SCHEMA_REVISION: u32 = 39;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct VersionInfo {
+    // The version string of the build, as in the Server header.
+    pub version: String,
+    pub api_version: u32,
+    pub schema_revision: u32,
+}
+
+impl VersionInfo {
+    pub fn new(version: &str) -> VersionInfo {
+        VersionInfo {
+            version: version.to_string(),
+            api_version: API_VERSION,
+            schema_revision: SCHEMA_REVISION,
+        }
+    }
+
+    /// The version reported for a manager that predates GET /version.
+    pub fn legacy() -> VersionInfo {
+        VersionInfo {
+            version: String::from("unknown"),
+            api_version: 1,
+            schema_revision: 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Compatibility {
+    Compatible,
+    Warning(String),
+    Incompatible(String),
+}
+
+/// Whether a client of `client`'s version can work with a manager of
+/// `manager`'s.
+pub fn check(client: &VersionInfo, manager: &VersionInfo) -> Compatibility {
+    if client.api_version != manager.api_version {
+        let upgrade = if client.api_version < manager.api_version {
+            "rebalancer-adm"
+        } else {
+            "the manager"
+        };
+
+        return Compatibility::Incompatible(format!(
+            "rebalancer-adm {} (API version {}) can not be used with manager \
+             {} (API version {}); upgrade {}",
+            client.version,
+            client.api_version,
+            manager.version,
+            manager.api_version,
+            upgrade
+        ));
+    }
+
+    if client.schema_revision < manager.schema_revision {
+        return Compatibility::Warning(format!(
+            "the manager ({}, schema revision {}) is newer than rebalancer-adm \
+             ({}, schema revision {}); some of what the manager reports may \
+             not be shown",
+            manager.version,
+            manager.schema_revision,
+            client.version,
+            client.schema_revision
+        ));
+    }
+
+    if client.schema_revision > manager.schema_revision {
+        return Compatibility::Warning(format!(
+            "the manager ({}, schema revision {}) is older than rebalancer-adm \
+             ({}, schema revision {}); some commands or options may not be \
+             supported",
+            manager.version,
+            manager.schema_revision,
+            client.version,
+            client.schema_revision
+        ));
+    }
+
+    Compatibility::Compatible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(api_version: u32, schema_revision: u32) -> VersionInfo {
+        VersionInfo {
+            version: String::from("test"),
+            api_version,
+            schema_revision,
+        }
+    }
+
+    #[test]
+    fn compat_check() {
+        assert_eq!(
+            check(&version(1, 1), &version(1, 1)),
+            Compatibility::Compatible
+        );
+
+        match check(&version(1, 1), &version(2, 1)) {
+            Compatibility::Incompatible(msg) => {
+                assert!(msg.ends_with("upgrade rebalancer-adm"))
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match check(&version(2, 3), &version(1, 3)) {
+            Compatibility::Incompatible(msg) => {
+                assert!(msg.ends_with("upgrade the manager"))
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match check(&version(1, 1), &version(1, 2)) {
+            Compatibility::Warning(msg) => assert!(msg.contains("is newer")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match check(&version(1, 1), &VersionInfo::legacy()) {
+            Compatibility::Warning(msg) => assert!(msg.contains("is older")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
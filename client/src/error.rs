@@ -0,0 +1,257 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The errors that the rebalancer reports, by a code that does not change.
+//
+// The manager and agent each had their own ways of saying what went wrong:
+// the reason that a task failed or an object was skipped, the error recorded
+// against an object, the plain text of the body of a refused request, and
+// whatever string happened to label the error metric.  Something that had to
+// act on an error (retry it, move on, or page an operator) had to match on
+// those strings, and any change of wording broke it.
+//
+// Each of those now maps onto a RebalancerError, whose code (its snake_case
+// name, e.g. `insufficient_space`) is never renamed or reused, along with what
+// is to be done about it.  The manager answers every refused request with an
+// ApiError, the `error_count` metric of the manager and the agent is labeled
+// with the code, and the skipped objects of a job are reported with the code
+// of their reason.  The reasons themselves are kept, since they say more:
+// many reasons share a code.  A code that a client does not know of, from a
+// newer manager, is read as `unknown`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(
+    Clone, Copy, Debug, Display, EnumIter, EnumString, Eq, Hash, PartialEq,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum RebalancerError {
+    // The request was malformed, or asked for something that can not be done.
+    InvalidRequest,
+
+    // What the request was for does not exist.
+    NotFound,
+
+    // The request conflicts with something already done, e.g. a job that is
+    // already running or an assignment that is already complete.
+    Conflict,
+
+    // The manager or agent can not take the request at the moment, e.g.
+    // because it is shutting down.
+    Unavailable,
+
+    // A service that was needed could not be reached: an agent, storinfo,
+    // moray or a storage node.
+    Unreachable,
+
+    // An agent, or the object on it, is busy.
+    Busy,
+
+    // There was no room for the object on its destination.
+    InsufficientSpace,
+
+    // The agent does not support what it was asked to do.
+    Unsupported,
+
+    // An assignment was lost, rejected, or did not match the agent's copy of
+    // it.
+    AssignmentLost,
+
+    // An operator cancelled what was to be done.
+    Cancelled,
+
+    // A job stopped early because its circuit breaker paused it.
+    Paused,
+
+    // A job reached its limit on the number of objects.
+    LimitReached,
+
+    // The object is not where it was expected to be.
+    ObjectMissing,
+
+    // The copy of the object is not of the generation that it was expected
+    // to be.
+    GenerationMismatch,
+
+    // There was nowhere to put the object without losing durability or
+    // failure domains.
+    NoDestination,
+
+    // There was nowhere to get the object from.
+    NoSource,
+
+    // The source of the object answered with an error that it expects to
+    // clear up.
+    SourceError,
+
+    // The source of the object refused to give it up.
+    SourceRefused,
+
+    // The object's metadata is missing something, or is malformed.
+    BadMetadata,
+
+    // The object's data does not match its checksum.
+    ChecksumMismatch,
+
+    // The copy on the destination could not be confirmed.
+    Unverified,
+
+    // The object's metadata could not be updated.
+    MetadataUpdateFailed,
+
+    // A local filesystem error.
+    Filesystem,
+
+    // A database error.
+    Database,
+
+    // Anything else that went wrong.
+    Internal,
+
+    // A code that this version does not know of.
+    Unknown,
+}
+
+/// What is to be done about an error.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Display, Eq, PartialEq, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ErrorHandling {
+    // The same thing might succeed if it is tried again later.
+    Retry,
+
+    // The same thing will not succeed, but nothing else is amiss.
+    Skip,
+
+    // Something is wrong that an operator needs to look at.
+    Page,
+}
+
+impl RebalancerError {
+    pub fn handling(self) -> ErrorHandling {
+        match self {
+            RebalancerError::Unavailable
+            | RebalancerError::Unreachable
+            | RebalancerError::Busy
+            | RebalancerError::InsufficientSpace
+            | RebalancerError::AssignmentLost
+            | RebalancerError::SourceError
+            | RebalancerError::Unverified
+            | RebalancerError::MetadataUpdateFailed => ErrorHandling::Retry,
+
+            RebalancerError::InvalidRequest
+            | RebalancerError::NotFound
+            | RebalancerError::Conflict
+            | RebalancerError::Unsupported
+            | RebalancerError::Cancelled
+            | RebalancerError::LimitReached
+            | RebalancerError::ObjectMissing
+            | RebalancerError::GenerationMismatch
+            | RebalancerError::NoDestination
+            | RebalancerError::NoSource
+            | RebalancerError::SourceRefused
+            | RebalancerError::BadMetadata => ErrorHandling::Skip,
+
+            RebalancerError::Paused
+            | RebalancerError::ChecksumMismatch
+            | RebalancerError::Filesystem
+            | RebalancerError::Database
+            | RebalancerError::Internal
+            | RebalancerError::Unknown => ErrorHandling::Page,
+        }
+    }
+}
+
+impl Serialize for RebalancerError {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RebalancerError {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(d)?;
+
+        Ok(
+            RebalancerError::from_str(&code)
+                .unwrap_or(RebalancerError::Unknown),
+        )
+    }
+}
+
+/// The body of the manager's response to a request that it refused.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ApiError {
+    pub error: RebalancerError,
+    pub handling: ErrorHandling,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new<S: Into<String>>(error: RebalancerError, message: S) -> Self {
+        ApiError {
+            error,
+            handling: error.handling(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message.trim(), self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn error_codes() {
+        // Every code reads back as itself.
+        for error in RebalancerError::iter() {
+            let json = serde_json::to_string(&error).unwrap();
+            assert_eq!(json, format!("\"{}\"", error));
+            assert_eq!(
+                serde_json::from_str::<RebalancerError>(&json).unwrap(),
+                error
+            );
+        }
+
+        let body = ApiError::new(
+            RebalancerError::InsufficientSpace,
+            "No room on 1.stor",
+        );
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            serde_json::json!({
+                "error": "insufficient_space",
+                "handling": "retry",
+                "message": "No room on 1.stor",
+            })
+        );
+
+        // A code from a newer manager is not an error in itself.
+        let body: ApiError = serde_json::from_str(
+            r#"{"error": "tachyon_storm", "handling": "page",
+                "message": "Too many tachyons"}"#,
+        )
+        .unwrap();
+        assert_eq!(body.error, RebalancerError::Unknown);
+        assert_eq!(body.handling, ErrorHandling::Page);
+    }
+}
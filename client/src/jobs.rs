@@ -0,0 +1,745 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The jobs that can be asked of the manager, and what it says about them.
+
+use std::str::FromStr;
+
+use diesel::sql_types;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[cfg(feature = "postgres")]
+use diesel::deserialize::{self, FromSql};
+#[cfg(feature = "postgres")]
+use diesel::pg::{Pg, PgValue};
+#[cfg(feature = "postgres")]
+use diesel::serialize::{self, IsNull, Output, ToSql};
+#[cfg(feature = "postgres")]
+use std::io::Write;
+
+/// The JobPayload is an enum with variants of JobActions.  A properly
+/// formatted JobPayload submitted from the client in JSON form looks like:
+///
+/// ```json
+/// {
+///     "action": <job action (String)>,
+///     "params": { <job action specific params > }
+/// }
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "action", content = "params")]
+#[serde(rename_all = "lowercase")]
+pub enum JobPayload {
+    Evacuate(EvacuateJobPayload),
+    #[serde(rename = "create-copy")]
+    CreateCopy(CreateCopyJobPayload),
+    #[serde(rename = "remove-copy")]
+    RemoveCopy(RemoveCopyJobPayload),
+    Verify(VerifyJobPayload),
+    Rollback(RollbackJobPayload),
+}
+
+impl JobPayload {
+    /// Returns true if the job hands its work to agents, and so can not be
+    /// run by a manager in agent-less verification mode.  A rollback job does
+    /// not need agents, but it does change metadata, which a manager in that
+    /// mode is not there to do.
+    pub fn needs_agents(&self) -> bool {
+        match self {
+            JobPayload::Evacuate(_)
+            | JobPayload::CreateCopy(_)
+            | JobPayload::RemoveCopy(_)
+            | JobPayload::Rollback(_) => true,
+            JobPayload::Verify(_) => false,
+        }
+    }
+
+    /// Check the parameters of the job, as the manager does before creating
+    /// it.  This lets rebalancer-adm refuse a bad job before sending it.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            JobPayload::Evacuate(payload) => payload.validate(),
+            JobPayload::CreateCopy(payload) => payload.validate(),
+            JobPayload::RemoveCopy(payload) => payload.validate(),
+            JobPayload::Rollback(payload) => payload.validate(),
+            JobPayload::Verify(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct EvacuateJobPayload {
+    pub from_shark: String,
+    pub max_objects: Option<u32>,
+
+    // Overrides the service wide max_fill_percentage for the duration of
+    // this job only.
+    pub max_fill_percentage: Option<u32>,
+
+    // Never take a destination shark beyond this utilization, whatever the
+    // max_fill_percentage in effect.  Unlike max_fill_percentage, this is
+    // kept for a retry or resumed job.
+    pub max_dest_utilization_percent: Option<u32>,
+
+    // Where the job is placed in the job queue if it can not be started
+    // right away.  Defaults to JobPriority::Normal.
+    pub priority: Option<JobPriority>,
+
+    // Read objects that have no other copy from the shark being evacuated,
+    // a few at a time, rather than skipping them.
+    pub slow_source: Option<bool>,
+
+    // Leave the job awaiting confirmation by an operator, rather than
+    // complete, once it has finished.  Defaults to
+    // options.require_confirmation.
+    pub require_confirmation: Option<bool>,
+
+    // Check each copy on its destination before updating the object's
+    // metadata to point at it.  Defaults to options.verify_before_update.
+    pub verify_before_update: Option<bool>,
+
+    // Fetch objects from the agents on their source sharks rather than from
+    // the sharks' web servers, wherever those agents serve them.  Defaults to
+    // options.direct_pull.
+    pub direct_pull: Option<bool>,
+
+    // Take the destination sharks from this file on the manager rather than
+    // from storinfo.  Defaults to sharks_file.
+    pub sharks_file: Option<String>,
+
+    // Record why each object was given to its destination, or not, in the
+    // job's placement trace.  Defaults to options.trace_placement.
+    pub trace_placement: Option<bool>,
+
+    // What to do with objects whose metadata has a missing or malformed
+    // checksum.  Defaults to options.checksum_policy.
+    pub checksum_policy: Option<ChecksumPolicy>,
+
+    // Only scan the configured shards from min_shard to max_shard, and of
+    // those only the ones in shards if any are listed.  By default every
+    // configured shard is scanned.
+    pub min_shard: Option<u32>,
+    pub max_shard: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shards: Vec<u32>,
+
+    // The number of shards scanned at once.  Defaults to
+    // options.max_md_read_threads.
+    pub scan_parallelism: Option<u32>,
+
+    // Only hand work to agents within this daily window.  By default the
+    // job runs whenever it is started.  Like max_dest_utilization_percent,
+    // this is kept for a retry or resumed job.
+    pub schedule: Option<JobSchedule>,
+
+    // Only move the objects that match this filter.  By default every object
+    // found on the shark is moved.  Like schedule, this is kept for a retry
+    // or resumed job.
+    pub filter: Option<ObjectFilter>,
+
+    // Where the job finds the objects on the shark.  By default the shards
+    // of the metadata tier are scanned by sharkspotter.  Like filter, this
+    // is kept for a retry or resumed job.
+    pub source: Option<ObjectSource>,
+
+    // If another evacuate job of from_shark is queued or running, queue this
+    // one to start once that one has finished, rather than refusing it.
+    pub queue_if_active: Option<bool>,
+}
+
+/// Add a copy of the objects on `shark` to other sharks, to raise their
+/// durability.  The copy on `shark` stays where it is.
+#[derive(Serialize, Deserialize, Default)]
+pub struct CreateCopyJobPayload {
+    pub shark: String,
+
+    // Only copy objects that have fewer than this many copies.  If this is
+    // not given every object on the shark is copied.
+    pub min_copies: Option<u32>,
+
+    pub max_objects: Option<u32>,
+
+    // As for EvacuateJobPayload.
+    pub max_fill_percentage: Option<u32>,
+    pub max_dest_utilization_percent: Option<u32>,
+    pub priority: Option<JobPriority>,
+    pub require_confirmation: Option<bool>,
+    pub verify_before_update: Option<bool>,
+    pub direct_pull: Option<bool>,
+    pub sharks_file: Option<String>,
+    pub trace_placement: Option<bool>,
+    pub checksum_policy: Option<ChecksumPolicy>,
+    pub min_shard: Option<u32>,
+    pub max_shard: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shards: Vec<u32>,
+    pub scan_parallelism: Option<u32>,
+    pub schedule: Option<JobSchedule>,
+    pub filter: Option<ObjectFilter>,
+    pub source: Option<ObjectSource>,
+}
+
+/// Remove the copy on `shark` of each object that has enough copies on other
+/// sharks, e.g. to clean up extra copies left behind by a partial rebalance.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RemoveCopyJobPayload {
+    pub shark: String,
+
+    // Only remove the copies of objects that have at least this many copies
+    // on other sharks.
+    pub min_copies: u32,
+
+    pub max_objects: Option<u32>,
+
+    // As for EvacuateJobPayload.
+    pub priority: Option<JobPriority>,
+    pub require_confirmation: Option<bool>,
+    pub min_shard: Option<u32>,
+    pub max_shard: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shards: Vec<u32>,
+    pub scan_parallelism: Option<u32>,
+    pub schedule: Option<JobSchedule>,
+    pub filter: Option<ObjectFilter>,
+    pub source: Option<ObjectSource>,
+}
+
+/// Check that `shark` holds the objects that the metadata tier says it does,
+/// by asking the shark itself rather than an agent.  See the verify module.
+#[derive(Serialize, Deserialize, Default)]
+pub struct VerifyJobPayload {
+    pub shark: String,
+    pub max_objects: Option<u32>,
+
+    // As for EvacuateJobPayload.
+    pub priority: Option<JobPriority>,
+}
+
+/// Put back the sharks that objects had before the job `job_id` changed their
+/// metadata.  See the rollback module.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RollbackJobPayload {
+    pub job_id: String,
+
+    // Only roll back the objects that the job put on this shark.
+    pub dest_shark: Option<String>,
+
+    // Only roll back these objects.  By default every object that the job
+    // changed is rolled back.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub object_ids: Vec<String>,
+
+    pub max_objects: Option<u32>,
+
+    // As for EvacuateJobPayload.
+    pub priority: Option<JobPriority>,
+    pub require_confirmation: Option<bool>,
+}
+
+/// Jobs of a higher priority are started before any queued jobs of a lower
+/// priority, regardless of the order in which they were created.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum JobPriority {
+    Normal,
+    Urgent,
+}
+
+impl Default for JobPriority {
+    fn default() -> Self {
+        JobPriority::Normal
+    }
+}
+
+/// A daily window within which a job hands work to agents, e.g. from
+/// `"00:00"` to `"06:00"`.  The window ends just before `end`, and one whose
+/// end is earlier than its start runs past midnight.  Times are taken to be
+/// at `utc_offset` (`"+HH:MM"` or `"-HH:MM"`), or in UTC if it is not given.
+/// See the manager's jobs::schedule module.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobSchedule {
+    pub start: String,
+    pub end: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<String>,
+}
+
+// Minutes after midnight of a time in the form HH:MM.
+fn parse_time_of_day(name: &str, time: &str) -> Result<u32, String> {
+    let invalid =
+        || format!("{} must be of the form HH:MM, got {}", name, time);
+    let two_digits =
+        |s: &str| s.len() == 2 && s.bytes().all(|b| b.is_ascii_digit());
+    let mut parts = time.splitn(2, ':');
+    let (hours, minutes) = match (parts.next(), parts.next()) {
+        (Some(h), Some(m)) if two_digits(h) && two_digits(m) => (h, m),
+        _ => return Err(invalid()),
+    };
+    let hours = hours.parse::<u32>().map_err(|_| invalid())?;
+    let minutes = minutes.parse::<u32>().map_err(|_| invalid())?;
+
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+
+    Ok(hours * 60 + minutes)
+}
+
+// The furthest that any timezone is from UTC.
+static MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+impl JobSchedule {
+    /// The start and end of the window in minutes after midnight, and the
+    /// offset from UTC that they are at in minutes east of UTC.
+    pub fn minutes(&self) -> Result<(u32, u32, i32), String> {
+        let start = parse_time_of_day("schedule start", &self.start)?;
+        let end = parse_time_of_day("schedule end", &self.end)?;
+
+        let offset = match &self.utc_offset {
+            None => 0,
+            Some(offset) => {
+                let invalid = || {
+                    format!(
+                        "schedule utc_offset must be of the form +HH:MM or \
+                         -HH:MM, got {}",
+                        offset
+                    )
+                };
+                let sign = match offset.chars().next() {
+                    Some('+') => 1,
+                    Some('-') => -1,
+                    _ => return Err(invalid()),
+                };
+                let minutes =
+                    parse_time_of_day("schedule utc_offset", &offset[1..])
+                        .map_err(|_| invalid())? as i32;
+
+                if minutes > MAX_UTC_OFFSET_MINUTES {
+                    return Err(invalid());
+                }
+                sign * minutes
+            }
+        };
+
+        Ok((start, end, offset))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let (start, end, _) = self.minutes()?;
+
+        if start == end {
+            return Err(format!(
+                "schedule start and end must differ, got {} for both",
+                self.start
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn validate_schedule(schedule: &Option<JobSchedule>) -> Result<(), String> {
+    match schedule {
+        Some(s) => s.validate(),
+        None => Ok(()),
+    }
+}
+
+/// Which of the objects that a job finds it works on, e.g. only those of one
+/// owner, or only the largest.  An object must match every condition that is
+/// given.  Sizes are in bytes and times in milliseconds since the epoch, and
+/// each range includes both of its ends.  See the manager's jobs::filter
+/// module.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ObjectFilter {
+    // The owner_uuid of the objects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+
+    // When the objects were created, as recorded in their mtime.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<i64>,
+}
+
+impl ObjectFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(owner) = &self.owner {
+            Uuid::from_str(owner).map_err(|e| {
+                format!("Invalid filter owner {}: {}", owner, e)
+            })?;
+        }
+
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                return Err(format!(
+                    "filter min_size {} is greater than max_size {}",
+                    min, max
+                ));
+            }
+        }
+
+        if let (Some(after), Some(before)) =
+            (self.created_after, self.created_before)
+        {
+            if after > before {
+                return Err(format!(
+                    "filter created_after {} is later than created_before {}",
+                    after, before
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_filter(filter: &Option<ObjectFilter>) -> Result<(), String> {
+    match filter {
+        Some(f) => f.validate(),
+        None => Ok(()),
+    }
+}
+
+/// Where a job finds the objects that it works on.  A `file` is a snapshot
+/// of the metadata tier on the manager: a file of newline delimited JSON
+/// records in the form that sharkspotter reports them, each with the
+/// `shard` that it is on, its `etag` and its `manta_value`.  It stands in
+/// for the metadata tier only when objects are found, so it is meant for
+/// load tests and dry runs.  See the manager's jobs::snapshot module.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ObjectSource {
+    Sharkspotter,
+    File { path: String },
+}
+
+impl Default for ObjectSource {
+    fn default() -> Self {
+        ObjectSource::Sharkspotter
+    }
+}
+
+impl ObjectSource {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ObjectSource::Sharkspotter => Ok(()),
+            ObjectSource::File { path } if !path.starts_with('/') => Err(
+                format!("source path must be an absolute path, got {}", path),
+            ),
+            ObjectSource::File { .. } => Ok(()),
+        }
+    }
+}
+
+fn validate_source(source: &Option<ObjectSource>) -> Result<(), String> {
+    match source {
+        Some(s) => s.validate(),
+        None => Ok(()),
+    }
+}
+
+/// Check that the parameter `name`, if given, is a percentage between 1 and
+/// 100.
+pub fn validate_percentage(
+    name: &str,
+    value: Option<u32>,
+) -> Result<(), String> {
+    match value {
+        Some(pct) if pct < 1 || pct > 100 => {
+            Err(format!("{} must be between 1 and 100, got {}", name, pct))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The scan of the metadata tier is done by sharkspotter, which will not run
+// more than this many threads.
+static MAX_SCAN_PARALLELISM: u32 = 100;
+
+// Whether the shards are configured is checked by Config::select_shards().
+fn validate_shard_selection(
+    min_shard: Option<u32>,
+    max_shard: Option<u32>,
+    scan_parallelism: Option<u32>,
+) -> Result<(), String> {
+    if let (Some(min), Some(max)) = (min_shard, max_shard) {
+        if min > max {
+            return Err(format!(
+                "min_shard {} is greater than max_shard {}",
+                min, max
+            ));
+        }
+    }
+
+    match scan_parallelism {
+        Some(p) if p < 1 || p > MAX_SCAN_PARALLELISM => Err(format!(
+            "scan_parallelism must be between 1 and {}, got {}",
+            MAX_SCAN_PARALLELISM, p
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl EvacuateJobPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_percentage("max_fill_percentage", self.max_fill_percentage)?;
+        validate_percentage(
+            "max_dest_utilization_percent",
+            self.max_dest_utilization_percent,
+        )?;
+        validate_shard_selection(
+            self.min_shard,
+            self.max_shard,
+            self.scan_parallelism,
+        )?;
+        validate_schedule(&self.schedule)?;
+        validate_filter(&self.filter)?;
+        validate_source(&self.source)
+    }
+}
+
+impl CreateCopyJobPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_percentage("max_fill_percentage", self.max_fill_percentage)?;
+        validate_percentage(
+            "max_dest_utilization_percent",
+            self.max_dest_utilization_percent,
+        )?;
+        validate_shard_selection(
+            self.min_shard,
+            self.max_shard,
+            self.scan_parallelism,
+        )?;
+        validate_schedule(&self.schedule)?;
+        validate_filter(&self.filter)?;
+        validate_source(&self.source)?;
+
+        // Every object on the shark has at least one copy already.
+        if let Some(min) = self.min_copies {
+            if min < 2 {
+                return Err(format!(
+                    "min_copies must be at least 2, got {}",
+                    min
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RollbackJobPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        Uuid::from_str(&self.job_id)
+            .map(|_| ())
+            .map_err(|e| format!("Invalid job_id {}: {}", self.job_id, e))
+    }
+}
+
+impl RemoveCopyJobPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        // Removing the last copy of an object would lose it.
+        if self.min_copies < 1 {
+            return Err(format!(
+                "min_copies must be at least 1, got {}",
+                self.min_copies
+            ));
+        }
+        validate_shard_selection(
+            self.min_shard,
+            self.max_shard,
+            self.scan_parallelism,
+        )?;
+        validate_schedule(&self.schedule)?;
+        validate_filter(&self.filter)?;
+        validate_source(&self.source)
+    }
+}
+
+/// What evacuate and create-copy jobs do with an object whose metadata has a
+/// missing or malformed contentMD5.  See the manager's jobs::checksum
+/// module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumPolicy {
+    /// Mark the object as an error.
+    Fail,
+
+    /// Skip the object.
+    Skip,
+
+    /// Copy the object without checking it, and record a warning.
+    Copy,
+}
+
+/// The body of a request to confirm a job.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ConfirmJobPayload {
+    pub confirmed_by: String,
+    pub comment: Option<String>,
+}
+
+#[sql_type = "sql_types::Text"]
+#[derive(
+    AsExpression,
+    Clone,
+    Debug,
+    Deserialize,
+    Display,
+    EnumString,
+    EnumVariantNames,
+    FromSqlRow,
+    PartialEq,
+    Serialize,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum JobState {
+    Init,
+    Setup,
+    Queued,
+    Running,
+    Stopped,
+    Interrupted,
+    Resumed,
+    Paused,
+    AwaitingConfirmation,
+    Complete,
+    Failed,
+}
+
+#[cfg(feature = "postgres")]
+impl ToSql<sql_types::Text, Pg> for JobState {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        let action = self.to_string();
+        out.write_all(action.as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl FromSql<sql_types::Text, Pg> for JobState {
+    fn from_sql(bytes: Option<PgValue<'_>>) -> deserialize::Result<Self> {
+        let t: PgValue = not_none!(bytes);
+        let t_str = String::from_utf8_lossy(t.as_bytes());
+        Self::from_str(&t_str).map_err(std::convert::Into::into)
+    }
+}
+
+/// A job, as listed by GET /jobs.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobListEntry {
+    pub id: String,
+
+    // The kind of job, e.g. `Evacuate` or `CreateCopy`.
+    pub action: String,
+    pub state: JobState,
+
+    // Milliseconds since the epoch at which the job was created, or 0 for
+    // jobs created before this was recorded.
+    #[serde(default)]
+    pub created: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_payload_format() {
+        let payload = JobPayload::CreateCopy(CreateCopyJobPayload {
+            shark: String::from("1.stor.domain"),
+            min_copies: Some(2),
+            priority: Some(JobPriority::Urgent),
+            checksum_policy: Some(ChecksumPolicy::Copy),
+            ..Default::default()
+        });
+        let value = serde_json::to_value(&payload).expect("serialize");
+
+        assert_eq!(value["action"], "create-copy");
+        assert_eq!(value["params"]["shark"], "1.stor.domain");
+        assert_eq!(value["params"]["min_copies"], 2);
+        assert_eq!(value["params"]["priority"], "urgent");
+        assert_eq!(value["params"]["checksum_policy"], "copy");
+        assert!(value["params"].get("shards").is_none());
+
+        let payload: EvacuateJobPayload =
+            serde_json::from_value(serde_json::json!({
+                "from_shark": "1.stor.domain",
+                "source": { "type": "file", "path": "/var/tmp/objects.json" },
+            }))
+            .expect("deserialize");
+        assert_eq!(
+            payload.source,
+            Some(ObjectSource::File {
+                path: String::from("/var/tmp/objects.json")
+            })
+        );
+        assert!(payload.validate().is_ok());
+
+        let payload = EvacuateJobPayload {
+            source: Some(ObjectSource::File {
+                path: String::from("objects.json"),
+            }),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_err());
+
+        let entry: JobListEntry = serde_json::from_value(serde_json::json!({
+            "id": "8e0c2b5a-7a0f-4e3b-a2b4-3c5d0f5e8c11",
+            "action": "Evacuate",
+            "state": "AwaitingConfirmation",
+        }))
+        .expect("deserialize");
+        assert_eq!(entry.state, JobState::AwaitingConfirmation);
+        assert_eq!(entry.created, 0);
+    }
+
+    #[test]
+    fn job_schedule_validate() {
+        let schedule =
+            |start: &str, end: &str, offset: Option<&str>| JobSchedule {
+                start: start.to_string(),
+                end: end.to_string(),
+                utc_offset: offset.map(String::from),
+            };
+
+        assert_eq!(schedule("00:00", "06:00", None).minutes(), Ok((0, 360, 0)));
+        assert_eq!(
+            schedule("22:30", "05:15", Some("-07:00")).minutes(),
+            Ok((1350, 315, -420))
+        );
+        assert_eq!(
+            schedule("01:00", "02:00", Some("+05:30")).minutes(),
+            Ok((60, 120, 330))
+        );
+
+        for (start, end, offset) in &[
+            ("24:00", "06:00", None),
+            ("00:60", "06:00", None),
+            ("0:00", "06:00", None),
+            ("+1:00", "06:00", None),
+            ("00:00", "6", None),
+            ("00:00", "06:00", Some("07:00")),
+            ("00:00", "06:00", Some("+15:00")),
+            ("06:00", "06:00", None),
+        ] {
+            assert!(schedule(start, end, *offset).validate().is_err());
+        }
+    }
+}
@@ -0,0 +1,26 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+/// The types of the manager's HTTP API, and a client for it.  Tools that
+/// talk to the manager use these rather than describing its requests and
+/// responses for themselves, and the manager uses them to build those
+/// responses, so that the two can not drift apart unnoticed.
+
+#[macro_use]
+extern crate diesel;
+
+#[macro_use]
+extern crate strum_macros;
+
+pub mod client;
+pub mod compat;
+pub mod error;
+pub mod jobs;
+pub mod status;
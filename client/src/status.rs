@@ -0,0 +1,307 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The status of a job, as reported by GET /jobs/<uuid>.
+
+use crate::jobs::{JobSchedule, JobState, ObjectFilter, ObjectSource};
+
+use std::collections::{BTreeMap, HashMap};
+
+use libmanta::moray::MantaObjectShark;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "action")]
+pub enum JobStatusConfig {
+    Evacuate(JobConfigEvacuate),
+    CreateCopy(JobConfigCreateCopy),
+    RemoveCopy(JobConfigRemoveCopy),
+    Verify(JobConfigVerify),
+    Rollback(JobConfigRollback),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum JobStatusResults {
+    Evacuate(JobStatusResultsEvacuate),
+    Verify(JobStatusResultsVerify),
+    Rollback(JobStatusResultsRollback),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobStatus {
+    pub config: JobStatusConfig,
+    pub results: JobStatusResults,
+    pub state: JobState,
+
+    // 1-based position in the job queue, only present for queued jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+
+    // Who signed off the job, only present for jobs that were awaiting
+    // confirmation and have been confirmed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<JobConfirmation>,
+
+    // Why the job was paused, only present for paused jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pause: Option<JobPause>,
+
+    // What the job's metrics came to over its run, only present for jobs
+    // that have stopped running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<JobMetrics>,
+
+    // The changes made to the job while it ran, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub updates: Vec<JobUpdate>,
+
+    // How far the job has got with each phase and each shard, only present
+    // for evacuate, create-copy and remove-copy jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+
+    // What became of the objects that had a missing or malformed checksum,
+    // only present for evacuate and create-copy jobs that found any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ChecksumSummary>,
+
+    // The objects that were too large for every destination, only present
+    // for evacuate and create-copy jobs that skipped any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_fit: Option<NoFitSummary>,
+
+    // Whether the job's schedule lets it hand work to agents now, only
+    // present for jobs that were given a schedule and have yet to finish.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<JobScheduleStatus>,
+
+    // The manager that holds the job's lease, only present for jobs that are
+    // queued or running when managers are coordinating.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+/// How far a job has got with each of the phases that its objects go through,
+/// and with each of the shards that they were found in.  The phases overlap:
+/// objects are copied while the shards are still being scanned, and their
+/// metadata is updated as soon as they have been copied.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobProgress {
+    pub phases: JobPhases,
+    pub shards: Vec<ShardProgress>,
+
+    // The number of assignments that the job has handed to agents (or is
+    // posting) and has not yet had all of its objects back from.
+    #[serde(default)]
+    pub assignments: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobPhases {
+    pub ingestion: IngestionProgress,
+    pub copying: PhaseProgress,
+    pub metadata_update: PhaseProgress,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Display, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum PhaseState {
+    Pending,
+    InProgress,
+    Done,
+}
+
+/// The scan of the job's shards for its objects.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IngestionProgress {
+    pub state: PhaseState,
+    pub shards_scanned: usize,
+    pub shards: usize,
+    pub objects: i64,
+}
+
+/// The number of objects that are waiting for or going through a phase, and
+/// the number that have been through it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PhaseProgress {
+    pub state: PhaseState,
+    pub in_progress: i64,
+    pub done: i64,
+}
+
+/// The objects that a job found in a shard, by where they have got to.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ShardProgress {
+    pub shard: i32,
+    pub scanned: bool,
+    pub objects: i64,
+    pub copying: i64,
+    pub metadata_update: i64,
+    pub complete: i64,
+    pub skipped: i64,
+    pub error: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobConfigEvacuate {
+    pub from_shark: MantaObjectShark,
+
+    // The utilization ceiling of the job's destinations, if it was given one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dest_utilization_percent: Option<u32>,
+
+    // The daily window that the job runs within, if it was given one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<JobSchedule>,
+
+    // Which of the objects found the job works on, if it was given a filter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ObjectFilter>,
+
+    // Where the job found its objects, if it was given a source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ObjectSource>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobConfigCreateCopy {
+    pub shark: MantaObjectShark,
+    pub min_copies: Option<u32>,
+
+    // As for JobConfigEvacuate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_dest_utilization_percent: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<JobSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ObjectFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ObjectSource>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobConfigRemoveCopy {
+    pub shark: MantaObjectShark,
+    pub min_copies: u32,
+
+    // As for JobConfigEvacuate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<JobSchedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<ObjectFilter>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ObjectSource>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobConfigVerify {
+    pub shark: MantaObjectShark,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JobConfigRollback {
+    // The job whose metadata changes are rolled back, and its shark.
+    pub job_id: String,
+    pub shark: MantaObjectShark,
+
+    // Only the objects that the job put on this shark are rolled back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dest_shark: Option<String>,
+}
+
+// The number of the job's objects in each status.
+pub type JobStatusResultsEvacuate = HashMap<String, i64>;
+pub type JobStatusResultsVerify = HashMap<String, i64>;
+pub type JobStatusResultsRollback = HashMap<String, i64>;
+
+/// Who signed off a job that was awaiting confirmation, and when.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobConfirmation {
+    pub job_id: String,
+    pub confirmed_by: String,
+    pub comment: Option<String>,
+
+    // Milliseconds since the epoch at which the job was confirmed.
+    pub timestamp: i64,
+}
+
+/// Why a job was paused.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobPause {
+    pub job_id: String,
+
+    // Why the job was paused, `error_threshold` or `worker_panic`.
+    pub reason: String,
+
+    // What exactly caused the job to be paused.
+    pub detail: String,
+
+    // Milliseconds since the epoch at which the job was paused.
+    pub timestamp: i64,
+}
+
+/// What a job's metrics came to over its run.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobMetrics {
+    // Milliseconds since the epoch at which the job stopped running.
+    pub timestamp: i64,
+    pub samples: Vec<MetricSample>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MetricSample {
+    // The name of the sample as Prometheus would have it, e.g.
+    // `object_count` or `assignment_time_bucket`.
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+}
+
+/// A change made to a running job, as reported with the job's status.
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobUpdate {
+    pub parameter: String,
+    pub old_value: Value,
+    pub new_value: Value,
+    pub timestamp: i64,
+}
+
+/// The number of objects of a job that were found with a missing or
+/// malformed checksum, by what became of them.
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ChecksumSummary {
+    pub failed: i64,
+    pub skipped: i64,
+    pub copied: i64,
+}
+
+/// The objects of a job that were too large for every destination.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct NoFitSummary {
+    pub count: i64,
+    pub bytes: i64,
+    pub largest_object: String,
+    pub largest_bytes: i64,
+    pub remediation: String,
+}
+
+/// Where a job is in its daily window.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JobScheduleStatus {
+    // Whether the window is open, and so the job is handing work to agents.
+    pub open: bool,
+
+    // Milliseconds since the epoch at which the window next opens, if it is
+    // closed, or closes, if it is open.
+    pub next_change: i64,
+}
@@ -0,0 +1,209 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The HTTP client used for every request that the manager makes of an agent:
+// posting assignments, polling their status, cancelling them, and reconciling
+// them after a crash.
+//
+// Each job used to create clients of its own, and some requests were made
+// with a client created just for that request, so that every poll of an
+// assignment could open a fresh connection to the agent.  Instead, a single
+// client is shared by everything in the manager (see shared()).  It keeps up
+// to `agent_client.pool_size` idle connections open to each agent so that
+// they can be reused, unless `agent_client.keep_alive` is false.
+//
+// The same number limits how many requests may be in flight to any one agent
+// at a time.  A request is made by first checking out a connection to the
+// agent with checkout(), which waits for a connection to be returned if the
+// agent already has pool_size requests in flight.  The connection is returned
+// when the AgentConnection is dropped.
+//
+// configure() must be called before the first request is made.  Changes to
+// the configuration require a service restart.
+
+use crate::config::ConfigAgentClient;
+use crate::metrics::{
+    metrics_agent_checkout_inc, metrics_agent_requests_dec,
+    metrics_agent_requests_inc, AGENT_CHECKOUT_IMMEDIATE,
+    AGENT_CHECKOUT_WAITED,
+};
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+pub struct AgentClientPool {
+    client: reqwest::Client,
+    pool_size: usize,
+
+    // Agent (storage id) -> number of requests in flight to it.
+    in_flight: Mutex<HashMap<String, usize>>,
+    returned: Condvar,
+}
+
+/// A connection to an agent checked out of the pool.  This dereferences to
+/// the client that requests to the agent should be made with.
+pub struct AgentConnection<'a> {
+    pool: &'a AgentClientPool,
+    agent: String,
+}
+
+lazy_static! {
+    static ref SHARED: RwLock<Option<Arc<AgentClientPool>>> = RwLock::new(None);
+}
+
+/// Use the given client configuration from now on.  Requests already in
+/// flight complete on the pool they were made from.
+pub fn configure(config: &ConfigAgentClient) {
+    *SHARED.write().expect("agent client lock") =
+        Some(Arc::new(AgentClientPool::new(config)));
+}
+
+/// The pool shared by everything in this manager.  This is created with the
+/// default configuration if configure() has not been called.
+pub fn shared() -> Arc<AgentClientPool> {
+    if let Some(pool) = SHARED.read().expect("agent client lock").as_ref() {
+        return Arc::clone(pool);
+    }
+
+    let mut shared = SHARED.write().expect("agent client lock");
+    Arc::clone(shared.get_or_insert_with(|| {
+        Arc::new(AgentClientPool::new(&ConfigAgentClient::default()))
+    }))
+}
+
+impl AgentClientPool {
+    pub fn new(config: &ConfigAgentClient) -> AgentClientPool {
+        let pool_size = config.pool_size.max(1);
+        let max_idle = if config.keep_alive { pool_size } else { 0 };
+
+        let client = reqwest::Client::builder()
+            .max_idle_per_host(max_idle)
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.request_timeout_secs))
+            .build()
+            .expect("build agent client");
+
+        AgentClientPool {
+            client,
+            pool_size,
+            in_flight: Mutex::new(HashMap::new()),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Check out a connection to an agent, waiting for one to be returned if
+    /// the agent already has `pool_size` requests in flight.
+    pub fn checkout(&self, agent: &str) -> AgentConnection {
+        let mut in_flight = self.in_flight.lock().expect("agent pool lock");
+        let mut waited = false;
+
+        while in_flight.get(agent).map_or(0, |n| *n) >= self.pool_size {
+            waited = true;
+            in_flight = self.returned.wait(in_flight).expect("agent pool lock");
+        }
+
+        *in_flight.entry(agent.to_string()).or_insert(0) += 1;
+
+        metrics_agent_checkout_inc(if waited {
+            AGENT_CHECKOUT_WAITED
+        } else {
+            AGENT_CHECKOUT_IMMEDIATE
+        });
+        metrics_agent_requests_inc();
+
+        AgentConnection {
+            pool: self,
+            agent: agent.to_string(),
+        }
+    }
+
+    /// The number of requests in flight to an agent.
+    pub fn in_flight(&self, agent: &str) -> usize {
+        let in_flight = self.in_flight.lock().expect("agent pool lock");
+        in_flight.get(agent).map_or(0, |n| *n)
+    }
+
+    fn checkin(&self, agent: &str) {
+        let mut in_flight = self.in_flight.lock().expect("agent pool lock");
+
+        if let Some(count) = in_flight.get_mut(agent) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                in_flight.remove(agent);
+            }
+        }
+
+        metrics_agent_requests_dec();
+        self.returned.notify_all();
+    }
+}
+
+impl<'a> Deref for AgentConnection<'a> {
+    type Target = reqwest::Client;
+
+    fn deref(&self) -> &reqwest::Client {
+        &self.pool.client
+    }
+}
+
+impl<'a> Drop for AgentConnection<'a> {
+    fn drop(&mut self) {
+        self.pool.checkin(&self.agent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn checkout_limited_per_agent() {
+        let config = ConfigAgentClient {
+            pool_size: 2,
+            ..Default::default()
+        };
+        let pool = Arc::new(AgentClientPool::new(&config));
+
+        let first = pool.checkout("1.stor.domain");
+        let _second = pool.checkout("1.stor.domain");
+        assert_eq!(pool.in_flight("1.stor.domain"), 2);
+
+        // Other agents are not held up by this one.
+        {
+            let _other = pool.checkout("2.stor.domain");
+            assert_eq!(pool.in_flight("2.stor.domain"), 1);
+        }
+        assert_eq!(pool.in_flight("2.stor.domain"), 0);
+
+        // A third request to the same agent waits until a connection is
+        // returned.
+        let (tx, rx) = mpsc::channel();
+        let waiter_pool = Arc::clone(&pool);
+        let waiter = thread::spawn(move || {
+            let _third = waiter_pool.checkout("1.stor.domain");
+            tx.send(()).expect("send");
+        });
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+        drop(first);
+        rx.recv_timeout(Duration::from_secs(5))
+            .expect("checkout after checkin");
+        waiter.join().expect("join waiter");
+
+        assert_eq!(pool.in_flight("1.stor.domain"), 1);
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Recommended Prometheus alerting rules for the manager.
+//
+// Every deployment wants to be told about the same handful of failure modes:
+// jobs failing to move a large fraction of their objects, jobs that have
+// stopped making progress, and a manager whose metrics have gone away
+// altogether.  Rather than have each site write these rules by hand, the
+// manager generates them (as a Prometheus rules file in YAML) from its own
+// metric names, the constant labels it attaches to every metric, and the
+// thresholds in the "alerts" section of its configuration.
+
+use crate::config::ConfigAlerts;
+use crate::metrics::{ASSIGNMENTS_OUTSTANDING, MD_THREAD_GAUGE, SKIP_COUNT};
+
+use rebalancer::metrics::{ERROR_COUNT, OBJECT_COUNT};
+use std::collections::{BTreeMap, HashMap};
+
+static GROUP_NAME: &str = "rebalancer-manager";
+
+// The window over which the error ratio is calculated.
+static ERROR_RATIO_WINDOW: &str = "15m";
+
+struct AlertRule {
+    name: &'static str,
+    expr: String,
+    for_minutes: u64,
+    severity: &'static str,
+    summary: String,
+}
+
+// A Prometheus label matcher list (without the braces) that selects the
+// metrics of this manager.  The labels are sorted so that the output is
+// stable.
+fn selector(labels: &BTreeMap<&String, &String>) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{}=\"{}\"",
+                k,
+                v.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Join a metric's own label matchers with those of the selector.
+fn matchers(own: &str, sel: &str) -> String {
+    match (own.is_empty(), sel.is_empty()) {
+        (true, _) => sel.to_string(),
+        (false, true) => own.to_string(),
+        (false, false) => format!("{},{}", own, sel),
+    }
+}
+
+fn yaml_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// The labeled series of a counter vector only exist once they have been
+// incremented, so every sum over one falls back to zero.  Otherwise, for
+// example, the error ratio could not be calculated until at least one object
+// had been skipped.
+fn rules(config: &ConfigAlerts, sel: &str) -> Vec<AlertRule> {
+    let failed = format!(
+        "(sum(rate({}{{{}}}[{w}])) or vector(0)) + \
+         (sum(rate({}{{{}}}[{w}])) or vector(0))",
+        ERROR_COUNT,
+        matchers("error=\"total\"", sel),
+        SKIP_COUNT,
+        matchers("reason=\"total\"", sel),
+        w = ERROR_RATIO_WINDOW
+    );
+    let moved = format!(
+        "(sum(rate({}{{{}}}[{}])) or vector(0))",
+        OBJECT_COUNT,
+        matchers("type=\"total\"", sel),
+        ERROR_RATIO_WINDOW
+    );
+
+    vec![
+        AlertRule {
+            name: "RebalancerErrorRatioHigh",
+            expr: format!(
+                "({f}) / ({m} + {f}) > {}",
+                config.error_ratio,
+                f = failed,
+                m = moved
+            ),
+            for_minutes: 15,
+            severity: "warning",
+            summary: format!(
+                "More than {}% of the objects processed by rebalancer jobs \
+                 are failing to move",
+                config.error_ratio * 100.0
+            ),
+        },
+        AlertRule {
+            name: "RebalancerAssignmentsStalled",
+            expr: format!(
+                "sum({}{{{}}}) > 0 and on() \
+                 (sum(increase({}{{{}}}[{}m])) or vector(0)) == 0",
+                ASSIGNMENTS_OUTSTANDING,
+                sel,
+                OBJECT_COUNT,
+                matchers("type=\"total\"", sel),
+                config.stalled_minutes
+            ),
+            for_minutes: 5,
+            severity: "critical",
+            summary: format!(
+                "Rebalancer assignments are outstanding but no objects \
+                 have been moved in {} minutes",
+                config.stalled_minutes
+            ),
+        },
+        AlertRule {
+            name: "RebalancerMetricsStale",
+            expr: format!("absent({}{{{}}})", MD_THREAD_GAUGE, sel),
+            for_minutes: config.stale_minutes,
+            severity: "warning",
+            summary: format!(
+                "No metrics have been scraped from the rebalancer manager \
+                 in {} minutes",
+                config.stale_minutes
+            ),
+        },
+    ]
+}
+
+/// Render the recommended alerting rules as a Prometheus rules file.  Each
+/// rule selects, and is labeled with, the given constant labels.
+pub fn alert_rules(
+    config: &ConfigAlerts,
+    const_labels: &HashMap<String, String>,
+) -> String {
+    let labels: BTreeMap<&String, &String> = const_labels.iter().collect();
+    let sel = selector(&labels);
+    let mut out = format!("groups:\n  - name: {}\n    rules:\n", GROUP_NAME);
+
+    for rule in rules(config, &sel) {
+        out.push_str(&format!("      - alert: {}\n", rule.name));
+        out.push_str(&format!("        expr: {}\n", yaml_quote(&rule.expr)));
+        out.push_str(&format!("        for: {}m\n", rule.for_minutes));
+        out.push_str("        labels:\n");
+        out.push_str(&format!("          severity: {}\n", rule.severity));
+        for (k, v) in labels.iter() {
+            out.push_str(&format!("          {}: {}\n", k, yaml_quote(v)));
+        }
+        out.push_str("        annotations:\n");
+        out.push_str(&format!(
+            "          summary: {}\n",
+            yaml_quote(&rule.summary)
+        ));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_use_labels_and_thresholds() {
+        let config = ConfigAlerts {
+            error_ratio: 0.25,
+            stalled_minutes: 45,
+            stale_minutes: 7,
+        };
+        let mut labels = HashMap::new();
+        labels.insert("service".to_string(), "1.rebalancer.x".to_string());
+        labels.insert("datacenter".to_string(), "dc'1".to_string());
+
+        let out = alert_rules(&config, &labels);
+        let sel = "datacenter=\"dc''1\",service=\"1.rebalancer.x\"";
+
+        assert!(out.starts_with("groups:\n"));
+        assert!(out.contains(&format!("absent(md_thread_gauge{{{}}})", sel)));
+        assert!(out
+            .contains(&format!("skip_count{{reason=\"total\",{}}}[15m]", sel)));
+        assert!(out.contains(") > 0.25'"));
+        assert!(out.contains("[45m])) or vector(0)) == 0'"));
+        assert!(out.contains("        for: 7m\n"));
+        assert!(out.contains("          datacenter: 'dc''1'\n"));
+        assert_eq!(out.matches("      - alert: ").count(), 3);
+    }
+}
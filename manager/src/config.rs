@@ -10,16 +10,27 @@
 
 extern crate clap;
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::BufReader;
 use std::sync::{Arc, Barrier, Mutex};
 
 use crossbeam_channel::TrySendError;
-use serde::{de, de::Error as de_Error, Deserialize, Deserializer};
+use serde::{
+    de, de::Error as de_Error, Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::Value;
 use signal_hook::{self, iterator::Signals};
 
+use crate::jobs::bandwidth;
+use crate::jobs::quarantine;
+use crate::storinfo;
+use rebalancer::common::TaskOrder;
+use rebalancer::config_schema::{self, ConfigCheck, ConfigSchema};
 use rebalancer::error::Error;
+use rebalancer::metrics::{self, ConfigMetrics, MetricsMode};
 use rebalancer::util;
+pub use rebalancer_client::jobs::ChecksumPolicy;
 use slog::Level;
 use std::thread;
 use std::thread::JoinHandle;
@@ -52,6 +63,15 @@ static DEFAULT_STATIC_QUEUE_DEPTH: usize = 10;
 // destination sharks.
 static DEFAULT_MAX_ASSIGNMENT_AGE: u64 = 600;
 
+// The maximum number of objects whose metadata is updated in a single moray
+// batch request, when batched updates are enabled.
+static DEFAULT_MD_UPDATE_BATCH_SIZE: usize = 50;
+
+// The number of times that the metadata of an object whose update conflicted
+// with another change to it is re-read and the update retried, before the
+// object is given up on.
+static DEFAULT_MAX_ETAG_CONFLICT_RETRIES: u32 = 3;
+
 // The chunk size used when scanning the metadata tier or during a retry when
 // reading from the local database.
 static DEFAULT_METADATA_READ_CHUNK_SIZE: usize = 10000;
@@ -60,27 +80,318 @@ static DEFAULT_METADATA_READ_CHUNK_SIZE: usize = 10000;
 // metadata tier.
 static DEFAULT_MAX_METADATA_READ_THREADS: usize = 10;
 
+// The largest metadata record, in bytes of its JSON encoding, that a job will
+// work on.  Larger records are skipped and counted (see the jobs::record
+// module).
+static DEFAULT_MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+// The most objects that a job holds in memory waiting to be placed.  Objects
+// given back to be placed again beyond this are kept in the job's database
+// instead (see the jobs::overflow module).
+static DEFAULT_MAX_RESIDENT_OBJECTS: usize = 50_000;
+
+// The most bytes per second that the assignments of every running job may
+// move between them (see the jobs::bandwidth module).  0 means no limit.
+static DEFAULT_MAX_AGGREGATE_BYTES_PER_SECOND: u64 = 0;
+
+// The maximum number of jobs that will run at the same time.  Any jobs created
+// beyond this are queued until a running job finishes.
+static DEFAULT_MAX_CONCURRENT_JOBS: usize = 1;
+
+// In slow source mode, the maximum number of objects in a single assignment
+// that will be read from the shark being evacuated.
+static DEFAULT_SLOW_SOURCE_MAX_READS: usize = 4;
+
+// Defaults for the thresholds of the recommended alerting rules.
+static DEFAULT_ALERT_ERROR_RATIO: f64 = 0.05;
+static DEFAULT_ALERT_STALLED_MINUTES: u64 = 30;
+static DEFAULT_ALERT_STALE_MINUTES: u64 = 10;
+
+// The share of the data outstanding to all destination sharks, across every
+// running job, above which `GET /destinations` flags a single shark.
+static DEFAULT_DESTINATION_CONCENTRATION_PERCENTAGE: u32 = 50;
+
+// Where the databases of finished jobs are archived to.  This is on the
+// rebalancer's delegated dataset, alongside the postgres data directory.
+static DEFAULT_JOB_ARCHIVE_DIR: &str = "/rebalancer/archive";
+
+// Where each job's own log file is written, when job logs are enabled, and
+// how much of it is kept.
+static DEFAULT_JOB_LOG_DIR: &str = "/var/log/rebalancer";
+static DEFAULT_JOB_LOG_MAX_SIZE_MB: u64 = 100;
+static DEFAULT_JOB_LOG_MAX_FILES: u32 = 5;
+
+// Defaults for post-job hooks.
+static DEFAULT_HOOK_TIMEOUT_SECS: u64 = 60;
+static DEFAULT_HOOK_ATTEMPTS: u32 = 3;
+static DEFAULT_HOOK_RETRY_DELAY_SECS: u64 = 10;
+
+// Defaults for the warm-up of new jobs.  Jobs start at full concurrency unless
+// ramp_minutes is set.
+static DEFAULT_RAMP_START_PERCENTAGE: u32 = 25;
+static DEFAULT_RAMP_MAX_ERROR_RATIO: f64 = 0.05;
+
+// Defaults for the client used for requests to agents.  The request timeout is
+// the one that the manager's clients have always had.
+static DEFAULT_AGENT_POOL_SIZE: usize = 8;
+static DEFAULT_AGENT_CONNECT_TIMEOUT_SECS: u64 = 10;
+static DEFAULT_AGENT_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+// Bounds and targets for adaptive assignment sizing, which is off by default.
+static DEFAULT_MIN_ADAPTIVE_TASKS: usize = 10;
+static DEFAULT_MAX_ADAPTIVE_TASKS: usize = 200;
+static DEFAULT_TARGET_ASSIGNMENT_SECS: u64 = 120;
+static DEFAULT_SIZING_MAX_ERROR_RATIO: f64 = 0.05;
+
+// Bounds of the time between polls of an assignment.  The minimum is the
+// interval at which assignments were always polled before polls were
+// scheduled.
+static DEFAULT_MIN_POLL_MS: u64 = 500;
+static DEFAULT_MAX_POLL_SECS: u64 = 30;
+
+// Defaults for the per-job circuit breaker.  A job's error rate can never be
+// above 1, and a max_consecutive_errors of 0 is off, so by default jobs are
+// never paused.
+static DEFAULT_BREAKER_MAX_ERROR_RATE: f64 = 1.0;
+static DEFAULT_BREAKER_MAX_CONSECUTIVE_ERRORS: u64 = 0;
+static DEFAULT_BREAKER_MIN_OBJECTS: u64 = 1000;
+
+// Defaults for the history of agents across jobs, and when jobs avoid an
+// agent because of it.
+static DEFAULT_HISTORY_MIN_ASSIGNMENTS: u64 = 10;
+static DEFAULT_HISTORY_SLOW_FRACTION: f64 = 0.5;
+static DEFAULT_HISTORY_MAX_FAILURE_RATIO: f64 = 0.2;
+
+// Defaults for quarantining agents whose assignments can not be posted.
+static DEFAULT_QUARANTINE_MAX_FAILURES: u64 = 5;
+static DEFAULT_QUARANTINE_COOLDOWN_SECS: u64 = 600;
+
+// Defaults for verify jobs, which check objects against the storage nodes
+// directly rather than through agents.
+static DEFAULT_VERIFY_THREADS: usize = 8;
+static DEFAULT_VERIFY_TIMEOUT_SECS: u64 = 30;
+
+// Defaults for the storinfo poller.  The poll interval and page size are what
+// every job used when it polled storinfo itself.
+static DEFAULT_STORINFO_POLL_INTERVAL_SECS: u64 = 10;
+static DEFAULT_STORINFO_PAGE_SIZE: usize = 100;
+static DEFAULT_STORINFO_MIN_REQUEST_INTERVAL_MS: u64 = 100;
+
+// Bounds of the time between two saves of a job's scan checkpoints.  The
+// minimum is the interval at which they were always saved.
+static DEFAULT_CHECKPOINT_MIN_INTERVAL_SECS: u64 = 10;
+static DEFAULT_CHECKPOINT_MAX_INTERVAL_SECS: u64 = 60;
+static DEFAULT_CHECKPOINT_HIGH_THROUGHPUT: u64 = 1000;
+
+// How long a manager's lease on a job lasts without being renewed, when more
+// than one manager shares the jobs database.
+static DEFAULT_LEASE_SECS: u64 = 30;
+
 pub const MAX_TUNABLE_MD_UPDATE_THREADS: usize = 250;
 
-#[derive(Deserialize, Default, Debug, Clone)]
+// As with the metadata update threads, these only bound what a running job can
+// be changed to (see jobs::tuning), so that a fat finger does not swamp the
+// destination sharks or their agents.
+pub const MAX_TUNABLE_SHARKS: usize = 100;
+pub const MAX_TUNABLE_TASKS_PER_ASSIGNMENT: usize = 10_000;
+
+// Every key that the manager understands.  This must be kept in sync with the
+// Config and ConfigOptions structures below.
+static CONFIG_SCHEMA: ConfigSchema = ConfigSchema {
+    known: &[
+        "domain_name",
+        "shards",
+        "snaplink_cleanup_required",
+        "sharks_file",
+        "options",
+        "options.max_tasks_per_assignment",
+        "options.max_metadata_update_threads",
+        "options.max_sharks",
+        "options.use_static_md_update_threads",
+        "options.use_sharded_md_updates",
+        "options.static_queue_depth",
+        "options.max_assignment_age",
+        "options.use_batched_updates",
+        "options.md_update_batch_size",
+        "options.max_etag_conflict_retries",
+        "options.md_read_chunk_size",
+        "options.max_md_read_threads",
+        "options.max_concurrent_jobs",
+        "options.slow_source",
+        "options.slow_source_max_reads",
+        "options.require_confirmation",
+        "options.verify_before_update",
+        "options.header_check_percent",
+        "options.trace_placement",
+        "options.checksum_policy",
+        "options.max_record_bytes",
+        "options.max_resident_objects",
+        "options.max_aggregate_bytes_per_second",
+        "options.resolve_snaplinks",
+        "options.task_order",
+        "options.compress_transfers",
+        "options.direct_pull",
+        "notifications",
+        "notifications.webhooks",
+        "notifications.error_thresholds",
+        "alerts",
+        "alerts.error_ratio",
+        "alerts.stalled_minutes",
+        "alerts.stale_minutes",
+        "retention",
+        "retention.job_retention_days",
+        "retention.archive_dir",
+        "job_logs",
+        "job_logs.enabled",
+        "job_logs.directory",
+        "job_logs.max_size_mb",
+        "job_logs.max_files",
+        "hooks",
+        "database",
+        "database.host",
+        "database.port",
+        "database.user",
+        "database.password",
+        "ramp",
+        "ramp.ramp_minutes",
+        "ramp.start_percentage",
+        "ramp.max_error_ratio",
+        "agent_client",
+        "agent_client.pool_size",
+        "agent_client.keep_alive",
+        "agent_client.connect_timeout_secs",
+        "agent_client.request_timeout_secs",
+        "assignment_sizing",
+        "assignment_sizing.adaptive",
+        "assignment_sizing.min_tasks_per_assignment",
+        "assignment_sizing.max_tasks_per_assignment",
+        "assignment_sizing.target_assignment_secs",
+        "assignment_sizing.max_error_ratio",
+        "polling",
+        "polling.min_poll_ms",
+        "polling.max_poll_secs",
+        "circuit_breaker",
+        "circuit_breaker.max_error_rate",
+        "circuit_breaker.max_consecutive_errors",
+        "circuit_breaker.min_objects",
+        "agent_history",
+        "agent_history.record",
+        "agent_history.placement_hints",
+        "agent_history.min_assignments",
+        "agent_history.slow_fraction",
+        "agent_history.max_failure_ratio",
+        "agent_quarantine",
+        "agent_quarantine.max_failures",
+        "agent_quarantine.cooldown_secs",
+        "verification",
+        "verification.agentless",
+        "verification.method",
+        "verification.threads",
+        "verification.timeout_secs",
+        "storinfo",
+        "storinfo.poll_interval_secs",
+        "storinfo.page_size",
+        "storinfo.min_request_interval_ms",
+        "storinfo.watch_changes",
+        "checkpoints",
+        "checkpoints.min_interval_secs",
+        "checkpoints.max_interval_secs",
+        "checkpoints.high_throughput",
+        "coordination",
+        "coordination.enabled",
+        "coordination.manager_id",
+        "coordination.url",
+        "coordination.lease_secs",
+        "metrics",
+        "metrics.host",
+        "metrics.port",
+        "metrics.datacenter",
+        "metrics.service",
+        "metrics.server",
+        "metrics.mode",
+        "metrics.push_url",
+        "metrics.statsd_address",
+        "metrics.push_interval_secs",
+        "listen_port",
+        "max_fill_percentage",
+        "destination_concentration_percentage",
+        "log_level",
+    ],
+    deprecated: &[
+        // Older versions of the SAPI template misspelled this key, which
+        // meant that it was never applied.
+        ("max_fill_precentage", "max_fill_percentage"),
+    ],
+};
+
+// The keys (and sections) that are only read when the manager starts.  When
+// the configuration file is reloaded, a change to any of these is logged but
+// not applied until the manager is restarted.  Everything else is read from
+// the configuration in memory as it is needed, and is applied at once.
+static RESTART_REQUIRED: &[&str] = &[
+    "domain_name",
+    "listen_port",
+    "database",
+    "agent_client",
+    "metrics.host",
+    "metrics.port",
+    "metrics.mode",
+    "metrics.push_url",
+    "metrics.statsd_address",
+    "metrics.push_interval_secs",
+    "storinfo.page_size",
+    "storinfo.min_request_interval_ms",
+    "storinfo.watch_changes",
+    "coordination",
+];
+
+/// The keys that changed when the configuration file was reloaded, as
+/// dotted paths like those of CONFIG_SCHEMA.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigReload {
+    /// Keys whose new values are now in effect.
+    pub applied: Vec<String>,
+    /// Keys whose old values are kept until the manager is restarted.
+    pub restart_required: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
 pub struct Shard {
     pub host: String,
 }
 
 // Until we can determine a reasonable set of defaults and limits these
 // tunables are intentionally not exposed in the documentation.
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
 #[serde(default)]
 pub struct ConfigOptions {
     pub max_tasks_per_assignment: usize,
     pub max_metadata_update_threads: usize,
     pub max_sharks: usize,
     pub use_static_md_update_threads: bool,
+    pub use_sharded_md_updates: bool,
     pub static_queue_depth: usize,
     pub max_assignment_age: u64,
     pub use_batched_updates: bool,
+    pub md_update_batch_size: usize,
+    pub max_etag_conflict_retries: u32,
     pub md_read_chunk_size: usize,
     pub max_md_read_threads: usize,
+    pub max_concurrent_jobs: usize,
+    pub slow_source: bool,
+    pub slow_source_max_reads: usize,
+    pub require_confirmation: bool,
+    pub verify_before_update: bool,
+    pub header_check_percent: u32,
+    pub trace_placement: bool,
+    pub checksum_policy: ChecksumPolicy,
+    pub max_record_bytes: usize,
+    pub max_resident_objects: usize,
+    pub max_aggregate_bytes_per_second: u64,
+    pub resolve_snaplinks: bool,
+    pub task_order: Option<TaskOrder>,
+    pub compress_transfers: bool,
+    pub direct_pull: bool,
 }
 
 impl Default for ConfigOptions {
@@ -90,16 +401,553 @@ impl Default for ConfigOptions {
             max_metadata_update_threads: DEFAULT_MAX_METADATA_UPDATE_THREADS,
             max_sharks: DEFAULT_MAX_SHARKS,
             use_static_md_update_threads: false,
+            use_sharded_md_updates: false,
             static_queue_depth: DEFAULT_STATIC_QUEUE_DEPTH,
             max_assignment_age: DEFAULT_MAX_ASSIGNMENT_AGE,
             use_batched_updates: true,
+            md_update_batch_size: DEFAULT_MD_UPDATE_BATCH_SIZE,
+            max_etag_conflict_retries: DEFAULT_MAX_ETAG_CONFLICT_RETRIES,
             md_read_chunk_size: DEFAULT_METADATA_READ_CHUNK_SIZE,
             max_md_read_threads: DEFAULT_MAX_METADATA_READ_THREADS,
+            max_concurrent_jobs: DEFAULT_MAX_CONCURRENT_JOBS,
+            slow_source: false,
+            slow_source_max_reads: DEFAULT_SLOW_SOURCE_MAX_READS,
+            require_confirmation: false,
+            verify_before_update: false,
+            header_check_percent: 0,
+            trace_placement: false,
+            checksum_policy: ChecksumPolicy::Skip,
+            max_record_bytes: DEFAULT_MAX_RECORD_BYTES,
+            max_resident_objects: DEFAULT_MAX_RESIDENT_OBJECTS,
+            max_aggregate_bytes_per_second:
+                DEFAULT_MAX_AGGREGATE_BYTES_PER_SECOND,
+            resolve_snaplinks: false,
+            task_order: None,
+            compress_transfers: false,
+            direct_pull: false,
+        }
+    }
+}
+
+/// Where, and on what occasions, job lifecycle events are sent.  See the
+/// notify module.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigNotifications {
+    /// URLs that each event is POSTed to.  No events are generated if this
+    /// is empty.
+    pub webhooks: Vec<String>,
+
+    /// Counts of objects that a job has failed to move at which an
+    /// error_threshold event is sent.
+    pub error_thresholds: Vec<u64>,
+}
+
+/// The events on which post-job hooks are run.  See the hooks module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Complete,
+    Failed,
+    Paused,
+}
+
+impl HookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookEvent::Complete => "complete",
+            HookEvent::Failed => "failed",
+            HookEvent::Paused => "paused",
+        }
+    }
+}
+
+/// A command to run, or a URL to POST to, with a job's summary once the job
+/// is finished.  See the hooks module.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigHook {
+    /// The program to run and its arguments.  This is not run by a shell.
+    pub command: Vec<String>,
+
+    /// The URL to POST to, instead of running a command.
+    pub url: Option<String>,
+
+    /// The events that the hook is run on.
+    pub on: Vec<HookEvent>,
+
+    /// Seconds that the command may run for, or that the URL may take to
+    /// respond, before the attempt fails.
+    pub timeout_secs: u64,
+
+    /// Times that the hook is tried before it is given up on.
+    pub attempts: u32,
+
+    /// Seconds between attempts.
+    pub retry_delay_secs: u64,
+}
+
+impl Default for ConfigHook {
+    fn default() -> ConfigHook {
+        ConfigHook {
+            command: vec![],
+            url: None,
+            on: vec![HookEvent::Complete, HookEvent::Failed, HookEvent::Paused],
+            timeout_secs: DEFAULT_HOOK_TIMEOUT_SECS,
+            attempts: DEFAULT_HOOK_ATTEMPTS,
+            retry_delay_secs: DEFAULT_HOOK_RETRY_DELAY_SECS,
+        }
+    }
+}
+
+impl ConfigHook {
+    fn validate(&self) -> Result<(), String> {
+        match (self.command.is_empty(), &self.url) {
+            (true, None) => Err(String::from("neither command nor url is set")),
+            (false, Some(_)) => {
+                Err(String::from("both command and url are set"))
+            }
+            _ if self.timeout_secs == 0 => {
+                Err(String::from("timeout_secs must be at least 1"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Thresholds of the recommended Prometheus alerting rules returned by
+/// `GET /alerts`.  See the alerts module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigAlerts {
+    /// Fraction of processed objects that fail to move above which the
+    /// error ratio alert fires.
+    pub error_ratio: f64,
+
+    /// Minutes for which assignments may be outstanding without any objects
+    /// being moved before the stalled assignments alert fires.
+    pub stalled_minutes: u64,
+
+    /// Minutes for which the manager's metrics may be missing before the
+    /// staleness alert fires.
+    pub stale_minutes: u64,
+}
+
+impl Default for ConfigAlerts {
+    fn default() -> ConfigAlerts {
+        ConfigAlerts {
+            error_ratio: DEFAULT_ALERT_ERROR_RATIO,
+            stalled_minutes: DEFAULT_ALERT_STALLED_MINUTES,
+            stale_minutes: DEFAULT_ALERT_STALE_MINUTES,
+        }
+    }
+}
+
+/// How long finished jobs are kept, and where they are archived to once they
+/// expire.  See the jobs::retention module.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigRetention {
+    /// Days after its creation at which a finished job is archived and
+    /// removed.  Finished jobs are kept forever if this is 0.
+    pub job_retention_days: u64,
+
+    /// Directory that the databases of archived jobs are written to.
+    pub archive_dir: String,
+}
+
+impl Default for ConfigRetention {
+    fn default() -> ConfigRetention {
+        ConfigRetention {
+            job_retention_days: 0,
+            archive_dir: DEFAULT_JOB_ARCHIVE_DIR.to_string(),
+        }
+    }
+}
+
+/// Whether each job also logs to a file of its own, and how those files are
+/// rotated.  See the joblog module.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigJobLogs {
+    pub enabled: bool,
+
+    /// Directory that the log files of jobs are written to.
+    pub directory: String,
+
+    /// Size, in MB, at which a job's log file is rotated.  0 means never.
+    pub max_size_mb: u64,
+
+    /// Number of rotated log files kept for each job.
+    pub max_files: u32,
+}
+
+impl Default for ConfigJobLogs {
+    fn default() -> ConfigJobLogs {
+        ConfigJobLogs {
+            enabled: false,
+            directory: DEFAULT_JOB_LOG_DIR.to_string(),
+            max_size_mb: DEFAULT_JOB_LOG_MAX_SIZE_MB,
+            max_files: DEFAULT_JOB_LOG_MAX_FILES,
+        }
+    }
+}
+
+/// The PostgreSQL server that holds the jobs table and the database of each
+/// job.  See the pg_db module.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigDatabase {
+    /// Host name or address of the server.  If this is empty the server
+    /// running in the manager zone is used, over its unix domain socket.
+    pub host: String,
+
+    pub port: Option<u16>,
+
+    pub user: String,
+
+    pub password: String,
+}
+
+impl Default for ConfigDatabase {
+    fn default() -> ConfigDatabase {
+        ConfigDatabase {
+            host: String::new(),
+            port: None,
+            user: String::from("postgres"),
+            password: String::from("postgres"),
+        }
+    }
+}
+
+/// How a new job works up to its full concurrency.  See the jobs::ramp
+/// module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigRamp {
+    /// Minutes over which a new job works up to its full concurrency.  Jobs
+    /// start at full concurrency if this is 0.
+    pub ramp_minutes: u64,
+
+    /// Percentage of its full concurrency that a new job starts at.
+    pub start_percentage: u32,
+
+    /// Fraction of objects that fail to move, over a window of the ramp,
+    /// above which the ramp is held.
+    pub max_error_ratio: f64,
+}
+
+impl Default for ConfigRamp {
+    fn default() -> ConfigRamp {
+        ConfigRamp {
+            ramp_minutes: 0,
+            start_percentage: DEFAULT_RAMP_START_PERCENTAGE,
+            max_error_ratio: DEFAULT_RAMP_MAX_ERROR_RATIO,
+        }
+    }
+}
+
+/// The client used for requests to agents.  See the agent_client module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigAgentClient {
+    /// Maximum number of requests in flight to any one agent, and of idle
+    /// connections kept open to it.
+    pub pool_size: usize,
+
+    /// Keep connections to agents open so that they can be reused by later
+    /// requests.
+    pub keep_alive: bool,
+
+    pub connect_timeout_secs: u64,
+
+    pub request_timeout_secs: u64,
+}
+
+impl Default for ConfigAgentClient {
+    fn default() -> ConfigAgentClient {
+        ConfigAgentClient {
+            pool_size: DEFAULT_AGENT_POOL_SIZE,
+            keep_alive: true,
+            connect_timeout_secs: DEFAULT_AGENT_CONNECT_TIMEOUT_SECS,
+            request_timeout_secs: DEFAULT_AGENT_REQUEST_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// How the number of tasks in each assignment follows the agent it is sent
+/// to.  See the jobs::sizing module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigAssignmentSizing {
+    /// Size assignments according to how each agent is keeping up, rather
+    /// than always using `options.max_tasks_per_assignment`.
+    pub adaptive: bool,
+
+    pub min_tasks_per_assignment: usize,
+
+    pub max_tasks_per_assignment: usize,
+
+    /// Seconds that an agent should take to complete an assignment.
+    pub target_assignment_secs: u64,
+
+    /// Fraction of an assignment's tasks that may fail before the agent's
+    /// assignments are made smaller.
+    pub max_error_ratio: f64,
+}
+
+impl Default for ConfigAssignmentSizing {
+    fn default() -> ConfigAssignmentSizing {
+        ConfigAssignmentSizing {
+            adaptive: false,
+            min_tasks_per_assignment: DEFAULT_MIN_ADAPTIVE_TASKS,
+            max_tasks_per_assignment: DEFAULT_MAX_ADAPTIVE_TASKS,
+            target_assignment_secs: DEFAULT_TARGET_ASSIGNMENT_SECS,
+            max_error_ratio: DEFAULT_SIZING_MAX_ERROR_RATIO,
+        }
+    }
+}
+
+/// How often agents are asked about their assignments.  See the
+/// jobs::polling module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigPolling {
+    /// Shortest time between two polls of an assignment.
+    pub min_poll_ms: u64,
+
+    /// Longest time between two polls of an assignment.
+    pub max_poll_secs: u64,
+}
+
+impl Default for ConfigPolling {
+    fn default() -> ConfigPolling {
+        ConfigPolling {
+            min_poll_ms: DEFAULT_MIN_POLL_MS,
+            max_poll_secs: DEFAULT_MAX_POLL_SECS,
+        }
+    }
+}
+
+/// When a job that keeps failing to move objects is paused.  See the
+/// jobs::breaker module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigCircuitBreaker {
+    /// Fraction of the objects processed by a job that may fail to move
+    /// before the job is paused.
+    pub max_error_rate: f64,
+
+    /// Number of objects in a row that may fail to move before the job is
+    /// paused.  0 means no limit.
+    pub max_consecutive_errors: u64,
+
+    /// Number of objects a job must have processed before its error rate is
+    /// checked.
+    pub min_objects: u64,
+}
+
+impl Default for ConfigCircuitBreaker {
+    fn default() -> ConfigCircuitBreaker {
+        ConfigCircuitBreaker {
+            max_error_rate: DEFAULT_BREAKER_MAX_ERROR_RATE,
+            max_consecutive_errors: DEFAULT_BREAKER_MAX_CONSECUTIVE_ERRORS,
+            min_objects: DEFAULT_BREAKER_MIN_OBJECTS,
+        }
+    }
+}
+
+/// What is kept of how each agent has done across jobs, and how jobs place
+/// objects because of it.  See the jobs::history module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigAgentHistory {
+    /// Add what each job's destination agents do to their history.
+    pub record: bool,
+
+    /// Move the agents that their history says to avoid to the end of each
+    /// job's list of destinations.
+    pub placement_hints: bool,
+
+    /// Number of assignments an agent must have had before its history is
+    /// held against it.
+    pub min_assignments: u64,
+
+    /// Fraction of the median throughput of all agents below which an agent
+    /// is avoided.
+    pub slow_fraction: f64,
+
+    /// Fraction of an agent's assignments that could not be posted, or of
+    /// its tasks that failed, above which it is avoided.
+    pub max_failure_ratio: f64,
+}
+
+impl Default for ConfigAgentHistory {
+    fn default() -> ConfigAgentHistory {
+        ConfigAgentHistory {
+            record: true,
+            placement_hints: true,
+            min_assignments: DEFAULT_HISTORY_MIN_ASSIGNMENTS,
+            slow_fraction: DEFAULT_HISTORY_SLOW_FRACTION,
+            max_failure_ratio: DEFAULT_HISTORY_MAX_FAILURE_RATIO,
+        }
+    }
+}
+
+/// When an agent whose assignments keep failing to be posted is quarantined.
+/// See the jobs::quarantine module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ConfigAgentQuarantine {
+    /// Number of posts in a row to an agent that may fail before the agent
+    /// is quarantined.  0 means that agents are only quarantined by hand.
+    pub max_failures: u64,
+
+    /// Seconds that an agent is quarantined for.
+    pub cooldown_secs: u64,
+}
+
+impl Default for ConfigAgentQuarantine {
+    fn default() -> ConfigAgentQuarantine {
+        ConfigAgentQuarantine {
+            max_failures: DEFAULT_QUARANTINE_MAX_FAILURES,
+            cooldown_secs: DEFAULT_QUARANTINE_COOLDOWN_SECS,
+        }
+    }
+}
+
+/// How verify jobs ask storage nodes about objects.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VerifyMethod {
+    Head,
+    Get,
+}
+
+/// How verify jobs check objects, and whether the manager is running without
+/// agents.  See the jobs::verify module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigVerification {
+    /// Only accept jobs that can be run without agents, i.e. verify jobs.
+    pub agentless: bool,
+
+    /// The request made of the storage node for each object.  GET is for
+    /// front doors that do not answer HEAD requests; only the response
+    /// headers are read.
+    pub method: VerifyMethod,
+
+    /// Number of objects that a verify job checks at once.
+    pub threads: usize,
+
+    /// Seconds to wait for a storage node to respond about an object.
+    pub timeout_secs: u64,
+}
+
+impl Default for ConfigVerification {
+    fn default() -> ConfigVerification {
+        ConfigVerification {
+            agentless: false,
+            method: VerifyMethod::Head,
+            threads: DEFAULT_VERIFY_THREADS,
+            timeout_secs: DEFAULT_VERIFY_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// How the list of sharks is fetched from the storinfo service.  See the
+/// storinfo module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigStorinfo {
+    /// Seconds between two polls of storinfo.
+    pub poll_interval_secs: u64,
+
+    /// Number of sharks asked for in each request.
+    pub page_size: usize,
+
+    /// Shortest time between two requests to storinfo, so that paging
+    /// through a big fleet does not hammer it.
+    pub min_request_interval_ms: u64,
+
+    /// Tell running jobs about each shark that appears or disappears between
+    /// two polls.
+    pub watch_changes: bool,
+}
+
+impl Default for ConfigStorinfo {
+    fn default() -> ConfigStorinfo {
+        ConfigStorinfo {
+            poll_interval_secs: DEFAULT_STORINFO_POLL_INTERVAL_SECS,
+            page_size: DEFAULT_STORINFO_PAGE_SIZE,
+            min_request_interval_ms: DEFAULT_STORINFO_MIN_REQUEST_INTERVAL_MS,
+            watch_changes: false,
+        }
+    }
+}
+
+/// How often a scanning job saves its scan checkpoints.  See the
+/// jobs::checkpoint module.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct ConfigCheckpoints {
+    /// Time between saves while the scan is slow.
+    pub min_interval_secs: u64,
+
+    /// Time between saves once the scan is fast, and so the most scanning
+    /// that a crash can lose.
+    pub max_interval_secs: u64,
+
+    /// Records scanned per second at or above which the saves are
+    /// `max_interval_secs` apart.
+    pub high_throughput: u64,
+}
+
+impl Default for ConfigCheckpoints {
+    fn default() -> ConfigCheckpoints {
+        ConfigCheckpoints {
+            min_interval_secs: DEFAULT_CHECKPOINT_MIN_INTERVAL_SECS,
+            max_interval_secs: DEFAULT_CHECKPOINT_MAX_INTERVAL_SECS,
+            high_throughput: DEFAULT_CHECKPOINT_HIGH_THROUGHPUT,
+        }
+    }
+}
+
+/// Whether this manager shares the jobs database with other managers, and
+/// how it is known to them.  See the jobs::lease module.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConfigCoordination {
+    /// Run only the jobs that this manager holds leases on, and take over
+    /// those of managers whose leases have lapsed.
+    pub enabled: bool,
+
+    /// The name that the manager's leases are held under.  It should be
+    /// unique, and the same each time the manager starts, so that a manager
+    /// that is restarted picks up its own jobs at once.  If it is not given,
+    /// one is made up each time the manager starts.
+    pub manager_id: Option<String>,
+
+    /// The URL that the other managers list this manager's API at.
+    pub url: Option<String>,
+
+    /// Seconds that a lease lasts without being renewed.  Leases are renewed
+    /// three times in each of these.
+    pub lease_secs: u64,
+}
+
+impl Default for ConfigCoordination {
+    fn default() -> ConfigCoordination {
+        ConfigCoordination {
+            enabled: false,
+            manager_id: None,
+            url: None,
+            lease_secs: DEFAULT_LEASE_SECS,
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     pub domain_name: String,
 
@@ -112,20 +960,92 @@ pub struct Config {
     #[serde(default)]
     pub snaplink_cleanup_required: bool,
 
+    /// A file describing the destination sharks, used in place of storinfo.
+    /// See storinfo::SharksFile.
+    #[serde(default)]
+    pub sharks_file: Option<String>,
+
     #[serde(default)]
     pub options: ConfigOptions,
 
+    #[serde(default)]
+    pub notifications: ConfigNotifications,
+
+    #[serde(default)]
+    pub alerts: ConfigAlerts,
+
+    #[serde(default)]
+    pub retention: ConfigRetention,
+
+    #[serde(default)]
+    pub job_logs: ConfigJobLogs,
+
+    #[serde(default)]
+    pub hooks: Vec<ConfigHook>,
+
+    #[serde(default)]
+    pub ramp: ConfigRamp,
+
+    #[serde(default)]
+    pub database: ConfigDatabase,
+
+    #[serde(default)]
+    pub agent_client: ConfigAgentClient,
+
+    #[serde(default)]
+    pub assignment_sizing: ConfigAssignmentSizing,
+
+    #[serde(default)]
+    pub polling: ConfigPolling,
+
+    #[serde(default)]
+    pub circuit_breaker: ConfigCircuitBreaker,
+
+    #[serde(default)]
+    pub agent_history: ConfigAgentHistory,
+
+    #[serde(default)]
+    pub agent_quarantine: ConfigAgentQuarantine,
+
+    #[serde(default)]
+    pub verification: ConfigVerification,
+
+    #[serde(default)]
+    pub storinfo: ConfigStorinfo,
+
+    #[serde(default)]
+    pub checkpoints: ConfigCheckpoints,
+
+    /// Changes require a service restart.
+    #[serde(default)]
+    pub coordination: ConfigCoordination,
+
+    /// How the metrics are made available.  Changes require a service
+    /// restart.
+    #[serde(default)]
+    pub metrics: ConfigMetrics,
+
     #[serde(default = "Config::default_port")]
     pub listen_port: u16,
 
     #[serde(default = "Config::default_max_fill_percentage")]
     pub max_fill_percentage: u32,
 
+    #[serde(default = "Config::default_destination_concentration_percentage")]
+    pub destination_concentration_percentage: u32,
+
     #[serde(
         deserialize_with = "log_level_deserialize",
+        serialize_with = "log_level_serialize",
         default = "Config::default_log_level"
     )]
     pub log_level: Level,
+
+    /// Warnings about deprecated or unknown keys found while parsing the
+    /// configuration file.  These are held on to so that they can be logged
+    /// once the logger has been initialized.
+    #[serde(skip)]
+    pub notices: Vec<String>,
 }
 
 impl Default for Config {
@@ -134,14 +1054,71 @@ impl Default for Config {
             domain_name: String::new(),
             shards: vec![],
             snaplink_cleanup_required: false,
+            sharks_file: None,
             options: ConfigOptions::default(),
+            notifications: ConfigNotifications::default(),
+            alerts: ConfigAlerts::default(),
+            retention: ConfigRetention::default(),
+            job_logs: ConfigJobLogs::default(),
+            hooks: vec![],
+            ramp: ConfigRamp::default(),
+            database: ConfigDatabase::default(),
+            agent_client: ConfigAgentClient::default(),
+            assignment_sizing: ConfigAssignmentSizing::default(),
+            polling: ConfigPolling::default(),
+            circuit_breaker: ConfigCircuitBreaker::default(),
+            agent_history: ConfigAgentHistory::default(),
+            agent_quarantine: ConfigAgentQuarantine::default(),
+            verification: ConfigVerification::default(),
+            storinfo: ConfigStorinfo::default(),
+            checkpoints: ConfigCheckpoints::default(),
+            coordination: ConfigCoordination::default(),
+            metrics: ConfigMetrics::default(),
             listen_port: 80,
             max_fill_percentage: 100,
+            destination_concentration_percentage:
+                DEFAULT_DESTINATION_CONCENTRATION_PERCENTAGE,
             log_level: Level::Debug,
+            notices: vec![],
+        }
+    }
+}
+
+// The keys whose values differ between two configurations, as JSON.  Objects
+// are compared key by key, and anything else (including arrays) as a whole.
+fn changed_keys(old: &Value, new: &Value, prefix: &str) -> Vec<String> {
+    match (old, new) {
+        (Value::Object(o), Value::Object(n)) => {
+            let mut keys: Vec<&String> = o.keys().chain(n.keys()).collect();
+            keys.sort();
+            keys.dedup();
+
+            keys.into_iter()
+                .flat_map(|k| {
+                    let key = if prefix.is_empty() {
+                        k.clone()
+                    } else {
+                        format!("{}.{}", prefix, k)
+                    };
+                    changed_keys(
+                        o.get(k).unwrap_or(&Value::Null),
+                        n.get(k).unwrap_or(&Value::Null),
+                        &key,
+                    )
+                })
+                .collect()
         }
+        _ if old == new => vec![],
+        _ => vec![prefix.to_string()],
     }
 }
 
+fn is_restart_required(key: &str) -> bool {
+    RESTART_REQUIRED.iter().any(|r| {
+        key == *r || (key.starts_with(r) && key[r.len()..].starts_with('.'))
+    })
+}
+
 fn log_level_deserialize<'de, D>(deserializer: D) -> Result<Level, D::Error>
 where
     D: Deserializer<'de>,
@@ -161,6 +1138,16 @@ where
     }
 }
 
+fn log_level_serialize<S>(
+    level: &Level,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&level.as_str().to_lowercase())
+}
+
 impl Config {
     /// This method assumes the `shards: Vec<Shard>` is sorted.
     pub fn min_shard_num(&self) -> u32 {
@@ -172,6 +1159,47 @@ impl Config {
         util::shard_host2num(self.shards.last().expect("last").host.as_str())
     }
 
+    /// The numbers of the configured shards, in order.
+    pub fn shard_nums(&self) -> Vec<u32> {
+        self.shards
+            .iter()
+            .map(|s| util::shard_host2num(s.host.as_str()))
+            .collect()
+    }
+
+    /// Keep only the shards from `min_shard` to `max_shard`, and of those only
+    /// the ones in `shards` unless it is empty, for a job that scans some of
+    /// the metadata tier rather than all of it.  The shards stay sorted.  It
+    /// is an error to list a shard that is not configured, or to leave no
+    /// shards at all.
+    pub fn select_shards(
+        &mut self,
+        min_shard: Option<u32>,
+        max_shard: Option<u32>,
+        shards: &[u32],
+    ) -> Result<(), String> {
+        let configured = self.shard_nums();
+        if let Some(missing) = shards.iter().find(|s| !configured.contains(s)) {
+            return Err(format!("Shard {} is not configured", missing));
+        }
+
+        let min_shard = min_shard.unwrap_or(0);
+        let max_shard = max_shard.unwrap_or(std::u32::MAX);
+
+        self.shards.retain(|s| {
+            let num = util::shard_host2num(s.host.as_str());
+            num >= min_shard
+                && num <= max_shard
+                && (shards.is_empty() || shards.contains(&num))
+        });
+
+        if self.shards.is_empty() {
+            return Err(String::from("No configured shards were selected"));
+        }
+
+        Ok(())
+    }
+
     fn default_port() -> u16 {
         80
     }
@@ -180,6 +1208,10 @@ impl Config {
         100
     }
 
+    fn default_destination_concentration_percentage() -> u32 {
+        DEFAULT_DESTINATION_CONCENTRATION_PERCENTAGE
+    }
+
     fn default_log_level() -> Level {
         Level::Debug
     }
@@ -190,48 +1222,275 @@ impl Config {
             .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
         let file = File::open(config_path)?;
         let reader = BufReader::new(file);
-        let mut config: Config = serde_json::from_reader(reader)?;
+        let mut raw: Value = serde_json::from_reader(reader)?;
+        let notices = CONFIG_SCHEMA.normalize(&mut raw);
+        let mut config: Config = serde_json::from_value(raw)?;
+
+        config.notices = notices;
+
+        // A hook that could never be run is dropped, rather than failing to
+        // start the manager over it.
+        let mut hooks = vec![];
+        for (i, hook) in config.hooks.drain(..).enumerate() {
+            match hook.validate() {
+                Ok(()) => hooks.push(hook),
+                Err(e) => {
+                    config.notices.push(format!("Ignoring hook {}: {}", i, e))
+                }
+            }
+        }
+        config.hooks = hooks;
 
         // Both min_shard_num() and max_shard_num() depend on this vector
         // being sorted.  Do not change or remove this line without making a
-        // complementary change to those two functions.
-        config
-            .shards
-            .sort_by_key(|s| util::shard_host2num(s.host.as_str()));
+        // complementary change to those two functions.  A host without a
+        // shard number is reported by validate().
+        config.shards.sort_by_key(|s| {
+            util::parse_shard_num(s.host.as_str()).unwrap_or(0)
+        });
 
         Ok(config)
     }
 
-    fn config_updater(
-        config_update_rx: crossbeam_channel::Receiver<()>,
-        update_config: Arc<Mutex<Config>>,
-        config_file: Option<String>,
-    ) -> JoinHandle<()> {
-        thread::Builder::new()
-            .name(String::from("config updater"))
-            .spawn(move || loop {
-                match config_update_rx.recv() {
-                    Ok(()) => {
-                        let new_config =
-                            match Config::parse_config(&config_file) {
-                                Ok(c) => c,
-                                Err(e) => {
-                                    error!(
-                                        "Error parsing config after signal \
-                                         received. Not updating: {}",
+    /// Check the values of the configuration against each other and against
+    /// this zone, returning a message for each one that is wrong.  This is
+    /// done before the manager starts anything, and by --validate-config.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut check = ConfigCheck::default();
+
+        check.domain_name("domain_name", &self.domain_name);
+
+        check.ensure(
+            !self.shards.is_empty(),
+            "shards",
+            "at least one shard is required",
+        );
+        let mut shard_nums = HashSet::new();
+        for shard in self.shards.iter() {
+            match util::parse_shard_num(&shard.host) {
+                Some(num) if !shard_nums.insert(num) => check.error(
+                    "shards",
+                    &format!("shard {} is listed more than once", num),
+                ),
+                Some(_) => check.domain_name("shards", &shard.host),
+                None => check.error(
+                    "shards",
+                    &format!(
+                        "'{}' does not start with a shard number, e.g. \
+                         1.moray.{}",
+                        shard.host, self.domain_name
+                    ),
+                ),
+            }
+        }
+
+        check.port("listen_port", self.listen_port);
+        self.metrics.check(&mut check);
+        check.ensure(
+            self.metrics.mode != MetricsMode::Http
+                || self.metrics.port != self.listen_port,
+            "metrics.port",
+            "must not be the same as listen_port",
+        );
+
+        check.percentage("max_fill_percentage", self.max_fill_percentage);
+        check.percentage(
+            "destination_concentration_percentage",
+            self.destination_concentration_percentage,
+        );
+
+        check.ensure(
+            self.assignment_sizing.min_tasks_per_assignment
+                <= self.assignment_sizing.max_tasks_per_assignment,
+            "assignment_sizing.min_tasks_per_assignment",
+            "must not be more than assignment_sizing.max_tasks_per_assignment",
+        );
+        check.ensure(
+            self.polling.min_poll_ms
+                <= self.polling.max_poll_secs.saturating_mul(1000),
+            "polling.min_poll_ms",
+            "must not be more than polling.max_poll_secs",
+        );
+        for (key, value) in &[
+            (
+                "agent_history.slow_fraction",
+                self.agent_history.slow_fraction,
+            ),
+            (
+                "agent_history.max_failure_ratio",
+                self.agent_history.max_failure_ratio,
+            ),
+        ] {
+            check.ensure(
+                *value >= 0.0 && *value <= 1.0,
+                key,
+                "must be a fraction from 0 to 1",
+            );
+        }
+
+        check.ensure(
+            self.options.max_resident_objects > 0,
+            "options.max_resident_objects",
+            "must be more than 0",
+        );
+
+        check.ensure(
+            !self.coordination.enabled || self.coordination.lease_secs >= 3,
+            "coordination.lease_secs",
+            "must be at least 3",
+        );
+        check.ensure(
+            self.coordination.manager_id.as_ref().map_or(true, |id| {
+                !id.is_empty()
+                    && id.len() <= 64
+                    && !id.contains(char::is_whitespace)
+            }),
+            "coordination.manager_id",
+            "must be from 1 to 64 characters, none of them spaces",
+        );
+
+        check.ensure(
+            self.agent_quarantine.max_failures == 0
+                || self.agent_quarantine.cooldown_secs > 0,
+            "agent_quarantine.cooldown_secs",
+            "must be more than 0 unless agent_quarantine.max_failures is 0",
+        );
+
+        // The archive directory is only needed on demand when jobs are kept
+        // forever, and archiving a job then reports its own error.
+        if self.retention.job_retention_days > 0 {
+            check.writable_dir(
+                "retention.archive_dir",
+                &self.retention.archive_dir,
+            );
+        }
+        if self.job_logs.enabled {
+            check.writable_dir("job_logs.directory", &self.job_logs.directory);
+        }
+
+        if let Some(path) = &self.sharks_file {
+            if let Err(e) = storinfo::SharksFile::new(path) {
+                check.error("sharks_file", &e);
+            }
+        }
+
+        check.finish()
+    }
+
+    /// The configuration as it is actually in effect, after defaults have
+    /// been applied, with any secrets redacted.
+    pub fn effective(&self) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        config_schema::redact(&mut value);
+        value
+    }
+
+    /// Take on the settings of `new`, a reloaded configuration, except for
+    /// those that only take effect when the manager is restarted.
+    pub fn reload(&mut self, mut new: Config) -> ConfigReload {
+        let old_value = serde_json::to_value(&*self).unwrap_or(Value::Null);
+        let new_value = serde_json::to_value(&new).unwrap_or(Value::Null);
+        let mut reload = ConfigReload::default();
+
+        for key in changed_keys(&old_value, &new_value, "") {
+            if is_restart_required(&key) {
+                reload.restart_required.push(key);
+            } else {
+                reload.applied.push(key);
+            }
+        }
+
+        new.domain_name = self.domain_name.clone();
+        new.listen_port = self.listen_port;
+        new.database = self.database.clone();
+        new.agent_client = self.agent_client;
+        new.metrics = ConfigMetrics {
+            datacenter: new.metrics.datacenter,
+            service: new.metrics.service,
+            server: new.metrics.server,
+            ..self.metrics.clone()
+        };
+        new.storinfo = ConfigStorinfo {
+            poll_interval_secs: new.storinfo.poll_interval_secs,
+            ..self.storinfo
+        };
+
+        *self = new;
+        reload
+    }
+
+    /// Log any problems found with the configuration file followed by the
+    /// effective configuration.
+    pub fn log_effective(&self) {
+        for notice in self.notices.iter() {
+            warn!("{}", notice);
+        }
+
+        info!("Effective configuration: {}", self.effective());
+    }
+
+    fn config_updater(
+        config_update_rx: crossbeam_channel::Receiver<()>,
+        update_config: Arc<Mutex<Config>>,
+        config_file: Option<String>,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(String::from("config updater"))
+            .spawn(move || loop {
+                match config_update_rx.recv() {
+                    Ok(()) => {
+                        let new_config =
+                            match Config::parse_config(&config_file) {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    error!(
+                                        "Error parsing config after signal \
+                                         received. Not updating: {}",
                                         e
                                     );
                                     continue;
                                 }
                             };
+                        if let Err(errors) = new_config.validate() {
+                            error!(
+                                "Invalid config after signal received. Not \
+                                 updating: {}",
+                                errors.join("; ")
+                            );
+                            continue;
+                        }
                         let mut config_lock =
                             update_config.lock().expect("Lock update_config");
 
-                        *config_lock = new_config;
-                        debug!(
-                            "Configuration has been updated: {:#?}",
-                            *config_lock
+                        let reload = config_lock.reload(new_config);
+
+                        // These are held outside of the configuration, so
+                        // they are updated here.
+                        util::set_log_level(config_lock.log_level);
+                        storinfo::set_poll_interval(
+                            config_lock.storinfo.poll_interval_secs,
                         );
+                        bandwidth::set_max_bytes_per_second(
+                            config_lock.options.max_aggregate_bytes_per_second,
+                        );
+                        quarantine::configure(&config_lock.agent_quarantine);
+                        metrics::set_const_labels(&config_lock.metrics);
+
+                        info!("Configuration has been updated");
+                        if !reload.applied.is_empty() {
+                            info!(
+                                "Configuration changes applied: {}",
+                                reload.applied.join(", ")
+                            );
+                        }
+                        if !reload.restart_required.is_empty() {
+                            warn!(
+                                "Configuration changes that require a \
+                                 restart, not applied: {}",
+                                reload.restart_required.join(", ")
+                            );
+                        }
+                        config_lock.log_effective();
                     }
                     Err(e) => {
                         warn!(
@@ -247,7 +1506,8 @@ impl Config {
     }
 
     // Run a thread that listens for the SIGUSR1 signal which config-agent
-    // should be sending us via SMF when the config file is updated.  When a
+    // should be sending us via SMF when the config file is updated, or for
+    // SIGHUP, which an operator may send after editing it.  When a
     // signal is trapped it simply sends an empty message to the updater thread
     // which handles updating the configuration state in memory.  We don't want
     // to block or take any locks here because the signal is asynchronous.
@@ -264,9 +1524,9 @@ impl Config {
     }
 
     // This thread spawns two other threads.  One of them handles the SIGUSR1
-    // signal and in turn notifies the other that the config file needs to be
-    // re-parsed.  This function returns a JoinHandle that will only join
-    // after both of the other threads have completed.
+    // and SIGHUP signals and in turn notifies the other that the config file
+    // needs to be re-parsed.  This function returns a JoinHandle that will
+    // only join after both of the other threads have completed.
     pub fn start_config_watcher(
         config: Arc<Mutex<Config>>,
         config_file: Option<String>,
@@ -301,15 +1561,15 @@ fn _config_update_signal_handler(
     config_update_tx: crossbeam_channel::Sender<()>,
     update_barrier: Arc<Barrier>,
 ) {
-    let signals =
-        Signals::new(&[signal_hook::SIGUSR1]).expect("register signals");
+    let signals = Signals::new(&[signal_hook::SIGUSR1, signal_hook::SIGHUP])
+        .expect("register signals");
 
     update_barrier.wait();
 
     for signal in signals.forever() {
         trace!("Signal Received: {}", signal);
         match signal {
-            signal_hook::SIGUSR1 => {
+            signal_hook::SIGUSR1 | signal_hook::SIGHUP => {
                 // If there is already a message in the buffer
                 // (i.e. TrySendError::Full), then the updater
                 // thread will be doing an update anyway so no
@@ -339,6 +1599,8 @@ mod tests {
     use lazy_static::lazy_static;
     use libc;
     use mustache::MapBuilder;
+    use rebalancer::common::Task;
+    use rebalancer::metrics::MetricsMode;
     use std::fs::File;
     use std::io::Read;
 
@@ -414,6 +1676,27 @@ mod tests {
 
         assert_eq!(config.min_shard_num(), 2);
         assert_eq!(config.max_shard_num(), 1000);
+        assert_eq!(config.shard_nums(), vec![2, 99, 100, 200, 1000]);
+
+        let mut selected = config.clone();
+        assert!(selected.select_shards(Some(50), Some(500), &[]).is_ok());
+        assert_eq!(selected.shard_nums(), vec![99, 100, 200]);
+
+        let mut selected = config.clone();
+        assert!(selected.select_shards(None, None, &[1000, 2]).is_ok());
+        assert_eq!(selected.min_shard_num(), 2);
+        assert_eq!(selected.max_shard_num(), 1000);
+        assert_eq!(selected.shard_nums(), vec![2, 1000]);
+
+        let mut selected = config.clone();
+        assert!(selected.select_shards(Some(100), None, &[2, 200]).is_ok());
+        assert_eq!(selected.shard_nums(), vec![200]);
+
+        assert!(config.clone().select_shards(None, None, &[3]).is_err());
+        assert!(config
+            .clone()
+            .select_shards(Some(300), Some(400), &[])
+            .is_err());
 
         config_fini();
     }
@@ -474,6 +1757,26 @@ mod tests {
         assert_eq!(config.options.max_metadata_update_threads, 2222);
         assert_eq!(config.options.max_sharks, 3333);
         assert_eq!(config.options.use_static_md_update_threads, false);
+        assert_eq!(config.options.use_sharded_md_updates, false);
+        assert_eq!(config.options.require_confirmation, false);
+        assert_eq!(config.options.verify_before_update, false);
+        assert_eq!(config.options.header_check_percent, 0);
+        assert_eq!(config.options.trace_placement, false);
+        assert_eq!(config.options.checksum_policy, ChecksumPolicy::Skip);
+        assert_eq!(config.options.max_record_bytes, DEFAULT_MAX_RECORD_BYTES);
+        assert_eq!(
+            config.options.max_resident_objects,
+            DEFAULT_MAX_RESIDENT_OBJECTS
+        );
+        assert_eq!(
+            config.options.max_etag_conflict_retries,
+            DEFAULT_MAX_ETAG_CONFLICT_RETRIES
+        );
+        assert_eq!(config.options.max_aggregate_bytes_per_second, 0);
+        assert_eq!(config.options.resolve_snaplinks, false);
+        assert_eq!(config.options.task_order, None);
+        assert_eq!(config.options.compress_transfers, false);
+        assert_eq!(config.options.direct_pull, false);
         assert_eq!(
             config.options.static_queue_depth,
             DEFAULT_STATIC_QUEUE_DEPTH
@@ -486,6 +1789,826 @@ mod tests {
         config_fini();
     }
 
+    #[test]
+    fn config_schema_test() {
+        unit_test_init();
+
+        let file_contents = r#"{
+                "options": {
+                    "max_sharks": 3333,
+                    "max_shark": 4444
+                },
+                "max_fill_precentage": 80,
+                "domain_name": "perf1.scloud.host",
+                "shards": [
+                    {
+                        "host": "1.moray.perf1.scloud.host"
+                    }
+                ]
+            }
+        "#;
+
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+        let config = write_config_file(file_contents.as_bytes());
+
+        // The deprecated key is applied under its new name.
+        assert_eq!(config.max_fill_percentage, 80);
+        assert_eq!(config.options.max_sharks, 3333);
+
+        assert_eq!(config.notices.len(), 2);
+        assert!(config
+            .notices
+            .iter()
+            .any(|n| n.contains("max_fill_precentage")));
+        assert!(config
+            .notices
+            .iter()
+            .any(|n| n.contains("options.max_shark'")));
+
+        // The defaults are included in the effective configuration.
+        let effective = config.effective();
+        assert_eq!(effective["max_fill_percentage"], 80);
+        assert_eq!(effective["listen_port"], 80);
+        assert_eq!(
+            effective["destination_concentration_percentage"],
+            DEFAULT_DESTINATION_CONCENTRATION_PERCENTAGE
+        );
+        assert_eq!(effective["log_level"], "debug");
+        assert_eq!(
+            effective["options"]["max_concurrent_jobs"],
+            DEFAULT_MAX_CONCURRENT_JOBS
+        );
+
+        config_fini();
+    }
+
+    #[test]
+    fn notifications_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_WEBHOOK_URL", "http://hooks.local/a?b&c")
+            .insert_str("REBALANCER_WEBHOOK_ERROR_THRESHOLDS", "10, 100")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(
+            config.notifications.webhooks,
+            vec!["http://hooks.local/a?b&c".to_string()]
+        );
+        assert_eq!(config.notifications.error_thresholds, vec![10, 100]);
+        assert!(config.notices.is_empty());
+
+        // Notifications are off unless a webhook is configured.
+        let config = config_init();
+        assert!(config.notifications.webhooks.is_empty());
+        assert!(config.notifications.error_thresholds.is_empty());
+
+        config_fini();
+    }
+
+    #[test]
+    fn retention_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_JOB_RETENTION_DAYS", "30")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.retention.job_retention_days, 30);
+        assert_eq!(config.retention.archive_dir, DEFAULT_JOB_ARCHIVE_DIR);
+        assert!(config.notices.is_empty());
+
+        // Finished jobs are kept forever unless a retention is configured.
+        let config = config_init();
+        assert_eq!(config.retention.job_retention_days, 0);
+
+        config_fini();
+    }
+
+    #[test]
+    fn job_logs_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_bool("REBALANCER_JOB_LOGS", true)
+            .insert_str("REBALANCER_JOB_LOG_MAX_MB", "10")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert!(config.job_logs.enabled);
+        assert_eq!(config.job_logs.directory, DEFAULT_JOB_LOG_DIR);
+        assert_eq!(config.job_logs.max_size_mb, 10);
+        assert_eq!(config.job_logs.max_files, DEFAULT_JOB_LOG_MAX_FILES);
+        assert!(config.notices.is_empty());
+
+        // Jobs only log to the manager's log unless job logs are enabled.
+        let config = config_init();
+        assert!(!config.job_logs.enabled);
+
+        config_fini();
+    }
+
+    #[test]
+    fn hooks_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_JOB_HOOK_URL", "http://hooks.local/a?b&c")
+            .insert_str("REBALANCER_JOB_HOOK_TIMEOUT_SECS", "5")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.hooks.len(), 1);
+        let hook = &config.hooks[0];
+        assert_eq!(hook.url, Some("http://hooks.local/a?b&c".to_string()));
+        assert!(hook.command.is_empty());
+        assert_eq!(hook.timeout_secs, 5);
+        assert_eq!(hook.attempts, DEFAULT_HOOK_ATTEMPTS);
+        assert_eq!(
+            hook.on,
+            vec![HookEvent::Complete, HookEvent::Failed, HookEvent::Paused]
+        );
+        assert!(config.notices.is_empty());
+
+        // No hooks are run unless one is configured.
+        let config = config_init();
+        assert!(config.hooks.is_empty());
+
+        config_fini();
+    }
+
+    #[test]
+    fn hook_validation() {
+        let hook = ConfigHook::default();
+        assert!(hook.validate().is_err());
+
+        let hook = ConfigHook {
+            command: vec![String::from("/opt/site/bin/job-done")],
+            ..Default::default()
+        };
+        assert!(hook.validate().is_ok());
+
+        let hook = ConfigHook {
+            command: vec![String::from("/opt/site/bin/job-done")],
+            url: Some(String::from("http://hooks.local")),
+            ..Default::default()
+        };
+        assert!(hook.validate().is_err());
+
+        let hook = ConfigHook {
+            url: Some(String::from("http://hooks.local")),
+            timeout_secs: 0,
+            ..Default::default()
+        };
+        assert!(hook.validate().is_err());
+    }
+
+    #[test]
+    fn ramp_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_RAMP_MINUTES", "15")
+            .insert_str("REBALANCER_RAMP_START_PCT", "10")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.ramp.ramp_minutes, 15);
+        assert_eq!(config.ramp.start_percentage, 10);
+        assert_eq!(config.ramp.max_error_ratio, DEFAULT_RAMP_MAX_ERROR_RATIO);
+        assert!(config.notices.is_empty());
+
+        // Jobs start at full concurrency unless a ramp is configured.
+        let config = config_init();
+        assert_eq!(config.ramp.ramp_minutes, 0);
+
+        config_fini();
+    }
+
+    #[test]
+    fn database_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_DB_HOST", "db.fake.joyent.us")
+            .insert_str("REBALANCER_DB_PORT", "5433")
+            .insert_str("REBALANCER_DB_PASSWORD", "hunter2")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.database.host, "db.fake.joyent.us");
+        assert_eq!(config.database.port, Some(5433));
+        assert_eq!(config.database.user, "postgres");
+        assert_eq!(config.database.password, "hunter2");
+        assert!(config.notices.is_empty());
+
+        // The password is not given out with the effective configuration.
+        assert_ne!(config.effective()["database"]["password"], "hunter2");
+
+        // The local server is used unless a host is configured.
+        let config = config_init();
+        assert!(config.database.host.is_empty());
+
+        config_fini();
+    }
+
+    #[test]
+    fn agent_client_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_AGENT_POOL_SIZE", "4")
+            .insert_bool("REBALANCER_AGENT_DISABLE_KEEP_ALIVE", true)
+            .insert_str("REBALANCER_AGENT_REQUEST_TIMEOUT_SECS", "120")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.agent_client.pool_size, 4);
+        assert!(!config.agent_client.keep_alive);
+        assert_eq!(
+            config.agent_client.connect_timeout_secs,
+            DEFAULT_AGENT_CONNECT_TIMEOUT_SECS
+        );
+        assert_eq!(config.agent_client.request_timeout_secs, 120);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(config.agent_client.pool_size, DEFAULT_AGENT_POOL_SIZE);
+        assert!(config.agent_client.keep_alive);
+
+        config_fini();
+    }
+
+    #[test]
+    fn assignment_sizing_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_bool("REBALANCER_ADAPTIVE_ASSIGNMENTS", true)
+            .insert_str("REBALANCER_MAX_ADAPTIVE_TASKS", "500")
+            .insert_str("REBALANCER_TARGET_ASSIGNMENT_SECS", "60")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert!(config.assignment_sizing.adaptive);
+        assert_eq!(config.assignment_sizing.max_tasks_per_assignment, 500);
+        assert_eq!(
+            config.assignment_sizing.min_tasks_per_assignment,
+            DEFAULT_MIN_ADAPTIVE_TASKS
+        );
+        assert_eq!(config.assignment_sizing.target_assignment_secs, 60);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert!(!config.assignment_sizing.adaptive);
+
+        config_fini();
+    }
+
+    #[test]
+    fn task_order_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_TASK_ORDER", "smallest_first")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+        assert_eq!(config.options.task_order, Some(TaskOrder::SmallestFirst));
+
+        let task = |id: &str, size: Option<u64>| Task {
+            object_id: id.to_string(),
+            content_length: size,
+            ..Default::default()
+        };
+        let ids = |tasks: &[Task]| -> Vec<String> {
+            tasks.iter().map(|t| t.object_id.clone()).collect()
+        };
+        let received = vec![
+            task("a", Some(20)),
+            task("b", None),
+            task("c", Some(10)),
+            task("d", Some(20)),
+        ];
+
+        // Objects of the same size keep the order that they were sent in, and
+        // those of unknown size go last.
+        let mut tasks = received.clone();
+        TaskOrder::SmallestFirst.sort(&mut tasks);
+        assert_eq!(ids(&tasks), vec!["c", "a", "d", "b"]);
+
+        let mut tasks = received.clone();
+        TaskOrder::LargestFirst.sort(&mut tasks);
+        assert_eq!(ids(&tasks), vec!["a", "d", "c", "b"]);
+
+        let mut tasks = received.clone();
+        TaskOrder::Received.sort(&mut tasks);
+        assert_eq!(ids(&tasks), ids(&received));
+
+        let config = config_init();
+        assert_eq!(config.options.task_order, None);
+
+        config_fini();
+    }
+
+    #[test]
+    fn polling_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_MAX_POLL_SECS", "120")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.polling.max_poll_secs, 120);
+        assert_eq!(config.polling.min_poll_ms, DEFAULT_MIN_POLL_MS);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(config.polling.max_poll_secs, DEFAULT_MAX_POLL_SECS);
+
+        config_fini();
+    }
+
+    #[test]
+    fn circuit_breaker_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_MAX_ERROR_RATE", "0.2")
+            .insert_str("REBALANCER_MAX_CONSECUTIVE_ERRORS", "500")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.circuit_breaker.max_error_rate, 0.2);
+        assert_eq!(config.circuit_breaker.max_consecutive_errors, 500);
+        assert_eq!(
+            config.circuit_breaker.min_objects,
+            DEFAULT_BREAKER_MIN_OBJECTS
+        );
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(
+            config.circuit_breaker.max_error_rate,
+            DEFAULT_BREAKER_MAX_ERROR_RATE
+        );
+        assert_eq!(config.circuit_breaker.max_consecutive_errors, 0);
+
+        config_fini();
+    }
+
+    #[test]
+    fn agent_quarantine_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_AGENT_QUARANTINE_FAILURES", "3")
+            .insert_str("REBALANCER_AGENT_QUARANTINE_SECS", "120")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.agent_quarantine.max_failures, 3);
+        assert_eq!(config.agent_quarantine.cooldown_secs, 120);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(
+            config.agent_quarantine,
+            ConfigAgentQuarantine {
+                max_failures: DEFAULT_QUARANTINE_MAX_FAILURES,
+                cooldown_secs: DEFAULT_QUARANTINE_COOLDOWN_SECS,
+            }
+        );
+
+        config_fini();
+    }
+
+    #[test]
+    fn verification_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_bool("REBALANCER_AGENTLESS", true)
+            .insert_str("REBALANCER_VERIFY_METHOD", "get")
+            .insert_str("REBALANCER_VERIFY_THREADS", "32")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert!(config.verification.agentless);
+        assert_eq!(config.verification.method, VerifyMethod::Get);
+        assert_eq!(config.verification.threads, 32);
+        assert_eq!(
+            config.verification.timeout_secs,
+            DEFAULT_VERIFY_TIMEOUT_SECS
+        );
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert!(!config.verification.agentless);
+        assert_eq!(config.verification.method, VerifyMethod::Head);
+        assert_eq!(config.verification.threads, DEFAULT_VERIFY_THREADS);
+
+        config_fini();
+    }
+
+    #[test]
+    fn storinfo_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_STORINFO_POLL_SECS", "30")
+            .insert_str("REBALANCER_STORINFO_PAGE_SIZE", "500")
+            .insert_bool("REBALANCER_STORINFO_WATCH_CHANGES", true)
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.storinfo.poll_interval_secs, 30);
+        assert_eq!(config.storinfo.page_size, 500);
+        assert_eq!(
+            config.storinfo.min_request_interval_ms,
+            DEFAULT_STORINFO_MIN_REQUEST_INTERVAL_MS
+        );
+        assert!(config.storinfo.watch_changes);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(
+            config.storinfo.poll_interval_secs,
+            DEFAULT_STORINFO_POLL_INTERVAL_SECS
+        );
+        assert_eq!(config.storinfo.page_size, DEFAULT_STORINFO_PAGE_SIZE);
+        assert!(!config.storinfo.watch_changes);
+
+        config_fini();
+    }
+
+    #[test]
+    fn checkpoints_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_CHECKPOINT_MAX_SECS", "300")
+            .insert_str("REBALANCER_CHECKPOINT_HIGH_THROUGHPUT", "5000")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.checkpoints.max_interval_secs, 300);
+        assert_eq!(config.checkpoints.high_throughput, 5000);
+        assert_eq!(
+            config.checkpoints.min_interval_secs,
+            DEFAULT_CHECKPOINT_MIN_INTERVAL_SECS
+        );
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(
+            config.checkpoints.max_interval_secs,
+            DEFAULT_CHECKPOINT_MAX_INTERVAL_SECS
+        );
+
+        config_fini();
+    }
+
+    #[test]
+    fn coordination_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_bool("REBALANCER_COORDINATION", true)
+            .insert_str("REBALANCER_LEASE_SECS", "60")
+            .insert_map("auto", |bld| {
+                bld.insert_str(
+                    "ZONENAME",
+                    "b5d4ad3e-0fc5-4e3c-8d0a-507f5d1e8a61",
+                )
+                .insert_str("MANTA_IP", "10.1.1.2")
+            })
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert!(config.coordination.enabled);
+        assert_eq!(config.coordination.lease_secs, 60);
+        assert_eq!(
+            config.coordination.manager_id.as_ref().map(String::as_str),
+            Some("b5d4ad3e-0fc5-4e3c-8d0a-507f5d1e8a61")
+        );
+        assert_eq!(
+            config.coordination.url.as_ref().map(String::as_str),
+            Some("http://10.1.1.2")
+        );
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert!(!config.coordination.enabled);
+        assert_eq!(config.coordination.lease_secs, DEFAULT_LEASE_SECS);
+        assert_eq!(config.coordination.manager_id, None);
+
+        config_fini();
+    }
+
+    #[test]
+    fn metrics_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_METRICS_MODE", "statsd")
+            .insert_str("REBALANCER_METRICS_STATSD_ADDRESS", "10.1.1.1:8125")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+
+        assert_eq!(config.metrics.mode, MetricsMode::Statsd);
+        assert_eq!(config.metrics.statsd_address, "10.1.1.1:8125");
+        assert_eq!(config.metrics.port, ConfigMetrics::default().port);
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert_eq!(config.metrics.mode, MetricsMode::Http);
+
+        config_fini();
+    }
+
+    #[test]
+    fn validate_test() {
+        unit_test_init();
+        let mut config = config_init();
+        assert!(config.validate().is_ok());
+
+        config.domain_name = "fake..joyent.us".to_string();
+        config.shards.push(Shard {
+            host: "1.fake.joyent.us".to_string(),
+        });
+        config.shards.push(Shard {
+            host: "moray.fake.joyent.us".to_string(),
+        });
+        config.listen_port = config.metrics.port;
+        config.max_fill_percentage = 0;
+        config.assignment_sizing.min_tasks_per_assignment =
+            config.assignment_sizing.max_tasks_per_assignment + 1;
+        config.options.max_resident_objects = 0;
+        config.coordination.enabled = true;
+        config.coordination.lease_secs = 1;
+        config.retention.job_retention_days = 1;
+        config.retention.archive_dir = TEST_CONFIG_FILE.to_string();
+
+        let errors = config.validate().expect_err("invalid config");
+        let expected = [
+            "domain_name: 'fake..joyent.us' is not a valid domain name",
+            "shards: shard 1 is listed more than once",
+            "shards: 'moray.fake.joyent.us' does not start with a shard number",
+            "metrics.port: must not be the same as listen_port",
+            "max_fill_percentage: must be a percentage from 1 to 100, not 0",
+            "assignment_sizing.min_tasks_per_assignment: must not be more",
+            "options.max_resident_objects: must be more than 0",
+            "coordination.lease_secs: must be at least 3",
+            "retention.archive_dir:",
+        ];
+
+        assert_eq!(errors.len(), expected.len(), "{:?}", errors);
+        for (error, prefix) in errors.iter().zip(expected.iter()) {
+            assert!(error.starts_with(prefix), "{}", error);
+        }
+
+        config_fini();
+    }
+
+    #[test]
+    fn reload_test() {
+        unit_test_init();
+        let mut config = config_init();
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_bool("SNAPLINK_CLEANUP_REQUIRED", true)
+            .insert_str("REBALANCER_LOG_LEVEL", "trace")
+            .insert_str("REBALANCER_MAX_TASKS_PER_ASSIGNMENT", "200")
+            .insert_str("REBALANCER_MAX_AGGREGATE_BYTES_PER_SECOND", "1000000")
+            .insert_str("REBALANCER_STORINFO_POLL_SECS", "60")
+            .insert_str("REBALANCER_STORINFO_PAGE_SIZE", "10")
+            .insert_str("REBALANCER_DB_HOST", "db.fake.joyent.us")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+        let new_config = update_test_config_with_vars(&vars);
+
+        let reload = config.reload(new_config);
+
+        assert_eq!(
+            reload.applied,
+            vec![
+                "log_level",
+                "options.max_aggregate_bytes_per_second",
+                "options.max_tasks_per_assignment",
+                "storinfo.poll_interval_secs",
+            ]
+        );
+        assert_eq!(
+            reload.restart_required,
+            vec!["database.host", "storinfo.page_size"]
+        );
+
+        // Only the changes that can be made at runtime are in effect.
+        assert_eq!(config.log_level, Level::Trace);
+        assert_eq!(config.options.max_tasks_per_assignment, 200);
+        assert_eq!(config.options.max_aggregate_bytes_per_second, 1000000);
+        assert_eq!(config.storinfo.poll_interval_secs, 60);
+        assert_eq!(config.storinfo.page_size, DEFAULT_STORINFO_PAGE_SIZE);
+        assert_eq!(config.database.host, ConfigDatabase::default().host);
+
+        // Reloading the same configuration changes nothing.
+        let same = config.clone();
+        assert_eq!(config.reload(same), ConfigReload::default());
+
+        config_fini();
+    }
+
+    #[test]
+    fn sharks_file_test() {
+        unit_test_init();
+        std::fs::remove_file(TEST_CONFIG_FILE).unwrap_or(());
+
+        let vars = MapBuilder::new()
+            .insert_str("DOMAIN_NAME", "fake.joyent.us")
+            .insert_str("REBALANCER_SHARKS_FILE", "/opt/smartdc/sharks.json")
+            .insert_vec("INDEX_MORAY_SHARDS", |builder| {
+                builder.push_map(|bld| {
+                    bld.insert_str("host", "1.fake.joyent.us")
+                        .insert_bool("last", true)
+                })
+            })
+            .build();
+
+        let config = update_test_config_with_vars(&vars);
+        assert_eq!(
+            config.sharks_file,
+            Some(String::from("/opt/smartdc/sharks.json"))
+        );
+        assert!(config.notices.is_empty());
+
+        let config = config_init();
+        assert!(config.sharks_file.is_none());
+
+        config_fini();
+    }
+
     #[test]
     fn missing_snaplink_cleanup_required() {
         unit_test_init();
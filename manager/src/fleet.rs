@@ -0,0 +1,331 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The status of every agent in the fleet, as reported by GET /agents.
+//
+// Whether the agent on a storage node was even running used to be found out by
+// logging in to the node.  Instead, the manager takes every storage node in
+// the list most recently received from storinfo, along with any agent that is
+// not in it but that a post has failed to or that is quarantined (see
+// jobs::quarantine), and asks each one for its GET /healthcheck, up to
+// PROBE_THREADS at a time.  An agent is reachable if it answered at all, even
+// if only to say that it is unhealthy.  Agents that predate the version and
+// uptime being reported by their healthcheck are reachable, with those left
+// out.
+//
+// Probes are made with the same client as every other request to an agent
+// (see agent_client), so they count towards the requests that may be in
+// flight to it, and time out as posts do.  Agents that have registered with
+// the manager (see jobs::registry) are listed along with their registration,
+// whether or not they are in the list from storinfo.
+
+use crate::agent_client;
+use crate::jobs::quarantine::{self, AgentStatus};
+use crate::jobs::registry::{self, RegisteredAgent};
+use crate::storinfo::StorageNode;
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+use threadpool::ThreadPool;
+
+// The most agents that are probed at once.
+static PROBE_THREADS: usize = 32;
+
+/// What an agent reports of itself in its GET /healthcheck.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct AgentProbe {
+    pub healthy: bool,
+    pub staging_free_mb: Option<u64>,
+    pub assignments_scheduled: usize,
+    pub assignments_running: usize,
+    pub version: Option<String>,
+    pub boot_id: Option<String>,
+    pub uptime_secs: Option<u64>,
+}
+
+/// An agent in the fleet, and what became of probing it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FleetAgent {
+    pub storage_id: String,
+
+    /// None for an agent that is not in the list from storinfo.
+    pub datacenter: Option<String>,
+    pub in_storinfo: bool,
+
+    /// The agent answered its healthcheck, whether or not it is healthy.
+    pub reachable: bool,
+
+    /// Why the agent could not be reached, or its answer not understood.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<AgentProbe>,
+
+    /// How posting assignments to the agent has gone, if any has failed or
+    /// it is quarantined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub posts: Option<AgentStatus>,
+
+    /// What the agent said of itself when it last registered, if it has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration: Option<RegisteredAgent>,
+}
+
+impl FleetAgent {
+    fn new(storage_id: &str) -> FleetAgent {
+        FleetAgent {
+            storage_id: storage_id.to_string(),
+            datacenter: None,
+            in_storinfo: false,
+            reachable: false,
+            error: None,
+            health: None,
+            posts: None,
+            registration: None,
+        }
+    }
+
+    fn is_quarantined(&self) -> bool {
+        self.posts
+            .as_ref()
+            .map_or(false, |p| p.quarantine.is_some())
+    }
+}
+
+/// The outcome of probing one agent: whether it answered, and what it said.
+pub type ProbeResult = (bool, Result<AgentProbe, String>);
+
+#[derive(Debug, Serialize)]
+pub struct FleetStatus {
+    pub total: usize,
+    pub reachable: usize,
+    pub healthy: usize,
+    pub quarantined: usize,
+    pub registered: usize,
+
+    /// The number of reachable agents running each version.
+    pub versions: BTreeMap<String, usize>,
+
+    /// Sorted by storage id.
+    pub agents: Vec<FleetAgent>,
+}
+
+impl FleetStatus {
+    fn new(agents: Vec<FleetAgent>) -> FleetStatus {
+        let mut versions = BTreeMap::new();
+
+        for agent in agents.iter().filter(|a| a.reachable) {
+            let version = agent
+                .health
+                .as_ref()
+                .and_then(|h| h.version.clone())
+                .unwrap_or_else(|| String::from("unknown"));
+            *versions.entry(version).or_insert(0) += 1;
+        }
+
+        FleetStatus {
+            total: agents.len(),
+            reachable: agents.iter().filter(|a| a.reachable).count(),
+            healthy: agents
+                .iter()
+                .filter(|a| a.health.as_ref().map_or(false, |h| h.healthy))
+                .count(),
+            quarantined: agents.iter().filter(|a| a.is_quarantined()).count(),
+            registered: agents
+                .iter()
+                .filter(|a| {
+                    a.registration.as_ref().map_or(false, |r| !r.lapsed)
+                })
+                .count(),
+            versions,
+            agents,
+        }
+    }
+}
+
+/// Every agent to be probed: those of `sharks`, and those of `known` and
+/// `registered` that are not among them, sorted by storage id.
+pub fn fleet_agents(
+    sharks: &[StorageNode],
+    known: Vec<AgentStatus>,
+    registered: Vec<RegisteredAgent>,
+) -> Vec<FleetAgent> {
+    let mut agents: HashMap<String, FleetAgent> = HashMap::new();
+
+    for shark in sharks.iter() {
+        let mut agent = FleetAgent::new(&shark.manta_storage_id);
+        agent.datacenter = Some(shark.datacenter.clone());
+        agent.in_storinfo = true;
+        agents.insert(shark.manta_storage_id.clone(), agent);
+    }
+
+    for status in known.into_iter() {
+        agents
+            .entry(status.storage_id.clone())
+            .or_insert_with(|| FleetAgent::new(&status.storage_id))
+            .posts = Some(status);
+    }
+
+    for registration in registered.into_iter() {
+        let storage_id = registration.registration.storage_id.clone();
+        agents
+            .entry(storage_id.clone())
+            .or_insert_with(|| FleetAgent::new(&storage_id))
+            .registration = Some(registration);
+    }
+
+    let mut agents: Vec<FleetAgent> =
+        agents.into_iter().map(|(_, a)| a).collect();
+    agents.sort_by(|a, b| a.storage_id.cmp(&b.storage_id));
+    agents
+}
+
+/// Record the outcome of probing `agent`.
+pub fn apply_probe(agent: &mut FleetAgent, result: ProbeResult) {
+    let (reachable, health) = result;
+
+    agent.reachable = reachable;
+    match health {
+        Ok(health) => agent.health = Some(health),
+        Err(e) => agent.error = Some(e),
+    }
+}
+
+// Ask the agent on `storage_id` for its healthcheck.  It answers with a 503
+// if it is unhealthy, with the same body.
+fn probe(storage_id: &str) -> ProbeResult {
+    let url = format!("http://{}:7878/healthcheck", storage_id);
+    let pool = agent_client::shared();
+    let client = pool.checkout(storage_id);
+
+    let mut response = match client.get(&url).send() {
+        Ok(r) => r,
+        Err(e) => return (false, Err(e.to_string())),
+    };
+
+    let status = response.status();
+    let health = response.json::<AgentProbe>().map_err(|e| {
+        format!("Unexpected healthcheck response ({}): {}", status, e)
+    });
+
+    (true, health)
+}
+
+/// Probe every agent of `sharks`, and every other that a post has failed to,
+/// that is quarantined or that has registered.
+pub fn probe_fleet(sharks: &[StorageNode]) -> FleetStatus {
+    let mut agents = fleet_agents(
+        sharks,
+        quarantine::shared().list(),
+        registry::shared().list(),
+    );
+    if agents.is_empty() {
+        return FleetStatus::new(agents);
+    }
+
+    let pool = ThreadPool::new(PROBE_THREADS.min(agents.len()));
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    for (i, agent) in agents.iter().enumerate() {
+        let tx = tx.clone();
+        let storage_id = agent.storage_id.clone();
+
+        pool.execute(move || {
+            let _ = tx.send((i, probe(&storage_id)));
+        });
+    }
+    drop(tx);
+
+    for (i, result) in rx.iter() {
+        let agent = &mut agents[i];
+
+        apply_probe(agent, result);
+        if let Some(e) = agent.error.as_ref() {
+            debug!("Probing agent {}: {}", agent.storage_id, e);
+        }
+    }
+
+    FleetStatus::new(agents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::quarantine::PostFailures;
+
+    fn node(id: &str, dc: &str) -> StorageNode {
+        StorageNode {
+            available_mb: 1000,
+            percent_used: 10,
+            filesystem: String::from("/manta"),
+            datacenter: dc.to_string(),
+            manta_storage_id: id.to_string(),
+            timestamp: 0,
+        }
+    }
+
+    fn status(id: &str) -> AgentStatus {
+        AgentStatus {
+            storage_id: id.to_string(),
+            failures: PostFailures::default(),
+            consecutive_failures: 3,
+            last_failure: Some(1),
+            quarantine: None,
+        }
+    }
+
+    #[test]
+    fn fleet_status() {
+        let sharks = vec![node("2.stor", "dc2"), node("1.stor", "dc1")];
+        let known = vec![status("2.stor"), status("3.stor")];
+        let mut agents = fleet_agents(&sharks, known, vec![]);
+
+        // Agents known only for their failed posts are listed too.
+        let ids: Vec<&str> =
+            agents.iter().map(|a| a.storage_id.as_str()).collect();
+        assert_eq!(ids, vec!["1.stor", "2.stor", "3.stor"]);
+        assert_eq!(agents[1].datacenter, Some(String::from("dc2")));
+        assert!(agents[1].posts.is_some());
+        assert!(!agents[2].in_storinfo);
+        assert!(agents[0].posts.is_none());
+
+        // An agent that predates reporting its version still parses.
+        let old: AgentProbe = serde_json::from_str(
+            "{\"healthy\": false, \"staging_free_mb\": 10, \
+             \"min_staging_free_mb\": 1024, \"assignments_scheduled\": 1, \
+             \"assignments_running\": 0}",
+        )
+        .expect("old healthcheck");
+        assert_eq!(old.version, None);
+
+        let new = AgentProbe {
+            healthy: true,
+            version: Some(String::from("0.1.0 (no-STAMP)")),
+            ..AgentProbe::default()
+        };
+
+        apply_probe(&mut agents[0], (true, Ok(new)));
+        apply_probe(&mut agents[1], (true, Ok(old)));
+        apply_probe(&mut agents[2], (false, Err(String::from("refused"))));
+
+        let fleet = FleetStatus::new(agents);
+        assert_eq!(fleet.total, 3);
+        assert_eq!(fleet.reachable, 2);
+        assert_eq!(fleet.healthy, 1);
+        assert_eq!(fleet.quarantined, 0);
+        assert_eq!(fleet.registered, 0);
+        assert_eq!(fleet.versions["0.1.0 (no-STAMP)"], 1);
+        assert_eq!(fleet.versions["unknown"], 1);
+        assert_eq!(fleet.agents[2].error, Some(String::from("refused")));
+    }
+}
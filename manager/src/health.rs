@@ -0,0 +1,99 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The health of the manager, as reported by GET /healthcheck.  The manager
+// is considered healthy if it can connect to its local database and, while
+// any job is running, if the list of sharks that jobs use to pick
+// destinations has been refreshed recently.  The number of outstanding
+// assignments is reported for information only.
+
+use crate::metrics::{metrics_gauge_get, ASSIGNMENTS_OUTSTANDING};
+use crate::pg_db::{connect_db, REBALANCER_DB};
+use crate::storinfo;
+
+use serde::Serialize;
+use std::time::Duration;
+
+// The storinfo poller refreshes the list of sharks every 10 seconds by
+// default, so this allows for a few consecutive failures to reach the
+// storinfo service.
+static STORINFO_MAX_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseHealth {
+    pub connected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManagerHealth {
+    pub healthy: bool,
+    pub database: DatabaseHealth,
+    pub running_jobs: usize,
+
+    /// Seconds since the list of sharks was last received from the storinfo
+    /// service, or None if it never has been.
+    pub storinfo_age: Option<u64>,
+    pub storinfo_fresh: bool,
+
+    pub assignments_outstanding: u64,
+}
+
+// Storinfo is only polled once a job has run, so with no jobs running an old
+// (or missing) list of sharks is expected.
+fn storinfo_fresh(running_jobs: usize, age: Option<Duration>) -> bool {
+    running_jobs == 0 || age.map_or(false, |a| a <= STORINFO_MAX_AGE)
+}
+
+impl ManagerHealth {
+    pub fn check(running_jobs: usize) -> ManagerHealth {
+        let database = match connect_db(REBALANCER_DB) {
+            Ok(_) => DatabaseHealth {
+                connected: true,
+                error: None,
+            },
+            Err(e) => DatabaseHealth {
+                connected: false,
+                error: Some(e.to_string()),
+            },
+        };
+
+        let age = storinfo::last_update_age();
+        let storinfo_fresh = storinfo_fresh(running_jobs, age);
+
+        ManagerHealth {
+            healthy: database.connected && storinfo_fresh,
+            database,
+            running_jobs,
+            storinfo_age: age.map(|a| a.as_secs()),
+            storinfo_fresh,
+            assignments_outstanding: metrics_gauge_get(ASSIGNMENTS_OUTSTANDING)
+                as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storinfo_age_only_matters_with_running_jobs() {
+        let old = Some(STORINFO_MAX_AGE * 2);
+        let recent = Some(Duration::from_secs(1));
+
+        assert!(storinfo_fresh(0, None));
+        assert!(storinfo_fresh(0, old));
+        assert!(storinfo_fresh(1, recent));
+        assert!(!storinfo_fresh(1, old));
+        assert!(!storinfo_fresh(2, None));
+    }
+}
@@ -0,0 +1,345 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Post-job hooks.
+//
+// Notifications (see the notify module) tell a webhook that something
+// happened to a job, but whatever has to be done about it once a job is
+// finished (updating an inventory, moving a ticket along, scheduling the
+// cleanup of an evacuated shark) still means going and getting the job's
+// status.  A hook is instead handed the status along with the event: it is
+// either a command, which is run with a JSON summary of the job on its
+// standard input, or a URL, which the summary is POSTed to.  Each hook is run
+// on the events it is configured for:
+//
+//  * complete: the job ran to completion, or, if it was awaiting
+//    confirmation, was confirmed.
+//  * failed: the job failed or was stopped.
+//  * paused: the job's circuit breaker paused it.
+//
+// Jobs that are interrupted by the manager shutting down are resumed when it
+// starts again, so they run no hooks.
+//
+// Hooks are run one at a time on a background thread, so that a job is never
+// held up by them.  A command succeeds if it exits 0 before its timeout (and
+// is killed if it does not), and a URL if it returns a 2xx status.  A hook
+// that does not succeed is tried again, up to its configured number of
+// attempts, and is then logged and given up on.
+
+use crate::config::{ConfigHook, HookEvent};
+use crate::jobs::status::{self, JobStatus};
+use rebalancer::util::now_ms;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use uuid::Uuid;
+
+// How often a running command is checked on.
+static COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a hook is given about the job that it is run for.
+#[derive(Debug, Serialize)]
+pub struct HookPayload {
+    pub job_id: String,
+    pub event: HookEvent,
+
+    /// Milliseconds since the epoch at which the event happened.
+    pub timestamp: u64,
+
+    /// For failed and paused events, the error that ended the job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// The job's status, as returned by GET /jobs/<uuid>.  This is null if
+    /// the status could not be looked up.
+    pub status: Option<JobStatus>,
+}
+
+struct HookRun {
+    hooks: Vec<ConfigHook>,
+    job_id: String,
+    event: HookEvent,
+    timestamp: u64,
+    error: Option<String>,
+}
+
+lazy_static! {
+    static ref HOOK_TX: Mutex<crossbeam_channel::Sender<HookRun>> =
+        Mutex::new(start_hook_thread());
+}
+
+fn start_hook_thread() -> crossbeam_channel::Sender<HookRun> {
+    let (tx, rx) = crossbeam_channel::unbounded::<HookRun>();
+
+    thread::Builder::new()
+        .name(String::from("job hooks"))
+        .spawn(move || {
+            while let Ok(run) = rx.recv() {
+                run_hooks(run);
+            }
+        })
+        .expect("start job hook thread");
+
+    tx
+}
+
+fn job_status(job_id: &str) -> Option<JobStatus> {
+    let uuid = match Uuid::from_str(job_id) {
+        Ok(uuid) => uuid,
+        Err(e) => {
+            warn!("Invalid job id {}: {}", job_id, e);
+            return None;
+        }
+    };
+
+    match status::get_job(uuid) {
+        Ok(status) => Some(status),
+        Err(e) => {
+            warn!("Could not get status of job {} for hooks: {:?}", job_id, e);
+            None
+        }
+    }
+}
+
+fn run_hooks(run: HookRun) {
+    // The status is only looked up here, rather than when the hooks are
+    // queued, so that the job is not held up by it.
+    let payload = HookPayload {
+        status: job_status(&run.job_id),
+        job_id: run.job_id,
+        event: run.event,
+        timestamp: run.timestamp,
+        error: run.error,
+    };
+
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Could not serialize hook payload: {}", e);
+            return;
+        }
+    };
+
+    for hook in run.hooks.iter() {
+        run_hook(hook, &payload, &body);
+    }
+}
+
+fn hook_name(hook: &ConfigHook) -> String {
+    match &hook.url {
+        Some(url) => url.clone(),
+        None => hook.command.join(" "),
+    }
+}
+
+fn run_hook(hook: &ConfigHook, payload: &HookPayload, body: &[u8]) {
+    let name = hook_name(hook);
+    let attempts = hook.attempts.max(1);
+
+    for attempt in 1..=attempts {
+        let res = match &hook.url {
+            Some(url) => post(hook, url, body),
+            None => run_command(hook, payload, body),
+        };
+
+        let err = match res {
+            Ok(()) => {
+                info!(
+                    "Ran {:?} hook for job {}: {}",
+                    payload.event, payload.job_id, name
+                );
+                return;
+            }
+            Err(e) => e,
+        };
+
+        warn!(
+            "Attempt {} of {} to run {:?} hook for job {} ({}) failed: {}",
+            attempt, attempts, payload.event, payload.job_id, name, err
+        );
+
+        if attempt < attempts {
+            thread::sleep(Duration::from_secs(hook.retry_delay_secs));
+        }
+    }
+
+    error!(
+        "Giving up on {:?} hook for job {}: {}",
+        payload.event, payload.job_id, name
+    );
+}
+
+fn post(hook: &ConfigHook, url: &str, body: &[u8]) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(hook.timeout_secs))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let resp = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_vec())
+        .send()
+        .map_err(|e| e.to_string())?;
+
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("status {}", resp.status()))
+    }
+}
+
+fn run_command(
+    hook: &ConfigHook,
+    payload: &HookPayload,
+    body: &[u8],
+) -> Result<(), String> {
+    let (program, args) = match hook.command.split_first() {
+        Some(argv) => argv,
+        None => return Err(String::from("no command")),
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .env("REBALANCER_JOB_ID", &payload.job_id)
+        .env("REBALANCER_JOB_EVENT", payload.event.as_str())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // The summary is written from a thread of its own so that a command
+    // that never reads its input can still be timed out.  A command is free
+    // not to read it, so failing to write it is not an error.
+    if let Some(mut stdin) = child.stdin.take() {
+        let body = body.to_vec();
+        thread::spawn(move || {
+            let _ = stdin.write_all(&body);
+        });
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(hook.timeout_secs);
+    loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(exit) if exit.success() => return Ok(()),
+            Some(exit) => return Err(format!("command {}", exit)),
+            None => (),
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!(
+                "timed out after {} seconds",
+                hook.timeout_secs
+            ));
+        }
+
+        thread::sleep(COMMAND_POLL_INTERVAL);
+    }
+}
+
+/// Queue the hooks configured for `event` to be run for the job `job_id`.
+/// This never blocks on the hooks themselves.
+pub fn run(
+    hooks: &[ConfigHook],
+    job_id: &str,
+    event: HookEvent,
+    error: Option<String>,
+) {
+    let hooks: Vec<ConfigHook> = hooks
+        .iter()
+        .filter(|h| h.on.contains(&event))
+        .cloned()
+        .collect();
+
+    if hooks.is_empty() {
+        return;
+    }
+
+    debug!(
+        "Running {} {:?} hooks for job {}",
+        hooks.len(),
+        event,
+        job_id
+    );
+
+    let run = HookRun {
+        hooks,
+        job_id: job_id.to_string(),
+        event,
+        timestamp: now_ms() as u64,
+        error,
+    };
+
+    if let Err(e) = HOOK_TX.lock().expect("hook lock").send(run) {
+        error!("Job hook thread has exited: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_hook(argv: &[&str], timeout_secs: u64) -> ConfigHook {
+        ConfigHook {
+            command: argv.iter().map(|a| a.to_string()).collect(),
+            timeout_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hook_commands() {
+        let payload = HookPayload {
+            job_id: String::from("job"),
+            event: HookEvent::Failed,
+            timestamp: 0,
+            error: Some(String::from("error")),
+            status: None,
+        };
+        let body = serde_json::to_vec(&payload).unwrap();
+
+        // The command is given the summary and the event in its environment.
+        let hook = command_hook(
+            &[
+                "sh",
+                "-c",
+                "grep -q '\"event\":\"failed\"' && \
+                 [ \"$REBALANCER_JOB_EVENT\" = failed ] && \
+                 [ \"$REBALANCER_JOB_ID\" = job ]",
+            ],
+            10,
+        );
+        assert_eq!(run_command(&hook, &payload, &body), Ok(()));
+
+        let hook = command_hook(&["sh", "-c", "exit 3"], 10);
+        assert!(run_command(&hook, &payload, &body).is_err());
+
+        // A command that does not finish in time is killed.
+        let hook = command_hook(&["sleep", "30"], 1);
+        let start = Instant::now();
+        assert!(run_command(&hook, &payload, &body)
+            .unwrap_err()
+            .starts_with("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let hook = command_hook(&["/nonexistent/hook"], 10);
+        assert!(run_command(&hook, &payload, &body).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// A log file for each job.
+//
+// The manager logs everything to one place, so the log of a manager that runs
+// several jobs at once has their messages interleaved, and picking one job's
+// messages out of it after the fact is painful.  With job logs enabled, each
+// job also writes its messages, in the same bunyan format, to a file of its
+// own in the configured directory (job-<uuid>.log), and each of its messages
+// carries the job's id in a `job` field in both places.
+//
+// The rebalancer's log macros log to whichever logger slog-scope has for the
+// current thread, so a job's messages are sent to its file by running each of
+// the job's threads in the scope of the job's logger.  The job's logger is
+// created along with the job (see JobBuilder), and a thread started by one of
+// the job's threads with inherit(), or with one of the watchdog's spawn
+// functions, logs where the thread that started it does.
+//
+// A job's log file is rotated once it reaches max_size_mb, with the rotated
+// files numbered from newest (job-<uuid>.log.1) to oldest, and only the newest
+// max_files of them kept.  Log files are not removed along with their job.
+
+use crate::config::ConfigJobLogs;
+use rebalancer::util;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use slog::{o, Drain, Duplicate, Level, Logger};
+
+/// The path of the log file of the job `job_id` in `dir`.
+pub fn log_path(dir: &str, job_id: &str) -> PathBuf {
+    Path::new(dir).join(format!("job-{}.log", job_id))
+}
+
+/// A logger for the job `job_id` that logs both where the current thread
+/// does and, if job logs are enabled, to the job's own log file.  If the
+/// file can not be opened the job only logs where the current thread does.
+pub fn job_logger(
+    config: &ConfigJobLogs,
+    level: Level,
+    job_id: &str,
+) -> Logger {
+    let current = slog_scope::logger();
+
+    if !config.enabled {
+        return current.new(o!("job" => job_id.to_string()));
+    }
+
+    let path = log_path(&config.directory, job_id);
+    let file = fs::create_dir_all(&config.directory).and_then(|_| {
+        RotatingFile::open(
+            &path,
+            config.max_size_mb * 1024 * 1024,
+            config.max_files,
+        )
+    });
+
+    match file {
+        Ok(file) => {
+            let file_log = util::create_bunyan_logger(file, level);
+            Logger::root(
+                Duplicate::new(current, file_log).ignore_res(),
+                o!("job" => job_id.to_string()),
+            )
+        }
+        Err(e) => {
+            error!("Could not open log file {}: {}", path.display(), e);
+            current.new(o!("job" => job_id.to_string()))
+        }
+    }
+}
+
+/// Wrap `f`, which is to be run on another thread, so that it logs where the
+/// current thread does.
+pub fn inherit<F, R>(f: F) -> impl FnOnce() -> R + Send + 'static
+where
+    F: FnOnce() -> R + Send + 'static,
+{
+    let log = slog_scope::logger();
+    move || slog_scope::scope(&log, f)
+}
+
+// A file that is rotated once it is `max_bytes` long.  A file is only rotated
+// between lines, so that no record is split across two files.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: u32,
+    // Whether the last byte written ended a line.
+    line_start: bool,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// The path of the `n`th newest rotated file of `path`.
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", n));
+    PathBuf::from(rotated)
+}
+
+// Remove a file that may well not exist.
+fn remove_if_present(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+impl RotatingFile {
+    fn open(
+        path: &Path,
+        max_bytes: u64,
+        max_files: u32,
+    ) -> io::Result<RotatingFile> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_bytes,
+            max_files,
+            line_start: true,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            remove_if_present(&self.path)?;
+        } else {
+            remove_if_present(&rotated_path(&self.path, self.max_files))?;
+            for n in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.max_bytes > 0 && self.size >= self.max_bytes
+        {
+            // This is called from within the logger, so there is nowhere to
+            // log a failure to.  The file goes on being written to instead.
+            if let Err(e) = self.rotate() {
+                eprintln!("Could not rotate {}: {}", self.path.display(), e);
+                self.size = 0;
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn rotate_between_lines() {
+        let dir = std::env::temp_dir()
+            .join(format!("rebalancer-joblog-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("job-test.log");
+
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        // A line is not split if it goes over the limit.
+        file.write_all(b"0123456789abc").unwrap();
+        file.write_all(b"\n").unwrap();
+        for line in &[&b"second\n"[..], b"third\n", b"fourth\n"] {
+            file.write_all(line).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |p: &Path| fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "second\nthird\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "0123456789abc\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        // Only the newest max_files rotated files are kept.
+        file.write_all(b"fifth\n").unwrap();
+        file.write_all(b"sixth\n").unwrap();
+        assert_eq!(read(&path), "sixth\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "fourth\nfifth\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "second\nthird\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// A cap on the rate of data moved across every running job.
+//
+// Each job limits itself to so many destination sharks and so many objects
+// per assignment, but nothing bounds the total: with
+// options.max_concurrent_jobs jobs running, the traffic put on the network and
+// on the storage fleet is that many times what any one of them would put on
+// it.  If
+// `options.max_aggregate_bytes_per_second` is set, every job in the manager
+// draws on a single token bucket (see shared()) before posting an assignment
+// to an agent, and waits until the bytes of that assignment are available.
+//
+// The bucket fills at the configured rate and holds at most one second's
+// worth, so that a manager that has been idle can not then post a burst of
+// much more than the rate.  An assignment larger than the bucket would never
+// fit in it, so one is let through whenever the bucket is not in debt, and
+// the bytes that it is over by are paid off before the next.  Over time, the
+// bytes posted do not exceed the rate by more than the largest assignment.
+//
+// Delete assignments move no data, so are never held up.  A value of 0 (the
+// default) means no limit.  The rate may be changed while the manager is
+// running and takes effect within a second, including for posts that are
+// already waiting.
+
+use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The longest that a waiting post goes without checking whether the rate has
+// changed, or whether its job is stopping.
+static MAX_WAIT: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    // Bytes that may be posted now.  This is below 0 while an assignment
+    // larger than what was in the bucket is being paid off.
+    tokens: f64,
+    last_fill: Instant,
+}
+
+pub struct BandwidthBudget {
+    bytes_per_second: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+impl BandwidthBudget {
+    pub fn new(bytes_per_second: u64) -> BandwidthBudget {
+        BandwidthBudget {
+            bytes_per_second: AtomicU64::new(bytes_per_second),
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last_fill: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second.load(Ordering::SeqCst)
+    }
+
+    pub fn set_bytes_per_second(&self, bytes_per_second: u64) {
+        self.bytes_per_second
+            .store(bytes_per_second, Ordering::SeqCst);
+    }
+
+    /// Take `bytes` from the bucket as of `now`, if it is not in debt.
+    /// Otherwise returns how long it will be until it is.
+    fn reserve(&self, bytes: u64, now: Instant) -> Option<Duration> {
+        let rate = self.bytes_per_second();
+        if rate == 0 || bytes == 0 {
+            return None;
+        }
+
+        let mut bucket = self.bucket.lock().expect("bandwidth bucket lock");
+
+        if now > bucket.last_fill {
+            let elapsed = now.duration_since(bucket.last_fill);
+            let filled = elapsed.as_secs_f64() * rate as f64;
+
+            bucket.tokens = (bucket.tokens + filled).min(rate as f64);
+            bucket.last_fill = now;
+        }
+
+        if bucket.tokens >= 0.0 {
+            bucket.tokens -= bytes as f64;
+            return None;
+        }
+
+        let wait_ms = (-bucket.tokens * 1000.0 / rate as f64).ceil() as u64;
+        Some(Duration::from_millis(wait_ms.max(1)))
+    }
+
+    /// Wait until `bytes` may be posted, or until `stopping` returns true,
+    /// and return the time spent waiting.
+    pub fn acquire<F>(&self, bytes: u64, stopping: F) -> Duration
+    where
+        F: Fn() -> bool,
+    {
+        let start = Instant::now();
+
+        while let Some(wait) = self.reserve(bytes, Instant::now()) {
+            if stopping() {
+                break;
+            }
+            thread::sleep(wait.min(MAX_WAIT));
+        }
+
+        start.elapsed()
+    }
+}
+
+lazy_static! {
+    static ref SHARED: Arc<BandwidthBudget> = Arc::new(BandwidthBudget::new(0));
+}
+
+/// The budget shared by every job running in this manager.
+pub fn shared() -> Arc<BandwidthBudget> {
+    Arc::clone(&SHARED)
+}
+
+/// Change the rate of the shared budget.  0 means no limit.
+pub fn set_max_bytes_per_second(bytes_per_second: u64) {
+    SHARED.set_bytes_per_second(bytes_per_second);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bandwidth_reserve() {
+        let budget = BandwidthBudget::new(1000);
+        let start = budget.bucket.lock().unwrap().last_fill;
+
+        // The first post goes straight away, and leaves the bucket in debt
+        // for what it was over by.
+        assert_eq!(budget.reserve(1500, start), None);
+        assert_eq!(
+            budget.reserve(10, start),
+            Some(Duration::from_millis(1500))
+        );
+
+        // Once the debt is paid off, the next goes.
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(budget.reserve(10, later), None);
+
+        // However long the bucket is left, it holds no more than a second's
+        // worth.
+        let idle = later + Duration::from_secs(60);
+        assert_eq!(budget.reserve(1010, idle), None);
+        assert_eq!(budget.reserve(1, idle), Some(Duration::from_millis(10)));
+
+        // Without a limit, or with nothing to move, nothing waits.
+        assert_eq!(budget.reserve(0, idle), None);
+        budget.set_bytes_per_second(0);
+        assert_eq!(budget.reserve(1 << 40, idle), None);
+    }
+}
@@ -0,0 +1,338 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Pausing jobs that keep failing.
+//
+// Something wrong with the job's environment (a bad set of destinations, an
+// unreachable metadata tier and so on) can cause every object that a job
+// processes to fail to move.  Left alone, such a job grinds through millions
+// of objects, failing each of them in turn.  Each job has a CircuitBreaker
+// instead, which counts the objects the job moves and those it fails to move,
+// and trips when either:
+//
+//  * more than `circuit_breaker.max_error_rate` of the objects that the job
+//    has processed have failed, once it has processed at least
+//    `circuit_breaker.min_objects`, or
+//  * `circuit_breaker.max_consecutive_errors` objects in a row have failed.
+//
+// Once the breaker has tripped the job stops much as it would for a shutdown
+// of the manager: it stops scanning for objects, discards the assignments it
+// has not posted yet and waits for those it has posted to finish.  The job
+// then ends in the Paused state rather than Complete, and what tripped the
+// breaker is recorded in the job_pauses table of the rebalancer database and
+// reported with the job's status.  A paused job is not resumed on its own.
+// Once the cause has been dealt with, the objects it failed to move can be
+// retried, and a new job for the same shark picks up what it did not reach.
+//
+// The thresholds can be changed while the job runs (see the tuning module).
+// A change takes effect from the next object that fails to move.
+//
+// A job is also paused if it loses one of its threads, with a reason of
+// WorkerPanic (see the watchdog module).
+
+use super::REBALANCER_DB;
+use crate::config::ConfigCircuitBreaker;
+use crate::pg_db;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+pub use rebalancer_client::status::JobPause;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+table! {
+    use diesel::sql_types::{BigInt, Text};
+    job_pauses (job_id) {
+        job_id -> Text,
+        reason -> Text,
+        detail -> Text,
+        timestamp -> BigInt,
+    }
+}
+
+/// Why a job was paused.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PauseReason {
+    ErrorThreshold,
+    WorkerPanic,
+}
+
+/// A job's pause, as it is kept in the database.
+#[derive(Debug, Deserialize, Serialize, Insertable, Queryable, PartialEq)]
+#[table_name = "job_pauses"]
+pub struct JobPauseRecord {
+    pub job_id: String,
+
+    // One of PauseReason.
+    pub reason: String,
+
+    // What exactly caused the job to be paused.
+    pub detail: String,
+
+    // Milliseconds since the epoch at which the job was paused.
+    pub timestamp: i64,
+}
+
+impl From<JobPauseRecord> for JobPause {
+    fn from(record: JobPauseRecord) -> JobPause {
+        JobPause {
+            job_id: record.job_id,
+            reason: record.reason,
+            detail: record.detail,
+            timestamp: record.timestamp,
+        }
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    moved: u64,
+    failed: u64,
+    consecutive: u64,
+}
+
+pub struct CircuitBreaker {
+    job_id: String,
+    config: Mutex<ConfigCircuitBreaker>,
+    tripped: AtomicBool,
+    state: Mutex<BreakerState>,
+
+    // Why the breaker tripped, once it has.
+    detail: Mutex<Option<String>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(job_id: &str, config: &ConfigCircuitBreaker) -> CircuitBreaker {
+        CircuitBreaker {
+            job_id: job_id.to_string(),
+            config: Mutex::new(*config),
+            tripped: AtomicBool::new(false),
+            state: Mutex::new(BreakerState::default()),
+            detail: Mutex::new(None),
+        }
+    }
+
+    /// Record that `count` objects were moved.
+    pub fn moved(&self, count: u64) {
+        if count == 0 || self.is_tripped() {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("breaker lock");
+        state.moved = state.moved.saturating_add(count);
+        state.consecutive = 0;
+    }
+
+    /// Record that `count` objects could not be moved, and trip the breaker
+    /// if that takes the job over one of its limits.
+    pub fn failed(&self, count: u64) {
+        if count == 0 || self.is_tripped() {
+            return;
+        }
+
+        let detail = {
+            let mut state = self.state.lock().expect("breaker lock");
+            state.failed = state.failed.saturating_add(count);
+            state.consecutive = state.consecutive.saturating_add(count);
+            self.check(&state)
+        };
+
+        if let Some(detail) = detail {
+            warn!("Job {}: pausing, {}", self.job_id, detail);
+            *self.detail.lock().expect("breaker detail lock") = Some(detail);
+            self.tripped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn check(&self, state: &BreakerState) -> Option<String> {
+        let config = self.config();
+        let max_consecutive = config.max_consecutive_errors;
+        if max_consecutive > 0 && state.consecutive >= max_consecutive {
+            return Some(format!(
+                "{} objects in a row failed to move \
+                 (max_consecutive_errors: {})",
+                state.consecutive, max_consecutive
+            ));
+        }
+
+        let processed = state.moved.saturating_add(state.failed);
+        if processed == 0 || processed < config.min_objects {
+            return None;
+        }
+
+        let rate = state.failed as f64 / processed as f64;
+        if rate > config.max_error_rate {
+            return Some(format!(
+                "{} of {} objects failed to move (max_error_rate: {})",
+                state.failed, processed, config.max_error_rate
+            ));
+        }
+
+        None
+    }
+
+    pub fn config(&self) -> ConfigCircuitBreaker {
+        *self.config.lock().expect("breaker config lock")
+    }
+
+    /// Change the thresholds at which the breaker trips, returning what they
+    /// were before.
+    pub fn set_config(
+        &self,
+        config: ConfigCircuitBreaker,
+    ) -> ConfigCircuitBreaker {
+        let mut current = self.config.lock().expect("breaker config lock");
+        std::mem::replace(&mut *current, config)
+    }
+
+    /// Returns true once the job should be paused.
+    pub fn is_tripped(&self) -> bool {
+        self.tripped.load(Ordering::SeqCst)
+    }
+
+    /// Why the breaker tripped, if it has.
+    pub fn detail(&self) -> Option<String> {
+        self.detail.lock().expect("breaker detail lock").clone()
+    }
+
+    /// The number of objects that the job has failed to move.
+    pub fn failed_objects(&self) -> u64 {
+        self.state.lock().expect("breaker lock").failed
+    }
+}
+
+pub fn create_pause_table(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS job_pauses(
+            job_id TEXT PRIMARY KEY,
+            reason TEXT NOT NULL,
+            detail TEXT NOT NULL,
+            timestamp BIGINT NOT NULL
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+/// Record why a job was paused.
+pub fn record_pause(
+    job_id: &str,
+    reason: PauseReason,
+    detail: &str,
+) -> Result<JobPause, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let pause = JobPauseRecord {
+        job_id: job_id.to_string(),
+        reason: reason.to_string(),
+        detail: detail.to_string(),
+        timestamp: now_ms(),
+    };
+
+    diesel::insert_into(job_pauses::table)
+        .values(&pause)
+        .on_conflict(job_pauses::job_id)
+        .do_nothing()
+        .execute(&conn)
+        .map_err(Error::from)?;
+
+    Ok(JobPause::from(pause))
+}
+
+/// Why a job was paused, if it has been.
+pub fn get_pause(job_id: &str) -> Result<Option<JobPause>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    job_pauses::table
+        .filter(job_pauses::job_id.eq(job_id))
+        .first::<JobPauseRecord>(&conn)
+        .optional()
+        .map(|record| record.map(JobPause::from))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(
+        max_error_rate: f64,
+        max_consecutive_errors: u64,
+    ) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "job",
+            &ConfigCircuitBreaker {
+                max_error_rate,
+                max_consecutive_errors,
+                min_objects: 100,
+            },
+        )
+    }
+
+    #[test]
+    fn breaker_disabled() {
+        let b = CircuitBreaker::new("job", &ConfigCircuitBreaker::default());
+
+        b.failed(1_000_000);
+        assert!(!b.is_tripped());
+        assert_eq!(b.detail(), None);
+    }
+
+    #[test]
+    fn breaker_error_rate() {
+        let b = breaker(0.5, 0);
+
+        // Not enough objects have been processed to judge the job yet.
+        b.failed(60);
+        b.moved(39);
+        assert!(!b.is_tripped());
+
+        b.moved(1);
+        b.failed(1);
+        assert!(b.is_tripped());
+        assert_eq!(b.failed_objects(), 61);
+        assert!(b.detail().expect("detail").contains("61 of 101"));
+    }
+
+    #[test]
+    fn breaker_consecutive_errors() {
+        let b = breaker(1.0, 10);
+
+        b.failed(9);
+        b.moved(1);
+        b.failed(9);
+        assert!(!b.is_tripped());
+
+        b.failed(1);
+        assert!(b.is_tripped());
+        assert!(b.detail().expect("detail").contains("10 objects in a row"));
+    }
+
+    #[test]
+    fn breaker_set_config() {
+        let b = breaker(1.0, 10);
+
+        b.failed(5);
+        assert!(!b.is_tripped());
+
+        let old = b.set_config(ConfigCircuitBreaker {
+            max_consecutive_errors: 6,
+            ..b.config()
+        });
+        assert_eq!(old.max_consecutive_errors, 10);
+
+        b.failed(1);
+        assert!(b.is_tripped());
+        assert!(b.detail().expect("detail").contains("6 objects in a row"));
+    }
+}
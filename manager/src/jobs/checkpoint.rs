@@ -0,0 +1,166 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// When a scanning job records how far it has got.
+//
+// A job's scan checkpoints (how many objects each shard has given up, the
+// last of them, and whether the shard is done) are what a resumed job starts
+// from, so everything scanned since the last of them is scanned again after
+// a crash.  They used to be saved every 10 seconds regardless of how fast the
+// scan was going.  Instead, the time between two saves follows the scan's
+// throughput:
+//
+//  * A slow scan, which puts little load on the job's database, is
+//    checkpointed every `min_interval_secs`, so that little of it is lost.
+//  * As the scan speeds up the saves are spread out, up to
+//    `max_interval_secs` apart once it reaches `high_throughput` records a
+//    second, so that a fast scan batches many records in to each save rather
+//    than competing with its own object inserts for the database.
+//
+// `max_interval_secs` is therefore the most scanning that a crash can lose.
+// The throughput is measured over the records received since the last save.
+
+use crate::config::ConfigCheckpoints;
+
+use std::time::{Duration, Instant};
+
+pub struct CheckpointSchedule {
+    min_interval: Duration,
+    max_interval: Duration,
+    high_throughput: f64,
+    last_saved: Instant,
+    records: u64,
+}
+
+impl CheckpointSchedule {
+    pub fn new(config: &ConfigCheckpoints) -> CheckpointSchedule {
+        Self::new_at(config, Instant::now())
+    }
+
+    fn new_at(config: &ConfigCheckpoints, now: Instant) -> CheckpointSchedule {
+        let min_interval = Duration::from_secs(config.min_interval_secs);
+        let max_interval =
+            Duration::from_secs(config.max_interval_secs).max(min_interval);
+
+        CheckpointSchedule {
+            min_interval,
+            max_interval,
+            high_throughput: config.high_throughput.max(1) as f64,
+            last_saved: now,
+            records: 0,
+        }
+    }
+
+    /// Count a record received by the scan.
+    pub fn record(&mut self) {
+        self.records += 1;
+    }
+
+    /// Returns true if the checkpoints should be saved now.
+    pub fn is_due(&self) -> bool {
+        self.is_due_at(Instant::now())
+    }
+
+    fn is_due_at(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_saved);
+
+        // Until the shortest interval has passed there is no need to work
+        // out the throughput at all.
+        if elapsed < self.min_interval {
+            return false;
+        }
+
+        elapsed >= self.interval(elapsed)
+    }
+
+    // The time between saves at the throughput seen over `elapsed`.
+    fn interval(&self, elapsed: Duration) -> Duration {
+        let secs = elapsed.as_secs_f64().max(std::f64::EPSILON);
+        let throughput = self.records as f64 / secs;
+        let fraction = (throughput / self.high_throughput).min(1.0);
+
+        self.min_interval
+            + (self.max_interval - self.min_interval).mul_f64(fraction)
+    }
+
+    /// Record that the checkpoints have been saved.
+    pub fn saved(&mut self) {
+        self.saved_at(Instant::now())
+    }
+
+    fn saved_at(&mut self, now: Instant) {
+        self.last_saved = now;
+        self.records = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConfigCheckpoints {
+        ConfigCheckpoints {
+            min_interval_secs: 10,
+            max_interval_secs: 60,
+            high_throughput: 1000,
+        }
+    }
+
+    #[test]
+    fn checkpoint_interval_follows_throughput() {
+        let start = Instant::now();
+        let secs = |s: u64| start + Duration::from_secs(s);
+        let mut schedule = CheckpointSchedule::new_at(&config(), start);
+
+        assert!(!schedule.is_due_at(secs(5)));
+
+        // A slow scan is checkpointed as often as is allowed.
+        for _ in 0..10 {
+            schedule.record();
+        }
+        assert!(schedule.is_due_at(secs(11)));
+        schedule.saved_at(secs(10));
+        assert!(!schedule.is_due_at(secs(15)));
+
+        // A scan at half the high throughput waits about half way between
+        // the shortest and longest intervals, less as it slows down.
+        for _ in 0..(20 * 500) {
+            schedule.record();
+        }
+        assert!(!schedule.is_due_at(secs(30)));
+        assert!(schedule.is_due_at(secs(40)));
+
+        // A fast scan is batched up to the longest interval, and no more.
+        schedule.saved_at(secs(40));
+        for _ in 0..(60 * 5000) {
+            schedule.record();
+        }
+        assert!(!schedule.is_due_at(secs(90)));
+        assert!(schedule.is_due_at(secs(100)));
+    }
+
+    #[test]
+    fn checkpoint_interval_bounds() {
+        // A maximum below the minimum is raised to it.
+        let config = ConfigCheckpoints {
+            min_interval_secs: 10,
+            max_interval_secs: 0,
+            high_throughput: 0,
+        };
+        let start = Instant::now();
+        let mut schedule = CheckpointSchedule::new_at(&config, start);
+        for _ in 0..1_000_000 {
+            schedule.record();
+        }
+
+        assert!(!schedule.is_due_at(start + Duration::from_secs(9)));
+        assert!(schedule.is_due_at(start + Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// What a job does with objects whose metadata has no usable checksum.
+//
+// The agent checks each copy that it downloads against the contentMD5 in the
+// object's metadata.  Objects written by early versions of manta may have no
+// contentMD5 at all, or one that is not the base64 encoding of an MD5 sum,
+// and those used to be sent to the agent anyway, only for the download to be
+// thrown away when the check failed.  Instead, evacuate and create-copy jobs
+// now check the contentMD5 of each object before it is assigned, and treat
+// one that is missing or malformed according to the job's checksum policy
+// (`options.checksum_policy`, or the job's `checksum_policy` parameter):
+//
+//  * fail: the object is marked as an error (`bad_checksum`).
+//  * skip: the object is skipped (`bad_checksum`), so that a retry job with
+//    another policy can pick it up later.
+//  * copy: the object is copied without its checksum being checked, and a
+//    warning is recorded for it.
+//
+// Whichever the policy, each such object is recorded in the
+// checksum_exceptions table of the job's database, and the count of each
+// disposition is reported as the `checksums` of the job's status.  Remove-copy
+// jobs do not apply the policy, since the agent can only tell that its copy
+// is the one described in the metadata by its checksum.
+
+use crate::config::ChecksumPolicy;
+use rebalancer::error::Error;
+pub use rebalancer_client::status::ChecksumSummary;
+
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
+
+table! {
+    use diesel::sql_types::{Integer, Text};
+    checksum_exceptions(id) {
+        id -> Integer,
+        object_id -> Text,
+        problem -> Text,
+        disposition -> Text,
+        content_md5 -> Text,
+    }
+}
+
+static DISPOSITION_COUNT_QUERY: &str =
+    "SELECT disposition, count(disposition) \
+                                        FROM checksum_exceptions \
+                                        GROUP BY disposition";
+
+/// What is wrong with an object's checksum.
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ChecksumProblem {
+    Missing,
+    Malformed,
+}
+
+/// What became of an object with a missing or malformed checksum.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ChecksumDisposition {
+    Failed,
+    Skipped,
+    Copied,
+}
+
+impl From<ChecksumPolicy> for ChecksumDisposition {
+    fn from(policy: ChecksumPolicy) -> Self {
+        match policy {
+            ChecksumPolicy::Fail => ChecksumDisposition::Failed,
+            ChecksumPolicy::Skip => ChecksumDisposition::Skipped,
+            ChecksumPolicy::Copy => ChecksumDisposition::Copied,
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "checksum_exceptions"]
+struct NewChecksumException {
+    object_id: String,
+    problem: String,
+    disposition: String,
+    content_md5: String,
+}
+
+#[derive(QueryableByName, Debug)]
+struct DispositionCount {
+    #[sql_type = "Text"]
+    disposition: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+// The characters of standard base64.
+fn is_base64_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '+' || c == '/'
+}
+
+/// Returns what is wrong with `content_md5` as the base64 encoding of an MD5
+/// sum, if anything.  Sixteen bytes encode to 22 characters followed by two
+/// padding characters, the last of which carries only two bits of the sum.
+pub fn check(content_md5: &str) -> Option<ChecksumProblem> {
+    if content_md5.is_empty() {
+        return Some(ChecksumProblem::Missing);
+    }
+
+    let chars: Vec<char> = content_md5.chars().collect();
+    let well_formed = chars.len() == 24
+        && chars[..22].iter().all(|c| is_base64_char(*c))
+        && "AQgw".contains(chars[21])
+        && chars[22..] == ['=', '='];
+
+    if well_formed {
+        None
+    } else {
+        Some(ChecksumProblem::Malformed)
+    }
+}
+
+pub fn create_checksum_exceptions_table(
+    conn: &PgConnection,
+) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE checksum_exceptions(
+        id SERIAL PRIMARY KEY,
+        object_id TEXT,
+        problem TEXT,
+        disposition TEXT,
+        content_md5 TEXT
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE checksum_exceptions") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// Record what became of the object `object_id`, whose checksum
+/// `content_md5` has `problem`.  As this only adds to what the job reports,
+/// an error is logged rather than failing the job.
+pub fn record(
+    conn: &PgConnection,
+    object_id: &str,
+    content_md5: &str,
+    problem: ChecksumProblem,
+    disposition: ChecksumDisposition,
+) {
+    let entry = NewChecksumException {
+        object_id: object_id.to_string(),
+        problem: problem.to_string(),
+        disposition: disposition.to_string(),
+        content_md5: content_md5.to_string(),
+    };
+
+    if let Err(e) = diesel::insert_into(checksum_exceptions::table)
+        .values(&entry)
+        .execute(conn)
+    {
+        warn!(
+            "LocalDB: Error recording checksum of object {}: {}",
+            object_id, e
+        );
+    }
+}
+
+/// Returns the number of objects of each disposition in a job's checksum
+/// exceptions.
+pub fn get_checksum_summary(
+    conn: &PgConnection,
+) -> Result<ChecksumSummary, Error> {
+    let counts: Vec<DispositionCount> =
+        sql_query(DISPOSITION_COUNT_QUERY).load(conn)?;
+    let mut summary = ChecksumSummary::default();
+
+    for dc in counts {
+        match dc.disposition.parse::<ChecksumDisposition>() {
+            Ok(ChecksumDisposition::Failed) => summary.failed += dc.count,
+            Ok(ChecksumDisposition::Skipped) => summary.skipped += dc.count,
+            Ok(ChecksumDisposition::Copied) => summary.copied += dc.count,
+            Err(_) => warn!("Unknown checksum disposition {}", dc.disposition),
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_check() {
+        // The MD5 sums of "" and "hello\n".
+        assert_eq!(check("1B2M2Y8AsgTpgAmY7PhCfg=="), None);
+        assert_eq!(check("sZRqySSS0jR8YjW00mERhA=="), None);
+
+        assert_eq!(check(""), Some(ChecksumProblem::Missing));
+
+        for bad in &[
+            "d41d8cd98f00b204e9800998ecf8427e",
+            "1B2M2Y8AsgTpgAmY7PhCfg",
+            "1B2M2Y8AsgTpgAmY7PhCfh==",
+            "1B2M2Y8AsgTpgAmY7Ph-fg==",
+            "1B2M2Y8AsgTpgAmY7PhCfg==\n",
+            "not a checksum",
+        ] {
+            assert_eq!(check(bad), Some(ChecksumProblem::Malformed), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Operator sign-off of finished jobs.
+//
+// Where change management requires that someone review the outcome of a job
+// before anything that depends on it goes ahead, a job can be created with
+// `require_confirmation` (or every job can be, with
+// `options.require_confirmation`).  Such a job does not become Complete once
+// it has finished all of its work.  It is left AwaitingConfirmation instead,
+// so that an operator can review its results (its status, skipped objects and
+// so on) and then sign it off with confirm_job(), at which point it becomes
+// Complete.
+//
+// Until it has been confirmed, a job can not be retried, and it is not
+// archived (see the retention module), since neither of those should happen
+// to a job whose outcome has not been accepted.  Who confirmed the job, and
+// when, is recorded in the job_confirmations table of the rebalancer database
+// and reported with the job's status.
+
+use super::jobs::dsl::{id as job_id_col, jobs as jobs_db, state as state_col};
+use super::{JobDbEntry, JobState, REBALANCER_DB};
+use crate::pg_db;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+pub use rebalancer_client::jobs::ConfirmJobPayload;
+pub use rebalancer_client::status::JobConfirmation;
+
+use std::fmt;
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+table! {
+    use diesel::sql_types::{BigInt, Nullable, Text};
+    job_confirmations (job_id) {
+        job_id -> Text,
+        confirmed_by -> Text,
+        comment -> Nullable<Text>,
+        timestamp -> BigInt,
+    }
+}
+
+/// A job's confirmation, as it is kept in the database.
+#[derive(Debug, Deserialize, Serialize, Insertable, Queryable, PartialEq)]
+#[table_name = "job_confirmations"]
+pub struct JobConfirmationRecord {
+    pub job_id: String,
+    pub confirmed_by: String,
+    pub comment: Option<String>,
+
+    // Milliseconds since the epoch at which the job was confirmed.
+    pub timestamp: i64,
+}
+
+impl From<JobConfirmationRecord> for JobConfirmation {
+    fn from(record: JobConfirmationRecord) -> JobConfirmation {
+        JobConfirmation {
+            job_id: record.job_id,
+            confirmed_by: record.confirmed_by,
+            comment: record.comment,
+            timestamp: record.timestamp,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfirmError {
+    NotFound,
+    NotAwaiting(JobState),
+    MissingIdentity,
+    Db(Error),
+}
+
+impl fmt::Display for ConfirmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfirmError::NotFound => write!(f, "job not found"),
+            ConfirmError::NotAwaiting(state) => {
+                write!(f, "job is not awaiting confirmation (state: {})", state)
+            }
+            ConfirmError::MissingIdentity => {
+                write!(f, "confirmed_by must not be empty")
+            }
+            ConfirmError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for ConfirmError {
+    fn from(error: Error) -> Self {
+        ConfirmError::Db(error)
+    }
+}
+
+impl From<diesel::result::Error> for ConfirmError {
+    fn from(error: diesel::result::Error) -> Self {
+        ConfirmError::Db(Error::from(error))
+    }
+}
+
+pub fn create_confirmation_table(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS job_confirmations(
+            job_id TEXT PRIMARY KEY,
+            confirmed_by TEXT NOT NULL,
+            comment TEXT,
+            timestamp BIGINT NOT NULL
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+/// Sign off a job that is awaiting confirmation, recording who confirmed it,
+/// and mark it Complete.
+pub fn confirm_job(
+    uuid: &Uuid,
+    confirmed_by: &str,
+    comment: Option<String>,
+) -> Result<JobConfirmation, ConfirmError> {
+    let confirmed_by = confirmed_by.trim();
+    if confirmed_by.is_empty() {
+        return Err(ConfirmError::MissingIdentity);
+    }
+
+    let job_id = uuid.to_string();
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    let confirmation = JobConfirmationRecord {
+        job_id: job_id.clone(),
+        confirmed_by: confirmed_by.to_string(),
+        comment,
+        timestamp: now_ms(),
+    };
+
+    conn.transaction::<_, ConfirmError, _>(|| {
+        let entry: JobDbEntry = jobs_db
+            .filter(job_id_col.eq(&job_id))
+            .first(&conn)
+            .optional()?
+            .ok_or(ConfirmError::NotFound)?;
+
+        // Only one of two concurrent confirmations of the same job updates
+        // its state.
+        let updated = diesel::update(
+            jobs_db
+                .filter(job_id_col.eq(&job_id))
+                .filter(state_col.eq(JobState::AwaitingConfirmation)),
+        )
+        .set(state_col.eq(JobState::Complete))
+        .execute(&conn)?;
+
+        if updated == 0 {
+            return Err(ConfirmError::NotAwaiting(entry.state));
+        }
+
+        diesel::insert_into(job_confirmations::table)
+            .values(&confirmation)
+            .execute(&conn)?;
+
+        Ok(())
+    })?;
+
+    info!(
+        "Job {} confirmed by {}{}",
+        job_id,
+        confirmation.confirmed_by,
+        confirmation
+            .comment
+            .as_ref()
+            .map(|c| format!(": {}", c))
+            .unwrap_or_default()
+    );
+
+    Ok(JobConfirmation::from(confirmation))
+}
+
+/// Who confirmed a job, if it has been confirmed.
+pub fn get_confirmation(
+    job_id: &str,
+) -> Result<Option<JobConfirmation>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    job_confirmations::table
+        .filter(job_confirmations::job_id.eq(job_id))
+        .first::<JobConfirmationRecord>(&conn)
+        .optional()
+        .map(|record| record.map(JobConfirmation::from))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{self, JobActionDbEntry};
+    use rebalancer::util;
+
+    #[test]
+    fn confirm_job_test() {
+        let _guard = util::init_global_logger(None);
+        jobs::create_job_database().expect("create job database");
+        let conn =
+            pg_db::connect_or_create_db(REBALANCER_DB).expect("rebalancer db");
+
+        let running = Uuid::new_v4();
+        let awaiting = Uuid::new_v4();
+
+        for (uuid, state) in &[
+            (running, JobState::Running),
+            (awaiting, JobState::AwaitingConfirmation),
+        ] {
+            diesel::insert_into(jobs_db)
+                .values(&JobDbEntry {
+                    id: uuid.to_string(),
+                    action: JobActionDbEntry::Evacuate,
+                    state: state.clone(),
+                    created: 1,
+                })
+                .execute(&conn)
+                .expect("insert job");
+        }
+
+        match confirm_job(&running, "operator", None) {
+            Err(ConfirmError::NotAwaiting(JobState::Running)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match confirm_job(&Uuid::new_v4(), "operator", None) {
+            Err(ConfirmError::NotFound) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match confirm_job(&awaiting, "  ", None) {
+            Err(ConfirmError::MissingIdentity) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let confirmation =
+            confirm_job(&awaiting, "operator", Some(String::from("CM-1234")))
+                .expect("confirm job");
+        assert_eq!(confirmation.confirmed_by, "operator");
+
+        let entry: JobDbEntry = jobs_db
+            .filter(job_id_col.eq(awaiting.to_string()))
+            .first(&conn)
+            .expect("load job");
+        assert_eq!(entry.state, JobState::Complete);
+
+        assert_eq!(
+            get_confirmation(&awaiting.to_string()).expect("confirmation"),
+            Some(confirmation)
+        );
+
+        // A job can only be confirmed once.
+        match confirm_job(&awaiting, "operator", None) {
+            Err(ConfirmError::NotAwaiting(JobState::Complete)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The corrupt objects that agents have found on their storage nodes.
+//
+// An agent with its scrubber enabled (see rebalancer::scrubber) posts each
+// object on its storage node whose checksum no longer matches to
+// POST /agents/<storage_id>/corrupt, at every pass of the scrubber that finds
+// it.  The manager keeps the latest report of each corrupt copy, by storage
+// node and object, and lists them all with GET /corrupt, which is what the
+// copies to be repaired are taken from.  Once a copy has been repaired, the
+// reports from its storage node are cleared with
+// DELETE /agents/<storage_id>/corrupt; a copy that is still corrupt is
+// reported again at the agent's next pass.
+//
+// Reports are only held in memory, like registrations (see jobs::registry),
+// so a manager that is restarted learns of each corrupt copy again from the
+// next pass of its agent's scrubber.
+
+use super::StorageId;
+pub use rebalancer::scrubber::CorruptObject;
+use rebalancer::util::now_ms;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+/// A corrupt copy of an object, as reported by GET /corrupt.  Times are in
+/// ms since the epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReportedCorruption {
+    pub storage_id: StorageId,
+
+    #[serde(flatten)]
+    pub object: CorruptObject,
+
+    pub first_reported: i64,
+    pub last_reported: i64,
+}
+
+// Copies are kept by storage id, owner and object id.
+type CopyKey = (StorageId, String, String);
+
+#[derive(Default)]
+pub struct CorruptionReports {
+    copies: Mutex<HashMap<CopyKey, ReportedCorruption>>,
+}
+
+impl CorruptionReports {
+    pub fn new() -> CorruptionReports {
+        CorruptionReports::default()
+    }
+
+    /// Record that `storage_id` found `object` to be corrupt, as reported at
+    /// `now`.  Returns the report, and whether the copy is new to the
+    /// manager.
+    pub fn report_at(
+        &self,
+        storage_id: &str,
+        object: CorruptObject,
+        now: i64,
+    ) -> (ReportedCorruption, bool) {
+        let mut copies = self.copies.lock().expect("corruption reports lock");
+        let key = (
+            storage_id.to_string(),
+            object.owner.clone(),
+            object.object_id.clone(),
+        );
+
+        let first_reported = copies.get(&key).map(|r| r.first_reported);
+        let report = ReportedCorruption {
+            storage_id: storage_id.to_string(),
+            object,
+            first_reported: first_reported.unwrap_or(now),
+            last_reported: now,
+        };
+        copies.insert(key, report.clone());
+
+        (report, first_reported.is_none())
+    }
+
+    pub fn report(
+        &self,
+        storage_id: &str,
+        object: CorruptObject,
+    ) -> (ReportedCorruption, bool) {
+        self.report_at(storage_id, object, now_ms())
+    }
+
+    /// Forget every report from `storage_id`, returning how many there were.
+    pub fn clear(&self, storage_id: &str) -> usize {
+        let mut copies = self.copies.lock().expect("corruption reports lock");
+        let before = copies.len();
+
+        copies.retain(|(id, _, _), _| id != storage_id);
+        before - copies.len()
+    }
+
+    /// Every corrupt copy, by storage id, owner and object id.
+    pub fn list(&self) -> Vec<ReportedCorruption> {
+        let copies = self.copies.lock().expect("corruption reports lock");
+        let mut keys: Vec<&CopyKey> = copies.keys().collect();
+
+        keys.sort();
+        keys.into_iter().map(|k| copies[k].clone()).collect()
+    }
+}
+
+lazy_static! {
+    static ref SHARED: Arc<CorruptionReports> =
+        Arc::new(CorruptionReports::new());
+}
+
+/// The reports shared by every part of this manager.
+pub fn shared() -> Arc<CorruptionReports> {
+    Arc::clone(&SHARED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corrupt(object_id: &str) -> CorruptObject {
+        CorruptObject {
+            owner: String::from("d50c4fc4-f408-492f-b8bc-a0dd7c73683f"),
+            object_id: object_id.to_string(),
+            md5sum: String::from("1B2M2Y8AsgTpgAmY7PhCfg=="),
+            found_md5sum: String::from("XUFAKrxLKna5cZ2REBfFkg=="),
+            size: 5,
+            detected: 1_600_000_000,
+        }
+    }
+
+    #[test]
+    fn corruption_reports() {
+        let reports = CorruptionReports::new();
+        let start: i64 = 1_600_000_000_000;
+
+        let (report, new) = reports.report_at("2.stor", corrupt("b"), start);
+        assert!(new);
+        assert_eq!(report.first_reported, start);
+
+        // The next pass's report of the same copy keeps when it was first
+        // reported.
+        let later = start + 60_000;
+        let (report, new) = reports.report_at("2.stor", corrupt("b"), later);
+        assert!(!new);
+        assert_eq!(report.first_reported, start);
+        assert_eq!(report.last_reported, later);
+
+        reports.report_at("2.stor", corrupt("a"), later);
+        reports.report_at("1.stor", corrupt("b"), later);
+
+        let listed: Vec<(String, String)> = reports
+            .list()
+            .into_iter()
+            .map(|r| (r.storage_id, r.object.object_id))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (String::from("1.stor"), String::from("b")),
+                (String::from("2.stor"), String::from("a")),
+                (String::from("2.stor"), String::from("b")),
+            ]
+        );
+
+        assert_eq!(reports.clear("2.stor"), 2);
+        assert_eq!(reports.clear("2.stor"), 0);
+        assert_eq!(reports.list().len(), 1);
+    }
+}
@@ -0,0 +1,584 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Estimating what an evacuate job would do, without running it.
+//
+// Before a storage node is decommissioned, whoever approves it wants to know
+// how much data is on it, whether the rest of the fleet has room for that
+// data, and how long moving it would take.  An evacuation plan estimates the
+// data from what storinfo says is used on each shark, which includes garbage
+// and objects that the job would leave where they are.  An estimate (POST
+// /estimate) takes the payload of an evacuate job that might be created, and:
+//
+//  * counts the objects that the job would find, and their bytes, on each
+//    shard.  Rather than having sharkspotter send every record of every
+//    shard to the manager, each shard is asked for its count with a single
+//    query through moray (see count_query()), `scan_parallelism` shards at a
+//    time.  The count is of the objects whose metadata has a copy on the
+//    shark and which match the job's filter, leaving out empty objects.  A
+//    job whose `source` is a snapshot file has its file counted instead,
+//    record by record, just as the job would read it (see the jobs::source
+//    module).
+//  * places those bytes on the sharks that the job could choose from (those
+//    from storinfo, or from the job's sharks_file, other than the shark
+//    itself and those that are draining or quarantined), as the job would:
+//    the sharks with the most room first, less what other jobs are already
+//    sending them, up to the job's max_fill_percentage and
+//    max_dest_utilization_percent (see place()).  Objects are placed as
+//    megabytes rather than one at a time, so failure domains and the sizes of
+//    individual objects are not taken into account.
+//  * works out how long moving the bytes would take at the given
+//    `throughput_mb_per_sec`, or at options.max_aggregate_bytes_per_second if
+//    that is set and no throughput is given.
+//
+// Nothing is recorded, and no job is created.
+
+use super::record::{self, RecordDisposition};
+use super::{filter, plan, projected, quarantine, source};
+use super::{EvacuateJobPayload, ObjectFilter, ObjectSource};
+use crate::config::Config;
+use crate::moray_client;
+use crate::storinfo::StorageNode;
+use rebalancer::error::Error;
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+static MB: u64 = 1024 * 1024;
+
+// How long moray is given to count the objects of a shard, in ms.  Each count
+// reads every record of the shard.
+static COUNT_TIMEOUT_MS: u64 = 30 * 60 * 1000;
+
+/// The body of a request for an estimate: the payload of an evacuate job,
+/// along with the rate at which its data would be moved.
+#[derive(Default, Deserialize, Serialize)]
+pub struct EstimatePayload {
+    #[serde(flatten)]
+    pub job: EvacuateJobPayload,
+
+    // Defaults to options.max_aggregate_bytes_per_second, if it is set.
+    pub throughput_mb_per_sec: Option<u64>,
+}
+
+impl EstimatePayload {
+    pub fn validate(&self) -> Result<(), String> {
+        self.job.validate()?;
+
+        if self.throughput_mb_per_sec == Some(0) {
+            return Err(String::from(
+                "throughput_mb_per_sec must be at least 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The objects on a shard that a job would find.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ShardEstimate {
+    pub shard: u32,
+    pub objects: u64,
+    pub bytes: u64,
+}
+
+/// What a destination would be given.  `available_mb` is what it has left
+/// once what other jobs are sending it has landed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DestinationEstimate {
+    pub shark: String,
+    pub datacenter: String,
+    pub available_mb: u64,
+    pub placed_mb: u64,
+    pub percent_used: u32,
+    pub projected_percent_used: u32,
+}
+
+/// An estimate as it is reported.  `capacity_mb` is the most that the
+/// destinations could take, and `unplaced_mb` how much of the job's data
+/// they could not.  The destinations are those that would be given
+/// anything, the most first.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Estimate {
+    pub from_shark: String,
+    pub objects: u64,
+    pub bytes: u64,
+    pub required_mb: u64,
+    pub shards: Vec<ShardEstimate>,
+    pub max_fill_percentage: u32,
+    pub capacity_mb: u64,
+    pub unplaced_mb: u64,
+    pub destinations: Vec<DestinationEstimate>,
+    pub throughput_mb_per_sec: Option<u64>,
+    pub estimated_seconds: Option<u64>,
+}
+
+// A value of a row returned by moray's sql, which returns big integers as
+// strings.
+fn sql_u64(row: &Value, column: &str) -> u64 {
+    match row.get(column) {
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        Some(v) => v.as_u64().unwrap_or(0),
+        None => 0,
+    }
+}
+
+// Escape the characters that LIKE treats specially.
+fn like_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// The query that counts the objects on a shard that have a copy on
+/// `storage_id` and match `filter`, along with its parameters.  The sharks
+/// of an object are only in its metadata, so the query reads every record of
+/// the shard, but does so within the shard's database.
+pub fn count_query(
+    storage_id: &str,
+    filter: &Option<ObjectFilter>,
+) -> (String, Vec<String>) {
+    let size = "(_value::json->>'contentLength')::bigint";
+    let mtime = "(_value::json->>'mtime')::bigint";
+
+    let mut conditions = vec![
+        String::from("type = 'object'"),
+        String::from("_value LIKE $1"),
+        format!("{} > 0", size),
+    ];
+    let mut vals = vec![format!(
+        "%\"manta_storage_id\":\"{}\"%",
+        like_escape(storage_id)
+    )];
+
+    let mut condition = |cond: String, val: String| {
+        vals.push(val);
+        conditions.push(format!("{} ${}", cond, vals.len()));
+    };
+
+    if let Some(f) = filter {
+        if let Some(owner) = &f.owner {
+            condition(String::from("owner ="), owner.clone());
+        }
+        if let Some(min) = f.min_size {
+            condition(format!("{} >=", size), min.to_string());
+        }
+        if let Some(max) = f.max_size {
+            condition(format!("{} <=", size), max.to_string());
+        }
+        if let Some(after) = f.created_after {
+            condition(format!("{} >=", mtime), after.to_string());
+        }
+        if let Some(before) = f.created_before {
+            condition(format!("{} <=", mtime), before.to_string());
+        }
+    }
+
+    let query = format!(
+        "SELECT count(*) AS objects, COALESCE(sum({}), 0) AS bytes FROM \
+         manta WHERE {}",
+        size,
+        conditions.join(" AND ")
+    );
+
+    (query, vals)
+}
+
+fn count_shard(
+    shard: u32,
+    domain: &str,
+    query: &str,
+    vals: &[String],
+) -> Result<ShardEstimate, Error> {
+    let mut mclient = moray_client::create_client(shard, domain)?;
+    let mut estimate = ShardEstimate {
+        shard,
+        ..Default::default()
+    };
+
+    mclient.sql(
+        query,
+        vals.iter().map(String::as_str).collect(),
+        format!("{{\"timeout\": {}}}", COUNT_TIMEOUT_MS),
+        |row| {
+            estimate.objects += sql_u64(row, "objects");
+            estimate.bytes += sql_u64(row, "bytes");
+            Ok(())
+        },
+    )?;
+
+    Ok(estimate)
+}
+
+// Count the objects of each of `shards`, `parallelism` shards at a time.
+fn count_shards(
+    shards: Vec<u32>,
+    domain: &str,
+    storage_id: &str,
+    filter: &Option<ObjectFilter>,
+    parallelism: usize,
+) -> Result<Vec<ShardEstimate>, Error> {
+    let (query, vals) = count_query(storage_id, filter);
+    let queue: Arc<Mutex<VecDeque<u32>>> =
+        Arc::new(Mutex::new(shards.into_iter().collect()));
+
+    let counters: Vec<thread::JoinHandle<Result<Vec<ShardEstimate>, Error>>> =
+        (0..parallelism.max(1))
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let domain = domain.to_string();
+                let query = query.clone();
+                let vals = vals.clone();
+
+                thread::Builder::new()
+                    .name("estimate_counter".to_string())
+                    .spawn(move || {
+                        let mut counted = vec![];
+                        loop {
+                            let shard = match queue
+                                .lock()
+                                .expect("shard queue lock")
+                                .pop_front()
+                            {
+                                Some(s) => s,
+                                None => return Ok(counted),
+                            };
+                            counted.push(count_shard(
+                                shard, &domain, &query, &vals,
+                            )?);
+                        }
+                    })
+                    .expect("Start estimate counter thread")
+            })
+            .collect();
+
+    let mut counted = vec![];
+    let mut ret = Ok(());
+    for counter in counters {
+        match counter.join().expect("estimate counter join") {
+            Ok(c) => counted.extend(c),
+            Err(e) => ret = Err(e),
+        }
+    }
+    ret?;
+
+    counted.sort_by_key(|c| c.shard);
+    Ok(counted)
+}
+
+// Count the objects in the snapshot file at `path` that a job of
+// `storage_id` would work on.
+fn count_file(
+    path: &str,
+    storage_id: &str,
+    filter: &Option<ObjectFilter>,
+    max_record_bytes: usize,
+) -> Result<Vec<ShardEstimate>, Error> {
+    let mut shards: BTreeMap<u32, ShardEstimate> = BTreeMap::new();
+
+    for line in source::open(path)? {
+        let mut msg = match line? {
+            (_, Ok(msg)) => msg,
+            (n, Err(e)) => {
+                debug!("Not counting line {} of {}: {}", n, path, e);
+                continue;
+            }
+        };
+
+        let object = &mut msg.manta_value;
+        let bytes = object
+            .get("contentLength")
+            .and_then(Value::as_u64)
+            .unwrap_or(0);
+        if bytes == 0
+            || record::classify(object, max_record_bytes)
+                != RecordDisposition::Object
+            || !record::has_copy_on(object, storage_id)
+            || !filter.as_ref().map_or(true, |f| filter::matches(f, object))
+        {
+            continue;
+        }
+
+        let shard = shards.entry(msg.shard).or_insert(ShardEstimate {
+            shard: msg.shard,
+            ..Default::default()
+        });
+        shard.objects += 1;
+        shard.bytes += bytes;
+    }
+
+    Ok(shards.into_iter().map(|(_, s)| s).collect())
+}
+
+/// Place `required_mb` on `sharks`, leaving out those in `excluded`, without
+/// taking any of them beyond `max_fill_percentage`.  The sharks with the most
+/// room are filled first, until they have as little left as those with the
+/// next most, and so on, which is where a job that gives each assignment to
+/// the shark with the most room at the time ends up.  `pending_mb` is how
+/// much more other jobs are sending each shark.  Returns the destinations
+/// that would be given anything, the most first, and how much could not be
+/// placed.
+pub fn place<F>(
+    required_mb: u64,
+    sharks: &[StorageNode],
+    excluded: &HashSet<String>,
+    max_fill_percentage: u32,
+    pending_mb: F,
+) -> (Vec<DestinationEstimate>, u64)
+where
+    F: Fn(&str) -> u64,
+{
+    struct Candidate<'a> {
+        node: &'a StorageNode,
+        total: u64,
+        available: u64,
+        room: u64,
+        placed: u64,
+    }
+
+    let mut candidates: Vec<Candidate> = sharks
+        .iter()
+        .filter(|s| !excluded.contains(&s.manta_storage_id))
+        .map(|s| {
+            let total = plan::estimate_used_mb(s) + s.available_mb;
+            let available = s
+                .available_mb
+                .saturating_sub(pending_mb(&s.manta_storage_id));
+            let ceiling = total * u64::from(max_fill_percentage) / 100;
+            let room = ceiling.saturating_sub(total - available);
+
+            Candidate {
+                node: s,
+                total,
+                available,
+                room,
+                placed: 0,
+            }
+        })
+        .collect();
+
+    let capacity: u64 = candidates.iter().map(|c| c.room).sum();
+    let target = required_mb.min(capacity);
+
+    // What the candidates would be given if every one of them were filled
+    // until it had no more than `level` left.
+    let given = |c: &Candidate, level: u64| {
+        c.available.saturating_sub(level).min(c.room)
+    };
+    let fill = |candidates: &[Candidate], level: u64| -> u64 {
+        candidates.iter().map(|c| given(c, level)).sum()
+    };
+
+    // The lowest level that places no more than the target.
+    let (mut low, mut high) =
+        (0, candidates.iter().map(|c| c.available).max().unwrap_or(0));
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if fill(&candidates, mid) <= target {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    let mut remainder = target - fill(&candidates, low);
+    candidates.sort_by(|a, b| b.available.cmp(&a.available));
+    for c in candidates.iter_mut() {
+        c.placed = given(c, low);
+
+        // A megabyte less than the level, for as many as are needed to make
+        // up the target.
+        if remainder > 0
+            && low > 0
+            && c.placed < c.room
+            && c.available - c.placed == low
+        {
+            c.placed += 1;
+            remainder -= 1;
+        }
+    }
+
+    let pct = |used: u64, total: u64| (used * 100 / total.max(1)) as u32;
+    let mut destinations: Vec<DestinationEstimate> = candidates
+        .iter()
+        .filter(|c| c.placed > 0)
+        .map(|c| {
+            let used = c.total - c.available;
+            DestinationEstimate {
+                shark: c.node.manta_storage_id.clone(),
+                datacenter: c.node.datacenter.clone(),
+                available_mb: c.available,
+                placed_mb: c.placed,
+                percent_used: pct(used, c.total),
+                projected_percent_used: pct(used + c.placed, c.total),
+            }
+        })
+        .collect();
+    destinations.sort_by(|a, b| b.placed_mb.cmp(&a.placed_mb));
+
+    (destinations, required_mb - target)
+}
+
+/// Estimate what the evacuate job of `payload` would do, given the job's
+/// copy of the configuration and the sharks that it would choose its
+/// destinations from.
+pub fn estimate(
+    payload: &EstimatePayload,
+    config: &Config,
+    sharks: &[StorageNode],
+) -> Result<Estimate, Error> {
+    let job = &payload.job;
+
+    let shards = match &job.source {
+        Some(ObjectSource::File { path }) => count_file(
+            path,
+            &job.from_shark,
+            &job.filter,
+            config.options.max_record_bytes,
+        )?,
+        _ => count_shards(
+            config.shard_nums(),
+            &config.domain_name,
+            &job.from_shark,
+            &job.filter,
+            config.options.max_md_read_threads,
+        )?,
+    };
+
+    let objects = shards.iter().map(|s| s.objects).sum();
+    let bytes: u64 = shards.iter().map(|s| s.bytes).sum();
+    let required_mb = (bytes + MB - 1) / MB;
+
+    let max_fill_percentage = match job.max_dest_utilization_percent {
+        Some(ceiling) => ceiling.min(config.max_fill_percentage),
+        None => config.max_fill_percentage,
+    };
+
+    let excluded: HashSet<String> = sharks
+        .iter()
+        .map(|s| &s.manta_storage_id)
+        .filter(|id| {
+            **id == job.from_shark
+                || plan::is_draining(id)
+                || quarantine::is_quarantined(id)
+        })
+        .cloned()
+        .collect();
+
+    let projected = projected::shared();
+    let (destinations, unplaced_mb) =
+        place(required_mb, sharks, &excluded, max_fill_percentage, |id| {
+            projected.unreflected_mb(id, "")
+        });
+    let capacity_mb =
+        plan::destination_capacity_mb(sharks, &excluded, max_fill_percentage);
+
+    let throughput_mb_per_sec = payload.throughput_mb_per_sec.or_else(|| {
+        match config.options.max_aggregate_bytes_per_second / MB {
+            0 => None,
+            mb => Some(mb),
+        }
+    });
+    let estimated_seconds =
+        throughput_mb_per_sec.map(|rate| (required_mb + rate - 1) / rate);
+
+    Ok(Estimate {
+        from_shark: job.from_shark.clone(),
+        objects,
+        bytes,
+        required_mb,
+        shards,
+        max_fill_percentage,
+        capacity_mb,
+        unplaced_mb,
+        destinations,
+        throughput_mb_per_sec,
+        estimated_seconds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, available_mb: u64, pct: u8) -> StorageNode {
+        StorageNode {
+            available_mb,
+            percent_used: pct,
+            filesystem: String::from("/manta"),
+            datacenter: String::from("dc1"),
+            manta_storage_id: id.to_string(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn estimate_placement() {
+        // Sharks of 1000MB, with 800, 500 and 200MB left.
+        let sharks = vec![
+            node("1.stor", 800, 20),
+            node("2.stor", 500, 50),
+            node("3.stor", 200, 80),
+            node("4.stor", 1000, 0),
+        ];
+        let excluded: HashSet<String> =
+            vec![String::from("4.stor")].into_iter().collect();
+
+        // The emptiest shark is filled until it is level with the next.
+        let (dests, unplaced) = place(300, &sharks, &excluded, 90, |_| 0);
+        assert_eq!(unplaced, 0);
+        assert_eq!(dests.len(), 1);
+        assert_eq!((dests[0].placed_mb, dests[0].percent_used), (300, 20));
+        assert_eq!(dests[0].projected_percent_used, 50);
+
+        // Then both, and the odd megabyte goes to one of them.
+        let (dests, _) = place(401, &sharks, &excluded, 90, |_| 0);
+        let placed: Vec<u64> = dests.iter().map(|d| d.placed_mb).collect();
+        assert_eq!(placed, vec![351, 50]);
+
+        // What other jobs are sending a shark leaves it less room, and no
+        // shark goes beyond the ceiling.
+        let (dests, unplaced) =
+            place(2000, &sharks, &excluded, 90, |id| match id {
+                "1.stor" => 100,
+                _ => 0,
+            });
+        let placed: Vec<u64> = dests.iter().map(|d| d.placed_mb).collect();
+        assert_eq!(placed, vec![600, 400, 100]);
+        assert_eq!(unplaced, 2000 - 1100);
+        assert!(dests.iter().all(|d| d.projected_percent_used == 90));
+    }
+
+    #[test]
+    fn estimate_count_query() {
+        let filter = ObjectFilter {
+            owner: Some(String::from("a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10")),
+            min_size: Some(4096),
+            ..Default::default()
+        };
+        let (query, vals) = count_query("1.stor_x.domain", &Some(filter));
+
+        assert!(query.contains("_value LIKE $1"));
+        assert!(query.contains("owner = $2"));
+        assert!(query.contains("::bigint >= $3"));
+        assert_eq!(
+            vals,
+            vec![
+                String::from("%\"manta_storage_id\":\"1.stor\\_x.domain\"%"),
+                String::from("a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10"),
+                String::from("4096"),
+            ]
+        );
+    }
+}
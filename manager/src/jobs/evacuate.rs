@@ -9,38 +9,89 @@
  */
 
 use crate::metrics::{
-    metrics_error_inc, metrics_gauge_dec, metrics_gauge_inc, metrics_gauge_set,
-    metrics_object_inc_by, metrics_skip_inc, metrics_skip_inc_by,
-    ACTION_EVACUATE, MD_THREAD_GAUGE,
+    metrics_bandwidth_wait_observe, metrics_gauge_dec, metrics_gauge_inc,
+    metrics_gauge_set, metrics_header_check_inc, metrics_md_conflict_inc,
+    metrics_md_update_observe, metrics_moray_shard_error_inc,
+    metrics_moray_shard_observe, metrics_placement_excluded_inc,
+    metrics_poll_inc, metrics_record_disposition_inc, metrics_shark_add,
+    metrics_shark_remove, metrics_source_inc, GaugeShare,
+    ASSIGNMENTS_OUTSTANDING, HEADER_CHECK_MATCH, HEADER_CHECK_MISMATCH,
+    HEADER_CHECK_UNVERIFIABLE, MD_CONFLICT_ALREADY_APPLIED,
+    MD_CONFLICT_GAVE_UP, MD_CONFLICT_OBSOLETE, MD_CONFLICT_REBASED,
+    MD_THREAD_GAUGE, MD_UPDATE_QUEUE_DEPTH, MORAY_OP_READ, MORAY_OP_WRITE,
+    OBJECT_QUEUE_DEPTH, OVERFLOW_OBJECTS, PLACEMENT_REPLICA_IN_DATACENTER,
+    PLACEMENT_REPLICA_ON_SHARK, POLL_COMPLETE, POLL_FAILED, POLL_NOT_READY,
+    RESIDENT_OBJECTS, SOURCE_EVAC_SHARK, SOURCE_REPLICA,
 };
 use rebalancer::common::{
-    self, AssignmentPayload, ObjectId, ObjectSkippedReason, Task, TaskStatus,
+    self, AssignmentPayload, ObjectId, ObjectSkippedReason, Task, TaskAction,
+    TaskStatus,
 };
 use rebalancer::error::{
-    CrossbeamError, Error, InternalError, InternalErrorCode,
+    CrossbeamError, Error, InternalError, InternalErrorCode, RebalancerError,
 };
+use rebalancer::faults::{self, FaultPoint};
 use rebalancer::libagent::{
-    AgentAssignmentState, Assignment as AgentAssignment,
+    AgentAssignmentList, AgentAssignmentState, Assignment as AgentAssignment,
+    AssignmentRejection,
 };
+use rebalancer::util::now_ms;
 use rebalancer::util::{MAX_HTTP_STATUS_CODE, MIN_HTTP_STATUS_CODE};
 
-use crate::config::{Config, MAX_TUNABLE_MD_UPDATE_THREADS};
+use crate::agent_client::{self, AgentClientPool};
+use crate::config::{
+    Config, ConfigCircuitBreaker, MAX_TUNABLE_MD_UPDATE_THREADS,
+    MAX_TUNABLE_SHARKS, MAX_TUNABLE_TASKS_PER_ASSIGNMENT,
+};
+use crate::joblog;
+use crate::jobs::bandwidth;
+use crate::jobs::breaker::{self, CircuitBreaker, PauseReason};
+use crate::jobs::checkpoint::CheckpointSchedule;
+use crate::jobs::checksum::{self, ChecksumDisposition, ChecksumProblem};
+use crate::jobs::events::{
+    AssignmentEventWriter, BreakerMonitor, EvacuateEvent, EventBus,
+    FailureNotifier, HistoryMonitor, JobFeedback, MetricsRecorder,
+};
+use crate::jobs::filter::{self, ObjectFilter};
+use crate::jobs::history::{HistoryRecorder, PlacementHints};
+use crate::jobs::nofit;
+use crate::jobs::overflow::{self, OverflowQueue};
+use crate::jobs::placement::{self, PlacementDecision};
+use crate::jobs::plan;
+use crate::jobs::polling::PollSchedule;
+use crate::jobs::projected::{self, ProjectedUtilization};
+use crate::jobs::quarantine::{self, PostFailure};
+use crate::jobs::ramp::RampSchedule;
+use crate::jobs::record::{self, RecordDisposition};
+use crate::jobs::registry;
+use crate::jobs::schedule::{self, JobSchedule, ScheduleWindow};
+use crate::jobs::sizing::AssignmentSizer;
+use crate::jobs::snaplink::{self, Snaplink, SnaplinkStatus};
+use crate::jobs::source::{self, ObjectSource};
+use crate::jobs::source_copies::{self, SourceCopies};
+use crate::jobs::tuning::{self, JobTunables, Tunables};
+use crate::jobs::verify::{self, VerifyObject, VerifyObjectStatus};
+use crate::jobs::watchdog::{self, spawn_restartable, spawn_supervised};
 use crate::jobs::{
     assignment_cache_usage, Assignment, AssignmentCacheEntry, AssignmentId,
-    AssignmentState, JobUpdateMessage, StorageId,
+    AssignmentState, JobUpdateMessage, JobUpdateRequest, StorageId,
 };
 use crate::moray_client;
+use crate::notify::FailureTracker;
 use crate::pg_db;
-use crate::storinfo::{self as mod_storinfo, SharkSource, StorageNode};
+use crate::shutdown;
+use crate::storinfo::{
+    self as mod_storinfo, SharkChange, SharkSource, StorageNode,
+};
 
 use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 use std::error::Error as _Error;
-use std::io::Write;
+use std::io::{self, Write};
 use std::str::FromStr;
 use std::string::ToString;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
@@ -56,6 +107,7 @@ use moray::objects::{
 use quickcheck::{Arbitrary, Gen};
 use quickcheck_helpers::random::string as random_string;
 use rand::seq::SliceRandom;
+use rand::Rng;
 use reqwest;
 use serde::{self, Deserialize, Serialize};
 use serde_json::Value;
@@ -105,6 +157,22 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::{Integer, Nullable};
+    copy_config {
+        id -> Integer,
+        min_copies -> Nullable<Integer>,
+    }
+}
+
+table! {
+    use diesel::sql_types::Integer;
+    dest_limit_config {
+        id -> Integer,
+        max_dest_utilization_percent -> Integer,
+    }
+}
+
 table! {
     use diesel::sql_types::{Text, Array, Integer};
     duplicates(id) {
@@ -114,6 +182,69 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::{BigInt, Bool, Integer, Text};
+    scan_checkpoint(shard) {
+        shard -> Integer,
+        objects_scanned -> BigInt,
+        last_object_id -> Text,
+        complete -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Text};
+    download_attempts(id) {
+        id -> Text,
+        attempts -> Integer,
+        elapsed_ms -> BigInt,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Jsonb, Text};
+    slow_tasks(id) {
+        id -> Text,
+        assignment_id -> Text,
+        dest_shark -> Text,
+        elapsed_ms -> BigInt,
+        autopsy -> Jsonb,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Jsonb, Text};
+    header_mismatches(id) {
+        id -> Text,
+        dest_shark -> Text,
+        mismatches -> Jsonb,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+    assignment_events(id) {
+        id -> Integer,
+        assignment_id -> Text,
+        timestamp -> BigInt,
+        event -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Jsonb, Text};
+    metadata_audit(id) {
+        id -> Integer,
+        object_id -> Text,
+        key -> Text,
+        old_sharks -> Jsonb,
+        new_sharks -> Jsonb,
+        etag -> Text,
+        timestamp -> BigInt,
+    }
+}
+
 #[derive(Insertable, Queryable, Identifiable)]
 #[table_name = "evacuateobjects"]
 struct UpdateEvacuateObject<'a> {
@@ -127,6 +258,245 @@ pub struct EvacuateJobDbConfig {
     pub from_shark: Value,
 }
 
+/// What a create-copy or remove-copy job was asked to do (see
+/// EvacuateJobMode).  Evacuate jobs have no entry.
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "copy_config"]
+pub struct CopyJobDbConfig {
+    id: i32,
+    pub min_copies: Option<i32>,
+}
+
+/// The utilization ceiling that a job was given for its destinations, if it
+/// was given one (see EvacuateJob::set_max_dest_utilization()).  Jobs without
+/// one have no entry.
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "dest_limit_config"]
+pub struct DestLimitDbConfig {
+    id: i32,
+    pub max_dest_utilization_percent: i32,
+}
+
+/// How far the scan of a single shard has got.  The objects scanned include
+/// those that a job resumed from another sent on again (see
+/// replay_settled_shards()), and `complete` is set once every object in the
+/// shard has been sent on.
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "scan_checkpoint"]
+pub struct ScanCheckpoint {
+    pub shard: i32,
+    pub objects_scanned: i64,
+    pub last_object_id: String,
+    pub complete: bool,
+}
+
+// The number of objects of a shard that a job has recorded in its database.
+#[derive(QueryableByName)]
+struct ShardObjectCount {
+    #[sql_type = "sql_types::Integer"]
+    shard: i32,
+    #[sql_type = "sql_types::BigInt"]
+    count: i64,
+}
+
+/// A message from a shard scanner to the sharkspotter translator.
+enum ScanMessage {
+    Record(SharkspotterMessage),
+    // Every record in the shard has been sent.
+    ShardScanned(u32),
+}
+
+/// How many times, and for how long, an agent tried to download an object
+/// before it reported the object as failed.
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "download_attempts"]
+pub struct DownloadAttemptsEntry {
+    pub id: String,
+    pub attempts: i32,
+    pub elapsed_ms: i64,
+}
+
+/// An object that an agent took longer than its slow task threshold to move
+/// (whether it moved it or not), along with what the agent saw of it (see
+/// rebalancer::common::TaskAutopsy).
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "slow_tasks"]
+pub struct SlowTaskEntry {
+    pub id: String,
+    pub assignment_id: String,
+    pub dest_shark: String,
+    pub elapsed_ms: i64,
+    pub autopsy: Value,
+}
+
+/// An object whose copy on `dest_shark` was not served with the custom headers
+/// in its metadata, and how they differed (a list of
+/// jobs::verify::HeaderMismatch).  See EvacuateJob::check_dest_headers().
+#[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
+#[table_name = "header_mismatches"]
+pub struct HeaderMismatchEntry {
+    pub id: String,
+    pub dest_shark: String,
+    pub mismatches: Value,
+}
+
+#[derive(Insertable)]
+#[table_name = "metadata_audit"]
+struct NewMetadataAudit {
+    object_id: String,
+    key: String,
+    old_sharks: Value,
+    new_sharks: Value,
+    etag: String,
+    timestamp: i64,
+}
+
+/// A change that a job made to an object's metadata: the object's sharks
+/// before and after the change, and the etag that the object had before it
+/// (which the change was conditional on).  Putting `old_sharks` back undoes
+/// the change.  The timestamp is in ms since the epoch.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct MetadataAuditEntry {
+    pub id: i32,
+    pub object_id: String,
+    pub key: String,
+    pub old_sharks: Value,
+    pub new_sharks: Value,
+    pub etag: String,
+    pub timestamp: i64,
+}
+
+/// Something that happened to an assignment.  These are recorded in the job's
+/// local database as they happen so that the life of any one assignment can
+/// be seen after the fact (see assignment_lifecycle()).
+#[derive(Display, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum AssignmentEvent {
+    Created,         // Its tasks have been inserted into the local DB.
+    PostFailed,      // An attempt to post it to the agent failed.
+    Assigned,        // The agent accepted it.
+    Skipped,         // It was given up on, and its objects were skipped.
+    CancelRequested, // An operator asked the agent to cancel it.
+    AgentComplete,   // The agent reported that it had finished.
+    PostProcessed,   // The metadata of its objects has been updated.
+    Rerouted,        // Its objects were sent elsewhere, e.g. for lack of room.
+    Readopted,       // The agent was restarted, and still held it.
+}
+
+#[derive(Insertable)]
+#[table_name = "assignment_events"]
+struct NewAssignmentEvent<'a> {
+    assignment_id: &'a str,
+    timestamp: i64,
+    event: String,
+    detail: Option<String>,
+}
+
+/// A recorded AssignmentEvent.  The timestamp is in ms since the epoch.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct AssignmentEventEntry {
+    pub timestamp: i64,
+    pub event: String,
+    pub detail: Option<String>,
+}
+
+/// One object (task) in an assignment, and what became of it.
+#[derive(Debug, Serialize)]
+pub struct AssignmentTaskEntry {
+    pub object_id: String,
+    pub key: Option<String>,
+    pub status: String,
+    pub skipped_reason: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Everything the job's local database knows about one assignment.
+#[derive(Debug, Serialize)]
+pub struct AssignmentLifecycle {
+    pub id: String,
+    pub dest_shark: Option<String>,
+
+    // The most recent event, if any were recorded.
+    pub state: Option<String>,
+
+    // The number of times the assignment was posted to the agent.
+    pub post_attempts: usize,
+
+    // The total size of its objects, and when the agent last accepted it
+    // and reported that it had finished, in ms since the epoch.
+    pub bytes: u64,
+    pub posted: Option<i64>,
+    pub completed: Option<i64>,
+
+    // The number of tasks in each object status.
+    pub dispositions: HashMap<String, usize>,
+
+    pub events: Vec<AssignmentEventEntry>,
+    pub tasks: Vec<AssignmentTaskEntry>,
+}
+
+/// One of a job's assignments at a glance, as listed by
+/// assignment_summaries().  The fields are as for AssignmentLifecycle, with
+/// `tasks` the number of its tasks.
+#[derive(Debug, Serialize)]
+pub struct AssignmentSummary {
+    pub id: String,
+    pub dest_shark: Option<String>,
+    pub state: Option<String>,
+    pub tasks: i64,
+    pub bytes: i64,
+    pub post_attempts: usize,
+    pub posted: Option<i64>,
+    pub completed: Option<i64>,
+}
+
+#[derive(QueryableByName)]
+struct AssignmentIdRow {
+    #[sql_type = "sql_types::Text"]
+    assignment_id: String,
+}
+
+// The tasks of an assignment that a job has recorded in its database.
+#[derive(QueryableByName)]
+struct AssignmentTaskCount {
+    #[sql_type = "sql_types::Text"]
+    assignment_id: String,
+    #[sql_type = "sql_types::Text"]
+    dest_shark: String,
+    #[sql_type = "sql_types::BigInt"]
+    tasks: i64,
+    #[sql_type = "sql_types::BigInt"]
+    bytes: i64,
+}
+
+// The most objects found by the scan of the metadata tier that are queued for
+// the assignment manager, unless options.max_resident_objects is fewer.
+static DISCOVERY_QUEUE_DEPTH: usize = 100;
+
+// Every assignment has a created event, so its first event orders it by when
+// it was created.
+static ASSIGNMENT_IDS_QUERY: &str = "SELECT assignment_id \
+                                     FROM assignment_events \
+                                     GROUP BY assignment_id \
+                                     ORDER BY min(id) \
+                                     LIMIT $1 OFFSET $2";
+
+// Jobs that ran before assignment events were recorded only have their tasks.
+static OBJECT_ASSIGNMENT_IDS_QUERY: &str = "SELECT assignment_id \
+                                            FROM evacuateobjects \
+                                            WHERE assignment_id <> '' \
+                                            GROUP BY assignment_id \
+                                            ORDER BY assignment_id \
+                                            LIMIT $1 OFFSET $2";
+
+static ASSIGNMENT_TASK_COUNT_QUERY: &str =
+    "SELECT assignment_id, min(dest_shark) AS dest_shark, \
+     count(*) AS tasks, \
+     COALESCE(sum((object->>'contentLength')::bigint), 0)::bigint AS bytes \
+     FROM evacuateobjects \
+     WHERE assignment_id = ANY($1) \
+     GROUP BY assignment_id";
+
 #[derive(Clone, Debug, Insertable, AsChangeset, Queryable, Serialize)]
 #[table_name = "duplicates"]
 pub struct Duplicate {
@@ -232,6 +602,29 @@ pub enum EvacuateObjectError {
     MetadataUpdateFailed,
     MissingSharks,
     BadContentLength,
+    BadChecksum,
+    SnaplinkUpdateFailed,
+}
+
+impl EvacuateObjectError {
+    /// The code of this error, as reported to clients and in metrics.
+    pub fn rebalancer_error(self) -> RebalancerError {
+        match self {
+            EvacuateObjectError::BadMorayClient => RebalancerError::Unreachable,
+            EvacuateObjectError::BadMorayObject
+            | EvacuateObjectError::BadMantaObject
+            | EvacuateObjectError::BadShardNumber
+            | EvacuateObjectError::DuplicateShark
+            | EvacuateObjectError::BadContentLength
+            | EvacuateObjectError::BadChecksum => RebalancerError::BadMetadata,
+            EvacuateObjectError::MissingSharks => RebalancerError::NoSource,
+            EvacuateObjectError::MetadataUpdateFailed
+            | EvacuateObjectError::SnaplinkUpdateFailed => {
+                RebalancerError::MetadataUpdateFailed
+            }
+            EvacuateObjectError::InternalError => RebalancerError::Internal,
+        }
+    }
 }
 
 impl Arbitrary for EvacuateObjectError {
@@ -309,7 +702,7 @@ fn create_table_common(
     conn.execute(&create_query).map_err(Error::from)
 }
 
-fn create_config_table(conn: &PgConnection) -> Result<usize, Error> {
+pub fn create_config_table(conn: &PgConnection) -> Result<usize, Error> {
     let create_query = "CREATE TABLE config(
         id Integer PRIMARY KEY,
         from_shark Jsonb
@@ -318,6 +711,24 @@ fn create_config_table(conn: &PgConnection) -> Result<usize, Error> {
     create_table_common(conn, "config", create_query)
 }
 
+fn create_copy_config_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE copy_config(
+        id Integer PRIMARY KEY,
+        min_copies Integer
+    );";
+
+    create_table_common(conn, "copy_config", create_query)
+}
+
+fn create_dest_limit_config_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE dest_limit_config(
+        id Integer PRIMARY KEY,
+        max_dest_utilization_percent Integer NOT NULL
+    );";
+
+    create_table_common(conn, "dest_limit_config", create_query)
+}
+
 fn create_duplicate_table(conn: &PgConnection) -> Result<usize, Error> {
     let create_query = "CREATE TABLE duplicates(
         id TEXT PRIMARY KEY,
@@ -328,12 +739,272 @@ fn create_duplicate_table(conn: &PgConnection) -> Result<usize, Error> {
     create_table_common(conn, "duplicates", create_query)
 }
 
+fn create_scan_checkpoint_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE scan_checkpoint(
+        shard Integer PRIMARY KEY,
+        objects_scanned BigInt,
+        last_object_id TEXT,
+        complete BOOLEAN NOT NULL DEFAULT false
+    );";
+
+    create_table_common(conn, "scan_checkpoint", create_query)
+}
+
+fn create_download_attempts_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE download_attempts(
+        id TEXT PRIMARY KEY,
+        attempts Integer,
+        elapsed_ms BigInt
+    );";
+
+    create_table_common(conn, "download_attempts", create_query)
+}
+
+fn create_slow_tasks_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE slow_tasks(
+        id TEXT PRIMARY KEY,
+        assignment_id TEXT,
+        dest_shark TEXT,
+        elapsed_ms BigInt,
+        autopsy Jsonb
+    );";
+
+    create_table_common(conn, "slow_tasks", create_query)
+}
+
+fn create_header_mismatches_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE header_mismatches(
+        id TEXT PRIMARY KEY,
+        dest_shark TEXT,
+        mismatches Jsonb
+    );";
+
+    create_table_common(conn, "header_mismatches", create_query)
+}
+
+fn create_assignment_events_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE assignment_events(
+        id SERIAL PRIMARY KEY,
+        assignment_id TEXT,
+        timestamp BigInt,
+        event TEXT,
+        detail TEXT
+    );";
+
+    create_table_common(conn, "assignment_events", create_query)?;
+
+    conn.execute(
+        "CREATE INDEX assignment_events_id on assignment_events \
+         (assignment_id);",
+    )
+    .map_err(Error::from)
+}
+
+fn create_metadata_audit_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE metadata_audit(
+        id SERIAL PRIMARY KEY,
+        object_id TEXT,
+        key TEXT,
+        old_sharks Jsonb,
+        new_sharks Jsonb,
+        etag TEXT,
+        timestamp BigInt
+    );";
+
+    create_table_common(conn, "metadata_audit", create_query)
+}
+
+// An object may fail again in a later assignment (e.g. after it has been
+// retried), in which case only its latest attempts are kept.
+fn save_download_attempts(
+    conn: &PgConnection,
+    entries: &[DownloadAttemptsEntry],
+) -> Result<(), Error> {
+    use self::download_attempts::dsl::{download_attempts, id};
+
+    for entry in entries {
+        diesel::insert_into(download_attempts)
+            .values(entry)
+            .on_conflict(id)
+            .do_update()
+            .set(entry)
+            .execute(conn)
+            .map_err(Error::from)?;
+    }
+
+    Ok(())
+}
+
+// As with download attempts, only the latest slow attempt at an object is
+// kept.
+fn save_slow_tasks(
+    conn: &PgConnection,
+    entries: &[SlowTaskEntry],
+) -> Result<(), Error> {
+    use self::slow_tasks::dsl::{id, slow_tasks};
+
+    for entry in entries {
+        diesel::insert_into(slow_tasks)
+            .values(entry)
+            .on_conflict(id)
+            .do_update()
+            .set(entry)
+            .execute(conn)
+            .map_err(Error::from)?;
+    }
+
+    Ok(())
+}
+
+// An object is only checked again if it is moved again, in which case only
+// its latest copy is of interest.
+fn save_header_mismatch(
+    conn: &PgConnection,
+    entry: &HeaderMismatchEntry,
+) -> Result<(), Error> {
+    use self::header_mismatches::dsl::{header_mismatches, id};
+
+    diesel::insert_into(header_mismatches)
+        .values(entry)
+        .on_conflict(id)
+        .do_update()
+        .set(entry)
+        .execute(conn)
+        .map(|_| ())
+        .map_err(Error::from)
+}
+
+fn save_metadata_audit(
+    conn: &PgConnection,
+    entries: &[NewMetadataAudit],
+) -> Result<(), Error> {
+    use self::metadata_audit::dsl::metadata_audit;
+
+    // Keep each insert well clear of postgres' limit on bind parameters.
+    for chunk in entries.chunks(1000) {
+        diesel::insert_into(metadata_audit)
+            .values(chunk)
+            .execute(conn)
+            .map_err(Error::from)?;
+    }
+
+    Ok(())
+}
+
+fn object_sharks(object: &Value) -> Value {
+    object.get("sharks").cloned().unwrap_or(Value::Null)
+}
+
+// The audit entry for putting `object` in place of an object whose sharks
+// were `old_sharks`, conditional on its etag being `etag`.
+fn metadata_audit_entry(
+    old_sharks: &Value,
+    object: &Value,
+    etag: &str,
+) -> Result<NewMetadataAudit, Error> {
+    Ok(NewMetadataAudit {
+        object_id: common::get_objectId_from_value(object)?,
+        key: common::get_key_from_object_value(object)?,
+        old_sharks: old_sharks.clone(),
+        new_sharks: object_sharks(object),
+        etag: etag.to_string(),
+        timestamp: now_ms(),
+    })
+}
+
+/// Get up to `limit` of the changes that a job made to object metadata, in
+/// the order in which they were made, starting `offset` changes in.
+pub fn metadata_audit(
+    conn: &PgConnection,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<MetadataAuditEntry>, Error> {
+    use self::metadata_audit::dsl::{id, metadata_audit as audit_table};
+
+    audit_table
+        .order(id)
+        .limit(limit)
+        .offset(offset)
+        .load::<MetadataAuditEntry>(conn)
+        .map_err(Error::from)
+}
+
+/// Record an event in the life of an assignment.  This is only used for
+/// debugging, so an error is logged rather than failing the job.
+pub fn insert_assignment_event(
+    conn: &PgConnection,
+    assignment_uuid: &str,
+    event: AssignmentEvent,
+    detail: Option<String>,
+) {
+    use self::assignment_events::dsl::assignment_events;
+
+    if let Err(e) = diesel::insert_into(assignment_events)
+        .values(&NewAssignmentEvent {
+            assignment_id: assignment_uuid,
+            timestamp: now_ms(),
+            event: event.to_string(),
+            detail,
+        })
+        .execute(conn)
+    {
+        warn!(
+            "Could not record event '{}' for assignment {}: {}",
+            event, assignment_uuid, e
+        );
+    }
+}
+
+/// Get the scan checkpoints recorded by a job.
+pub fn scan_checkpoint(job_id: &str) -> Result<Vec<ScanCheckpoint>, Error> {
+    use self::scan_checkpoint::dsl::scan_checkpoint as checkpoint_table;
+
+    let conn = pg_db::connect_db(job_id)?;
+
+    // Jobs run before shards were marked complete never finished one.
+    conn.execute(
+        "ALTER TABLE scan_checkpoint ADD COLUMN IF NOT EXISTS \
+         complete BOOLEAN NOT NULL DEFAULT false;",
+    )?;
+
+    checkpoint_table
+        .load::<ScanCheckpoint>(&conn)
+        .map_err(Error::from)
+}
+
+/// Get the scan checkpoints of the shards that the job `job_id` finished
+/// with: every object in the shard was sent on, and every one of them was
+/// recorded in the job's database.  Objects that were found but not recorded
+/// (e.g. those of assignments that were discarded when the job was
+/// interrupted) would be missed if the shard were not scanned again.
+pub fn settled_shards(job_id: &str) -> Result<Vec<ScanCheckpoint>, Error> {
+    let checkpoints = scan_checkpoint(job_id)?;
+    let conn = pg_db::connect_db(job_id)?;
+
+    let recorded: HashMap<i32, i64> = diesel::sql_query(
+        "SELECT shard, count(*) AS count FROM evacuateobjects GROUP BY shard",
+    )
+    .load::<ShardObjectCount>(&conn)?
+    .into_iter()
+    .map(|c| (c.shard, c.count))
+    .collect();
+
+    Ok(checkpoints
+        .into_iter()
+        .filter(|c| {
+            c.complete
+                && recorded.get(&c.shard).cloned().unwrap_or(0)
+                    >= c.objects_scanned
+        })
+        .collect())
+}
+
 // We only want to store a single configuration entry for the evacaute job.
 // The reason we store it here instead of adding it on as a json blob to the
 // rebalancer database's jobs table is because this keeps all the
 // information for the evacuate job in a single location.  Doing so makes
 // backing up the database after completion much easier.
-fn update_evacuate_config_impl(
+pub fn update_evacuate_config_impl(
     conn: &PgConnection,
     from_shark: &MantaObjectShark,
 ) -> Result<usize, Error> {
@@ -370,7 +1041,49 @@ fn update_evacuate_config_impl(
     Ok(updated_records)
 }
 
-fn build_skipped_strings() -> Vec<String> {
+// As with the evacuate job configuration, there is only a single entry.
+fn update_copy_config_impl(
+    conn: &PgConnection,
+    min_copies: Option<u32>,
+) -> Result<usize, Error> {
+    use self::copy_config::dsl::{copy_config as copy_table, id as copy_id};
+
+    let value = CopyJobDbConfig {
+        id: 1,
+        min_copies: min_copies.map(|n| n as i32),
+    };
+
+    diesel::insert_into(copy_table)
+        .values(&value)
+        .on_conflict(copy_id)
+        .do_update()
+        .set(&value)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+// As with the copy configuration, there is only a single entry.
+fn update_dest_limit_config_impl(
+    conn: &PgConnection,
+    percent: u32,
+) -> Result<usize, Error> {
+    use self::dest_limit_config::dsl::{dest_limit_config as limit_table, id};
+
+    let value = DestLimitDbConfig {
+        id: 1,
+        max_dest_utilization_percent: percent as i32,
+    };
+
+    diesel::insert_into(limit_table)
+        .values(&value)
+        .on_conflict(id)
+        .do_update()
+        .set(&value)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+pub fn build_skipped_strings() -> Vec<String> {
     let mut skipped_strings: Vec<String> = vec![];
 
     for reason in ObjectSkippedReason::iter() {
@@ -431,21 +1144,461 @@ pub fn create_evacuateobjects_table(
     )
     .map_err(Error::from)
 }
-// --- END Diesel Stuff --- //
 
-struct FiniMsg;
+// Every object in an assignment records the destination shark that the
+// assignment was sent to, so we only need to look at one of them.
+fn assignment_dest_shark(
+    job_uuid: &str,
+    assignment_uuid: &str,
+) -> Result<Option<String>, Error> {
+    use self::evacuateobjects::dsl::{
+        assignment_id, dest_shark, evacuateobjects,
+    };
 
-impl Default for EvacuateObjectStatus {
-    fn default() -> Self {
-        EvacuateObjectStatus::Unprocessed
-    }
+    let conn = pg_db::connect_db(job_uuid)?;
+
+    evacuateobjects
+        .select(dest_shark)
+        .filter(assignment_id.eq(assignment_uuid))
+        .first::<String>(&conn)
+        .optional()
+        .map_err(Error::from)
 }
 
-/// Wrap a given MantaObject in another structure so that we can track it's
-/// progress through the evacuation process.
-#[derive(
-    Insertable,
-    Queryable,
+/// Get up to `limit` of a job's objects, in order of object ID, starting
+/// after the given ID.  This allows all of the objects of a very large job to
+/// be read without holding them all in memory.
+pub fn objects_after(
+    conn: &PgConnection,
+    after_id: Option<&str>,
+    limit: i64,
+) -> Result<Vec<EvacuateObject>, Error> {
+    use self::evacuateobjects::dsl::{evacuateobjects, id};
+
+    let query = evacuateobjects.order(id).limit(limit).into_boxed();
+    let query = match after_id {
+        Some(after) => query.filter(id.gt(after.to_string())),
+        None => query,
+    };
+
+    query.load::<EvacuateObject>(conn).map_err(Error::from)
+}
+
+/// Get everything known about one assignment of a job: its tasks and what
+/// became of each of them, and each recorded event in its life.  Returns
+/// None if the job has no such assignment.
+pub fn assignment_lifecycle(
+    conn: &PgConnection,
+    assignment_uuid: &str,
+) -> Result<Option<AssignmentLifecycle>, Error> {
+    use self::assignment_events::dsl::{
+        assignment_events, assignment_id as event_assignment_id, detail, event,
+        id as event_id, timestamp,
+    };
+    use self::evacuateobjects::dsl::{assignment_id, evacuateobjects, id};
+
+    let objects: Vec<EvacuateObject> = evacuateobjects
+        .filter(assignment_id.eq(assignment_uuid))
+        .order(id)
+        .load(conn)
+        .map_err(Error::from)?;
+
+    // Jobs that ran before assignment events were recorded have no table
+    // for them.  Their tasks are still worth reporting.
+    let events: Vec<AssignmentEventEntry> = assignment_events
+        .select((timestamp, event, detail))
+        .filter(event_assignment_id.eq(assignment_uuid))
+        .order(event_id)
+        .load(conn)
+        .unwrap_or_else(|e| {
+            warn!(
+                "Could not load events of assignment {}: {}",
+                assignment_uuid, e
+            );
+            vec![]
+        });
+
+    if objects.is_empty() && events.is_empty() {
+        return Ok(None);
+    }
+
+    let mut dispositions = HashMap::new();
+    for eobj in objects.iter() {
+        *dispositions.entry(eobj.status.to_string()).or_insert(0) += 1;
+    }
+
+    let (post_attempts, posted, completed) = assignment_event_times(&events);
+
+    let tasks = objects
+        .iter()
+        .map(|eobj| AssignmentTaskEntry {
+            object_id: eobj.id.clone(),
+            key: eobj
+                .object
+                .get("key")
+                .and_then(|k| k.as_str())
+                .map(String::from),
+            status: eobj.status.to_string(),
+            skipped_reason: eobj.skipped_reason.as_ref().map(|r| r.to_string()),
+            error: eobj.error.as_ref().map(|e| e.to_string()),
+        })
+        .collect();
+
+    Ok(Some(AssignmentLifecycle {
+        id: assignment_uuid.to_string(),
+        dest_shark: objects.first().map(|o| o.dest_shark.clone()),
+        state: events.last().map(|e| e.event.clone()),
+        post_attempts,
+        bytes: objects.iter().map(object_bytes).sum(),
+        posted,
+        completed,
+        dispositions,
+        events,
+        tasks,
+    }))
+}
+
+// The number of times an assignment was posted to its agent, and when the
+// agent last accepted it and reported that it had finished.
+fn assignment_event_times(
+    events: &[AssignmentEventEntry],
+) -> (usize, Option<i64>, Option<i64>) {
+    let last = |event: AssignmentEvent| {
+        let event = event.to_string();
+        events
+            .iter()
+            .filter(|e| e.event == event)
+            .map(|e| e.timestamp)
+            .last()
+    };
+
+    let post_attempts = events
+        .iter()
+        .filter(|e| {
+            e.event == AssignmentEvent::PostFailed.to_string()
+                || e.event == AssignmentEvent::Assigned.to_string()
+        })
+        .count();
+
+    (
+        post_attempts,
+        last(AssignmentEvent::Assigned),
+        last(AssignmentEvent::AgentComplete),
+    )
+}
+
+/// Summarize up to `limit` of a job's assignments, in the order that they
+/// were created, starting `offset` assignments in.  An assignment whose
+/// objects were all rerouted elsewhere has no tasks left of its own.
+pub fn assignment_summaries(
+    conn: &PgConnection,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AssignmentSummary>, Error> {
+    use self::assignment_events::dsl::{
+        assignment_events, assignment_id as event_assignment_id, detail, event,
+        id as event_id, timestamp,
+    };
+
+    let page = |query: &str| {
+        diesel::sql_query(query)
+            .bind::<sql_types::BigInt, _>(limit)
+            .bind::<sql_types::BigInt, _>(offset)
+            .load::<AssignmentIdRow>(conn)
+    };
+
+    let (ids, events) = match page(ASSIGNMENT_IDS_QUERY) {
+        Ok(rows) => {
+            let ids: Vec<String> =
+                rows.into_iter().map(|r| r.assignment_id).collect();
+            let events: Vec<(String, AssignmentEventEntry)> = assignment_events
+                .select((event_assignment_id, (timestamp, event, detail)))
+                .filter(event_assignment_id.eq_any(&ids))
+                .order(event_id)
+                .load(conn)
+                .map_err(Error::from)?;
+            (ids, events)
+        }
+        Err(e) => {
+            debug!("No assignment events, listing tasks only: {}", e);
+            let rows = page(OBJECT_ASSIGNMENT_IDS_QUERY)?;
+            (rows.into_iter().map(|r| r.assignment_id).collect(), vec![])
+        }
+    };
+
+    let mut counts: HashMap<String, AssignmentTaskCount> =
+        diesel::sql_query(ASSIGNMENT_TASK_COUNT_QUERY)
+            .bind::<sql_types::Array<sql_types::Text>, _>(&ids)
+            .load::<AssignmentTaskCount>(conn)?
+            .into_iter()
+            .map(|c| (c.assignment_id.clone(), c))
+            .collect();
+
+    let mut events_by_id: HashMap<String, Vec<AssignmentEventEntry>> =
+        HashMap::new();
+    for (id, entry) in events {
+        events_by_id.entry(id).or_insert_with(Vec::new).push(entry);
+    }
+
+    Ok(ids
+        .into_iter()
+        .map(|id| {
+            let events = events_by_id.remove(&id).unwrap_or_default();
+            let (post_attempts, posted, completed) =
+                assignment_event_times(&events);
+            let count = counts.remove(&id);
+
+            AssignmentSummary {
+                dest_shark: count.as_ref().map(|c| c.dest_shark.clone()),
+                state: events.last().map(|e| e.event.clone()),
+                tasks: count.as_ref().map(|c| c.tasks).unwrap_or(0),
+                bytes: count.as_ref().map(|c| c.bytes).unwrap_or(0),
+                post_attempts,
+                posted,
+                completed,
+                id,
+            }
+        })
+        .collect())
+}
+// --- END Diesel Stuff --- //
+
+#[derive(Debug)]
+pub enum CancelAssignmentError {
+    NotFound,           // No assignment by that uuid exists in the job.
+    AlreadyComplete,    // The agent has already finished the assignment.
+    AgentError(String), // Could not communicate with the agent.
+}
+
+/// Ask the agent that an assignment was sent to to stop processing it.  The
+/// agent reports every task that it did not get to as failed with a reason of
+/// `AssignmentCancelled`.  The running job's assignment checker then picks up
+/// the assignment as it normally would, returns the space that was reserved
+/// on the destination shark, and gives the objects of those tasks back to the
+/// job's assignment manager to be sent elsewhere, as is done for a drained
+/// destination (see EvacuateJob::reroute_cancelled_tasks()).  Only if the
+/// assignment manager has already finished are they marked as skipped, to be
+/// re-processed by a subsequent retry job.
+pub fn cancel_assignment(
+    job_uuid: &str,
+    assignment_uuid: &str,
+) -> Result<(), CancelAssignmentError> {
+    let dest_shark = match assignment_dest_shark(job_uuid, assignment_uuid) {
+        Ok(Some(ds)) => ds,
+        Ok(None) => return Err(CancelAssignmentError::NotFound),
+        Err(e) => {
+            return Err(CancelAssignmentError::AgentError(format!(
+                "Could not look up assignment {}: {}",
+                assignment_uuid, e
+            )));
+        }
+    };
+
+    let uri = format!(
+        "http://{}:7878/assignments/{}/cancel",
+        dest_shark, assignment_uuid
+    );
+
+    info!(
+        "Operator requested cancellation of assignment {} (job {}) on {}",
+        assignment_uuid, job_uuid, dest_shark
+    );
+
+    let pool = agent_client::shared();
+    let res = pool.checkout(&dest_shark).post(&uri).send().map_err(|e| {
+        CancelAssignmentError::AgentError(format!(
+            "Could not contact agent on {}: {}",
+            dest_shark, e
+        ))
+    })?;
+
+    match res.status() {
+        reqwest::StatusCode::OK => {
+            if let Ok(conn) = pg_db::connect_db(job_uuid) {
+                insert_assignment_event(
+                    &conn,
+                    assignment_uuid,
+                    AssignmentEvent::CancelRequested,
+                    None,
+                );
+            }
+            Ok(())
+        }
+        reqwest::StatusCode::NOT_FOUND => Err(CancelAssignmentError::NotFound),
+        reqwest::StatusCode::CONFLICT => {
+            Err(CancelAssignmentError::AlreadyComplete)
+        }
+        status => Err(CancelAssignmentError::AgentError(format!(
+            "Agent on {} responded with {}",
+            dest_shark, status
+        ))),
+    }
+}
+
+/// What became of the assignments that a job had outstanding when the
+/// manager stopped without shutting down gracefully.
+#[derive(Debug, Default)]
+pub struct ReconcileSummary {
+    /// Assignments that the agent had finished.
+    pub complete: usize,
+
+    /// Assignments that the agent was still working on, which were
+    /// cancelled.
+    pub cancelled: usize,
+
+    /// Assignments that the agent did not know about, or that could not be
+    /// looked up.
+    pub missing: usize,
+
+    /// Objects marked as skipped as a result.
+    pub skipped_objects: usize,
+}
+
+// Mark every object in an assignment that is still assigned as skipped.
+fn skip_assigned_objects(
+    conn: &PgConnection,
+    assignment_uuid: &str,
+    reason: ObjectSkippedReason,
+) -> Result<usize, Error> {
+    use self::evacuateobjects::dsl::{
+        assignment_id, evacuateobjects, skipped_reason, status,
+    };
+
+    diesel::update(evacuateobjects)
+        .filter(assignment_id.eq(assignment_uuid))
+        .filter(status.eq(EvacuateObjectStatus::Assigned))
+        .set((
+            status.eq(EvacuateObjectStatus::Skipped),
+            skipped_reason.eq(Some(reason)),
+        ))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+// Mark the objects of the tasks that an agent reported as failed as skipped.
+fn skip_failed_task_objects(
+    conn: &PgConnection,
+    tasks: Vec<Task>,
+) -> Result<usize, Error> {
+    use self::evacuateobjects::dsl::{
+        evacuateobjects, id, skipped_reason, status,
+    };
+
+    let mut count = 0;
+
+    for t in tasks {
+        if let TaskStatus::Failed(reason) = t.status {
+            count += diesel::update(evacuateobjects)
+                .filter(id.eq(t.object_id))
+                .set((
+                    status.eq(EvacuateObjectStatus::Skipped),
+                    skipped_reason.eq(Some(reason)),
+                ))
+                .execute(conn)
+                .map_err(Error::from)?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Bring the local database of a job that was running when the manager
+/// stopped unexpectedly up to date with the agents.  Each agent that the job
+/// had an outstanding assignment with is asked for the state of that
+/// assignment:
+///  * If the agent has finished it, the objects of any tasks that failed are
+///    marked as skipped.  The objects that were copied remain assigned, as
+///    their metadata was never updated.
+///  * If the agent is still working on it, the assignment is cancelled, since
+///    nothing would update the metadata of the objects it copies, and its
+///    objects are marked as skipped (`assignment_cancelled`).
+///  * If the agent does not know about it, or can not be reached, its objects
+///    are marked as skipped (`agent_assignment_no_ent`).
+pub fn reconcile_assignments(
+    job_uuid: &str,
+) -> Result<ReconcileSummary, Error> {
+    use self::evacuateobjects::dsl::{
+        assignment_id, dest_shark, evacuateobjects, status,
+    };
+
+    let conn = pg_db::connect_db(job_uuid)?;
+    let outstanding: Vec<(String, String)> = evacuateobjects
+        .select((assignment_id, dest_shark))
+        .filter(status.eq(EvacuateObjectStatus::Assigned))
+        .distinct()
+        .load(&conn)
+        .map_err(Error::from)?;
+
+    let pool = agent_client::shared();
+    let mut summary = ReconcileSummary::default();
+
+    for (assignment_uuid, shark) in outstanding {
+        let uri =
+            format!("http://{}:7878/assignments/{}", shark, assignment_uuid);
+
+        let agent_assignment = match pool.checkout(&shark).get(&uri).send() {
+            Ok(mut resp) => {
+                if resp.status().is_success() {
+                    resp.json::<AgentAssignment>().ok()
+                } else {
+                    None
+                }
+            }
+            Err(e) => {
+                warn!("Could not contact agent on {}: {}", shark, e);
+                None
+            }
+        };
+
+        match agent_assignment.map(|a| a.stats.state) {
+            Some(AgentAssignmentState::Complete(failed)) => {
+                summary.complete += 1;
+                summary.skipped_objects += skip_failed_task_objects(
+                    &conn,
+                    failed.unwrap_or_default(),
+                )?;
+            }
+            Some(_) => {
+                if let Err(e) = cancel_assignment(job_uuid, &assignment_uuid) {
+                    warn!(
+                        "Could not cancel assignment {} on {}: {:?}",
+                        assignment_uuid, shark, e
+                    );
+                }
+                summary.cancelled += 1;
+                summary.skipped_objects += skip_assigned_objects(
+                    &conn,
+                    &assignment_uuid,
+                    ObjectSkippedReason::AssignmentCancelled,
+                )?;
+            }
+            None => {
+                summary.missing += 1;
+                summary.skipped_objects += skip_assigned_objects(
+                    &conn,
+                    &assignment_uuid,
+                    ObjectSkippedReason::AgentAssignmentNoEnt,
+                )?;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+struct FiniMsg;
+
+impl Default for EvacuateObjectStatus {
+    fn default() -> Self {
+        EvacuateObjectStatus::Unprocessed
+    }
+}
+
+/// Wrap a given MantaObject in another structure so that we can track it's
+/// progress through the evacuation process.
+#[derive(
+    Insertable,
+    Queryable,
     Identifiable,
     AsChangeset,
     AsExpression,
@@ -528,6 +1681,8 @@ pub struct EvacuateDestShark {
     pub assigned_mb: u64,
 }
 
+/// A change to a running job, as sent with PUT /jobs/<uuid>.  See the
+/// jobs::tuning module.
 ///
 /// ```
 /// use serde_json::json;
@@ -539,18 +1694,66 @@ pub struct EvacuateDestShark {
 /// });
 ///
 /// let deserialized: EvacuateJobUpdateMessage = serde_json::from_value(payload).unwrap();
-/// let EvacuateJobUpdateMessage::SetMetadataThreads(thr_count) = deserialized;
-/// assert_eq!(thr_count, 30);
+/// match deserialized {
+///     EvacuateJobUpdateMessage::SetMetadataThreads(thr_count) => {
+///         assert_eq!(thr_count, 30)
+///     }
+///     msg => panic!("unexpected message: {:?}", msg),
+/// }
 /// ```
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "action", content = "params", rename_all = "snake_case")]
 pub enum EvacuateJobUpdateMessage {
     SetMetadataThreads(usize),
+    SetMaxSharks(usize),
+    SetMaxTasksPerAssignment(usize),
+    SetCircuitBreaker(CircuitBreakerUpdate),
+    DrainDestination(StorageId),
+}
+
+/// New thresholds for a job's circuit breaker.  Those that are not given are
+/// left as they are.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreakerUpdate {
+    pub max_error_rate: Option<f64>,
+    pub max_consecutive_errors: Option<u64>,
+    pub min_objects: Option<u64>,
+}
+
+impl CircuitBreakerUpdate {
+    fn apply(&self, config: ConfigCircuitBreaker) -> ConfigCircuitBreaker {
+        ConfigCircuitBreaker {
+            max_error_rate: self
+                .max_error_rate
+                .unwrap_or(config.max_error_rate),
+            max_consecutive_errors: self
+                .max_consecutive_errors
+                .unwrap_or(config.max_consecutive_errors),
+            min_objects: self.min_objects.unwrap_or(config.min_objects),
+        }
+    }
+}
+
+// Check that a new value for a count-like tunable is between 1 and `max`.
+fn validate_tunable(
+    name: &str,
+    value: usize,
+    max: usize,
+) -> Result<(), String> {
+    if value < 1 {
+        return Err(format!("Cannot set {} below 1", name));
+    }
+
+    if value > max {
+        return Err(format!("Cannot set {} above {}", name, max));
+    }
+
+    Ok(())
 }
 
 impl EvacuateJobUpdateMessage {
     pub fn validate(&self) -> Result<(), String> {
-        #[allow(clippy::single_match)]
         match self {
             EvacuateJobUpdateMessage::SetMetadataThreads(num_threads) => {
                 if *num_threads < 1 {
@@ -571,6 +1774,39 @@ impl EvacuateJobUpdateMessage {
                     ));
                 }
             }
+            EvacuateJobUpdateMessage::SetMaxSharks(max_sharks) => {
+                validate_tunable("max_sharks", *max_sharks, MAX_TUNABLE_SHARKS)?
+            }
+            EvacuateJobUpdateMessage::SetMaxTasksPerAssignment(max_tasks) => {
+                validate_tunable(
+                    "max_tasks_per_assignment",
+                    *max_tasks,
+                    MAX_TUNABLE_TASKS_PER_ASSIGNMENT,
+                )?
+            }
+            EvacuateJobUpdateMessage::SetCircuitBreaker(update) => {
+                if update.max_error_rate.is_none()
+                    && update.max_consecutive_errors.is_none()
+                    && update.min_objects.is_none()
+                {
+                    return Err(String::from(
+                        "No circuit breaker thresholds given",
+                    ));
+                }
+
+                if let Some(rate) = update.max_error_rate {
+                    if !(0.0..=1.0).contains(&rate) {
+                        return Err(String::from(
+                            "max_error_rate must be between 0 and 1",
+                        ));
+                    }
+                }
+            }
+            EvacuateJobUpdateMessage::DrainDestination(shark) => {
+                if shark.is_empty() {
+                    return Err(String::from("No destination given to drain"));
+                }
+            }
         }
         Ok(())
     }
@@ -586,6 +1822,34 @@ pub enum EvacuateJobType {
     Retry(String),
 }
 
+/// What the job does with the objects that it finds on `from_shark`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvacuateJobMode {
+    /// Move each object to another shark, and remove `from_shark` from its
+    /// metadata.
+    Evacuate,
+
+    /// Add a copy of each object on another shark, leaving the copy on
+    /// `from_shark` in place.  If `min_copies` is given, only objects that
+    /// have fewer copies than that are copied.
+    CreateCopy { min_copies: Option<u32> },
+
+    /// Remove the copy of each object on `from_shark`, provided that at least
+    /// `min_copies` copies remain on other sharks.  The agent on `from_shark`
+    /// deletes its copy, and `from_shark` is then removed from the object's
+    /// metadata.
+    RemoveCopy { min_copies: u32 },
+}
+
+/// The run (boot) of a destination's agent that a job last reconciled its
+/// assignments with, and when the agent was first found to be unreachable,
+/// if it has been since.
+#[derive(Debug, Default)]
+pub struct AgentBoot {
+    pub boot_id: Option<String>,
+    pub unreachable_since: Option<std::time::Instant>,
+}
+
 /// Evacuate a given shark
 pub struct EvacuateJob {
     pub config: Config,
@@ -605,20 +1869,125 @@ pub struct EvacuateJob {
 
     pub conn: Mutex<PgConnection>,
 
-    pub post_client: reqwest::Client,
-
-    pub get_client: reqwest::Client,
+    /// Shared by every job, see the agent_client module.
+    pub agent_pool: Arc<AgentClientPool>,
 
     pub bytes_transferred: AtomicU64,
 
+    /// The number of objects added to assignments with another replica as
+    /// the source, and with the shark being evacuated as the source (which
+    /// only happens in slow source mode).
+    pub replica_sourced: AtomicU64,
+    pub evac_shark_sourced: AtomicU64,
+
+    /// The number of times a destination shark was passed over for an
+    /// object because of where the object's other copies are.
+    pub placement_excluded: AtomicU64,
+
+    /// The number of metadata records found that were not rebalanced
+    /// because they are not regular objects, by disposition.
+    pub record_dispositions: Mutex<HashMap<RecordDisposition, u64>>,
+
     pub db_name: String,
 
     pub evac_type: EvacuateJobType,
 
+    pub mode: EvacuateJobMode,
+
     // Timer starts when first object is found and stops at the end of the job.
     pub object_movement_start_time: Mutex<Option<std::time::Instant>>,
 
-    pub update_rx: Option<crossbeam_channel::Receiver<JobUpdateMessage>>,
+    pub update_rx: Option<crossbeam_channel::Receiver<JobUpdateRequest>>,
+
+    /// The limits that can be changed while the job runs.  See the
+    /// jobs::tuning module.
+    pub tunables: Tunables,
+
+    /// Cleared once the job no longer takes updates.
+    pub accepting_updates: AtomicBool,
+
+    /// Destination shark utilization shared with all other running jobs.
+    pub projected: Arc<ProjectedUtilization>,
+
+    /// Count of objects that could not be moved, for error threshold
+    /// notifications.
+    pub failures: Arc<FailureTracker>,
+
+    /// How far the job has worked up to its full concurrency.
+    pub ramp: Arc<RampSchedule>,
+
+    /// Number of tasks to put in the assignments for each destination.
+    pub sizing: Arc<AssignmentSizer>,
+
+    /// Pauses the job if too many of its objects fail to move.
+    pub breaker: Arc<CircuitBreaker>,
+
+    /// When each of the job's assignments is next due to be polled.
+    pub polling: PollSchedule,
+
+    /// Where what happens to the job's objects and assignments is published
+    /// for metrics, the job's database and notifications.  See the
+    /// jobs::events module.
+    pub events: EventBus,
+
+    /// Set if the job stopped early because the manager is shutting down.
+    pub interrupted: AtomicBool,
+
+    /// Destinations whose agents have turned down an assignment for lack of
+    /// space, and when they did.  See reroute_assignment().
+    pub full_sharks: Mutex<HashMap<StorageId, std::time::Instant>>,
+
+    /// What the job knows of the run of the agent on each destination that
+    /// it has had to reconcile its assignments with.  See reconcile_agent().
+    pub agent_boots: Mutex<HashMap<StorageId, AgentBoot>>,
+
+    /// The objects of assignments that were turned down for lack of space,
+    /// waiting to be given to other destinations, some of which may have been
+    /// spilled to the local database (see the jobs::overflow module).  This
+    /// is None once the assignment manager has finished.
+    pub rerouted: Mutex<Option<OverflowQueue>>,
+
+    /// The destinations of objects with more than one copy on the shark
+    /// being evacuated.  See the jobs::source_copies module.
+    pub source_copies: SourceCopies,
+
+    /// Destinations that an operator has drained, which are given no more of
+    /// the job's objects.  See drain_destination().
+    pub drained_dests: Mutex<HashSet<StorageId>>,
+
+    /// Destinations that their history across jobs says to avoid.  See the
+    /// jobs::history module.
+    pub hints: PlacementHints,
+
+    /// The interrupted job that this job was resumed from, if any.  This job
+    /// does not scan the shards that that job finished with again (see
+    /// start_sharkspotter()).
+    pub resume_from: Option<String>,
+
+    /// The utilization beyond which the job never takes a destination, if
+    /// it was given one.  See set_max_dest_utilization().
+    pub max_dest_utilization: Option<u32>,
+
+    /// The daily window outside of which the job posts no assignments, if it
+    /// was given one.  See set_schedule().
+    pub schedule: Option<ScheduleWindow>,
+
+    /// Which of the objects found the job works on, if it was given a
+    /// filter.  See set_filter().
+    pub filter: Option<ObjectFilter>,
+
+    /// Where the job finds its objects, if it was given a source.  See
+    /// set_source().
+    pub source: Option<ObjectSource>,
+
+    /// Asks destinations about their copies before the metadata is updated,
+    /// if options.verify_before_update is set.  See verify_dest_copy().
+    pub dest_verifier: Option<reqwest::Client>,
+
+    /// Asks destinations for the custom headers of a sample of their copies
+    /// once the metadata has been updated, if options.header_check_percent
+    /// is set.  See check_dest_headers().
+    pub header_checker: Option<reqwest::Client>,
 
     /// TESTING ONLY
     pub max_objects: Option<u32>,
@@ -660,7 +2029,7 @@ impl EvacuateJob {
         storage_id: String,
         config: &Config,
         db_name: &str,
-        update_rx: Option<crossbeam_channel::Receiver<JobUpdateMessage>>,
+        update_rx: Option<crossbeam_channel::Receiver<JobUpdateRequest>>,
         max_objects: Option<u32>,
     ) -> Result<Self, Error> {
         let mut job = Self::new_common(storage_id, config, db_name, update_rx)?;
@@ -674,7 +2043,7 @@ impl EvacuateJob {
         storage_id: String,
         config: &Config,
         db_name: &str,
-        update_rx: Option<crossbeam_channel::Receiver<JobUpdateMessage>>,
+        update_rx: Option<crossbeam_channel::Receiver<JobUpdateRequest>>,
         retry_uuid: &str,
     ) -> Result<Self, Error> {
         let mut job = Self::new_common(storage_id, config, db_name, update_rx)?;
@@ -685,76 +2054,619 @@ impl EvacuateJob {
         Ok(job)
     }
 
-    fn new_common(
-        storage_id: String,
-        config: &Config,
-        db_name: &str,
-        update_rx: Option<crossbeam_channel::Receiver<JobUpdateMessage>>,
-    ) -> Result<Self, Error> {
-        let mut from_shark = MantaObjectShark::default();
-        let conn = match pg_db::create_and_connect_db(db_name) {
-            Ok(c) => c,
-            Err(e) => {
-                println!("Error creating Evacuate Job: {}", e);
-                std::process::exit(1);
-            }
-        };
-
-        create_evacuateobjects_table(&conn)?;
-        create_config_table(&conn)?;
-        create_duplicate_table(&conn)?;
-
-        from_shark.manta_storage_id = storage_id;
-
-        update_evacuate_config_impl(&conn, &from_shark)?;
+    /// Have the job add copies of objects rather than evacuate them (see
+    /// EvacuateJobMode::CreateCopy).  This is recorded in the job's database
+    /// so that the job can be retried or resumed as a create-copy job.
+    pub fn set_create_copy(
+        &mut self,
+        min_copies: Option<u32>,
+    ) -> Result<(), Error> {
+        let conn = self.conn.lock().expect("DB conn lock");
+        update_copy_config_impl(&conn, min_copies)?;
 
-        Ok(Self {
-            config: config.to_owned(),
-            min_avail_mb: Some(1000), // TODO: config
-            dest_shark_hash: RwLock::new(HashMap::new()),
-            assignments: RwLock::new(HashMap::new()),
-            from_shark,
-            conn: Mutex::new(conn),
-            max_objects: Some(10),
-            post_client: reqwest::Client::new(),
-            get_client: reqwest::Client::new(),
-            update_rx,
-            evac_type: EvacuateJobType::Initial,
-            db_name: db_name.to_string(),
-            bytes_transferred: AtomicU64::new(0),
-            object_movement_start_time: Mutex::new(None),
-        })
+        self.mode = EvacuateJobMode::CreateCopy { min_copies };
+        Ok(())
     }
 
-    pub fn create_tables(&self) -> Result<usize, Error> {
+    /// Have the job remove copies of objects rather than evacuate them (see
+    /// EvacuateJobMode::RemoveCopy).  As with set_create_copy(), this is
+    /// recorded in the job's database.
+    pub fn set_remove_copy(&mut self, min_copies: u32) -> Result<(), Error> {
         let conn = self.conn.lock().expect("DB conn lock");
-        create_evacuateobjects_table(&*conn)?;
-        create_config_table(&*conn)
-    }
+        update_copy_config_impl(&conn, Some(min_copies))?;
 
-    // This is not purely a validation function.  We do set the from_shark field
-    // in here so that it has the complete manta_storage_id and datacenter.
-    fn validate(&mut self) -> Result<(), Error> {
-        let from_shark = moray_client::get_manta_object_shark(
-            &self.from_shark.manta_storage_id,
-            &self.config.domain_name,
-        )?;
+        self.mode = EvacuateJobMode::RemoveCopy { min_copies };
+        Ok(())
+    }
 
-        self.from_shark = from_shark;
+    /// Never have the job take a destination beyond `percent` utilization,
+    /// whatever its max_fill_percentage.  Unlike max_fill_percentage, this
+    /// is recorded in the job's database, so that a retry or resumed job is
+    /// held to the same ceiling.
+    pub fn set_max_dest_utilization(
+        &mut self,
+        percent: Option<u32>,
+    ) -> Result<(), Error> {
+        if let Some(pct) = percent {
+            let conn = self.conn.lock().expect("DB conn lock");
+            update_dest_limit_config_impl(&conn, pct)?;
+        }
 
+        self.max_dest_utilization = percent;
         Ok(())
     }
 
-    fn update_evacuate_config(&self) -> Result<usize, Error> {
+    /// Only have the job post assignments within the daily window of
+    /// `schedule`.  As with the utilization ceiling, this is recorded in the
+    /// job's database.  See the jobs::schedule module.
+    pub fn set_schedule(
+        &mut self,
+        schedule: Option<JobSchedule>,
+    ) -> Result<(), Error> {
+        let schedule = match schedule {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        let window = ScheduleWindow::new(&schedule).map_err(|e| {
+            InternalError::new(Some(InternalErrorCode::JobBuilderError), e)
+        })?;
+
+        let conn = self.conn.lock().expect("DB conn lock");
+        schedule::record_schedule(&conn, &schedule)?;
+
+        self.schedule = Some(window);
+        Ok(())
+    }
+
+    /// Only have the job work on the objects that match `filter`.  As with
+    /// the schedule, this is recorded in the job's database.  See the
+    /// jobs::filter module.
+    pub fn set_filter(
+        &mut self,
+        filter: Option<ObjectFilter>,
+    ) -> Result<(), Error> {
+        let filter = match filter {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+
+        let conn = self.conn.lock().expect("DB conn lock");
+        filter::record_filter(&conn, &filter)?;
+
+        self.filter = Some(filter);
+        Ok(())
+    }
+
+    /// Have the job find its objects in `source` rather than by scanning the
+    /// metadata tier.  This too is recorded in the job's database.  See the
+    /// jobs::source module.
+    pub fn set_source(
+        &mut self,
+        source: Option<ObjectSource>,
+    ) -> Result<(), Error> {
+        let source = match source {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        let conn = self.conn.lock().expect("DB conn lock");
+        source::record_source(&conn, &source)?;
+
+        self.source = Some(source);
+        Ok(())
+    }
+
+    // Whether the object whose metadata is `record` is one that the job
+    // works on.
+    fn filter_matches(&self, record: &Value) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(true, |f| filter::matches(f, record))
+    }
+
+    // The utilization up to which the job fills its destinations.
+    fn dest_fill_percentage(&self) -> u32 {
+        match self.max_dest_utilization {
+            Some(ceiling) => ceiling.min(self.config.max_fill_percentage),
+            None => self.config.max_fill_percentage,
+        }
+    }
+
+    /// Have the job pick up the scan of the metadata tier where the job
+    /// `job_id`, which was interrupted, left off.
+    pub fn set_resume_from(&mut self, job_id: &str) {
+        self.resume_from = Some(job_id.to_string());
+    }
+
+    pub fn is_create_copy(&self) -> bool {
+        match self.mode {
+            EvacuateJobMode::CreateCopy { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_remove_copy(&self) -> bool {
+        match self.mode {
+            EvacuateJobMode::RemoveCopy { .. } => true,
+            _ => false,
+        }
+    }
+
+    // A remove-copy job passes over the objects that would be left with
+    // fewer than its minimum number of copies, counting only those on
+    // sharks other than the one that it removes copies from.  An object
+    // whose copies can not be made out is passed over too.
+    fn has_too_few_copies(&self, record: &Value) -> bool {
+        let min_copies = match self.mode {
+            EvacuateJobMode::RemoveCopy { min_copies } => min_copies as usize,
+            _ => return false,
+        };
+
+        common::get_sharks_from_value(record)
+            .map(|sharks| {
+                sharks
+                    .iter()
+                    .filter(|s| {
+                        s.manta_storage_id != self.from_shark.manta_storage_id
+                    })
+                    .count()
+                    < min_copies
+            })
+            .unwrap_or(true)
+    }
+
+    // A remove-copy job only ever sends its tasks to the agent on the shark
+    // that it removes copies from.  Nothing is added to that shark, so
+    // unlike the destinations of other jobs, its available space is of no
+    // concern and it need not be among those that storinfo offers.
+    fn get_removal_shark_list(&self) -> Vec<EvacuateDestShark> {
+        let shark = StorageNode {
+            available_mb: 0,
+            percent_used: 0,
+            filesystem: String::new(),
+            datacenter: self.from_shark.datacenter.clone(),
+            manta_storage_id: self.from_shark.manta_storage_id.clone(),
+            timestamp: 0,
+        };
+
+        let dest_shark = self
+            .dest_shark_hash
+            .write()
+            .expect("dest_shark_hash write lock")
+            .entry(shark.manta_storage_id.clone())
+            .or_insert_with(|| EvacuateDestShark {
+                shark,
+                status: DestSharkStatus::Ready,
+                assigned_mb: 0,
+            })
+            .to_owned();
+
+        vec![dest_shark]
+    }
+
+    // A create-copy job with a minimum number of copies passes over the
+    // objects that already have that many.
+    fn has_enough_copies(&self, record: &Value) -> bool {
+        let min_copies = match self.mode {
+            EvacuateJobMode::CreateCopy {
+                min_copies: Some(n),
+            } => n as usize,
+            _ => return false,
+        };
+
+        common::get_sharks_from_value(record)
+            .map(|sharks| sharks.len() >= min_copies)
+            .unwrap_or(false)
+    }
+
+    fn new_common(
+        storage_id: String,
+        config: &Config,
+        db_name: &str,
+        update_rx: Option<crossbeam_channel::Receiver<JobUpdateRequest>>,
+    ) -> Result<Self, Error> {
+        let mut from_shark = MantaObjectShark::default();
+        let conn = match pg_db::create_and_connect_db(db_name) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Error creating Evacuate Job: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        create_evacuateobjects_table(&conn)?;
+        create_config_table(&conn)?;
+        create_duplicate_table(&conn)?;
+        overflow::create_overflow_table(&conn)?;
+        create_copy_config_table(&conn)?;
+        create_dest_limit_config_table(&conn)?;
+        schedule::create_schedule_config_table(&conn)?;
+        filter::create_filter_config_table(&conn)?;
+        source::create_source_config_table(&conn)?;
+        create_scan_checkpoint_table(&conn)?;
+        create_assignment_events_table(&conn)?;
+        create_download_attempts_table(&conn)?;
+        create_slow_tasks_table(&conn)?;
+        create_header_mismatches_table(&conn)?;
+        create_metadata_audit_table(&conn)?;
+        placement::create_placement_trace_table(&conn)?;
+        checksum::create_checksum_exceptions_table(&conn)?;
+        snaplink::create_snaplinks_table(&conn)?;
+
+        from_shark.manta_storage_id = storage_id;
+
+        update_evacuate_config_impl(&conn, &from_shark)?;
+
+        let failures =
+            Arc::new(FailureTracker::new(db_name, &config.notifications));
+        let ramp = Arc::new(RampSchedule::new(db_name, &config.ramp));
+        let sizing =
+            Arc::new(AssignmentSizer::new(db_name, &config.assignment_sizing));
+        let breaker =
+            Arc::new(CircuitBreaker::new(db_name, &config.circuit_breaker));
+
+        let events = EventBus::new(db_name);
+        events.subscribe(MetricsRecorder)?;
+        events.subscribe(AssignmentEventWriter::new(db_name))?;
+        events.subscribe(FailureNotifier::new(Arc::clone(&failures)))?;
+        events.subscribe(JobFeedback::new(
+            Arc::clone(&ramp),
+            Arc::clone(&sizing),
+        ))?;
+        events.subscribe(BreakerMonitor::new(Arc::clone(&breaker)))?;
+        events.subscribe(HistoryMonitor::new(HistoryRecorder::new(
+            db_name,
+            &config.agent_history,
+        )))?;
+
+        let verify_client = || {
+            reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.verification.timeout_secs))
+                .build()
+        };
+
+        let dest_verifier = if config.options.verify_before_update {
+            Some(verify_client()?)
+        } else {
+            None
+        };
+
+        let header_checker = if config.options.header_check_percent > 0 {
+            Some(verify_client()?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            config: config.to_owned(),
+            min_avail_mb: Some(1000), // TODO: config
+            dest_shark_hash: RwLock::new(HashMap::new()),
+            assignments: RwLock::new(HashMap::new()),
+            from_shark,
+            conn: Mutex::new(conn),
+            max_objects: Some(10),
+            resume_from: None,
+            max_dest_utilization: None,
+            schedule: None,
+            filter: None,
+            source: None,
+            dest_verifier,
+            header_checker,
+            agent_pool: agent_client::shared(),
+            update_rx,
+            tunables: Tunables::new(&config.options),
+            accepting_updates: AtomicBool::new(true),
+            evac_type: EvacuateJobType::Initial,
+            mode: EvacuateJobMode::Evacuate,
+            db_name: db_name.to_string(),
+            bytes_transferred: AtomicU64::new(0),
+            replica_sourced: AtomicU64::new(0),
+            evac_shark_sourced: AtomicU64::new(0),
+            placement_excluded: AtomicU64::new(0),
+            record_dispositions: Mutex::new(HashMap::new()),
+            object_movement_start_time: Mutex::new(None),
+            projected: projected::shared(),
+            failures,
+            ramp,
+            sizing,
+            breaker,
+            polling: PollSchedule::new(&config.polling),
+            events,
+            interrupted: AtomicBool::new(false),
+            full_sharks: Mutex::new(HashMap::new()),
+            agent_boots: Mutex::new(HashMap::new()),
+            rerouted: Mutex::new(Some(OverflowQueue::new(
+                config.options.max_resident_objects,
+            ))),
+            source_copies: SourceCopies::new(),
+            drained_dests: Mutex::new(HashSet::new()),
+            hints: PlacementHints::new(&config.agent_history),
+        })
+    }
+
+    /// Returns true once the job should stop looking for more objects to
+    /// move, because either the manager is shutting down or the job's
+    /// circuit breaker has tripped.
+    pub fn stopping(&self) -> bool {
+        shutdown::requested() || self.breaker.is_tripped()
+    }
+
+    /// Apply a change made with PUT /jobs/<uuid>, and record it along with
+    /// the value it replaced.
+    pub fn apply_update(
+        &self,
+        msg: EvacuateJobUpdateMessage,
+    ) -> Result<(), String> {
+        msg.validate()?;
+
+        match msg {
+            EvacuateJobUpdateMessage::SetMetadataThreads(count) => {
+                if self.config.options.use_static_md_update_threads {
+                    return Err(String::from(
+                        "Job uses a static number of metadata update threads",
+                    ));
+                }
+
+                let old = self
+                    .tunables
+                    .update(|t| t.max_metadata_update_threads = count);
+                self.record_update(
+                    "max_metadata_update_threads",
+                    &old.max_metadata_update_threads,
+                    &count,
+                );
+            }
+            EvacuateJobUpdateMessage::SetMaxSharks(max_sharks) => {
+                let old = self.tunables.update(|t| t.max_sharks = max_sharks);
+                self.record_update("max_sharks", &old.max_sharks, &max_sharks);
+            }
+            EvacuateJobUpdateMessage::SetMaxTasksPerAssignment(max_tasks) => {
+                let old = self
+                    .tunables
+                    .update(|t| t.max_tasks_per_assignment = max_tasks);
+                self.record_update(
+                    "max_tasks_per_assignment",
+                    &old.max_tasks_per_assignment,
+                    &max_tasks,
+                );
+            }
+            EvacuateJobUpdateMessage::SetCircuitBreaker(update) => {
+                let new = update.apply(self.breaker.config());
+                let old = self.breaker.set_config(new);
+                self.record_update("circuit_breaker", &old, &new);
+            }
+            EvacuateJobUpdateMessage::DrainDestination(shark) => {
+                self.drain_destination(shark)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Stop giving the job's objects to `shark`, e.g. because it has degraded,
+    // without giving up on what it is already doing.  The objects waiting to
+    // be posted to it are sent elsewhere by the assignment manager (see
+    // AssignmentMsg::Drain), and its agent is asked to cancel the assignments
+    // that it holds.  The agent finishes the task that each of its workers is
+    // on, and the tasks that it did not get to are sent elsewhere too (see
+    // reroute_cancelled_tasks()), rather than being skipped.  Unlike a full
+    // destination, a drained one is not given any more of the job's objects
+    // at all, but it is left alone by every other job.
+    fn drain_destination(&self, shark: StorageId) -> Result<(), String> {
+        if self.is_remove_copy() {
+            return Err(String::from(
+                "Remove-copy jobs have no destinations to drain",
+            ));
+        }
+
+        if !self
+            .dest_shark_hash
+            .read()
+            .expect("dest_shark_hash read lock")
+            .contains_key(&shark)
+        {
+            return Err(format!("{} is not a destination of the job", shark));
+        }
+
+        let (old, new) = {
+            let mut drained =
+                self.drained_dests.lock().expect("drained dests lock");
+            let mut old: Vec<StorageId> = drained.iter().cloned().collect();
+            old.sort();
+
+            if !drained.insert(shark.clone()) {
+                return Err(format!("{} is already drained", shark));
+            }
+
+            let mut new = old.clone();
+            new.push(shark.clone());
+            new.sort();
+            (old, new)
+        };
+        self.record_update("drained_destinations", &old, &new);
+
+        let posted: Vec<AssignmentId> = self
+            .assignments
+            .read()
+            .expect("assignments read lock")
+            .values()
+            .filter(|ace| {
+                ace.dest_shark.manta_storage_id == shark
+                    && ace.state == AssignmentState::Assigned
+            })
+            .map(|ace| ace.id.clone())
+            .collect();
+
+        info!(
+            "Draining destination {}, cancelling its {} assignments",
+            shark,
+            posted.len()
+        );
+        self.cancel_drained(posted);
+
+        Ok(())
+    }
+
+    fn is_drained(&self, shark: &str) -> bool {
+        self.drained_dests
+            .lock()
+            .expect("drained dests lock")
+            .contains(shark)
+    }
+
+    // Ask the agent on a drained destination to cancel `assignments`.  This
+    // is done from a thread of its own, so that whoever drained the
+    // destination is not kept waiting on the agent.
+    fn cancel_drained(&self, assignments: Vec<AssignmentId>) {
+        if assignments.is_empty() {
+            return;
+        }
+
+        let job_id = self.db_name.clone();
+        let spawned = thread::Builder::new()
+            .name("drain_cancel".to_string())
+            .spawn(joblog::inherit(move || {
+                for id in assignments.iter() {
+                    match cancel_assignment(&job_id, id) {
+                        Ok(())
+                        | Err(CancelAssignmentError::AlreadyComplete) => (),
+                        Err(e) => warn!(
+                            "Could not cancel assignment {} of a drained \
+                             destination: {:?}",
+                            id, e
+                        ),
+                    }
+                }
+            }));
+
+        if let Err(e) = spawned {
+            error!("Could not start drain cancellation thread: {}", e);
+        }
+    }
+
+    // The change has already been made, so failing to record it is not
+    // reported to whoever made it.
+    fn record_update<T: Serialize + std::fmt::Debug>(
+        &self,
+        parameter: &str,
+        old: &T,
+        new: &T,
+    ) {
+        info!(
+            "Job {}: {} changed from {:?} to {:?}",
+            self.db_name, parameter, old, new
+        );
+
+        if let Err(e) =
+            tuning::record_update(&self.db_name, parameter, old, new)
+        {
+            error!("Could not record update of {}: {}", parameter, e);
+        }
+    }
+
+    pub fn create_tables(&self) -> Result<usize, Error> {
+        let conn = self.conn.lock().expect("DB conn lock");
+        create_evacuateobjects_table(&*conn)?;
+        create_config_table(&*conn)
+    }
+
+    // This is not purely a validation function.  We do set the from_shark field
+    // in here so that it has the complete manta_storage_id and datacenter.
+    fn validate(&mut self) -> Result<(), Error> {
+        let from_shark = moray_client::get_manta_object_shark(
+            &self.from_shark.manta_storage_id,
+            &self.config.domain_name,
+        )?;
+
+        self.from_shark = from_shark;
+
+        Ok(())
+    }
+
+    fn update_evacuate_config(&self) -> Result<usize, Error> {
         let locked_conn = self.conn.lock().expect("DB conn lock");
 
         update_evacuate_config_impl(&locked_conn, &self.from_shark)
     }
 
+    // Count a destination that was passed over because of the object's
+    // other copies.  This is how often the placement constraints in
+    // validate_destination() bind.
+    fn count_placement_excluded(&self, reason: &ObjectSkippedReason) {
+        let label = match reason {
+            ObjectSkippedReason::ObjectAlreadyOnDestShark => {
+                PLACEMENT_REPLICA_ON_SHARK
+            }
+            ObjectSkippedReason::ObjectAlreadyInDatacenter => {
+                PLACEMENT_REPLICA_IN_DATACENTER
+            }
+            _ => return,
+        };
+
+        self.placement_excluded.fetch_add(1, Ordering::SeqCst);
+        metrics_placement_excluded_inc(label);
+    }
+
+    // Count a metadata record that was not rebalanced because it is not a
+    // regular object.
+    fn count_record_disposition(
+        &self,
+        disposition: RecordDisposition,
+        record: &Value,
+    ) {
+        debug!(
+            "Not rebalancing record ({}): {}",
+            disposition,
+            record
+                .get("key")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+        );
+
+        *self
+            .record_dispositions
+            .lock()
+            .expect("record dispositions lock")
+            .entry(disposition)
+            .or_insert(0) += 1;
+        metrics_record_disposition_inc(&disposition.to_string());
+    }
+
+    // Record how far the scan of each shard has got, so that a job resumed
+    // after this one is interrupted (or the manager crashes) knows which
+    // shards it need not scan again.
+    fn save_scan_checkpoints(
+        &self,
+        checkpoints: &HashMap<i32, ScanCheckpoint>,
+    ) -> Result<(), Error> {
+        use self::scan_checkpoint::dsl::{
+            scan_checkpoint as checkpoint_table, shard as checkpoint_shard,
+        };
+
+        let locked_conn = self.conn.lock().expect("DB conn lock");
+
+        for checkpoint in checkpoints.values() {
+            diesel::insert_into(checkpoint_table)
+                .values(checkpoint)
+                .on_conflict(checkpoint_shard)
+                .do_update()
+                .set(checkpoint)
+                .execute(&*locked_conn)
+                .map_err(Error::from)?;
+        }
+
+        Ok(())
+    }
+
     pub fn run(mut self) -> Result<(), Error> {
         self.validate()?;
         self.update_evacuate_config()?;
 
+        if self.config.options.resolve_snaplinks {
+            self.carry_over_snaplinks();
+        }
+
         let mut ret = Ok(());
 
         // job_action will be shared between threads so create an Arc for it.
@@ -762,8 +2674,7 @@ impl EvacuateJob {
 
         // get what the evacuate job needs from the config structure
         let domain = &job_action.config.domain_name;
-        let min_shard = job_action.config.min_shard_num();
-        let max_shard = job_action.config.max_shard_num();
+        let shards = job_action.config.shard_nums();
 
         // TODO: How big should each channel be?
         // Set up channels for thread to communicate.
@@ -787,27 +2698,53 @@ impl EvacuateJob {
         // local_db_generator() is not waiting to put objects into the
         // channel while it could be running the next chunk query on the local
         // db.
+        //
+        // Either way, the channel and the objects waiting to be rerouted hold
+        // no more than options.max_resident_objects between them (see the
+        // jobs::overflow module).
+        let depth = match &job_action.evac_type {
+            EvacuateJobType::Initial => DISCOVERY_QUEUE_DEPTH,
+            EvacuateJobType::Retry(_) => {
+                job_action.config.options.md_read_chunk_size
+            }
+        };
+        let (depth, rerouted_depth) = overflow::resident_shares(
+            job_action.config.options.max_resident_objects,
+            depth,
+        );
+        if let Some(queue) =
+            job_action.rerouted.lock().expect("rerouted lock").as_mut()
+        {
+            queue.set_max_resident(rerouted_depth);
+        }
+
         let obj_generator_thread = match &job_action.evac_type {
             EvacuateJobType::Initial => {
-                let channel = crossbeam::bounded(100);
+                let channel = crossbeam::bounded(depth);
                 obj_tx = channel.0;
                 obj_rx = channel.1;
-                start_sharkspotter(
-                    obj_tx,
-                    domain.as_str(),
-                    Arc::clone(&job_action),
-                    min_shard,
-                    max_shard,
-                )?
+                match &job_action.source {
+                    Some(ObjectSource::File { path }) => {
+                        start_file_scan(obj_tx, Arc::clone(&job_action), path)?
+                    }
+                    _ => start_sharkspotter(
+                        obj_tx,
+                        domain.as_str(),
+                        Arc::clone(&job_action),
+                        shards,
+                    )?,
+                }
             }
             EvacuateJobType::Retry(retry_uuid) => {
                 // start local db generator
-                let channel = crossbeam::bounded(
-                    job_action.config.options.md_read_chunk_size,
-                );
+                let channel = crossbeam::bounded(depth);
                 obj_tx = channel.0;
                 obj_rx = channel.1;
-                start_local_db_generator(obj_tx, retry_uuid)?
+                start_local_db_generator(
+                    obj_tx,
+                    Arc::clone(&job_action),
+                    retry_uuid,
+                )?
             }
         };
 
@@ -825,207 +2762,990 @@ impl EvacuateJob {
         let post_thread =
             start_assignment_post(full_assignment_rx, Arc::clone(&job_action))?;
 
-        // start storinfo thread which will periodically update the list of
+        let update_thread = start_update_listener(Arc::clone(&job_action))?;
+
+        // The destination sharks come from a file if the job was given one.
+        // Otherwise join the storinfo poller (starting it if this is the
+        // first job to run), which periodically updates the list of
         // available sharks.
-        let mut storinfo = mod_storinfo::Storinfo::new(domain)?;
-        storinfo.start().map_err(Error::from)?;
-        let storinfo = Arc::new(storinfo);
+        let assignment_manager = match &job_action.config.sharks_file {
+            Some(path) => {
+                let sharks =
+                    mod_storinfo::SharksFile::new(path).map_err(|e| {
+                        InternalError::new(
+                            Some(InternalErrorCode::StorinfoError),
+                            e,
+                        )
+                    })?;
+                info!("Using the destination sharks in {}", path);
+
+                start_assignment_manager(
+                    full_assignment_tx,
+                    checker_fini_tx,
+                    obj_rx,
+                    Arc::clone(&job_action),
+                    Arc::new(sharks),
+                )?
+            }
+            None => {
+                let mut storinfo = mod_storinfo::Storinfo::new(
+                    domain,
+                    &job_action.config.storinfo,
+                )?;
+                storinfo.start().map_err(Error::from)?;
 
-        let assignment_manager = start_assignment_manager(
-            full_assignment_tx,
-            checker_fini_tx,
-            obj_rx,
-            Arc::clone(&job_action),
-            Arc::clone(&storinfo),
-        )?;
+                start_assignment_manager(
+                    full_assignment_tx,
+                    checker_fini_tx,
+                    obj_rx,
+                    Arc::clone(&job_action),
+                    Arc::new(storinfo),
+                )?
+            }
+        };
 
         // At this point the rebalance job is running and we are blocked at
         // the assignment_manager thread join.
 
-        // TODO: should we "expect()" all these joins?
+        // Each of these threads is supervised (see watchdog.rs), so a panic
+        // in one of them is returned as an error rather than through the
+        // join.
         match assignment_manager.join().expect("Assignment Manager") {
             Ok(()) => (),
             Err(e) => {
-                if let Error::Internal(err) = e {
+                if let Error::Internal(err) = &e {
                     if err.code == InternalErrorCode::StorinfoError {
                         error!(
                             "Encountered empty storinfo on startup, exiting \
                              safely"
                         );
-                        set_run_error(&mut ret, err);
-                    } else {
-                        panic!("Error {}", err);
                     }
-                } else {
-                    panic!("Error {}", e);
                 }
+                set_run_error(&mut ret, e);
+            }
+        }
+
+        obj_generator_thread
+            .join()
+            .expect("Sharkspotter Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining sharkspotter: {}\n", e);
+                set_run_error(&mut ret, e);
+            });
+
+        post_thread
+            .join()
+            .expect("Post Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining post thread: {}\n", e);
+                set_run_error(&mut ret, e);
+            });
+
+        assignment_checker_thread
+            .join()
+            .expect("Checker Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining assignment checker thread: {}\n", e);
+                set_run_error(&mut ret, e);
+            });
+
+        metadata_update_thread
+            .join()
+            .expect("MD Update Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining metadata update thread: {}\n", e);
+                set_run_error(&mut ret, e);
+            });
+
+        // Objects with snaplinks are only complete once their links have
+        // been updated as well.  A job that was interrupted leaves them to
+        // the job that resumes it.
+        if job_action.config.options.resolve_snaplinks
+            && !job_action.interrupted.load(Ordering::SeqCst)
+        {
+            resolve_snaplinks(&job_action);
+        }
+
+        // There is nothing left to change.
+        job_action.accepting_updates.store(false, Ordering::SeqCst);
+        if let Some(thread) = update_thread {
+            thread
+                .join()
+                .expect("Update Listener Thread")
+                .unwrap_or_else(|e| {
+                    error!("Error joining update listener thread: {}\n", e);
+                    set_run_error(&mut ret, e);
+                });
+        }
+
+        job_action.projected.job_finished(&job_action.db_name);
+
+        // Let the subscribers catch up with everything the job did before
+        // its destination sharks are removed from the metrics, and before
+        // the job is reported as finished.
+        job_action.events.close();
+
+        for shark in job_action
+            .dest_shark_hash
+            .read()
+            .expect("dest_shark_hash read lock")
+            .keys()
+        {
+            metrics_shark_remove(shark);
+        }
+
+        // A job that was paused is not resumed after a shutdown, since it
+        // would only go on failing.
+        if ret.is_ok() {
+            if let Some(detail) = job_action.breaker.detail() {
+                if let Err(e) = breaker::record_pause(
+                    &job_action.db_name,
+                    PauseReason::ErrorThreshold,
+                    &detail,
+                ) {
+                    error!("Could not record pause of job: {}", e);
+                }
+
+                ret = Err(InternalError::new(
+                    Some(InternalErrorCode::JobPaused),
+                    format!("Job paused: {}", detail),
+                )
+                .into());
+            } else if job_action.interrupted.load(Ordering::SeqCst) {
+                ret = Err(InternalError::new(
+                    Some(InternalErrorCode::JobInterrupted),
+                    "Job interrupted by manager shutdown",
+                )
+                .into());
+            }
+        }
+
+        info!(
+            "Evacuate Job transferred {} bytes",
+            job_action.bytes_transferred.load(Ordering::SeqCst)
+        );
+
+        info!(
+            "Evacuate Job assigned {} objects from other replicas and {} \
+             from the evacuating shark",
+            job_action.replica_sourced.load(Ordering::SeqCst),
+            job_action.evac_shark_sourced.load(Ordering::SeqCst)
+        );
+
+        info!(
+            "Evacuate Job passed over {} destinations because they already \
+             held (or were in the same data center as) a copy of the object",
+            job_action.placement_excluded.load(Ordering::SeqCst)
+        );
+
+        for (disposition, count) in job_action
+            .record_dispositions
+            .lock()
+            .expect("record dispositions lock")
+            .iter()
+        {
+            info!(
+                "Evacuate Job did not rebalance {} records ({})",
+                count, disposition
+            );
+        }
+
+        ret
+    }
+
+    fn mark_objects_complete(&self, completed_objects: Vec<EvacuateObject>) {
+        let mut obj_ids = vec![];
+        let mut per_shark: HashMap<StorageId, Vec<u64>> = HashMap::new();
+
+        for eobj in completed_objects.into_iter() {
+            per_shark
+                .entry(eobj.dest_shark.clone())
+                .or_default()
+                .push(object_bytes(&eobj));
+
+            eobj.object
+                .get("contentLength")
+                .and_then(|cl| {
+                    if let Some(bytes) = cl.as_u64() {
+                        // TODO: metrics
+                        self.bytes_transferred
+                            .fetch_add(bytes, Ordering::SeqCst);
+                    } else {
+                        warn!("Could not get bytes as number from {}", cl);
+                    }
+                    Some(())
+                })
+                .or_else(|| {
+                    warn!("Could not find contentLength for {}", eobj.id);
+                    None
+                });
+
+            obj_ids.push(eobj.id);
+        }
+
+        debug!("Updated Objects: {:?}", obj_ids);
+        self.mark_many_objects(obj_ids, EvacuateObjectStatus::Complete);
+
+        for (dest_shark, sizes) in per_shark.into_iter() {
+            self.events
+                .publish(EvacuateEvent::ObjectsMoved { dest_shark, sizes });
+        }
+    }
+
+    // The autopsies of slow tasks are only kept for the job's report, so
+    // failing to save them does not hold up the assignment.
+    fn record_slow_tasks(&self, ace: &AssignmentCacheEntry, tasks: &[Task]) {
+        if tasks.is_empty() {
+            return;
+        }
+
+        info!("Assignment {} had {} slow tasks", ace.id, tasks.len());
+
+        let entries: Vec<SlowTaskEntry> = tasks
+            .iter()
+            .filter_map(|t| {
+                let autopsy = t.autopsy.as_ref()?;
+                let value = serde_json::to_value(autopsy)
+                    .map_err(|e| {
+                        warn!("Could not serialize autopsy: {}", e);
+                    })
+                    .ok()?;
+
+                Some(SlowTaskEntry {
+                    id: t.object_id.clone(),
+                    assignment_id: ace.id.clone(),
+                    dest_shark: ace.dest_shark.manta_storage_id.clone(),
+                    elapsed_ms: autopsy.elapsed_ms as i64,
+                    autopsy: value,
+                })
+            })
+            .collect();
+
+        let locked_conn = self.conn.lock().expect("db conn lock");
+        if let Err(e) = save_slow_tasks(&*locked_conn, &entries) {
+            warn!("LocalDB: Error saving slow tasks: {}", e);
+        }
+    }
+
+    // The metadata has already been changed by the time that the change is
+    // recorded, so there is no point in failing the assignment if it can not
+    // be.
+    fn record_metadata_audit(&self, entries: &[NewMetadataAudit]) {
+        if entries.is_empty() {
+            return;
+        }
+
+        let locked_conn = self.conn.lock().expect("db conn lock");
+        if let Err(e) = save_metadata_audit(&*locked_conn, entries) {
+            error!(
+                "LocalDB: Error recording {} metadata updates in the audit \
+                 table: {}",
+                entries.len(),
+                e
+            );
+        }
+    }
+
+    // Record a single put of `object`, logging rather than recording it if
+    // the object is missing its id or key.
+    fn audit_put(&self, old_sharks: &Value, object: &Value, etag: &str) {
+        match metadata_audit_entry(old_sharks, object, etag) {
+            Ok(entry) => self.record_metadata_audit(&[entry]),
+            Err(e) => {
+                error!("Could not audit metadata update ({}): {:?}", e, object)
+            }
+        }
+    }
+
+    fn record_assignment_event(
+        &self,
+        assignment_id: &str,
+        event: AssignmentEvent,
+        detail: Option<String>,
+    ) {
+        self.events.publish(EvacuateEvent::AssignmentTransition {
+            assignment_id: assignment_id.to_string(),
+            event,
+            detail,
+        });
+    }
+
+    fn set_assignment_state(
+        &self,
+        assignment_id: &str,
+        state: AssignmentState,
+    ) -> Result<(), Error> {
+        match self
+            .assignments
+            .write()
+            .expect("assignment write lock")
+            .get_mut(assignment_id)
+        {
+            Some(a) => {
+                a.state = state;
+                info!("Set assignment state to {:?} for {}", a.state, a.id);
+            }
+            None => {
+                let msg = format!(
+                    "Error updating assignment state for: {}",
+                    &assignment_id
+                );
+                error!("{}", &msg);
+
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::AssignmentLookupError),
+                    msg,
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::ptr_arg)]
+    fn skip_assignment(
+        &self,
+        assign_id: &AssignmentId,
+        skip_reason: ObjectSkippedReason,
+        assignment_state: AssignmentState,
+    ) {
+        self.mark_assignment_skipped(assign_id, skip_reason);
+        self.record_assignment_event(
+            assign_id,
+            AssignmentEvent::Skipped,
+            Some(format!("{:?}: {}", assignment_state, skip_reason)),
+        );
+
+        self.set_assignment_state(assign_id, assignment_state)
+            .unwrap_or_else(|e| {
+                // TODO: should we panic? if so just replace with expect()
+                panic!("{}", e);
+            });
+
+        self.remove_assignment_from_cache(assign_id);
+    }
+
+    // Removes assignment cache entry from cache.  The cache entry is
+    // implicitly dropped when the function returns.
+    fn remove_assignment_from_cache(&self, assignment_id: &str) {
+        let mut assignments =
+            self.assignments.write().expect("assignments write");
+
+        trace!(
+            "Assignment Cache size: {} bytes",
+            std::mem::size_of::<AssignmentCacheEntry>() * assignments.len()
+        );
+
+        let entry = assignments.remove(assignment_id);
+        if entry.is_none() {
+            warn!(
+                "Attempt to remove assignment not in cache: {}",
+                assignment_id
+            );
+        }
+
+        debug_assert!(
+            entry.is_some(),
+            format!("Remove assignment not in hash: {}", assignment_id)
+        );
+
+        let initial_size = assignment_cache_usage(&assignments);
+        assignments.shrink_to_fit();
+        trace!(
+            "Assignment cache reduced by {}",
+            initial_size - assignment_cache_usage(&assignments)
+        );
+    }
+
+    // Count objects that could not be moved after they were assigned,
+    // because an agent or the metadata tier returned an error.  Unlike
+    // objects that are skipped before they are assigned, these also count
+    // against the job's ramp up.
+    fn count_failed(&self, reason: Option<ObjectSkippedReason>, count: usize) {
+        self.events.publish(EvacuateEvent::ObjectsNotMoved {
+            reason,
+            assigned: true,
+            count,
+            sizes: vec![],
+        });
+    }
+
+    fn skip_object(
+        &self,
+        eobj: &mut EvacuateObject,
+        reason: ObjectSkippedReason,
+    ) {
+        info!("Skipping object {}: {}.", &eobj.id, reason);
+
+        eobj.status = EvacuateObjectStatus::Skipped;
+        eobj.skipped_reason = Some(reason);
+        self.insert_into_db(&eobj);
+
+        self.events.publish(EvacuateEvent::ObjectsNotMoved {
+            reason: Some(reason),
+            assigned: false,
+            count: 1,
+            sizes: vec![object_bytes(eobj)],
+        });
+    }
+
+    // Record an object that can not be moved because of something wrong with
+    // the object itself, found before it was added to an assignment.
+    fn fail_object(&self, eobj: &mut EvacuateObject, err: EvacuateObjectError) {
+        error!("Failing object {}: {:?}.", &eobj.id, err);
+
+        eobj.status = EvacuateObjectStatus::Error;
+        eobj.error = Some(err);
+        self.insert_into_db(&eobj);
+
+        self.events
+            .publish(EvacuateEvent::ErrorObserved { error: err });
+        self.events.publish(EvacuateEvent::ObjectsNotMoved {
+            reason: None,
+            assigned: false,
+            count: 1,
+            sizes: vec![object_bytes(eobj)],
+        });
+    }
+
+    // Deal with an object whose metadata has a missing or malformed checksum
+    // as the job's checksum policy says, and record what became of it.
+    // Returns true if the object is to be copied regardless.  See the
+    // checksum module.
+    fn apply_checksum_policy(
+        &self,
+        eobj: &mut EvacuateObject,
+        content_md5: &str,
+        problem: ChecksumProblem,
+    ) -> bool {
+        let disposition =
+            ChecksumDisposition::from(self.config.options.checksum_policy);
+
+        {
+            let locked_conn = self.conn.lock().expect("db conn lock");
+            checksum::record(
+                &*locked_conn,
+                &eobj.id,
+                content_md5,
+                problem,
+                disposition,
+            );
+        }
+
+        match disposition {
+            ChecksumDisposition::Failed => {
+                self.fail_object(eobj, EvacuateObjectError::BadChecksum);
+                false
+            }
+            ChecksumDisposition::Skipped => {
+                self.skip_object(eobj, ObjectSkippedReason::BadChecksum);
+                false
+            }
+            ChecksumDisposition::Copied => {
+                warn!(
+                    "Copying object {} without checking it, its checksum is \
+                     {} ({:?})",
+                    &eobj.id, problem, content_md5
+                );
+                true
+            }
+        }
+    }
+
+    // Returns true if the agent on `shark` turned down an assignment for lack
+    // of space recently enough that it should not be given any more objects.
+    fn is_shark_full(&self, shark: &str) -> bool {
+        let mut full_sharks = self.full_sharks.lock().expect("full sharks");
+
+        match full_sharks.get(shark) {
+            Some(when) if when.elapsed() < FULL_SHARK_HOLDOFF => true,
+            Some(_) => {
+                full_sharks.remove(shark);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // An assignment could not be got from its agent.  An agent that is
+    // restarted still holds the assignments that it had accepted, and
+    // carries on with each of them from where it left off, so rather than
+    // giving up on the assignment straight away, ask the agent which
+    // assignments it holds, along with whether it has been restarted since
+    // the job last asked.  Returns false if the assignment should be given
+    // up on, which is only once the agent has been unreachable for longer
+    // than AGENT_RESTART_GRACE.  If the agent can be reached, the assignment
+    // is polled again as usual, and if the agent has been restarted all of
+    // the job's assignments that it still holds are re-adopted.  Whatever
+    // the agent no longer holds is either finished, or is not known to it
+    // and is skipped the next time that it is polled.
+    fn reconcile_agent(&self, ace: &AssignmentCacheEntry) -> bool {
+        let shark = &ace.dest_shark.manta_storage_id;
+        let since = self
+            .agent_boots
+            .lock()
+            .expect("agent boots")
+            .get(shark)
+            .and_then(|b| b.boot_id.clone());
+
+        let mut uri = format!("http://{}:7878/assignments", shark);
+        if let Some(boot_id) = &since {
+            uri.push_str(&format!("?since={}", boot_id));
+        }
+
+        let held = self
+            .agent_pool
+            .checkout(shark)
+            .get(&uri)
+            .send()
+            .map_err(|e| e.to_string())
+            .and_then(|mut resp| {
+                if resp.status().is_success() {
+                    resp.json::<AgentAssignmentList>()
+                        .map_err(|e| e.to_string())
+                } else {
+                    Err(format!("status {}", resp.status()))
+                }
+            });
+
+        let mut boots = self.agent_boots.lock().expect("agent boots");
+        let boot = boots.entry(shark.clone()).or_default();
+
+        let list = match held {
+            Ok(list) => list,
+            Err(e) => {
+                let unreachable = match boot.unreachable_since {
+                    Some(when) => when,
+                    None => {
+                        warn!(
+                            "Agent on {} can not be reached ({}), waiting \
+                             up to {} seconds for it to come back",
+                            shark,
+                            e,
+                            AGENT_RESTART_GRACE.as_secs()
+                        );
+                        let now = std::time::Instant::now();
+                        boot.unreachable_since = Some(now);
+                        now
+                    }
+                };
+
+                if unreachable.elapsed() < AGENT_RESTART_GRACE {
+                    debug!(
+                        "Agent on {} can not be reached ({}), checking on \
+                         assignment {} again later",
+                        shark, e, ace.id
+                    );
+                    return true;
+                }
+
+                warn!(
+                    "Agent on {} has not been reachable for {} seconds ({}), \
+                     giving up on assignment {}",
+                    shark,
+                    unreachable.elapsed().as_secs(),
+                    e,
+                    ace.id
+                );
+                return false;
             }
+        };
+
+        boot.unreachable_since = None;
+        boot.boot_id = Some(list.boot_id.clone());
+        drop(boots);
+
+        if !list.restarted {
+            return true;
         }
 
-        storinfo.fini();
+        info!(
+            "Agent on {} has been restarted (boot {}) and holds {} \
+             assignments",
+            shark,
+            list.boot_id,
+            list.assignments.len()
+        );
+
+        let readopted: Vec<AssignmentId> = self
+            .assignments
+            .read()
+            .expect("assignments read lock")
+            .values()
+            .filter(|a| {
+                a.state == AssignmentState::Assigned
+                    && &a.dest_shark.manta_storage_id == shark
+                    && list.assignments.iter().any(|held| held.uuid == a.id)
+            })
+            .map(|a| a.id.clone())
+            .collect();
+
+        for id in readopted {
+            self.record_assignment_event(
+                &id,
+                AssignmentEvent::Readopted,
+                Some(format!("agent boot {}", list.boot_id)),
+            );
+            self.polling.forget(&id);
+        }
+
+        true
+    }
+
+    // With options.trace_placement set, record a decision about where to put
+    // the object `object_id` in the job's placement trace.  See the
+    // placement module.
+    fn trace_placement(
+        &self,
+        object_id: &str,
+        dest_shark: Option<&str>,
+        decision: PlacementDecision,
+        detail: Option<String>,
+    ) {
+        if !self.config.options.trace_placement {
+            return;
+        }
+
+        let locked_conn = self.conn.lock().expect("db conn lock");
+        placement::record(
+            &*locked_conn,
+            object_id,
+            dest_shark,
+            decision,
+            detail,
+        );
+    }
+
+    // The agent on the assignment's destination turned it down because the
+    // destination does not have room for it.  Stop sending objects to that
+    // destination for a while, and send the assignment's objects elsewhere
+    // (see requeue_assignment()).
+    fn reroute_assignment(&self, assignment: &Assignment, detail: &str) {
+        let shark = &assignment.dest_shark.manta_storage_id;
+
+        warn!(
+            "Agent on {} has no room for assignment {} ({}), not sending it \
+             any more objects for {} seconds",
+            shark,
+            assignment.id,
+            detail,
+            FULL_SHARK_HOLDOFF.as_secs()
+        );
+
+        self.full_sharks
+            .lock()
+            .expect("full sharks")
+            .insert(shark.clone(), std::time::Instant::now());
+
+        self.requeue_assignment(
+            assignment,
+            ObjectSkippedReason::DestinationInsufficientSpace,
+            detail,
+        );
+    }
 
-        obj_generator_thread
-            .join()
-            .expect("Sharkspotter Thread")
-            .unwrap_or_else(|e| {
-                error!("Error joining sharkspotter: {}\n", e);
-                set_run_error(&mut ret, e);
-            });
+    // Give the objects of an assignment that was not posted back to the
+    // assignment manager so that it can send them elsewhere.  The objects are
+    // removed from the local database, as they will be recorded again as part
+    // of whatever assignment they end up in.  If the assignment manager has
+    // already finished, the objects are skipped for `reason` instead, and a
+    // retry job can move them.
+    fn requeue_assignment(
+        &self,
+        assignment: &Assignment,
+        reason: ObjectSkippedReason,
+        detail: &str,
+    ) {
+        use self::evacuateobjects::dsl::{assignment_id, evacuateobjects};
 
-        post_thread
-            .join()
-            .expect("Post Thread")
-            .unwrap_or_else(|e| {
-                error!("Error joining post thread: {}\n", e);
-                set_run_error(&mut ret, e);
-            });
+        let shark = &assignment.dest_shark.manta_storage_id;
+        let mut rerouted = self.rerouted.lock().expect("rerouted lock");
+        if rerouted.is_none() {
+            drop(rerouted);
+            assignment_post_fail(
+                self,
+                assignment,
+                reason,
+                AssignmentState::Rejected,
+            );
+            return;
+        }
 
-        assignment_checker_thread
-            .join()
-            .expect("Checker Thread")
-            .unwrap_or_else(|e| {
-                error!("Error joining assignment checker thread: {}\n", e);
-                set_run_error(&mut ret, e);
-            });
+        let objects = self.load_assignment_objects(
+            &assignment.id,
+            EvacuateObjectStatus::Assigned,
+        );
 
-        metadata_update_thread
-            .join()
-            .expect("MD Update Thread")
-            .unwrap_or_else(|e| {
-                error!("Error joining metadata update thread: {}\n", e);
-                set_run_error(&mut ret, e);
-            });
+        {
+            let locked_conn = self.conn.lock().expect("DB conn lock");
+            if let Err(e) = diesel::delete(
+                evacuateobjects.filter(assignment_id.eq(&assignment.id)),
+            )
+            .execute(&*locked_conn)
+            {
+                error!(
+                    "Could not remove objects of assignment {}: {}",
+                    assignment.id, e
+                );
+            }
+        }
 
-        info!(
-            "Evacuate Job transferred {} bytes",
-            job_action.bytes_transferred.load(Ordering::SeqCst)
+        let count = objects.len();
+        push_rerouted(
+            rerouted.as_mut().expect("rerouted queue"),
+            &self.conn,
+            objects,
         );
+        drop(rerouted);
 
-        ret
+        self.mark_dest_shark_ready(shark, assignment.total_size, false);
+        self.events.publish(EvacuateEvent::AssignmentPostFailed {
+            dest_shark: shark.clone(),
+            tasks: assignment.tasks.len(),
+        });
+        self.record_assignment_event(
+            &assignment.id,
+            AssignmentEvent::Rerouted,
+            Some(format!("{} objects: {}", count, detail)),
+        );
+        self.remove_assignment_from_cache(&assignment.id);
     }
 
-    fn mark_objects_complete(&self, completed_objects: Vec<EvacuateObject>) {
-        let mut obj_ids = vec![];
+    // Give the objects of `tasks`, which the agent did not get to before the
+    // assignment was cancelled (by an operator, or because its destination
+    // was drained), back to the assignment manager so that it can send them
+    // elsewhere.  As with requeue_assignment(), they are removed from the
+    // local database.  Returns the tasks whose objects could not be given
+    // back, because the assignment manager has already finished, so that
+    // they are skipped instead.
+    fn reroute_cancelled_tasks(
+        &self,
+        assignment_id: &str,
+        objects: &[EvacuateObject],
+        tasks: Vec<Task>,
+        detail: &str,
+    ) -> Vec<Task> {
+        use self::evacuateobjects::dsl::{evacuateobjects, id};
+
+        if tasks.is_empty() {
+            return tasks;
+        }
 
-        for eobj in completed_objects.into_iter() {
-            eobj.object
-                .get("contentLength")
-                .and_then(|cl| {
-                    if let Some(bytes) = cl.as_u64() {
-                        // TODO: metrics
-                        self.bytes_transferred
-                            .fetch_add(bytes, Ordering::SeqCst);
-                    } else {
-                        warn!("Could not get bytes as number from {}", cl);
-                    }
-                    Some(())
-                })
-                .or_else(|| {
-                    warn!("Could not find contentLength for {}", eobj.id);
-                    None
-                });
+        let mut rerouted = self.rerouted.lock().expect("rerouted lock");
+        let queue = match rerouted.as_mut() {
+            Some(q) => q,
+            None => return tasks,
+        };
 
-            obj_ids.push(eobj.id);
+        let objects: Vec<EvacuateObject> = objects
+            .iter()
+            .filter(|o| tasks.iter().any(|t| t.object_id == o.id))
+            .cloned()
+            .collect();
+        let ids: Vec<ObjectId> = objects.iter().map(|o| o.id.clone()).collect();
+
+        {
+            let locked_conn = self.conn.lock().expect("DB conn lock");
+            if let Err(e) =
+                diesel::delete(evacuateobjects.filter(id.eq_any(ids)))
+                    .execute(&*locked_conn)
+            {
+                error!(
+                    "Could not remove drained objects of assignment {}: {}",
+                    assignment_id, e
+                );
+            }
         }
 
-        debug!("Updated Objects: {:?}", obj_ids);
-        metrics_object_inc_by(Some(ACTION_EVACUATE), obj_ids.len());
-        self.mark_many_objects(obj_ids, EvacuateObjectStatus::Complete);
+        let count = objects.len();
+        push_rerouted(queue, &self.conn, objects);
+        drop(rerouted);
+
+        self.record_assignment_event(
+            assignment_id,
+            AssignmentEvent::Rerouted,
+            Some(format!("{} objects: {}", count, detail)),
+        );
+
+        vec![]
     }
 
-    fn set_assignment_state(
+    // Give the objects of an assignment to `dest` that have copies still to
+    // be made for others of their entries on the shark being evacuated back
+    // to the assignment manager, rather than updating them (see the
+    // jobs::source_copies module).  As with reroute_cancelled_tasks(), they
+    // are removed from the local database.  If the assignment manager has
+    // already finished they are skipped instead.  Returns the ids of the
+    // objects that were taken out of the assignment either way.
+    fn reroute_source_copies(
         &self,
         assignment_id: &str,
-        state: AssignmentState,
-    ) -> Result<(), Error> {
-        match self
-            .assignments
-            .write()
-            .expect("assignment write lock")
-            .get_mut(assignment_id)
-        {
-            Some(a) => {
-                a.state = state;
-                info!("Set assignment state to {:?} for {}", a.state, a.id);
-            }
+        dest: &StorageNode,
+        objects: &[EvacuateObject],
+    ) -> Vec<ObjectId> {
+        use self::evacuateobjects::dsl::{evacuateobjects, id};
+
+        if self.source_copies.is_empty() {
+            return vec![];
+        }
+
+        // The queue is held while the copies are recorded, so that
+        // source_copies_pending() sees each object either as waiting for its
+        // copy or as queued.
+        let mut rerouted = self.rerouted.lock().expect("rerouted lock");
+        let objects: Vec<EvacuateObject> = objects
+            .iter()
+            .filter(|o| self.source_copies.copied(&o.id, dest))
+            .cloned()
+            .collect();
+
+        if objects.is_empty() {
+            return vec![];
+        }
+
+        let ids: Vec<ObjectId> = objects.iter().map(|o| o.id.clone()).collect();
+        let queue = match rerouted.as_mut() {
+            Some(q) => q,
             None => {
-                let msg = format!(
-                    "Error updating assignment state for: {}",
-                    &assignment_id
-                );
-                error!("{}", &msg);
+                drop(rerouted);
+                self.source_copies.forget(&ids);
+                for object_id in ids.iter() {
+                    self.mark_object_skipped(
+                        object_id,
+                        ObjectSkippedReason::AssignmentCancelled,
+                    );
+                }
+                return ids;
+            }
+        };
 
-                return Err(InternalError::new(
-                    Some(InternalErrorCode::AssignmentLookupError),
-                    msg,
-                )
-                .into());
+        {
+            let locked_conn = self.conn.lock().expect("DB conn lock");
+            if let Err(e) =
+                diesel::delete(evacuateobjects.filter(id.eq_any(&ids)))
+                    .execute(&*locked_conn)
+            {
+                error!(
+                    "Could not remove objects of assignment {} with more \
+                     copies to make: {}",
+                    assignment_id, e
+                );
             }
         }
-        Ok(())
+
+        push_rerouted(queue, &self.conn, objects);
+        drop(rerouted);
+
+        self.record_assignment_event(
+            assignment_id,
+            AssignmentEvent::Rerouted,
+            Some(format!(
+                "{} objects: more copies on {} to move",
+                ids.len(),
+                self.from_shark.manta_storage_id
+            )),
+        );
+
+        ids
     }
 
-    #[allow(clippy::ptr_arg)]
-    fn skip_assignment(
-        &self,
-        assign_id: &AssignmentId,
-        skip_reason: ObjectSkippedReason,
-        assignment_state: AssignmentState,
-    ) {
-        self.mark_assignment_skipped(assign_id, skip_reason);
+    // Returns true if there are objects that the assignment manager is yet to
+    // be given back for their next copy, or that it has been given back and
+    // has yet to send on.  Objects whose copy failed are not coming back, so
+    // their plans are forgotten.
+    fn source_copies_pending(&self) -> bool {
+        use self::evacuateobjects::dsl::{evacuateobjects, id, status};
 
-        self.set_assignment_state(assign_id, assignment_state)
-            .unwrap_or_else(|e| {
-                // TODO: should we panic? if so just replace with expect()
-                panic!("{}", e);
-            });
+        let rerouted = self.rerouted.lock().expect("rerouted lock");
+        if rerouted.as_ref().map_or(false, |q| !q.is_empty()) {
+            return true;
+        }
 
-        self.remove_assignment_from_cache(assign_id);
-    }
+        let waiting = self.source_copies.waiting();
+        if waiting.is_empty() {
+            return false;
+        }
 
-    // Removes assignment cache entry from cache.  The cache entry is
-    // implicitly dropped when the function returns.
-    fn remove_assignment_from_cache(&self, assignment_id: &str) {
-        let mut assignments =
-            self.assignments.write().expect("assignments write");
+        let given_up: Vec<ObjectId> = {
+            let locked_conn = self.conn.lock().expect("DB conn lock");
+            evacuateobjects
+                .filter(id.eq_any(&waiting))
+                .filter(
+                    status
+                        .eq(EvacuateObjectStatus::Skipped)
+                        .or(status.eq(EvacuateObjectStatus::Error)),
+                )
+                .select(id)
+                .load::<ObjectId>(&*locked_conn)
+                .unwrap_or_default()
+        };
+        drop(rerouted);
 
-        trace!(
-            "Assignment Cache size: {} bytes",
-            std::mem::size_of::<AssignmentCacheEntry>() * assignments.len()
-        );
+        self.source_copies.forget(&given_up);
+        waiting.len() > given_up.len()
+    }
 
-        let entry = assignments.remove(assignment_id);
-        if entry.is_none() {
-            warn!(
-                "Attempt to remove assignment not in cache: {}",
-                assignment_id
+    // Give the objects that the thread of a drained destination had not
+    // posted yet back to the assignment manager.  These are not in the local
+    // database yet.
+    fn reroute_unposted(&self, objects: Vec<EvacuateObject>) {
+        let mut rerouted = self.rerouted.lock().expect("rerouted lock");
+        if let Some(queue) = rerouted.as_mut() {
+            push_rerouted(queue, &self.conn, objects);
+            return;
+        }
+        drop(rerouted);
+
+        for mut eobj in objects.into_iter() {
+            self.skip_object(
+                &mut eobj,
+                ObjectSkippedReason::AssignmentCancelled,
             );
         }
+    }
 
-        debug_assert!(
-            entry.is_some(),
-            format!("Remove assignment not in hash: {}", assignment_id)
-        );
+    // The next object to be sent to another destination because the one it
+    // was first assigned to had no room for it, or was drained.
+    fn next_rerouted(&self) -> Option<EvacuateObject> {
+        self.rerouted
+            .lock()
+            .expect("rerouted lock")
+            .as_mut()
+            .and_then(|q| q.pop(&self.conn))
+    }
 
-        let initial_size = assignment_cache_usage(&assignments);
-        assignments.shrink_to_fit();
-        trace!(
-            "Assignment cache reduced by {}",
-            initial_size - assignment_cache_usage(&assignments)
-        );
+    // The number of objects waiting to be rerouted that are held in memory,
+    // and the number that have been spilled to the local database.
+    fn rerouted_depth(&self) -> (usize, usize) {
+        self.rerouted
+            .lock()
+            .expect("rerouted lock")
+            .as_ref()
+            .map_or((0, 0), |q| (q.resident(), q.spilled()))
     }
 
-    fn skip_object(
-        &self,
-        eobj: &mut EvacuateObject,
-        reason: ObjectSkippedReason,
-    ) {
-        info!("Skipping object {}: {}.", &eobj.id, reason);
-        metrics_skip_inc(Some(&reason.to_string()));
+    // Whether close_rerouted() has been called, which is the last thing the
+    // assignment manager does.
+    fn rerouted_closed(&self) -> bool {
+        self.rerouted.lock().expect("rerouted lock").is_none()
+    }
 
-        eobj.status = EvacuateObjectStatus::Skipped;
-        eobj.skipped_reason = Some(reason);
-        self.insert_into_db(&eobj);
+    // Called once the assignment manager has finished.  Any objects still
+    // waiting to be rerouted are skipped, as are the objects of any
+    // assignment turned down from now on.
+    fn close_rerouted(&self) {
+        let remaining = self.rerouted.lock().expect("rerouted lock").take();
+        let mut remaining = match remaining {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        while let Some(mut eobj) = remaining.pop(&self.conn) {
+            self.skip_object(
+                &mut eobj,
+                ObjectSkippedReason::DestinationInsufficientSpace,
+            );
+        }
     }
 
     // This generates a new Assignment and sets the max_size with
@@ -1056,14 +3776,88 @@ impl EvacuateJob {
                 InternalError::new(Some(InternalErrorCode::SharkNotFound), msg)
             })
             .and_then(|dest_shark| {
+                // Space that other jobs have claimed, or that has been
+                // written but is not yet reflected by storinfo, is not
+                // available to us either.
+                let unreflected =
+                    self.projected.unreflected_mb(shark, &self.db_name);
+
                 Ok(_calculate_available_mb(
                     dest_shark,
-                    self.config.max_fill_percentage,
-                ))
+                    self.dest_fill_percentage(),
+                )
+                .saturating_sub(unreflected))
             })
             .map_err(Error::from)
     }
 
+    // Add a storage node to our destination shark hash, or bring the entry
+    // that we already have for it up to date.
+    fn update_dest_shark(
+        &self,
+        dest_shark_hash: &mut HashMap<StorageId, EvacuateDestShark>,
+        sn: &StorageNode,
+    ) {
+        self.projected
+            .storinfo_update(&sn.manta_storage_id, sn.timestamp);
+
+        if let Some(dest_shark) =
+            dest_shark_hash.get_mut(sn.manta_storage_id.as_str())
+        {
+            // This is the only place that can move a dest shark from the
+            // Unavailable state to the Ready state.
+            if dest_shark.status == DestSharkStatus::Unavailable {
+                dest_shark.status = DestSharkStatus::Ready;
+            }
+
+            // Update the available_mb as reported by storinfo.  We use
+            // this along with dest_shark.assigned_mb to calculate the
+            // available storage space in get_shark_available_mb().
+            // Otherwise we should not modify available_mb directly.
+            dest_shark.shark.available_mb = sn.available_mb;
+        } else {
+            // create new dest shark and add it to the hash
+            let new_shark = EvacuateDestShark {
+                shark: sn.to_owned(),
+                status: DestSharkStatus::Init,
+                assigned_mb: 0,
+            };
+            debug!("Adding new destination shark {:?} ", new_shark);
+            metrics_shark_add(&sn.manta_storage_id);
+            dest_shark_hash.insert(sn.manta_storage_id.clone(), new_shark);
+        }
+    }
+
+    /// Apply the sharks that storinfo has seen appear or disappear since
+    /// we last looked, without waiting for a snapshot of our own.
+    fn apply_shark_changes(
+        &self,
+        changes: Vec<SharkChange>,
+        algo: &mod_storinfo::ChooseAlgorithm,
+    ) {
+        let mut dest_shark_hash = self
+            .dest_shark_hash
+            .write()
+            .expect("apply shark changes write lock");
+
+        for change in changes {
+            match change {
+                SharkChange::Added(sn) => {
+                    info!("Shark {} has appeared", sn.manta_storage_id);
+                    if algo.admits(&sn) {
+                        self.update_dest_shark(&mut dest_shark_hash, &sn);
+                    }
+                }
+                SharkChange::Removed(sn_id) => {
+                    info!("Shark {} has disappeared", sn_id);
+                    if let Some(dest_shark) = dest_shark_hash.get_mut(&sn_id) {
+                        dest_shark.status = DestSharkStatus::Unavailable;
+                    }
+                }
+            }
+        }
+    }
+
     /// Iterate over a new set of storage nodes and update our destination
     /// shark hash accordingly.
     fn update_dest_sharks(&self, new_sharks: &[StorageNode]) {
@@ -1073,30 +3867,7 @@ impl EvacuateJob {
             .expect("update dest_shark_hash write lock");
 
         for sn in new_sharks.iter() {
-            if let Some(dest_shark) =
-                dest_shark_hash.get_mut(sn.manta_storage_id.as_str())
-            {
-                // This is the only place that can move a dest shark from the
-                // Unavailable state to the Ready state.
-                if dest_shark.status == DestSharkStatus::Unavailable {
-                    dest_shark.status = DestSharkStatus::Ready;
-                }
-
-                // Update the available_mb as reported by storinfo.  We use
-                // this along with dest_shark.assigned_mb to calculate the
-                // available storage space in get_shark_available_mb().
-                // Otherwise we should not modify available_mb directly.
-                dest_shark.shark.available_mb = sn.available_mb;
-            } else {
-                // create new dest shark and add it to the hash
-                let new_shark = EvacuateDestShark {
-                    shark: sn.to_owned(),
-                    status: DestSharkStatus::Init,
-                    assigned_mb: 0,
-                };
-                debug!("Adding new destination shark {:?} ", new_shark);
-                dest_shark_hash.insert(sn.manta_storage_id.clone(), new_shark);
-            }
+            self.update_dest_shark(&mut dest_shark_hash, sn);
         }
 
         // Walk the list of our destination sharks, if it doesn't exist in
@@ -1134,21 +3905,39 @@ impl EvacuateJob {
         let shark_list_retry_delay = std::time::Duration::from_millis(500);
 
         trace!("Getting new shark list");
+        let algo = mod_storinfo::ChooseAlgorithm::Default(algo);
         while tries < retries {
-            if let Some(valid_sharks) =
-                storinfo.choose(&mod_storinfo::ChooseAlgorithm::Default(algo))
-            {
+            let changes = storinfo.changes();
+            if !changes.is_empty() {
+                self.apply_shark_changes(changes, &algo);
+            }
+
+            if let Some(valid_sharks) = storinfo.choose(&algo) {
                 self.update_dest_sharks(&valid_sharks);
             }
 
             // Turn the shark hash into a list, and filter out any
-            // unavailable sharks.
+            // unavailable sharks, any that a plan is evacuating, any whose
+            // agent is quarantined or has registered without support for
+            // copy tasks, and any that the job has drained.
             shark_list = self
                 .dest_shark_hash
                 .read()
                 .expect("dest_shark_hash read lock")
                 .values()
                 .filter(|v| v.status != DestSharkStatus::Unavailable)
+                .filter(|v| !self.is_shark_full(&v.shark.manta_storage_id))
+                .filter(|v| !plan::is_draining(&v.shark.manta_storage_id))
+                .filter(|v| {
+                    !quarantine::is_quarantined(&v.shark.manta_storage_id)
+                })
+                .filter(|v| {
+                    registry::supports(
+                        &v.shark.manta_storage_id,
+                        TaskAction::Copy,
+                    )
+                })
+                .filter(|v| !self.is_drained(&v.shark.manta_storage_id))
                 .map(|v| v.to_owned())
                 .collect();
 
@@ -1174,8 +3963,26 @@ impl EvacuateJob {
             // Rust sort methods sort from lowest to highest order.  We want
             // the sharks with the most available_mb at the beginning of the
             // list so we reverse the sort.
-            shark_list.sort_by_key(|s| s.shark.available_mb);
+            // Sharks that other jobs are already filling are moved down the
+            // list.
+            shark_list.sort_by_cached_key(|s| {
+                s.shark.available_mb.saturating_sub(
+                    self.projected.unreflected_mb(
+                        &s.shark.manta_storage_id,
+                        &self.db_name,
+                    ),
+                )
+            });
             shark_list.as_mut_slice().reverse();
+
+            // Sharks whose agents have done badly for earlier jobs go last,
+            // in the same order among themselves.
+            let avoided = self.hints.avoided();
+            if !avoided.is_empty() {
+                shark_list.sort_by_key(|s| {
+                    avoided.contains(&s.shark.manta_storage_id)
+                });
+            }
             Ok(shark_list)
         }
     }
@@ -1225,16 +4032,106 @@ impl EvacuateJob {
             .expect("empty existing entries")
             .to_owned();
 
-        let existing_shard = existing_entry.shard;
+        let existing_shard = existing_entry.shard;
+        let existing_key =
+            common::get_key_from_object_value(&existing_entry.object)
+                .unwrap_or_default();
+
+        if snaplink::is_link(&existing_key, existing_shard, &key, new_shard) {
+            self.record_snaplink(eobj, conn);
+        }
+
+        let duplicate = Duplicate {
+            id: object_id,
+            key,
+            shards: vec![new_shard, existing_shard],
+        };
+
+        self.insert_duplicate_object(duplicate, new_shard);
+    }
+
+    // Record `eobj` as a link of the object with the same id that the job
+    // already has, if the job resolves snaplinks (see the snaplink module).
+    fn record_snaplink(&self, eobj: &EvacuateObject, conn: &PgConnection) {
+        if !self.config.options.resolve_snaplinks {
+            return;
+        }
+
+        let key = match common::get_key_from_object_value(&eobj.object) {
+            Ok(k) => k,
+            Err(e) => {
+                error!("Could not get key of snaplink {}: {}", eobj.id, e);
+                return;
+            }
+        };
 
-        let duplicate = Duplicate {
-            id: object_id,
+        let link = Snaplink {
+            id: eobj.id.clone(),
             key,
-            shards: vec![new_shard, existing_shard],
+            shard: eobj.shard,
+            object: eobj.object.clone(),
+            etag: eobj.etag.clone(),
+            status: SnaplinkStatus::Pending.to_string(),
         };
 
-        self.insert_duplicate_object(duplicate, new_shard);
+        info!("Recording snaplink of object {}: {}", link.id, link.key);
+
+        if let Err(e) = snaplink::record_link(conn, &link) {
+            error!("Could not record snaplink of object {}: {}", link.id, e);
+        }
+    }
+
+    // Take on the links that the job being retried or resumed had yet to
+    // update.
+    fn carry_over_snaplinks(&self) {
+        let old_job = match (&self.evac_type, &self.resume_from) {
+            (EvacuateJobType::Retry(uuid), _) => uuid,
+            (_, Some(uuid)) => uuid,
+            _ => return,
+        };
+
+        let locked_conn = self.conn.lock().expect("DB conn lock");
+        match snaplink::carry_over(&*locked_conn, old_job) {
+            Ok(0) => (),
+            Ok(count) => {
+                info!("Taking on {} snaplinks of job {}", count, old_job)
+            }
+            Err(e) => {
+                error!("Could not take on snaplinks of job {}: {}", old_job, e)
+            }
+        }
+    }
+
+    // Objects that have links still to be updated are left in post
+    // processing until they are (see resolve_snaplinks()), rather than
+    // marked complete with the rest of their assignment.
+    fn hold_linked_objects(
+        &self,
+        objects: Vec<EvacuateObject>,
+    ) -> Vec<EvacuateObject> {
+        if !self.config.options.resolve_snaplinks || objects.is_empty() {
+            return objects;
+        }
+
+        let ids: Vec<ObjectId> = objects.iter().map(|o| o.id.clone()).collect();
+        let locked_conn = self.conn.lock().expect("DB conn lock");
+        let linked =
+            snaplink::linked(&*locked_conn, &ids).unwrap_or_else(|e| {
+                let msg = format!("LocalDB: Error looking up snaplinks {}", e);
+                error!("{}", msg);
+                panic!(msg);
+            });
+
+        if !linked.is_empty() {
+            debug!("Holding {} objects with snaplinks", linked.len());
+        }
+
+        objects
+            .into_iter()
+            .filter(|o| !linked.contains(&o.id))
+            .collect()
     }
+
     // We want to concatenate the shards array with the new shard, but if
     // this is the first time we have inserted this object then we want to
     // make sure we include the existing shard (which should have been added
@@ -1242,12 +4139,8 @@ impl EvacuateJob {
     // fails due to conflict that means we already have that shard number in
     // the array.  In that case we only want to insert the new_shard number.
     fn insert_duplicate_object(&self, duplicate: Duplicate, new_shard: i32) {
-        let db_name = &self.db_name;
         let new_shard_array = vec![new_shard];
-        let connect_string = format!(
-            "host=localhost user=postgres password=postgres dbname={}",
-            db_name
-        );
+        let connect_string = pg_db::db_conn_string(&self.db_name);
 
         let mut client = pg::Client::connect(&connect_string, pg::NoTls)
             .expect("PG Connection error");
@@ -1380,6 +4273,18 @@ impl EvacuateJob {
 
         assert_eq!(num_records, obj_list.len());
 
+        insert_assignment_event(
+            &*locked_conn,
+            &assign_id,
+            AssignmentEvent::Created,
+            Some(format!(
+                "{} tasks ({}MB) for {}",
+                num_records,
+                assignment.total_size,
+                assignment.dest_shark.manta_storage_id
+            )),
+        );
+
         Ok(num_records)
     }
 
@@ -1454,7 +4359,7 @@ impl EvacuateJob {
             "Marked {} objects in assignment ({}) as skipped: {:?}",
             skipped_count, assignment_uuid, reason
         );
-        metrics_skip_inc_by(Some(&reason.to_string()), skipped_count);
+        self.count_failed(Some(reason), skipped_count);
         skipped_count
     }
 
@@ -1499,6 +4404,49 @@ impl EvacuateJob {
 
         // TODO: We may need to remove this assignment from the cache
 
+        self.count_failed(None, update_cnt);
+        update_cnt
+    }
+
+    /// Mark the objects in an assignment whose metadata has not yet been
+    /// updated as error.  Unlike mark_assignment_error() this leaves any
+    /// objects that have already been completed alone.
+    fn mark_assignment_post_processing_error(
+        &self,
+        assignment_uuid: &str,
+        err: EvacuateObjectError,
+    ) -> usize {
+        use self::evacuateobjects::dsl::{
+            assignment_id, error, evacuateobjects, skipped_reason, status,
+        };
+
+        let locked_conn = self.conn.lock().expect("DB conn lock");
+
+        let update_cnt = diesel::update(evacuateobjects)
+            .filter(assignment_id.eq(assignment_uuid))
+            .filter(status.eq(EvacuateObjectStatus::PostProcessing))
+            .set((
+                status.eq(EvacuateObjectStatus::Error),
+                skipped_reason.eq::<Option<ObjectSkippedReason>>(None),
+                error.eq(Some(err)),
+            ))
+            .execute(&*locked_conn)
+            .unwrap_or_else(|e| {
+                let msg = format!(
+                    "Error updating assignment: {} ({})",
+                    assignment_uuid, e
+                );
+                error!("{}", msg);
+                panic!(msg);
+            });
+
+        debug!(
+            "Marked {} post processing objects in assignment ({}) as \
+             error: {:?}",
+            update_cnt, assignment_uuid, err
+        );
+
+        self.count_failed(None, update_cnt);
         update_cnt
     }
 
@@ -1516,6 +4464,20 @@ impl EvacuateJob {
         let mut updates: HashMap<ObjectSkippedReason, Vec<String>> =
             HashMap::new();
 
+        // The agent's account of its attempts at each object is only kept
+        // for the record, so failing to save it does not stop the objects
+        // from being skipped.
+        let attempts: Vec<DownloadAttemptsEntry> = task_vec
+            .iter()
+            .filter_map(|t| {
+                t.download.map(|d| DownloadAttemptsEntry {
+                    id: t.object_id.clone(),
+                    attempts: d.attempts as i32,
+                    elapsed_ms: d.elapsed_ms as i64,
+                })
+            })
+            .collect();
+
         for t in task_vec {
             if let TaskStatus::Failed(reason) = t.status {
                 let entry = updates.entry(reason).or_insert_with(|| vec![]);
@@ -1528,13 +4490,16 @@ impl EvacuateJob {
 
         let locked_conn = self.conn.lock().expect("db conn lock");
 
+        if let Err(e) = save_download_attempts(&*locked_conn, &attempts) {
+            warn!("LocalDB: Error saving download attempts: {}", e);
+        }
+
         for (reason, vec_obj_ids) in updates {
             // Since we are bulk updating objects in the database by the same
-            // reason, we can just as easily do the same thing with our metrics.
-            // This is because all tasks in the vector are being skipped for
-            // the same reason.
+            // reason, we can just as easily count them together.  This is
+            // because all tasks in the vector are being skipped for the same
+            // reason.
             let vec_len = vec_obj_ids.len();
-            metrics_skip_inc_by(Some(&reason.to_string()), vec_len);
 
             let rows_updated = diesel::update(evacuateobjects)
                 .filter(id.eq_any(vec_obj_ids))
@@ -1556,6 +4521,171 @@ impl EvacuateJob {
                 "Attempted to update {} rows, but only updated {}",
                 vec_len, rows_updated
             );
+
+            self.count_failed(Some(reason), vec_len);
+        }
+    }
+
+    // Skip an object that has already been recorded, e.g. one whose copy
+    // could not be confirmed (see verify_dest_copy()).
+    fn mark_object_skipped(
+        &self,
+        object_id: &str, // ObjectId
+        reason: ObjectSkippedReason,
+    ) -> usize {
+        use self::evacuateobjects::dsl::{
+            error, evacuateobjects, id, skipped_reason, status,
+        };
+
+        let locked_conn = self.conn.lock().expect("db conn lock");
+
+        debug!("Updating object {} as skipped: {:?}", object_id, reason);
+
+        let update_cnt = diesel::update(evacuateobjects)
+            .filter(id.eq(object_id))
+            .set((
+                status.eq(EvacuateObjectStatus::Skipped),
+                skipped_reason.eq(Some(reason)),
+                error.eq::<Option<EvacuateObjectError>>(None),
+            ))
+            .execute(&*locked_conn)
+            .unwrap_or_else(|e| {
+                let msg =
+                    format!("Error updating assignment: {} ({})", object_id, e);
+                error!("{}", msg);
+                panic!(msg);
+            });
+
+        assert_eq!(update_cnt, 1);
+        self.count_failed(Some(reason), 1);
+        update_cnt
+    }
+
+    // With options.verify_before_update set, ask the front door of
+    // `dest_shark` whether it holds the copy of `eobj` that the agent made,
+    // at the size in the object's metadata, before the metadata is updated
+    // to point at it.  The agent checked the copy's MD5 when it downloaded
+    // it, so this catches copies that have gone missing or been cut short
+    // since.  An object whose copy can not be confirmed is skipped
+    // (`destination_unverified`), so that a retry job copies it again.
+    // Returns true if the metadata of the object may be updated.
+    fn verify_dest_copy(
+        &self,
+        eobj: &EvacuateObject,
+        dest_shark: &StorageNode,
+    ) -> bool {
+        let client = match &self.dest_verifier {
+            Some(client) => client,
+            None => return true,
+        };
+
+        // A remove-copy job deletes the copy on its "destination".
+        if self.is_remove_copy() {
+            return true;
+        }
+
+        let (status, detail) =
+            match VerifyObject::from_record(eobj.shard as u32, &eobj.object) {
+                Ok(object) => verify::check_object(
+                    client,
+                    self.config.verification.method,
+                    &dest_shark.manta_storage_id,
+                    &object,
+                ),
+                Err(e) => (VerifyObjectStatus::Unverifiable, Some(e)),
+            };
+
+        if status == VerifyObjectStatus::Present {
+            return true;
+        }
+
+        warn!(
+            "Copy of object {} on {} is {}, not updating its metadata: {}",
+            eobj.id,
+            dest_shark.manta_storage_id,
+            status,
+            detail.unwrap_or_default()
+        );
+        self.mark_object_skipped(
+            &eobj.id,
+            ObjectSkippedReason::DestinationUnverified,
+        );
+        false
+    }
+
+    // With options.header_check_percent set, ask the front door of
+    // `dest_shark`, for that percentage of the objects whose metadata has
+    // been updated to point at it, for the copy, and compare the custom
+    // headers that it is served with to those in the object's metadata.
+    // Any difference is logged and recorded in the header_mismatches table.
+    // This is only reported: by now the metadata points at the copy, and its
+    // content has been checked.
+    fn check_dest_headers(
+        &self,
+        eobj: &EvacuateObject,
+        dest_shark: &StorageNode,
+    ) {
+        let client = match &self.header_checker {
+            Some(client) => client,
+            None => return,
+        };
+
+        if self.is_remove_copy() {
+            return;
+        }
+
+        let percent = self.config.options.header_check_percent;
+        if rand::thread_rng().gen_range(0, 100) >= percent {
+            return;
+        }
+
+        let checked =
+            VerifyObject::from_record(eobj.shard as u32, &eobj.object)
+                .and_then(|object| {
+                    verify::check_headers(
+                        client,
+                        self.config.verification.method,
+                        &dest_shark.manta_storage_id,
+                        &object,
+                    )
+                });
+
+        let mismatches = match checked {
+            Ok(mismatches) if mismatches.is_empty() => {
+                metrics_header_check_inc(HEADER_CHECK_MATCH);
+                return;
+            }
+            Ok(mismatches) => mismatches,
+            Err(e) => {
+                warn!(
+                    "Could not check headers of object {} on {}: {}",
+                    eobj.id, dest_shark.manta_storage_id, e
+                );
+                metrics_header_check_inc(HEADER_CHECK_UNVERIFIABLE);
+                return;
+            }
+        };
+
+        warn!(
+            "Copy of object {} on {} is served with {} custom headers that \
+             differ from its metadata: {:?}",
+            eobj.id,
+            dest_shark.manta_storage_id,
+            mismatches.len(),
+            mismatches
+        );
+        metrics_header_check_inc(HEADER_CHECK_MISMATCH);
+
+        let entry = HeaderMismatchEntry {
+            id: eobj.id.clone(),
+            dest_shark: dest_shark.manta_storage_id.clone(),
+            mismatches: serde_json::to_value(&mismatches)
+                .unwrap_or(Value::Null),
+        };
+
+        let locked_conn = self.conn.lock().expect("db conn lock");
+        if let Err(e) = save_header_mismatch(&*locked_conn, &entry) {
+            warn!("LocalDB: Error saving header mismatch: {}", e);
         }
     }
 
@@ -1568,7 +4698,8 @@ impl EvacuateJob {
             error, evacuateobjects, id, skipped_reason, status,
         };
 
-        metrics_error_inc(Some(&err.to_string()));
+        self.events
+            .publish(EvacuateEvent::ErrorObserved { error: err });
 
         let locked_conn = self.conn.lock().expect("db conn lock");
 
@@ -1590,6 +4721,7 @@ impl EvacuateJob {
             });
 
         assert_eq!(update_cnt, 1);
+        self.count_failed(None, 1);
         update_cnt
     }
 
@@ -1661,6 +4793,7 @@ impl EvacuateJob {
     #[allow(clippy::ptr_arg)]
     fn mark_dest_shark_assigned(&self, dest_shark: &StorageId, size: u64) {
         trace!("Marking shark {} assigned with {}MB", dest_shark, size);
+        self.projected.reserve(dest_shark, &self.db_name, size);
         self.mark_dest_shark(
             dest_shark,
             DestSharkStatus::Assigned,
@@ -1678,9 +4811,19 @@ impl EvacuateJob {
         )
     }
 
+    // `landed` indicates whether the agent has processed the assignment (and
+    // so its data may now be on the shark) as opposed to it having been
+    // abandoned before it was sent.
     #[allow(clippy::ptr_arg)]
-    fn mark_dest_shark_ready(&self, dest_shark: &StorageId, size: u64) {
+    fn mark_dest_shark_ready(
+        &self,
+        dest_shark: &StorageId,
+        size: u64,
+        landed: bool,
+    ) {
         trace!("Marking shark {} ready with {}MB", dest_shark, size);
+        self.projected
+            .release(dest_shark, &self.db_name, size, landed);
         self.mark_dest_shark(
             dest_shark,
             DestSharkStatus::Ready,
@@ -1712,6 +4855,18 @@ impl EvacuateJob {
             .load::<EvacuateObject>(&*locked_conn)
             .expect("getting filtered objects")
     }
+
+    fn load_object(&self, object_id: &str) -> Option<EvacuateObject> {
+        use self::evacuateobjects::dsl::{evacuateobjects, id};
+
+        let locked_conn = self.conn.lock().expect("DB conn");
+
+        evacuateobjects
+            .filter(id.eq(object_id))
+            .first::<EvacuateObject>(&*locked_conn)
+            .optional()
+            .expect("getting object")
+    }
 }
 
 /// 1. Set AssignmentState to Assigned.
@@ -1728,6 +4883,12 @@ fn assignment_post_success(
         assignment.total_size
     );
 
+    job_action.events.publish(EvacuateEvent::AssignmentPosted {
+        assignment_id: assignment.id.clone(),
+        dest_shark: assignment.dest_shark.manta_storage_id.clone(),
+        tasks: assignment.tasks.len(),
+    });
+
     assignment.state = AssignmentState::Assigned;
     let mut assignments = job_action
         .assignments
@@ -1753,8 +4914,6 @@ fn assignment_post_fail(
     reason: ObjectSkippedReason,
     assignment_state: AssignmentState,
 ) {
-    // TODO: Should we blacklist this destination shark?
-
     warn!(
         "Post of assignment ({}) with size {} failed: {}",
         assignment.id, assignment.total_size, reason
@@ -1763,16 +4922,106 @@ fn assignment_post_fail(
     job_action.mark_dest_shark_ready(
         &assignment.dest_shark.manta_storage_id,
         assignment.total_size,
+        false,
     );
 
+    job_action
+        .events
+        .publish(EvacuateEvent::AssignmentPostFailed {
+            dest_shark: assignment.dest_shark.manta_storage_id.clone(),
+            tasks: assignment.tasks.len(),
+        });
+    job_action.events.publish(EvacuateEvent::AssignmentFailed {
+        dest_shark: assignment.dest_shark.manta_storage_id.clone(),
+        tasks: assignment.tasks.len(),
+        bytes: assignment.total_bytes,
+        sizes: vec![],
+    });
+
     job_action.skip_assignment(&assignment.id, reason, assignment_state);
 }
 
+// The number of times an assignment is posted before it is given up on.  The
+// agent recognizes a post of an assignment that it already has (see
+// Task::idempotency_id()), so an assignment that reached the agent even though
+// the post timed out is not processed twice.
+static POST_ATTEMPTS: u32 = 3;
+static POST_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// How long a destination whose agent turned down an assignment for lack of
+// space is left out of the job's destinations.
+static FULL_SHARK_HOLDOFF: Duration = Duration::from_secs(300);
+
+// How often the assignment manager, having been given every object, checks
+// for objects given back to it for their next copy.
+static SOURCE_COPIES_WAIT: Duration = Duration::from_secs(1);
+
+// How long an agent that can not be reached may be given to come back, e.g.
+// from being restarted, before its assignments are given up on.
+static AGENT_RESTART_GRACE: Duration = Duration::from_secs(300);
+
 impl PostAssignment for EvacuateJob {
     fn post(&self, assignment: Assignment) -> Result<(), Error> {
+        // An assignment that was already on its way to its destination when
+        // the destination was drained is sent elsewhere instead.
+        if self.is_drained(&assignment.dest_shark.manta_storage_id) {
+            self.requeue_assignment(
+                &assignment,
+                ObjectSkippedReason::AssignmentCancelled,
+                "destination drained",
+            );
+            return Ok(());
+        }
+
+        // Outside of the job's window, nothing more is posted until it opens.
+        if let Some(window) = &self.schedule {
+            schedule::wait_for_window(&self.db_name, window, || {
+                self.stopping()
+            });
+        }
+
+        // Wait for this assignment's share of the bandwidth budget of the
+        // whole manager.  A job that is stopping sends what it has without
+        // waiting.
+        let waited = bandwidth::shared()
+            .acquire(assignment.total_bytes, || self.stopping());
+        if waited > Duration::from_secs(0) {
+            debug!(
+                "Assignment {} waited {:?} for bandwidth",
+                assignment.id, waited
+            );
+            metrics_bandwidth_wait_observe(waited.as_secs_f64());
+        }
+
+        // Objects are only asked for compressed by an agent that has said
+        // that it can decode them.
+        let encodings = if self.config.options.compress_transfers {
+            registry::encodings(&assignment.dest_shark.manta_storage_id)
+        } else {
+            vec![]
+        };
+
+        // With direct_pull, each object is fetched from the agent on its
+        // source wherever that agent serves objects, and from the source's
+        // web server otherwise.
+        let direct_pull = self.config.options.direct_pull;
+        let tasks = assignment
+            .tasks
+            .values()
+            .map(|t| {
+                let mut task = t.to_owned();
+                task.direct = direct_pull
+                    && task.action.is_copy()
+                    && registry::serves_objects(&task.source.manta_storage_id);
+                task
+            })
+            .collect();
+
         let payload = AssignmentPayload {
             id: assignment.id.clone(),
-            tasks: assignment.tasks.values().map(|t| t.to_owned()).collect(),
+            tasks,
+            order: self.config.options.task_order,
+            encodings,
         };
 
         let agent_uri = format!(
@@ -1781,27 +5030,104 @@ impl PostAssignment for EvacuateJob {
         );
 
         trace!("Sending {:#?} to {}", payload, agent_uri);
-        let res = match self.post_client.post(&agent_uri).json(&payload).send()
-        {
-            Ok(r) => r,
-            Err(e) => {
+        let mut attempt = 1;
+        let mut res = loop {
+            let sent = match self
+                .agent_pool
+                .checkout(&assignment.dest_shark.manta_storage_id)
+                .post(&agent_uri)
+                .json(&payload)
+                .send()
+            {
+                // An answer dropped by an injected fault is taken to be one
+                // that never came.
+                Ok(r) => faults::answer(FaultPoint::ManagerAssignmentPost, r)
+                    .map_err(|e| (PostFailure::Timeout, e)),
+                Err(e) => Err((PostFailure::from_error(&e), Error::from(e))),
+            };
+
+            let err = match sent {
+                // On a retry, a conflict means that the agent received an
+                // earlier post but has not finished saving it yet.
+                Ok(r) => {
+                    if attempt == 1
+                        || r.status() != reqwest::StatusCode::CONFLICT
+                    {
+                        break r;
+                    }
+                    self.record_assignment_event(
+                        &assignment.id,
+                        AssignmentEvent::PostFailed,
+                        Some(format!(
+                            "attempt {}: status {}",
+                            attempt,
+                            r.status()
+                        )),
+                    );
+                    format!("status {}", r.status())
+                }
+                Err((failure, e)) => {
+                    self.record_assignment_event(
+                        &assignment.id,
+                        AssignmentEvent::PostFailed,
+                        Some(format!("attempt {}: {}", attempt, e)),
+                    );
+                    quarantine::shared().post_failed(
+                        &assignment.dest_shark.manta_storage_id,
+                        failure,
+                    );
+                    if attempt == POST_ATTEMPTS {
+                        assignment_post_fail(
+                            self,
+                            &assignment,
+                            ObjectSkippedReason::DestinationUnreachable,
+                            AssignmentState::AgentUnavailable,
+                        );
+                        return Err(e);
+                    }
+                    e.to_string()
+                }
+            };
+
+            if attempt == POST_ATTEMPTS {
                 assignment_post_fail(
                     self,
                     &assignment,
-                    ObjectSkippedReason::DestinationUnreachable,
-                    AssignmentState::AgentUnavailable,
+                    ObjectSkippedReason::AssignmentRejected,
+                    AssignmentState::Rejected,
+                );
+
+                let msg = format!(
+                    "Error posting assignment {} to {} ({})",
+                    payload.id, assignment.dest_shark.manta_storage_id, err
                 );
-                return Err(e.into());
+                return Err(InternalError::new(None, msg).into());
             }
+
+            warn!(
+                "Attempt {} of {} to post assignment {} to {} failed: {}",
+                attempt,
+                POST_ATTEMPTS,
+                payload.id,
+                assignment.dest_shark.manta_storage_id,
+                err
+            );
+            thread::sleep(POST_RETRY_DELAY);
+            attempt += 1;
         };
 
         if !res.status().is_success() {
-            assignment_post_fail(
-                self,
-                &assignment,
-                ObjectSkippedReason::AssignmentRejected,
-                AssignmentState::Rejected,
+            self.record_assignment_event(
+                &assignment.id,
+                AssignmentEvent::PostFailed,
+                Some(format!("attempt {}: status {}", attempt, res.status())),
             );
+            if res.status().is_server_error() {
+                quarantine::shared().post_failed(
+                    &assignment.dest_shark.manta_storage_id,
+                    PostFailure::ServerError,
+                );
+            }
 
             let err = format!(
                 "Error posting assignment {} to {} ({})",
@@ -1810,11 +5136,48 @@ impl PostAssignment for EvacuateJob {
                 res.status()
             );
 
+            if res.status() == reqwest::StatusCode::INSUFFICIENT_STORAGE {
+                let detail = match res.json::<AssignmentRejection>() {
+                    Ok(AssignmentRejection::InsufficientSpace {
+                        required_mb,
+                        available_mb,
+                    }) => format!(
+                        "{}MB required, {}MB available",
+                        required_mb, available_mb
+                    ),
+                    Err(_) => res.status().to_string(),
+                };
+
+                self.reroute_assignment(&assignment, &detail);
+                return Err(InternalError::new(None, err).into());
+            }
+
+            assignment_post_fail(
+                self,
+                &assignment,
+                ObjectSkippedReason::AssignmentRejected,
+                AssignmentState::Rejected,
+            );
+
             return Err(InternalError::new(None, err).into());
         }
 
         debug!("Post of {} was successful", payload.id);
+        quarantine::shared()
+            .post_succeeded(&assignment.dest_shark.manta_storage_id);
+        self.record_assignment_event(
+            &assignment.id,
+            AssignmentEvent::Assigned,
+            Some(format!("attempt {}", attempt)),
+        );
+
+        // The destination may have been drained while it was being posted.
+        let id = assignment.id.clone();
+        let drained = self.is_drained(&assignment.dest_shark.manta_storage_id);
         assignment_post_success(self, assignment);
+        if drained {
+            self.cancel_drained(vec![id]);
+        }
         Ok(())
     }
 }
@@ -1830,7 +5193,15 @@ impl GetAssignment for EvacuateJob {
         );
 
         debug!("Getting Assignment: {:?}", uri);
-        match self.get_client.get(&uri).send() {
+        let res = self
+            .agent_pool
+            .checkout(&ace.dest_shark.manta_storage_id)
+            .get(&uri)
+            .send()
+            .map_err(Error::from)
+            .and_then(|r| faults::answer(FaultPoint::ManagerAssignmentGet, r));
+
+        match res {
             Ok(mut resp) => {
                 if !resp.status().is_success() {
                     self.skip_assignment(
@@ -1853,13 +5224,15 @@ impl GetAssignment for EvacuateJob {
                 resp.json::<AgentAssignment>().map_err(Error::from)
             }
             Err(e) => {
-                self.skip_assignment(
-                    &ace.id,
-                    ObjectSkippedReason::NetworkError,
-                    AssignmentState::AgentUnavailable,
-                );
+                if !self.reconcile_agent(ace) {
+                    self.skip_assignment(
+                        &ace.id,
+                        ObjectSkippedReason::NetworkError,
+                        AssignmentState::AgentUnavailable,
+                    );
+                }
 
-                Err(e.into())
+                Err(e)
             }
         }
     }
@@ -1889,26 +5262,92 @@ impl UpdateMetadata for EvacuateJob {
                 )
                 .into());
             }
-        };
+        };
+
+        // A remove-copy job takes the old shark out altogether, provided that
+        // it does not leave the object without a copy.  A create-copy job
+        // adds the new shark rather than replacing the old one, provided that
+        // it does not hold a copy already.
+        if self.is_remove_copy() {
+            let before = sharks.len();
+            sharks.retain(|s| s.manta_storage_id != old_shark.manta_storage_id);
+
+            if sharks.len() == before || sharks.is_empty() {
+                let msg = format!(
+                    "Could not remove shark {} while attempting to update \
+                     metadata. Manta Object: {:?}",
+                    old_shark.manta_storage_id, object
+                );
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::SharkNotFound),
+                    msg,
+                )
+                .into());
+            }
+        } else if self.is_create_copy() {
+            if sharks
+                .iter()
+                .any(|s| s.manta_storage_id == new_shark.manta_storage_id)
+            {
+                let msg = format!(
+                    "Found duplicate shark while attempting to update \
+                     metadata. Manta Object: {:?}, New Shark: {:?}",
+                    object, new_shark
+                );
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::DuplicateShark),
+                    msg,
+                )
+                .into());
+            }
 
-        // replace shark value
-        for shark in sharks.iter_mut() {
-            if shark.manta_storage_id == old_shark.manta_storage_id {
-                shark.manta_storage_id = new_shark.manta_storage_id.clone();
-                shark.datacenter = new_shark.datacenter.clone();
-                if !shark_found {
-                    shark_found = true;
-                } else {
-                    let msg =
-                        format!("Found duplicate shark while attempting \
-                        to update metadata. Manta Object: {:?}, New Shark: \
-                        {:?}",
-                         object, new_shark);
-                    return Err(InternalError::new(
-                        Some(InternalErrorCode::DuplicateShark),
-                        msg,
-                    )
-                    .into());
+            sharks.push(MantaObjectShark {
+                manta_storage_id: new_shark.manta_storage_id.clone(),
+                datacenter: new_shark.datacenter.clone(),
+            });
+        } else if source_copies::copies_on_shark(
+            &object,
+            &old_shark.manta_storage_id,
+        ) > 1
+        {
+            // Every copy on the old shark is replaced at once, by the
+            // destinations that were copied to for the others and then by
+            // the new shark.
+            let id = common::get_objectId_from_value(&object)?;
+            let copied = self
+                .source_copies
+                .get(&id)
+                .map(|plan| plan.copied)
+                .unwrap_or_default();
+            let mut dests: Vec<&StorageNode> = copied.iter().collect();
+            dests.push(new_shark);
+
+            source_copies::replace_copies(
+                &mut sharks,
+                &old_shark.manta_storage_id,
+                &dests,
+            )?;
+        } else {
+            // replace shark value
+            for shark in sharks.iter_mut() {
+                if shark.manta_storage_id == old_shark.manta_storage_id {
+                    shark.manta_storage_id = new_shark.manta_storage_id.clone();
+                    shark.datacenter = new_shark.datacenter.clone();
+                    if !shark_found {
+                        shark_found = true;
+                    } else {
+                        let msg = format!(
+                            "Found duplicate shark while attempting \
+                            to update metadata. Manta Object: {:?}, New Shark: \
+                            {:?}",
+                            object, new_shark
+                        );
+                        return Err(InternalError::new(
+                            Some(InternalErrorCode::DuplicateShark),
+                            msg,
+                        )
+                        .into());
+                    }
                 }
             }
         }
@@ -1996,7 +5435,49 @@ impl ProcessAssignment for EvacuateJob {
             &agent_assignment.stats.state
         );
 
-        match agent_assignment.stats.state {
+        if let AgentAssignmentState::Complete(_) = agent_assignment.stats.state
+        {
+            let stats = &agent_assignment.stats;
+            self.record_assignment_event(
+                &ace.id,
+                AssignmentEvent::AgentComplete,
+                Some(format!(
+                    "{} of {} tasks complete, {} failed",
+                    stats.complete, stats.total, stats.failed
+                )),
+            );
+            self.events.publish(EvacuateEvent::AssignmentCompleted {
+                assignment_id: ace.id.clone(),
+                total: stats.total,
+                failed: stats.failed,
+            });
+            self.record_slow_tasks(&ace, &stats.slow_tasks);
+        }
+
+        // A copy that a remove-copy job finds already gone from the agent
+        // only needs to be removed from the object's metadata.
+        let state = match agent_assignment.stats.state {
+            AgentAssignmentState::Complete(Some(failed_tasks))
+                if self.is_remove_copy() =>
+            {
+                let not_found = TaskStatus::Failed(
+                    ObjectSkippedReason::AgentObjectNotFound,
+                );
+                let failed_tasks: Vec<Task> = failed_tasks
+                    .into_iter()
+                    .filter(|t| t.status != not_found)
+                    .collect();
+
+                if failed_tasks.is_empty() {
+                    AgentAssignmentState::Complete(None)
+                } else {
+                    AgentAssignmentState::Complete(Some(failed_tasks))
+                }
+            }
+            state => state,
+        };
+
+        match state {
             AgentAssignmentState::Scheduled | AgentAssignmentState::Running => {
                 warn!(
                     "Trying to process an assignment that is Scheduled or \
@@ -2007,6 +5488,18 @@ impl ProcessAssignment for EvacuateJob {
             }
 
             AgentAssignmentState::Complete(None) => {
+                if !self.source_copies.is_empty() {
+                    let objects = self.load_assignment_objects(
+                        &ace.id,
+                        EvacuateObjectStatus::Assigned,
+                    );
+                    self.reroute_source_copies(
+                        &ace.id,
+                        &ace.dest_shark,
+                        &objects,
+                    );
+                }
+
                 // mark all EvacuateObjects with this assignment id as
                 // successful
                 self.mark_assignment_objects(
@@ -2042,6 +5535,66 @@ impl ProcessAssignment for EvacuateJob {
                     })
                     .collect();
 
+                // Objects with copies still to make go on to their next
+                // destination.
+                let successful_tasks = if self.source_copies.is_empty() {
+                    successful_tasks
+                } else {
+                    let successful_objects: Vec<EvacuateObject> = objects
+                        .iter()
+                        .filter(|obj| successful_tasks.contains(&obj.id))
+                        .cloned()
+                        .collect();
+                    let rerouted = self.reroute_source_copies(
+                        &ace.id,
+                        &ace.dest_shark,
+                        &successful_objects,
+                    );
+
+                    successful_tasks
+                        .into_iter()
+                        .filter(|obj_id| !rerouted.contains(obj_id))
+                        .collect()
+                };
+
+                // The tasks that the agent did not get to before the
+                // assignment was cancelled are sent elsewhere rather than
+                // skipped.
+                let cancelled = TaskStatus::Failed(
+                    ObjectSkippedReason::AssignmentCancelled,
+                );
+                let (cancelled_tasks, mut failed_tasks): (Vec<Task>, _) =
+                    failed_tasks
+                        .into_iter()
+                        .partition(|t| t.status == cancelled);
+                let detail =
+                    if self.is_drained(&ace.dest_shark.manta_storage_id) {
+                        "destination drained"
+                    } else {
+                        "assignment cancelled"
+                    };
+                failed_tasks.extend(self.reroute_cancelled_tasks(
+                    &ace.id,
+                    &objects,
+                    cancelled_tasks,
+                    detail,
+                ));
+
+                let failed_sizes: Vec<u64> = objects
+                    .iter()
+                    .filter(|obj| {
+                        failed_tasks.iter().any(|ft| ft.object_id == obj.id)
+                    })
+                    .map(object_bytes)
+                    .collect();
+
+                self.events.publish(EvacuateEvent::AssignmentFailed {
+                    dest_shark: ace.dest_shark.manta_storage_id.clone(),
+                    tasks: failed_sizes.len(),
+                    bytes: failed_sizes.iter().sum(),
+                    sizes: failed_sizes,
+                });
+
                 self.mark_many_task_objects_skipped(failed_tasks);
                 self.mark_many_objects(
                     successful_tasks,
@@ -2054,12 +5607,43 @@ impl ProcessAssignment for EvacuateJob {
         self.mark_dest_shark_ready(
             &ace.dest_shark.manta_storage_id,
             ace.total_size,
+            true,
         );
 
         Ok(())
     }
 }
 
+// Queue `objects` to be sent to another destination by the assignment
+// manager, as they were when they were first found.  Those for which there is
+// no room in memory are spilled to the local database that `conn` is
+// connected to.
+fn push_rerouted(
+    queue: &mut OverflowQueue,
+    conn: &Mutex<PgConnection>,
+    objects: Vec<EvacuateObject>,
+) {
+    let objects = objects
+        .into_iter()
+        .map(|mut eobj| {
+            eobj.status = EvacuateObjectStatus::Unprocessed;
+            eobj.assignment_id = String::new();
+            eobj.dest_shark = String::new();
+            eobj
+        })
+        .collect();
+
+    queue.push(conn, objects);
+}
+
+// The size of an object in bytes, as recorded in its metadata.
+fn object_bytes(eobj: &EvacuateObject) -> u64 {
+    eobj.object
+        .get("contentLength")
+        .and_then(|cl| cl.as_u64())
+        .unwrap_or(0)
+}
+
 fn _insert_bad_moray_object(
     job_action: &EvacuateJob,
     object: Value,
@@ -2181,6 +5765,7 @@ fn _calculate_available_mb(
 // to consider the trade off if any of inserts.
 fn local_db_generator(
     obj_tx: crossbeam::Sender<EvacuateObject>,
+    job_action: &EvacuateJob,
     retry_uuid: &str,
 ) -> Result<(), Error> {
     use self::evacuateobjects::dsl::{evacuateobjects, id as obj_id, status};
@@ -2235,6 +5820,11 @@ fn local_db_generator(
     // rebalancer.  We expect each query to take < 1ms, which would imply a
     // total time of 5 hours for 18 million skips/errors.
     for id in ids {
+        if job_action.stopping() {
+            info!("Job is stopping, local db generator exiting");
+            break;
+        }
+
         let obj = evacuateobjects
             .filter(obj_id.eq(id))
             .get_result::<EvacuateObject>(&conn)
@@ -2251,6 +5841,10 @@ fn local_db_generator(
         // object has for a sharks array should be the same as what it
         // was when we first found it.
         if let Err(e) = obj_tx.send(obj) {
+            if job_action.stopping() {
+                break;
+            }
+
             warn!("local db generator exiting early: {}", e);
             return Err(InternalError::new(
                 Some(InternalErrorCode::Crossbeam),
@@ -2265,84 +5859,570 @@ fn local_db_generator(
 
 fn start_local_db_generator(
     obj_tx: crossbeam::Sender<EvacuateObject>,
+    job_action: Arc<EvacuateJob>,
     retry_uuid: &str,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
     let db_name = retry_uuid.to_string();
-    thread::Builder::new()
-        .name(String::from("local_generator"))
-        .spawn(move || local_db_generator(obj_tx, &db_name))
-        .map_err(Error::from)
+    let job_id = job_action.db_name.clone();
+    spawn_supervised(
+        &job_id,
+        "local_generator",
+        watchdog::any_time,
+        move || local_db_generator(obj_tx, &job_action, &db_name),
+    )
 }
 
 /// Start the sharkspotter thread and feed the objects into the assignment
 /// thread.  If the assignment thread (the rx side of the channel) exits
 /// prematurely the sender.send() method will return a SenderError and that
 /// needs to be handled properly.
+///
+/// The shards scanned are those of the job's configuration, which a job may
+/// have narrowed to some of the shards (see Config::select_shards()).  Each
+/// shard is scanned on its own, `max_md_read_threads` (or the job's
+/// `scan_parallelism`) at a time, so that it is known when the scan of each
+/// shard is complete.  How far the scan of each shard has got is recorded in
+/// the job's scan_checkpoint table as the job goes (see the checkpoint module),
+/// and once more when it stops.  A job resumed from one that was interrupted
+/// (or that the manager crashed under) scans only the shards that the
+/// interrupted job was to scan, and does not scan those that it settled (see
+/// settled_shards()) again.  Instead it sends on the objects of those shards
+/// that the interrupted job did not finish with, from the interrupted job's
+/// database.  A shard that was only part way through is scanned again from its
+/// beginning, since sharkspotter can not start part way through a shard, and
+/// the objects that had already been moved off the shark are simply not found
+/// again.
 fn start_sharkspotter(
     obj_tx: crossbeam::Sender<EvacuateObject>,
     domain: &str,
     job_action: Arc<EvacuateJob>,
-    min_shard: u32,
-    max_shard: u32,
+    mut shards: Vec<u32>,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
-    let shark = &job_action.from_shark.manta_storage_id;
-    let config = sharkspotter::config::Config {
-        domain: String::from(domain),
-        min_shard,
-        max_shard,
-        sharks: vec![shark.to_string()],
-        chunk_size: job_action.config.options.md_read_chunk_size as u64,
-        direct_db: true,
-        max_threads: job_action.config.options.max_md_read_threads,
-        ..Default::default()
+    // The interrupted job had a checkpoint for every shard that it was to
+    // scan from the start, which keeps the shards that it was narrowed to.
+    if let Some(old_job) = &job_action.resume_from {
+        match scan_checkpoint(old_job) {
+            Ok(checkpoints) if !checkpoints.is_empty() => {
+                shards.retain(|s| {
+                    checkpoints.iter().any(|c| c.shard as u32 == *s)
+                });
+            }
+            Ok(_) => (),
+            Err(e) => warn!(
+                "Could not get the scan checkpoints of job {}, scanning all \
+                 shards: {}",
+                old_job, e
+            ),
+        }
+    }
+
+    let settled: Vec<ScanCheckpoint> = match &job_action.resume_from {
+        Some(old_job) => settled_shards(old_job).unwrap_or_else(|e| {
+            warn!(
+                "Could not get the scan checkpoints of job {}, scanning all \
+                 shards: {}",
+                old_job, e
+            );
+            vec![]
+        }),
+        None => vec![],
+    };
+
+    let shards: VecDeque<u32> = shards
+        .into_iter()
+        .filter(|s| !settled.iter().any(|c| c.shard as u32 == *s))
+        .collect();
+
+    info!(
+        "Scanning {} shards, {} settled by an earlier job",
+        shards.len(),
+        settled.len()
+    );
+
+    let domain = domain.to_string();
+    let log = slog_scope::logger();
+    let scan_threads = job_action.config.options.max_md_read_threads.max(1);
+
+    let job_id = job_action.db_name.clone();
+    spawn_supervised(&job_id, "sharkspotter", watchdog::any_time, move || {
+        let mut checkpoints: HashMap<i32, ScanCheckpoint> = HashMap::new();
+
+        if let Some(old_job) = &job_action.resume_from {
+            if !settled.is_empty() {
+                checkpoints = replay_settled_shards(
+                    &obj_tx,
+                    &job_action,
+                    old_job,
+                    &settled,
+                )?;
+            }
+        }
+
+        // Every shard to be scanned has a checkpoint from the start, so that
+        // the job's status can tell how many shards are left to scan.
+        for shard in shards.iter() {
+            scan_checkpoint_entry(&mut checkpoints, *shard as i32);
+        }
+        job_action.save_scan_checkpoints(&checkpoints)?;
+
+        let (scan_tx, scan_rx) = crossbeam::bounded(10);
+        let translator_job = Arc::clone(&job_action);
+        let translator: JoinHandle<Result<(), Error>> = thread::Builder::new()
+            .name("sharkspotter_translator".to_string())
+            .spawn(joblog::inherit(move || {
+                translate_scan(scan_rx, obj_tx, &translator_job, checkpoints)
+            }))
+            .expect("Start sharkspotter translator thread");
+
+        let queue = Arc::new(Mutex::new(shards));
+        let scanners: Vec<JoinHandle<Result<(), Error>>> = (0..scan_threads)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let scan_tx = scan_tx.clone();
+                let job_action = Arc::clone(&job_action);
+                let domain = domain.clone();
+                let log = log.clone();
+
+                thread::Builder::new()
+                    .name("sharkspotter_scanner".to_string())
+                    .spawn(joblog::inherit(move || {
+                        scan_shards(
+                            &queue,
+                            &domain,
+                            &job_action,
+                            &log,
+                            &scan_tx,
+                        )
+                    }))
+                    .expect("Start sharkspotter scanner thread")
+            })
+            .collect();
+
+        // The translator finishes once every scanner has.
+        drop(scan_tx);
+
+        let mut ret = Ok(());
+        for scanner in scanners {
+            if let Err(e) = scanner.join().expect("sharkspotter scanner join") {
+                set_run_error(&mut ret, e);
+            }
+        }
+
+        if let Err(e) = translator.join().expect("sharkspotter translator join")
+        {
+            set_run_error(&mut ret, e);
+        }
+
+        ret
+    })
+}
+
+/// Read the objects of the job from the snapshot file at `path` instead of
+/// scanning the metadata tier, and feed them into the assignment thread in
+/// the same way as start_sharkspotter() does.  A job resumed from one that
+/// was interrupted sends on the objects of the shards that the interrupted
+/// job settled from the interrupted job's database, and leaves the records
+/// of those shards in the file out.  See the jobs::source module.
+fn start_file_scan(
+    obj_tx: crossbeam::Sender<EvacuateObject>,
+    job_action: Arc<EvacuateJob>,
+    path: &str,
+) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
+    let records = source::open(path)?;
+
+    let settled: Vec<ScanCheckpoint> = match &job_action.resume_from {
+        Some(old_job) => settled_shards(old_job).unwrap_or_else(|e| {
+            warn!(
+                "Could not get the scan checkpoints of job {}, reading all \
+                 shards: {}",
+                old_job, e
+            );
+            vec![]
+        }),
+        None => vec![],
+    };
+
+    info!(
+        "Reading objects from {}, {} shards settled by an earlier job",
+        path,
+        settled.len()
+    );
+
+    let path = path.to_string();
+    let job_id = job_action.db_name.clone();
+    spawn_supervised(&job_id, "file_scan", watchdog::any_time, move || {
+        let mut checkpoints: HashMap<i32, ScanCheckpoint> = HashMap::new();
+
+        if let Some(old_job) = &job_action.resume_from {
+            if !settled.is_empty() {
+                checkpoints = replay_settled_shards(
+                    &obj_tx,
+                    &job_action,
+                    old_job,
+                    &settled,
+                )?;
+                job_action.save_scan_checkpoints(&checkpoints)?;
+            }
+        }
+
+        let (scan_tx, scan_rx) = crossbeam::bounded(10);
+        let translator_job = Arc::clone(&job_action);
+        let translator: JoinHandle<Result<(), Error>> = thread::Builder::new()
+            .name("sharkspotter_translator".to_string())
+            .spawn(joblog::inherit(move || {
+                translate_scan(scan_rx, obj_tx, &translator_job, checkpoints)
+            }))
+            .expect("Start sharkspotter translator thread");
+
+        // The shards that the file has records of, in the order in which
+        // they were first found.
+        let mut shards: Vec<u32> = vec![];
+        let mut ret = Ok(());
+        let mut forwarded = true;
+
+        for record in records {
+            if job_action.stopping() {
+                forwarded = false;
+                break;
+            }
+
+            let (line, record) = match record {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Could not read {}: {}", path, e);
+                    set_run_error(&mut ret, e);
+                    break;
+                }
+            };
+
+            let msg = match record {
+                Ok(m) => m,
+                Err(e) => {
+                    warn!("Skipping line {} of {}: {}", line, path, e);
+                    continue;
+                }
+            };
+
+            if settled.iter().any(|c| c.shard as u32 == msg.shard) {
+                continue;
+            }
+            if !shards.contains(&msg.shard) {
+                shards.push(msg.shard);
+            }
+
+            if scan_tx.send(ScanMessage::Record(msg)).is_err() {
+                forwarded = false;
+                break;
+            }
+        }
+
+        // Only a file that was read to its end has had its shards scanned.
+        if forwarded && ret.is_ok() {
+            for shard in shards {
+                if scan_tx.send(ScanMessage::ShardScanned(shard)).is_err() {
+                    break;
+                }
+            }
+        }
+        drop(scan_tx);
+
+        if let Err(e) = translator.join().expect("sharkspotter translator join")
+        {
+            set_run_error(&mut ret, e);
+        }
+
+        ret
+    })
+}
+
+// Scan shards taken from `queue` until there are none left, sending each
+// record found on to the translator, followed by word that the shard has
+// been scanned.
+fn scan_shards(
+    queue: &Mutex<VecDeque<u32>>,
+    domain: &str,
+    job_action: &EvacuateJob,
+    log: &slog::Logger,
+    scan_tx: &crossbeam::Sender<ScanMessage>,
+) -> Result<(), Error> {
+    loop {
+        if job_action.stopping() {
+            return Ok(());
+        }
+
+        let shard = match queue.lock().expect("shard queue lock").pop_front() {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        // Each scan is of a single shard, so it needs only one thread.
+        let config = sharkspotter::config::Config {
+            domain: domain.to_string(),
+            min_shard: shard,
+            max_shard: shard,
+            sharks: vec![job_action.from_shark.manta_storage_id.clone()],
+            chunk_size: job_action.config.options.md_read_chunk_size as u64,
+            direct_db: true,
+            max_threads: 1,
+            ..Default::default()
+        };
+
+        debug!("Starting sharkspotter scan: {:?}", &config);
+
+        let (ss_tx, ss_rx) = crossbeam_channel::bounded(10);
+        let scan_log = log.clone();
+        let scan = thread::Builder::new()
+            .name(format!("sharkspotter_shard_{}", shard))
+            .spawn(joblog::inherit(move || {
+                sharkspotter::run_multithreaded(&config, scan_log, ss_tx)
+            }))
+            .expect("Start sharkspotter thread");
+
+        // Once the translator stops (because the job is stopping, or it has
+        // found as many objects as it needs) there is nowhere to send the
+        // records, and dropping the receiver stops the scan too.  The time
+        // spent waiting for each record is the shard's read latency.
+        let mut forwarded = true;
+        let mut waiting = std::time::Instant::now();
+        for msg in ss_rx.iter() {
+            metrics_moray_shard_observe(
+                shard,
+                MORAY_OP_READ,
+                waiting.elapsed().as_secs_f64(),
+                false,
+            );
+
+            if scan_tx.send(ScanMessage::Record(msg)).is_err() {
+                forwarded = false;
+                break;
+            }
+            waiting = std::time::Instant::now();
+        }
+        drop(ss_rx);
+
+        let result = scan.join().expect("sharkspotter join");
+        if !forwarded || job_action.stopping() {
+            return Ok(());
+        }
+
+        if let Err(e) = result {
+            metrics_moray_shard_error_inc(shard, MORAY_OP_READ);
+            return Err(Error::from(e));
+        }
+
+        if scan_tx.send(ScanMessage::ShardScanned(shard)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// Turn the records that the scanners find in to evacuate objects, keeping
+// track of how far the scan of each shard has got.
+fn translate_scan(
+    scan_rx: crossbeam::Receiver<ScanMessage>,
+    obj_tx: crossbeam::Sender<EvacuateObject>,
+    job_action: &EvacuateJob,
+    mut checkpoints: HashMap<i32, ScanCheckpoint>,
+) -> Result<(), Error> {
+    let mut schedule = CheckpointSchedule::new(&job_action.config.checkpoints);
+    let mut on_shark: u64 = 0;
+    let mut not_on_shark: u64 = 0;
+
+    while let Ok(scan_msg) = scan_rx.recv() {
+        if job_action.stopping() {
+            info!("Job is stopping, stopping scan");
+            break;
+        }
+
+        schedule.record();
+        if schedule.is_due() {
+            job_action.save_scan_checkpoints(&checkpoints)?;
+            schedule.saved();
+        }
+
+        let mut ss_msg = match scan_msg {
+            ScanMessage::Record(msg) => msg,
+            ScanMessage::ShardScanned(shard) => {
+                debug!("Finished scanning shard {}", shard);
+                scan_checkpoint_entry(&mut checkpoints, shard as i32)
+                    .complete = true;
+                continue;
+            }
+        };
+
+        let disposition = record::classify(
+            &mut ss_msg.manta_value,
+            job_action.config.options.max_record_bytes,
+        );
+        if disposition != RecordDisposition::Object {
+            job_action
+                .count_record_disposition(disposition, &ss_msg.manta_value);
+            continue;
+        }
+
+        if !record::has_copy_on(
+            &ss_msg.manta_value,
+            &job_action.from_shark.manta_storage_id,
+        ) {
+            not_on_shark += 1;
+            job_action.count_record_disposition(
+                RecordDisposition::NotOnShark,
+                &ss_msg.manta_value,
+            );
+            continue;
+        }
+        on_shark += 1;
+
+        if !job_action.filter_matches(&ss_msg.manta_value) {
+            job_action.count_record_disposition(
+                RecordDisposition::Filtered,
+                &ss_msg.manta_value,
+            );
+            continue;
+        }
+
+        if job_action.has_enough_copies(&ss_msg.manta_value) {
+            job_action.count_record_disposition(
+                RecordDisposition::EnoughCopies,
+                &ss_msg.manta_value,
+            );
+            continue;
+        }
+
+        if job_action.has_too_few_copies(&ss_msg.manta_value) {
+            job_action.count_record_disposition(
+                RecordDisposition::TooFewCopies,
+                &ss_msg.manta_value,
+            );
+            continue;
+        }
+
+        // Objects that can not be moved are recorded in the database too, so
+        // they are counted along with those that are sent on.
+        let shard = ss_msg.shard as i32;
+        let eo: EvacuateObject = match EvacuateObject::try_from(ss_msg) {
+            Ok(o) => o,
+            Err(e) => {
+                scan_checkpoint_entry(&mut checkpoints, shard)
+                    .objects_scanned += 1;
+                job_action.insert_into_db(&e);
+                job_action.events.publish(EvacuateEvent::ObjectsNotMoved {
+                    reason: None,
+                    assigned: false,
+                    count: 1,
+                    sizes: vec![],
+                });
+                continue;
+            }
+        };
+
+        let checkpoint = scan_checkpoint_entry(&mut checkpoints, eo.shard);
+        checkpoint.objects_scanned += 1;
+        checkpoint.last_object_id = eo.id.clone();
+
+        if let Err(e) = obj_tx.send(eo) {
+            warn!(
+                "Could not send evacuate object.  Receive side of channel \
+                 exited prematurely.  Is max_objects set? {}",
+                e
+            );
+
+            break;
+        }
+    }
+
+    job_action.save_scan_checkpoints(&checkpoints)?;
+
+    // Every object found having its copy recorded under some other storage
+    // id suggests that the job was given a storage id other than the one the
+    // metadata records, rather than that the scan matched a few similar
+    // names.
+    if not_on_shark > 0 && on_shark == 0 {
+        error!(
+            "None of the {} objects found for {} have a copy recorded on \
+             that storage id",
+            not_on_shark, job_action.from_shark.manta_storage_id
+        );
+    }
+
+    info!("Sharkspotter translator thread exiting");
+    Ok(())
+}
+
+fn scan_checkpoint_entry(
+    checkpoints: &mut HashMap<i32, ScanCheckpoint>,
+    shard: i32,
+) -> &mut ScanCheckpoint {
+    checkpoints.entry(shard).or_insert_with(|| ScanCheckpoint {
+        shard,
+        objects_scanned: 0,
+        last_object_id: String::new(),
+        complete: false,
+    })
+}
+
+// Send on the objects that the job `old_job` found in the shards that it
+// settled, but did not finish with, and return the scan checkpoints of those
+// shards for this job.  As with a retry job the objects are looked up one at
+// a time, rather than all loaded at once.
+fn replay_settled_shards(
+    obj_tx: &crossbeam::Sender<EvacuateObject>,
+    job_action: &EvacuateJob,
+    old_job: &str,
+    settled: &[ScanCheckpoint],
+) -> Result<HashMap<i32, ScanCheckpoint>, Error> {
+    use self::evacuateobjects::dsl::{
+        evacuateobjects, id as obj_id, shard as obj_shard, status,
     };
 
-    debug!("Starting sharkspotter thread: {:?}", &config);
+    let conn = pg_db::connect_db(old_job)?;
+    let shards: Vec<i32> = settled.iter().map(|c| c.shard).collect();
+
+    let ids = evacuateobjects
+        .select(obj_id)
+        .filter(obj_shard.eq_any(shards.clone()))
+        .filter(status.ne(EvacuateObjectStatus::Complete))
+        .load::<String>(&conn)?;
+
+    info!(
+        "Sending on {} objects that job {} did not finish with from {} \
+         settled shards",
+        ids.len(),
+        old_job,
+        shards.len()
+    );
+
+    let mut checkpoints = HashMap::new();
+    for shard in shards.iter() {
+        scan_checkpoint_entry(&mut checkpoints, *shard);
+    }
 
-    let log = slog_scope::logger();
+    for id in ids {
+        if job_action.stopping() {
+            info!("Job is stopping, no longer sending on settled objects");
+            return Ok(checkpoints);
+        }
 
-    let (ss_trans_tx, ss_trans_rx) = crossbeam_channel::bounded(10);
-    thread::Builder::new()
-        .name(String::from("sharkspotter"))
-        .spawn(move || {
-            let ss_trans_handle: JoinHandle<Result<(), Error>> =
-                thread::Builder::new()
-                    .name("sharkspotter_translator".to_string())
-                    .spawn(move || {
-                        while let Ok(ss_msg) = ss_trans_rx.recv() {
-                            let eo: EvacuateObject =
-                                match EvacuateObject::try_from(ss_msg) {
-                                    Ok(o) => o,
-                                    Err(e) => {
-                                        job_action.insert_into_db(&e);
-                                        continue;
-                                    }
-                                };
-
-                            if let Err(e) = obj_tx.send(eo) {
-                                warn!(
-                                    "Could not send evacuate object.  Receive \
-                                     side of channel exited prematurely.  Is \
-                                     max_objects set? {}",
-                                    e
-                                );
+        let obj = evacuateobjects
+            .filter(obj_id.eq(id))
+            .get_result::<EvacuateObject>(&conn)?;
 
-                                break;
-                            }
-                        }
+        let checkpoint = scan_checkpoint_entry(&mut checkpoints, obj.shard);
+        checkpoint.objects_scanned += 1;
+        checkpoint.last_object_id = obj.id.clone();
 
-                        info!("Sharkspotter translator thread exiting");
-                        Ok(())
-                    })
-                    .expect("Start sharkspotter translator thread");
-            sharkspotter::run_multithreaded(&config, log, ss_trans_tx)
-                .map_err(Error::from)?;
+        if obj_tx.send(obj).is_err() {
+            return Ok(checkpoints);
+        }
+    }
 
-            ss_trans_handle
-                .join()
-                .expect("sharkspotter translator join")
-        })
-        .map_err(Error::from)
+    // Only once all of their objects have been sent on are the shards settled
+    // for this job too.
+    for checkpoint in checkpoints.values_mut() {
+        checkpoint.complete = true;
+    }
+
+    Ok(checkpoints)
 }
 
 /// The assignment manager manages the destination sharks and
@@ -2366,8 +6446,10 @@ fn start_sharkspotter(
 
 #[derive(Clone)]
 enum AssignmentMsg {
-    Flush, // send all assignments to the Post thread, but keep running
-    Stop,  // send all assignments to the Post thread, and stop running
+    Flush,   // send all assignments to the Post thread, but keep running
+    Stop,    // send all assignments to the Post thread, and stop running
+    Discard, // drop any unposted assignment, and stop running
+    Drain,   // reroute the objects of any unposted assignment, and stop
     Data(Box<EvacuateObject>), // Add EvacuateObject to active assignment
 }
 
@@ -2438,6 +6520,15 @@ fn _stop_join_drain_assignment_threads(
     _join_drain_shark_assignment_threads(shark_hash);
 }
 
+// Takes ownership of the shark_hash, sends all associated threads the discard
+// command, and eventually drops the shark_hash, reclaiming memory.
+fn _discard_join_drain_assignment_threads(
+    mut shark_hash: HashMap<StorageId, SharkHashEntry>,
+) {
+    _msg_all_assignment_threads(&mut shark_hash, AssignmentMsg::Discard);
+    _join_drain_shark_assignment_threads(shark_hash);
+}
+
 fn _stop_join_some_assignment_threads(
     shark_hash: &mut HashMap<StorageId, SharkHashEntry>,
     shark_id_list: Vec<StorageId>,
@@ -2466,6 +6557,35 @@ fn _stop_join_some_assignment_threads(
     }
 }
 
+// The destinations in `shark_id_list` have been drained, so rather than
+// posting the assignments that their threads have not posted yet, the threads
+// give the objects of those assignments back to be sent elsewhere.
+fn _drain_join_some_assignment_threads(
+    shark_hash: &mut HashMap<StorageId, SharkHashEntry>,
+    shark_id_list: Vec<StorageId>,
+) {
+    for key in shark_id_list.iter() {
+        let ent = match shark_hash.remove(key) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        trace!("Draining {:?} thread", ent.handle.thread().name());
+        if let Err(e) = ent.tx.send(AssignmentMsg::Drain) {
+            error!("Error sending Drain command to {}: {}", key, e);
+        }
+
+        match ent.handle.join() {
+            Ok(Err(e)) => error!(
+                "Shark assignment thread ({}) encountered an error: {}",
+                key, e
+            ),
+            Ok(Ok(())) => (),
+            Err(_) => error!("Error joining shark assignment thread {}", key),
+        }
+    }
+}
+
 fn assignment_manager_impl<S>(
     full_assignment_tx: crossbeam::Sender<Assignment>,
     checker_fini_tx: crossbeam_channel::Sender<FiniMsg>,
@@ -2479,10 +6599,10 @@ where
     move || {
         let mut done = false;
         let mut object_count = 0;
+        let mut object_queue = GaugeShare::new(OBJECT_QUEUE_DEPTH);
+        let mut resident_objects = GaugeShare::new(RESIDENT_OBJECTS);
+        let mut overflow_objects = GaugeShare::new(OVERFLOW_OBJECTS);
         let max_objects = job_action.max_objects;
-        let max_sharks = job_action.config.options.max_sharks;
-        let max_tasks_per_assignment =
-            job_action.config.options.max_tasks_per_assignment;
 
         let algo = mod_storinfo::DefaultChooseAlgorithm {
             min_avail_mb: job_action.min_avail_mb,
@@ -2492,14 +6612,26 @@ where
         let mut shark_hash: HashMap<StorageId, SharkHashEntry> = HashMap::new();
 
         while !done {
+            // These may have been changed since the last time around (see
+            // jobs::tuning).
+            let JobTunables {
+                max_sharks,
+                max_tasks_per_assignment,
+                ..
+            } = job_action.tunables.get();
+
             // TODO: MANTA-4519
             // get a fresh shark list
-            let mut shark_list =
-                job_action.get_shark_list(Arc::clone(&storinfo), &algo, 3)?;
+            let mut shark_list = if job_action.is_remove_copy() {
+                job_action.get_removal_shark_list()
+            } else {
+                job_action.get_shark_list(Arc::clone(&storinfo), &algo, 3)?
+            };
 
             // TODO: file ticket, tunable number of sharks which implies
-            // number of threads.
-            shark_list.truncate(max_sharks);
+            // number of threads.  While the job is ramping up it uses only
+            // some of them.
+            shark_list.truncate(job_action.ramp.limit(max_sharks));
 
             // We've already truncated the list to only include the sharks
             // with the most available_mb.  Shuffling here ensures
@@ -2527,7 +6659,12 @@ where
             }
 
             // Send the stop command which flushes any outstanding
-            // assignments, then join the threads
+            // assignments, then join the threads.  The threads of drained
+            // destinations give their objects back instead.
+            let (drained_keys, remove_keys): (Vec<_>, Vec<_>) = remove_keys
+                .into_iter()
+                .partition(|key| job_action.is_drained(key));
+            _drain_join_some_assignment_threads(&mut shark_hash, drained_keys);
             _stop_join_some_assignment_threads(&mut shark_hash, remove_keys);
 
             // Create and add any shark threads that are in the list but
@@ -2544,12 +6681,12 @@ where
                         "shark_assignment_generator({})",
                         shark.manta_storage_id
                     ))
-                    .spawn(shark_assignment_generator(
+                    .spawn(joblog::inherit(shark_assignment_generator(
                         Arc::clone(&job_action),
                         shark.clone(),
                         rx,
                         full_assignment_tx.clone(),
-                    ))?;
+                    )))?;
 
                 shark_hash.insert(
                     shark.manta_storage_id.clone(),
@@ -2568,6 +6705,12 @@ where
             //      * send object to that shark's thread
             // end loop
             for _ in 0..max_tasks_per_assignment * max_sharks {
+                if job_action.stopping() {
+                    info!("Job is stopping, no more assignments");
+                    done = true;
+                    break;
+                }
+
                 // Get an object
                 if let Some(max) = max_objects {
                     if object_count >= max {
@@ -2594,44 +6737,220 @@ where
                     }
                 }
 
-                let mut eobj = match obj_rx.recv() {
-                    Ok(obj) => {
-                        if object_count == 0 {
-                            *job_action
-                                .object_movement_start_time
-                                .lock()
-                                .unwrap() = Some(std::time::Instant::now());
-                        }
+                // Objects turned down by a full destination go first, so
+                // that they are not left waiting behind the rest of the scan.
+                let mut eobj = if let Some(obj) = job_action.next_rerouted() {
+                    trace!("Rerouting object {}", &obj.id);
+                    obj
+                } else {
+                    match obj_rx.recv() {
+                        Ok(obj) => {
+                            if object_count == 0 {
+                                *job_action
+                                    .object_movement_start_time
+                                    .lock()
+                                    .unwrap() = Some(std::time::Instant::now());
+                            }
 
-                        trace!("Received object {:#?}", &obj);
-                        object_count += 1;
+                            trace!("Received object {:#?}", &obj);
+                            object_count += 1;
+                            object_queue.set(obj_rx.len());
 
-                        obj
-                    }
-                    Err(e) => {
-                        warn!("Didn't receive object. {}\n", e);
-                        info!("Sending last assignments");
-                        done = true;
-                        break;
+                            obj
+                        }
+                        Err(e) => {
+                            // Objects with more copies to make on the shark
+                            // being evacuated are given back once the copy
+                            // that they are in an assignment for is made.
+                            if job_action.source_copies_pending() {
+                                thread::sleep(SOURCE_COPIES_WAIT);
+                                break;
+                            }
+
+                            warn!("Didn't receive object. {}\n", e);
+                            info!("Sending last assignments");
+                            done = true;
+                            break;
+                        }
                     }
                 };
 
+                let (rerouted, spilled) = job_action.rerouted_depth();
+                resident_objects.set(obj_rx.len() + rerouted);
+                overflow_objects.set(spilled);
+
                 // Iterate over the list of sharks and get the first
-                // valid one.
+                // valid one.  Removing a copy takes no space.
                 let mut last_reason = ObjectSkippedReason::AgentBusy;
-                let shark_list_entry: Option<&StorageNode> =
-                    shark_list.iter().find(|shark| {
-                        if let Some(reason) = validate_destination(
-                            &eobj.object,
+                let no_space =
+                    ObjectSkippedReason::DestinationInsufficientSpace;
+                let content_mb = if job_action.is_remove_copy() {
+                    0
+                } else {
+                    object_bytes(&eobj) / (1024 * 1024)
+                };
+
+                // An object with more than one copy on the shark being
+                // evacuated is placed as it will be once the copies that
+                // have been made are in its metadata.  See the
+                // jobs::source_copies module.
+                let from_id = &job_action.from_shark.manta_storage_id;
+                let copies_on_source = if job_action.is_remove_copy()
+                    || job_action.is_create_copy()
+                {
+                    1
+                } else {
+                    source_copies::copies_on_shark(&eobj.object, from_id)
+                };
+                let plan = if copies_on_source > 1 {
+                    job_action
+                        .source_copies
+                        .get(&eobj.id)
+                        .filter(|plan| !plan.copied.is_empty())
+                } else {
+                    None
+                };
+                let planned_object = plan.as_ref().and_then(|plan| {
+                    source_copies::with_copies(
+                        &eobj.object,
+                        from_id,
+                        &plan.copied,
+                    )
+                    .ok()
+                });
+                let object = planned_object.as_ref().unwrap_or(&eobj.object);
+
+                let mut usable = |object: &Value, shark: &StorageNode| {
+                    // Drained, or quarantined, since the list was got.
+                    if job_action.is_drained(&shark.manta_storage_id)
+                        || quarantine::is_quarantined(&shark.manta_storage_id)
+                    {
+                        return false;
+                    }
+
+                    // Too large for all of the space that this shark
+                    // has free, let alone what is left of it.
+                    if content_mb > shark.available_mb {
+                        job_action.trace_placement(
+                            &eobj.id,
+                            Some(shark.manta_storage_id.as_str()),
+                            PlacementDecision::Capacity,
+                            Some(format!(
+                                "content_mb: {}, available_mb: {}",
+                                content_mb, shark.available_mb
+                            )),
+                        );
+                        last_reason = no_space;
+                        return false;
+                    }
+
+                    if job_action.is_shark_full(&shark.manta_storage_id) {
+                        job_action.trace_placement(
+                            &eobj.id,
+                            Some(shark.manta_storage_id.as_str()),
+                            PlacementDecision::Full,
+                            None,
+                        );
+                        last_reason = no_space;
+                        return false;
+                    }
+
+                    // The objects of a remove-copy job were checked for
+                    // enough copies elsewhere as they were found.
+                    let invalid = if job_action.is_remove_copy() {
+                        None
+                    } else if job_action.is_create_copy() {
+                        validate_copy_destination(object, &shark)
+                    } else {
+                        validate_destination(
+                            object,
                             &job_action.from_shark,
                             &shark,
-                        ) {
-                            trace!("shark is not valid because: {}", reason);
-                            last_reason = reason;
-                            return false;
-                        }
-                        true
+                        )
+                    };
+
+                    if let Some(reason) = invalid {
+                        trace!("shark is not valid because: {}", reason);
+                        job_action.count_placement_excluded(&reason);
+                        job_action.trace_placement(
+                            &eobj.id,
+                            Some(shark.manta_storage_id.as_str()),
+                            PlacementDecision::Constraint,
+                            Some(reason.to_string()),
+                        );
+                        last_reason = reason;
+                        return false;
+                    }
+
+                    if job_action.config.options.trace_placement {
+                        let reserved = job_action.projected.unreflected_mb(
+                            &shark.manta_storage_id,
+                            &job_action.db_name,
+                        );
+                        job_action.trace_placement(
+                            &eobj.id,
+                            Some(shark.manta_storage_id.as_str()),
+                            PlacementDecision::Chosen,
+                            Some(format!(
+                                "available_mb: {}, reserved_mb: {}",
+                                shark.available_mb, reserved
+                            )),
+                        );
+                    }
+
+                    true
+                };
+
+                // The destination picked for the next copy of such an
+                // object, if it can still be used, and otherwise any other.
+                let mut shark_list_entry: Option<&StorageNode> = None;
+                if let Some(next) = plan.as_ref().and_then(|p| p.next()) {
+                    shark_list_entry = shark_list.iter().find(|shark| {
+                        shark.manta_storage_id == next.manta_storage_id
+                            && usable(object, shark)
                     });
+                }
+                if shark_list_entry.is_none() {
+                    shark_list_entry =
+                        shark_list.iter().find(|shark| usable(object, shark));
+                }
+
+                // The first time that such an object is placed, the
+                // destinations of its other copies are picked as well, each
+                // of which must be valid with those before it in the
+                // object's metadata.
+                if copies_on_source > 1 && plan.is_none() {
+                    if let Some(first) = shark_list_entry {
+                        let mut destinations = vec![first.clone()];
+                        while destinations.len() < copies_on_source {
+                            let planned = match source_copies::with_copies(
+                                &eobj.object,
+                                from_id,
+                                &destinations,
+                            ) {
+                                Ok(planned) => planned,
+                                Err(_) => break,
+                            };
+                            match shark_list
+                                .iter()
+                                .find(|shark| usable(&planned, shark))
+                            {
+                                Some(shark) => destinations.push(shark.clone()),
+                                None => break,
+                            }
+                        }
+
+                        if destinations.len() < copies_on_source {
+                            shark_list_entry = None;
+                            last_reason =
+                                ObjectSkippedReason::NoSecondDestination;
+                        } else {
+                            job_action
+                                .source_copies
+                                .plan(&eobj.id, destinations);
+                        }
+                    }
+                }
 
                 // Get the associated shark_hash_entry which holds the
                 // send side of the shark_assignment_generator channel.
@@ -2640,7 +6959,22 @@ where
                         .get(&shark.manta_storage_id)
                         .expect("shark not found in hash"),
                     None => {
+                        // An object that is too large for every shark is
+                        // not going to fit on one later either.
+                        if nofit::fits_nowhere(
+                            content_mb,
+                            shark_list.iter().map(|s| s.available_mb),
+                        ) {
+                            last_reason = ObjectSkippedReason::NoFitDestination;
+                        }
+
                         warn!("No sharks available");
+                        job_action.trace_placement(
+                            &eobj.id,
+                            None,
+                            PlacementDecision::NoDestination,
+                            Some(last_reason.to_string()),
+                        );
                         job_action.skip_object(&mut eobj, last_reason);
                         continue;
                     }
@@ -2687,9 +7021,25 @@ where
         }
 
         // Flush all the threads first so that while we are joining they
-        // are all flushing.
-        info!("Shutting down all assignment threads");
-        _stop_join_drain_assignment_threads(shark_hash);
+        // are all flushing.  If the manager is shutting down there is not
+        // time to post and wait for new assignments, and a paused job should
+        // not post any more, so any that have not been posted are dropped
+        // instead.  None of their objects have been recorded yet, so a later
+        // job will find them again.
+        if job_action.stopping() {
+            info!("Discarding unposted assignments");
+            if shutdown::requested() {
+                job_action.interrupted.store(true, Ordering::SeqCst);
+            }
+            _discard_join_drain_assignment_threads(shark_hash);
+        } else {
+            info!("Shutting down all assignment threads");
+            _stop_join_drain_assignment_threads(shark_hash);
+        }
+
+        // There is no one left to send rerouted objects to another
+        // destination.
+        job_action.close_rerouted();
 
         info!("Manager: Shutting down assignment checker");
         checker_fini_tx.send(FiniMsg).expect("Fini Msg");
@@ -2707,16 +7057,20 @@ fn start_assignment_manager<S>(
 where
     S: SharkSource + 'static,
 {
-    thread::Builder::new()
-        .name(String::from("assignment_manager"))
-        .spawn(assignment_manager_impl(
+    let job_id = job_action.db_name.clone();
+    let manager_job = Arc::clone(&job_action);
+    spawn_supervised(
+        &job_id,
+        "assignment_manager",
+        move || manager_job.rerouted_closed(),
+        assignment_manager_impl(
             full_assignment_tx,
             checker_fini_tx,
             obj_rx,
             job_action,
             storinfo,
-        ))
-        .map_err(Error::from)
+        ),
+    )
 }
 
 // Insert the assignment into the assignment cache then send it to the post
@@ -2732,6 +7086,8 @@ fn _channel_send_assignment(
         // it to be posted and to check for it later on.
         let assignment_uuid = assignment.id.clone();
         let assignment_size = assignment.total_size;
+        let assignment_bytes = assignment.total_bytes;
+        let task_count = assignment.tasks.len();
         let dest_shark = assignment.dest_shark.manta_storage_id.clone();
 
         job_action
@@ -2759,16 +7115,32 @@ fn _channel_send_assignment(
         // available_mb for this shark.  Note that we also call
         // mark_dest_shark_ready() on a channel send failure.
         job_action.mark_dest_shark_assigned(&dest_shark, assignment_size);
+        job_action.events.publish(EvacuateEvent::AssignmentSent {
+            dest_shark: dest_shark.clone(),
+            tasks: task_count,
+            bytes: assignment_bytes,
+        });
 
         full_assignment_tx.send(assignment).map_err(|e| {
             error!("Error sending assignment to be posted: {}", e);
 
+            job_action.events.publish(EvacuateEvent::AssignmentFailed {
+                dest_shark: dest_shark.clone(),
+                tasks: task_count,
+                bytes: assignment_bytes,
+                sizes: vec![],
+            });
+
             job_action.mark_assignment_error(
                 &assignment_uuid,
                 EvacuateObjectError::InternalError,
             );
 
-            job_action.mark_dest_shark_ready(&dest_shark, assignment_size);
+            job_action.mark_dest_shark_ready(
+                &dest_shark,
+                assignment_size,
+                false,
+            );
 
             InternalError::new(
                 Some(InternalErrorCode::Crossbeam),
@@ -2781,10 +7153,12 @@ fn _channel_send_assignment(
 }
 
 enum AssignmentAddObjectError {
+    BadChecksum,
     BadMantaObject,
     DestinationInsufficentSpace,
     SouceIsEvacShark,
     DuplicateObject,
+    AgentUnsupported,
 }
 
 // return True of False if we should flush the assignment or not
@@ -2815,13 +7189,52 @@ fn add_object_to_assignment(
             }
         };
 
+    // The agent checks each copy against the checksum in the object's
+    // metadata, so an object without a usable one is dealt with here, as the
+    // job's checksum policy says.  A remove-copy job leaves it to the agent,
+    // which will not remove a copy that it can not tell is of the generation
+    // that the metadata describes.
+    let unchecked = match checksum::check(&manta_object.content_md5) {
+        Some(problem) if !job_action.is_remove_copy() => {
+            if !job_action.apply_checksum_policy(
+                &mut eobj,
+                &manta_object.content_md5,
+                problem,
+            ) {
+                return Err(AssignmentAddObjectError::BadChecksum);
+            }
+            true
+        }
+        _ => false,
+    };
+
+    // Always prefer a copy of the object on some other shark.  In slow source
+    // mode an object whose only copy is on the shark being evacuated is read
+    // from that shark instead of being skipped, and the assignment generator
+    // limits how many of those reads go into each assignment.
     let source = manta_object
         .sharks
         .iter()
         .find(|s| s.manta_storage_id != from_shark_host);
 
-    let source = match source {
-        Some(src) => src,
+    // A create-copy job leaves the copy on its shark in place, so that copy
+    // is as good a source as any other.  The agent on the shark that a
+    // remove-copy job removes copies from deletes its own copy, which reads
+    // from no source (the task's source is the shark itself) and takes no
+    // space.
+    let (source, from_evac_shark) = match source {
+        _ if job_action.is_remove_copy() => (&job_action.from_shark, false),
+        Some(src) => (src, false),
+        None if job_action.is_create_copy()
+            && !manta_object.sharks.is_empty() =>
+        {
+            (&manta_object.sharks[0], false)
+        }
+        None if job_action.config.options.slow_source
+            && !manta_object.sharks.is_empty() =>
+        {
+            (&manta_object.sharks[0], true)
+        }
         None => {
             // The only shark we could find was the one that
             // is being evacuated.
@@ -2832,10 +7245,43 @@ fn add_object_to_assignment(
         }
     };
 
+    // The agent only deletes a copy that is still of the generation that the
+    // metadata describes.
+    let (action, generation) = if job_action.is_remove_copy() {
+        let generation = common::object_generation(
+            &manta_object.content_md5,
+            manta_object.content_length,
+        );
+        (TaskAction::Delete, Some(generation))
+    } else {
+        (TaskAction::Copy, None)
+    };
+
+    // An agent that has registered is only sent the tasks that it said it
+    // supports.
+    if !registry::supports(&shark.manta_storage_id, action) {
+        job_action
+            .skip_object(&mut eobj, ObjectSkippedReason::AgentUnsupported);
+        return Err(AssignmentAddObjectError::AgentUnsupported);
+    }
+
     // Make sure there is enough space for this object on the
     // shark.
-    let content_mb = manta_object.content_length / (1024 * 1024);
+    let content_mb = if action.is_copy() {
+        manta_object.content_length / (1024 * 1024)
+    } else {
+        0
+    };
     if content_mb > *available_space {
+        job_action.trace_placement(
+            &eobj.id,
+            Some(shark.manta_storage_id.as_str()),
+            PlacementDecision::Capacity,
+            Some(format!(
+                "content_mb: {}, available_mb: {}",
+                content_mb, available_space
+            )),
+        );
         job_action.skip_object(
             &mut eobj,
             ObjectSkippedReason::DestinationInsufficientSpace,
@@ -2854,6 +7300,12 @@ fn add_object_to_assignment(
                 md5sum: manta_object.content_md5.to_owned(),
                 source: source.to_owned(),
                 status: TaskStatus::Pending,
+                content_length: Some(manta_object.content_length),
+                download: None,
+                autopsy: None,
+                action,
+                generation,
+                unchecked,
             },
         )
         .is_some()
@@ -2887,6 +7339,13 @@ fn add_object_to_assignment(
 
         job_action.insert_duplicate_object(duplicate, eobj.shard);
 
+        // The entry already in the assignment is not at hand, but it can
+        // only be this one again if it was found in the same shard.
+        {
+            let locked_conn = job_action.conn.lock().expect("DB conn lock");
+            job_action.record_snaplink(&eobj, &*locked_conn);
+        }
+
         return Err(AssignmentAddObjectError::DuplicateObject);
     }
 
@@ -2895,6 +7354,20 @@ fn add_object_to_assignment(
     *available_space -= content_mb;
     assignment.total_size += content_mb;
 
+    // Nothing is transferred for a delete task.
+    if action.is_copy() {
+        assignment.total_bytes += manta_object.content_length;
+
+        if from_evac_shark {
+            assignment.evac_shark_reads += 1;
+            job_action.evac_shark_sourced.fetch_add(1, Ordering::SeqCst);
+            metrics_source_inc(SOURCE_EVAC_SHARK);
+        } else {
+            job_action.replica_sourced.fetch_add(1, Ordering::SeqCst);
+            metrics_source_inc(SOURCE_REPLICA);
+        }
+    }
+
     trace!(
         "{}: Available space: {} | Tasks: {}",
         assignment.id,
@@ -2946,6 +7419,18 @@ fn flush_assignment(
     Ok(available_space)
 }
 
+// The number of tasks to put in the next assignment for `shark`: that of the
+// job's assignment sizing, but no more than its agent takes, if it has said.
+fn assignment_max_tasks(job_action: &EvacuateJob, shark: &str) -> usize {
+    registry::max_tasks(
+        shark,
+        job_action.sizing.max_tasks(
+            shark,
+            job_action.tunables.get().max_tasks_per_assignment,
+        ),
+    )
+}
+
 fn shark_assignment_generator(
     job_action: Arc<EvacuateJob>,
     shark: StorageNode, // TODO: reference?
@@ -2953,8 +7438,11 @@ fn shark_assignment_generator(
     full_assignment_tx: crossbeam::Sender<Assignment>,
 ) -> impl Fn() -> Result<(), Error> {
     move || {
-        let max_tasks = job_action.config.options.max_tasks_per_assignment;
+        let mut max_tasks =
+            assignment_max_tasks(&job_action, &shark.manta_storage_id);
         let max_age = job_action.config.options.max_assignment_age;
+        let max_evac_shark_reads =
+            std::cmp::max(job_action.config.options.slow_source_max_reads, 1);
         let from_shark_host = job_action.from_shark.manta_storage_id.clone();
         let mut stop = false;
         let mut flush = false;
@@ -3011,6 +7499,21 @@ fn shark_assignment_generator(
                     debug!("Received Stop");
                     stop = true;
                 }
+                AssignmentMsg::Discard => {
+                    debug!(
+                        "Received Discard, dropping {} unposted tasks",
+                        assignment.tasks.len()
+                    );
+                    return Ok(());
+                }
+                AssignmentMsg::Drain => {
+                    debug!(
+                        "Received Drain, rerouting {} unposted tasks",
+                        eobj_vec.len()
+                    );
+                    job_action.reroute_unposted(eobj_vec);
+                    return Ok(());
+                }
                 AssignmentMsg::Flush => {
                     let assignment_len = assignment.tasks.len();
                     if assignment_len > 0
@@ -3036,6 +7539,8 @@ fn shark_assignment_generator(
                         Ok(eobj) => eobj_vec.push(eobj),
                         Err(e) => match e {
                             AssignmentAddObjectError::DuplicateObject |
+                            AssignmentAddObjectError::AgentUnsupported |
+                            AssignmentAddObjectError::BadChecksum |
                             AssignmentAddObjectError::BadMantaObject |
                             AssignmentAddObjectError::SouceIsEvacShark => {
                                 // We either skipped or errored on an object,
@@ -3059,6 +7564,10 @@ fn shark_assignment_generator(
                                     &shark,
                                     &full_assignment_tx,
                                 )?;
+                                max_tasks = assignment_max_tasks(
+                                    &job_action,
+                                    &shark.manta_storage_id,
+                                );
 
                                 continue;
                             }
@@ -3079,9 +7588,13 @@ fn shark_assignment_generator(
             //      * We were told to flush or stop
             //        OR
             //      * We have reached the maximum number of tasks per assignment
+            //        OR
+            //      * We have reached the maximum number of tasks that read
+            //        from the shark being evacuated (slow source mode only)
             if !assignment.tasks.is_empty() && flush
                 || stop
                 || assignment.tasks.len() >= max_tasks
+                || assignment.evac_shark_reads >= max_evac_shark_reads
             {
                 flush = false;
 
@@ -3092,6 +7605,12 @@ fn shark_assignment_generator(
                     &shark,
                     &full_assignment_tx,
                 )?;
+
+                // The agent may have reported on earlier assignments since
+                // this one was started, and the job's assignment size may
+                // have been changed.
+                max_tasks =
+                    assignment_max_tasks(&job_action, &shark.manta_storage_id);
             }
         } // End while !stop (get next message/object)
 
@@ -3129,8 +7648,10 @@ fn validate_destination(
         .iter()
         .any(|s| s.manta_storage_id == dest_shark.manta_storage_id);
 
-    // We've found the object on the destination shark.  We will need to skip
-    // this object for now and find a destination for it later.  If we don't do
+    // We've found the object on the destination shark.  This applies to every
+    // shark that holds a copy of the object, not only the one being
+    // evacuated.  We will need to skip this object for now and find a
+    // destination for it later.  If we don't do
     // this check it would reduce the durability level of the object.  That is,
     // it would reduce the number of copies of the object in the region by one.
     if obj_on_dest {
@@ -3152,6 +7673,31 @@ fn validate_destination(
     None
 }
 
+// A copy that is added, rather than moved, can not reduce the number of data
+// centers that an object is in, so the only destinations ruled out are those
+// that already hold a copy.
+fn validate_copy_destination(
+    mobj_value: &Value,
+    dest_shark: &StorageNode,
+) -> Option<ObjectSkippedReason> {
+    match common::get_sharks_from_value(mobj_value) {
+        Ok(sharks) => {
+            if sharks
+                .iter()
+                .any(|s| s.manta_storage_id == dest_shark.manta_storage_id)
+            {
+                Some(ObjectSkippedReason::ObjectAlreadyOnDestShark)
+            } else {
+                None
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            Some(ObjectSkippedReason::SourceOtherError)
+        }
+    }
+}
+
 fn assignment_post<T>(
     assign_rx: crossbeam::Receiver<Assignment>,
     job_action: Arc<T>,
@@ -3193,10 +7739,56 @@ fn start_assignment_post(
     full_assignment_rx: crossbeam::Receiver<Assignment>,
     job_action: Arc<EvacuateJob>,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
-    thread::Builder::new()
-        .name(String::from("assignment_poster"))
-        .spawn(move || assignment_post(full_assignment_rx, job_action))
-        .map_err(Error::from)
+    let job_id = job_action.db_name.clone();
+    let finished = watchdog::drained(&full_assignment_rx);
+    spawn_supervised(&job_id, "assignment_poster", finished, move || {
+        assignment_post(full_assignment_rx, job_action)
+    })
+}
+
+// How often the update listener checks whether the job is still taking
+// updates, when it has none to apply.
+static UPDATE_LISTENER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Apply the changes made to the job with PUT /jobs/<uuid> until the job no
+/// longer takes them, replying to each with whether it was applied.  The
+/// job's threads pick up the new values as they go (see jobs::tuning).
+fn start_update_listener(
+    job_action: Arc<EvacuateJob>,
+) -> Result<Option<thread::JoinHandle<Result<(), Error>>>, Error> {
+    let update_rx = match &job_action.update_rx {
+        Some(urx) => urx.clone(),
+        None => return Ok(None),
+    };
+
+    let job_id = job_action.db_name.clone();
+    let listener_job = Arc::clone(&job_action);
+    let drained = watchdog::drained(&update_rx);
+    let finished = move || {
+        !listener_job.accepting_updates.load(Ordering::SeqCst) || drained()
+    };
+    spawn_supervised(&job_id, "update_listener", finished, move || {
+        while job_action.accepting_updates.load(Ordering::SeqCst) {
+            let request = match update_rx.recv_timeout(UPDATE_LISTENER_INTERVAL)
+            {
+                Ok(r) => r,
+                Err(crossbeam::RecvTimeoutError::Timeout) => continue,
+                Err(crossbeam::RecvTimeoutError::Disconnected) => break,
+            };
+
+            debug!("Received job update message: {:#?}", request.message);
+            let JobUpdateMessage::Evacuate(msg) = request.message;
+            let result = job_action.apply_update(msg);
+
+            // Whoever sent the update may have given up waiting.
+            if request.reply.send(result).is_err() {
+                warn!("Could not reply to job update");
+            }
+        }
+
+        Ok(())
+    })
+    .map(Some)
 }
 
 /// Structures implementing this trait are able to post assignments to an agent.
@@ -3271,149 +7863,179 @@ fn _checker_should_run(checker_fini_rx: &crossbeam::Receiver<FiniMsg>) -> bool {
 /// 3. Finally the assignment is sent to the metadata update broker which will
 /// handle updating the metadata of every object in the assignment in the
 /// Manta Metadata tier.
+///
+/// Everything the checker needs is in the assignment cache and the local DB,
+/// so it is restarted if it panics.  An assignment that it was processing at
+/// the time is checked again if it is still in the Assigned state.
 fn start_assignment_checker(
     job_action: Arc<EvacuateJob>,
     checker_fini_rx: crossbeam::Receiver<FiniMsg>,
     md_update_tx: crossbeam::Sender<AssignmentCacheEntry>,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
-    thread::Builder::new()
-        .name(String::from("Assignment Checker"))
-        .spawn(move || {
-            let mut run = true;
-            loop {
-                let mut found_assignment_count = 0;
-                if run {
-                    run = _checker_should_run(&checker_fini_rx);
-                }
+    let job_id = job_action.db_name.clone();
+    spawn_restartable(&job_id, "Assignment Checker", move || {
+        let mut run = true;
+        let mut outstanding = GaugeShare::new(ASSIGNMENTS_OUTSTANDING);
+        loop {
+            let mut found_assignment_count = 0;
+            if run {
+                run = _checker_should_run(&checker_fini_rx);
+            }
 
-                // We'd rather not hold the assignment hash lock here while we
-                // run through all the HTTP GETs and while each completed
-                // assignment is processed.  Furthermore, there's really no need
-                // to hold the read lock here.  If another thread is created at
-                // some point to query other assignments then we simply make an
-                // extra HTTP GET call, perhaps get an already reported
-                // assignment back, and when we take the write lock later we
-                // will realize that this assignment is already in the
-                // PostProcess state and skip it.  Under the current
-                // implementation that will never happen, and if it does happen
-                // in the future, no harm, no foul.  One alternative would be
-                // to take the write lock for the duration of this loop and
-                // process all the assignments right here, but the number of
-                // assignments could grow significantly and that approach
-                // would have a significantly negative impact on performance.
-                let assignments = job_action
-                    .assignments
-                    .read()
-                    .expect("assignments read lock")
-                    .clone();
-
-                debug!("Checking Assignments");
-
-                let outstanding_assignments = || {
-                    assignments.values().any(|ace| {
-                        ace.state == AssignmentState::Assigned
-                            || ace.state == AssignmentState::Init
-                    })
-                };
+            // We'd rather not hold the assignment hash lock here while we
+            // run through all the HTTP GETs and while each completed
+            // assignment is processed.  Furthermore, there's really no need
+            // to hold the read lock here.  If another thread is created at
+            // some point to query other assignments then we simply make an
+            // extra HTTP GET call, perhaps get an already reported
+            // assignment back, and when we take the write lock later we
+            // will realize that this assignment is already in the
+            // PostProcess state and skip it.  Under the current
+            // implementation that will never happen, and if it does happen
+            // in the future, no harm, no foul.  One alternative would be
+            // to take the write lock for the duration of this loop and
+            // process all the assignments right here, but the number of
+            // assignments could grow significantly and that approach
+            // would have a significantly negative impact on performance.
+            let assignments = job_action
+                .assignments
+                .read()
+                .expect("assignments read lock")
+                .clone();
 
-                if !run && !outstanding_assignments() {
-                    info!(
-                        "Assignment Checker: Shutdown received and there \
+            debug!("Checking Assignments");
+
+            let outstanding_assignments = || {
+                assignments.values().any(|ace| {
+                    ace.state == AssignmentState::Assigned
+                        || ace.state == AssignmentState::Init
+                })
+            };
+
+            outstanding.set(
+                assignments
+                    .values()
+                    .filter(|ace| ace.state == AssignmentState::Assigned)
+                    .count(),
+            );
+
+            if !run && !outstanding_assignments() {
+                info!(
+                    "Assignment Checker: Shutdown received and there \
                          are no remaining assignments in the assigned state to \
                          check on.  Exiting."
-                    );
-                    break;
-                }
+                );
+                break;
+            }
 
-                for ace in assignments.values() {
-                    if ace.state != AssignmentState::Assigned {
-                        trace!("Skipping unassigned assignment {:?}", ace);
-                        continue;
-                    }
+            for ace in assignments.values() {
+                if ace.state != AssignmentState::Assigned {
+                    trace!("Skipping unassigned assignment {:?}", ace);
+                    continue;
+                }
 
-                    debug!(
-                        "Assignment Checker, checking: {} | {:?}",
-                        ace.id, ace.state
-                    );
+                if !job_action.polling.is_due(
+                    &ace.id,
+                    &ace.dest_shark.manta_storage_id,
+                    ace.task_count,
+                ) {
+                    trace!("Assignment {} is not due to be polled", ace.id);
+                    continue;
+                }
 
-                    // TODO: Async/await candidate
-                    let ag_assignment =
-                        match assignment_get(Arc::clone(&job_action), &ace) {
-                            Ok(a) => a,
-                            Err(e) => {
-                                // If necessary the assignment and its associated
-                                // objects are marked as skipped in the get()
-                                // method.
-                                error!("Could not get assignment: {}", e);
-                                continue;
-                            }
-                        };
+                debug!(
+                    "Assignment Checker, checking: {} | {:?}",
+                    ace.id, ace.state
+                );
 
-                    debug!(
-                        "Got Assignment: {} {:?}",
-                        ag_assignment.uuid, ag_assignment.stats
-                    );
-                    // If agent assignment is complete, process it and pass
-                    // it to the metadata update broker.  Otherwise, continue
-                    // to next assignment.
-                    match ag_assignment.stats.state {
-                        AgentAssignmentState::Complete(_) => {
-                            // We don't want to shut this thread down simply
-                            // because we have issues handling one assignment.
-                            // The process() function should mark the
-                            // associated objects appropriately.
-                            debug!(
-                                "Processing Assignment: {:?}",
-                                ag_assignment
-                            );
-                            job_action.process(ag_assignment).unwrap_or_else(
-                                |e| {
-                                    error!("Error Processing Assignment {}", e);
-                                },
-                            );
+                // TODO: Async/await candidate
+                let ag_assignment =
+                    match assignment_get(Arc::clone(&job_action), &ace) {
+                        Ok(a) => a,
+                        Err(e) => {
+                            // If necessary the assignment and its associated
+                            // objects are marked as skipped in the get()
+                            // method.
+                            error!("Could not get assignment: {}", e);
+                            metrics_poll_inc(POLL_FAILED);
+                            job_action.polling.forget(&ace.id);
+                            continue;
                         }
-                        _ => continue,
+                    };
+
+                debug!(
+                    "Got Assignment: {} {:?}",
+                    ag_assignment.uuid, ag_assignment.stats
+                );
+                // If agent assignment is complete, process it and pass
+                // it to the metadata update broker.  Otherwise, continue
+                // to next assignment.
+                match ag_assignment.stats.state {
+                    AgentAssignmentState::Complete(_) => {
+                        metrics_poll_inc(POLL_COMPLETE);
+                        job_action.polling.completed(
+                            &ace.id,
+                            &ace.dest_shark.manta_storage_id,
+                            ag_assignment.stats.total,
+                        );
+
+                        // We don't want to shut this thread down simply
+                        // because we have issues handling one assignment.
+                        // The process() function should mark the
+                        // associated objects appropriately.
+                        debug!("Processing Assignment: {:?}", ag_assignment);
+                        job_action.process(ag_assignment).unwrap_or_else(|e| {
+                            error!("Error Processing Assignment {}", e);
+                        });
+                    }
+                    _ => {
+                        metrics_poll_inc(POLL_NOT_READY);
+                        job_action
+                            .polling
+                            .not_ready(&ace.id, &ag_assignment.stats);
+                        continue;
                     }
+                }
 
-                    found_assignment_count += 1;
+                found_assignment_count += 1;
 
-                    match md_update_tx.send(ace.to_owned()) {
-                        Ok(()) => (),
-                        Err(e) => {
-                            job_action.mark_assignment_error(
-                                &ace.id,
-                                EvacuateObjectError::InternalError,
-                            );
-                            error!(
-                                "Assignment Checker: Error sending \
+                match md_update_tx.send(ace.to_owned()) {
+                    Ok(()) => metrics_gauge_inc(MD_UPDATE_QUEUE_DEPTH),
+                    Err(e) => {
+                        job_action.mark_assignment_error(
+                            &ace.id,
+                            EvacuateObjectError::InternalError,
+                        );
+                        error!(
+                            "Assignment Checker: Error sending \
                                  assignment to the metadata \
                                  broker {}",
-                                e
-                            );
-                            return Err(InternalError::new(
-                                Some(InternalErrorCode::Crossbeam),
-                                CrossbeamError::from(e).description(),
-                            )
-                            .into());
-                        }
+                            e
+                        );
+                        return Err(InternalError::new(
+                            Some(InternalErrorCode::Crossbeam),
+                            CrossbeamError::from(e).description(),
+                        )
+                        .into());
                     }
                 }
+            }
 
-                // TODO: MANTA-5106
-                if found_assignment_count == 0 {
-                    trace!("Found 0 completed assignments, sleeping for 500ms");
-                    thread::sleep(Duration::from_millis(500));
-                    continue;
-                }
-
+            // TODO: MANTA-5106
+            if found_assignment_count == 0 {
+                let interval = job_action.polling.min_interval();
                 trace!(
-                    "Found {} completed assignments",
-                    found_assignment_count
+                    "Found 0 completed assignments, sleeping for {:?}",
+                    interval
                 );
+                thread::sleep(interval);
+                continue;
             }
-            Ok(())
-        })
-        .map_err(Error::from)
+
+            trace!("Found {} completed assignments", found_assignment_count);
+        }
+        Ok(())
+    })
 }
 
 enum UpdateWorkerMsg {
@@ -3467,7 +8089,11 @@ fn metadata_update_worker_static(
 
             let id = ace.id.clone();
             trace!("Assignment Metadata Update Start: {}", id);
-            metadata_update_assignment(&job_action, ace, &mut client_hash);
+            metadata_update_assignment_supervised(
+                &job_action,
+                ace,
+                &mut client_hash,
+            );
             trace!("Assignment Metadata Update Complete: {}", id);
         }
     }
@@ -3514,7 +8140,11 @@ fn metadata_update_worker_dynamic(
                 Steal::Retry => continue,
                 Steal::Empty => break,
             };
-            metadata_update_assignment(&job_action, ace, &mut client_hash);
+            metadata_update_assignment_supervised(
+                &job_action,
+                ace,
+                &mut client_hash,
+            );
         }
 
         debug!("Exiting metadata update worker.");
@@ -3541,6 +8171,7 @@ fn get_client_from_hash<'a>(
             ) {
                 Ok(client) => client,
                 Err(e) => {
+                    metrics_moray_shard_error_inc(shard, MORAY_OP_WRITE);
                     let msg = format!(
                         "Failed to get Moray Client for shard {}: {}",
                         shard, e
@@ -3557,14 +8188,142 @@ fn get_client_from_hash<'a>(
     }
 }
 
+// The metadata that we update objects with is what sharkspotter found at the
+// time the object was discovered, and the put is conditional on the object's
+// etag being unchanged since.  This saves re-reading every object before it
+// is updated, at the cost of an occasional conflict if the object has been
+// modified in the meantime (e.g. its headers were updated).  When that
+// happens, re-read the object and, provided that the change still applies to
+// it, rebase the change onto the fresh copy and try again with its current
+// etag.  Something that is modifying the object often enough can conflict
+// with the retry as well, so this is done up to
+// options.max_etag_conflict_retries times before the object is given up on.
+fn metadata_update_on_conflict(
+    job_action: &Arc<EvacuateJob>,
+    mclient: &mut MorayClient,
+    object: &EvacuateObjectValue,
+    dest_shark: &StorageNode,
+) -> Result<(), Error> {
+    let key = common::get_key_from_object_value(object)?;
+    let id = common::get_objectId_from_value(object)?;
+    let retries = job_action.config.options.max_etag_conflict_retries;
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let (fresh, etag) = moray_client::get_object(mclient, &key)?;
+        let old_sharks = object_sharks(&fresh);
+        let updated =
+            match rebase_metadata_update(job_action, &id, fresh, dest_shark) {
+                Ok(Some(updated)) => updated,
+                Ok(None) => {
+                    metrics_md_conflict_inc(MD_CONFLICT_ALREADY_APPLIED);
+                    return Ok(());
+                }
+                Err(e) => {
+                    metrics_md_conflict_inc(MD_CONFLICT_OBSOLETE);
+                    return Err(e);
+                }
+            };
+
+        match moray_client::put_object(mclient, &updated, &etag) {
+            Ok(()) => {
+                job_action.audit_put(&old_sharks, &updated, &etag);
+                metrics_md_conflict_inc(MD_CONFLICT_REBASED);
+                return Ok(());
+            }
+            Err(e)
+                if moray_client::is_etag_conflict(&e) && attempt < retries =>
+            {
+                info!(
+                    "Etag conflict updating object {} again ({} of {} \
+                     retries), re-reading: {}",
+                    id, attempt, retries, e
+                );
+            }
+            Err(e) => {
+                if moray_client::is_etag_conflict(&e) {
+                    warn!(
+                        "Giving up on object {} after {} etag conflicts",
+                        id,
+                        attempt + 1
+                    );
+                    metrics_md_conflict_inc(MD_CONFLICT_GAVE_UP);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+// Apply the change that a job planned for object `id` to `fresh`, as the
+// object has been re-read since the plan was made.  Returns None if there is
+// nothing left to change, and an error if the change no longer applies.
+fn rebase_metadata_update(
+    job_action: &EvacuateJob,
+    id: &str,
+    fresh: Value,
+    dest_shark: &StorageNode,
+) -> Result<Option<Value>, Error> {
+    // If the key now refers to a different object then the data we copied is
+    // no longer what the key points to.
+    if common::get_objectId_from_value(&fresh)? != id {
+        return Err(InternalError::new(
+            Some(InternalErrorCode::MetadataUpdateFailure),
+            format!("Object {} was overwritten during evacuation", id),
+        )
+        .into());
+    }
+
+    let sharks = common::get_sharks_from_value(&fresh)?;
+    let on_shark = |storage_id: &str| {
+        sharks.iter().any(|s| s.manta_storage_id == storage_id)
+    };
+
+    // Nor is there anything left to do for a create-copy job once the object
+    // is on the destination, or for a remove-copy job once it is not on the
+    // shark that copies are being removed from.
+    if job_action.is_create_copy() && on_shark(&dest_shark.manta_storage_id) {
+        return Ok(None);
+    }
+
+    if job_action.is_remove_copy()
+        && !on_shark(&job_action.from_shark.manta_storage_id)
+    {
+        return Ok(None);
+    }
+
+    if !on_shark(&job_action.from_shark.manta_storage_id) {
+        // An earlier attempt may have succeeded without our knowing it.
+        if on_shark(&dest_shark.manta_storage_id) {
+            return Ok(None);
+        }
+
+        return Err(InternalError::new(
+            Some(InternalErrorCode::SharkNotFound),
+            format!(
+                "Object {} is no longer on {}",
+                id, job_action.from_shark.manta_storage_id
+            ),
+        )
+        .into());
+    }
+
+    job_action.update_object_shark(fresh, dest_shark).map(Some)
+}
+
 // Called when we are not using batched updates or a batched update fails and
-// we want to update each object one by one.
+// we want to update each object one by one.  `old_sharks` are the sharks
+// that the object had before `object` was updated from it.
 fn metadata_update_one(
     job_action: &Arc<EvacuateJob>,
     client: MetadataClientOption,
     object: &EvacuateObjectValue,
+    old_sharks: &Value,
     etag: &str,
     shard: u32,
+    dest_shark: &StorageNode,
 ) -> Result<(), Error> {
     let mclient = match client {
         MetadataClientOption::Client(c) => c,
@@ -3574,14 +8333,34 @@ fn metadata_update_one(
     };
 
     let now = std::time::Instant::now();
-    let ret = moray_client::put_object(mclient, object, etag)
-        .map_err(|e| {
-            InternalError::new(
-                Some(InternalErrorCode::MetadataUpdateFailure),
-                e.description(),
-            )
-        })
-        .map_err(Error::from);
+    let ret = match moray_client::put_object(mclient, object, etag) {
+        Ok(()) => {
+            job_action.audit_put(old_sharks, object, etag);
+            Ok(())
+        }
+        Err(e)
+            if moray_client::is_etag_conflict(&e)
+                && job_action.config.options.max_etag_conflict_retries > 0 =>
+        {
+            info!("Etag conflict updating object, re-reading: {}", e);
+            metadata_update_on_conflict(job_action, mclient, object, dest_shark)
+        }
+        r => r,
+    }
+    .map_err(|e| {
+        InternalError::new(
+            Some(InternalErrorCode::MetadataUpdateFailure),
+            e.description(),
+        )
+    })
+    .map_err(Error::from);
+
+    metrics_moray_shard_observe(
+        shard,
+        MORAY_OP_WRITE,
+        now.elapsed().as_secs_f64(),
+        ret.is_err(),
+    );
 
     if ret.is_err() {
         error!(
@@ -3601,19 +8380,29 @@ fn metadata_update_one(
 // two calls to moray to update all the objects for this assignment in their
 // respective shards.
 //
-// If a batch fails this function falls back to updating each object
-// individually for that batch.
+// Each shard's objects are sent md_update_batch_size at a time.  If a batch
+// fails this function falls back to updating each object individually for
+// that batch.
+//
+// `old_sharks` holds the sharks that each object had before it was updated,
+// by object id.
 fn metadata_update_batch(
     job_action: &Arc<EvacuateJob>,
     client_hash: &mut HashMap<u32, MorayClient>,
     batched_reqs: HashMap<u32, Vec<BatchRequest>>,
+    old_sharks: &HashMap<ObjectId, Value>,
+    dest_shark: &StorageNode,
 ) -> Vec<ObjectId> {
     let mut marked_error = vec![];
-    for (shard, requests) in batched_reqs.into_iter() {
-        let num_reqs = requests.len();
+    let batch_size =
+        std::cmp::max(1, job_action.config.options.md_update_batch_size);
+
+    for (shard, mut requests) in batched_reqs.into_iter() {
         info!(
-            "Updating {} objects for shard {} in a single batch",
-            num_reqs, shard
+            "Updating {} objects for shard {} in batches of up to {}",
+            requests.len(),
+            shard,
+            batch_size
         );
         let mclient = match get_client_from_hash(job_action, client_hash, shard)
         {
@@ -3640,43 +8429,97 @@ fn metadata_update_batch(
             }
         };
 
-        // If we fail the batch, step through the objects and attempt to
-        // update each one individually. For each object that fails to
-        // update mark it as error, and add it to the marked_error Vec to
-        // be trimmed from our list of successful updates later.
-        let now = std::time::Instant::now();
-        if let Err(e) =
-            mclient.batch(&requests, &ObjectMethodOptions::default(), |_| {
-                // elapsed() gives us a u128, but unfortunately AtomicU128 is
-                // nightly only.
-                let md_update_time = now.elapsed().as_micros();
+        while !requests.is_empty() {
+            let rest = requests.split_off(batch_size.min(requests.len()));
+            let batch = std::mem::replace(&mut requests, rest);
+            let num_reqs = batch.len();
 
-                info!(
-                    "Batch updated {} objects in {}us",
-                    num_reqs, md_update_time
-                );
-                Ok(())
-            })
-        {
-            error!("Batch update failed, retrying individually: {}", e);
-            retry_batch_update(
-                job_action,
-                requests,
+            // If we fail the batch, step through the objects and attempt to
+            // update each one individually. For each object that fails to
+            // update mark it as error, and add it to the marked_error Vec to
+            // be trimmed from our list of successful updates later.
+            let now = std::time::Instant::now();
+            let ret = if faults::failed(FaultPoint::MorayUpdate) {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Moray error injected by a fault",
+                ))
+            } else {
+                mclient.batch(&batch, &ObjectMethodOptions::default(), |_| {
+                    // elapsed() gives us a u128, but unfortunately AtomicU128
+                    // is nightly only.
+                    let md_update_time = now.elapsed().as_micros();
+
+                    info!(
+                        "Batch updated {} objects in {}us",
+                        num_reqs, md_update_time
+                    );
+                    Ok(())
+                })
+            };
+
+            metrics_moray_shard_observe(
                 shard,
-                mclient,
-                &mut marked_error,
+                MORAY_OP_WRITE,
+                now.elapsed().as_secs_f64(),
+                ret.is_err(),
             );
+
+            if let Err(e) = ret {
+                error!("Batch update failed, retrying individually: {}", e);
+                retry_batch_update(
+                    job_action,
+                    batch,
+                    shard,
+                    mclient,
+                    &mut marked_error,
+                    old_sharks,
+                    dest_shark,
+                );
+            } else {
+                audit_batch(job_action, &batch, old_sharks);
+            }
         }
     }
     marked_error
 }
 
+// Record each of the puts in a batch that succeeded.
+fn audit_batch(
+    job_action: &Arc<EvacuateJob>,
+    batch: &[BatchRequest],
+    old_sharks: &HashMap<ObjectId, Value>,
+) {
+    let entries: Vec<NewMetadataAudit> = batch
+        .iter()
+        .filter_map(|r| {
+            let br = match r {
+                BatchRequest::Put(br) => br,
+                _ => return None,
+            };
+            let etag = br.options.etag.specified_value()?;
+            let id = common::get_objectId_from_value(&br.value).ok()?;
+            let old = old_sharks.get(&id).cloned().unwrap_or(Value::Null);
+
+            metadata_audit_entry(&old, &br.value, &etag)
+                .map_err(|e| {
+                    error!("Could not audit metadata update ({}): {}", id, e);
+                })
+                .ok()
+        })
+        .collect();
+
+    job_action.record_metadata_audit(&entries);
+}
+
 fn retry_batch_update(
     job_action: &Arc<EvacuateJob>,
     requests: Vec<BatchRequest>,
     shard: u32,
     client: &mut MorayClient,
     marked_error: &mut Vec<ObjectId>,
+    old_sharks: &HashMap<ObjectId, Value>,
+    dest_shark: &StorageNode,
 ) {
     for r in requests.into_iter() {
         let br: BatchPutOp = match r {
@@ -3691,13 +8534,19 @@ fn retry_batch_update(
             .expect("etag should be specified");
 
         let o = br.value;
+        let old = common::get_objectId_from_value(&o)
+            .ok()
+            .and_then(|id| old_sharks.get(&id).cloned())
+            .unwrap_or(Value::Null);
 
         if let Err(muo_err) = metadata_update_one(
             job_action,
             MetadataClientOption::Client(client),
             &o,
+            &old,
             &etag,
             shard,
+            dest_shark,
         ) {
             let id = common::get_objectId_from_value(&o)
                 .expect("cannot get objectId");
@@ -3737,6 +8586,54 @@ fn batch_add_putobj(
     Ok(())
 }
 
+// A panic while updating the metadata for one assignment should not take the
+// worker, and with it every assignment queued behind it, down too.  The
+// objects in the assignment that had not been updated are marked as error so
+// that a retry job will pick them up, and the worker's moray clients are
+// dropped in case one of them was the cause.  If the local DB itself is the
+// problem (i.e. its lock was poisoned by the panic) the objects can not be
+// marked, and the assignment checker will stop the job when it next tries to
+// use the DB.
+fn metadata_update_assignment_supervised(
+    job_action: &Arc<EvacuateJob>,
+    ace: AssignmentCacheEntry,
+    client_hash: &mut HashMap<u32, MorayClient>,
+) {
+    metrics_gauge_dec(MD_UPDATE_QUEUE_DEPTH);
+
+    let id = ace.id.clone();
+    let payload = match watchdog::catch_panic(|| {
+        metadata_update_assignment(job_action, ace, client_hash)
+    }) {
+        Ok(()) => return,
+        Err(payload) => payload,
+    };
+
+    error!(
+        "Job {}: metadata update worker panicked while updating assignment \
+         {}: {}",
+        job_action.db_name,
+        id,
+        watchdog::panic_message(&*payload)
+    );
+
+    client_hash.clear();
+
+    if job_action.conn.is_poisoned() {
+        error!(
+            "Job {}: local DB unusable, objects in assignment {} left in \
+             post processing state",
+            job_action.db_name, id
+        );
+        return;
+    }
+
+    job_action.mark_assignment_post_processing_error(
+        &id,
+        EvacuateObjectError::MetadataUpdateFailed,
+    );
+}
+
 fn metadata_update_assignment(
     job_action: &Arc<EvacuateJob>,
     ace: AssignmentCacheEntry,
@@ -3744,114 +8641,388 @@ fn metadata_update_assignment(
 ) {
     info!("Updating metadata for assignment: {}", ace.id);
 
+    let objects = job_action
+        .load_assignment_objects(&ace.id, EvacuateObjectStatus::PostProcessing);
+
+    let num_objects = objects.len();
+    trace!("Updating metadata for {} objects", num_objects);
+
+    let updated_objects = metadata_update_objects(
+        job_action,
+        objects,
+        &ace.dest_shark,
+        client_hash,
+    );
+
+    metadata_update_finish(job_action, &ace.id, updated_objects, num_objects);
+}
+
+// Update the metadata of some of the objects in an assignment, returning
+// those that were updated.  Those that could not be updated are marked as
+// errors in the local DB.
+fn metadata_update_objects(
+    job_action: &Arc<EvacuateJob>,
+    objects: Vec<EvacuateObject>,
+    dest_shark: &StorageNode,
+    client_hash: &mut HashMap<u32, MorayClient>,
+) -> Vec<EvacuateObject> {
     // There is one moray client per shard, so when we collect the requests
     // into a batch we need to know which moray client this is going to based
     // on the shard number.
     let mut batched_reqs: HashMap<u32, Vec<BatchRequest>> = HashMap::new();
+    let mut old_sharks: HashMap<ObjectId, Value> = HashMap::new();
     let mut updated_objects = vec![];
-    let dest_shark = &ace.dest_shark;
-    let objects = job_action
-        .load_assignment_objects(&ace.id, EvacuateObjectStatus::PostProcessing);
-
-    trace!("Updating metadata for {} objects", objects.len());
 
     client_hash.shrink_to_fit();
 
-    for eobj in objects {
-        let etag = eobj.etag.clone();
-        let mobj = eobj.object.clone();
+    for eobj in objects {
+        let etag = eobj.etag.clone();
+        let mobj = eobj.object.clone();
+
+        // Unfortunately sqlite only accepts signed integers.  So we
+        // have to do the conversion here and cross our fingers that
+        // we don't have more than 2.1 billion shards.
+        // We do check this value coming in from sharkspotter as well.
+        if eobj.shard < 0 {
+            job_action.mark_object_error(
+                &eobj.id,
+                EvacuateObjectError::BadShardNumber,
+            );
+
+            // TODO: panic for now, but for release we should
+            // continue to next object.
+            panic!("Cannot have a negative shard {:#?}", eobj);
+        }
+
+        let shard = eobj.shard as u32;
+
+        if !job_action.verify_dest_copy(&eobj, dest_shark) {
+            continue;
+        }
+
+        // This function updates the manta object with the new
+        // sharks, and then returns the updated Manta metadata object.
+        match job_action.update_object_shark(mobj, dest_shark) {
+            Ok(o) => {
+                if job_action.config.options.use_batched_updates {
+                    old_sharks
+                        .insert(eobj.id.clone(), object_sharks(&eobj.object));
+                    if let Err(e) =
+                        batch_add_putobj(&mut batched_reqs, o, shard, etag)
+                    {
+                        error!(
+                            "Could not add put object operation to batch ({}): \
+                            {}",
+                            &eobj.id, e
+                        );
+                        job_action.mark_object_error(&eobj.id, e.into());
+                        continue;
+                    }
+                } else {
+                    let start = std::time::Instant::now();
+                    let ret = metadata_update_one(
+                        job_action,
+                        MetadataClientOption::Hash(client_hash),
+                        &o,
+                        &object_sharks(&eobj.object),
+                        &etag,
+                        shard,
+                        dest_shark,
+                    );
+
+                    metrics_md_update_observe(
+                        start.elapsed().as_secs_f64(),
+                        ret.is_err(),
+                    );
+
+                    if let Err(e) = ret {
+                        error!(
+                            "Error updating object:\n{:#?}\nwith dest_shark \
+                             {:?}\n({}).",
+                            o, dest_shark, e
+                        );
+                        job_action.mark_object_error(&eobj.id, e.into());
+                        continue;
+                    }
+                }
+            }
+
+            Err(e) => {
+                error!(
+                    "MD Update worker: Error updating \n\n{:#?}, with \
+                     dest_shark {:?}\n\n({}).",
+                    &eobj.object, dest_shark, e
+                );
+                job_action.mark_object_error(&eobj.id, e.into());
+            }
+        };
+
+        updated_objects.push(eobj);
+    }
+
+    if job_action.config.options.use_batched_updates {
+        let start = std::time::Instant::now();
+        let marked_error = metadata_update_batch(
+            job_action,
+            client_hash,
+            batched_reqs,
+            &old_sharks,
+            dest_shark,
+        );
+        let elapsed = start.elapsed().as_secs_f64();
+
+        for o in updated_objects.iter() {
+            metrics_md_update_observe(elapsed, marked_error.contains(&o.id));
+        }
+
+        // Remove any of the objects that we had to mark as "Error" from the list
+        // of updated objects.
+        updated_objects.retain(|o| !marked_error.contains(&o.id));
+    }
+
+    for eobj in updated_objects.iter() {
+        job_action.check_dest_headers(eobj, dest_shark);
+    }
+
+    updated_objects
+}
+
+// Once the metadata of every object in an assignment has been updated (or
+// could not be), the assignment is complete.
+fn metadata_update_finish(
+    job_action: &Arc<EvacuateJob>,
+    assignment_id: &str,
+    updated_objects: Vec<EvacuateObject>,
+    num_objects: usize,
+) {
+    info!("Assignment Complete: {}", assignment_id);
+
+    job_action.record_assignment_event(
+        assignment_id,
+        AssignmentEvent::PostProcessed,
+        Some(format!(
+            "{} of {} objects updated",
+            updated_objects.len(),
+            num_objects
+        )),
+    );
+    job_action.remove_assignment_from_cache(assignment_id);
+
+    let updated_objects = job_action.hold_linked_objects(updated_objects);
+    job_action.mark_objects_complete(updated_objects);
+    // TODO: check for DB insert error
+}
+
+// Update the links of every object that has links still to be updated (see
+// the snaplink module), now that the objects themselves have been updated.
+fn resolve_snaplinks(job_action: &Arc<EvacuateJob>) {
+    let pending = {
+        let locked_conn = job_action.conn.lock().expect("DB conn lock");
+        match snaplink::pending_links(&*locked_conn) {
+            Ok(p) => p,
+            Err(e) => {
+                error!(
+                    "Could not get the snaplinks of job {}: {}",
+                    job_action.db_name, e
+                );
+                return;
+            }
+        }
+    };
+
+    if pending.is_empty() {
+        return;
+    }
+
+    info!("Updating the snaplinks of {} objects", pending.len());
+
+    let mut client_hash: HashMap<u32, MorayClient> = HashMap::new();
+    let mut updated = 0;
+    let mut failed = 0;
+
+    for (id, links) in pending.iter() {
+        let (u, f) =
+            resolve_object_snaplinks(job_action, id, links, &mut client_hash);
+        updated += u;
+        failed += f;
+    }
+
+    info!(
+        "Evacuate Job updated {} snaplinks, and could not update {}",
+        updated, failed
+    );
+}
+
+// Update the links of the object `id`, with those in each shard in a single
+// batch, and mark the object complete if every one of them was updated, or
+// as an error otherwise.  Returns how many links were updated, and how many
+// could not be.
+fn resolve_object_snaplinks(
+    job_action: &Arc<EvacuateJob>,
+    id: &str,
+    links: &[Snaplink],
+    client_hash: &mut HashMap<u32, MorayClient>,
+) -> (usize, usize) {
+    let mark_links = |links: &[&Snaplink], status: SnaplinkStatus| {
+        let locked_conn = job_action.conn.lock().expect("DB conn lock");
+        if let Err(e) = snaplink::mark_links(&*locked_conn, links, status) {
+            error!("Could not mark snaplinks of {} {}: {}", id, status, e);
+        }
+    };
+    let all: Vec<&Snaplink> = links.iter().collect();
+
+    // The links of an object that was not moved still match it.
+    let eobj = match job_action.load_object(id) {
+        Some(eobj)
+            if eobj.status == EvacuateObjectStatus::PostProcessing
+                || eobj.status == EvacuateObjectStatus::Complete =>
+        {
+            eobj
+        }
+        _ => {
+            mark_links(&all, SnaplinkStatus::Skipped);
+            return (0, 0);
+        }
+    };
+
+    let dest_shark = job_action
+        .dest_shark_hash
+        .read()
+        .expect("dest_shark_hash read lock")
+        .get(&eobj.dest_shark)
+        .map(|d| d.shark.clone());
 
-        // Unfortunately sqlite only accepts signed integers.  So we
-        // have to do the conversion here and cross our fingers that
-        // we don't have more than 2.1 billion shards.
-        // We do check this value coming in from sharkspotter as well.
-        if eobj.shard < 0 {
+    let dest_shark = match dest_shark {
+        Some(d) => d,
+        None => {
+            error!(
+                "Could not find destination {} of object {} to update its \
+                 snaplinks with",
+                eobj.dest_shark, id
+            );
+            mark_links(&all, SnaplinkStatus::Failed);
             job_action.mark_object_error(
-                &eobj.id,
-                EvacuateObjectError::BadShardNumber,
+                id,
+                EvacuateObjectError::SnaplinkUpdateFailed,
             );
-
-            // TODO: panic for now, but for release we should
-            // continue to next object.
-            panic!("Cannot have a negative shard {:#?}", eobj);
+            return (0, all.len());
         }
+    };
 
-        let shard = eobj.shard as u32;
+    let mut updated: Vec<&Snaplink> = vec![];
+    let mut failed: Vec<&Snaplink> = vec![];
 
-        // This function updates the manta object with the new
-        // sharks, and then returns the updated Manta metadata object.
-        match job_action.update_object_shark(mobj, dest_shark) {
-            Ok(o) => {
-                if job_action.config.options.use_batched_updates {
-                    if let Err(e) =
-                        batch_add_putobj(&mut batched_reqs, o, shard, etag)
-                    {
-                        error!(
-                            "Could not add put object operation to batch ({}): \
-                            {}",
-                            &eobj.id, e
-                        );
-                        job_action.mark_object_error(&eobj.id, e.into());
-                        continue;
-                    }
-                } else if let Err(e) = metadata_update_one(
-                    job_action,
-                    MetadataClientOption::Hash(client_hash),
-                    &o,
-                    &etag,
-                    shard,
-                ) {
-                    error!(
-                        "Error updating object:\n{:#?}\nwith dest_shark \
-                         {:?}\n({}).",
-                        o, dest_shark, e
-                    );
-                    job_action.mark_object_error(&eobj.id, e.into());
-                    continue;
+    for (shard, shard_links) in snaplink::by_shard(links).into_iter() {
+        let mut batched_reqs: HashMap<u32, Vec<BatchRequest>> = HashMap::new();
+        let mut puts = vec![];
+
+        for link in shard_links.into_iter() {
+            let put = job_action
+                .update_object_shark(link.object.clone(), &dest_shark)
+                .and_then(|value| {
+                    batch_add_putobj(
+                        &mut batched_reqs,
+                        value.clone(),
+                        shard,
+                        link.etag.clone(),
+                    )?;
+                    Ok(value)
+                });
+
+            match put {
+                Ok(value) => puts.push((link, value)),
+                Err(e) => {
+                    error!("Could not update snaplink {}: {}", link.key, e);
+                    failed.push(link);
                 }
             }
+        }
+
+        if puts.is_empty() {
+            continue;
+        }
 
+        let mclient = match get_client_from_hash(job_action, client_hash, shard)
+        {
+            Ok(c) => c,
             Err(e) => {
-                error!(
-                    "MD Update worker: Error updating \n\n{:#?}, with \
-                     dest_shark {:?}\n\n({}).",
-                    &eobj.object, dest_shark, e
-                );
-                job_action.mark_object_error(&eobj.id, e.into());
+                error!("Could not get client for snaplink update: {}", e);
+                failed.extend(puts.iter().map(|(link, _)| *link));
+                continue;
             }
         };
 
-        updated_objects.push(eobj);
-    }
+        let batch = batched_reqs.remove(&shard).unwrap_or_default();
+        let now = std::time::Instant::now();
+        let ret =
+            mclient.batch(&batch, &ObjectMethodOptions::default(), |_| Ok(()));
 
-    if job_action.config.options.use_batched_updates {
-        let marked_error =
-            metadata_update_batch(job_action, client_hash, batched_reqs);
+        metrics_moray_shard_observe(
+            shard,
+            MORAY_OP_WRITE,
+            now.elapsed().as_secs_f64(),
+            ret.is_err(),
+        );
 
-        // Remove any of the objects that we had to mark as "Error" from the list
-        // of updated objects.
-        updated_objects.retain(|o| !marked_error.contains(&o.id));
+        match ret {
+            Ok(()) => {
+                for (link, value) in puts.iter() {
+                    job_action.audit_put(
+                        &object_sharks(&link.object),
+                        value,
+                        &link.etag,
+                    );
+                    updated.push(*link);
+                }
+                continue;
+            }
+            // Links that conflict are re-read and rebased one at a time, as
+            // any other object is.
+            Err(e) => error!(
+                "Snaplink batch update of object {} failed, retrying \
+                 individually: {}",
+                id, e
+            ),
+        }
+
+        for (link, value) in puts.iter() {
+            match metadata_update_one(
+                job_action,
+                MetadataClientOption::Client(mclient),
+                value,
+                &object_sharks(&link.object),
+                &link.etag,
+                shard,
+                &dest_shark,
+            ) {
+                Ok(()) => updated.push(*link),
+                Err(e) => {
+                    error!("Could not update snaplink {}: {}", link.key, e);
+                    failed.push(*link);
+                }
+            }
+        }
     }
 
-    info!("Assignment Complete: {}", &ace.id);
+    mark_links(&updated, SnaplinkStatus::Updated);
 
-    job_action.remove_assignment_from_cache(&ace.id);
-    job_action.mark_objects_complete(updated_objects);
-    // TODO: check for DB insert error
+    if !failed.is_empty() {
+        mark_links(&failed, SnaplinkStatus::Failed);
+        job_action
+            .mark_object_error(id, EvacuateObjectError::SnaplinkUpdateFailed);
+    } else if eobj.status == EvacuateObjectStatus::PostProcessing {
+        job_action.mark_objects_complete(vec![eobj]);
+    }
+
+    (updated.len(), failed.len())
 }
 
 fn update_dynamic_metadata_threads(
     pool: &mut ThreadPool,
     queue_back: &Arc<Injector<DyanmicWorkerMsg>>,
     max_thread_count: &mut usize,
-    msg: JobUpdateMessage,
+    new_worker_count: usize,
 ) {
-    // Currently there is only one valid message here so we
-    // can use this irrefutable pattern.
-    // If we add any additional messages we have to use match
-    // statements.
-    let JobUpdateMessage::Evacuate(eum) = msg;
-    let EvacuateJobUpdateMessage::SetMetadataThreads(new_worker_count) = eum;
     let difference: i32 = new_worker_count as i32 - *max_thread_count as i32;
 
     info!(
@@ -3914,88 +9085,334 @@ fn metadata_update_broker_dynamic(
     md_update_rx: crossbeam::Receiver<AssignmentCacheEntry>,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
     let mut max_thread_count =
-        job_action.config.options.max_metadata_update_threads;
-    let mut pool = ThreadPool::with_name(
-        "Dyn_MD_Update".into(),
-        job_action.config.options.max_metadata_update_threads,
-    );
+        job_action.tunables.get().max_metadata_update_threads;
+    let mut pool =
+        ThreadPool::with_name("Dyn_MD_Update".into(), max_thread_count);
     let queue = Arc::new(Injector::<DyanmicWorkerMsg>::new());
-    let update_rx = match &job_action.update_rx {
-        Some(urx) => urx.clone(),
-        None => panic!(
-            "Missing update_rx channel for job with dynamic update threads"
-        ),
-    };
 
-    thread::Builder::new()
-        .name(String::from("Metadata Update broker"))
-        .spawn(move || {
-            loop {
-                if let Ok(msg) = update_rx.try_recv() {
-                    debug!("Received metadata update message: {:#?}", msg);
-                    update_dynamic_metadata_threads(
-                        &mut pool,
-                        &queue,
-                        &mut max_thread_count,
-                        msg,
-                    );
-                }
-                let ace = match md_update_rx.recv() {
-                    Ok(ace) => ace,
-                    Err(e) => {
-                        // If the queue is empty and there are no active or
-                        // queued threads, kick one off to drain the queue.
-                        if !queue.is_empty()
-                            && pool.active_count() == 0
-                            && pool.queued_count() == 0
-                        {
-                            let worker = metadata_update_worker_dynamic(
-                                Arc::clone(&job_action),
-                                Arc::clone(&queue),
-                            );
+    let job_id = job_action.db_name.clone();
+    let finished = watchdog::drained(&md_update_rx);
+    spawn_supervised(&job_id, "Metadata Update broker", finished, move || {
+        loop {
+            let wanted = job_action.tunables.get().max_metadata_update_threads;
+            if wanted != max_thread_count {
+                update_dynamic_metadata_threads(
+                    &mut pool,
+                    &queue,
+                    &mut max_thread_count,
+                    wanted,
+                );
+            }
+            let ace = match md_update_rx.recv() {
+                Ok(ace) => ace,
+                Err(e) => {
+                    // If the queue is empty and there are no active or
+                    // queued threads, kick one off to drain the queue.
+                    if !queue.is_empty()
+                        && pool.active_count() == 0
+                        && pool.queued_count() == 0
+                    {
+                        let worker = metadata_update_worker_dynamic(
+                            Arc::clone(&job_action),
+                            Arc::clone(&queue),
+                        );
 
-                            pool.execute(worker);
-                        }
+                        pool.execute(joblog::inherit(worker));
+                    }
 
-                        warn!(
-                            "Could not receive metadata from assignment \
+                    warn!(
+                        "Could not receive metadata from assignment \
                              checker thread: {}",
-                            e
-                        );
+                        e
+                    );
 
-                        break;
-                    }
-                };
+                    break;
+                }
+            };
 
-                queue.push(DyanmicWorkerMsg::Data(ace));
+            queue.push(DyanmicWorkerMsg::Data(ace));
 
-                // If all the pools threads are devoted to workers there's
-                // really no reason to queue up a new worker.
-                let total_jobs = pool.active_count() + pool.queued_count();
-                trace!("Total dynamic metadata update threads: {}", total_jobs);
-                if total_jobs >= pool.max_count() {
-                    trace!(
-                        "Total threads ({}) exceeds max thread count for pool \
+            // If all the pools threads are devoted to workers there's
+            // really no reason to queue up a new worker.
+            // While the job is ramping up, only some of the pool's threads
+            // are used.
+            let total_jobs = pool.active_count() + pool.queued_count();
+            let max_count = job_action.ramp.limit(pool.max_count());
+            trace!("Total dynamic metadata update threads: {}", total_jobs);
+            if total_jobs >= max_count {
+                trace!(
+                    "Total threads ({}) exceeds max thread count for pool \
                          ({}) not starting new thread",
-                        total_jobs,
-                        pool.max_count()
+                    total_jobs,
+                    max_count
+                );
+                continue;
+            }
+
+            // XXX: async/await candidate?
+            let worker = metadata_update_worker_dynamic(
+                Arc::clone(&job_action),
+                Arc::clone(&queue),
+            );
+
+            pool.execute(joblog::inherit(worker));
+        }
+        pool.join();
+        metrics_gauge_set(MD_THREAD_GAUGE, 0);
+        Ok(())
+    })
+}
+
+// An assignment whose objects have been split up by shard among the workers
+// of the sharded metadata update broker.  Whichever worker finishes the last
+// of its shards completes the assignment.
+struct ShardedAssignment {
+    id: AssignmentId,
+    dest_shark: StorageNode,
+    num_objects: usize,
+    remaining: AtomicUsize,
+    updated: Mutex<Vec<EvacuateObject>>,
+}
+
+enum ShardWorkerMsg {
+    Data(Arc<ShardedAssignment>, Vec<EvacuateObject>),
+    Stop,
+}
+
+type ShardWorker = (crossbeam::Sender<ShardWorkerMsg>, JoinHandle<()>);
+
+// Each worker owns the shards whose number modulo the number of workers is
+// its index.  An object's shard is determined by its key, so every update to
+// an object is made by the same worker, in the order that it was received.
+fn shard_worker_index(shard: i32, num_workers: usize) -> usize {
+    std::cmp::max(shard, 0) as usize % num_workers
+}
+
+// Record that one shard of an assignment has been updated, and complete the
+// assignment if it was the last.
+fn shard_update_done(
+    job_action: &Arc<EvacuateJob>,
+    sa: &ShardedAssignment,
+    updated: Vec<EvacuateObject>,
+) {
+    let mut locked_updated = sa.updated.lock().expect("sharded update lock");
+    locked_updated.extend(updated);
+
+    if sa.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+        let updated = std::mem::replace(&mut *locked_updated, vec![]);
+        drop(locked_updated);
+        metadata_update_finish(job_action, &sa.id, updated, sa.num_objects);
+    }
+}
+
+// A worker keeps one moray client for each of the shards it owns, for as long
+// as it runs.  Like metadata_update_assignment_supervised(), a panic while
+// updating a shard marks the objects it was given as errors rather than
+// stopping the job.
+fn metadata_update_worker_sharded(
+    job_action: Arc<EvacuateJob>,
+    rx: crossbeam::Receiver<ShardWorkerMsg>,
+) -> impl FnOnce() {
+    move || {
+        let mut client_hash: HashMap<u32, MorayClient> = HashMap::new();
+
+        metrics_gauge_inc(MD_THREAD_GAUGE);
+        debug!(
+            "Started sharded metadata update worker: {:?}",
+            thread::current().id()
+        );
+
+        while let Ok(ShardWorkerMsg::Data(sa, objects)) = rx.recv() {
+            let ids: Vec<ObjectId> =
+                objects.iter().map(|o| o.id.clone()).collect();
+
+            let updated = match watchdog::catch_panic(|| {
+                metadata_update_objects(
+                    &job_action,
+                    objects,
+                    &sa.dest_shark,
+                    &mut client_hash,
+                )
+            }) {
+                Ok(updated) => updated,
+                Err(payload) => {
+                    error!(
+                        "Job {}: sharded metadata update worker panicked \
+                         while updating assignment {}: {}",
+                        job_action.db_name,
+                        sa.id,
+                        watchdog::panic_message(&*payload)
                     );
-                    continue;
+
+                    client_hash.clear();
+
+                    if !job_action.conn.is_poisoned() {
+                        for id in ids.iter() {
+                            job_action.mark_object_error(
+                                id,
+                                EvacuateObjectError::MetadataUpdateFailed,
+                            );
+                        }
+                    }
+                    vec![]
                 }
+            };
 
-                // XXX: async/await candidate?
-                let worker = metadata_update_worker_dynamic(
-                    Arc::clone(&job_action),
-                    Arc::clone(&queue),
+            shard_update_done(&job_action, &sa, updated);
+        }
+
+        debug!("Exiting sharded metadata update worker.");
+        metrics_gauge_dec(MD_THREAD_GAUGE);
+    }
+}
+
+fn start_shard_workers(
+    job_action: &Arc<EvacuateJob>,
+    num_workers: usize,
+) -> Vec<ShardWorker> {
+    (0..num_workers)
+        .map(|i| {
+            let (tx, rx) = crossbeam_channel::unbounded();
+            let handle = thread::Builder::new()
+                .name(format!("MD Update Shard [{}]", i))
+                .spawn(joblog::inherit(metadata_update_worker_sharded(
+                    Arc::clone(job_action),
+                    rx,
+                )))
+                .expect("create sharded MD update thread");
+
+            (tx, handle)
+        })
+        .collect()
+}
+
+// Each worker finishes whatever it has already been given before it receives
+// the Stop message.
+fn stop_shard_workers(workers: Vec<ShardWorker>) {
+    for (tx, _) in workers.iter() {
+        if let Err(e) = tx.send(ShardWorkerMsg::Stop) {
+            error!("Error stopping sharded metadata update worker: {}", e);
+        }
+    }
+
+    for (_, handle) in workers.into_iter() {
+        if handle.join().is_err() {
+            error!("Error joining sharded metadata update worker");
+        }
+    }
+}
+
+/// This thread runs until EvacuateJob Completion.  Unlike the other brokers,
+/// which hand whole assignments to their workers, this one loads the objects
+/// of each completed assignment from the local DB, splits them up by shard,
+/// and sends each shard's objects to the worker that owns that shard (see
+/// shard_worker_index()).  Different shards are updated in parallel by
+/// different workers, while the updates to any one shard, and so to any one
+/// object, are made in order.
+///
+/// The number of workers can be adjusted while the job is running.  See
+/// EvacuateJobUpdateMessage.  Since this changes which worker owns each
+/// shard, the existing workers are stopped, and finish what they have been
+/// given, before the new ones start.
+fn metadata_update_broker_sharded(
+    job_action: Arc<EvacuateJob>,
+    md_update_rx: crossbeam::Receiver<AssignmentCacheEntry>,
+) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
+    let job_id = job_action.db_name.clone();
+    let finished = watchdog::drained(&md_update_rx);
+    spawn_supervised(&job_id, "Metadata Update broker", finished, move || {
+        let mut num_workers = std::cmp::max(
+            job_action.tunables.get().max_metadata_update_threads,
+            1,
+        );
+        let mut workers = start_shard_workers(&job_action, num_workers);
+
+        loop {
+            let count = std::cmp::max(
+                job_action.tunables.get().max_metadata_update_threads,
+                1,
+            );
+            if count != num_workers {
+                info!(
+                    "Updating sharded metadata update thread count from {} \
+                     to {}.",
+                    num_workers, count
                 );
 
-                pool.execute(worker);
+                stop_shard_workers(workers);
+                num_workers = count;
+                workers = start_shard_workers(&job_action, num_workers);
             }
-            pool.join();
-            metrics_gauge_set(MD_THREAD_GAUGE, 0);
-            Ok(())
-        })
-        .map_err(Error::from)
+
+            let ace = match md_update_rx.recv() {
+                Ok(ace) => ace,
+                Err(e) => {
+                    warn!(
+                        "Could not receive metadata from assignment \
+                         checker thread: {}",
+                        e
+                    );
+                    break;
+                }
+            };
+
+            metrics_gauge_dec(MD_UPDATE_QUEUE_DEPTH);
+            info!("Updating metadata for assignment: {}", ace.id);
+
+            let objects = job_action.load_assignment_objects(
+                &ace.id,
+                EvacuateObjectStatus::PostProcessing,
+            );
+            let num_objects = objects.len();
+
+            let mut by_shard: HashMap<i32, Vec<EvacuateObject>> =
+                HashMap::new();
+            for eobj in objects.into_iter() {
+                by_shard.entry(eobj.shard).or_default().push(eobj);
+            }
+
+            if by_shard.is_empty() {
+                metadata_update_finish(&job_action, &ace.id, vec![], 0);
+                continue;
+            }
+
+            let sa = Arc::new(ShardedAssignment {
+                id: ace.id.clone(),
+                dest_shark: ace.dest_shark.clone(),
+                num_objects,
+                remaining: AtomicUsize::new(by_shard.len()),
+                updated: Mutex::new(vec![]),
+            });
+
+            for (shard, objects) in by_shard.into_iter() {
+                let (tx, _) = &workers[shard_worker_index(shard, num_workers)];
+                let msg = ShardWorkerMsg::Data(Arc::clone(&sa), objects);
+
+                // The worker is gone, so nothing will update these objects.
+                if let Err(e) = tx.send(msg) {
+                    error!(
+                        "Error sending shard {} of assignment {} to sharded \
+                         metadata update worker",
+                        shard, sa.id
+                    );
+
+                    if let ShardWorkerMsg::Data(_, objects) = e.into_inner() {
+                        for eobj in objects.iter() {
+                            job_action.mark_object_error(
+                                &eobj.id,
+                                EvacuateObjectError::MetadataUpdateFailed,
+                            );
+                        }
+                    }
+                    shard_update_done(&job_action, &sa, vec![]);
+                }
+            }
+        }
+
+        stop_shard_workers(workers);
+        metrics_gauge_set(MD_THREAD_GAUGE, 0);
+        Ok(())
+    })
 }
 
 // Note how we create a separate channel here.  We could simply increase
@@ -4020,10 +9437,10 @@ fn _update_broker_static(
         let th_rx = static_rx.clone();
         let handle = thread::Builder::new()
             .name(format!("MD Update [{}]", i))
-            .spawn(metadata_update_worker_static(
+            .spawn(joblog::inherit(metadata_update_worker_static(
                 Arc::clone(&job_action),
                 th_rx,
-            ))
+            )))
             .expect("create static MD update thread");
 
         thread_handles.push(handle);
@@ -4055,6 +9472,7 @@ fn _update_broker_static(
 
         let msg = UpdateWorkerMsg::Data(ace);
         if let Err(e) = static_tx.send(msg) {
+            metrics_gauge_dec(MD_UPDATE_QUEUE_DEPTH);
             error!(
                 "Error sending assignment cache entry to static metadata \
                  update worker thread: {}",
@@ -4075,10 +9493,11 @@ fn metadata_update_broker_static(
     job_action: Arc<EvacuateJob>,
     md_update_rx: crossbeam::Receiver<AssignmentCacheEntry>,
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
-    thread::Builder::new()
-        .name(String::from("Metadata Update broker"))
-        .spawn(move || _update_broker_static(job_action, md_update_rx))
-        .map_err(Error::from)
+    let job_id = job_action.db_name.clone();
+    let finished = watchdog::drained(&md_update_rx);
+    spawn_supervised(&job_id, "Metadata Update broker", finished, move || {
+        _update_broker_static(job_action, md_update_rx)
+    })
 }
 
 fn start_metadata_update_broker(
@@ -4087,6 +9506,8 @@ fn start_metadata_update_broker(
 ) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
     if job_action.config.options.use_static_md_update_threads {
         metadata_update_broker_static(job_action, md_update_rx)
+    } else if job_action.config.options.use_sharded_md_updates {
+        metadata_update_broker_sharded(job_action, md_update_rx)
     } else {
         metadata_update_broker_dynamic(job_action, md_update_rx)
     }
@@ -4097,19 +9518,30 @@ fn start_metadata_update_broker(
 // channels, an error in one channel cascades to other channels.  So, it is
 // most likely the case that the first error is the most (and only) valuable
 // one to the user.  At any rate, all errors are still logged, but the job
-// error is only for the job DB.
-fn set_run_error<E>(current_error: &mut Result<(), Error>, err: E)
+// error is only for the job DB.  The exception is a thread panicking, which
+// is the cause of whatever errors then cascade through the other threads, so
+// it replaces any error already recorded.
+pub fn set_run_error<E>(current_error: &mut Result<(), Error>, err: E)
 where
     E: Into<Error>,
 {
-    if current_error.is_ok() {
-        *current_error = Err(err.into());
+    let err = err.into();
+    let replace = match current_error {
+        Ok(()) => true,
+        Err(cur) => {
+            watchdog::is_worker_panic(&err) && !watchdog::is_worker_panic(cur)
+        }
+    };
+
+    if replace {
+        *current_error = Err(err);
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ChecksumPolicy;
     use crate::metrics::metrics_init;
     use crate::storinfo::ChooseAlgorithm;
     use lazy_static::lazy_static;
@@ -4119,8 +9551,8 @@ mod tests {
     use rebalancer::common::ObjectSkippedReason;
     use rebalancer::libagent::{router as agent_router, AgentAssignmentStats};
     use rebalancer::metrics::MetricsMap;
+    use rebalancer::transfer::Transfer;
     use rebalancer::util;
-    use reqwest::Client;
 
     lazy_static! {
         static ref INITIALIZED: Mutex<bool> = Mutex::new(false);
@@ -4178,7 +9610,7 @@ mod tests {
     // functionality.
     fn process_task_always_pass(
         task: &mut Task,
-        _client: &Client,
+        _transfer: &dyn Transfer,
         _metrics: &Option<MetricsMap>,
     ) {
         task.set_status(TaskStatus::Complete);
@@ -4238,6 +9670,10 @@ mod tests {
         job_action.from_shark.datacenter = "dc1".into();
         assert!(job_action.create_tables().is_ok());
 
+        // Keep tests that run concurrently from seeing each other's
+        // assignments.
+        job_action.projected = Arc::new(ProjectedUtilization::default());
+
         job_action
     }
 
@@ -4279,42 +9715,46 @@ mod tests {
         configure_test_job_common(job_action)
     }
 
-    fn start_test_obj_generator_thread(
-        obj_tx: crossbeam_channel::Sender<EvacuateObject>,
-        test_objects: Vec<MantaObject>,
-        from_shark: String,
-    ) -> thread::JoinHandle<Result<(), Error>> {
-        thread::Builder::new()
-            .name(String::from("test object generator thread"))
-            .spawn(move || {
-                for o in test_objects.into_iter() {
-                    let shard = 1;
-                    let etag = String::from("Fake_etag");
-                    let mobj_value =
-                        serde_json::to_string(&o).expect("mobj value");
-
-                    let moray_value = serde_json::to_value(
-                        TestMorayObject::new(mobj_value, etag),
-                    )
-                    .expect("moray value");
-
-                    let manta_value =
-                        sharkspotter::manta_obj_from_moray_obj(&moray_value)
-                            .expect("manta value");
-
-                    let etag =
-                        sharkspotter::etag_from_moray_value(&moray_value)
-                            .expect("etag from moray value");
+    // Create an EvacuateObject the same way that one is created from a
+    // sharkspotter message.
+    fn test_evacuate_object(
+        o: &MantaObject,
+        from_shark: &str,
+    ) -> EvacuateObject {
+        let shard = 1;
+        let etag = String::from("Fake_etag");
+        let mobj_value = serde_json::to_string(o).expect("mobj value");
+
+        let moray_value =
+            serde_json::to_value(TestMorayObject::new(mobj_value, etag))
+                .expect("moray value");
+
+        let manta_value = sharkspotter::manta_obj_from_moray_obj(&moray_value)
+            .expect("manta value");
+
+        let etag = sharkspotter::etag_from_moray_value(&moray_value)
+            .expect("etag from moray value");
+
+        let ssobj = SharkspotterMessage {
+            manta_value,
+            etag,
+            shark: from_shark.to_string(),
+            shard,
+        };
 
-                    let ssobj = SharkspotterMessage {
-                        manta_value,
-                        etag,
-                        shark: from_shark.clone(),
-                        shard,
-                    };
+        EvacuateObject::try_from(ssobj).expect("evac obj from sharkspotter obj")
+    }
 
-                    let eobj = EvacuateObject::try_from(ssobj)
-                        .expect("evac obj from sharkspotter obj");
+    fn start_test_obj_generator_thread(
+        obj_tx: crossbeam_channel::Sender<EvacuateObject>,
+        test_objects: Vec<MantaObject>,
+        from_shark: String,
+    ) -> thread::JoinHandle<Result<(), Error>> {
+        thread::Builder::new()
+            .name(String::from("test object generator thread"))
+            .spawn(move || {
+                for o in test_objects.into_iter() {
+                    let eobj = test_evacuate_object(&o, &from_shark);
 
                     match obj_tx.send(eobj) {
                         Ok(()) => (),
@@ -5025,6 +10465,8 @@ mod tests {
             assignment_id, evacuateobjects, skipped_reason, status,
         };
 
+        // Assignment events are written by a subscriber of the job's events.
+        job_action.events.flush();
         let locked_conn = job_action.conn.lock().expect("DB conn");
 
         let records: Vec<EvacuateObject> = evacuateobjects
@@ -5047,6 +10489,32 @@ mod tests {
 
             assert_eq!(skipped_reason_records.len(), *count as usize);
         });
+
+        let lifecycle = assignment_lifecycle(&*locked_conn, &uuid)
+            .expect("assignment lifecycle")
+            .expect("assignment found");
+        let events: Vec<&str> =
+            lifecycle.events.iter().map(|e| e.event.as_str()).collect();
+
+        assert_eq!(lifecycle.tasks.len(), eobjs.len());
+        assert_eq!(lifecycle.dispositions.get("skipped"), Some(&records.len()));
+        assert_eq!(events, vec!["created", "agent_complete"]);
+        assert_eq!(lifecycle.post_attempts, 0);
+        assert!(lifecycle.posted.is_none());
+        assert!(lifecycle.completed.is_some());
+
+        let summaries = assignment_summaries(&*locked_conn, 10, 0)
+            .expect("assignment summaries");
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].id, uuid);
+        assert_eq!(summaries[0].tasks, eobjs.len() as i64);
+        assert_eq!(summaries[0].bytes as u64, lifecycle.bytes);
+        assert_eq!(summaries[0].dest_shark, lifecycle.dest_shark);
+        assert_eq!(summaries[0].state, lifecycle.state);
+        assert_eq!(summaries[0].completed, lifecycle.completed);
+        assert!(assignment_summaries(&*locked_conn, 10, 1)
+            .expect("assignment summaries")
+            .is_empty());
     }
 
     #[test]
@@ -5089,6 +10557,270 @@ mod tests {
         }
     }
 
+    #[test]
+    fn shark_changes_test() {
+        unit_test_init();
+
+        // Gives one snapshot, and from then on only changes.
+        struct WatchedStorinfo {
+            snapshot: Mutex<Option<Vec<StorageNode>>>,
+            changes: Mutex<Vec<SharkChange>>,
+        }
+        impl SharkSource for WatchedStorinfo {
+            fn choose(&self, _: &ChooseAlgorithm) -> Option<Vec<StorageNode>> {
+                self.snapshot.lock().unwrap().take()
+            }
+
+            fn changes(&self) -> Vec<SharkChange> {
+                self.changes.lock().unwrap().drain(..).collect()
+            }
+        }
+
+        let shark = |id: &str, available_mb: u64| StorageNode {
+            manta_storage_id: id.to_string(),
+            available_mb,
+            ..Default::default()
+        };
+        let storinfo = Arc::new(WatchedStorinfo {
+            snapshot: Mutex::new(Some(vec![
+                shark("1.stor.fake", 1000),
+                shark("2.stor.fake", 1000),
+            ])),
+            changes: Mutex::new(vec![]),
+        });
+        let algo = mod_storinfo::DefaultChooseAlgorithm {
+            min_avail_mb: Some(100),
+            blacklist: vec![],
+        };
+        let job_action = create_test_evacuate_job(99);
+        let ids = |list: Vec<EvacuateDestShark>| {
+            let mut ids: Vec<String> =
+                list.into_iter().map(|s| s.shark.manta_storage_id).collect();
+            ids.sort();
+            ids
+        };
+
+        let list = job_action
+            .get_shark_list(Arc::clone(&storinfo), &algo, 1)
+            .expect("shark list");
+        assert_eq!(ids(list), vec!["1.stor.fake", "2.stor.fake"]);
+
+        // A shark that is too full to be chosen is not added.
+        *storinfo.changes.lock().unwrap() = vec![
+            SharkChange::Removed(String::from("1.stor.fake")),
+            SharkChange::Added(shark("3.stor.fake", 1000)),
+            SharkChange::Added(shark("4.stor.fake", 10)),
+        ];
+        let list = job_action
+            .get_shark_list(Arc::clone(&storinfo), &algo, 1)
+            .expect("shark list");
+        assert_eq!(ids(list), vec!["2.stor.fake", "3.stor.fake"]);
+    }
+
+    #[test]
+    fn reconcile_agent_test() {
+        unit_test_init();
+        let job_action = create_test_evacuate_job(10);
+        let boot = |shark: &str| {
+            let boots = job_action.agent_boots.lock().expect("agent boots");
+            (boots[shark].boot_id.clone(), boots[shark].unreachable_since)
+        };
+
+        // The first time that the job asks the agent started by the tests it
+        // has no boot to ask since, so it takes the agent to have restarted.
+        let ace = AssignmentCacheEntry::from(Assignment::new(
+            generate_storage_node(true),
+        ));
+        assert!(job_action.reconcile_agent(&ace));
+        let (boot_id, unreachable_since) = boot("localhost");
+        assert!(boot_id.is_some());
+        assert!(unreachable_since.is_none());
+
+        assert!(job_action.reconcile_agent(&ace));
+        assert_eq!(boot("localhost").0, boot_id);
+
+        // An agent that can not be reached is waited for, but not forever.
+        let mut dest_shark = generate_storage_node(false);
+        dest_shark.manta_storage_id = String::from("agent.invalid");
+        let ace = AssignmentCacheEntry::from(Assignment::new(dest_shark));
+        assert!(job_action.reconcile_agent(&ace));
+        assert!(boot("agent.invalid").1.is_some());
+
+        job_action
+            .agent_boots
+            .lock()
+            .expect("agent boots")
+            .get_mut("agent.invalid")
+            .expect("agent boot")
+            .unreachable_since =
+            std::time::Instant::now().checked_sub(AGENT_RESTART_GRACE);
+        assert!(!job_action.reconcile_agent(&ace));
+    }
+
+    #[test]
+    fn drain_destination_test() {
+        unit_test_init();
+        let job_action = create_test_evacuate_job(10);
+        let drain = |shark: &str| {
+            job_action.apply_update(EvacuateJobUpdateMessage::DrainDestination(
+                shark.to_string(),
+            ))
+        };
+
+        let payload = serde_json::json!({
+            "action": "drain_destination",
+            "params": "1.stor.domain"
+        });
+        match serde_json::from_value(payload).expect("drain update") {
+            EvacuateJobUpdateMessage::DrainDestination(shark) => {
+                assert_eq!(shark, "1.stor.domain")
+            }
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+
+        assert!(drain("").is_err());
+        assert!(drain("1.stor.domain").is_err());
+
+        let mut sn = generate_storage_node(false);
+        sn.manta_storage_id = String::from("1.stor.domain");
+        job_action.update_dest_shark(
+            &mut job_action
+                .dest_shark_hash
+                .write()
+                .expect("dest_shark_hash write lock"),
+            &sn,
+        );
+
+        assert!(!job_action.is_drained("1.stor.domain"));
+        assert!(drain("1.stor.domain").is_ok());
+        assert!(job_action.is_drained("1.stor.domain"));
+        assert!(drain("1.stor.domain").is_err());
+    }
+
+    #[test]
+    fn slow_source_test() {
+        unit_test_init();
+
+        let mut g = StdThreadGen::new(10);
+        let mut job_action = create_test_evacuate_job(10);
+        let from_shark = job_action.from_shark.manta_storage_id.clone();
+        let dest_shark = generate_storage_node(true);
+        let mut available_space = std::u64::MAX;
+
+        // An object whose only copy is on the shark being evacuated.
+        let mut single_copy_object = || {
+            let mut mobj = MantaObject::arbitrary(&mut g);
+            mobj.content_length = 1;
+            mobj.sharks = vec![MantaObjectShark {
+                datacenter: String::from("dc1"),
+                manta_storage_id: from_shark.clone(),
+            }];
+            test_evacuate_object(&mobj, &from_shark)
+        };
+
+        let eobj = single_copy_object();
+        let mut assignment = Assignment::new(dest_shark.clone());
+        assert!(add_object_to_assignment(
+            &job_action,
+            eobj,
+            &dest_shark,
+            &mut assignment,
+            &mut available_space,
+            &from_shark,
+        )
+        .is_err());
+        assert!(assignment.tasks.is_empty());
+
+        job_action.config.options.slow_source = true;
+
+        let eobj = single_copy_object();
+        let added = add_object_to_assignment(
+            &job_action,
+            eobj,
+            &dest_shark,
+            &mut assignment,
+            &mut available_space,
+            &from_shark,
+        );
+        assert!(added.is_ok());
+
+        let task = assignment.tasks.values().next().expect("task");
+        assert_eq!(task.source.manta_storage_id, from_shark);
+        assert_eq!(assignment.evac_shark_reads, 1);
+        assert_eq!(job_action.evac_shark_sourced.load(Ordering::SeqCst), 1);
+        assert_eq!(job_action.replica_sourced.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn placement_trace_test() {
+        unit_test_init();
+
+        let mut g = StdThreadGen::new(10);
+        let mut job_action = create_test_evacuate_job(10);
+        let from_shark = job_action.from_shark.manta_storage_id.clone();
+        let dest_shark = generate_storage_node(true);
+        let mut available_space = 1;
+
+        let mut mobj = MantaObject::arbitrary(&mut g);
+        mobj.content_length = 2 * 1024 * 1024;
+        mobj.sharks = vec![MantaObjectShark {
+            datacenter: String::from("dc1"),
+            manta_storage_id: String::from("2.stor.domain"),
+        }];
+        let eobj = test_evacuate_object(&mobj, &from_shark);
+        let object_id = eobj.id.clone();
+
+        // Nothing is traced unless the job asks for it.
+        job_action.trace_placement(
+            &object_id,
+            None,
+            PlacementDecision::NoDestination,
+            None,
+        );
+
+        job_action.config.options.trace_placement = true;
+        job_action.trace_placement(
+            "other object",
+            Some(dest_shark.manta_storage_id.as_str()),
+            PlacementDecision::Chosen,
+            None,
+        );
+
+        let mut assignment = Assignment::new(dest_shark.clone());
+        assert!(add_object_to_assignment(
+            &job_action,
+            eobj,
+            &dest_shark,
+            &mut assignment,
+            &mut available_space,
+            &from_shark,
+        )
+        .is_err());
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let all = placement::get_placement_trace(&*conn, None, 10, 0)
+            .expect("placement trace");
+        assert_eq!(all.len(), 2);
+
+        let traced = placement::get_placement_trace(
+            &*conn,
+            Some(object_id.as_str()),
+            10,
+            0,
+        )
+        .expect("placement trace");
+        assert_eq!(traced.len(), 1);
+        assert_eq!(traced[0].decision, "capacity");
+        assert_eq!(
+            traced[0].dest_shark.as_ref(),
+            Some(&dest_shark.manta_storage_id)
+        );
+        assert_eq!(
+            traced[0].detail.as_ref().map(String::as_str),
+            Some("content_mb: 2, available_mb: 1")
+        );
+    }
+
     #[test]
     fn skip_object_test() {
         // TODO: add test that includes skipped objects
@@ -5101,6 +10833,472 @@ mod tests {
         unit_test_init();
     }
 
+    #[test]
+    fn snaplink_hold_test() {
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+        job_action.config.options.resolve_snaplinks = true;
+        let mut g = StdThreadGen::new(10);
+
+        let obj = MantaObject::arbitrary(&mut g);
+        let mut link_obj = obj.clone();
+        link_obj.key = format!("{}.link", obj.key);
+
+        let eobj = EvacuateObject {
+            id: obj.object_id.clone(),
+            object: serde_json::to_value(obj).expect("obj value"),
+            shard: 1,
+            ..Default::default()
+        };
+        let link = EvacuateObject {
+            object: serde_json::to_value(&link_obj).expect("link value"),
+            ..eobj.clone()
+        };
+
+        job_action.insert_into_db(&eobj);
+        job_action.insert_into_db(&link);
+
+        // The same entry found again is not a link of itself.
+        job_action.insert_into_db(&eobj);
+
+        let pending = {
+            let conn = job_action.conn.lock().expect("db conn lock");
+            snaplink::pending_links(&*conn).expect("pending links")
+        };
+        let links = &pending[&eobj.id];
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].key, link_obj.key);
+
+        // The object is held back rather than marked complete.
+        let other = EvacuateObject {
+            id: random_string(&mut g, 36),
+            ..Default::default()
+        };
+        let ready = job_action.hold_linked_objects(vec![eobj.clone(), other]);
+        assert_eq!(ready.len(), 1);
+        assert_ne!(ready[0].id, eobj.id);
+
+        // The links of an object that was not moved are left as they are.
+        job_action.mark_object_skipped(
+            &eobj.id,
+            ObjectSkippedReason::SourceIsEvacShark,
+        );
+        let job_action = Arc::new(job_action);
+        let mut client_hash = HashMap::new();
+        assert_eq!(
+            resolve_object_snaplinks(
+                &job_action,
+                &eobj.id,
+                links,
+                &mut client_hash
+            ),
+            (0, 0)
+        );
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        assert!(snaplink::pending_links(&*conn)
+            .expect("pending links")
+            .is_empty());
+    }
+
+    #[test]
+    fn create_copy_test() {
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+        job_action
+            .set_create_copy(Some(3))
+            .expect("set create copy");
+        assert!(job_action.is_create_copy());
+
+        let mut g = StdThreadGen::new(10);
+        let mut obj = MantaObject::arbitrary(&mut g);
+        obj.sharks[0] = job_action.from_shark.clone();
+        let obj_value = serde_json::to_value(obj.clone()).expect("obj value");
+
+        // The object has 2 of the 3 copies wanted.
+        assert!(!job_action.has_enough_copies(&obj_value));
+
+        let to_shark = generate_storage_node(true);
+        assert!(validate_copy_destination(&obj_value, &to_shark).is_none());
+
+        // The new shark is added, and the original copies are kept.
+        let updated = job_action
+            .update_object_shark(obj_value, &to_shark)
+            .expect("update object shark");
+        let sharks =
+            common::get_sharks_from_value(&updated).expect("updated sharks");
+        assert_eq!(sharks.len(), 3);
+        let ids: Vec<&str> =
+            sharks.iter().map(|s| s.manta_storage_id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                job_action.from_shark.manta_storage_id.as_str(),
+                obj.sharks[1].manta_storage_id.as_str(),
+                to_shark.manta_storage_id.as_str(),
+            ]
+        );
+
+        assert!(job_action.has_enough_copies(&updated));
+        assert_eq!(
+            validate_copy_destination(&updated, &to_shark),
+            Some(ObjectSkippedReason::ObjectAlreadyOnDestShark)
+        );
+        assert!(job_action.update_object_shark(updated, &to_shark).is_err());
+    }
+
+    #[test]
+    fn checksum_policy_test() {
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+        let mut g = StdThreadGen::new(10);
+
+        let policies = [
+            (ChecksumPolicy::Fail, EvacuateObjectStatus::Error, false),
+            (ChecksumPolicy::Skip, EvacuateObjectStatus::Skipped, false),
+            (
+                ChecksumPolicy::Copy,
+                EvacuateObjectStatus::Unprocessed,
+                true,
+            ),
+        ];
+
+        for (policy, status, copied) in policies.iter() {
+            job_action.config.options.checksum_policy = *policy;
+
+            let mut obj = MantaObject::arbitrary(&mut g);
+            obj.content_md5 = String::new();
+            let mut eobj = EvacuateObject {
+                id: obj.object_id.clone(),
+                object: serde_json::to_value(obj).expect("obj value"),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                job_action.apply_checksum_policy(
+                    &mut eobj,
+                    "",
+                    ChecksumProblem::Missing
+                ),
+                *copied
+            );
+            assert_eq!(eobj.status, *status);
+        }
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let summary =
+            checksum::get_checksum_summary(&*conn).expect("checksum summary");
+        assert_eq!(
+            summary,
+            checksum::ChecksumSummary {
+                failed: 1,
+                skipped: 1,
+                copied: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn max_dest_utilization_test() {
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+        job_action.config.max_fill_percentage = 80;
+        assert_eq!(job_action.dest_fill_percentage(), 80);
+
+        // The ceiling only ever lowers the fill limit.
+        job_action
+            .set_max_dest_utilization(Some(50))
+            .expect("set ceiling");
+        assert_eq!(job_action.dest_fill_percentage(), 50);
+
+        job_action
+            .set_max_dest_utilization(Some(90))
+            .expect("set ceiling");
+        assert_eq!(job_action.dest_fill_percentage(), 80);
+
+        // The ceiling is recorded for a retry or resumed job.
+        use self::dest_limit_config::dsl::dest_limit_config;
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let recorded: DestLimitDbConfig =
+            dest_limit_config.first(&*conn).expect("dest limit config");
+        assert_eq!(recorded.max_dest_utilization_percent, 90);
+    }
+
+    #[test]
+    fn verify_before_update_test() {
+        use self::evacuateobjects::dsl::{evacuateobjects, id};
+
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+
+        let mut g = StdThreadGen::new(10);
+        let mut eobj = EvacuateObject::arbitrary(&mut g);
+        eobj.shard = 1;
+        eobj.status = EvacuateObjectStatus::PostProcessing;
+        eobj.skipped_reason = None;
+        eobj.error = None;
+        job_action.insert_into_db(&eobj);
+
+        // Without verify_before_update, copies are taken on trust.
+        let mut to_shark = generate_storage_node(true);
+        assert!(job_action.verify_dest_copy(&eobj, &to_shark));
+
+        // A destination that can not be asked about its copy is as good as
+        // one that does not have it.
+        job_action.dest_verifier = Some(reqwest::Client::new());
+        to_shark.manta_storage_id = String::from("127.0.0.1:1");
+        assert!(!job_action.verify_dest_copy(&eobj, &to_shark));
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let recorded: EvacuateObject = evacuateobjects
+            .filter(id.eq(&eobj.id))
+            .first(&*conn)
+            .expect("recorded object");
+        assert_eq!(recorded.status, EvacuateObjectStatus::Skipped);
+        assert_eq!(
+            recorded.skipped_reason,
+            Some(ObjectSkippedReason::DestinationUnverified)
+        );
+    }
+
+    #[test]
+    fn check_dest_headers_test() {
+        use self::header_mismatches::dsl::header_mismatches;
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+
+        let mut g = StdThreadGen::new(10);
+        let mut eobj = EvacuateObject::arbitrary(&mut g);
+        eobj.shard = 1;
+        eobj.object["headers"] = serde_json::json!({ "m-color": "blue" });
+
+        // The destination serves the copy without the object's header.
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let mut to_shark = generate_storage_node(true);
+        to_shark.manta_storage_id =
+            listener.local_addr().expect("local addr").to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut buf = [0; 4096];
+            let _ = stream.read(&mut buf);
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\
+                      Connection: close\r\n\r\n",
+                )
+                .expect("write response");
+        });
+
+        // Without header_check_percent, nothing is asked.
+        job_action.check_dest_headers(&eobj, &to_shark);
+
+        job_action.config.options.header_check_percent = 100;
+        job_action.header_checker = Some(reqwest::Client::new());
+        job_action.check_dest_headers(&eobj, &to_shark);
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let recorded: Vec<HeaderMismatchEntry> =
+            header_mismatches.load(&*conn).expect("header mismatches");
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].id, eobj.id);
+        assert_eq!(
+            recorded[0].mismatches,
+            serde_json::json!([{
+                "header": "m-color",
+                "expected": "blue",
+                "actual": null,
+            }])
+        );
+    }
+
+    #[test]
+    fn metadata_audit_test() {
+        unit_test_init();
+        let job_action = create_test_evacuate_job(10);
+
+        let mut g = StdThreadGen::new(10);
+        let mut obj = MantaObject::arbitrary(&mut g);
+        obj.sharks[0] = job_action.from_shark.clone();
+        let obj_value = serde_json::to_value(obj.clone()).expect("obj value");
+        let old_sharks = object_sharks(&obj_value);
+
+        let to_shark = generate_storage_node(true);
+        let updated = job_action
+            .update_object_shark(obj_value, &to_shark)
+            .expect("update object shark");
+
+        job_action.audit_put(&old_sharks, &updated, "etag");
+
+        let conn = job_action.conn.lock().expect("db conn lock");
+        let entries = metadata_audit(&conn, 10, 0).expect("metadata audit");
+        assert_eq!(entries.len(), 1);
+
+        let entry = &entries[0];
+        assert_eq!(entry.object_id, obj.object_id);
+        assert_eq!(entry.etag, "etag");
+        assert_eq!(
+            entry.old_sharks,
+            serde_json::to_value(&obj.sharks).unwrap()
+        );
+        assert_eq!(entry.new_sharks, object_sharks(&updated));
+        assert_ne!(entry.old_sharks, entry.new_sharks);
+
+        assert!(metadata_audit(&conn, 10, 1).expect("audit").is_empty());
+    }
+
+    #[test]
+    fn source_copies_update_test() {
+        unit_test_init();
+        let job_action = create_test_evacuate_job(10);
+
+        let mut g = StdThreadGen::new(10);
+        let mut obj = MantaObject::arbitrary(&mut g);
+        obj.sharks =
+            vec![job_action.from_shark.clone(), job_action.from_shark.clone()];
+        let obj_value = serde_json::to_value(obj.clone()).expect("obj value");
+
+        let mut first = generate_storage_node(false);
+        first.manta_storage_id = String::from("1.stor.joyent.us");
+        let mut second = generate_storage_node(false);
+        second.manta_storage_id = String::from("2.stor.joyent.us");
+
+        // Without the first copy having been made, moving both copies to the
+        // one destination conflicts, and the update is refused.
+        assert!(job_action
+            .update_object_shark(obj_value.clone(), &second)
+            .is_err());
+
+        job_action
+            .source_copies
+            .plan(&obj.object_id, vec![first.clone(), second.clone()]);
+        assert!(job_action
+            .update_object_shark(obj_value.clone(), &second)
+            .is_err());
+
+        // Once it has, both copies are replaced in the one update.
+        assert!(job_action.source_copies.copied(&obj.object_id, &first));
+        let updated = job_action
+            .update_object_shark(obj_value.clone(), &second)
+            .expect("update object shark");
+        let sharks =
+            common::get_sharks_from_value(&updated).expect("updated sharks");
+        let ids: Vec<&str> =
+            sharks.iter().map(|s| s.manta_storage_id.as_str()).collect();
+        assert_eq!(ids, vec!["1.stor.joyent.us", "2.stor.joyent.us"]);
+
+        // The last copy may not go where the first did.
+        assert!(job_action.update_object_shark(obj_value, &first).is_err());
+    }
+
+    #[test]
+    fn metadata_rebase_test() {
+        unit_test_init();
+        let job_action = create_test_evacuate_job(10);
+
+        let mut g = StdThreadGen::new(10);
+        let mut obj = MantaObject::arbitrary(&mut g);
+        obj.sharks[0] = job_action.from_shark.clone();
+        let mut to_shark = generate_storage_node(false);
+        to_shark.manta_storage_id = String::from("1.stor.joyent.us");
+
+        // The object was changed, but is still on the evacuated shark: the
+        // change is made to it as it now is.
+        obj.content_type = String::from("text/plain");
+        let fresh = serde_json::to_value(obj.clone()).expect("obj value");
+        let rebased = rebase_metadata_update(
+            &job_action,
+            &obj.object_id,
+            fresh,
+            &to_shark,
+        )
+        .expect("rebase")
+        .expect("rebased object");
+        assert_eq!(rebased["contentType"], "text/plain");
+        let sharks =
+            common::get_sharks_from_value(&rebased).expect("rebased sharks");
+        assert!(sharks
+            .iter()
+            .any(|s| s.manta_storage_id == to_shark.manta_storage_id));
+        assert!(!sharks
+            .iter()
+            .any(|s| s.manta_storage_id
+                == job_action.from_shark.manta_storage_id));
+
+        // An update that already went through leaves nothing to do.
+        assert!(rebase_metadata_update(
+            &job_action,
+            &obj.object_id,
+            rebased.clone(),
+            &to_shark,
+        )
+        .expect("rebase")
+        .is_none());
+
+        // The change no longer applies to an object that has left the
+        // evacuated shark for somewhere else, or to another object.
+        let mut moved = obj.clone();
+        moved.sharks[0].manta_storage_id = String::from("2.stor.joyent.us");
+        let moved = serde_json::to_value(moved).expect("obj value");
+        assert!(rebase_metadata_update(
+            &job_action,
+            &obj.object_id,
+            moved,
+            &to_shark
+        )
+        .is_err());
+
+        let fresh = serde_json::to_value(obj.clone()).expect("obj value");
+        assert!(
+            rebase_metadata_update(&job_action, "other", fresh, &to_shark)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn remove_copy_test() {
+        unit_test_init();
+        let mut job_action = create_test_evacuate_job(10);
+        job_action.set_remove_copy(1).expect("set remove copy");
+        assert!(job_action.is_remove_copy());
+        assert!(!job_action.is_create_copy());
+
+        let mut g = StdThreadGen::new(10);
+        let mut obj = MantaObject::arbitrary(&mut g);
+        obj.sharks[0] = job_action.from_shark.clone();
+        let obj_value = serde_json::to_value(obj.clone()).expect("obj value");
+
+        // The object has 1 copy other than the one to be removed.
+        assert!(!job_action.has_too_few_copies(&obj_value));
+
+        // The job's own shark is its only destination.
+        let shark_list = job_action.get_removal_shark_list();
+        assert_eq!(shark_list.len(), 1);
+        let shark = shark_list[0].shark.clone();
+        assert_eq!(
+            shark.manta_storage_id,
+            job_action.from_shark.manta_storage_id
+        );
+
+        // The copy on the job's shark is removed, and the other is kept.
+        let updated = job_action
+            .update_object_shark(obj_value.clone(), &shark)
+            .expect("update object shark");
+        let sharks =
+            common::get_sharks_from_value(&updated).expect("updated sharks");
+        let ids: Vec<&str> =
+            sharks.iter().map(|s| s.manta_storage_id.as_str()).collect();
+        assert_eq!(ids, vec![obj.sharks[1].manta_storage_id.as_str()]);
+
+        // There is nothing left to remove, and the last copy must stay.
+        assert!(job_action.update_object_shark(updated, &shark).is_err());
+
+        job_action.set_remove_copy(2).expect("set remove copy");
+        assert!(job_action.has_too_few_copies(&obj_value));
+    }
+
     #[test]
     fn validate_destination_test() {
         unit_test_init();
@@ -5224,8 +11422,12 @@ mod tests {
             ),
             EvacuateJobType::Retry(retry_uuid) => {
                 // start local db generator
-                start_local_db_generator(obj_tx, retry_uuid)
-                    .expect("local db generator")
+                start_local_db_generator(
+                    obj_tx,
+                    Arc::clone(&job_action),
+                    retry_uuid,
+                )
+                .expect("local db generator")
             }
         };
 
@@ -5322,6 +11524,49 @@ mod tests {
         info!("Completed full test: {}", job_id);
     }
 
+    #[test]
+    fn full_test_sharded_md_updates() {
+        unit_test_init();
+
+        let num_objects: usize = 100;
+        let mut test_objects = vec![];
+        let mut g = StdThreadGen::new(10);
+
+        for _ in 0..num_objects {
+            let mobj = MantaObject::arbitrary(&mut g);
+            test_objects.push(mobj);
+        }
+
+        let mut job_action = create_test_evacuate_job(num_objects);
+        let job_id = job_action.db_name.clone();
+        job_action.config.options.use_sharded_md_updates = true;
+        job_action
+            .tunables
+            .update(|t| t.max_metadata_update_threads = 3);
+
+        run_full_test(test_objects, None, job_action);
+        info!("Completed sharded metadata update test: {}", job_id);
+
+        use super::evacuateobjects::dsl::{evacuateobjects, status};
+
+        let conn = pg_db::connect_db(&job_id).expect("job db");
+        let post_processing: Vec<EvacuateObject> = evacuateobjects
+            .filter(status.eq(EvacuateObjectStatus::PostProcessing))
+            .load(&conn)
+            .expect("post processing objects");
+
+        // Every assignment that the agent finished has been completed.
+        assert!(post_processing.is_empty());
+    }
+
+    #[test]
+    fn shard_worker_index_test() {
+        assert_eq!(shard_worker_index(0, 3), 0);
+        assert_eq!(shard_worker_index(4, 3), 1);
+        assert_eq!(shard_worker_index(5, 1), 0);
+        assert_eq!(shard_worker_index(-1, 3), 0);
+    }
+
     fn validate_duplicate_table(job_id: &str, expected_shards: usize) {
         use diesel::sql_query;
         use diesel::sql_types::Integer;
@@ -5414,7 +11659,9 @@ mod tests {
 
         let mut job_action = create_test_evacuate_job(num_objects);
         let job_id = job_action.db_name.clone();
-        job_action.config.options.max_tasks_per_assignment = 1;
+        job_action
+            .tunables
+            .update(|t| t.max_tasks_per_assignment = 1);
 
         run_full_test(test_objects, None, job_action);
         info!("Completed duplicate handler test: {}", job_id);
@@ -5463,7 +11710,10 @@ mod tests {
         )
         .expect("job status");
 
-        let JobStatusResults::Evacuate(results) = job_status_results;
+        let results = match job_status_results {
+            JobStatusResults::Evacuate(results) => results,
+            res => panic!("unexpected job status results: {:?}", res),
+        };
         assert_eq!(results.get("Total"), Some(&(num_objects as i64)));
 
         // Almost all objects will be errors due to bad_moray_client.  But
@@ -0,0 +1,615 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The internal event bus of an evacuate job.
+//
+// Much of what happens as a job runs is of interest to more than the job
+// itself: metrics are counted, the life of each assignment is recorded in the
+// job's database, failures are counted towards error threshold
+// notifications and the job's circuit breaker, the job's ramp up and
+// assignment sizing follow how its objects are faring, and what each agent
+// does is added to its history.  Rather than have the job loop call out to
+// each of these wherever something happens, the job publishes an
+// EvacuateEvent describing what happened, and each of these is a subscriber
+// of the job's EventBus.
+//
+// Every subscriber is given the events on a channel of its own, and handles
+// them on a thread of its own, in the order in which they were published.  A
+// slow subscriber (such as the database writer) therefore holds up neither
+// the job nor any other subscriber.  Because of that, a subscriber may not
+// have seen an event yet when publish() returns.  flush() waits until every
+// subscriber has handled everything published before it was called.
+//
+// Other consumers (e.g. something streaming a job's progress out) can take a
+// channel of events with stream(), without a thread of their own.
+//
+// A new cross-cutting feature should be another subscriber, so that the job
+// loop itself does not change.  Gauges that describe the job's own threads
+// and queues are still set directly, since they are not things happening to
+// objects or assignments.
+
+use super::breaker::CircuitBreaker;
+use super::evacuate::{
+    insert_assignment_event, AssignmentEvent, EvacuateObjectError,
+};
+use super::history::HistoryRecorder;
+use super::ramp::RampSchedule;
+use super::sizing::AssignmentSizer;
+use super::watchdog;
+use super::{AssignmentId, StorageId};
+use crate::joblog;
+use crate::metrics::{
+    metrics_error_inc, metrics_object_inc_by, metrics_object_size_observe,
+    metrics_shark_inc_by, metrics_skip_inc_by, ACTION_EVACUATE, SHARK_ASSIGNED,
+    SHARK_COMPLETED, SHARK_FAILED,
+};
+use crate::notify::FailureTracker;
+use crate::pg_db;
+use rebalancer::common::ObjectSkippedReason;
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel as crossbeam;
+use diesel::pg::PgConnection;
+
+/// Something that happened in an evacuate job.
+#[derive(Debug, Clone)]
+pub enum EvacuateEvent {
+    /// The metadata of objects moved to `dest_shark` has been updated.
+    /// `sizes` has the size in bytes of each object.
+    ObjectsMoved {
+        dest_shark: StorageId,
+        sizes: Vec<u64>,
+    },
+
+    /// `count` objects will not be moved by this job.  `assigned` objects
+    /// had already been given to an agent.  `sizes` has the size of each of
+    /// them that was known when they were given up on.
+    ObjectsNotMoved {
+        reason: Option<ObjectSkippedReason>,
+        assigned: bool,
+        count: usize,
+        sizes: Vec<u64>,
+    },
+
+    /// An object was found to be in error.
+    ErrorObserved { error: EvacuateObjectError },
+
+    /// An assignment of `tasks` tasks, of `bytes` bytes in total, was handed
+    /// off to be posted to `dest_shark`.
+    AssignmentSent {
+        dest_shark: StorageId,
+        tasks: usize,
+        bytes: u64,
+    },
+
+    /// Tasks of an assignment to `dest_shark` failed.  `sizes` has the size
+    /// of each failed object, where it is known.
+    AssignmentFailed {
+        dest_shark: StorageId,
+        tasks: usize,
+        bytes: u64,
+        sizes: Vec<u64>,
+    },
+
+    /// An assignment was accepted by the agent on `dest_shark`.
+    AssignmentPosted {
+        assignment_id: AssignmentId,
+        dest_shark: StorageId,
+        tasks: usize,
+    },
+
+    /// An assignment of `tasks` tasks could not be posted to `dest_shark`.
+    AssignmentPostFailed { dest_shark: StorageId, tasks: usize },
+
+    /// The agent reported that it had finished an assignment, `failed` of
+    /// whose `total` tasks failed.
+    AssignmentCompleted {
+        assignment_id: AssignmentId,
+        total: usize,
+        failed: usize,
+    },
+
+    /// A step in the life of an assignment.  See AssignmentEvent.
+    AssignmentTransition {
+        assignment_id: AssignmentId,
+        event: AssignmentEvent,
+        detail: Option<String>,
+    },
+}
+
+/// Something that handles the events published by a job.
+pub trait EventSubscriber: Send {
+    /// A name for the subscriber's thread and log messages.
+    fn name(&self) -> &'static str;
+
+    fn handle(&mut self, event: &EvacuateEvent);
+}
+
+enum BusMessage {
+    Event(Arc<EvacuateEvent>),
+    Flush(crossbeam::Sender<()>),
+}
+
+struct Subscription {
+    tx: crossbeam::Sender<BusMessage>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub struct EventBus {
+    job_id: String,
+    subscriptions: RwLock<Vec<Subscription>>,
+    streams: Mutex<Vec<crossbeam::Sender<Arc<EvacuateEvent>>>>,
+}
+
+impl EventBus {
+    pub fn new(job_id: &str) -> EventBus {
+        EventBus {
+            job_id: job_id.to_string(),
+            subscriptions: RwLock::new(vec![]),
+            streams: Mutex::new(vec![]),
+        }
+    }
+
+    /// Start handing events to `subscriber`, on a thread of its own.  It is
+    /// only given events published after this returns.
+    pub fn subscribe<S>(&self, mut subscriber: S) -> Result<(), std::io::Error>
+    where
+        S: EventSubscriber + 'static,
+    {
+        let (tx, rx) = crossbeam::unbounded();
+        let job_id = self.job_id.clone();
+        let name = subscriber.name();
+
+        let thread = thread::Builder::new()
+            .name(format!("{} events", name))
+            .spawn(joblog::inherit(move || {
+                for msg in rx.iter() {
+                    let event = match msg {
+                        BusMessage::Event(event) => event,
+                        BusMessage::Flush(ack) => {
+                            ack.send(()).unwrap_or(());
+                            continue;
+                        }
+                    };
+
+                    // One bad event is not a reason to stop handling the
+                    // rest of them.
+                    let res =
+                        watchdog::catch_panic(|| subscriber.handle(&event));
+                    if res.is_err() {
+                        error!(
+                            "Job {}: {} could not handle {:?}",
+                            job_id, name, event
+                        );
+                    }
+                }
+            }))?;
+
+        self.subscriptions.write().expect("event bus lock").push(
+            Subscription {
+                tx,
+                thread: Some(thread),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// A channel of the events published from now on.  Dropping the
+    /// receiver ends the stream.
+    pub fn stream(&self) -> crossbeam::Receiver<Arc<EvacuateEvent>> {
+        let (tx, rx) = crossbeam::unbounded();
+        self.streams.lock().expect("event stream lock").push(tx);
+        rx
+    }
+
+    pub fn publish(&self, event: EvacuateEvent) {
+        let event = Arc::new(event);
+
+        for sub in self.subscriptions.read().expect("event bus lock").iter() {
+            if sub.tx.send(BusMessage::Event(Arc::clone(&event))).is_err() {
+                warn!("Job {}: event subscriber has gone away", self.job_id);
+            }
+        }
+
+        let mut streams = self.streams.lock().expect("event stream lock");
+        streams.retain(|tx| tx.send(Arc::clone(&event)).is_ok());
+    }
+
+    /// Wait until every subscriber has handled every event published before
+    /// this was called.
+    pub fn flush(&self) {
+        let acks: Vec<crossbeam::Receiver<()>> = self
+            .subscriptions
+            .read()
+            .expect("event bus lock")
+            .iter()
+            .filter_map(|sub| {
+                let (ack_tx, ack_rx) = crossbeam::bounded(1);
+                sub.tx.send(BusMessage::Flush(ack_tx)).ok().map(|_| ack_rx)
+            })
+            .collect();
+
+        for ack in acks {
+            ack.recv().unwrap_or(());
+        }
+    }
+
+    /// Let every subscriber handle the events already published, and then
+    /// stop them.  Events published after this are dropped.
+    pub fn close(&self) {
+        let subscriptions: Vec<Subscription> = self
+            .subscriptions
+            .write()
+            .expect("event bus lock")
+            .drain(..)
+            .collect();
+
+        for mut sub in subscriptions {
+            let thread = sub.thread.take();
+            drop(sub);
+            if let Some(thread) = thread {
+                thread.join().unwrap_or_else(|_| {
+                    error!("Job {}: event subscriber panicked", self.job_id)
+                });
+            }
+        }
+
+        self.streams.lock().expect("event stream lock").clear();
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Counts the job's objects and assignments in the manager's metrics.
+pub struct MetricsRecorder;
+
+impl EventSubscriber for MetricsRecorder {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        match event {
+            EvacuateEvent::ObjectsMoved { dest_shark, sizes } => {
+                for bytes in sizes.iter() {
+                    metrics_object_size_observe(*bytes, false);
+                }
+                metrics_shark_inc_by(
+                    dest_shark,
+                    SHARK_COMPLETED,
+                    sizes.len(),
+                    sizes.iter().sum(),
+                );
+                metrics_object_inc_by(Some(ACTION_EVACUATE), sizes.len());
+            }
+            EvacuateEvent::ObjectsNotMoved {
+                reason,
+                count,
+                sizes,
+                ..
+            } => {
+                if let Some(reason) = reason {
+                    metrics_skip_inc_by(Some(&reason.to_string()), *count);
+                }
+                for bytes in sizes.iter() {
+                    metrics_object_size_observe(*bytes, true);
+                }
+            }
+            EvacuateEvent::ErrorObserved { error } => {
+                metrics_error_inc(Some(&error.rebalancer_error().to_string()));
+            }
+            EvacuateEvent::AssignmentSent {
+                dest_shark,
+                tasks,
+                bytes,
+            } => {
+                metrics_shark_inc_by(
+                    dest_shark,
+                    SHARK_ASSIGNED,
+                    *tasks,
+                    *bytes,
+                );
+            }
+            EvacuateEvent::AssignmentFailed {
+                dest_shark,
+                tasks,
+                bytes,
+                sizes,
+            } => {
+                for size in sizes.iter() {
+                    metrics_object_size_observe(*size, true);
+                }
+                metrics_shark_inc_by(dest_shark, SHARK_FAILED, *tasks, *bytes);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Records the life of each assignment in the job's database.
+pub struct AssignmentEventWriter {
+    db_name: String,
+    conn: Option<PgConnection>,
+}
+
+impl AssignmentEventWriter {
+    pub fn new(db_name: &str) -> AssignmentEventWriter {
+        AssignmentEventWriter {
+            db_name: db_name.to_string(),
+            conn: None,
+        }
+    }
+}
+
+impl EventSubscriber for AssignmentEventWriter {
+    fn name(&self) -> &'static str {
+        "assignment events"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        let (assignment_id, event, detail) = match event {
+            EvacuateEvent::AssignmentTransition {
+                assignment_id,
+                event,
+                detail,
+            } => (assignment_id, event, detail),
+            _ => return,
+        };
+
+        // The job's tables are created before any of its assignments are.
+        if self.conn.is_none() {
+            match pg_db::connect_db(&self.db_name) {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    warn!(
+                        "Could not record event '{}' for assignment {}: {}",
+                        event, assignment_id, e
+                    );
+                    return;
+                }
+            }
+        }
+
+        if let Some(conn) = self.conn.as_ref() {
+            insert_assignment_event(
+                conn,
+                assignment_id,
+                *event,
+                detail.clone(),
+            );
+        }
+    }
+}
+
+/// Counts objects that could not be moved towards the job's error threshold
+/// notifications.
+pub struct FailureNotifier {
+    failures: Arc<FailureTracker>,
+}
+
+impl FailureNotifier {
+    pub fn new(failures: Arc<FailureTracker>) -> FailureNotifier {
+        FailureNotifier { failures }
+    }
+}
+
+impl EventSubscriber for FailureNotifier {
+    fn name(&self) -> &'static str {
+        "notifier"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        if let EvacuateEvent::ObjectsNotMoved { count, .. } = event {
+            self.failures.add(*count as u64);
+        }
+    }
+}
+
+/// Counts the objects that the job moves and fails to move towards its
+/// circuit breaker.
+pub struct BreakerMonitor {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl BreakerMonitor {
+    pub fn new(breaker: Arc<CircuitBreaker>) -> BreakerMonitor {
+        BreakerMonitor { breaker }
+    }
+}
+
+impl EventSubscriber for BreakerMonitor {
+    fn name(&self) -> &'static str {
+        "breaker"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        match event {
+            EvacuateEvent::ObjectsMoved { sizes, .. } => {
+                self.breaker.moved(sizes.len() as u64);
+            }
+            EvacuateEvent::ObjectsNotMoved { count, .. } => {
+                self.breaker.failed(*count as u64);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Feeds how the job's objects and assignments are faring back into its
+/// ramp up and assignment sizing.  Objects that are given up on before they
+/// are assigned do not count against the ramp.
+pub struct JobFeedback {
+    ramp: Arc<RampSchedule>,
+    sizing: Arc<AssignmentSizer>,
+}
+
+impl JobFeedback {
+    pub fn new(
+        ramp: Arc<RampSchedule>,
+        sizing: Arc<AssignmentSizer>,
+    ) -> JobFeedback {
+        JobFeedback { ramp, sizing }
+    }
+}
+
+impl EventSubscriber for JobFeedback {
+    fn name(&self) -> &'static str {
+        "feedback"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        match event {
+            EvacuateEvent::ObjectsMoved { sizes, .. } => {
+                self.ramp.moved(sizes.len() as u64);
+            }
+            EvacuateEvent::ObjectsNotMoved {
+                assigned: true,
+                count,
+                ..
+            } => {
+                self.ramp.failed(*count as u64);
+            }
+            EvacuateEvent::AssignmentPosted {
+                assignment_id,
+                dest_shark,
+                tasks,
+            } => {
+                self.sizing.posted(assignment_id, dest_shark, *tasks);
+            }
+            EvacuateEvent::AssignmentPostFailed { dest_shark, tasks } => {
+                self.sizing.post_failed(dest_shark, *tasks);
+            }
+            EvacuateEvent::AssignmentCompleted {
+                assignment_id,
+                total,
+                failed,
+            } => {
+                self.sizing.completed(assignment_id, *total, *failed);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Adds what each of the job's destination agents does to its history
+/// across jobs.
+pub struct HistoryMonitor {
+    recorder: HistoryRecorder,
+}
+
+impl HistoryMonitor {
+    pub fn new(recorder: HistoryRecorder) -> HistoryMonitor {
+        HistoryMonitor { recorder }
+    }
+}
+
+impl EventSubscriber for HistoryMonitor {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+
+    fn handle(&mut self, event: &EvacuateEvent) {
+        match event {
+            EvacuateEvent::ObjectsMoved { dest_shark, sizes } => {
+                self.recorder.moved(dest_shark, sizes.iter().sum());
+            }
+            EvacuateEvent::AssignmentPosted {
+                assignment_id,
+                dest_shark,
+                ..
+            } => {
+                self.recorder.posted(assignment_id, dest_shark);
+            }
+            EvacuateEvent::AssignmentPostFailed { dest_shark, .. } => {
+                self.recorder.post_failed(dest_shark);
+            }
+            EvacuateEvent::AssignmentCompleted {
+                assignment_id,
+                total,
+                failed,
+            } => {
+                self.recorder.completed(assignment_id, *total, *failed);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Collector(Arc<Mutex<Vec<String>>>);
+
+    impl EventSubscriber for Collector {
+        fn name(&self) -> &'static str {
+            "collector"
+        }
+
+        fn handle(&mut self, event: &EvacuateEvent) {
+            match event {
+                EvacuateEvent::AssignmentTransition {
+                    assignment_id, ..
+                } => {
+                    // Slow enough that the publisher gets ahead.
+                    thread::sleep(Duration::from_millis(10));
+                    self.0.lock().unwrap().push(assignment_id.clone());
+                }
+                _ => panic!("unexpected event"),
+            }
+        }
+    }
+
+    fn transition(id: &str) -> EvacuateEvent {
+        EvacuateEvent::AssignmentTransition {
+            assignment_id: id.to_string(),
+            event: AssignmentEvent::Created,
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn bus_delivers_in_order() {
+        let bus = EventBus::new("job");
+        let seen = Arc::new(Mutex::new(vec![]));
+
+        bus.subscribe(Collector(Arc::clone(&seen)))
+            .expect("subscribe");
+        let stream = bus.stream();
+
+        for i in 0..5 {
+            bus.publish(transition(&i.to_string()));
+        }
+
+        // An event that the subscriber can not handle does not stop it.
+        bus.publish(EvacuateEvent::ErrorObserved {
+            error: EvacuateObjectError::InternalError,
+        });
+        bus.publish(transition("5"));
+
+        bus.flush();
+        let expected: Vec<String> = (0..6).map(|i| i.to_string()).collect();
+        assert_eq!(*seen.lock().unwrap(), expected);
+        assert_eq!(stream.try_iter().count(), 7);
+
+        // Events published after the bus is closed go nowhere.
+        bus.close();
+        bus.publish(transition("6"));
+        assert_eq!(seen.lock().unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,358 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Export of the outcome of every object in a job, for loading into analytics
+// tooling, or for keeping as a record of what a job moved.  A job may have on
+// the order of 100 million objects, so they are read from the job's local
+// database a chunk at a time and written out as they are read.
+//
+// Parquet is for analysis.  CSV and NDJSON have a line for each object, with
+// the shark that it was moved from and the one that it was moved to, and are
+// what the manager streams from GET /jobs/<uuid>/export.
+
+use super::evacuate::{
+    self, EvacuateJobDbConfig, EvacuateObject, EvacuateObjectError,
+};
+use crate::parquet::{Column, ColumnType, Datum, ParquetWriter};
+use crate::pg_db;
+use rebalancer::common::ObjectSkippedReason;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+
+use diesel::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+// The number of objects read from the database at a time.
+static EXPORT_CHUNK_SIZE: i64 = 10_000;
+
+// The number of rows in each Parquet row group.  Readers generally process
+// a row group at a time, so this keeps each one to a few tens of megabytes.
+static PARQUET_ROW_GROUP_SIZE: usize = 100_000;
+
+// The columns of a CSV export, in the order of the fields of ExportRecord.
+static CSV_HEADER: &str = "id,key,assignment_id,shard,status,source,\
+                           destination,bytes,skipped_reason,error,\
+                           error_code";
+
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExportFormat {
+    Parquet,
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Parquet => "application/octet-stream",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// The outcome of one object, as a line of a CSV or NDJSON export.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ExportRecord {
+    pub id: String,
+    pub key: Option<String>,
+    pub assignment_id: String,
+    pub shard: i32,
+    pub status: String,
+    // The shark that the job moved objects from.
+    pub source: String,
+    pub destination: String,
+    pub bytes: Option<i64>,
+    pub skipped_reason: Option<String>,
+    pub error: Option<String>,
+    // The code of the skipped reason or the error (see RebalancerError).
+    pub error_code: Option<String>,
+}
+
+// The code of what went wrong with an object, if anything did.
+fn error_code(eobj: &EvacuateObject) -> Option<String> {
+    eobj.skipped_reason
+        .as_ref()
+        .map(ObjectSkippedReason::rebalancer_error)
+        .or_else(|| eobj.error.map(EvacuateObjectError::rebalancer_error))
+        .map(|e| e.to_string())
+}
+
+impl ExportRecord {
+    fn new(eobj: EvacuateObject, source: &str) -> ExportRecord {
+        ExportRecord {
+            error_code: error_code(&eobj),
+            key: eobj
+                .object
+                .get("key")
+                .and_then(|k| k.as_str())
+                .map(String::from),
+            bytes: eobj.object.get("contentLength").and_then(|cl| cl.as_i64()),
+            id: eobj.id,
+            assignment_id: eobj.assignment_id,
+            shard: eobj.shard,
+            status: eobj.status.to_string(),
+            source: source.to_string(),
+            destination: eobj.dest_shark,
+            skipped_reason: eobj.skipped_reason.map(|r| r.to_string()),
+            error: eobj.error.map(|e| e.to_string()),
+        }
+    }
+
+    fn csv_line(&self) -> String {
+        let shard = self.shard.to_string();
+        let bytes = self.bytes.map(|b| b.to_string()).unwrap_or_default();
+        let fields = [
+            self.id.as_str(),
+            self.key.as_ref().map_or("", String::as_str),
+            self.assignment_id.as_str(),
+            shard.as_str(),
+            self.status.as_str(),
+            self.source.as_str(),
+            self.destination.as_str(),
+            bytes.as_str(),
+            self.skipped_reason.as_ref().map_or("", String::as_str),
+            self.error.as_ref().map_or("", String::as_str),
+            self.error_code.as_ref().map_or("", String::as_str),
+        ];
+
+        let fields: Vec<Cow<str>> =
+            fields.iter().map(|f| csv_field(f)).collect();
+        format!("{}\n", fields.join(","))
+    }
+}
+
+// Quote a CSV field if it has to be, as keys may have commas, quotes or new
+// lines in them.
+fn csv_field(field: &str) -> Cow<str> {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r')
+    {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn parquet_columns() -> Vec<Column> {
+    vec![
+        Column::required("id", ColumnType::Utf8),
+        Column::optional("key", ColumnType::Utf8),
+        Column::required("assignment_id", ColumnType::Utf8),
+        Column::required("shard", ColumnType::Int32),
+        Column::required("dest_shark", ColumnType::Utf8),
+        Column::required("status", ColumnType::Utf8),
+        Column::optional("skipped_reason", ColumnType::Utf8),
+        Column::optional("error", ColumnType::Utf8),
+        Column::optional("content_length", ColumnType::Int64),
+        Column::optional("error_code", ColumnType::Utf8),
+    ]
+}
+
+fn parquet_row(eobj: EvacuateObject) -> Vec<Datum> {
+    let key = eobj
+        .object
+        .get("key")
+        .and_then(|k| k.as_str())
+        .map_or(Datum::Null, |k| Datum::Utf8(k.to_string()));
+    let content_length = eobj
+        .object
+        .get("contentLength")
+        .and_then(|cl| cl.as_i64())
+        .map_or(Datum::Null, Datum::Int64);
+    let code = error_code(&eobj).map_or(Datum::Null, Datum::Utf8);
+
+    vec![
+        Datum::Utf8(eobj.id),
+        key,
+        Datum::Utf8(eobj.assignment_id),
+        Datum::Int32(eobj.shard),
+        Datum::Utf8(eobj.dest_shark),
+        Datum::Utf8(eobj.status.to_string()),
+        eobj.skipped_reason
+            .map_or(Datum::Null, |r| Datum::Utf8(r.to_string())),
+        eobj.error
+            .map_or(Datum::Null, |e| Datum::Utf8(e.to_string())),
+        content_length,
+        code,
+    ]
+}
+
+// The shark that the job moved objects from, as recorded in its config table.
+fn job_source(conn: &PgConnection) -> String {
+    use super::evacuate::config::dsl::config as config_table;
+
+    config_table
+        .first::<EvacuateJobDbConfig>(conn)
+        .ok()
+        .and_then(|c| {
+            c.from_shark
+                .get("manta_storage_id")
+                .and_then(|s| s.as_str())
+                .map(String::from)
+        })
+        .unwrap_or_default()
+}
+
+// Pass every object in the job to `f`, in order of object id.
+fn for_each_object<F>(conn: &PgConnection, mut f: F) -> Result<(), Error>
+where
+    F: FnMut(EvacuateObject) -> Result<(), Error>,
+{
+    let mut last_id: Option<String> = None;
+
+    loop {
+        let objects = evacuate::objects_after(
+            conn,
+            last_id.as_ref().map(String::as_str),
+            EXPORT_CHUNK_SIZE,
+        )?;
+
+        let last = match objects.last() {
+            Some(o) => o.id.clone(),
+            None => return Ok(()),
+        };
+
+        for eobj in objects {
+            f(eobj)?;
+        }
+
+        last_id = Some(last);
+    }
+}
+
+/// Open the database of the job `job_uuid` to export it.
+pub fn open_job(job_uuid: &str) -> Result<PgConnection, Error> {
+    // The job's database is named after its UUID, so make sure that is what
+    // we were given.
+    Uuid::from_str(job_uuid).map_err(Error::from)?;
+
+    pg_db::connect_db(job_uuid).map_err(|e| {
+        Error::from(InternalError::new(
+            Some(InternalErrorCode::DbQuery),
+            format!("Could not open the database of job {}: {}", job_uuid, e),
+        ))
+    })
+}
+
+/// Write one row for every object in a job to `out`, in the given format.
+/// Returns the number of objects written.
+pub fn export_job<W: Write>(
+    job_uuid: &str,
+    format: ExportFormat,
+    out: W,
+) -> Result<u64, Error> {
+    let conn = open_job(job_uuid)?;
+
+    export_objects(&conn, format, out)
+}
+
+/// As export_job(), for a job whose database is already open.
+pub fn export_objects<W: Write>(
+    conn: &PgConnection,
+    format: ExportFormat,
+    mut out: W,
+) -> Result<u64, Error> {
+    let source = job_source(conn);
+    let mut count = 0;
+
+    match format {
+        ExportFormat::Parquet => {
+            let mut writer = ParquetWriter::new(
+                out,
+                parquet_columns(),
+                PARQUET_ROW_GROUP_SIZE,
+            )?;
+
+            for_each_object(conn, |eobj| {
+                writer.write_row(&parquet_row(eobj)).map_err(Error::from)
+            })?;
+
+            count = writer.num_rows() as u64;
+            writer.finish()?;
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "{}", CSV_HEADER)?;
+
+            for_each_object(conn, |eobj| {
+                count += 1;
+                let line = ExportRecord::new(eobj, &source).csv_line();
+                out.write_all(line.as_bytes()).map_err(Error::from)
+            })?;
+
+            out.flush()?;
+        }
+        ExportFormat::Ndjson => {
+            for_each_object(conn, |eobj| {
+                count += 1;
+                serde_json::to_writer(
+                    &mut out,
+                    &ExportRecord::new(eobj, &source),
+                )?;
+                out.write_all(b"\n").map_err(Error::from)
+            })?;
+
+            out.flush()?;
+        }
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_csv_line() {
+        let mut record = ExportRecord {
+            id: String::from("obj"),
+            key: Some(String::from("/poseidon/stor/a,b")),
+            assignment_id: String::from("assign"),
+            shard: 2,
+            status: String::from("complete"),
+            source: String::from("1.stor"),
+            destination: String::from("2.stor"),
+            bytes: Some(10),
+            skipped_reason: None,
+            error: None,
+            error_code: None,
+        };
+
+        assert_eq!(
+            record.csv_line(),
+            "obj,\"/poseidon/stor/a,b\",assign,2,complete,1.stor,2.stor,10,,,\n"
+        );
+        assert_eq!(CSV_HEADER.split(',').count(), 11);
+
+        let skipped = EvacuateObject {
+            skipped_reason: Some(ObjectSkippedReason::HTTPStatusCode(503)),
+            ..Default::default()
+        };
+        assert_eq!(error_code(&skipped), Some(String::from("source_error")));
+
+        let failed = EvacuateObject {
+            error: Some(EvacuateObjectError::BadMorayClient),
+            ..Default::default()
+        };
+        assert_eq!(error_code(&failed), Some(String::from("unreachable")));
+        assert_eq!(error_code(&EvacuateObject::default()), None);
+
+        record.key = Some(String::from("say \"hi\""));
+        record.bytes = None;
+        assert!(record
+            .csv_line()
+            .starts_with("obj,\"say \"\"hi\"\"\",assign,"));
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Working on only some of the objects on a shark.
+//
+// A migration is often done in phases: the largest objects first, to free the
+// most space soonest, or one tenant at a time.  An evacuate, create-copy or
+// remove-copy job can be given a `filter` of the objects that it works on: the
+// owner_uuid of the objects, a range of sizes, and a range of creation times.
+// Each object that sharkspotter finds with a copy on the job's shark is
+// matched against the filter before anything else is done with it, and one
+// that does not match is left where it is and not recorded in the job's
+// database, so it is neither skipped nor retried.  Such objects are counted
+// in the `filtered` disposition of the record_disposition_count metric.
+//
+// Objects are immutable, so the mtime in an object's metadata is when it was
+// created.  An object whose metadata is missing a field that the filter asks
+// about does not match.
+//
+// The filter is recorded in the filter_config table of the job's database, so
+// that a retry or resumed job keeps to it, and is reported in the job's
+// status config.
+
+use rebalancer::error::Error;
+pub use rebalancer_client::jobs::ObjectFilter;
+
+use diesel::prelude::*;
+use serde_json::Value;
+
+table! {
+    use diesel::sql_types::{Integer, Jsonb};
+    filter_config (id) {
+        id -> Integer,
+        filter -> Jsonb,
+    }
+}
+
+#[derive(Insertable, AsChangeset, Queryable)]
+#[table_name = "filter_config"]
+struct FilterDbConfig {
+    id: i32,
+    filter: Value,
+}
+
+pub fn create_filter_config_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE filter_config(
+        id Integer PRIMARY KEY,
+        filter Jsonb NOT NULL
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE filter_config") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// Record the job's filter.  There is only a single entry.
+pub fn record_filter(
+    conn: &PgConnection,
+    filter: &ObjectFilter,
+) -> Result<usize, Error> {
+    use self::filter_config::dsl::{filter_config as filter_table, id};
+
+    let value = FilterDbConfig {
+        id: 1,
+        filter: serde_json::to_value(filter)?,
+    };
+
+    diesel::insert_into(filter_table)
+        .values(&value)
+        .on_conflict(id)
+        .do_update()
+        .set(&value)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+/// The job's filter.  Jobs without one have no entry, and jobs that were run
+/// before filters were recorded have no table for them.
+pub fn get_filter(conn: &PgConnection) -> Option<ObjectFilter> {
+    use self::filter_config::dsl::filter_config as filter_table;
+
+    filter_table
+        .first::<FilterDbConfig>(conn)
+        .ok()
+        .and_then(|c| serde_json::from_value(c.filter).ok())
+}
+
+// Whether the field `name` of `record` is within `min` to `max`.
+fn in_range(
+    record: &Value,
+    name: &str,
+    min: Option<i64>,
+    max: Option<i64>,
+) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+
+    match record.get(name).and_then(Value::as_i64) {
+        Some(v) => min.map_or(true, |m| v >= m) && max.map_or(true, |m| v <= m),
+        None => false,
+    }
+}
+
+/// Returns true if the object whose metadata is `record` matches `filter`.
+pub fn matches(filter: &ObjectFilter, record: &Value) -> bool {
+    if let Some(owner) = &filter.owner {
+        if record.get("owner").and_then(Value::as_str) != Some(owner) {
+            return false;
+        }
+    }
+
+    let min_size = filter.min_size.map(|s| s as i64);
+    let max_size = filter.max_size.map(|s| s as i64);
+
+    in_range(record, "contentLength", min_size, max_size)
+        && in_range(
+            record,
+            "mtime",
+            filter.created_after,
+            filter.created_before,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OWNER: &str = "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10";
+
+    #[test]
+    fn filter_matches() {
+        let record = serde_json::json!({
+            "owner": OWNER,
+            "contentLength": 4096,
+            "mtime": 1_600_000_000_000i64,
+        });
+
+        assert!(matches(&ObjectFilter::default(), &record));
+
+        let filter = ObjectFilter {
+            owner: Some(OWNER.to_string()),
+            min_size: Some(4096),
+            created_before: Some(1_600_000_000_000),
+            ..ObjectFilter::default()
+        };
+        assert!(matches(&filter, &record));
+
+        let other_owner = ObjectFilter {
+            owner: Some(String::from("b5f1e2d0-8c4a-4f7e-9a1d-6e2c3b4a5f60")),
+            ..ObjectFilter::default()
+        };
+        assert!(!matches(&other_owner, &record));
+
+        let smaller = ObjectFilter {
+            max_size: Some(4095),
+            ..ObjectFilter::default()
+        };
+        assert!(!matches(&smaller, &record));
+
+        let newer = ObjectFilter {
+            created_after: Some(1_600_000_000_001),
+            ..ObjectFilter::default()
+        };
+        assert!(!matches(&newer, &record));
+
+        // An object without an mtime can not be said to be in the range.
+        let no_mtime =
+            serde_json::json!({ "owner": OWNER, "contentLength": 4096 });
+        assert!(!matches(&filter, &no_mtime));
+        assert!(filter.validate().is_ok());
+        assert!(ObjectFilter {
+            min_size: Some(2),
+            max_size: Some(1),
+            ..ObjectFilter::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,481 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// How each agent has done, across every job.
+//
+// A job learns how each of its destinations' agents is keeping up (see the
+// sizing and polling modules), but forgets it when it ends, so every new job
+// starts out sending assignments to a chronically slow or failing agent as
+// readily as to any other, until it finds out for itself.  Instead, with
+// `agent_history.record` set, every job adds what each of its destinations'
+// agents did to the agent_history table of the rebalancer database: the
+// assignments that the agent completed and the time it took over them (from
+// being posted to being reported complete), the assignments that could not be
+// posted to it, its tasks and how many of them failed, and the bytes of the
+// objects that it moved.  This can be got with GET /agents/<id>/history.
+//
+// With `agent_history.placement_hints` also set, each job moves the agents
+// that the history says to avoid to the end of its list of destinations, so
+// that they are only given objects that no other destination will take.  An
+// agent is avoided once it has had at least `agent_history.min_assignments`
+// assignments (completed, or that could not be posted) if:
+//
+//  * more than `agent_history.max_failure_ratio` of those assignments could
+//    not be posted, or of its tasks failed, or
+//  * it moved less than `agent_history.slow_fraction` of the median bytes per
+//    second of all the agents with as much history.
+//
+// The history is read again at most once a minute, so an agent that picks up
+// is given objects again without the job having to be restarted.  Nothing is
+// ever removed from the history, so an agent that has been fixed may need its
+// row deleted to be rid of its reputation faster.
+
+use super::{AssignmentId, StorageId, REBALANCER_DB};
+use crate::config::ConfigAgentHistory;
+use crate::pg_db;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
+use serde::{Deserialize, Serialize};
+
+table! {
+    use diesel::sql_types::{BigInt, Text};
+    agent_history (storage_id) {
+        storage_id -> Text,
+        assignments -> BigInt,
+        post_failures -> BigInt,
+        tasks -> BigInt,
+        failed_tasks -> BigInt,
+        assignment_ms -> BigInt,
+        bytes_moved -> BigInt,
+        last_job_id -> Text,
+        updated -> BigInt,
+    }
+}
+
+static HISTORY_UPSERT: &str = "INSERT INTO agent_history \
+     (storage_id, assignments, post_failures, tasks, failed_tasks, \
+     assignment_ms, bytes_moved, last_job_id, updated) \
+     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+     ON CONFLICT (storage_id) DO UPDATE SET \
+     assignments = agent_history.assignments + EXCLUDED.assignments, \
+     post_failures = agent_history.post_failures + EXCLUDED.post_failures, \
+     tasks = agent_history.tasks + EXCLUDED.tasks, \
+     failed_tasks = agent_history.failed_tasks + EXCLUDED.failed_tasks, \
+     assignment_ms = agent_history.assignment_ms + EXCLUDED.assignment_ms, \
+     bytes_moved = agent_history.bytes_moved + EXCLUDED.bytes_moved, \
+     last_job_id = EXCLUDED.last_job_id, \
+     updated = EXCLUDED.updated";
+
+// How long a job goes by the history it last read.
+static HINTS_REFRESH: Duration = Duration::from_secs(60);
+
+/// What an agent has done for every job that has recorded it.
+#[derive(Clone, Debug, Deserialize, Serialize, Queryable, PartialEq)]
+pub struct AgentHistory {
+    pub storage_id: String,
+
+    /// Assignments that the agent completed.
+    pub assignments: i64,
+
+    /// Assignments that could not be posted to the agent.
+    pub post_failures: i64,
+
+    /// Tasks of the completed assignments, and how many of them failed.
+    pub tasks: i64,
+    pub failed_tasks: i64,
+
+    /// Milliseconds from the completed assignments being posted to their
+    /// being reported complete, added up.
+    pub assignment_ms: i64,
+
+    /// Bytes of the objects that the agent moved.
+    pub bytes_moved: i64,
+
+    /// The job that last added to the history.
+    pub last_job_id: String,
+
+    /// Milliseconds since the epoch at which the history was last added to.
+    pub updated: i64,
+}
+
+impl AgentHistory {
+    /// Bytes moved per second spent on assignments, once any time has been.
+    pub fn throughput(&self) -> Option<f64> {
+        if self.assignment_ms > 0 {
+            Some(self.bytes_moved as f64 * 1000.0 / self.assignment_ms as f64)
+        } else {
+            None
+        }
+    }
+
+    fn ratio(part: i64, whole: i64) -> f64 {
+        if whole > 0 {
+            part as f64 / whole as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// An agent's history, as reported by GET /agents/<id>/history.
+#[derive(Debug, Serialize)]
+pub struct AgentHistoryReport {
+    #[serde(flatten)]
+    pub history: AgentHistory,
+    pub throughput: Option<f64>,
+
+    /// Why jobs avoid the agent, if they do.
+    pub avoided: Option<String>,
+}
+
+/// Additions to an agent's history.
+#[derive(Debug, Default)]
+struct HistoryDelta {
+    assignments: i64,
+    post_failures: i64,
+    tasks: i64,
+    failed_tasks: i64,
+    assignment_ms: i64,
+    bytes_moved: i64,
+}
+
+pub fn create_history_table(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS agent_history(
+            storage_id TEXT PRIMARY KEY,
+            assignments BIGINT NOT NULL,
+            post_failures BIGINT NOT NULL,
+            tasks BIGINT NOT NULL,
+            failed_tasks BIGINT NOT NULL,
+            assignment_ms BIGINT NOT NULL,
+            bytes_moved BIGINT NOT NULL,
+            last_job_id TEXT NOT NULL,
+            updated BIGINT NOT NULL
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+/// Adds what a job sees of its destinations' agents to their history.
+pub struct HistoryRecorder {
+    job_id: String,
+    enabled: bool,
+    conn: Option<PgConnection>,
+
+    // Assignment -> the agent it was posted to, and when.
+    posted: HashMap<AssignmentId, (StorageId, Instant)>,
+}
+
+impl HistoryRecorder {
+    pub fn new(job_id: &str, config: &ConfigAgentHistory) -> HistoryRecorder {
+        HistoryRecorder {
+            job_id: job_id.to_string(),
+            enabled: config.record,
+            conn: None,
+            posted: HashMap::new(),
+        }
+    }
+
+    /// Record that an assignment was posted to `shark`.
+    pub fn posted(&mut self, assignment_id: &str, shark: &str) {
+        if self.enabled {
+            self.posted.insert(
+                assignment_id.to_string(),
+                (shark.to_string(), Instant::now()),
+            );
+        }
+    }
+
+    /// Record that an assignment could not be posted to `shark`.
+    pub fn post_failed(&mut self, shark: &str) {
+        self.add(
+            shark,
+            HistoryDelta {
+                post_failures: 1,
+                ..HistoryDelta::default()
+            },
+        );
+    }
+
+    /// Record that the agent has completed an assignment, `failed` of whose
+    /// `total` tasks failed.  An assignment that this job did not see being
+    /// posted (e.g. because the job was resumed since) is not recorded, as
+    /// the time the agent took over it is not known.
+    pub fn completed(
+        &mut self,
+        assignment_id: &str,
+        total: usize,
+        failed: usize,
+    ) {
+        let (shark, posted) = match self.posted.remove(assignment_id) {
+            Some(p) => p,
+            None => return,
+        };
+
+        self.add(
+            &shark,
+            HistoryDelta {
+                assignments: 1,
+                tasks: total as i64,
+                failed_tasks: failed as i64,
+                assignment_ms: posted.elapsed().as_millis() as i64,
+                ..HistoryDelta::default()
+            },
+        );
+    }
+
+    /// Record that objects of `bytes` bytes in all were moved to `shark`.
+    pub fn moved(&mut self, shark: &str, bytes: u64) {
+        self.add(
+            shark,
+            HistoryDelta {
+                bytes_moved: bytes as i64,
+                ..HistoryDelta::default()
+            },
+        );
+    }
+
+    // The history is only ever of use to later jobs, so an error is logged
+    // rather than failing this one.
+    fn add(&mut self, shark: &str, delta: HistoryDelta) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.conn.is_none() {
+            match pg_db::connect_or_create_db(REBALANCER_DB) {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    warn!("Could not record history of {}: {}", shark, e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(conn) = self.conn.as_ref() {
+            if let Err(e) = sql_query(HISTORY_UPSERT)
+                .bind::<Text, _>(shark)
+                .bind::<BigInt, _>(delta.assignments)
+                .bind::<BigInt, _>(delta.post_failures)
+                .bind::<BigInt, _>(delta.tasks)
+                .bind::<BigInt, _>(delta.failed_tasks)
+                .bind::<BigInt, _>(delta.assignment_ms)
+                .bind::<BigInt, _>(delta.bytes_moved)
+                .bind::<Text, _>(&self.job_id)
+                .bind::<BigInt, _>(now_ms())
+                .execute(conn)
+            {
+                warn!(
+                    "Job {}: could not record history of {}: {}",
+                    self.job_id, shark, e
+                );
+            }
+        }
+    }
+}
+
+/// Why each agent that should be avoided should be, given the history of
+/// every agent.
+pub fn avoided_agents(
+    histories: &[AgentHistory],
+    config: &ConfigAgentHistory,
+) -> HashMap<StorageId, String> {
+    let eligible: Vec<&AgentHistory> = histories
+        .iter()
+        .filter(|h| {
+            h.assignments + h.post_failures >= config.min_assignments as i64
+        })
+        .collect();
+
+    let mut throughputs: Vec<f64> =
+        eligible.iter().filter_map(|h| h.throughput()).collect();
+    throughputs.sort_by(|a, b| a.partial_cmp(b).expect("throughput"));
+    let median = throughputs.get(throughputs.len() / 2).copied();
+
+    let mut avoided = HashMap::new();
+    for h in eligible {
+        let attempted = h.assignments + h.post_failures;
+        let reason = if AgentHistory::ratio(h.post_failures, attempted)
+            > config.max_failure_ratio
+        {
+            format!(
+                "{} of {} assignments could not be posted",
+                h.post_failures, attempted
+            )
+        } else if AgentHistory::ratio(h.failed_tasks, h.tasks)
+            > config.max_failure_ratio
+        {
+            format!("{} of {} tasks failed", h.failed_tasks, h.tasks)
+        } else {
+            match (h.throughput(), median) {
+                (Some(t), Some(m)) if t < m * config.slow_fraction => format!(
+                    "moved {:.0} bytes per second, against a median of {:.0}",
+                    t, m
+                ),
+                _ => continue,
+            }
+        };
+
+        avoided.insert(h.storage_id.clone(), reason);
+    }
+
+    avoided
+}
+
+fn load_histories(conn: &PgConnection) -> Result<Vec<AgentHistory>, Error> {
+    agent_history::table
+        .order(agent_history::storage_id)
+        .load::<AgentHistory>(conn)
+        .map_err(Error::from)
+}
+
+/// The history of the agent on `storage_id`, if it has any.
+pub fn get_agent_history(
+    storage_id: &str,
+    config: &ConfigAgentHistory,
+) -> Result<Option<AgentHistoryReport>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let histories = load_histories(&conn)?;
+    let mut avoided = avoided_agents(&histories, config);
+
+    Ok(histories
+        .into_iter()
+        .find(|h| h.storage_id == storage_id)
+        .map(|history| AgentHistoryReport {
+            throughput: history.throughput(),
+            avoided: avoided.remove(storage_id),
+            history,
+        }))
+}
+
+/// The agents that a job should avoid giving objects to.
+pub struct PlacementHints {
+    config: ConfigAgentHistory,
+    avoided: Mutex<Option<(Instant, HashSet<StorageId>)>>,
+}
+
+impl PlacementHints {
+    pub fn new(config: &ConfigAgentHistory) -> PlacementHints {
+        PlacementHints {
+            config: *config,
+            avoided: Mutex::new(None),
+        }
+    }
+
+    /// The agents to avoid, as of the last time that the history was read.
+    /// If it can not be read, no agent is avoided until it can be.
+    pub fn avoided(&self) -> HashSet<StorageId> {
+        if !self.config.placement_hints {
+            return HashSet::new();
+        }
+
+        let mut avoided = self.avoided.lock().expect("placement hints lock");
+        if let Some((read, sharks)) = avoided.as_ref() {
+            if read.elapsed() < HINTS_REFRESH {
+                return sharks.clone();
+            }
+        }
+
+        let sharks: HashSet<StorageId> =
+            match pg_db::connect_or_create_db(REBALANCER_DB)
+                .and_then(|conn| load_histories(&conn))
+            {
+                Ok(histories) => avoided_agents(&histories, &self.config)
+                    .into_iter()
+                    .map(|(shark, reason)| {
+                        debug!("Avoiding destination {}: {}", shark, reason);
+                        shark
+                    })
+                    .collect(),
+                Err(e) => {
+                    warn!("Could not read agent history: {}", e);
+                    HashSet::new()
+                }
+            };
+
+        *avoided = Some((Instant::now(), sharks.clone()));
+        sharks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(
+        storage_id: &str,
+        assignments: i64,
+        failed_tasks: i64,
+        bytes_per_sec: i64,
+    ) -> AgentHistory {
+        AgentHistory {
+            storage_id: storage_id.to_string(),
+            assignments,
+            post_failures: 0,
+            tasks: assignments * 100,
+            failed_tasks,
+            assignment_ms: assignments * 1000,
+            bytes_moved: assignments * bytes_per_sec,
+            last_job_id: String::from("job"),
+            updated: 0,
+        }
+    }
+
+    #[test]
+    fn avoided_agents_test() {
+        let config = ConfigAgentHistory::default();
+        let mut histories = vec![
+            history("1.stor.domain", 20, 0, 1000),
+            history("2.stor.domain", 20, 0, 900),
+            history("3.stor.domain", 20, 0, 1100),
+            // Slow.
+            history("4.stor.domain", 20, 0, 100),
+            // Failing.
+            history("5.stor.domain", 20, 1000, 1000),
+            // Slow, but too new to tell.
+            history("6.stor.domain", 2, 0, 1),
+        ];
+        let mut unpostable = history("7.stor.domain", 5, 0, 1000);
+        unpostable.post_failures = 15;
+        histories.push(unpostable);
+
+        let avoided = avoided_agents(&histories, &config);
+        let mut sharks: Vec<&str> =
+            avoided.keys().map(String::as_str).collect();
+        sharks.sort();
+
+        assert_eq!(
+            sharks,
+            vec!["4.stor.domain", "5.stor.domain", "7.stor.domain"]
+        );
+        assert_eq!(avoided["5.stor.domain"], "1000 of 2000 tasks failed");
+        assert_eq!(
+            avoided["7.stor.domain"],
+            "15 of 20 assignments could not be posted"
+        );
+
+        // An agent with no time spent on assignments can not be slow.
+        let idle = AgentHistory {
+            assignment_ms: 0,
+            ..history("8.stor.domain", 20, 0, 0)
+        };
+        assert!(avoided_agents(&[idle], &config).is_empty());
+    }
+}
@@ -0,0 +1,737 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Running more than one manager against the same deployment.
+//
+// The jobs table, and the database of each job, can be kept on a PostgreSQL
+// server of their own (see pg_db), which any number of managers can share, so
+// that losing a manager zone does not stop the jobs it was running.  With
+// `coordination.enabled`, which job each manager runs is settled by leases,
+// kept in the leases table of the jobs database:
+//
+//  * A job is run by the manager that holds its lease.  The manager that
+//    queues a job takes out its lease first (see claim()), and releases it
+//    once the job has finished running.  A lease is only taken out on a job
+//    that has no lease, or whose lease has lapsed, or that is already leased
+//    to the same manager, in a single statement, so no two managers ever hold
+//    the same lease.
+//  * Each manager renews all of its leases three times in every
+//    `coordination.lease_secs`, and records that it is alive in the managers
+//    table as it does.  A lease lapses once it has gone `lease_secs` without
+//    being renewed.
+//  * At each renewal a manager looks for the jobs that are running, queued or
+//    interrupted according to the jobs table but that no manager holds a
+//    lease on, because the manager that ran them died, lost the database or
+//    was shut down.  It takes out a lease on each of them that it can, and
+//    then recovers and resumes it just as a manager that had been restarted
+//    would its own (see jobs::recover_crashed_jobs()).  The jobs of a manager
+//    are thus failed over to whichever of the others gets to them first, or
+//    to the manager itself if it is back first, since its leases are held
+//    under its `coordination.manager_id`.
+//  * A manager that finds that another has taken one of its leases, or that
+//    has not been able to renew them for two thirds of `lease_secs`, can no
+//    longer be sure that no other manager is running its jobs, and so exits
+//    at once rather than carry on with them.  The jobs are then recovered by
+//    whichever manager takes them over, as those of a manager that crashed.
+//    How long the leases have gone without being renewed is watched by a
+//    thread of its own (the lease fence), so that a renewal stuck on the
+//    database does not hold up the exit.  No statement run for a lease, nor
+//    connecting to run it, may take longer than a third of `lease_secs`
+//    either.
+//  * Evacuation plans are followed, and expired jobs archived, by only one
+//    manager at a time: the one that holds the COORDINATOR_LEASE.
+//
+// The list of jobs, and the status of each, are read from the shared
+// database, so they can be had from any manager, along with which manager
+// holds each job's lease (`owner`) and the managers themselves (GET
+// /managers).  Whatever a manager holds only in memory is its own, though: a
+// running job can only be changed through the manager that runs it, and each
+// manager only knows of the agents that register with it, and of what its
+// own jobs are sending each destination.
+
+use crate::config::ConfigCoordination;
+use crate::pg_db;
+use crate::shutdown;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+use rebalancer_client::jobs::JobState;
+
+use std::collections::HashSet;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Array, BigInt, Text};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// The lease held by the one manager that follows evacuation plans and
+/// archives expired jobs.
+pub static COORDINATOR_LEASE: &str = "coordinator";
+
+// Managers that have not been heard from for this long are dropped from the
+// managers table.
+static MANAGER_RETENTION_MS: i64 = 24 * 60 * 60 * 1000;
+
+table! {
+    use diesel::sql_types::{BigInt, Text};
+    leases (name) {
+        name -> Text,
+        owner -> Text,
+        acquired -> BigInt,
+        expires -> BigInt,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Nullable, Text};
+    managers (id) {
+        id -> Text,
+        url -> Nullable<Text>,
+        started -> BigInt,
+        heartbeat -> BigInt,
+    }
+}
+
+#[derive(Insertable, AsChangeset, Queryable)]
+#[table_name = "managers"]
+struct ManagerEntry {
+    id: String,
+    url: Option<String>,
+    started: i64,
+    heartbeat: i64,
+}
+
+#[derive(QueryableByName)]
+struct JobIdRow {
+    #[sql_type = "Text"]
+    id: String,
+}
+
+// Take out the lease `$1` for `$2`, as of `$3`, until `$4`, unless another
+// owner holds it and it has yet to lapse.  A lease that is taken out again by
+// its owner keeps the time at which it was first acquired.
+static LEASE_ACQUIRE: &str = "INSERT INTO leases \
+     (name, owner, acquired, expires) VALUES ($1, $2, $3, $4) \
+     ON CONFLICT (name) DO UPDATE SET \
+     owner = EXCLUDED.owner, \
+     acquired = CASE WHEN leases.owner = EXCLUDED.owner \
+     THEN leases.acquired ELSE EXCLUDED.acquired END, \
+     expires = EXCLUDED.expires \
+     WHERE leases.owner = EXCLUDED.owner \
+     OR leases.expires < EXCLUDED.acquired";
+
+// The jobs in any of the states `$1` that have no lease, or whose lease has
+// lapsed as of `$2`, or that are leased to `$3`.
+static LAPSED_JOBS_QUERY: &str = "SELECT jobs.id FROM jobs \
+     LEFT JOIN leases ON leases.name = jobs.id \
+     WHERE jobs.state = ANY($1) \
+     AND (leases.name IS NULL OR leases.expires < $2 OR leases.owner = $3)";
+
+/// A manager sharing the jobs database, as reported by GET /managers.  Times
+/// are in ms since the epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ManagerStatus {
+    pub id: String,
+    pub url: Option<String>,
+    pub started: i64,
+    pub heartbeat: i64,
+
+    /// The manager has been heard from within the last `lease_secs` of the
+    /// manager reporting it.
+    pub live: bool,
+
+    /// The manager holds the COORDINATOR_LEASE.
+    pub coordinator: bool,
+
+    /// The jobs that the manager holds unlapsed leases on.
+    pub jobs: Vec<String>,
+}
+
+struct Coordination {
+    enabled: bool,
+    manager_id: String,
+    url: Option<String>,
+    lease_ms: i64,
+}
+
+impl Default for Coordination {
+    fn default() -> Coordination {
+        Coordination {
+            enabled: false,
+            manager_id: Uuid::new_v4().to_string(),
+            url: None,
+            lease_ms: 0,
+        }
+    }
+}
+
+lazy_static! {
+    static ref COORDINATION: RwLock<Coordination> =
+        RwLock::new(Coordination::default());
+
+    // The leases held by this manager.  This is locked for as long as a
+    // lease is being taken out or released.  A renewal only renews the leases
+    // that were held when it started, and only counts as lost those that are
+    // still held once it is done, so that it never takes a lease that has
+    // just been taken out, or released, for one that was lost.
+    static ref HELD: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Coordinate with other managers as `config` says from now on.  This must
+/// be called before any job is queued.
+pub fn configure(config: &ConfigCoordination) {
+    let mut coordination = COORDINATION.write().expect("coordination lock");
+
+    coordination.enabled = config.enabled;
+    if let Some(id) = &config.manager_id {
+        coordination.manager_id = id.clone();
+    }
+    coordination.url = config.url.clone();
+    coordination.lease_ms = config.lease_secs as i64 * 1000;
+}
+
+/// Returns true if this manager shares the jobs database with others.
+pub fn enabled() -> bool {
+    COORDINATION.read().expect("coordination lock").enabled
+}
+
+/// The name that this manager's leases are held under.
+pub fn manager_id() -> String {
+    COORDINATION
+        .read()
+        .expect("coordination lock")
+        .manager_id
+        .clone()
+}
+
+pub fn create_lease_tables(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS leases(
+            name TEXT PRIMARY KEY,
+            owner TEXT NOT NULL,
+            acquired BIGINT NOT NULL,
+            expires BIGINT NOT NULL
+        );",
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS managers(
+            id TEXT PRIMARY KEY,
+            url TEXT,
+            started BIGINT NOT NULL,
+            heartbeat BIGINT NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+// Take out the lease `name` for `owner` as of `now`.  Returns false if
+// another owner holds it.
+fn acquire_at(
+    conn: &PgConnection,
+    name: &str,
+    owner: &str,
+    now: i64,
+    lease_ms: i64,
+) -> Result<bool, Error> {
+    let acquired = sql_query(LEASE_ACQUIRE)
+        .bind::<Text, _>(name)
+        .bind::<Text, _>(owner)
+        .bind::<BigInt, _>(now)
+        .bind::<BigInt, _>(now + lease_ms)
+        .execute(conn)?;
+
+    Ok(acquired > 0)
+}
+
+// Renew the leases `held` by `owner` as of `now`, returning the names of
+// those that it still holds.  Any other lease of `owner`'s, left by an
+// earlier run of the manager, is left to lapse.
+fn renew_at(
+    conn: &PgConnection,
+    owner: &str,
+    held: &HashSet<String>,
+    now: i64,
+    lease_ms: i64,
+) -> Result<HashSet<String>, Error> {
+    use self::leases::dsl::{
+        expires, leases as leases_table, name, owner as o,
+    };
+
+    let names: Vec<String> = held.iter().cloned().collect();
+
+    conn.transaction::<_, Error, _>(|| {
+        diesel::update(
+            leases_table.filter(o.eq(owner)).filter(name.eq_any(&names)),
+        )
+        .set(expires.eq(now + lease_ms))
+        .execute(conn)?;
+
+        Ok(leases_table
+            .filter(o.eq(owner))
+            .filter(name.eq_any(&names))
+            .select(name)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect())
+    })
+}
+
+fn release_lease(
+    conn: &PgConnection,
+    name: &str,
+    owner: &str,
+) -> Result<usize, Error> {
+    use self::leases::dsl::{leases as leases_table, name as n, owner as o};
+
+    diesel::delete(leases_table.filter(n.eq(name)).filter(o.eq(owner)))
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+// The owner of the lease `name`, unless it has lapsed as of `now`.
+fn owner_at(
+    conn: &PgConnection,
+    name: &str,
+    now: i64,
+) -> Result<Option<String>, Error> {
+    use self::leases::dsl::{
+        expires, leases as leases_table, name as n, owner,
+    };
+
+    Ok(leases_table
+        .filter(n.eq(name))
+        .filter(expires.ge(now))
+        .select(owner)
+        .first::<String>(conn)
+        .optional()?)
+}
+
+// The jobs that would have been running, queued or resumed by now, but that
+// no manager is holding on to: those that no other manager holds an unlapsed
+// lease on, and that `owner` is not holding either.
+fn lapsed_jobs_at(
+    conn: &PgConnection,
+    owner: &str,
+    held: &HashSet<String>,
+    now: i64,
+) -> Result<Vec<String>, Error> {
+    let states: Vec<String> =
+        [JobState::Running, JobState::Queued, JobState::Interrupted]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+    Ok(sql_query(LAPSED_JOBS_QUERY)
+        .bind::<Array<Text>, _>(&states)
+        .bind::<BigInt, _>(now)
+        .bind::<Text, _>(owner)
+        .load::<JobIdRow>(conn)?
+        .into_iter()
+        .map(|row| row.id)
+        .filter(|id| !held.contains(id))
+        .collect())
+}
+
+fn connect() -> Result<PgConnection, Error> {
+    let lease_ms = COORDINATION.read().expect("coordination lock").lease_ms;
+    if lease_ms == 0 {
+        return pg_db::connect_or_create_db(pg_db::REBALANCER_DB);
+    }
+
+    pg_db::connect_db_timeout(
+        pg_db::REBALANCER_DB,
+        Duration::from_millis(lease_ms as u64 / 3),
+    )
+}
+
+/// Take out the lease on the job `job_id` for this manager.  Returns false
+/// if another manager holds it.  Without coordination every job is this
+/// manager's.
+pub fn claim(job_id: &str) -> Result<bool, Error> {
+    let (owner, lease_ms) = {
+        let coordination = COORDINATION.read().expect("coordination lock");
+        if !coordination.enabled {
+            return Ok(true);
+        }
+        (coordination.manager_id.clone(), coordination.lease_ms)
+    };
+
+    let mut held = HELD.lock().expect("held leases lock");
+    let conn = connect()?;
+
+    if !acquire_at(&conn, job_id, &owner, now_ms(), lease_ms)? {
+        return Ok(false);
+    }
+
+    if held.insert(job_id.to_string()) {
+        debug!("Manager {} took out the lease on job {}", owner, job_id);
+    }
+    Ok(true)
+}
+
+/// Returns true if this manager holds the lease `name`.
+pub fn is_held(name: &str) -> bool {
+    enabled() && HELD.lock().expect("held leases lock").contains(name)
+}
+
+/// Give up this manager's lease on the job `job_id`, if it holds it.
+pub fn release(job_id: &str) {
+    if !enabled() {
+        return;
+    }
+
+    let mut held = HELD.lock().expect("held leases lock");
+    if !held.remove(job_id) {
+        return;
+    }
+
+    // A lease that can not be released lapses in the end anyway.
+    if let Err(e) =
+        connect().and_then(|conn| release_lease(&conn, job_id, &manager_id()))
+    {
+        warn!("Could not release the lease on job {}: {}", job_id, e);
+    }
+}
+
+/// The manager holding an unlapsed lease on the job `job_id`, if there is
+/// one.  None without coordination.
+pub fn owner(job_id: &str) -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+
+    connect()
+        .and_then(|conn| owner_at(&conn, job_id, now_ms()))
+        .unwrap_or_else(|e| {
+            warn!("Could not read the lease on job {}: {}", job_id, e);
+            None
+        })
+}
+
+/// The manager holding an unlapsed lease on the job `job_id`, if it is not
+/// this one.
+pub fn remote_owner(job_id: &str) -> Option<String> {
+    owner(job_id).filter(|o| *o != manager_id())
+}
+
+/// Returns true if this manager is to follow evacuation plans and archive
+/// expired jobs: without coordination it always is, and otherwise only while
+/// it holds the COORDINATOR_LEASE.
+pub fn is_coordinator() -> bool {
+    !enabled() || is_held(COORDINATOR_LEASE)
+}
+
+/// Every manager that has been heard from recently, by id.
+pub fn list_managers() -> Result<Vec<ManagerStatus>, Error> {
+    let conn = connect()?;
+    let now = now_ms();
+    let lease_ms = COORDINATION.read().expect("coordination lock").lease_ms;
+
+    let entries: Vec<ManagerEntry> =
+        managers::table.order(managers::id).load(&conn)?;
+    // The name and owner of every unlapsed lease.
+    let leases: Vec<(String, String)> = leases::table
+        .filter(leases::expires.ge(now))
+        .select((leases::name, leases::owner))
+        .order(leases::name)
+        .load(&conn)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|m| {
+            let held: Vec<&String> = leases
+                .iter()
+                .filter(|(_, owner)| *owner == m.id)
+                .map(|(name, _)| name)
+                .collect();
+
+            ManagerStatus {
+                live: m.heartbeat + lease_ms >= now,
+                coordinator: held.iter().any(|n| *n == COORDINATOR_LEASE),
+                jobs: held
+                    .into_iter()
+                    .filter(|n| *n != COORDINATOR_LEASE)
+                    .cloned()
+                    .collect(),
+                id: m.id,
+                url: m.url,
+                started: m.started,
+                heartbeat: m.heartbeat,
+            }
+        })
+        .collect())
+}
+
+/// What is done to take over the jobs whose leases have lapsed: recover and
+/// resume them, as at startup, and queue them.
+pub type TakeOver = Box<dyn Fn() + Send>;
+
+// Record that this manager is alive, and forget those that have not been
+// heard from for a long time.
+fn heartbeat(
+    conn: &PgConnection,
+    manager: &ManagerEntry,
+    now: i64,
+) -> Result<(), Error> {
+    use self::managers::dsl::{
+        heartbeat as hb, id, managers as managers_table,
+    };
+
+    let entry = ManagerEntry {
+        id: manager.id.clone(),
+        url: manager.url.clone(),
+        started: manager.started,
+        heartbeat: now,
+    };
+
+    diesel::insert_into(managers_table)
+        .values(&entry)
+        .on_conflict(id)
+        .do_update()
+        .set(&entry)
+        .execute(conn)?;
+    diesel::delete(managers_table.filter(hb.lt(now - MANAGER_RETENTION_MS)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// One pass of the lease keeper, which sets `last_renewed` to when it
+// started once it has renewed the leases.
+fn keep_leases(
+    manager: &ManagerEntry,
+    take_over: &SyncSender<()>,
+    last_renewed: &Mutex<Instant>,
+) -> Result<(), Error> {
+    let lease_ms = COORDINATION.read().expect("coordination lock").lease_ms;
+    let started = Instant::now();
+    let conn = connect()?;
+    let now = now_ms();
+
+    heartbeat(&conn, manager, now)?;
+
+    let renewing = HELD.lock().expect("held leases lock").clone();
+    let still_held = renew_at(&conn, &manager.id, &renewing, now, lease_ms)?;
+    *last_renewed.lock().expect("last renewed lock") = started;
+
+    let held = {
+        let mut held = HELD.lock().expect("held leases lock");
+        let lost: Vec<String> = renewing
+            .difference(&still_held)
+            .filter(|name| held.contains(*name))
+            .cloned()
+            .collect();
+
+        for name in lost.iter() {
+            if name == COORDINATOR_LEASE {
+                warn!("Manager {} is no longer the coordinator", manager.id);
+                held.remove(name);
+                continue;
+            }
+
+            // The lease may have been released and taken out again while it
+            // was being renewed.
+            if owner_at(&conn, name, now_ms())?.as_ref() == Some(&manager.id) {
+                continue;
+            }
+
+            error!(
+                "Job {} has been taken over by another manager, exiting",
+                name
+            );
+            std::process::exit(1);
+        }
+
+        held.clone()
+    };
+
+    // Nothing more is taken on once the manager is shutting down.
+    if shutdown::requested() {
+        return Ok(());
+    }
+
+    if !held.contains(COORDINATOR_LEASE)
+        && acquire_at(&conn, COORDINATOR_LEASE, &manager.id, now, lease_ms)?
+    {
+        info!("Manager {} is now the coordinator", manager.id);
+        HELD.lock()
+            .expect("held leases lock")
+            .insert(COORDINATOR_LEASE.to_string());
+    }
+
+    // A takeover that is already under way, or about to be, picks these up
+    // along with the others.
+    let lapsed = lapsed_jobs_at(&conn, &manager.id, &held, now)?;
+    if !lapsed.is_empty() && take_over.try_send(()).is_ok() {
+        info!(
+            "Taking over {} job(s) that no manager holds: {}",
+            lapsed.len(),
+            lapsed.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+// Exit if this manager holds the lease of any job, now that its leases have
+// gone `elapsed` without being renewed.  A lease that is being taken out or
+// released, while HELD is locked, is taken to be held.
+fn fence(elapsed: Duration) {
+    let mut held = match HELD.try_lock() {
+        Ok(held) => held,
+        Err(TryLockError::WouldBlock) => {
+            error!(
+                "Leases not renewed for {}s while one is being taken out or \
+                 released, exiting",
+                elapsed.as_secs()
+            );
+            std::process::exit(1);
+        }
+        Err(TryLockError::Poisoned(e)) => panic!("held leases lock: {}", e),
+    };
+
+    // Another manager may be coordinating by now.
+    held.remove(COORDINATOR_LEASE);
+    if !held.is_empty() {
+        error!(
+            "Leases of {} job(s) not renewed for {}s, exiting",
+            held.len(),
+            elapsed.as_secs()
+        );
+        std::process::exit(1);
+    }
+}
+
+/// Start the thread that renews this manager's leases, the one that exits
+/// the manager if they go too long without being renewed, and the one that
+/// takes over the jobs of managers whose leases have lapsed, using
+/// `take_over`.  Recovering a job can take a while, so the leases are kept up
+/// in the meantime.
+pub fn start_lease_keeper(take_over: TakeOver) -> thread::JoinHandle<()> {
+    let (manager, lease_ms) = {
+        let coordination = COORDINATION.read().expect("coordination lock");
+        let now = now_ms();
+
+        (
+            ManagerEntry {
+                id: coordination.manager_id.clone(),
+                url: coordination.url.clone(),
+                started: now,
+                heartbeat: now,
+            },
+            coordination.lease_ms,
+        )
+    };
+    let interval = Duration::from_millis(lease_ms as u64 / 3);
+    let fence_after = Duration::from_millis(lease_ms as u64 * 2 / 3);
+
+    info!(
+        "Coordinating with other managers as {}, with leases of {}s",
+        manager.id,
+        lease_ms / 1000
+    );
+
+    let (takeover_tx, takeover_rx) = mpsc::sync_channel::<()>(1);
+    thread::Builder::new()
+        .name(String::from("lease takeover"))
+        .spawn(move || {
+            for () in takeover_rx.iter() {
+                take_over();
+            }
+        })
+        .expect("start lease takeover thread");
+
+    let last_renewed = Arc::new(Mutex::new(Instant::now()));
+    let fence_renewed = Arc::clone(&last_renewed);
+    thread::Builder::new()
+        .name(String::from("lease fence"))
+        .spawn(move || loop {
+            let elapsed =
+                fence_renewed.lock().expect("last renewed lock").elapsed();
+
+            if elapsed < fence_after {
+                thread::sleep(fence_after - elapsed);
+                continue;
+            }
+
+            fence(elapsed);
+            thread::sleep(interval);
+        })
+        .expect("start lease fence thread");
+
+    thread::Builder::new()
+        .name(String::from("lease keeper"))
+        .spawn(move || loop {
+            if let Err(e) = keep_leases(&manager, &takeover_tx, &last_renewed) {
+                error!("Could not renew leases: {}", e);
+            }
+
+            thread::sleep(interval);
+        })
+        .expect("start lease keeper thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rebalancer::util;
+
+    #[test]
+    fn lease_test() {
+        let _guard = util::init_global_logger(None);
+        let db_name = Uuid::new_v4().to_string();
+
+        {
+            let conn = pg_db::create_and_connect_db(&db_name)
+                .expect("create test database");
+            create_lease_tables(&conn).expect("create lease tables");
+
+            let start: i64 = 1_600_000_000_000;
+            let lease_ms = 30_000;
+
+            assert!(acquire_at(&conn, "job", "a", start, lease_ms).unwrap());
+            assert_eq!(
+                owner_at(&conn, "job", start).unwrap(),
+                Some(String::from("a"))
+            );
+
+            // A lease is only taken over once it has lapsed.
+            let later = start + lease_ms - 1;
+            assert!(!acquire_at(&conn, "job", "b", later, lease_ms).unwrap());
+            assert!(acquire_at(&conn, "job", "a", later, lease_ms).unwrap());
+
+            let held: HashSet<String> =
+                vec![String::from("job")].into_iter().collect();
+            let renewed = renew_at(&conn, "a", &held, later, lease_ms).unwrap();
+            assert_eq!(renewed, held);
+
+            let lapsed = later + lease_ms + 1;
+            assert_eq!(owner_at(&conn, "job", lapsed).unwrap(), None);
+            assert!(acquire_at(&conn, "job", "b", lapsed, lease_ms).unwrap());
+
+            // Its old owner finds that it no longer holds it, and can not
+            // release it.
+            assert!(renew_at(&conn, "a", &held, lapsed, lease_ms)
+                .unwrap()
+                .is_empty());
+            assert_eq!(release_lease(&conn, "job", "a").unwrap(), 0);
+            assert_eq!(release_lease(&conn, "job", "b").unwrap(), 1);
+            assert_eq!(owner_at(&conn, "job", lapsed).unwrap(), None);
+        }
+
+        pg_db::drop_db(&db_name).expect("drop test database");
+    }
+}
@@ -8,67 +8,116 @@
  * Copyright 2020 Joyent, Inc.
  */
 
+pub mod bandwidth;
+pub mod breaker;
+pub mod checkpoint;
+pub mod checksum;
+pub mod confirmation;
+pub mod corruption;
+pub mod estimate;
 pub mod evacuate;
+pub mod events;
+pub mod export;
+pub mod filter;
+pub mod history;
+pub mod lease;
+pub mod nofit;
+pub mod overflow;
+pub mod placement;
+pub mod plan;
+pub mod polling;
+pub mod projected;
+pub mod quarantine;
+pub mod queue;
+pub mod ramp;
+pub mod record;
+pub mod registry;
+pub mod retention;
+pub mod rollback;
+pub mod schedule;
+pub mod sizing;
+pub mod snaplink;
+pub mod snapshot;
+pub mod source;
+pub mod source_copies;
 pub mod status;
+pub mod tuning;
+pub mod verify;
+pub mod watchdog;
 
 use crate::config::Config;
+use crate::config::HookEvent;
+use crate::hooks;
+use crate::joblog;
+use crate::migrations::{self, Migration};
+use crate::notify::{self, JobEvent, JobEventKind};
 use crate::pg_db::{connect_or_create_db, REBALANCER_DB};
 use crate::storinfo::StorageNode;
+use breaker::PauseReason;
 use evacuate::{EvacuateJob, EvacuateJobUpdateMessage};
 use rebalancer::common::{ObjectId, Task};
 use rebalancer::error::{Error, InternalError, InternalErrorCode};
+use rollback::RollbackJob;
+use verify::VerifyJob;
 
 use std::collections::HashMap;
 use std::fmt;
 use std::io::Write;
 use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use crate::jobs::status::JobStatusConfig;
+use crate::jobs::status::{JobListFilter, JobStatusConfig};
 use diesel::deserialize::{self, FromSql};
 use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
 use diesel::serialize::{self, IsNull, Output, ToSql};
 use diesel::sql_types;
 use serde::{Deserialize, Serialize};
+use slog::Logger;
 use uuid::Uuid;
 
 pub type StorageId = String; // Hostname
 pub type AssignmentId = String; // UUID
 pub type HttpStatusCode = u16;
 
-/// The JobPayload is an enum with variants of JobActions.  A properly
-/// formatted JobPayload submitted from the client in JSON form looks like:
-///
-/// ```json
-/// {
-///     "action": <job action (String)>,
-///     "params": { <job action specific params > }
-/// }
-/// ```
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "action", content = "params")]
-#[serde(rename_all = "lowercase")]
-pub enum JobPayload {
-    Evacuate(EvacuateJobPayload),
-}
-
-#[derive(Serialize, Deserialize, Default)]
-pub struct EvacuateJobPayload {
-    pub from_shark: String,
-    pub max_objects: Option<u32>,
-}
+// What can be asked of the manager is described by the client crate, so that
+// its users need not depend on the manager.
+pub use rebalancer_client::jobs::{
+    validate_percentage, CreateCopyJobPayload, EvacuateJobPayload, JobPayload,
+    JobPriority, JobSchedule, JobState, ObjectFilter, ObjectSource,
+    RemoveCopyJobPayload, RollbackJobPayload, VerifyJobPayload,
+};
 
 #[derive(Debug)]
 pub enum JobUpdateMessage {
     Evacuate(EvacuateJobUpdateMessage),
 }
 
+/// An update sent to a running job, and where the job replies with whether
+/// it was applied.
+pub struct JobUpdateRequest {
+    pub message: JobUpdateMessage,
+    pub reply: crossbeam_channel::Sender<Result<(), String>>,
+}
+
+// The channel over which a job that takes updates receives them.
+fn update_channel() -> (
+    Option<crossbeam_channel::Sender<JobUpdateRequest>>,
+    Option<crossbeam_channel::Receiver<JobUpdateRequest>>,
+) {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    (Some(tx), Some(rx))
+}
+
 pub struct Job {
     id: Uuid,
     action: JobAction,
     state: JobState,
     config: Config,
-    pub update_tx: Option<crossbeam_channel::Sender<JobUpdateMessage>>,
+    pub update_tx: Option<crossbeam_channel::Sender<JobUpdateRequest>>,
+
+    // Where the job logs to.  See the joblog module.
+    log: Logger,
 }
 
 // JobBuilder allows us to build a job before commiting its configuration and
@@ -80,15 +129,26 @@ pub struct JobBuilder {
     action: Option<JobAction>,
     state: JobState,
     config: Config,
-    update_tx: Option<crossbeam_channel::Sender<JobUpdateMessage>>,
+    update_tx: Option<crossbeam_channel::Sender<JobUpdateRequest>>,
+    log: Logger,
 }
 
 impl JobBuilder {
     pub fn new(config: Config) -> Self {
-        JobBuilder {
+        let builder = JobBuilder {
             config,
             ..Default::default()
-        }
+        };
+
+        // The job's logger is created before its action, so that the threads
+        // that the action starts right away log to it too.
+        let log = joblog::job_logger(
+            &builder.config.job_logs,
+            builder.config.log_level,
+            &builder.id.to_string(),
+        );
+
+        JobBuilder { log, ..builder }
     }
 
     // Create the configuration for an evacuate job action and add it to this
@@ -98,32 +158,23 @@ impl JobBuilder {
         from_shark: String,
         max_objects: Option<u32>,
     ) -> JobBuilder {
-        // A better approach here would be to create a thread in each job
-        // that would listen for the job update messages and then based on
-        // the message action would send the appropriate messages to the
-        // appropriate threads specific to that job and the update action.
-        // In the future if we expand this feature, our current
-        // implementation does not preclude this approach.  But that adds
-        // complexity (which increases risk) where it is really not needed.
-        // Instead, for now, we only create the channel if the job supports the
-        // one update action implemented.  An update_tx of 'None' signifies
-        // to the main.rs(server) that this job does not support dynamic
+        // The job listens for the updates sent down this channel on a
+        // thread of its own, which applies them and replies (see
+        // jobs::tuning).  An update_tx of 'None' signifies to the
+        // main.rs(server) that this job does not support dynamic
         // configuration updates, and will return an error to the user if an
         // update is attempted on this job.
-        let (tx, rx) = if self.config.options.use_static_md_update_threads {
-            (None, None)
-        } else {
-            let (tx, rx) = crossbeam_channel::unbounded();
-            (Some(tx), Some(rx))
-        };
-
-        match EvacuateJob::new(
-            from_shark,
-            &self.config,
-            &self.id.to_string(),
-            rx,
-            max_objects,
-        ) {
+        let (tx, rx) = update_channel();
+
+        match slog_scope::scope(&self.log, || {
+            EvacuateJob::new(
+                from_shark,
+                &self.config,
+                &self.id.to_string(),
+                rx,
+                max_objects,
+            )
+        }) {
             Ok(j) => {
                 let action = JobAction::Evacuate(Box::new(j));
                 self.action = Some(action);
@@ -138,14 +189,251 @@ impl JobBuilder {
         self
     }
 
+    // Create the configuration for a create-copy job action, which adds a
+    // copy of the objects on `shark` instead of moving them, and add it to
+    // this job's action field.
+    pub fn create_copy(
+        mut self,
+        shark: String,
+        min_copies: Option<u32>,
+        max_objects: Option<u32>,
+    ) -> JobBuilder {
+        // See evacuate() regarding the update channel.
+        let (tx, rx) = update_channel();
+
+        let job = slog_scope::scope(&self.log, || {
+            EvacuateJob::new(
+                shark,
+                &self.config,
+                &self.id.to_string(),
+                rx,
+                max_objects,
+            )
+        })
+        .and_then(|mut j| j.set_create_copy(min_copies).map(|_| j));
+
+        match job {
+            Ok(j) => {
+                let action = JobAction::CreateCopy(Box::new(j));
+                self.action = Some(action);
+                self.update_tx = tx;
+            }
+            Err(e) => {
+                error!("Failed to initialize create-copy job: {}", e);
+                self.state = JobState::Failed;
+            }
+        }
+
+        self
+    }
+
+    // Create the configuration for a remove-copy job action, which removes
+    // the copy on `shark` of the objects that have at least `min_copies`
+    // copies elsewhere, and add it to this job's action field.
+    pub fn remove_copy(
+        mut self,
+        shark: String,
+        min_copies: u32,
+        max_objects: Option<u32>,
+    ) -> JobBuilder {
+        // See evacuate() regarding the update channel.
+        let (tx, rx) = update_channel();
+
+        let job = slog_scope::scope(&self.log, || {
+            EvacuateJob::new(
+                shark,
+                &self.config,
+                &self.id.to_string(),
+                rx,
+                max_objects,
+            )
+        })
+        .and_then(|mut j| j.set_remove_copy(min_copies).map(|_| j));
+
+        match job {
+            Ok(j) => {
+                let action = JobAction::RemoveCopy(Box::new(j));
+                self.action = Some(action);
+                self.update_tx = tx;
+            }
+            Err(e) => {
+                error!("Failed to initialize remove-copy job: {}", e);
+                self.state = JobState::Failed;
+            }
+        }
+
+        self
+    }
+
+    // Create the configuration for a verify job action, which checks the
+    // objects on `shark` without moving them, and add it to this job's action
+    // field.  Verify jobs do not support dynamic configuration updates.
+    pub fn verify(
+        mut self,
+        shark: String,
+        max_objects: Option<u32>,
+    ) -> JobBuilder {
+        match slog_scope::scope(&self.log, || {
+            VerifyJob::new(
+                shark,
+                &self.config,
+                &self.id.to_string(),
+                max_objects,
+            )
+        }) {
+            Ok(j) => {
+                self.action = Some(JobAction::Verify(Box::new(j)));
+            }
+            Err(e) => {
+                error!("Failed to initialize verify job: {}", e);
+                self.state = JobState::Failed;
+            }
+        }
+
+        self
+    }
+
+    // Create the configuration for a rollback job action, which undoes the
+    // metadata changes of the job `job_id`, and add it to this job's action
+    // field.  Rollback jobs do not support dynamic configuration updates.
+    pub fn rollback(
+        mut self,
+        job_id: String,
+        dest_shark: Option<String>,
+        object_ids: Vec<String>,
+        max_objects: Option<u32>,
+    ) -> JobBuilder {
+        match slog_scope::scope(&self.log, || {
+            RollbackJob::new(
+                job_id,
+                dest_shark,
+                object_ids,
+                &self.config,
+                &self.id.to_string(),
+                max_objects,
+            )
+        }) {
+            Ok(j) => {
+                self.action = Some(JobAction::Rollback(Box::new(j)));
+            }
+            Err(e) => {
+                error!("Failed to initialize rollback job: {}", e);
+                self.state = JobState::Failed;
+            }
+        }
+
+        self
+    }
+
+    // Hold the job's destinations to a utilization ceiling (see
+    // EvacuateJob::set_max_dest_utilization()).  Only jobs that add copies
+    // to destinations have a use for one.
+    pub fn max_dest_utilization(mut self, percent: Option<u32>) -> JobBuilder {
+        if percent.is_none() {
+            return self;
+        }
+
+        let res = match &mut self.action {
+            Some(JobAction::Evacuate(j)) | Some(JobAction::CreateCopy(j)) => {
+                j.set_max_dest_utilization(percent)
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = res {
+            error!("Failed to set destination utilization ceiling: {}", e);
+            self.state = JobState::Failed;
+        }
+
+        self
+    }
+
+    // Only have the job hand work to agents within the daily window of
+    // `schedule` (see EvacuateJob::set_schedule()).  Verify and rollback
+    // jobs hand no work to agents.
+    pub fn schedule(mut self, schedule: Option<JobSchedule>) -> JobBuilder {
+        if schedule.is_none() {
+            return self;
+        }
+
+        let res = match &mut self.action {
+            Some(JobAction::Evacuate(j))
+            | Some(JobAction::CreateCopy(j))
+            | Some(JobAction::RemoveCopy(j)) => j.set_schedule(schedule),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = res {
+            error!("Failed to set job schedule: {}", e);
+            self.state = JobState::Failed;
+        }
+
+        self
+    }
+
+    // Only have the job work on the objects that match `filter` (see
+    // EvacuateJob::set_filter()).  Verify and rollback jobs do not scan the
+    // metadata tier with sharkspotter.
+    pub fn filter(mut self, filter: Option<ObjectFilter>) -> JobBuilder {
+        if filter.is_none() {
+            return self;
+        }
+
+        let res = match &mut self.action {
+            Some(JobAction::Evacuate(j))
+            | Some(JobAction::CreateCopy(j))
+            | Some(JobAction::RemoveCopy(j)) => j.set_filter(filter),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = res {
+            error!("Failed to set job filter: {}", e);
+            self.state = JobState::Failed;
+        }
+
+        self
+    }
+
+    // Have the job find its objects in `source` rather than by scanning the
+    // metadata tier (see EvacuateJob::set_source()).
+    pub fn source(mut self, source: Option<ObjectSource>) -> JobBuilder {
+        if source.is_none() {
+            return self;
+        }
+
+        let res = match &mut self.action {
+            Some(JobAction::Evacuate(j))
+            | Some(JobAction::CreateCopy(j))
+            | Some(JobAction::RemoveCopy(j)) => j.set_source(source),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = res {
+            error!("Failed to set job source: {}", e);
+            self.state = JobState::Failed;
+        }
+
+        self
+    }
+
+    // Have the job pick up the scan of the metadata tier where the
+    // interrupted job `job_id` left off.  Only jobs that scan the metadata
+    // tier with sharkspotter keep track of how far they got, so verify jobs
+    // start their scan over.
+    pub fn resume_from(mut self, job_id: &str) -> JobBuilder {
+        match &mut self.action {
+            Some(JobAction::Evacuate(j))
+            | Some(JobAction::CreateCopy(j))
+            | Some(JobAction::RemoveCopy(j)) => j.set_resume_from(job_id),
+            _ => (),
+        }
+
+        self
+    }
+
     pub fn retry(mut self, retry_uuid_str: &str) -> Result<JobBuilder, Error> {
         let retry_uuid = Uuid::from_str(retry_uuid_str).map_err(Error::from)?;
-        let (tx, rx) = if self.config.options.use_static_md_update_threads {
-            (None, None)
-        } else {
-            let (tx, rx) = crossbeam_channel::unbounded();
-            (Some(tx), Some(rx))
-        };
+        let (tx, rx) = update_channel();
 
         let job_status = status::get_job(retry_uuid).map_err(|e| {
             Error::from(InternalError::new(
@@ -157,15 +445,39 @@ impl JobBuilder {
             ))
         })?;
 
+        // The outcome of a job has to be accepted before it is built upon.
+        if job_status.state == JobState::AwaitingConfirmation {
+            return Err(InternalError::new(
+                Some(InternalErrorCode::JobBuilderError),
+                format!(
+                    "Job {} is awaiting confirmation and can not be retried",
+                    retry_uuid_str
+                ),
+            )
+            .into());
+        }
+
         match job_status.config {
             JobStatusConfig::Evacuate(conf) => {
-                match EvacuateJob::retry(
-                    conf.from_shark.manta_storage_id,
-                    &self.config,
-                    &self.id.to_string(),
-                    rx,
-                    retry_uuid_str,
-                ) {
+                let shark = conf.from_shark.manta_storage_id;
+                let limit = conf.max_dest_utilization_percent;
+                let schedule = conf.schedule;
+                let filter = conf.filter;
+                let source = conf.source;
+                match slog_scope::scope(&self.log, || {
+                    EvacuateJob::retry(
+                        shark,
+                        &self.config,
+                        &self.id.to_string(),
+                        rx,
+                        retry_uuid_str,
+                    )
+                })
+                .and_then(|mut j| j.set_max_dest_utilization(limit).map(|_| j))
+                .and_then(|mut j| j.set_schedule(schedule).map(|_| j))
+                .and_then(|mut j| j.set_filter(filter).map(|_| j))
+                .and_then(|mut j| j.set_source(source).map(|_| j))
+                {
                     Ok(j) => {
                         let action = JobAction::Evacuate(Box::new(j));
                         self.update_tx = tx;
@@ -180,6 +492,78 @@ impl JobBuilder {
                     }
                 }
             }
+            JobStatusConfig::CreateCopy(conf) => {
+                let shark = conf.shark.manta_storage_id;
+                let min_copies = conf.min_copies;
+                let limit = conf.max_dest_utilization_percent;
+                let schedule = conf.schedule;
+                let filter = conf.filter;
+                let source = conf.source;
+                let job = slog_scope::scope(&self.log, || {
+                    EvacuateJob::retry(
+                        shark,
+                        &self.config,
+                        &self.id.to_string(),
+                        rx,
+                        retry_uuid_str,
+                    )
+                })
+                .and_then(|mut j| j.set_create_copy(min_copies).map(|_| j))
+                .and_then(|mut j| j.set_max_dest_utilization(limit).map(|_| j))
+                .and_then(|mut j| j.set_schedule(schedule).map(|_| j))
+                .and_then(|mut j| j.set_filter(filter).map(|_| j))
+                .and_then(|mut j| j.set_source(source).map(|_| j));
+
+                match job {
+                    Ok(j) => {
+                        let action = JobAction::CreateCopy(Box::new(j));
+                        self.update_tx = tx;
+                        self.action = Some(action);
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to initialize retry create-copy job: {}",
+                            e
+                        );
+                        self.state = JobState::Failed;
+                    }
+                }
+            }
+            // The objects that a remove-copy job skipped may have lost copies
+            // since they were found, and must be checked again by a new job.
+            JobStatusConfig::RemoveCopy(_) => {
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::JobBuilderError),
+                    format!(
+                        "Job {} is a remove-copy job and can not be retried",
+                        retry_uuid_str
+                    ),
+                )
+                .into());
+            }
+            // A verify job has nothing to retry that a new one would not do.
+            JobStatusConfig::Verify(_) => {
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::JobBuilderError),
+                    format!(
+                        "Job {} is a verify job and can not be retried",
+                        retry_uuid_str
+                    ),
+                )
+                .into());
+            }
+            // The objects that a rollback job did not roll back are rolled
+            // back, if they still can be, by a new rollback job.
+            JobStatusConfig::Rollback(_) => {
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::JobBuilderError),
+                    format!(
+                        "Job {} is a rollback job and can not be retried",
+                        retry_uuid_str
+                    ),
+                )
+                .into());
+            }
         }
 
         Ok(self)
@@ -219,9 +603,11 @@ impl JobBuilder {
             state: JobState::Setup,
             config: self.config,
             update_tx: self.update_tx,
+            log: self.log,
         };
 
         job.insert_into_db()?;
+        job.notify(JobEventKind::Created, None);
 
         Ok(job)
     }
@@ -253,58 +639,31 @@ pub struct JobDbEntry {
     pub id: String,
     pub action: JobActionDbEntry,
     pub state: JobState,
+
+    // Milliseconds since the epoch at which the job was created, or 0 for
+    // jobs created before this was recorded.
+    #[serde(default)]
+    pub created: i64,
 }
 
 table! {
-    use diesel::sql_types::Text;
+    use diesel::sql_types::{BigInt, Text};
     jobs (id) {
         id -> Text,
         action -> Text,
         state -> Text,
+        created -> BigInt,
     }
 }
 
-#[sql_type = "sql_types::Text"]
-#[derive(
-    AsExpression,
-    Clone,
-    Debug,
-    Deserialize,
-    Display,
-    EnumString,
-    EnumVariantNames,
-    FromSqlRow,
-    PartialEq,
-    Serialize,
-)]
-#[strum(serialize_all = "snake_case")]
-pub enum JobState {
-    Init,
-    Setup,
-    Running,
-    Stopped,
-    Complete,
-    Failed,
-}
-
-impl ToSql<sql_types::Text, Pg> for JobState {
-    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
-        let action = self.to_string();
-        out.write_all(action.as_bytes())?;
-        Ok(IsNull::No)
-    }
-}
-
-impl FromSql<sql_types::Text, Pg> for JobState {
-    fn from_sql(bytes: Option<PgValue<'_>>) -> deserialize::Result<Self> {
-        let t: PgValue = not_none!(bytes);
-        let t_str = String::from_utf8_lossy(t.as_bytes());
-        Self::from_str(&t_str).map_err(std::convert::Into::into)
-    }
-}
-
+// Create-copy and remove-copy jobs are run by the same EvacuateJob as an
+// evacuate job (see evacuate::EvacuateJobMode).
 pub enum JobAction {
     Evacuate(Box<EvacuateJob>),
+    CreateCopy(Box<EvacuateJob>),
+    RemoveCopy(Box<EvacuateJob>),
+    Verify(Box<VerifyJob>),
+    Rollback(Box<RollbackJob>),
     None,
 }
 
@@ -312,6 +671,10 @@ impl JobAction {
     fn to_db_entry(&self) -> JobActionDbEntry {
         match self {
             JobAction::Evacuate(_) => JobActionDbEntry::Evacuate,
+            JobAction::CreateCopy(_) => JobActionDbEntry::CreateCopy,
+            JobAction::RemoveCopy(_) => JobActionDbEntry::RemoveCopy,
+            JobAction::Verify(_) => JobActionDbEntry::Verify,
+            JobAction::Rollback(_) => JobActionDbEntry::Rollback,
             _ => JobActionDbEntry::None,
         }
     }
@@ -332,6 +695,10 @@ impl JobAction {
 #[strum(serialize_all = "snake_case")]
 pub enum JobActionDbEntry {
     Evacuate,
+    CreateCopy,
+    RemoveCopy,
+    Verify,
+    Rollback,
     None,
 }
 
@@ -354,7 +721,9 @@ impl FromSql<sql_types::Text, Pg> for JobActionDbEntry {
 impl fmt::Debug for Job {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let action_str = match &self.action {
-            JobAction::Evacuate(ej) => format!(
+            JobAction::Evacuate(ej)
+            | JobAction::CreateCopy(ej)
+            | JobAction::RemoveCopy(ej) => format!(
                 "EvacuateJob: {{ dest_shark_list: {:#?}, \
                  assignments: {:#?}, \
                  from_shark: {:#?}, \
@@ -365,6 +734,12 @@ impl fmt::Debug for Job {
                 ej.from_shark,
                 ej.min_avail_mb,
             ),
+            JobAction::Verify(vj) => {
+                format!("VerifyJob: {{ shark: {:#?} }}", vj.shark)
+            }
+            JobAction::Rollback(rj) => {
+                format!("RollbackJob: {{ job_id: {} }}", rj.job_id)
+            }
             _ => String::new(),
         };
 
@@ -394,6 +769,10 @@ pub struct Assignment {
     tasks: HashMap<ObjectId, Task>,
     max_size: u64,
     total_size: u64,
+    total_bytes: u64,
+
+    // The number of tasks whose source is the shark being evacuated.
+    evac_shark_reads: usize,
     state: AssignmentState,
 }
 
@@ -404,6 +783,8 @@ impl Assignment {
             dest_shark,
             max_size: 0,
             total_size: 0,
+            total_bytes: 0,
+            evac_shark_reads: 0,
             tasks: HashMap::new(),
             state: AssignmentState::Init,
         }
@@ -417,6 +798,7 @@ pub struct AssignmentCacheEntry {
     id: AssignmentId,
     dest_shark: StorageNode,
     total_size: u64,
+    task_count: usize,
     state: AssignmentState,
 }
 
@@ -426,6 +808,7 @@ impl From<Assignment> for AssignmentCacheEntry {
             id: assignment.id,
             dest_shark: assignment.dest_shark,
             total_size: assignment.total_size,
+            task_count: assignment.tasks.len(),
             state: assignment.state,
         }
     }
@@ -446,83 +829,150 @@ impl Job {
         self.action = action;
     }
 
-    pub fn run(mut self) -> Result<(), Error> {
+    /// The shark that the job evacuates, if it is an evacuate job.
+    pub fn evacuated_shark(&self) -> Option<&str> {
+        match &self.action {
+            JobAction::Evacuate(j) => Some(&j.from_shark.manta_storage_id),
+            _ => None,
+        }
+    }
+
+    /// Run the job, with everything that it logs also going to its own log
+    /// file if job logs are enabled.
+    pub fn run(self) -> Result<(), Error> {
+        let log = self.log.clone();
+        slog_scope::scope(&log, || self.run_logged())
+    }
+
+    fn run_logged(mut self) -> Result<(), Error> {
         let job_id = self.id.to_string();
 
         self.update_state(JobState::Running)?;
         debug!("Starting job {:#?}", &self);
         info!("Starting Job: {}", &job_id);
         let now = std::time::Instant::now();
+        let baseline = snapshot::MetricsBaseline::capture();
 
         let result = match self.action {
-            JobAction::Evacuate(job_action) => {
-                match job_action.run() {
-                    Ok(()) => {
-                        info!(
-                            "Job {} completed in {} seconds",
-                            &job_id,
-                            now.elapsed().as_secs(),
-                        );
-                        Ok(())
-                    }
-                    Err(e) => match &e {
-                        // This dance is only intended to support the
-                        // evacuate object limit which will eventually be
-                        // removed.
-                        Error::Internal(err) => match err.code {
-                            InternalErrorCode::MaxObjectsLimit => {
-                                info!(
-                                    "Job {} completed in {} seconds",
-                                    &job_id,
-                                    now.elapsed().as_secs(),
-                                );
-                                Ok(())
-                            }
-                            _ => {
-                                error!(
-                                    "Job {} failed in {} seconds: {}",
-                                    &job_id,
-                                    now.elapsed().as_secs(),
-                                    err
-                                );
-                                Err(e)
-                            }
-                        },
-                        _ => {
-                            error!(
-                                "Job {} failed in {} seconds: {}",
-                                &job_id,
-                                now.elapsed().as_secs(),
-                                e
-                            );
-                            Err(e)
-                        }
-                    },
-                }
+            JobAction::Evacuate(job_action)
+            | JobAction::CreateCopy(job_action)
+            | JobAction::RemoveCopy(job_action) => {
+                log_run_result(&job_id, now, job_action.run())
             }
-            _ => Ok(()),
+            JobAction::Verify(job_action) => {
+                log_run_result(&job_id, now, job_action.run())
+            }
+            JobAction::Rollback(job_action) => {
+                log_run_result(&job_id, now, job_action.run())
+            }
+            JobAction::None => Ok(()),
         };
 
         let ret = match result {
             Ok(()) => {
-                self.state = JobState::Complete;
+                self.state = if self.config.options.require_confirmation {
+                    info!("Job {} is awaiting confirmation", &job_id);
+                    JobState::AwaitingConfirmation
+                } else {
+                    JobState::Complete
+                };
                 Ok(())
             }
             Err(e) => {
-                self.state = JobState::Failed;
+                // A job that lost one of its threads to a panic, or to an
+                // early exit, is paused rather than failed, with the thread
+                // and what happened to it recorded as the reason.
+                if let Some(detail) = watchdog::worker_panic_detail(&e) {
+                    if let Err(e) = breaker::record_pause(
+                        &job_id,
+                        PauseReason::WorkerPanic,
+                        &detail,
+                    ) {
+                        error!("Could not record pause of job: {}", e);
+                    }
+                    self.state = JobState::Paused;
+                } else if is_interrupted(&e) {
+                    self.state = JobState::Interrupted;
+                } else if is_paused(&e) {
+                    self.state = JobState::Paused;
+                } else {
+                    self.state = JobState::Failed;
+                }
                 Err(e)
             }
         };
 
+        match snapshot::record_snapshot(&job_id, &baseline) {
+            Ok(count) => {
+                debug!("Recorded {} metrics of job {}", count, &job_id)
+            }
+            Err(e) => {
+                warn!("Could not record metrics of job {}: {}", &job_id, e)
+            }
+        }
+
         update_job_db_state(job_id, &self.state)?;
+
+        match &ret {
+            Ok(()) => {
+                let kind = match self.state {
+                    JobState::AwaitingConfirmation => {
+                        JobEventKind::AwaitingConfirmation
+                    }
+                    _ => JobEventKind::Complete,
+                };
+                self.notify(kind, None);
+                if kind == JobEventKind::Complete {
+                    self.run_hooks(HookEvent::Complete, None);
+                }
+            }
+            Err(e) => {
+                let kind = match self.state {
+                    JobState::Stopped => JobEventKind::Stopped,
+                    JobState::Interrupted => JobEventKind::Interrupted,
+                    JobState::Paused => JobEventKind::Paused,
+                    _ => JobEventKind::Failed,
+                };
+                self.notify(kind, Some(e.to_string()));
+                match kind {
+                    JobEventKind::Interrupted => (),
+                    JobEventKind::Paused => {
+                        self.run_hooks(HookEvent::Paused, Some(e.to_string()))
+                    }
+                    _ => self.run_hooks(HookEvent::Failed, Some(e.to_string())),
+                }
+            }
+        }
+
         ret
     }
 
+    // Let any configured webhooks know about a change in the state of this
+    // job.
+    fn notify(&self, kind: JobEventKind, error: Option<String>) {
+        let mut event = JobEvent::new(&self.id.to_string(), kind);
+        event.error = error;
+        notify::send(&self.config.notifications, event);
+    }
+
+    // Run any post-job hooks configured for `event`.
+    fn run_hooks(&self, event: HookEvent, error: Option<String>) {
+        hooks::run(&self.config.hooks, &self.id.to_string(), event, error);
+    }
+
+    // This is only used to insert the job into the database, so the time at
+    // which the entry is made is the time at which the job was created.
     fn to_db_entry(&self) -> JobDbEntry {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as i64)
+            .unwrap_or(0);
+
         JobDbEntry {
             id: self.id.to_string(),
             action: self.action.to_db_entry(),
             state: self.state.clone(),
+            created,
         }
     }
 
@@ -545,7 +995,19 @@ impl Job {
 
     fn update_state(&mut self, to_state: JobState) -> Result<usize, Error> {
         let result = update_job_db_state(self.id.to_string(), &to_state);
+        let kind = match to_state {
+            JobState::Queued => Some(JobEventKind::Queued),
+            JobState::Running => Some(JobEventKind::Running),
+            JobState::Interrupted => Some(JobEventKind::Interrupted),
+            _ => None,
+        };
+
         self.state = to_state;
+
+        if let (Ok(_), Some(k)) = (&result, kind) {
+            self.notify(k, None);
+        }
+
         result
     }
 }
@@ -570,10 +1032,271 @@ impl Default for JobBuilder {
             state: JobState::default(),
             config: Config::default(),
             update_tx: None,
+            log: slog_scope::logger(),
         }
     }
 }
 
+// Log how a job's action finished, and turn the end of a job that hit its
+// object limit into a success.
+fn log_run_result(
+    job_id: &str,
+    now: Instant,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    match result {
+        Ok(()) => {
+            info!(
+                "Job {} completed in {} seconds",
+                &job_id,
+                now.elapsed().as_secs(),
+            );
+            Ok(())
+        }
+        Err(e) => match &e {
+            // This dance is only intended to support the
+            // evacuate object limit which will eventually be
+            // removed.
+            Error::Internal(err) => match err.code {
+                InternalErrorCode::MaxObjectsLimit => {
+                    info!(
+                        "Job {} completed in {} seconds",
+                        &job_id,
+                        now.elapsed().as_secs(),
+                    );
+                    Ok(())
+                }
+                InternalErrorCode::JobInterrupted => {
+                    info!(
+                        "Job {} interrupted after {} seconds",
+                        &job_id,
+                        now.elapsed().as_secs(),
+                    );
+                    Err(e)
+                }
+                InternalErrorCode::JobPaused => {
+                    warn!(
+                        "Job {} paused after {} seconds: {}",
+                        &job_id,
+                        now.elapsed().as_secs(),
+                        err
+                    );
+                    Err(e)
+                }
+                _ => {
+                    error!(
+                        "Job {} failed in {} seconds: {}",
+                        &job_id,
+                        now.elapsed().as_secs(),
+                        err
+                    );
+                    Err(e)
+                }
+            },
+            _ => {
+                error!(
+                    "Job {} failed in {} seconds: {}",
+                    &job_id,
+                    now.elapsed().as_secs(),
+                    e
+                );
+                Err(e)
+            }
+        },
+    }
+}
+
+fn is_interrupted(err: &Error) -> bool {
+    match err {
+        Error::Internal(e) => e.code == InternalErrorCode::JobInterrupted,
+        _ => false,
+    }
+}
+
+fn is_paused(err: &Error) -> bool {
+    match err {
+        Error::Internal(e) => e.code == InternalErrorCode::JobPaused,
+        _ => false,
+    }
+}
+
+/// Place a job in the Stopped state.  This is used when the thread running
+/// the job panics and the job itself is no longer available.
+pub fn mark_job_stopped(job_id: Uuid) -> Result<usize, Error> {
+    update_job_db_state(job_id.to_string(), &JobState::Stopped)
+}
+
+/// Find the jobs that were running or queued when the manager last stopped
+/// without shutting down gracefully (e.g. because it crashed), and place them
+/// in the Interrupted state so that they are resumed by
+/// `resume_interrupted_jobs()`.  The outstanding assignments of jobs that were
+/// running are first reconciled with the agents.  This must be called before
+/// any jobs are started.  With coordination, the same is done for those jobs
+/// of other managers whose leases have lapsed, once their leases have been
+/// taken out; the jobs that this manager holds, or that another holds, are
+/// left alone (see lease).
+pub fn recover_crashed_jobs() -> Result<usize, Error> {
+    let job_list =
+        status::list_jobs(&JobListFilter::default()).map_err(|e| {
+            InternalError::new(
+                Some(InternalErrorCode::DbQuery),
+                format!("Could not list jobs: {:?}", e),
+            )
+        })?;
+
+    let mut recovered = 0;
+
+    for entry in job_list
+        .into_iter()
+        .filter(|j| j.state == JobState::Running || j.state == JobState::Queued)
+        .filter(|j| !lease::is_held(&j.id))
+    {
+        if !lease::claim(&entry.id)? {
+            continue;
+        }
+
+        if entry.state == JobState::Running
+            && (entry.action == JobActionDbEntry::Evacuate
+                || entry.action == JobActionDbEntry::CreateCopy
+                || entry.action == JobActionDbEntry::RemoveCopy)
+        {
+            match evacuate::reconcile_assignments(&entry.id) {
+                Ok(summary) => info!(
+                    "Job {} reconciled with agents: {} assignments complete, \
+                     {} cancelled, {} missing, {} objects skipped",
+                    entry.id,
+                    summary.complete,
+                    summary.cancelled,
+                    summary.missing,
+                    summary.skipped_objects
+                ),
+                Err(e) => error!(
+                    "Could not reconcile assignments of job {}: {}",
+                    entry.id, e
+                ),
+            }
+        }
+
+        warn!(
+            "Job {} was {} when the manager stopped, marking it interrupted",
+            entry.id, entry.state
+        );
+        update_job_db_state(entry.id.clone(), &JobState::Interrupted)?;
+        recovered += 1;
+    }
+
+    Ok(recovered)
+}
+
+/// Create a new evacuate job for each job that was interrupted by a shutdown
+/// of the manager, and mark the interrupted jobs as Resumed.  Objects that
+/// were moved before the shutdown are no longer on the shark being
+/// evacuated, so each new job only finds what was left behind.  Nor does the
+/// new job scan the shards that the interrupted job finished with again,
+/// taking those of their objects that it did not finish with from its
+/// database instead (see evacuate::start_sharkspotter()).  An
+/// interrupted create-copy job is resumed as a new create-copy job, which
+/// copies again any object that it had not finished with, but only those
+/// still below its minimum number of copies if it has one.  Likewise an
+/// interrupted remove-copy job is resumed as a new remove-copy job, which only
+/// finds the copies that are still on its shark, and an interrupted verify
+/// job as a new verify job, which starts its checks over.  An interrupted
+/// rollback job is resumed as a new rollback job of every object, since the
+/// objects that it was given are not kept, for which the objects already
+/// rolled back are reverted already.  The new jobs are returned so that they
+/// can be queued.  With coordination, only the interrupted jobs that no other
+/// manager holds a lease on are resumed, and the lease on each is released
+/// once it has been (see lease).
+pub fn resume_interrupted_jobs(config: &Config) -> Result<Vec<Job>, Error> {
+    let job_list =
+        status::list_jobs(&JobListFilter::default()).map_err(|e| {
+            InternalError::new(
+                Some(InternalErrorCode::DbQuery),
+                format!("Could not list jobs: {:?}", e),
+            )
+        })?;
+
+    let mut resumed = vec![];
+
+    for entry in job_list
+        .into_iter()
+        .filter(|j| j.state == JobState::Interrupted)
+    {
+        if !lease::claim(&entry.id)? {
+            continue;
+        }
+
+        let old_uuid = Uuid::from_str(&entry.id).map_err(Error::from)?;
+        let builder = JobBuilder::new(config.clone());
+        let builder = match status::get_job(old_uuid) {
+            Ok(job_status) => match job_status.config {
+                JobStatusConfig::Evacuate(conf) => builder
+                    .evacuate(conf.from_shark.manta_storage_id, None)
+                    .max_dest_utilization(conf.max_dest_utilization_percent)
+                    .schedule(conf.schedule)
+                    .filter(conf.filter)
+                    .source(conf.source),
+                JobStatusConfig::CreateCopy(conf) => builder
+                    .create_copy(
+                        conf.shark.manta_storage_id,
+                        conf.min_copies,
+                        None,
+                    )
+                    .max_dest_utilization(conf.max_dest_utilization_percent)
+                    .schedule(conf.schedule)
+                    .filter(conf.filter)
+                    .source(conf.source),
+                JobStatusConfig::RemoveCopy(conf) => builder
+                    .remove_copy(
+                        conf.shark.manta_storage_id,
+                        conf.min_copies,
+                        None,
+                    )
+                    .schedule(conf.schedule)
+                    .filter(conf.filter)
+                    .source(conf.source),
+                JobStatusConfig::Verify(conf) => {
+                    builder.verify(conf.shark.manta_storage_id, None)
+                }
+                JobStatusConfig::Rollback(conf) => {
+                    builder.rollback(conf.job_id, conf.dest_shark, vec![], None)
+                }
+            },
+            Err(e) => {
+                error!(
+                    "Could not read interrupted job {}, not resuming: {:?}",
+                    entry.id, e
+                );
+                continue;
+            }
+        };
+
+        if let Ok(checkpoints) = evacuate::scan_checkpoint(&entry.id) {
+            let scanned: i64 =
+                checkpoints.iter().map(|c| c.objects_scanned).sum();
+            let complete = checkpoints.iter().filter(|c| c.complete).count();
+            info!(
+                "Job {} scanned {} objects in {} shards, and finished \
+                 scanning {} of them, before it was interrupted",
+                entry.id,
+                scanned,
+                checkpoints.len(),
+                complete
+            );
+        }
+
+        let job = builder.resume_from(&entry.id).commit()?;
+
+        update_job_db_state(entry.id.clone(), &JobState::Resumed)?;
+        lease::release(&entry.id);
+        info!("Job {} resumed as job {}", entry.id, job.get_id());
+
+        resumed.push(job);
+    }
+
+    Ok(resumed)
+}
+
 fn update_job_db_state(
     job_id: String,
     to_state: &JobState,
@@ -594,27 +1317,156 @@ fn update_job_db_state(
         .map_err(Error::from)
 }
 
-pub fn create_job_database() -> Result<(), Error> {
-    let conn = connect_or_create_db(REBALANCER_DB)?;
-
+// The checks on the action and state of a job, as lists of the strings that
+// each may be.
+fn job_checks() -> (String, String) {
     let action_strings = JobActionDbEntry::variants();
     let state_strings = JobState::variants();
 
-    let action_check = format!("'{}'", action_strings.join("', '"));
-    let state_check = format!("'{}'", state_strings.join("', '"));
+    (
+        format!("'{}'", action_strings.join("', '")),
+        format!("'{}'", state_strings.join("', '")),
+    )
+}
 
+fn create_jobs_table(conn: &PgConnection) -> Result<(), Error> {
+    let (action_check, state_check) = job_checks();
     let create_query = format!(
         "
             CREATE TABLE IF NOT EXISTS jobs(
                 id TEXT PRIMARY KEY,
                 action TEXT CHECK(action IN ({})) NOT NULL,
-                state TEXT CHECK(state IN ({})) NOT NULL
+                state TEXT CHECK(state IN ({})) NOT NULL,
+                created BIGINT NOT NULL DEFAULT 0
             );
         ",
         action_check, state_check,
     );
 
-    conn.execute(&create_query).map(|_| {}).map_err(Error::from)
+    conn.execute(&create_query)?;
+    Ok(())
+}
+
+// Older versions of the rebalancer did not record when each job was created.
+fn add_jobs_created(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "ALTER TABLE jobs ADD COLUMN IF NOT EXISTS \
+         created BIGINT NOT NULL DEFAULT 0;",
+    )?;
+    Ok(())
+}
+
+// The migrations of the jobs database (see the migrations module).  Each of
+// these predates migrations, and does nothing where it has already been done.
+static JOB_DATABASE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "jobs table",
+        up: create_jobs_table,
+    },
+    Migration {
+        version: 2,
+        description: "job creation times",
+        up: add_jobs_created,
+    },
+    Migration {
+        version: 3,
+        description: "job confirmations",
+        up: confirmation::create_confirmation_table,
+    },
+    Migration {
+        version: 4,
+        description: "job pauses",
+        up: breaker::create_pause_table,
+    },
+    Migration {
+        version: 5,
+        description: "agent history",
+        up: history::create_history_table,
+    },
+    Migration {
+        version: 6,
+        description: "job metrics snapshots",
+        up: snapshot::create_metrics_table,
+    },
+    Migration {
+        version: 7,
+        description: "job updates",
+        up: tuning::create_updates_table,
+    },
+    Migration {
+        version: 8,
+        description: "evacuation plans",
+        up: plan::create_plan_tables,
+    },
+    Migration {
+        version: 9,
+        description: "manager coordination",
+        up: lease::create_lease_tables,
+    },
+];
+
+#[derive(QueryableByName, Debug)]
+struct ConstraintDef {
+    #[sql_type = "sql_types::Text"]
+    definition: String,
+}
+
+// Replace the check constraint `name` on the jobs table with one that only
+// allows `column` to be one of `check` (see job_checks()), unless the one that
+// is there already allows each of them.  Postgres records the constraint as
+// e.g. `CHECK ((state = ANY (ARRAY['init'::text, ...])))`.
+fn replace_jobs_check(
+    conn: &PgConnection,
+    name: &str,
+    column: &str,
+    check: &str,
+) -> Result<(), Error> {
+    let existing: Option<ConstraintDef> = diesel::sql_query(
+        "SELECT pg_get_constraintdef(oid) AS definition FROM pg_constraint \
+         WHERE conrelid = 'jobs'::regclass AND conname = $1",
+    )
+    .bind::<sql_types::Text, _>(name)
+    .get_result(conn)
+    .optional()?;
+
+    if let Some(existing) = existing {
+        let allowed = check.split(", ").all(|value| {
+            existing.definition.contains(&format!("{}::text", value))
+        });
+        if allowed {
+            return Ok(());
+        }
+    }
+
+    info!("Replacing check constraint {} of the jobs table", name);
+    conn.transaction::<_, Error, _>(|| {
+        conn.execute(&format!(
+            "ALTER TABLE jobs DROP CONSTRAINT IF EXISTS {};",
+            name
+        ))?;
+        conn.execute(&format!(
+            "ALTER TABLE jobs ADD CONSTRAINT {} CHECK({} IN ({}));",
+            name, column, check
+        ))?;
+        Ok(())
+    })
+}
+
+pub fn create_job_database() -> Result<(), Error> {
+    let conn = connect_or_create_db(REBALANCER_DB)?;
+
+    migrations::migrate(&conn, JOB_DATABASE_MIGRATIONS)?;
+
+    // The jobs table may have been created by a version of the rebalancer
+    // that did not know about all of the current job states and actions, in
+    // which case the check constraints need to be replaced in order to allow
+    // them.  The states and actions are those of this version, whatever the
+    // migrations applied, so the constraints are checked every time, but are
+    // only replaced when they are out of date.
+    let (action_check, state_check) = job_checks();
+    replace_jobs_check(&conn, "jobs_state_check", "state", &state_check)?;
+    replace_jobs_check(&conn, "jobs_action_check", "action", &action_check)
 }
 
 #[cfg(test)]
@@ -641,4 +1493,62 @@ mod test {
         // We expect an error here because every parameter above is fake
         assert!(job.run().is_err());
     }
+
+    #[test]
+    fn payload_percentages() {
+        let payload = EvacuateJobPayload {
+            max_fill_percentage: Some(90),
+            max_dest_utilization_percent: Some(75),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_ok());
+
+        for bad in &[0, 101] {
+            let payload = EvacuateJobPayload {
+                max_dest_utilization_percent: Some(*bad),
+                ..Default::default()
+            };
+            assert!(payload.validate().is_err());
+
+            let payload = CreateCopyJobPayload {
+                max_dest_utilization_percent: Some(*bad),
+                ..Default::default()
+            };
+            assert!(payload.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn payload_shard_selection() {
+        let payload = EvacuateJobPayload {
+            min_shard: Some(2),
+            max_shard: Some(2),
+            shards: vec![2],
+            scan_parallelism: Some(100),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_ok());
+
+        let payload = EvacuateJobPayload {
+            min_shard: Some(3),
+            max_shard: Some(2),
+            ..Default::default()
+        };
+        assert!(payload.validate().is_err());
+
+        for bad in &[0, 101] {
+            let payload = CreateCopyJobPayload {
+                scan_parallelism: Some(*bad),
+                ..Default::default()
+            };
+            assert!(payload.validate().is_err());
+
+            let payload = RemoveCopyJobPayload {
+                min_copies: 1,
+                scan_parallelism: Some(*bad),
+                ..Default::default()
+            };
+            assert!(payload.validate().is_err());
+        }
+    }
 }
@@ -0,0 +1,119 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Objects that are too large for any of a job's destinations.
+//
+// An object larger than the free space on the destination that it is given to
+// used to be turned away only once it was being added to an assignment for
+// that destination, and skipped with the same `destination_insufficient_space`
+// as an object that merely arrived while its destination was momentarily full.
+// Evacuate and create-copy jobs now check the size of each object against the
+// free space on each candidate destination as they place it, and pass over
+// those that it does not fit on.  An object that fits on none of them is
+// skipped with a reason of `no_fit_destination`, and the job carries on with
+// the rest.
+//
+// Since these objects are skipped like any other, they are found in the job's
+// evacuateobjects table, and their number, total size and the largest of them
+// are reported as the `no_fit` of the job's status, along with what can be
+// done about them.  Remove-copy jobs write nothing, so never skip an object
+// for this.
+
+use rebalancer::common::ObjectSkippedReason;
+use rebalancer::error::Error;
+pub use rebalancer_client::status::NoFitSummary;
+
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{BigInt, Text};
+
+static REMEDIATION: &str = "Add destinations with more free space, or free \
+                            space on the existing ones, then retry the job.";
+
+#[derive(QueryableByName, Debug)]
+struct NoFitTotals {
+    #[sql_type = "BigInt"]
+    count: i64,
+    #[sql_type = "BigInt"]
+    bytes: i64,
+}
+
+#[derive(QueryableByName, Debug)]
+struct NoFitLargest {
+    #[sql_type = "Text"]
+    id: String,
+    #[sql_type = "BigInt"]
+    bytes: i64,
+}
+
+/// Returns true if an object of `content_mb` is larger than the free space on
+/// every one of `available_mb`.  With no destinations to compare against,
+/// nothing is taken to be too large, as the job has nowhere to put even the
+/// smallest object.
+pub fn fits_nowhere<I>(content_mb: u64, available_mb: I) -> bool
+where
+    I: IntoIterator<Item = u64>,
+{
+    let mut candidates = available_mb.into_iter().peekable();
+
+    candidates.peek().is_some() && candidates.all(|mb| content_mb > mb)
+}
+
+/// Returns the summary of the objects that a job skipped because they fit on
+/// no destination, if there were any.
+pub fn get_no_fit_summary(
+    conn: &PgConnection,
+) -> Result<Option<NoFitSummary>, Error> {
+    let reason = ObjectSkippedReason::NoFitDestination.to_string();
+    let totals: NoFitTotals = sql_query(format!(
+        "SELECT count(*) AS count, \
+         COALESCE(sum((object->>'contentLength')::bigint), 0)::bigint \
+         AS bytes \
+         FROM evacuateobjects \
+         WHERE status = 'skipped' AND skipped_reason = '{}'",
+        reason
+    ))
+    .get_result(conn)?;
+
+    if totals.count == 0 {
+        return Ok(None);
+    }
+
+    let largest: NoFitLargest = sql_query(format!(
+        "SELECT id, \
+         COALESCE((object->>'contentLength')::bigint, 0) AS bytes \
+         FROM evacuateobjects \
+         WHERE status = 'skipped' AND skipped_reason = '{}' \
+         ORDER BY bytes DESC, id LIMIT 1",
+        reason
+    ))
+    .get_result(conn)?;
+
+    Ok(Some(NoFitSummary {
+        count: totals.count,
+        bytes: totals.bytes,
+        largest_object: largest.id,
+        largest_bytes: largest.bytes,
+        remediation: REMEDIATION.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_fit_fits_nowhere() {
+        assert!(fits_nowhere(10, vec![0, 5, 9]));
+        assert!(!fits_nowhere(10, vec![0, 10, 9]));
+        assert!(!fits_nowhere(0, vec![0, 0]));
+        assert!(!fits_nowhere(10, vec![]));
+    }
+}
@@ -0,0 +1,301 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Keeping the objects that a job holds in memory to a bound.
+//
+// Every queue between sharkspotter and the assignment manager is a bounded
+// channel, so that a scan that finds objects faster than they can be placed
+// waits for the assignment manager rather than filling the manager's memory:
+// each scanner blocks on the channel to the translator, and sharkspotter on
+// the channel to its scanner.  The objects that are given back to be placed
+// again, though (those of an assignment turned down for lack of space, of a
+// drained destination, or with more copies to make on the shark being
+// evacuated), are given back by other threads, which can not wait for the
+// assignment manager without holding up the assignments of every other
+// destination.  On a very large shark these alone could come to millions of
+// objects.
+//
+// Between them, the queue of discovered objects and the objects waiting to be
+// placed again hold no more than `options.max_resident_objects` objects in
+// memory (see resident_shares()).  Objects given back beyond that are spilled
+// to the job's overflow_objects table, as they were when they were found, and
+// read back OVERFLOW_BATCH at a time, in the order they were given back, once
+// those in memory have been placed.  The `resident_objects` gauge is the
+// number of objects held in memory by the queues of all running jobs, and
+// `overflow_objects` the number spilled.
+
+use super::evacuate::EvacuateObject;
+use rebalancer::common::ObjectId;
+use rebalancer::error::Error;
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde_json::Value;
+
+// The most spilled objects read back at a time, and written in a single
+// insert.
+static OVERFLOW_BATCH: usize = 1000;
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Jsonb, Text};
+    overflow_objects (seq) {
+        seq -> BigInt,
+        id -> Text,
+        object -> Jsonb,
+        shard -> Integer,
+        etag -> Text,
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "overflow_objects"]
+struct SpilledObject<'a> {
+    id: &'a str,
+    object: &'a Value,
+    shard: i32,
+    etag: &'a str,
+}
+
+#[derive(Queryable)]
+struct OverflowObject {
+    seq: i64,
+    id: ObjectId,
+    object: Value,
+    shard: i32,
+    etag: String,
+}
+
+pub fn create_overflow_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE overflow_objects(
+        seq BIGSERIAL PRIMARY KEY,
+        id TEXT NOT NULL,
+        object Jsonb NOT NULL,
+        shard Integer NOT NULL,
+        etag TEXT NOT NULL
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE overflow_objects") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// How `max_resident` objects are shared out between the queue of discovered
+/// objects, which would be `depth` deep, and the objects waiting to be placed
+/// again.  The queue is at least 1 deep.
+pub fn resident_shares(max_resident: usize, depth: usize) -> (usize, usize) {
+    let depth = depth.min(max_resident).max(1);
+    (depth, max_resident.saturating_sub(depth))
+}
+
+fn spill(conn: &PgConnection, objects: &[EvacuateObject]) -> Result<(), Error> {
+    use self::overflow_objects::dsl::overflow_objects;
+
+    conn.transaction::<_, Error, _>(|| {
+        for chunk in objects.chunks(OVERFLOW_BATCH) {
+            let rows: Vec<SpilledObject> = chunk
+                .iter()
+                .map(|eobj| SpilledObject {
+                    id: &eobj.id,
+                    object: &eobj.object,
+                    shard: eobj.shard,
+                    etag: &eobj.etag,
+                })
+                .collect();
+
+            diesel::insert_into(overflow_objects)
+                .values(&rows)
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+// Take the `limit` objects that were spilled first back out of the database.
+fn unspill(
+    conn: &PgConnection,
+    limit: usize,
+) -> Result<Vec<EvacuateObject>, Error> {
+    use self::overflow_objects::dsl::{overflow_objects, seq};
+
+    conn.transaction::<_, Error, _>(|| {
+        let rows: Vec<OverflowObject> =
+            overflow_objects.order(seq).limit(limit as i64).load(conn)?;
+
+        if let Some(last) = rows.last() {
+            diesel::delete(overflow_objects.filter(seq.le(last.seq)))
+                .execute(conn)?;
+        }
+
+        Ok(rows
+            .into_iter()
+            .map(|row| EvacuateObject {
+                id: row.id,
+                object: row.object,
+                shard: row.shard,
+                etag: row.etag,
+                ..Default::default()
+            })
+            .collect())
+    })
+}
+
+/// The objects of a job waiting to be placed again, in the order in which
+/// they were given back.
+pub struct OverflowQueue {
+    resident: VecDeque<EvacuateObject>,
+    spilled: usize,
+    max_resident: usize,
+}
+
+impl OverflowQueue {
+    pub fn new(max_resident: usize) -> OverflowQueue {
+        OverflowQueue {
+            resident: VecDeque::new(),
+            spilled: 0,
+            max_resident,
+        }
+    }
+
+    /// Hold no more than `max_resident` objects in memory from now on.
+    /// Objects already held are kept.
+    pub fn set_max_resident(&mut self, max_resident: usize) {
+        self.max_resident = max_resident;
+    }
+
+    /// The number of objects held in memory.
+    pub fn resident(&self) -> usize {
+        self.resident.len()
+    }
+
+    /// The number of objects spilled to the job's database.
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resident.is_empty() && self.spilled == 0
+    }
+
+    /// Queue `objects`, spilling to the database that `conn` is connected to
+    /// those for which there is no room in memory.  Once any have been
+    /// spilled, the rest are too until they have all been read back, so that
+    /// none are placed ahead of those given back before them.  Objects that
+    /// can not be spilled are kept in memory after all.
+    pub fn push(
+        &mut self,
+        conn: &Mutex<PgConnection>,
+        objects: Vec<EvacuateObject>,
+    ) {
+        let mut objects = objects.into_iter();
+
+        if self.spilled == 0 {
+            let room = self.max_resident.saturating_sub(self.resident.len());
+            self.resident.extend(objects.by_ref().take(room));
+        }
+
+        let rest: Vec<EvacuateObject> = objects.collect();
+        if rest.is_empty() {
+            return;
+        }
+
+        let locked_conn = conn.lock().expect("DB conn lock");
+        match spill(&*locked_conn, &rest) {
+            Ok(()) => self.spilled += rest.len(),
+            Err(e) => {
+                error!(
+                    "Could not spill {} objects, keeping them in memory: {}",
+                    rest.len(),
+                    e
+                );
+                self.resident.extend(rest);
+            }
+        }
+    }
+
+    /// The next object to be placed again, read back from the database that
+    /// `conn` is connected to if there are none left in memory.
+    pub fn pop(
+        &mut self,
+        conn: &Mutex<PgConnection>,
+    ) -> Option<EvacuateObject> {
+        if self.resident.is_empty() && self.spilled > 0 {
+            let limit = OVERFLOW_BATCH.min(self.max_resident).max(1);
+            let locked_conn = conn.lock().expect("DB conn lock");
+
+            match unspill(&*locked_conn, limit) {
+                // Nothing left to read back means that nothing more was
+                // spilled than has been.
+                Ok(objects) if objects.is_empty() => self.spilled = 0,
+                Ok(objects) => {
+                    self.spilled = self.spilled.saturating_sub(objects.len());
+                    self.resident.extend(objects);
+                }
+                Err(e) => error!("Could not read back spilled objects: {}", e),
+            }
+        }
+
+        self.resident.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_db;
+    use rebalancer::util;
+    use uuid::Uuid;
+
+    #[test]
+    fn overflow_queue_test() {
+        let _guard = util::init_global_logger(None);
+        let db_name = Uuid::new_v4().to_string();
+        let conn = Mutex::new(
+            pg_db::create_and_connect_db(&db_name).expect("create test db"),
+        );
+        create_overflow_table(&*conn.lock().unwrap()).expect("create table");
+
+        let objects: Vec<EvacuateObject> = (0..5)
+            .map(|i| EvacuateObject {
+                id: format!("object{}", i),
+                object: serde_json::json!({ "key": i }),
+                shard: 1,
+                etag: format!("etag{}", i),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut queue = OverflowQueue::new(2);
+        queue.push(&conn, objects[..3].to_vec());
+        assert_eq!((queue.resident(), queue.spilled()), (2, 1));
+
+        // Nothing more is kept in memory until the spilled object has been
+        // read back, even once there is room.
+        assert_eq!(queue.pop(&conn).expect("object").id, "object0");
+        queue.push(&conn, objects[3..].to_vec());
+        assert_eq!((queue.resident(), queue.spilled()), (1, 3));
+
+        let popped: Vec<EvacuateObject> =
+            std::iter::from_fn(|| queue.pop(&conn)).collect();
+        assert_eq!(popped, objects[1..].to_vec());
+        assert!(queue.is_empty());
+
+        assert_eq!(resident_shares(50_000, 100), (100, 49_900));
+        assert_eq!(resident_shares(10, 100), (10, 0));
+        assert_eq!(resident_shares(0, 100), (1, 0));
+
+        drop(conn);
+        pg_db::drop_db(&db_name).expect("drop test db");
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Why each object went where it did.
+//
+// When the objects of a job end up spread over its destinations in a way
+// that nobody expected, the only way to find out why used to be to read the
+// assignment manager.  With `options.trace_placement` set (for every job, or
+// for a single job with its `trace_placement` parameter), every decision
+// that the job makes about where to put an object is instead recorded in the
+// placement_trace table of the job's database, and can be got with GET
+// /jobs/<uuid>/placement.  Each decision is one of:
+//
+//  * chosen: the object was given to the destination.  The detail is the
+//    space that the destination had available, and how much of that other
+//    jobs had reserved (see the projected module), which together put it
+//    where it was in the job's list of destinations.
+//  * full: the destination turned down an assignment for lack of space a
+//    short while ago, and the job is leaving it alone for now.
+//  * constraint: the destination would break the object's placement
+//    constraints, e.g. it already holds a copy of the object.  The detail is
+//    the constraint, as the skipped reason it would give the object.
+//  * capacity: the destination was chosen, but the assignment that was
+//    being filled for it did not have room left for the object.
+//  * no_destination: none of the destinations would do, and the object was
+//    skipped.
+//
+// The destinations are tried in order, the one with the most space available
+// first, and the first that will do is chosen, so the destinations after it
+// are not recorded.  Tracing adds a write to the job's database for every
+// decision, so it is only meant for diagnosing a job, not for every job.
+
+use rebalancer::error::Error;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use diesel::prelude::*;
+use serde::Serialize;
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+    placement_trace(id) {
+        id -> Integer,
+        object_id -> Text,
+        dest_shark -> Nullable<Text>,
+        decision -> Text,
+        detail -> Nullable<Text>,
+        timestamp -> BigInt,
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PlacementDecision {
+    Chosen,
+    Full,
+    Constraint,
+    Capacity,
+    NoDestination,
+}
+
+#[derive(Insertable)]
+#[table_name = "placement_trace"]
+struct NewPlacementTrace {
+    object_id: String,
+    dest_shark: Option<String>,
+    decision: String,
+    detail: Option<String>,
+    timestamp: i64,
+}
+
+/// A decision about where to put an object.  `dest_shark` is only None for
+/// no_destination decisions.  The timestamp is in ms since the epoch.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct PlacementTraceEntry {
+    pub id: i32,
+    pub object_id: String,
+    pub dest_shark: Option<String>,
+    pub decision: String,
+    pub detail: Option<String>,
+    pub timestamp: i64,
+}
+
+pub fn create_placement_trace_table(
+    conn: &PgConnection,
+) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE placement_trace(
+        id SERIAL PRIMARY KEY,
+        object_id TEXT,
+        dest_shark TEXT,
+        decision TEXT,
+        detail TEXT,
+        timestamp BigInt
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE placement_trace") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query)?;
+    conn.execute(
+        "CREATE INDEX placement_trace_object_id on placement_trace \
+         (object_id);",
+    )
+    .map_err(Error::from)
+}
+
+/// Record a decision about where to put the object `object_id`.  A trace is
+/// only used for debugging, so an error is logged rather than failing the
+/// job.
+pub fn record(
+    conn: &PgConnection,
+    object_id: &str,
+    dest_shark: Option<&str>,
+    decision: PlacementDecision,
+    detail: Option<String>,
+) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+
+    let entry = NewPlacementTrace {
+        object_id: object_id.to_string(),
+        dest_shark: dest_shark.map(String::from),
+        decision: decision.to_string(),
+        detail,
+        timestamp,
+    };
+
+    if let Err(e) = diesel::insert_into(placement_trace::table)
+        .values(&entry)
+        .execute(conn)
+    {
+        warn!(
+            "LocalDB: Error recording placement of object {}: {}",
+            object_id, e
+        );
+    }
+}
+
+/// Returns up to `limit` of the decisions in a job's placement trace, oldest
+/// first, starting `offset` decisions in.  If `object_id` is given only the
+/// decisions about that object are returned.
+pub fn get_placement_trace(
+    conn: &PgConnection,
+    object_id: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PlacementTraceEntry>, Error> {
+    use self::placement_trace::dsl;
+
+    let mut query = dsl::placement_trace.into_boxed();
+    if let Some(oid) = object_id {
+        query = query.filter(dsl::object_id.eq(oid.to_string()));
+    }
+
+    query
+        .order(dsl::id)
+        .limit(limit)
+        .offset(offset)
+        .load::<PlacementTraceEntry>(conn)
+        .map_err(Error::from)
+}
@@ -0,0 +1,1112 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Evacuating many storage nodes at once.
+//
+// Taking a whole datacenter (or any large set of storage nodes) out of
+// service used to mean creating an evacuate job for each of its sharks by
+// hand, keeping track of how many of them were running, and of whether the
+// rest of the fleet still had room for what was left.  An evacuation plan
+// does that instead.  A plan is created for a list of sharks, or for every
+// shark in a datacenter, and:
+//
+//  * estimates from storinfo how much data is on each of the sharks, and
+//    checks that the sharks outside of the plan have room for all of it
+//    without going beyond the plan's max_fill_percentage, refusing the plan
+//    if they do not;
+//  * orders the sharks in to steps, the fullest first, each of which is an
+//    evacuate job for one shark;
+//  * is left in the review state, with its estimates, until it is executed.
+//
+// The capacity is checked again when the plan is executed.  From then on the
+// plan coordinator thread starts the plan's steps in order, no more than
+// `max_concurrent` at a time, and follows each of their jobs.  A step is
+// complete once its job is complete (or awaiting confirmation).  If the job
+// fails, stops or is paused by its circuit breaker, the whole plan is paused
+// with the reason, so that the rest of the sharks are not evacuated while
+// something is wrong.
+//
+// A plan is controlled as a whole: pausing it stops any more of its steps
+// from being started, resuming it starts them again (retrying each step whose
+// job did not finish with a new job, which picks up what the last one did not
+// reach), and aborting it drops the steps that have not been started.  Jobs
+// that are already running carry on in each case, and can be dealt with as
+// any other job.
+//
+// While a plan is running or paused none of its sharks are chosen as the
+// destination of any job, including the plan's own, so that a shark is not
+// filled with objects from another that is being evacuated alongside it.  The
+// sharks of an aborted plan are chosen again once their jobs have finished,
+// and those of a complete plan straight away.
+//
+// Plans and their steps are kept in the evacuation_plans and plan_steps
+// tables of the rebalancer database.
+
+use super::jobs::dsl::{id as job_id_col, jobs as jobs_db};
+use super::{lease, validate_percentage, JobDbEntry, JobState, REBALANCER_DB};
+use crate::pg_db;
+use crate::shutdown;
+use crate::storinfo::StorageNode;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+    evacuation_plans (id) {
+        id -> Text,
+        state -> Text,
+        max_concurrent -> Integer,
+        max_fill_percentage -> Integer,
+        required_mb -> BigInt,
+        capacity_mb -> BigInt,
+        reason -> Nullable<Text>,
+        created -> BigInt,
+    }
+}
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Nullable, Text};
+    plan_steps (plan_id, seq) {
+        plan_id -> Text,
+        seq -> Integer,
+        shark -> Text,
+        datacenter -> Text,
+        estimated_mb -> BigInt,
+        state -> Text,
+        job_id -> Nullable<Text>,
+    }
+}
+
+// How often the plan coordinator checks on the plans that are running.
+static PLAN_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    // Held by the plan coordinator for each of its passes, and by each
+    // change made to a plan through the API, so that e.g. a step is not
+    // started while its plan is being aborted.
+    static ref PLAN_LOCK: Mutex<()> = Mutex::new(());
+
+    // The sharks that must not be chosen as destinations.
+    static ref DRAINING: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+}
+
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PlanState {
+    Review,
+    Running,
+    Paused,
+    Aborted,
+    Complete,
+}
+
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum StepState {
+    Pending,
+    Running,
+    Complete,
+    Failed,
+    Aborted,
+}
+
+/// What can be done to a plan once it is running.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PlanAction {
+    Pause,
+    Resume,
+    Abort,
+}
+
+/// Starts an evacuate job for a step of a plan, given the shark to evacuate
+/// and the plan's max_fill_percentage, returning the uuid of the job.
+pub type SubmitStep = Box<dyn Fn(&str, u32) -> Result<Uuid, String> + Send>;
+
+/// The body of a request to create a plan.  Exactly one of `sharks` and
+/// `datacenter` is given.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PlanPayload {
+    #[serde(default)]
+    pub sharks: Vec<String>,
+    pub datacenter: Option<String>,
+
+    // The number of the plan's steps that may be running at once.  Defaults
+    // to 1.
+    pub max_concurrent: Option<u32>,
+
+    // The fullest that the plan may leave any destination shark.  Defaults
+    // to the service wide max_fill_percentage.
+    pub max_fill_percentage: Option<u32>,
+}
+
+impl PlanPayload {
+    pub fn validate(&self) -> Result<(), String> {
+        validate_percentage("max_fill_percentage", self.max_fill_percentage)?;
+
+        match (self.sharks.is_empty(), &self.datacenter) {
+            (true, None) => {
+                return Err(String::from(
+                    "One of sharks and datacenter is required",
+                ))
+            }
+            (false, Some(_)) => {
+                return Err(String::from(
+                    "Only one of sharks and datacenter may be given",
+                ))
+            }
+            _ => (),
+        }
+
+        if self.max_concurrent == Some(0) {
+            return Err(String::from("max_concurrent must be at least 1"));
+        }
+
+        Ok(())
+    }
+}
+
+/// A plan as it is reported.  `capacity_mb` is the space found for the
+/// plan's data on the rest of the fleet when the plan was last checked.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Plan {
+    pub id: String,
+    pub state: String,
+    pub max_concurrent: u32,
+    pub max_fill_percentage: u32,
+    pub required_mb: u64,
+    pub capacity_mb: u64,
+    pub reason: Option<String>,
+
+    // Milliseconds since the epoch at which the plan was created.
+    pub created: i64,
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PlanStep {
+    pub seq: u32,
+    pub shark: String,
+    pub datacenter: String,
+    pub estimated_mb: u64,
+    pub state: String,
+    pub job_id: Option<String>,
+
+    // The state of the step's job, once it has one.
+    pub job_state: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "evacuation_plans"]
+struct PlanEntry {
+    id: String,
+    state: String,
+    max_concurrent: i32,
+    max_fill_percentage: i32,
+    required_mb: i64,
+    capacity_mb: i64,
+    reason: Option<String>,
+    created: i64,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "plan_steps"]
+struct StepEntry {
+    plan_id: String,
+    seq: i32,
+    shark: String,
+    datacenter: String,
+    estimated_mb: i64,
+    state: String,
+    job_id: Option<String>,
+}
+
+impl PlanEntry {
+    fn plan_state(&self) -> PlanState {
+        PlanState::from_str(&self.state).unwrap_or(PlanState::Aborted)
+    }
+}
+
+#[derive(Debug)]
+pub enum PlanError {
+    NotFound,
+    Invalid(String),
+    NotAllowed(PlanState, String),
+    InsufficientCapacity { required_mb: u64, capacity_mb: u64 },
+    Db(Error),
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::NotFound => write!(f, "plan not found"),
+            PlanError::Invalid(msg) => write!(f, "{}", msg),
+            PlanError::NotAllowed(state, action) => {
+                write!(f, "can not {} a plan that is {}", action, state)
+            }
+            PlanError::InsufficientCapacity {
+                required_mb,
+                capacity_mb,
+            } => write!(
+                f,
+                "the plan needs {}MB, but the other sharks only have room \
+                 for {}MB",
+                required_mb, capacity_mb
+            ),
+            PlanError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for PlanError {
+    fn from(error: Error) -> Self {
+        PlanError::Db(error)
+    }
+}
+
+impl From<diesel::result::Error> for PlanError {
+    fn from(error: diesel::result::Error) -> Self {
+        PlanError::Db(Error::from(error))
+    }
+}
+
+// Storinfo only reports how much space a shark has left and what percentage
+// of it is used, so the size of the shark is worked out from the two, as
+// roughly as the percentage is rounded.
+fn total_mb(node: &StorageNode) -> u64 {
+    let free_pct = 100u64.saturating_sub(u64::from(node.percent_used)).max(1);
+    node.available_mb * 100 / free_pct
+}
+
+/// An estimate of how much data there is on `node`.
+pub fn estimate_used_mb(node: &StorageNode) -> u64 {
+    total_mb(node).saturating_sub(node.available_mb)
+}
+
+/// How much more the sharks that are not in `excluded` can take before any
+/// of them is beyond `max_fill_percentage`.
+pub fn destination_capacity_mb(
+    sharks: &[StorageNode],
+    excluded: &HashSet<String>,
+    max_fill_percentage: u32,
+) -> u64 {
+    sharks
+        .iter()
+        .filter(|s| !excluded.contains(&s.manta_storage_id))
+        .map(|s| {
+            let total = total_mb(s);
+            let ceiling = total * u64::from(max_fill_percentage) / 100;
+            ceiling.saturating_sub(total - s.available_mb)
+        })
+        .sum()
+}
+
+// The sharks that `payload` asks for, fullest first.
+fn select_sharks<'a>(
+    payload: &PlanPayload,
+    sharks: &'a [StorageNode],
+) -> Result<Vec<&'a StorageNode>, PlanError> {
+    let mut selected: Vec<&StorageNode> = match &payload.datacenter {
+        Some(dc) => {
+            let in_dc: Vec<&StorageNode> =
+                sharks.iter().filter(|s| &s.datacenter == dc).collect();
+            if in_dc.is_empty() {
+                return Err(PlanError::Invalid(format!(
+                    "No sharks are known in datacenter {}",
+                    dc
+                )));
+            }
+            in_dc
+        }
+        None => {
+            let mut seen = HashSet::new();
+            let mut named = vec![];
+            for id in payload.sharks.iter().filter(|id| seen.insert(*id)) {
+                match sharks.iter().find(|s| &s.manta_storage_id == id) {
+                    Some(s) => named.push(s),
+                    None => {
+                        return Err(PlanError::Invalid(format!(
+                            "Shark {} is not known to storinfo",
+                            id
+                        )))
+                    }
+                }
+            }
+            named
+        }
+    };
+
+    selected.sort_by_key(|s| Reverse(estimate_used_mb(s)));
+    Ok(selected)
+}
+
+// Plan the evacuation of the sharks that `payload` asks for, from the list
+// of sharks received from storinfo.
+fn build_plan(
+    payload: &PlanPayload,
+    sharks: &[StorageNode],
+    max_fill_percentage: u32,
+) -> Result<(PlanEntry, Vec<StepEntry>), PlanError> {
+    let selected = select_sharks(payload, sharks)?;
+    let excluded: HashSet<String> = selected
+        .iter()
+        .map(|s| s.manta_storage_id.clone())
+        .collect();
+
+    let required_mb: u64 = selected.iter().map(|s| estimate_used_mb(s)).sum();
+    let capacity_mb =
+        destination_capacity_mb(sharks, &excluded, max_fill_percentage);
+
+    if required_mb > capacity_mb {
+        return Err(PlanError::InsufficientCapacity {
+            required_mb,
+            capacity_mb,
+        });
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let steps = selected
+        .iter()
+        .enumerate()
+        .map(|(i, s)| StepEntry {
+            plan_id: id.clone(),
+            seq: i as i32 + 1,
+            shark: s.manta_storage_id.clone(),
+            datacenter: s.datacenter.clone(),
+            estimated_mb: estimate_used_mb(s) as i64,
+            state: StepState::Pending.to_string(),
+            job_id: None,
+        })
+        .collect();
+
+    let plan = PlanEntry {
+        id,
+        state: PlanState::Review.to_string(),
+        max_concurrent: payload.max_concurrent.unwrap_or(1) as i32,
+        max_fill_percentage: max_fill_percentage as i32,
+        required_mb: required_mb as i64,
+        capacity_mb: capacity_mb as i64,
+        reason: None,
+        created: now_ms(),
+    };
+
+    Ok((plan, steps))
+}
+
+pub fn create_plan_tables(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS evacuation_plans(
+            id TEXT PRIMARY KEY,
+            state TEXT NOT NULL,
+            max_concurrent INTEGER NOT NULL,
+            max_fill_percentage INTEGER NOT NULL,
+            required_mb BIGINT NOT NULL,
+            capacity_mb BIGINT NOT NULL,
+            reason TEXT,
+            created BIGINT NOT NULL
+        );",
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS plan_steps(
+            plan_id TEXT NOT NULL,
+            seq INTEGER NOT NULL,
+            shark TEXT NOT NULL,
+            datacenter TEXT NOT NULL,
+            estimated_mb BIGINT NOT NULL,
+            state TEXT NOT NULL,
+            job_id TEXT,
+            PRIMARY KEY (plan_id, seq)
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+fn find_plan(conn: &PgConnection, id: &str) -> Result<PlanEntry, PlanError> {
+    evacuation_plans::table
+        .find(id)
+        .first(conn)
+        .optional()?
+        .ok_or(PlanError::NotFound)
+}
+
+fn load_steps(
+    conn: &PgConnection,
+    plan_id: &str,
+) -> Result<Vec<StepEntry>, Error> {
+    plan_steps::table
+        .filter(plan_steps::plan_id.eq(plan_id))
+        .order(plan_steps::seq)
+        .load(conn)
+        .map_err(Error::from)
+}
+
+fn plan_ids_in(
+    conn: &PgConnection,
+    states: &[PlanState],
+) -> Result<Vec<String>, Error> {
+    let states: Vec<String> = states.iter().map(|s| s.to_string()).collect();
+
+    evacuation_plans::table
+        .filter(evacuation_plans::state.eq_any(states))
+        .select(evacuation_plans::id)
+        .load(conn)
+        .map_err(Error::from)
+}
+
+// Put a plan together with the steps and the states of their jobs.
+fn to_plan(conn: &PgConnection, entry: PlanEntry) -> Result<Plan, Error> {
+    let mut steps = vec![];
+
+    for step in load_steps(conn, &entry.id)? {
+        let job_state = match &step.job_id {
+            Some(job_id) => jobs_db
+                .filter(job_id_col.eq(job_id))
+                .first::<JobDbEntry>(conn)
+                .optional()?
+                .map(|j| j.state.to_string()),
+            None => None,
+        };
+
+        steps.push(PlanStep {
+            seq: step.seq as u32,
+            shark: step.shark,
+            datacenter: step.datacenter,
+            estimated_mb: step.estimated_mb as u64,
+            state: step.state,
+            job_id: step.job_id,
+            job_state,
+        });
+    }
+
+    Ok(Plan {
+        id: entry.id,
+        state: entry.state,
+        max_concurrent: entry.max_concurrent as u32,
+        max_fill_percentage: entry.max_fill_percentage as u32,
+        required_mb: entry.required_mb as u64,
+        capacity_mb: entry.capacity_mb as u64,
+        reason: entry.reason,
+        created: entry.created,
+        steps,
+    })
+}
+
+/// Plan the evacuation of the sharks that `payload` asks for, given the
+/// list of sharks most recently received from storinfo.  The plan is left
+/// for review until it is executed.
+pub fn create_plan(
+    payload: &PlanPayload,
+    sharks: &[StorageNode],
+    default_max_fill_percentage: u32,
+) -> Result<Plan, PlanError> {
+    payload.validate().map_err(PlanError::Invalid)?;
+
+    let max_fill_percentage = payload
+        .max_fill_percentage
+        .unwrap_or(default_max_fill_percentage);
+    let (plan, steps) = build_plan(payload, sharks, max_fill_percentage)?;
+
+    let _lock = PLAN_LOCK.lock().expect("plan lock");
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    // A shark can only be evacuated by one plan at a time.
+    let open = plan_ids_in(
+        &conn,
+        &[PlanState::Review, PlanState::Running, PlanState::Paused],
+    )?;
+    let planned: HashSet<String> = plan_steps::table
+        .filter(plan_steps::plan_id.eq_any(open))
+        .filter(plan_steps::state.eq_any(vec![
+            StepState::Pending.to_string(),
+            StepState::Running.to_string(),
+        ]))
+        .select(plan_steps::shark)
+        .load::<String>(&conn)?
+        .into_iter()
+        .collect();
+
+    if let Some(step) = steps.iter().find(|s| planned.contains(&s.shark)) {
+        return Err(PlanError::Invalid(format!(
+            "Shark {} is already in another plan",
+            step.shark
+        )));
+    }
+
+    conn.transaction::<_, PlanError, _>(|| {
+        diesel::insert_into(evacuation_plans::table)
+            .values(&plan)
+            .execute(&conn)?;
+        diesel::insert_into(plan_steps::table)
+            .values(&steps)
+            .execute(&conn)?;
+        Ok(())
+    })?;
+
+    info!(
+        "Plan {} created to evacuate {} shark(s), {}MB",
+        plan.id,
+        steps.len(),
+        plan.required_mb
+    );
+
+    to_plan(&conn, plan).map_err(PlanError::from)
+}
+
+pub fn get_plan(id: &str) -> Result<Plan, PlanError> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let entry = find_plan(&conn, id)?;
+
+    to_plan(&conn, entry).map_err(PlanError::from)
+}
+
+/// Every plan, oldest first.
+pub fn list_plans() -> Result<Vec<Plan>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let entries: Vec<PlanEntry> = evacuation_plans::table
+        .order(evacuation_plans::created)
+        .load(&conn)?;
+
+    entries.into_iter().map(|e| to_plan(&conn, e)).collect()
+}
+
+/// Start a plan that is awaiting review, once the sharks outside of it have
+/// been found to still have room for its data.
+pub fn execute_plan(
+    id: &str,
+    sharks: &[StorageNode],
+) -> Result<Plan, PlanError> {
+    let _lock = PLAN_LOCK.lock().expect("plan lock");
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let entry = find_plan(&conn, id)?;
+
+    let state = entry.plan_state();
+    if state != PlanState::Review {
+        return Err(PlanError::NotAllowed(state, String::from("execute")));
+    }
+
+    let excluded: HashSet<String> = load_steps(&conn, id)?
+        .into_iter()
+        .map(|s| s.shark)
+        .collect();
+    let capacity_mb = destination_capacity_mb(
+        sharks,
+        &excluded,
+        entry.max_fill_percentage as u32,
+    );
+
+    if entry.required_mb as u64 > capacity_mb {
+        return Err(PlanError::InsufficientCapacity {
+            required_mb: entry.required_mb as u64,
+            capacity_mb,
+        });
+    }
+
+    diesel::update(evacuation_plans::table.find(id))
+        .set((
+            evacuation_plans::state.eq(PlanState::Running.to_string()),
+            evacuation_plans::capacity_mb.eq(capacity_mb as i64),
+        ))
+        .execute(&conn)?;
+    update_draining(&conn)?;
+
+    info!("Plan {} executed", id);
+
+    get_plan_with(&conn, id)
+}
+
+fn get_plan_with(conn: &PgConnection, id: &str) -> Result<Plan, PlanError> {
+    let entry = find_plan(conn, id)?;
+    to_plan(conn, entry).map_err(PlanError::from)
+}
+
+/// Pause, resume or abort a plan.  Jobs that the plan has already started
+/// are left running.
+pub fn control_plan(id: &str, action: PlanAction) -> Result<Plan, PlanError> {
+    let _lock = PLAN_LOCK.lock().expect("plan lock");
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let entry = find_plan(&conn, id)?;
+    let state = entry.plan_state();
+
+    let new_state = match (action, state) {
+        (PlanAction::Pause, PlanState::Running) => PlanState::Paused,
+        (PlanAction::Resume, PlanState::Paused) => PlanState::Running,
+        (PlanAction::Abort, PlanState::Review)
+        | (PlanAction::Abort, PlanState::Running)
+        | (PlanAction::Abort, PlanState::Paused) => PlanState::Aborted,
+        _ => return Err(PlanError::NotAllowed(state, action.to_string())),
+    };
+
+    conn.transaction::<_, PlanError, _>(|| {
+        let steps = plan_steps::table.filter(plan_steps::plan_id.eq(id));
+
+        match action {
+            // The steps whose jobs did not finish are tried again.
+            PlanAction::Resume => {
+                diesel::update(steps.filter(
+                    plan_steps::state.eq(StepState::Failed.to_string()),
+                ))
+                .set((
+                    plan_steps::state.eq(StepState::Pending.to_string()),
+                    plan_steps::job_id.eq(None::<String>),
+                ))
+                .execute(&conn)?;
+            }
+            PlanAction::Abort => {
+                diesel::update(steps.filter(
+                    plan_steps::state.eq(StepState::Pending.to_string()),
+                ))
+                .set(plan_steps::state.eq(StepState::Aborted.to_string()))
+                .execute(&conn)?;
+            }
+            PlanAction::Pause => (),
+        }
+
+        diesel::update(evacuation_plans::table.find(id))
+            .set((
+                evacuation_plans::state.eq(new_state.to_string()),
+                evacuation_plans::reason.eq(None::<String>),
+            ))
+            .execute(&conn)?;
+        Ok(())
+    })?;
+    update_draining(&conn)?;
+
+    info!("Plan {}: {} ({} -> {})", id, action, state, new_state);
+
+    get_plan_with(&conn, id)
+}
+
+/// Returns true if `shark` is being evacuated by a plan, and so must not be
+/// chosen as the destination of any job.
+pub fn is_draining(shark: &str) -> bool {
+    DRAINING
+        .read()
+        .expect("draining sharks lock")
+        .contains(shark)
+}
+
+/// Read which sharks the plans are evacuating.  The plan coordinator does
+/// this as the plans change, but it must also be done before any job is
+/// resumed on startup, so that the job does not choose one of them.
+pub fn refresh_draining() -> Result<(), Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    update_draining(&conn)
+}
+
+fn update_draining(conn: &PgConnection) -> Result<(), Error> {
+    let active = plan_ids_in(conn, &[PlanState::Running, PlanState::Paused])?;
+
+    let mut draining: HashSet<String> = plan_steps::table
+        .filter(plan_steps::plan_id.eq_any(active))
+        .select(plan_steps::shark)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+
+    // Including those of aborted plans that still have jobs running.
+    draining.extend(
+        plan_steps::table
+            .filter(plan_steps::state.eq(StepState::Running.to_string()))
+            .select(plan_steps::shark)
+            .load::<String>(conn)?,
+    );
+
+    *DRAINING.write().expect("draining sharks lock") = draining;
+    Ok(())
+}
+
+// Pause a running plan, saying why.
+fn pause_plan(
+    conn: &PgConnection,
+    plan_id: &str,
+    reason: String,
+) -> Result<(), Error> {
+    warn!("Pausing plan {}: {}", plan_id, reason);
+
+    diesel::update(
+        evacuation_plans::table
+            .find(plan_id)
+            .filter(evacuation_plans::state.eq(PlanState::Running.to_string())),
+    )
+    .set((
+        evacuation_plans::state.eq(PlanState::Paused.to_string()),
+        evacuation_plans::reason.eq(Some(reason)),
+    ))
+    .execute(conn)
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+fn set_step_state(
+    conn: &PgConnection,
+    step: &StepEntry,
+    state: StepState,
+) -> Result<(), Error> {
+    diesel::update(plan_steps::table.find((&step.plan_id, step.seq)))
+        .set(plan_steps::state.eq(state.to_string()))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(Error::from)
+}
+
+// Mark a running step complete or failed once its job has finished, pausing
+// the step's plan if the job did not get to the end.
+fn follow_step(conn: &PgConnection, step: &StepEntry) -> Result<(), Error> {
+    let job_id = step.job_id.clone().unwrap_or_default();
+    let job_state = jobs_db
+        .filter(job_id_col.eq(&job_id))
+        .first::<JobDbEntry>(conn)
+        .optional()?
+        .map(|j| j.state);
+
+    match job_state {
+        Some(JobState::Complete) | Some(JobState::AwaitingConfirmation) => {
+            set_step_state(conn, step, StepState::Complete)?;
+            info!(
+                "Plan {}: step {} ({}) is complete",
+                step.plan_id, step.seq, step.shark
+            );
+            return Ok(());
+        }
+        Some(JobState::Failed)
+        | Some(JobState::Stopped)
+        | Some(JobState::Paused)
+        | None => (),
+        Some(_) => return Ok(()),
+    }
+
+    let ended = job_state
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| String::from("missing"));
+
+    set_step_state(conn, step, StepState::Failed)?;
+    pause_plan(
+        conn,
+        &step.plan_id,
+        format!(
+            "job {} evacuating {} (step {}) is {}",
+            job_id, step.shark, step.seq, ended
+        ),
+    )
+}
+
+// Start as many of a running plan's pending steps as it allows, or mark the
+// plan complete once all of its steps are.
+fn advance_plan(
+    conn: &PgConnection,
+    plan: &PlanEntry,
+    submit: &dyn Fn(&str, u32) -> Result<Uuid, String>,
+) -> Result<(), Error> {
+    let steps = load_steps(conn, &plan.id)?;
+    let pending = StepState::Pending.to_string();
+    let mut running = steps
+        .iter()
+        .filter(|s| s.state == StepState::Running.to_string())
+        .count();
+
+    if running == 0 && steps.iter().all(|s| s.state != pending) {
+        diesel::update(evacuation_plans::table.find(&plan.id))
+            .set(evacuation_plans::state.eq(PlanState::Complete.to_string()))
+            .execute(conn)?;
+        info!("Plan {} is complete", plan.id);
+        return Ok(());
+    }
+
+    for step in steps.iter().filter(|s| s.state == pending) {
+        if running >= plan.max_concurrent.max(1) as usize
+            || shutdown::requested()
+        {
+            break;
+        }
+
+        match submit(&step.shark, plan.max_fill_percentage as u32) {
+            Ok(job_id) => {
+                diesel::update(
+                    plan_steps::table.find((&step.plan_id, step.seq)),
+                )
+                .set((
+                    plan_steps::state.eq(StepState::Running.to_string()),
+                    plan_steps::job_id.eq(Some(job_id.to_string())),
+                ))
+                .execute(conn)?;
+                running += 1;
+
+                info!(
+                    "Plan {}: step {} started job {} to evacuate {}",
+                    plan.id, step.seq, job_id, step.shark
+                );
+            }
+            Err(e) => {
+                return pause_plan(
+                    conn,
+                    &plan.id,
+                    format!(
+                        "could not start a job to evacuate {} (step {}): {}",
+                        step.shark, step.seq, e
+                    ),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// One pass of the plan coordinator.
+fn coordinate(
+    submit: &dyn Fn(&str, u32) -> Result<Uuid, String>,
+) -> Result<(), Error> {
+    let _lock = PLAN_LOCK.lock().expect("plan lock");
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    // The steps of every plan are followed, including those of plans that
+    // have been paused or aborted since they were started.
+    let running: Vec<StepEntry> = plan_steps::table
+        .filter(plan_steps::state.eq(StepState::Running.to_string()))
+        .load(&conn)?;
+    for step in &running {
+        follow_step(&conn, step)?;
+    }
+
+    let plans: Vec<PlanEntry> = evacuation_plans::table
+        .filter(evacuation_plans::state.eq(PlanState::Running.to_string()))
+        .order(evacuation_plans::created)
+        .load(&conn)?;
+    for plan in &plans {
+        advance_plan(&conn, plan, submit)?;
+    }
+
+    update_draining(&conn)
+}
+
+/// Start the thread that runs the steps of each running plan, using
+/// `submit` to start their jobs.  With coordination, only the manager that is
+/// the coordinator runs them, and the others keep track of which sharks the
+/// plans are evacuating.
+pub fn start_plan_coordinator(submit: SubmitStep) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("plan coordinator"))
+        .spawn(move || loop {
+            if shutdown::requested() {
+                return;
+            }
+
+            let result = if lease::is_coordinator() {
+                coordinate(&*submit)
+            } else {
+                refresh_draining()
+            };
+            if let Err(e) = result {
+                error!("Error coordinating evacuation plans: {}", e);
+            }
+
+            thread::sleep(PLAN_CHECK_INTERVAL);
+        })
+        .expect("start plan coordinator thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::jobs::dsl::state as job_state_col;
+    use crate::jobs::{self, JobActionDbEntry};
+    use rebalancer::util;
+
+    fn node(id: &str, dc: &str, available_mb: u64, pct: u8) -> StorageNode {
+        StorageNode {
+            available_mb,
+            percent_used: pct,
+            datacenter: dc.to_string(),
+            manta_storage_id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plan_capacity() {
+        // 1000MB sharks, with 600, 200 and 500MB on them.
+        let sharks = vec![
+            node("1.stor", "dc1", 400, 60),
+            node("2.stor", "dc1", 800, 20),
+            node("3.stor", "dc2", 500, 50),
+        ];
+
+        assert_eq!(estimate_used_mb(&sharks[0]), 600);
+        assert_eq!(estimate_used_mb(&sharks[1]), 200);
+
+        // Filled to 80%, 3.stor can take another 300MB.
+        let excluded: HashSet<String> =
+            ["1.stor", "2.stor"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(destination_capacity_mb(&sharks, &excluded, 80), 300);
+
+        // No more than is there is the most the plan can use.
+        assert_eq!(destination_capacity_mb(&sharks, &excluded, 40), 0);
+
+        // Draining dc2 needs 500MB, which dc1 has room for.
+        let payload = PlanPayload {
+            datacenter: Some(String::from("dc2")),
+            ..Default::default()
+        };
+        let (plan, steps) = build_plan(&payload, &sharks, 80).expect("plan");
+        assert_eq!(plan.required_mb, 500);
+        assert_eq!(plan.capacity_mb, 200 + 600);
+        assert_eq!(steps.len(), 1);
+
+        // Draining dc1 does not fit, whatever order it is asked in.
+        let payload = PlanPayload {
+            sharks: vec![String::from("2.stor"), String::from("1.stor")],
+            ..Default::default()
+        };
+        match build_plan(&payload, &sharks, 80) {
+            Err(PlanError::InsufficientCapacity {
+                required_mb: 800,
+                capacity_mb: 300,
+            }) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // With room to spare, the fullest shark goes first.
+        let (_, steps) = build_plan(&payload, &sharks, 100).expect("plan");
+        let order: Vec<&str> = steps.iter().map(|s| s.shark.as_str()).collect();
+        assert_eq!(order, vec!["1.stor", "2.stor"]);
+
+        let payload = PlanPayload {
+            sharks: vec![String::from("4.stor")],
+            ..Default::default()
+        };
+        assert!(build_plan(&payload, &sharks, 100).is_err());
+    }
+
+    #[test]
+    fn plan_execution() {
+        let _guard = util::init_global_logger(None);
+        jobs::create_job_database().expect("create job database");
+        let conn =
+            pg_db::connect_or_create_db(REBALANCER_DB).expect("rebalancer db");
+
+        let suffix = Uuid::new_v4().to_string();
+        let first = format!("1.{}", suffix);
+        let second = format!("2.{}", suffix);
+        let sharks = vec![
+            node(&first, &suffix, 400, 60),
+            node(&second, &suffix, 800, 20),
+            node(&format!("3.{}", suffix), "elsewhere", 10_000, 0),
+        ];
+
+        let payload = PlanPayload {
+            datacenter: Some(suffix.clone()),
+            ..Default::default()
+        };
+        let plan = create_plan(&payload, &sharks, 100).expect("create plan");
+        assert_eq!(plan.state, "review");
+        assert_eq!(plan.steps.len(), 2);
+        assert!(!is_draining(&first));
+
+        // The same sharks can not be planned twice.
+        match create_plan(&payload, &sharks, 100) {
+            Err(PlanError::Invalid(_)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match control_plan(&plan.id, PlanAction::Pause) {
+            Err(PlanError::NotAllowed(PlanState::Review, _)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let plan = execute_plan(&plan.id, &sharks).expect("execute plan");
+        assert_eq!(plan.state, "running");
+        assert!(is_draining(&first) && is_draining(&second));
+
+        // Each step's job is recorded as running as it is started.
+        let submit = |_: &str, _: u32| -> Result<Uuid, String> {
+            let job_id = Uuid::new_v4();
+            diesel::insert_into(jobs_db)
+                .values(&JobDbEntry {
+                    id: job_id.to_string(),
+                    action: JobActionDbEntry::Evacuate,
+                    state: JobState::Running,
+                    created: 1,
+                })
+                .execute(&conn)
+                .map_err(|e| e.to_string())?;
+            Ok(job_id)
+        };
+        let set_job_state = |job_id: &Option<String>, state: JobState| {
+            let job_id = job_id.clone().expect("step job id");
+            diesel::update(jobs_db.filter(job_id_col.eq(job_id)))
+                .set(job_state_col.eq(state))
+                .execute(&conn)
+                .expect("update job");
+        };
+
+        // Only one step runs at a time, the fullest shark's first.
+        coordinate(&submit).expect("coordinate");
+        let plan = get_plan(&plan.id).expect("get plan");
+        assert_eq!(plan.steps[0].shark, first);
+        assert_eq!(plan.steps[0].state, "running");
+        assert_eq!(
+            plan.steps[0].job_state.as_ref().map(String::as_str),
+            Some("running")
+        );
+        assert_eq!(plan.steps[1].state, "pending");
+
+        // A job that is paused pauses the plan.
+        set_job_state(&plan.steps[0].job_id, JobState::Paused);
+        coordinate(&submit).expect("coordinate");
+        let plan = get_plan(&plan.id).expect("get plan");
+        assert_eq!(plan.state, "paused");
+        assert!(plan.reason.is_some());
+        assert_eq!(plan.steps[0].state, "failed");
+        assert_eq!(plan.steps[1].state, "pending");
+
+        // Resuming the plan retries the step with a new job.
+        control_plan(&plan.id, PlanAction::Resume).expect("resume plan");
+        coordinate(&submit).expect("coordinate");
+        let plan = get_plan(&plan.id).expect("get plan");
+        assert_eq!(plan.state, "running");
+        assert_eq!(plan.steps[0].state, "running");
+
+        set_job_state(&plan.steps[0].job_id, JobState::Complete);
+        coordinate(&submit).expect("coordinate");
+        let plan = get_plan(&plan.id).expect("get plan");
+        assert_eq!(plan.steps[0].state, "complete");
+        assert_eq!(plan.steps[1].state, "running");
+
+        // Aborting the plan leaves the running job be, and its shark out of
+        // the destinations until the job has finished.
+        let plan =
+            control_plan(&plan.id, PlanAction::Abort).expect("abort plan");
+        assert_eq!(plan.state, "aborted");
+        assert!(!is_draining(&first));
+        assert!(is_draining(&second));
+
+        set_job_state(&plan.steps[1].job_id, JobState::Complete);
+        coordinate(&submit).expect("coordinate");
+        let plan = get_plan(&plan.id).expect("get plan");
+        assert_eq!(plan.state, "aborted");
+        assert_eq!(plan.steps[1].state, "complete");
+        assert!(!is_draining(&second));
+    }
+}
@@ -0,0 +1,283 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// When to ask agents about their assignments.
+//
+// The assignment checker used to ask every agent about every one of its
+// outstanding assignments twice a second.  Most of those requests find that
+// the assignment is still running, and a job with many large assignments on
+// slow agents makes a lot of them.  Instead, each assignment is given a time
+// at which it is next due to be polled:
+//
+//  * When an assignment is first seen, it is due after the time that its
+//    agent has been taking per task, times the number of tasks in the
+//    assignment.  Until the agent has completed an assignment for this job,
+//    it is due straight away.
+//  * When a poll finds that the assignment is not complete, it is due again
+//    after the agent's own estimate of the time it has left (see
+//    AgentAssignmentStats::estimate_remaining()).  Failing that, the time
+//    left is estimated from the progress reported by the agent, and failing
+//    that (e.g. it has not started yet) the time to the next poll is
+//    doubled.
+//
+// The time between polls is always kept between `min_poll_ms` and
+// `max_poll_secs`, so that fast completions are still found promptly and an
+// assignment is never left alone for too long.  Of each agent's completed
+// assignments, the time per task is a moving average so that one unusual
+// assignment does not skew the schedule much.
+
+use super::{AssignmentId, StorageId};
+use crate::config::ConfigPolling;
+use rebalancer::libagent::{AgentAssignmentState, AgentAssignmentStats};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// The weight given to the latest assignment in an agent's time per task.
+static SECS_PER_TASK_WEIGHT: f64 = 0.3;
+
+struct PollEntry {
+    first_seen: Instant,
+    due: Instant,
+    interval: Duration,
+}
+
+#[derive(Default)]
+struct PollState {
+    // Agent -> moving average of the seconds it has taken per task.
+    secs_per_task: HashMap<StorageId, f64>,
+    assignments: HashMap<AssignmentId, PollEntry>,
+}
+
+pub struct PollSchedule {
+    min_interval: Duration,
+    max_interval: Duration,
+    state: Mutex<PollState>,
+}
+
+impl PollSchedule {
+    pub fn new(config: &ConfigPolling) -> PollSchedule {
+        let min_interval = Duration::from_millis(config.min_poll_ms.max(1));
+        let max_interval =
+            Duration::from_secs(config.max_poll_secs).max(min_interval);
+
+        PollSchedule {
+            min_interval,
+            max_interval,
+            state: Mutex::new(PollState::default()),
+        }
+    }
+
+    fn clamp(&self, interval: Duration) -> Duration {
+        interval.max(self.min_interval).min(self.max_interval)
+    }
+
+    /// The shortest time between two polls of an assignment.
+    pub fn min_interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    /// Returns true if an assignment of `tasks` tasks on `shark` should be
+    /// polled now.
+    pub fn is_due(&self, id: &str, shark: &str, tasks: usize) -> bool {
+        self.is_due_at(id, shark, tasks, Instant::now())
+    }
+
+    fn is_due_at(
+        &self,
+        id: &str,
+        shark: &str,
+        tasks: usize,
+        now: Instant,
+    ) -> bool {
+        let mut state = self.state.lock().expect("poll schedule lock");
+
+        if let Some(entry) = state.assignments.get(id) {
+            return now >= entry.due;
+        }
+
+        let expected = state
+            .secs_per_task
+            .get(shark)
+            .map(|spt| Duration::from_secs_f64(spt * tasks as f64));
+
+        let (interval, due) = match expected {
+            Some(e) => (self.clamp(e), now + self.clamp(e)),
+            None => (self.min_interval, now),
+        };
+
+        state.assignments.insert(
+            id.to_string(),
+            PollEntry {
+                first_seen: now,
+                due,
+                interval,
+            },
+        );
+
+        expected.is_none()
+    }
+
+    /// Record that a poll found the assignment not yet complete.
+    pub fn not_ready(&self, id: &str, stats: &AgentAssignmentStats) {
+        self.not_ready_at(id, stats, Instant::now())
+    }
+
+    fn not_ready_at(
+        &self,
+        id: &str,
+        stats: &AgentAssignmentStats,
+        now: Instant,
+    ) {
+        let mut state = self.state.lock().expect("poll schedule lock");
+        let entry = match state.assignments.get_mut(id) {
+            Some(e) => e,
+            None => return,
+        };
+
+        let first_seen = entry.first_seen;
+        let from_progress = || {
+            if stats.complete == 0 {
+                return None;
+            }
+            let elapsed = now.saturating_duration_since(first_seen);
+            let remaining = stats.total.saturating_sub(stats.complete);
+            Some(elapsed.mul_f64(remaining as f64 / stats.complete as f64))
+        };
+
+        let interval = match stats.state {
+            AgentAssignmentState::Running => stats
+                .eta_secs
+                .map(Duration::from_secs)
+                .or_else(from_progress),
+            _ => None,
+        }
+        .unwrap_or(entry.interval * 2);
+
+        let interval = self.clamp(interval);
+        entry.interval = interval;
+        entry.due = now + interval;
+    }
+
+    /// Record that a poll found the assignment complete, and stop tracking
+    /// it.
+    pub fn completed(&self, id: &str, shark: &str, tasks: usize) {
+        self.completed_at(id, shark, tasks, Instant::now())
+    }
+
+    fn completed_at(&self, id: &str, shark: &str, tasks: usize, now: Instant) {
+        let mut state = self.state.lock().expect("poll schedule lock");
+        let entry = match state.assignments.remove(id) {
+            Some(e) => e,
+            None => return,
+        };
+
+        if tasks == 0 {
+            return;
+        }
+
+        let elapsed = now.saturating_duration_since(entry.first_seen);
+        let latest = elapsed.as_secs_f64() / tasks as f64;
+
+        state
+            .secs_per_task
+            .entry(shark.to_string())
+            .and_modify(|spt| {
+                *spt = SECS_PER_TASK_WEIGHT * latest
+                    + (1.0 - SECS_PER_TASK_WEIGHT) * *spt
+            })
+            .or_insert(latest);
+    }
+
+    /// Stop tracking an assignment that will not be polled again.
+    pub fn forget(&self, id: &str) {
+        let mut state = self.state.lock().expect("poll schedule lock");
+        state.assignments.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> PollSchedule {
+        PollSchedule::new(&ConfigPolling {
+            min_poll_ms: 500,
+            max_poll_secs: 60,
+        })
+    }
+
+    fn running(complete: usize, total: usize) -> AgentAssignmentStats {
+        let mut stats = AgentAssignmentStats::new(total);
+        stats.state = AgentAssignmentState::Running;
+        stats.complete = complete;
+        stats
+    }
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn poll_backoff_without_history() {
+        let sched = schedule();
+        let shark = "1.stor.domain";
+        let start = Instant::now();
+
+        // Nothing is known of this agent, so its first assignment is polled
+        // straight away.
+        assert!(sched.is_due_at("a1", shark, 10, start));
+
+        // Not started yet: the interval doubles, up to the maximum.
+        let scheduled = AgentAssignmentStats::new(10);
+        sched.not_ready_at("a1", &scheduled, start);
+        assert!(!sched.is_due_at("a1", shark, 10, start + secs(0)));
+        assert!(sched.is_due_at("a1", shark, 10, start + secs(1)));
+
+        let mut now = start;
+        for _ in 0..10 {
+            sched.not_ready_at("a1", &scheduled, now);
+            now += secs(60);
+        }
+        assert!(!sched.is_due_at("a1", shark, 10, now - secs(1)));
+        assert!(sched.is_due_at("a1", shark, 10, now));
+    }
+
+    #[test]
+    fn poll_follows_agent() {
+        let sched = schedule();
+        let shark = "1.stor.domain";
+        let start = Instant::now();
+
+        // Half of the tasks were done in 10 seconds, so the rest should take
+        // another 10.
+        assert!(sched.is_due_at("a1", shark, 10, start));
+        sched.not_ready_at("a1", &running(5, 10), start + secs(10));
+        assert!(!sched.is_due_at("a1", shark, 10, start + secs(19)));
+        assert!(sched.is_due_at("a1", shark, 10, start + secs(20)));
+
+        // The agent's own estimate is preferred.
+        let mut stats = running(5, 10);
+        stats.eta_secs = Some(3);
+        sched.not_ready_at("a1", &stats, start + secs(20));
+        assert!(sched.is_due_at("a1", shark, 10, start + secs(23)));
+
+        // That assignment took 2 seconds per task, so a new assignment of 5
+        // tasks is first polled after 10 seconds.
+        sched.completed_at("a1", shark, 10, start + secs(20));
+        assert!(!sched.is_due_at("a2", shark, 5, start + secs(20)));
+        assert!(!sched.is_due_at("a2", shark, 5, start + secs(29)));
+        assert!(sched.is_due_at("a2", shark, 5, start + secs(30)));
+
+        // Other agents are not affected.
+        assert!(sched.is_due_at("b1", "2.stor.domain", 5, start + secs(20)));
+    }
+}
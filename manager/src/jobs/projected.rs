@@ -0,0 +1,337 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Projected utilization of destination sharks across all jobs.
+//
+// Each evacuate job keeps its own count of the megabytes it has assigned to
+// a destination shark but which have not yet been reported back by the agent
+// (EvacuateDestShark.assigned_mb).  That count is invisible to every other job
+// running in the same manager, and storinfo will not reflect any of it until
+// the data has actually landed on the shark and minnow has reported in.  With
+// two jobs running at once, both will see the same lightly used shark, and
+// both will fill it up to max_fill_percentage independently.
+//
+// This module tracks, per destination shark:
+//  * The megabytes each job currently has outstanding (assigned, but the
+//    agent has not finished).
+//  * The megabytes of assignments that the agent has finished, but which
+//    storinfo has not yet reported on.  These are held until we see a
+//    storinfo record for the shark with a timestamp newer than the completion
+//    of the assignment.
+//
+// Every job in the manager shares a single ProjectedUtilization (see
+// shared()).  Jobs subtract whatever the other jobs have outstanding, along
+// with anything that has landed but is not yet reflected, from the space they
+// consider available on a shark.
+//
+// The same counts are used to report, across every running job, how much is
+// headed to each destination shark (see destination_load()), so that
+// operators can see when several jobs are converging on the same few sharks.
+
+use super::StorageId;
+use lazy_static::lazy_static;
+use rebalancer::util::now_ms;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Default)]
+struct Outstanding {
+    mb: u64,
+    assignments: u64,
+}
+
+#[derive(Default)]
+struct SharkProjection {
+    // Job uuid -> MB and number of assignments sent to this shark by that job
+    // and not yet completed.
+    outstanding: HashMap<String, Outstanding>,
+
+    // (completion time in ms since the epoch, MB) of assignments that have
+    // completed but which storinfo has not caught up with.
+    landed: Vec<(u64, u64)>,
+}
+
+/// What a single job has outstanding to a destination shark.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JobLoad {
+    pub job_id: String,
+    pub inbound_mb: u64,
+    pub assignments: u64,
+}
+
+/// What every running job has outstanding to a destination shark.
+/// `percentage` is this shark's share of the megabytes outstanding to all
+/// destination sharks.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DestinationLoad {
+    pub shark: StorageId,
+    pub inbound_mb: u64,
+    pub assignments: u64,
+    pub percentage: f64,
+    pub concentrated: bool,
+    pub jobs: Vec<JobLoad>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LoadReport {
+    pub inbound_mb: u64,
+    pub assignments: u64,
+    pub concentration_percentage: u32,
+    pub destinations: Vec<DestinationLoad>,
+}
+
+#[derive(Default)]
+pub struct ProjectedUtilization {
+    sharks: Mutex<HashMap<StorageId, SharkProjection>>,
+}
+
+lazy_static! {
+    static ref SHARED: Arc<ProjectedUtilization> =
+        Arc::new(ProjectedUtilization::default());
+}
+
+/// The projection shared by every job running in this manager.
+pub fn shared() -> Arc<ProjectedUtilization> {
+    Arc::clone(&SHARED)
+}
+
+impl ProjectedUtilization {
+    /// Record that a job has sent an assignment of `mb` to a shark.
+    pub fn reserve(&self, shark: &str, job_id: &str, mb: u64) {
+        let mut sharks = self.sharks.lock().expect("projected lock");
+        let entry = sharks
+            .entry(shark.to_string())
+            .or_default()
+            .outstanding
+            .entry(job_id.to_string())
+            .or_default();
+
+        entry.mb = entry.mb.saturating_add(mb);
+        entry.assignments = entry.assignments.saturating_add(1);
+    }
+
+    /// Record that an assignment of `mb` previously reserved by a job is no
+    /// longer outstanding.  If `landed` is true the data was (or may have
+    /// been) written to the shark, so it continues to count against the shark
+    /// until storinfo catches up.  Otherwise (e.g. the assignment was never
+    /// posted) it is simply released.
+    pub fn release(&self, shark: &str, job_id: &str, mb: u64, landed: bool) {
+        let mut sharks = self.sharks.lock().expect("projected lock");
+        let sp = sharks.entry(shark.to_string()).or_default();
+
+        if let Some(outstanding) = sp.outstanding.get_mut(job_id) {
+            outstanding.mb = outstanding.mb.saturating_sub(mb);
+            outstanding.assignments = outstanding.assignments.saturating_sub(1);
+            if outstanding.mb == 0 && outstanding.assignments == 0 {
+                sp.outstanding.remove(job_id);
+            }
+        }
+
+        if landed && mb > 0 {
+            sp.landed.push((now_ms() as u64, mb));
+        }
+    }
+
+    /// Called with each storinfo record that a job receives.  The timestamp
+    /// is the time (in ms since the epoch) at which minnow generated the
+    /// record.  Any data that landed before then is now reflected in the
+    /// record's available_mb and is no longer tracked here.
+    pub fn storinfo_update(&self, shark: &str, timestamp: u64) {
+        let mut sharks = self.sharks.lock().expect("projected lock");
+
+        if let Some(sp) = sharks.get_mut(shark) {
+            sp.landed.retain(|(completed, _)| *completed > timestamp);
+        }
+    }
+
+    /// The number of MB on a shark that the calling job should treat as used
+    /// in addition to what storinfo reports and what it has outstanding
+    /// itself.
+    pub fn unreflected_mb(&self, shark: &str, job_id: &str) -> u64 {
+        let sharks = self.sharks.lock().expect("projected lock");
+
+        sharks.get(shark).map_or(0, |sp| {
+            let others = sp
+                .outstanding
+                .iter()
+                .filter(|(id, _)| id.as_str() != job_id)
+                .fold(0u64, |acc, (_, o)| acc.saturating_add(o.mb));
+            let landed = sp
+                .landed
+                .iter()
+                .fold(0u64, |acc, (_, mb)| acc.saturating_add(*mb));
+
+            others.saturating_add(landed)
+        })
+    }
+
+    /// Drop anything a job still has outstanding.  Called once the job has
+    /// finished so that a job which exits abnormally does not hold space on
+    /// its destination sharks indefinitely.
+    pub fn job_finished(&self, job_id: &str) {
+        let mut sharks = self.sharks.lock().expect("projected lock");
+
+        for sp in sharks.values_mut() {
+            sp.outstanding.remove(job_id);
+        }
+    }
+
+    /// Report what is outstanding to each destination shark across every
+    /// running job, busiest first.  A shark is flagged as concentrated if its
+    /// share of everything outstanding exceeds `concentration_percentage`.
+    pub fn destination_load(
+        &self,
+        concentration_percentage: u32,
+    ) -> LoadReport {
+        let sharks = self.sharks.lock().expect("projected lock");
+
+        let mut destinations: Vec<DestinationLoad> = sharks
+            .iter()
+            .filter(|(_, sp)| !sp.outstanding.is_empty())
+            .map(|(shark, sp)| {
+                let mut jobs: Vec<JobLoad> = sp
+                    .outstanding
+                    .iter()
+                    .map(|(job_id, o)| JobLoad {
+                        job_id: job_id.to_string(),
+                        inbound_mb: o.mb,
+                        assignments: o.assignments,
+                    })
+                    .collect();
+                jobs.sort_by(|a, b| b.inbound_mb.cmp(&a.inbound_mb));
+
+                DestinationLoad {
+                    shark: shark.to_string(),
+                    inbound_mb: jobs
+                        .iter()
+                        .fold(0u64, |acc, j| acc.saturating_add(j.inbound_mb)),
+                    assignments: jobs
+                        .iter()
+                        .fold(0u64, |acc, j| acc.saturating_add(j.assignments)),
+                    percentage: 0.0,
+                    concentrated: false,
+                    jobs,
+                }
+            })
+            .collect();
+
+        let inbound_mb = destinations
+            .iter()
+            .fold(0u64, |acc, d| acc.saturating_add(d.inbound_mb));
+        let assignments = destinations
+            .iter()
+            .fold(0u64, |acc, d| acc.saturating_add(d.assignments));
+
+        for dest in destinations.iter_mut() {
+            if inbound_mb > 0 {
+                dest.percentage =
+                    dest.inbound_mb as f64 * 100.0 / inbound_mb as f64;
+            }
+            dest.concentrated =
+                dest.percentage > f64::from(concentration_percentage);
+        }
+
+        destinations.sort_by(|a, b| {
+            b.inbound_mb
+                .cmp(&a.inbound_mb)
+                .then_with(|| a.shark.cmp(&b.shark))
+        });
+
+        LoadReport {
+            inbound_mb,
+            assignments,
+            concentration_percentage,
+            destinations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outstanding_counts_against_other_jobs() {
+        let projected = ProjectedUtilization::default();
+        let shark = "1.stor.domain";
+
+        projected.reserve(shark, "job_a", 100);
+        projected.reserve(shark, "job_a", 50);
+        projected.reserve(shark, "job_b", 10);
+
+        assert_eq!(projected.unreflected_mb(shark, "job_a"), 10);
+        assert_eq!(projected.unreflected_mb(shark, "job_b"), 150);
+        assert_eq!(projected.unreflected_mb(shark, "job_c"), 160);
+
+        projected.release(shark, "job_a", 150, false);
+        assert_eq!(projected.unreflected_mb(shark, "job_b"), 0);
+
+        projected.job_finished("job_b");
+        assert_eq!(projected.unreflected_mb(shark, "job_a"), 0);
+    }
+
+    #[test]
+    fn landed_held_until_storinfo_update() {
+        let projected = ProjectedUtilization::default();
+        let shark = "1.stor.domain";
+
+        projected.reserve(shark, "job_a", 100);
+        projected.release(shark, "job_a", 100, true);
+
+        // Landed data counts against every job, including the one that put
+        // it there, since its own assigned_mb has already been decreased.
+        assert_eq!(projected.unreflected_mb(shark, "job_a"), 100);
+        assert_eq!(projected.unreflected_mb(shark, "job_b"), 100);
+
+        // A stale storinfo record does not account for it.
+        projected.storinfo_update(shark, 0);
+        assert_eq!(projected.unreflected_mb(shark, "job_b"), 100);
+
+        projected.storinfo_update(shark, std::u64::MAX);
+        assert_eq!(projected.unreflected_mb(shark, "job_b"), 0);
+    }
+
+    #[test]
+    fn destination_load_flags_concentration() {
+        let projected = ProjectedUtilization::default();
+
+        projected.reserve("1.stor.domain", "job_a", 300);
+        projected.reserve("1.stor.domain", "job_b", 200);
+        projected.reserve("1.stor.domain", "job_b", 100);
+        projected.reserve("2.stor.domain", "job_a", 400);
+
+        // Landed data is no longer inbound.
+        projected.reserve("3.stor.domain", "job_b", 50);
+        projected.release("3.stor.domain", "job_b", 50, true);
+
+        let report = projected.destination_load(50);
+        assert_eq!(report.inbound_mb, 1000);
+        assert_eq!(report.assignments, 4);
+        assert_eq!(report.destinations.len(), 2);
+
+        let busiest = &report.destinations[0];
+        assert_eq!(busiest.shark, "1.stor.domain");
+        assert_eq!(busiest.inbound_mb, 600);
+        assert_eq!(busiest.assignments, 3);
+        assert_eq!(busiest.jobs.len(), 2);
+        assert!(busiest.concentrated);
+
+        let other = &report.destinations[1];
+        assert_eq!(other.shark, "2.stor.domain");
+        assert!(!other.concentrated);
+
+        projected.job_finished("job_a");
+        projected.job_finished("job_b");
+        let report = projected.destination_load(50);
+        assert_eq!(report.inbound_mb, 0);
+        assert!(report.destinations.is_empty());
+    }
+}
@@ -0,0 +1,435 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Keeping jobs away from agents that keep failing.
+//
+// An agent that is down, or that answers every post with a 5xx, used to be
+// given assignment after assignment by each job that had its storage node as
+// a destination, for as long as the job ran: each one was posted a few times,
+// given up on, and its objects skipped.  Instead, the manager keeps count of
+// how posting assignments to each agent goes, across every job that it runs
+// (see shared()): how many posts failed because a connection to the agent
+// could not be made (it was refused, say), because the post timed out, or
+// because the agent answered with a 5xx status, and how many have failed in
+// a row.  Once `agent_quarantine.max_failures` posts in a row have failed,
+// the agent is quarantined for `agent_quarantine.cooldown_secs`: no job
+// chooses its storage node as a destination until it is released.  Posts
+// that were already under way when it was quarantined are seen through.
+//
+// An agent is released once its cooldown is over, and its count of failures
+// in a row starts again from 0.  An operator can also quarantine an agent by
+// hand, for a while or until it is released by hand, and release an agent
+// early.  Every agent with any failures, or that is quarantined, is listed by
+// GET /agents.
+//
+// The counts are only held in memory, so a manager that is restarted starts
+// out with none, and with no agent quarantined.  A value of 0 for
+// `max_failures` means that agents are never quarantined other than by hand.
+
+use super::StorageId;
+use crate::config::ConfigAgentQuarantine;
+use crate::metrics::{
+    metrics_agent_post_failure_inc, metrics_agents_quarantined_set,
+};
+use rebalancer::util::now_ms;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// Why a post of an assignment to an agent failed.
+#[derive(Clone, Copy, Debug, Display, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum PostFailure {
+    // A connection to the agent could not be made, e.g. it was refused.
+    Connection,
+
+    // The agent did not answer in time.
+    Timeout,
+
+    // The agent answered with a 5xx status.
+    ServerError,
+}
+
+impl PostFailure {
+    /// Why a post that got no response at all failed.
+    pub fn from_error(error: &reqwest::Error) -> PostFailure {
+        if error.is_timeout() {
+            PostFailure::Timeout
+        } else {
+            PostFailure::Connection
+        }
+    }
+}
+
+/// The posts to an agent that have failed, by why.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct PostFailures {
+    pub connection: u64,
+    pub timeout: u64,
+    pub server_error: u64,
+}
+
+/// Why, and until when, an agent is quarantined.  Times are in ms since the
+/// epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Quarantine {
+    pub since: i64,
+
+    /// None if the agent was quarantined by hand until it is released.
+    pub until: Option<i64>,
+
+    /// The agent was quarantined by hand rather than for its failures.
+    pub manual: bool,
+
+    pub reason: String,
+}
+
+/// How posting assignments to an agent has gone, as reported by GET /agents.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AgentStatus {
+    pub storage_id: StorageId,
+    pub failures: PostFailures,
+
+    /// Posts that have failed since the last one that succeeded, or since
+    /// the agent was last released.
+    pub consecutive_failures: u64,
+
+    /// When the last post to fail did, in ms since the epoch.
+    pub last_failure: Option<i64>,
+
+    pub quarantine: Option<Quarantine>,
+}
+
+impl AgentStatus {
+    fn new(storage_id: &str) -> AgentStatus {
+        AgentStatus {
+            storage_id: storage_id.to_string(),
+            failures: PostFailures::default(),
+            consecutive_failures: 0,
+            last_failure: None,
+            quarantine: None,
+        }
+    }
+
+    fn is_quarantined(&self, now: i64) -> bool {
+        match &self.quarantine {
+            Some(q) => q.until.map_or(true, |until| now < until),
+            None => false,
+        }
+    }
+}
+
+/// A request to quarantine an agent by hand.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct QuarantinePayload {
+    /// How long to quarantine the agent for.  If this is not given, the
+    /// agent is quarantined until it is released by hand.
+    pub duration_secs: Option<u64>,
+
+    pub reason: Option<String>,
+}
+
+pub struct AgentQuarantine {
+    config: Mutex<ConfigAgentQuarantine>,
+    agents: Mutex<HashMap<StorageId, AgentStatus>>,
+}
+
+impl AgentQuarantine {
+    pub fn new(config: &ConfigAgentQuarantine) -> AgentQuarantine {
+        AgentQuarantine {
+            config: Mutex::new(*config),
+            agents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn configure(&self, config: &ConfigAgentQuarantine) {
+        *self.config.lock().expect("quarantine config lock") = *config;
+    }
+
+    // Release the agents whose cooldown is over as of `now`.
+    fn expire(agents: &mut HashMap<StorageId, AgentStatus>, now: i64) {
+        for status in agents.values_mut() {
+            if status.quarantine.is_some() && !status.is_quarantined(now) {
+                info!("Releasing agent {} from quarantine", status.storage_id);
+                status.quarantine = None;
+                status.consecutive_failures = 0;
+            }
+        }
+
+        metrics_agents_quarantined_set(
+            agents.values().filter(|s| s.quarantine.is_some()).count(),
+        );
+    }
+
+    /// A post of an assignment to `agent` failed at `now`.  Returns true if
+    /// that got the agent quarantined.
+    fn failed_at(&self, agent: &str, failure: PostFailure, now: i64) -> bool {
+        let config = *self.config.lock().expect("quarantine config lock");
+        let mut agents = self.agents.lock().expect("quarantine lock");
+        Self::expire(&mut agents, now);
+
+        let status = agents
+            .entry(agent.to_string())
+            .or_insert_with(|| AgentStatus::new(agent));
+
+        match failure {
+            PostFailure::Connection => status.failures.connection += 1,
+            PostFailure::Timeout => status.failures.timeout += 1,
+            PostFailure::ServerError => status.failures.server_error += 1,
+        }
+        status.consecutive_failures += 1;
+        status.last_failure = Some(now);
+
+        if config.max_failures == 0
+            || status.quarantine.is_some()
+            || status.consecutive_failures < config.max_failures
+        {
+            return false;
+        }
+
+        let cooldown_ms = config.cooldown_secs.saturating_mul(1000) as i64;
+        let reason = format!(
+            "{} posts in a row failed, the last with {}",
+            status.consecutive_failures, failure
+        );
+        warn!(
+            "Quarantining agent {} for {} seconds: {}",
+            agent, config.cooldown_secs, reason
+        );
+        status.quarantine = Some(Quarantine {
+            since: now,
+            until: Some(now.saturating_add(cooldown_ms)),
+            manual: false,
+            reason,
+        });
+        Self::expire(&mut agents, now);
+
+        true
+    }
+
+    /// A post of an assignment to `agent` failed.  Returns true if that got
+    /// the agent quarantined.
+    pub fn post_failed(&self, agent: &str, failure: PostFailure) -> bool {
+        metrics_agent_post_failure_inc(&failure.to_string());
+        self.failed_at(agent, failure, now_ms())
+    }
+
+    /// A post of an assignment to `agent` succeeded.
+    pub fn post_succeeded(&self, agent: &str) {
+        let mut agents = self.agents.lock().expect("quarantine lock");
+
+        if let Some(status) = agents.get_mut(agent) {
+            status.consecutive_failures = 0;
+        }
+    }
+
+    fn is_quarantined_at(&self, agent: &str, now: i64) -> bool {
+        let agents = self.agents.lock().expect("quarantine lock");
+        agents.get(agent).map_or(false, |s| s.is_quarantined(now))
+    }
+
+    /// Returns true if `agent` must not be chosen as a destination.
+    pub fn is_quarantined(&self, agent: &str) -> bool {
+        self.is_quarantined_at(agent, now_ms())
+    }
+
+    fn quarantine_at(
+        &self,
+        agent: &str,
+        payload: &QuarantinePayload,
+        now: i64,
+    ) -> AgentStatus {
+        let mut agents = self.agents.lock().expect("quarantine lock");
+        let status = agents
+            .entry(agent.to_string())
+            .or_insert_with(|| AgentStatus::new(agent));
+
+        let until = payload
+            .duration_secs
+            .map(|secs| now.saturating_add(secs.saturating_mul(1000) as i64));
+        let reason = payload
+            .reason
+            .clone()
+            .unwrap_or_else(|| String::from("quarantined by an operator"));
+
+        warn!("Quarantining agent {}: {}", agent, reason);
+        status.quarantine = Some(Quarantine {
+            since: now,
+            until,
+            manual: true,
+            reason,
+        });
+        let status = status.clone();
+        Self::expire(&mut agents, now);
+
+        status
+    }
+
+    /// Quarantine `agent` by hand, whether or not it has any failures.
+    pub fn quarantine(
+        &self,
+        agent: &str,
+        payload: &QuarantinePayload,
+    ) -> AgentStatus {
+        self.quarantine_at(agent, payload, now_ms())
+    }
+
+    /// Release `agent` from quarantine, and start its count of failures in a
+    /// row again.  Returns None if nothing is known of the agent.
+    pub fn release(&self, agent: &str) -> Option<AgentStatus> {
+        let mut agents = self.agents.lock().expect("quarantine lock");
+
+        let status = agents.get_mut(agent).map(|status| {
+            if status.quarantine.take().is_some() {
+                info!("Agent {} released from quarantine by hand", agent);
+            }
+            status.consecutive_failures = 0;
+            status.clone()
+        });
+        Self::expire(&mut agents, now_ms());
+
+        status
+    }
+
+    fn list_at(&self, now: i64) -> Vec<AgentStatus> {
+        let mut agents = self.agents.lock().expect("quarantine lock");
+        Self::expire(&mut agents, now);
+
+        let mut list: Vec<AgentStatus> = agents.values().cloned().collect();
+        list.sort_by(|a, b| a.storage_id.cmp(&b.storage_id));
+
+        list
+    }
+
+    /// Every agent that has had a post fail, or that is quarantined, by
+    /// storage id.
+    pub fn list(&self) -> Vec<AgentStatus> {
+        self.list_at(now_ms())
+    }
+}
+
+lazy_static! {
+    static ref SHARED: Arc<AgentQuarantine> =
+        Arc::new(AgentQuarantine::new(&ConfigAgentQuarantine::default()));
+}
+
+/// The quarantine shared by every job running in this manager.
+pub fn shared() -> Arc<AgentQuarantine> {
+    Arc::clone(&SHARED)
+}
+
+/// Use the given thresholds from now on.
+pub fn configure(config: &ConfigAgentQuarantine) {
+    SHARED.configure(config);
+}
+
+/// Returns true if `agent` is quarantined, and so must not be chosen as the
+/// destination of any job.
+pub fn is_quarantined(agent: &str) -> bool {
+    SHARED.is_quarantined(agent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_failures() {
+        let quarantine = AgentQuarantine::new(&ConfigAgentQuarantine {
+            max_failures: 3,
+            cooldown_secs: 60,
+        });
+        let agent = "1.stor.domain";
+        let start: i64 = 1_600_000_000_000;
+
+        // A success sets the count of failures in a row back to 0.
+        assert!(!quarantine.failed_at(agent, PostFailure::Timeout, start));
+        assert!(!quarantine.failed_at(agent, PostFailure::Timeout, start));
+        quarantine.post_succeeded(agent);
+        assert!(!quarantine.failed_at(agent, PostFailure::Connection, start));
+        assert!(!quarantine.failed_at(agent, PostFailure::Connection, start));
+        assert!(!quarantine.is_quarantined_at(agent, start));
+
+        assert!(quarantine.failed_at(agent, PostFailure::ServerError, start));
+        assert!(quarantine.is_quarantined_at(agent, start));
+        assert!(!quarantine.is_quarantined_at("2.stor.domain", start));
+
+        let list = quarantine.list_at(start);
+        assert_eq!(list.len(), 1);
+        assert_eq!(
+            list[0].failures,
+            PostFailures {
+                connection: 2,
+                timeout: 2,
+                server_error: 1,
+            }
+        );
+        let q = list[0].quarantine.clone().expect("quarantined");
+        assert_eq!(q.until, Some(start + 60_000));
+        assert!(!q.manual);
+
+        // The cooldown ends, and the count starts again.
+        let later = start + 60_000;
+        assert!(!quarantine.is_quarantined_at(agent, later));
+        let list = quarantine.list_at(later);
+        assert_eq!(list[0].quarantine, None);
+        assert_eq!(list[0].consecutive_failures, 0);
+        assert!(!quarantine.failed_at(agent, PostFailure::Timeout, later));
+
+        // Without a threshold, agents are only quarantined by hand.
+        quarantine.configure(&ConfigAgentQuarantine {
+            max_failures: 0,
+            cooldown_secs: 60,
+        });
+        for _ in 0..10 {
+            assert!(!quarantine.failed_at(agent, PostFailure::Timeout, later));
+        }
+    }
+
+    #[test]
+    fn quarantine_manual() {
+        let quarantine =
+            AgentQuarantine::new(&ConfigAgentQuarantine::default());
+        let agent = "3.stor.domain";
+        let start: i64 = 1_600_000_000_000;
+
+        assert_eq!(quarantine.release(agent), None);
+
+        let status = quarantine.quarantine_at(
+            agent,
+            &QuarantinePayload::default(),
+            start,
+        );
+        let q = status.quarantine.expect("quarantined");
+        assert!(q.manual);
+        assert_eq!(q.until, None);
+        assert!(quarantine.is_quarantined_at(agent, start + 86_400_000));
+
+        let status = quarantine.release(agent).expect("released");
+        assert_eq!(status.quarantine, None);
+        assert!(!quarantine.is_quarantined_at(agent, start));
+
+        let payload = QuarantinePayload {
+            duration_secs: Some(10),
+            reason: Some(String::from("disk replacement")),
+        };
+        let status = quarantine.quarantine_at(agent, &payload, start);
+        assert_eq!(
+            status.quarantine.map(|q| q.reason),
+            Some(String::from("disk replacement"))
+        );
+        assert!(quarantine.is_quarantined_at(agent, start + 9_999));
+        assert!(!quarantine.is_quarantined_at(agent, start + 10_000));
+    }
+}
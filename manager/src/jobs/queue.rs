@@ -0,0 +1,308 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+use super::{lease, Job, JobPriority, JobState, StorageId};
+use crate::shutdown;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+// How often a caller blocked in `next()` re-evaluates the concurrency limit
+// even if nothing has been pushed or finished.  This allows a change to the
+// max_concurrent_jobs tunable to take effect without any other activity.
+static LIMIT_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct QueuedJob {
+    priority: JobPriority,
+    job: Job,
+
+    // The shark that the job evacuates, if it is an evacuate job.
+    shark: Option<StorageId>,
+}
+
+#[derive(Default)]
+struct QueueInner {
+    // Ordered by priority (highest first), and within a given priority by
+    // the order in which the jobs were pushed.
+    queued: Vec<QueuedJob>,
+
+    // Each job that is running, with the shark that it evacuates if any.
+    running: HashMap<Uuid, Option<StorageId>>,
+}
+
+impl QueueInner {
+    // The index of the first queued job that can be started, if fewer than
+    // `max_running` jobs are running.  A job that evacuates a shark waits for
+    // any other job evacuating the same shark to finish first.
+    fn ready(&self, max_running: usize) -> Option<usize> {
+        if self.running.len() >= max_running {
+            return None;
+        }
+
+        self.queued.iter().position(|q| match &q.shark {
+            Some(shark) => {
+                !self.running.values().any(|s| s.as_ref() == Some(shark))
+            }
+            None => true,
+        })
+    }
+}
+
+/// Jobs that have been accepted by the manager but which may not be able to
+/// start right away because the maximum number of concurrently running jobs
+/// has been reached, or because another job is evacuating the same shark.  A
+/// job is dequeued by `next()` once it can be started, and the caller must
+/// call `finished()` once the job has completed to release its slot.
+#[derive(Default)]
+pub struct JobQueue {
+    inner: Mutex<QueueInner>,
+    cvar: Condvar,
+}
+
+impl JobQueue {
+    pub fn new() -> JobQueue {
+        JobQueue::default()
+    }
+
+    /// Add a job to the queue, ahead of any jobs of lower priority.  The job
+    /// remains in the Queued state until it is started.  With coordination,
+    /// the lease on the job is taken out first, and a job that another
+    /// manager holds is refused.
+    pub fn push(
+        &self,
+        mut job: Job,
+        priority: JobPriority,
+    ) -> Result<(), Error> {
+        let job_id = job.get_id().to_string();
+        if !lease::claim(&job_id)? {
+            return Err(InternalError::new(
+                Some(InternalErrorCode::JobLeased),
+                format!("Job {} is leased to another manager", job_id),
+            )
+            .into());
+        }
+
+        job.update_state(JobState::Queued)?;
+
+        let shark = job.evacuated_shark().map(String::from);
+        let mut inner = self.inner.lock().expect("job queue lock");
+        let index = inner
+            .queued
+            .iter()
+            .position(|q| q.priority < priority)
+            .unwrap_or_else(|| inner.queued.len());
+
+        info!(
+            "Queueing job {} with {:?} priority at position {}",
+            job.get_id(),
+            priority,
+            index + 1
+        );
+
+        inner.queued.insert(
+            index,
+            QueuedJob {
+                priority,
+                job,
+                shark,
+            },
+        );
+        self.cvar.notify_all();
+
+        Ok(())
+    }
+
+    /// The 1-based position of a job in the queue, or None if the job is not
+    /// waiting to be run.
+    pub fn position(&self, uuid: &Uuid) -> Option<usize> {
+        let inner = self.inner.lock().expect("job queue lock");
+
+        inner
+            .queued
+            .iter()
+            .position(|q| q.job.get_id() == *uuid)
+            .map(|p| p + 1)
+    }
+
+    /// The job evacuating `shark` that is queued or running, if there is one.
+    /// A running job is given ahead of one that is only queued.
+    pub fn active_evacuation(&self, shark: &str) -> Option<Uuid> {
+        let inner = self.inner.lock().expect("job queue lock");
+        let running = inner
+            .running
+            .iter()
+            .find(|(_, s)| s.as_ref().map(String::as_str) == Some(shark))
+            .map(|(id, _)| *id);
+
+        running.or_else(|| {
+            inner
+                .queued
+                .iter()
+                .find(|q| q.shark.as_ref().map(String::as_str) == Some(shark))
+                .map(|q| q.job.get_id())
+        })
+    }
+
+    /// Block until there is a job in the queue that can be started and fewer
+    /// than `max_running()` jobs are running, then remove the first such job
+    /// from the queue and count it as running.  Once a shutdown has been
+    /// requested no more jobs are returned.
+    pub fn next<F>(&self, max_running: F) -> Job
+    where
+        F: Fn() -> usize,
+    {
+        let mut inner = self.inner.lock().expect("job queue lock");
+
+        loop {
+            if !shutdown::requested() {
+                if let Some(index) = inner.ready(max_running()) {
+                    let queued = inner.queued.remove(index);
+                    inner.running.insert(queued.job.get_id(), queued.shark);
+                    return queued.job;
+                }
+            }
+
+            inner = self
+                .cvar
+                .wait_timeout(inner, LIMIT_RECHECK_INTERVAL)
+                .expect("job queue wait")
+                .0;
+        }
+    }
+
+    /// The number of jobs returned from `next()` that have not yet finished.
+    pub fn running(&self) -> usize {
+        self.inner.lock().expect("job queue lock").running.len()
+    }
+
+    /// Remove every job that is still waiting to be run and move each of
+    /// them to the Interrupted state, so that they are resumed the next time
+    /// the manager starts, or by another manager if it gets to them first.
+    /// Returns the number of jobs removed.
+    pub fn interrupt_queued(&self) -> usize {
+        let queued: Vec<QueuedJob> = {
+            let mut inner = self.inner.lock().expect("job queue lock");
+            inner.queued.drain(..).collect()
+        };
+        let count = queued.len();
+
+        for mut q in queued {
+            if let Err(e) = q.job.update_state(JobState::Interrupted) {
+                error!(
+                    "Could not mark queued job {} interrupted: {}",
+                    q.job.get_id(),
+                    e
+                );
+            }
+            lease::release(&q.job.get_id().to_string());
+        }
+
+        count
+    }
+
+    /// Release the slot held by the job `job_id`, returned from `next()`,
+    /// along with the lease on it.
+    pub fn finished(&self, job_id: &Uuid) {
+        lease::release(&job_id.to_string());
+
+        let mut inner = self.inner.lock().expect("job queue lock");
+        inner.running.remove(job_id);
+        self.cvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::jobs::JobBuilder;
+    use rebalancer::util;
+
+    fn shark_job(shark: &str) -> Job {
+        let config = Config::parse_config(&Some("src/config.json".to_string()))
+            .expect("parse config");
+
+        JobBuilder::new(config)
+            .evacuate(shark.to_string(), Some(1))
+            .commit()
+            .expect("failed to create job")
+    }
+
+    fn test_job() -> Job {
+        shark_job("1.stor.domain")
+    }
+
+    #[test]
+    fn priority_order() {
+        let _guard = util::init_global_logger(None);
+        let queue = JobQueue::new();
+
+        let first = test_job();
+        let second = test_job();
+        let urgent = test_job();
+        let first_id = first.get_id();
+        let second_id = second.get_id();
+        let urgent_id = urgent.get_id();
+
+        queue.push(first, JobPriority::Normal).expect("push");
+        queue.push(second, JobPriority::Normal).expect("push");
+        queue.push(urgent, JobPriority::Urgent).expect("push");
+
+        assert_eq!(queue.position(&urgent_id), Some(1));
+        assert_eq!(queue.position(&first_id), Some(2));
+        assert_eq!(queue.position(&second_id), Some(3));
+
+        let job = queue.next(|| 1);
+        assert_eq!(job.get_id(), urgent_id);
+        assert_eq!(job.state, JobState::Queued);
+        assert_eq!(queue.position(&urgent_id), None);
+        assert_eq!(queue.position(&first_id), Some(1));
+
+        queue.finished(&urgent_id);
+        assert_eq!(queue.next(|| 1).get_id(), first_id);
+    }
+
+    #[test]
+    fn same_shark_waits() {
+        let _guard = util::init_global_logger(None);
+        let queue = JobQueue::new();
+
+        let first = shark_job("1.stor.domain");
+        let second = shark_job("1.stor.domain");
+        let other = shark_job("2.stor.domain");
+        let first_id = first.get_id();
+        let second_id = second.get_id();
+        let other_id = other.get_id();
+
+        assert_eq!(queue.active_evacuation("1.stor.domain"), None);
+        queue.push(first, JobPriority::Normal).expect("push");
+        queue.push(second, JobPriority::Urgent).expect("push");
+        queue.push(other, JobPriority::Normal).expect("push");
+        assert_eq!(queue.active_evacuation("1.stor.domain"), Some(second_id));
+
+        assert_eq!(queue.next(|| 3).get_id(), second_id);
+        assert_eq!(queue.active_evacuation("1.stor.domain"), Some(second_id));
+
+        // The other job of the same shark waits behind the running one,
+        // while a job of another shark does not.
+        assert_eq!(queue.inner.lock().unwrap().ready(3), Some(1));
+        assert_eq!(queue.next(|| 3).get_id(), other_id);
+        assert_eq!(queue.inner.lock().unwrap().ready(3), None);
+        assert_eq!(queue.position(&first_id), Some(1));
+
+        queue.finished(&second_id);
+        assert_eq!(queue.active_evacuation("1.stor.domain"), Some(first_id));
+        assert_eq!(queue.next(|| 3).get_id(), first_id);
+        assert_eq!(queue.running(), 2);
+    }
+}
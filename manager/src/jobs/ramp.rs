@@ -0,0 +1,234 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Warm-up of a new job.
+//
+// An evacuate job starts out sending assignments to as many destination
+// sharks as it is allowed (options.max_sharks), and starting as many metadata
+// update threads as it is allowed (options.max_metadata_update_threads).  For
+// a big job, that saturates the destination fleet and the metadata tier the
+// moment the job starts.  If `ramp.ramp_minutes` is set, a job instead starts
+// at `ramp.start_percentage` of each of these limits and works up to the full
+// limit over `ramp.ramp_minutes`.
+//
+// While it ramps up, the job counts the objects it moves and those it fails
+// to move.  At the end of every RAMP_WINDOW, if the fraction that failed
+// during that window is above `ramp.max_error_ratio`, the ramp is held where
+// it is for the next window.  Time spent holding does not count towards the
+// ramp.
+//
+// The ramp starts the first time the job asks for a limit, rather than when
+// the job is created, so that time spent queued does not count towards it.
+
+use crate::config::ConfigRamp;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often the error ratio is checked while ramping up.
+static RAMP_WINDOW: Duration = Duration::from_secs(30);
+
+struct RampState {
+    // Time spent ramping up, not counting time that the ramp was held.
+    progress: Duration,
+    last_tick: Option<Instant>,
+    window_start: Option<Instant>,
+    window_moved: u64,
+    window_failed: u64,
+    held: bool,
+}
+
+pub struct RampSchedule {
+    job_id: String,
+    config: ConfigRamp,
+    done: AtomicBool,
+    state: Mutex<RampState>,
+}
+
+impl RampSchedule {
+    pub fn new(job_id: &str, config: &ConfigRamp) -> RampSchedule {
+        RampSchedule {
+            job_id: job_id.to_string(),
+            config: *config,
+            done: AtomicBool::new(config.ramp_minutes == 0),
+            state: Mutex::new(RampState {
+                progress: Duration::from_secs(0),
+                last_tick: None,
+                window_start: None,
+                window_moved: 0,
+                window_failed: 0,
+                held: false,
+            }),
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        Duration::from_secs(self.config.ramp_minutes.saturating_mul(60))
+    }
+
+    /// Record that `count` objects were moved.
+    pub fn moved(&self, count: u64) {
+        if count == 0 || self.done.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("ramp lock");
+        state.window_moved = state.window_moved.saturating_add(count);
+    }
+
+    /// Record that `count` objects could not be moved.
+    pub fn failed(&self, count: u64) {
+        if count == 0 || self.done.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("ramp lock");
+        state.window_failed = state.window_failed.saturating_add(count);
+    }
+
+    /// Returns true if the job has reached its full concurrency.
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::SeqCst)
+    }
+
+    fn tick(&self, now: Instant) {
+        let mut state = self.state.lock().expect("ramp lock");
+
+        let last_tick = match state.last_tick {
+            Some(t) => t,
+            None => {
+                info!(
+                    "Job {} ramping up from {}% over {} minutes",
+                    self.job_id,
+                    self.config.start_percentage,
+                    self.config.ramp_minutes
+                );
+                state.last_tick = Some(now);
+                state.window_start = Some(now);
+                return;
+            }
+        };
+
+        if !state.held {
+            state.progress += now.saturating_duration_since(last_tick);
+        }
+        state.last_tick = Some(now);
+
+        let window_start = state.window_start.unwrap_or(now);
+        if now.saturating_duration_since(window_start) >= RAMP_WINDOW {
+            let total = state.window_moved.saturating_add(state.window_failed);
+            let ratio = if total > 0 {
+                state.window_failed as f64 / total as f64
+            } else {
+                0.0
+            };
+            let held = ratio > self.config.max_error_ratio;
+
+            if held && !state.held {
+                warn!(
+                    "Job {} holding ramp up: {} of {} objects failed",
+                    self.job_id, state.window_failed, total
+                );
+            } else if !held && state.held {
+                info!("Job {} resuming ramp up", self.job_id);
+            }
+
+            state.held = held;
+            state.window_start = Some(now);
+            state.window_moved = 0;
+            state.window_failed = 0;
+        }
+
+        if state.progress >= self.duration() {
+            info!("Job {} has ramped up to full concurrency", self.job_id);
+            self.done.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn limit_at(&self, target: usize, now: Instant) -> usize {
+        if target == 0 || self.is_done() {
+            return target;
+        }
+
+        self.tick(now);
+
+        if self.is_done() {
+            return target;
+        }
+
+        // target * (start + (100 - start) * progress / duration) / 100,
+        // rounded up.  This is done in integers so that, for example, half
+        // way through a ramp from 20% of 10 is exactly 6.
+        let start = u128::from(self.config.start_percentage.min(100));
+        let progress = self.state.lock().expect("ramp lock").progress;
+        let duration = self.duration().as_millis().max(1);
+        let numerator = target as u128
+            * (start * duration + (100 - start) * progress.as_millis());
+        let denominator = 100 * duration;
+        let limit = ((numerator + denominator - 1) / denominator) as usize;
+
+        limit.max(1).min(target)
+    }
+
+    /// The share of `target` (e.g. the maximum number of destination sharks)
+    /// that the job should use right now.  This is always at least 1.
+    pub fn limit(&self, target: usize) -> usize {
+        self.limit_at(target, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(m: u64) -> Duration {
+        Duration::from_secs(m * 60)
+    }
+
+    #[test]
+    fn ramp_disabled() {
+        let ramp = RampSchedule::new("job", &ConfigRamp::default());
+
+        assert!(ramp.is_done());
+        assert_eq!(ramp.limit(10), 10);
+    }
+
+    #[test]
+    fn ramp_holds_on_errors() {
+        let config = ConfigRamp {
+            ramp_minutes: 10,
+            start_percentage: 20,
+            max_error_ratio: 0.1,
+        };
+        let ramp = RampSchedule::new("job", &config);
+        let start = Instant::now();
+
+        assert_eq!(ramp.limit_at(10, start), 2);
+
+        // Half way.
+        assert_eq!(ramp.limit_at(10, start + minutes(5)), 6);
+
+        // Errors spike, so the ramp does not progress past the end of the
+        // window in which they were seen.
+        ramp.moved(5);
+        ramp.failed(5);
+        assert_eq!(ramp.limit_at(10, start + minutes(6)), 7);
+        assert_eq!(ramp.limit_at(10, start + minutes(8)), 7);
+
+        // Nothing failed in the last window, so the ramp resumes.
+        ramp.moved(100);
+        assert_eq!(ramp.limit_at(10, start + minutes(10)), 9);
+        assert!(!ramp.is_done());
+
+        assert_eq!(ramp.limit_at(10, start + minutes(12)), 10);
+        assert!(ramp.is_done());
+    }
+}
@@ -0,0 +1,404 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Classification of the metadata records that a job finds on the shark it is
+// evacuating.
+//
+// Most records in the manta bucket describe regular objects, but over the
+// years the bucket has also come to hold records that are not objects (e.g.
+// directories and links), and objects written by older versions of manta
+// whose records have a slightly different shape.  Rather than let these fail
+// to parse somewhere further down the pipeline, each record is classified as
+// soon as it is found:
+//
+//  * A regular object is passed on to be rebalanced.  Records written by older
+//    versions of manta are first rewritten into the current shape (see
+//    normalize()).
+//  * Anything else is given a disposition saying why it was not rebalanced,
+//    and is counted as such.  These records are not added to the job's
+//    database, as there is nothing to track for them.
+//
+// A storage node that exposes several zpools has a storage id for each of
+// them, and a job works on the objects of one of those storage ids only,
+// leaving the node's other pools serving.  Sharkspotter does not match the
+// storage ids in a record's list of sharks exactly, so a record that it finds
+// may only have a copy on another storage id with a similar name, such as one
+// of the node's other pools.  Such a record is not rebalanced either (see
+// has_copy_on()).
+//
+// Sharkspotter parses each record as it is read from the shard, and a record
+// that could not be used is skipped and counted like any other that is not
+// rebalanced, so that one bad record does not stop the scan of its shard: a
+// record that is not a JSON object at all is malformed, and one whose
+// encoding is larger than `options.max_record_bytes` is oversized.  An
+// oversized record is dropped as soon as it is found, rather than being held
+// on to for as long as its object is part of the job.
+
+use serde_json::Value;
+
+#[derive(Display, EnumIter, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[strum(serialize_all = "snake_case")]
+pub enum RecordDisposition {
+    Object,          // A regular object, to be rebalanced.
+    Malformed,       // A record that is not a JSON object.
+    Oversized,       // A record larger than options.max_record_bytes.
+    Directory,       // A directory, which has no data on any shark.
+    Link,            // A legacy link record, which can not be moved.
+    ZeroLength,      // An object with no data to move.
+    MissingObjectId, // An object record without an objectId.
+    UnknownType,     // A record of some other type.
+    EnoughCopies,    // An object that a create-copy job need not copy.
+    TooFewCopies,    // An object that a remove-copy job must not remove.
+    NotOnShark,      // An object without a copy on the job's storage id.
+    Filtered,        // An object that the job's filter leaves out.
+}
+
+// Records written before the "type" field was introduced are objects.
+fn record_type(record: &Value) -> &str {
+    record
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("object")
+}
+
+// Rewrite the fields of a record written by an older version of manta into
+// the shape that the rest of the job expects:
+//  * contentLength was, for a time, stored as a string.
+//  * Some records have a null list of sharks rather than an empty one.
+fn normalize(record: &mut Value) {
+    let obj = match record.as_object_mut() {
+        Some(o) => o,
+        None => return,
+    };
+
+    let content_length = obj
+        .get("contentLength")
+        .and_then(Value::as_str)
+        .and_then(|cl| cl.trim().parse::<u64>().ok());
+    if let Some(cl) = content_length {
+        obj.insert("contentLength".to_string(), Value::from(cl));
+    }
+
+    if let Some(Value::Null) = obj.get("sharks") {
+        obj.insert("sharks".to_string(), Value::Array(vec![]));
+    }
+}
+
+/// Returns true if the record lists a copy on exactly `storage_id`.
+pub fn has_copy_on(record: &Value, storage_id: &str) -> bool {
+    record
+        .get("sharks")
+        .and_then(Value::as_array)
+        .map(|sharks| {
+            sharks.iter().any(|s| {
+                s.get("manta_storage_id").and_then(Value::as_str)
+                    == Some(storage_id)
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// The length of the JSON encoding of `value`, not counting the escaping of
+/// any characters in its strings.
+pub fn encoded_len(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Bool(true) => 4,
+        Value::Bool(false) => 5,
+        Value::Number(n) => n.to_string().len(),
+        Value::String(s) => s.len() + 2,
+        Value::Array(vals) => {
+            vals.iter().map(encoded_len).sum::<usize>()
+                + vals.len().saturating_sub(1)
+                + 2
+        }
+        Value::Object(map) => {
+            map.iter()
+                .map(|(k, v)| k.len() + 3 + encoded_len(v))
+                .sum::<usize>()
+                + map.len().saturating_sub(1)
+                + 2
+        }
+    }
+}
+
+/// Returns true if the record is larger than `max_bytes`, unless that is 0.
+pub fn is_oversized(record: &Value, max_bytes: usize) -> bool {
+    max_bytes > 0 && encoded_len(record) > max_bytes
+}
+
+/// Classify a metadata record found by sharkspotter, as it is limited to
+/// `max_bytes` (see is_oversized()).  If the record is a regular object it is
+/// normalized in place.
+pub fn classify(record: &mut Value, max_bytes: usize) -> RecordDisposition {
+    if !record.is_object() {
+        return RecordDisposition::Malformed;
+    }
+
+    if is_oversized(record, max_bytes) {
+        return RecordDisposition::Oversized;
+    }
+
+    match record_type(record) {
+        "object" => (),
+        "directory" => return RecordDisposition::Directory,
+        "link" => return RecordDisposition::Link,
+        _ => return RecordDisposition::UnknownType,
+    }
+
+    let has_object_id = match record.get("objectId") {
+        Some(Value::String(id)) => !id.is_empty(),
+        _ => false,
+    };
+    if !has_object_id {
+        return RecordDisposition::MissingObjectId;
+    }
+
+    normalize(record);
+
+    match record.get("contentLength").and_then(Value::as_u64) {
+        Some(0) => RecordDisposition::ZeroLength,
+        _ => RecordDisposition::Object,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Record shapes seen in the manta buckets of long lived deployments.
+    fn corpus() -> Vec<(&'static str, Value, RecordDisposition)> {
+        vec![
+            (
+                "current object",
+                json!({
+                    "type": "object",
+                    "key": "/account/stor/file",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "objectId": "2d1c5d0e-8b0e-4a6a-8f61-3b8a8b4f6b8e",
+                    "contentLength": 1048576,
+                    "contentMD5": "1B2M2Y8AsgTpgAmY7PhCfg==",
+                    "etag": "2d1c5d0e-8b0e-4a6a-8f61-3b8a8b4f6b8e",
+                    "sharks": [
+                        {
+                            "datacenter": "dc1",
+                            "manta_storage_id": "1.stor.domain"
+                        },
+                        {
+                            "datacenter": "dc2",
+                            "manta_storage_id": "2.stor.domain"
+                        }
+                    ]
+                }),
+                RecordDisposition::Object,
+            ),
+            (
+                "object written before the type field",
+                json!({
+                    "key": "/account/stor/old",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "objectId": "5a0f2c3e-1d4b-4f7a-9c2e-6b8d0e1f2a3b",
+                    "contentLength": 512,
+                    "sharks": [{
+                        "datacenter": "dc1",
+                        "manta_storage_id": "1.stor.domain"
+                    }]
+                }),
+                RecordDisposition::Object,
+            ),
+            (
+                "object with a string content length",
+                json!({
+                    "type": "object",
+                    "key": "/account/stor/string-length",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "objectId": "7c1e3a5f-2b4d-4e6f-8a0b-1c2d3e4f5a6b",
+                    "contentLength": "2048",
+                    "sharks": [{
+                        "datacenter": "dc1",
+                        "manta_storage_id": "1.stor.domain"
+                    }]
+                }),
+                RecordDisposition::Object,
+            ),
+            (
+                "zero length object",
+                json!({
+                    "type": "object",
+                    "key": "/account/stor/empty",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "objectId": "9e2f4b6a-3c5d-4f7e-9a1b-2c3d4e5f6a7b",
+                    "contentLength": 0,
+                    "contentMD5": "1B2M2Y8AsgTpgAmY7PhCfg==",
+                    "sharks": null
+                }),
+                RecordDisposition::ZeroLength,
+            ),
+            (
+                "zero length object with a string content length",
+                json!({
+                    "key": "/account/stor/empty-old",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "objectId": "0b3a5c7e-4d6f-4a8b-8c2d-3e4f5a6b7c8d",
+                    "contentLength": "0",
+                    "sharks": [{
+                        "datacenter": "dc1",
+                        "manta_storage_id": "1.stor.domain"
+                    }]
+                }),
+                RecordDisposition::ZeroLength,
+            ),
+            (
+                "directory",
+                json!({
+                    "type": "directory",
+                    "key": "/account/stor/dir",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "mtime": 1584033003213u64
+                }),
+                RecordDisposition::Directory,
+            ),
+            (
+                "link",
+                json!({
+                    "type": "link",
+                    "key": "/account/stor/link",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "link": "/account/stor/file",
+                    "objectId": "2d1c5d0e-8b0e-4a6a-8f61-3b8a8b4f6b8e"
+                }),
+                RecordDisposition::Link,
+            ),
+            (
+                "object without an objectId",
+                json!({
+                    "type": "object",
+                    "key": "/account/stor/no-id",
+                    "owner": "a0c2e6b8-3f1e-4b3e-9d8b-2b8f1c6e7d10",
+                    "contentLength": 10,
+                    "sharks": [{
+                        "datacenter": "dc1",
+                        "manta_storage_id": "1.stor.domain"
+                    }]
+                }),
+                RecordDisposition::MissingObjectId,
+            ),
+            (
+                "object with an empty objectId",
+                json!({
+                    "type": "object",
+                    "key": "/account/stor/empty-id",
+                    "objectId": "",
+                    "contentLength": 10
+                }),
+                RecordDisposition::MissingObjectId,
+            ),
+            (
+                "unknown type",
+                json!({
+                    "type": "bucket",
+                    "key": "/account/buckets/b",
+                    "objectId": "3f5b7d9a-6e8f-4a0b-9c3d-4e5f6a7b8c9d"
+                }),
+                RecordDisposition::UnknownType,
+            ),
+            (
+                "string",
+                json!("not a record"),
+                RecordDisposition::Malformed,
+            ),
+            ("null", Value::Null, RecordDisposition::Malformed),
+            (
+                "array",
+                json!([{"objectId": "4a6c8e0b-7f9a-4b1c-8d2e-5f6a7b8c9d0e"}]),
+                RecordDisposition::Malformed,
+            ),
+        ]
+    }
+
+    #[test]
+    fn classify_corpus() {
+        for (name, mut record, expected) in corpus() {
+            assert_eq!(classify(&mut record, 0), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn record_size_limit() {
+        for (name, record, _) in corpus() {
+            let encoded = serde_json::to_string(&record).expect("encode");
+            assert_eq!(encoded_len(&record), encoded.len(), "{}", name);
+        }
+
+        let (_, record, _) = corpus().remove(0);
+        let len = encoded_len(&record);
+
+        assert_eq!(
+            classify(&mut record.clone(), len),
+            RecordDisposition::Object
+        );
+        assert_eq!(
+            classify(&mut record.clone(), len - 1),
+            RecordDisposition::Oversized
+        );
+        assert!(!is_oversized(&record, 0));
+    }
+
+    #[test]
+    fn normalize_legacy_object() {
+        let mut record = json!({
+            "key": "/account/stor/string-length",
+            "objectId": "7c1e3a5f-2b4d-4e6f-8a0b-1c2d3e4f5a6b",
+            "contentLength": " 2048",
+            "sharks": null
+        });
+
+        assert_eq!(classify(&mut record, 0), RecordDisposition::Object);
+        assert_eq!(record["contentLength"], json!(2048));
+        assert_eq!(record["sharks"], json!([]));
+    }
+
+    #[test]
+    fn non_objects_not_normalized() {
+        let mut record = json!({
+            "type": "directory",
+            "key": "/account/stor/dir",
+            "contentLength": "0"
+        });
+
+        assert_eq!(classify(&mut record, 0), RecordDisposition::Directory);
+        assert_eq!(record["contentLength"], json!("0"));
+    }
+
+    #[test]
+    fn copy_on_exact_storage_id() {
+        let record = json!({
+            "type": "object",
+            "objectId": "9b2d4f6a-8c0e-4a2b-b4d6-f8a0c2e4a6b8",
+            "contentLength": 4096,
+            "sharks": [
+                {
+                    "datacenter": "dc1",
+                    "manta_storage_id": "1.stor.domain"
+                },
+                {
+                    "datacenter": "dc1",
+                    "manta_storage_id": "1-pool2.stor.domain"
+                }
+            ]
+        });
+
+        assert!(has_copy_on(&record, "1.stor.domain"));
+        assert!(has_copy_on(&record, "1-pool2.stor.domain"));
+        assert!(!has_copy_on(&record, "1.stor"));
+        assert!(!has_copy_on(&record, "11.stor.domain"));
+        assert!(!has_copy_on(&json!({"sharks": null}), "1.stor.domain"));
+    }
+}
@@ -0,0 +1,314 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// The agents that have registered with the manager, and what they can do.
+//
+// An agent configured to register (see rebalancer::registration) sends the
+// manager its version, boot id and capabilities when it starts, and again at
+// each of its heartbeats, with PUT /agents/<storage_id>/registration.  Every
+// job in the manager (see shared()) then holds a registered agent to what it
+// said it can do: an object is not given to an assignment for an agent that
+// does not support the action of its task (the object is skipped with a
+// reason of `agent_unsupported`), and no assignment for an agent is given more
+// tasks than the agent takes.  A storage node whose agent does not support
+// copy tasks is not chosen as a destination at all.  With
+// `options.compress_transfers` set, an agent's assignments name the content
+// encodings that it registered, so that it downloads their objects
+// compressed where the sources will compress them.  A job with `direct_pull`
+// has its objects fetched from the agents on their sources, rather than from
+// the sources' web servers, wherever the source's agent has registered that
+// it serves them.
+//
+// An agent that has never registered is sent whatever it would have been
+// before registration, so that during a rolling upgrade the agents that have
+// yet to be upgraded carry on as they did.  The same goes for an agent whose
+// registration has lapsed, because it has missed MISSED_HEARTBEATS of its
+// heartbeats in a row: it may have been rolled back to a version that does not
+// register.  Registrations are only held in memory, so a manager that is
+// restarted knows of each agent again from its next heartbeat.
+
+use super::StorageId;
+use rebalancer::common::{ContentEncoding, TaskAction};
+use rebalancer::registration::AgentRegistration;
+use rebalancer::util::now_ms;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+// A registration lapses once this many heartbeats have gone by without the
+// agent registering again.
+static MISSED_HEARTBEATS: i64 = 3;
+
+/// An agent's registration, as reported by GET /agents.  Times are in ms
+/// since the epoch.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RegisteredAgent {
+    #[serde(flatten)]
+    pub registration: AgentRegistration,
+
+    /// When this run of the agent (i.e. its boot id) first registered.
+    pub registered: i64,
+    pub last_heartbeat: i64,
+
+    /// The agent has missed too many heartbeats for the registration to be
+    /// held to.
+    pub lapsed: bool,
+}
+
+impl RegisteredAgent {
+    fn lapsed_at(&self, now: i64) -> bool {
+        let heartbeat_ms = self.registration.heartbeat_secs as i64 * 1000;
+        now - self.last_heartbeat > heartbeat_ms * MISSED_HEARTBEATS
+    }
+}
+
+#[derive(Default)]
+pub struct AgentRegistry {
+    agents: Mutex<HashMap<StorageId, RegisteredAgent>>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> AgentRegistry {
+        AgentRegistry::default()
+    }
+
+    /// Record `registration` as received at `now`.  Returns the agent's
+    /// registration, and whether this run of the agent is new to the
+    /// manager.
+    pub fn register_at(
+        &self,
+        registration: AgentRegistration,
+        now: i64,
+    ) -> (RegisteredAgent, bool) {
+        let mut agents = self.agents.lock().expect("agent registry lock");
+        let storage_id = registration.storage_id.clone();
+
+        let registered = match agents.get(&storage_id) {
+            Some(r) if r.registration.boot_id == registration.boot_id => {
+                Some(r.registered)
+            }
+            _ => None,
+        };
+
+        let agent = RegisteredAgent {
+            registration,
+            registered: registered.unwrap_or(now),
+            last_heartbeat: now,
+            lapsed: false,
+        };
+        agents.insert(storage_id, agent.clone());
+
+        (agent, registered.is_none())
+    }
+
+    pub fn register(
+        &self,
+        registration: AgentRegistration,
+    ) -> (RegisteredAgent, bool) {
+        self.register_at(registration, now_ms())
+    }
+
+    /// The registration of `agent` as of `now`, lapsed or not.
+    pub fn get_at(&self, agent: &str, now: i64) -> Option<RegisteredAgent> {
+        let agents = self.agents.lock().expect("agent registry lock");
+
+        agents.get(agent).map(|r| RegisteredAgent {
+            lapsed: r.lapsed_at(now),
+            ..r.clone()
+        })
+    }
+
+    // The registration that `agent` is held to at `now`, if any.
+    fn current_at(&self, agent: &str, now: i64) -> Option<RegisteredAgent> {
+        self.get_at(agent, now).filter(|r| !r.lapsed)
+    }
+
+    /// Returns true unless `agent` has registered, and said that it does not
+    /// support `action`.
+    pub fn supports_at(
+        &self,
+        agent: &str,
+        action: TaskAction,
+        now: i64,
+    ) -> bool {
+        self.current_at(agent, now)
+            .map_or(true, |r| r.registration.capabilities.supports(action))
+    }
+
+    pub fn supports(&self, agent: &str, action: TaskAction) -> bool {
+        self.supports_at(agent, action, now_ms())
+    }
+
+    /// `max_tasks`, or the most tasks that `agent` takes in an assignment if
+    /// that is fewer.
+    pub fn max_tasks_at(
+        &self,
+        agent: &str,
+        max_tasks: usize,
+        now: i64,
+    ) -> usize {
+        self.current_at(agent, now)
+            .and_then(|r| r.registration.capabilities.max_tasks_per_assignment)
+            .map_or(max_tasks, |limit| max_tasks.min(limit.max(1)))
+    }
+
+    pub fn max_tasks(&self, agent: &str, max_tasks: usize) -> usize {
+        self.max_tasks_at(agent, max_tasks, now_ms())
+    }
+
+    /// The content encodings that `agent` can download objects in.  None
+    /// unless it has registered them.
+    pub fn encodings_at(&self, agent: &str, now: i64) -> Vec<ContentEncoding> {
+        self.current_at(agent, now)
+            .map(|r| r.registration.capabilities.encodings)
+            .unwrap_or_default()
+    }
+
+    pub fn encodings(&self, agent: &str) -> Vec<ContentEncoding> {
+        self.encodings_at(agent, now_ms())
+    }
+
+    /// Returns true only if `agent` has registered that it serves the
+    /// objects on its storage node.
+    pub fn serves_objects_at(&self, agent: &str, now: i64) -> bool {
+        self.current_at(agent, now)
+            .map_or(false, |r| r.registration.capabilities.serves_objects)
+    }
+
+    pub fn serves_objects(&self, agent: &str) -> bool {
+        self.serves_objects_at(agent, now_ms())
+    }
+
+    /// Every agent that has registered, by storage id.
+    pub fn list(&self) -> Vec<RegisteredAgent> {
+        let now = now_ms();
+        let mut list: Vec<RegisteredAgent> = self
+            .agents
+            .lock()
+            .expect("agent registry lock")
+            .values()
+            .map(|r| RegisteredAgent {
+                lapsed: r.lapsed_at(now),
+                ..r.clone()
+            })
+            .collect();
+
+        list.sort_by(|a, b| {
+            a.registration.storage_id.cmp(&b.registration.storage_id)
+        });
+        list
+    }
+}
+
+lazy_static! {
+    static ref SHARED: Arc<AgentRegistry> = Arc::new(AgentRegistry::new());
+}
+
+/// The registry shared by every job running in this manager.
+pub fn shared() -> Arc<AgentRegistry> {
+    Arc::clone(&SHARED)
+}
+
+/// Returns true unless `agent` has said that it does not support `action`.
+pub fn supports(agent: &str, action: TaskAction) -> bool {
+    SHARED.supports(agent, action)
+}
+
+/// `max_tasks`, capped by what `agent` takes in an assignment.
+pub fn max_tasks(agent: &str, max_tasks: usize) -> usize {
+    SHARED.max_tasks(agent, max_tasks)
+}
+
+/// The content encodings that `agent` has registered.
+pub fn encodings(agent: &str) -> Vec<ContentEncoding> {
+    SHARED.encodings(agent)
+}
+
+/// Returns true only if `agent` has registered that it serves its objects.
+pub fn serves_objects(agent: &str) -> bool {
+    SHARED.serves_objects(agent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rebalancer::registration::AgentCapabilities;
+
+    fn registration(
+        boot_id: &str,
+        caps: AgentCapabilities,
+    ) -> AgentRegistration {
+        AgentRegistration {
+            storage_id: String::from("1.stor.domain"),
+            version: String::from("0.1.0 (no-STAMP)"),
+            boot_id: boot_id.to_string(),
+            heartbeat_secs: 60,
+            capabilities: caps,
+        }
+    }
+
+    #[test]
+    fn registry_capabilities() {
+        let registry = AgentRegistry::new();
+        let agent = "1.stor.domain";
+        let start: i64 = 1_600_000_000_000;
+
+        // An agent that has not registered is sent anything.
+        assert!(registry.supports_at(agent, TaskAction::Delete, start));
+        assert_eq!(registry.max_tasks_at(agent, 200, start), 200);
+
+        let copy_only = AgentCapabilities {
+            actions: vec![TaskAction::Copy],
+            max_tasks_per_assignment: Some(50),
+            encodings: vec![],
+            serves_objects: false,
+        };
+        let (reg, new) =
+            registry.register_at(registration("a", copy_only), start);
+        assert!(new);
+        assert_eq!(reg.registered, start);
+        assert!(registry.supports_at(agent, TaskAction::Copy, start));
+        assert!(!registry.supports_at(agent, TaskAction::Delete, start));
+        assert_eq!(registry.max_tasks_at(agent, 200, start), 50);
+        assert_eq!(registry.max_tasks_at(agent, 10, start), 10);
+        assert!(registry.encodings_at(agent, start).is_empty());
+        assert!(!registry.serves_objects_at(agent, start));
+
+        // A heartbeat from the same run keeps when it first registered.
+        let beat = start + 60_000;
+        let caps = AgentCapabilities::new(None);
+        let (reg, new) =
+            registry.register_at(registration("a", caps.clone()), beat);
+        assert!(!new);
+        assert_eq!(reg.registered, start);
+        assert!(registry.supports_at(agent, TaskAction::Delete, beat));
+        assert_eq!(registry.max_tasks_at(agent, 200, beat), 200);
+        assert_eq!(
+            registry.encodings_at(agent, beat),
+            vec![ContentEncoding::Zstd, ContentEncoding::Gzip]
+        );
+        assert!(registry.serves_objects_at(agent, beat));
+
+        // A restarted agent is new again.
+        let (reg, new) = registry.register_at(registration("b", caps), beat);
+        assert!(new);
+        assert_eq!(reg.registered, beat);
+
+        // Once it misses three heartbeats, its registration lapses.
+        let late = beat + 3 * 60_000 + 1;
+        assert!(registry.get_at(agent, late).expect("registered").lapsed);
+        assert!(!registry.get_at(agent, late - 1).expect("registered").lapsed);
+        assert!(registry.encodings_at(agent, late).is_empty());
+        assert!(!registry.serves_objects_at(agent, late));
+    }
+}
@@ -0,0 +1,319 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Retention of finished jobs.
+//
+// Every job has a database of its own, named after the job's UUID, holding a
+// row for each object that the job processed.  Left alone, these accumulate
+// for as long as the manager is in service.  Once a finished job was created
+// more than `retention.job_retention_days` ago, it is archived:
+//
+//  * Its database is dumped with pg_dump, in pg_dump's compressed custom
+//    format, to <archive_dir>/<job uuid>.dump.  It can be loaded back with
+//    pg_restore if it is ever needed again.
+//  * Its database is dropped.
+//  * Its row is removed from the jobs table.
+//
+// An archived job no longer appears in the job list, and can no longer be
+// retried.  Jobs created before the manager recorded creation times have a
+// creation time of 0; their age is unknown, so they are only ever archived on
+// request (see archive_job()).
+
+use super::jobs::dsl::{id as job_id_col, jobs as jobs_db};
+use super::status::{self, JobListFilter};
+use super::{lease, JobDbEntry, JobState, REBALANCER_DB};
+use crate::config::{Config, ConfigRetention};
+use crate::pg_db;
+use crate::shutdown;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+use rebalancer::util::now_ms;
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use diesel::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+static PG_DUMP: &str = "/opt/postgresql/12.4/bin/pg_dump";
+
+// How often the retention thread looks for expired jobs.
+static RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+static MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug)]
+pub enum RetentionError {
+    NotFound,
+    NotFinished(JobState),
+    Archive(Error),
+}
+
+impl fmt::Display for RetentionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetentionError::NotFound => write!(f, "job not found"),
+            RetentionError::NotFinished(state) => {
+                write!(f, "job has not finished (state: {})", state)
+            }
+            RetentionError::Archive(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for RetentionError {
+    fn from(error: Error) -> Self {
+        RetentionError::Archive(error)
+    }
+}
+
+impl From<diesel::result::Error> for RetentionError {
+    fn from(error: diesel::result::Error) -> Self {
+        RetentionError::Archive(Error::from(error))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchivedJob {
+    pub id: String,
+
+    // The file the job's database was dumped to, or None if the job never
+    // had a database (e.g. it failed during setup).
+    pub archive: Option<String>,
+}
+
+/// Returns true if a job in this state will not run again.  A job that is
+/// awaiting confirmation has not finished until it is confirmed.  A paused
+/// job is not resumed, only retried as a new job.
+pub fn is_finished(state: &JobState) -> bool {
+    match state {
+        JobState::Complete
+        | JobState::Failed
+        | JobState::Stopped
+        | JobState::Resumed
+        | JobState::Paused => true,
+        _ => false,
+    }
+}
+
+// Dump a database to <archive_dir>/<db_name>.dump, returning the path of the
+// dump.  The dump is written under a temporary name first so that a partial
+// dump is never mistaken for a complete one.
+fn dump_database(db_name: &str, archive_dir: &str) -> Result<String, Error> {
+    fs::create_dir_all(archive_dir)?;
+
+    let path = Path::new(archive_dir).join(format!("{}.dump", db_name));
+    let partial = path.with_extension("dump.partial");
+
+    let output = Command::new(PG_DUMP)
+        .arg("--format=custom")
+        .arg("--compress=9")
+        .arg(format!("--file={}", partial.display()))
+        .arg(format!("--dbname={}", pg_db::db_url(db_name)))
+        .output()?;
+
+    if !output.status.success() {
+        fs::remove_file(&partial).unwrap_or(());
+        return Err(InternalError::new(
+            Some(InternalErrorCode::JobArchive),
+            format!(
+                "pg_dump of {} failed: {}",
+                db_name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )
+        .into());
+    }
+
+    fs::rename(&partial, &path)?;
+
+    Ok(path.display().to_string())
+}
+
+/// Archive a finished job's database to `archive_dir`, drop the database, and
+/// remove the job from the jobs table.
+pub fn archive_job(
+    uuid: &Uuid,
+    archive_dir: &str,
+) -> Result<ArchivedJob, RetentionError> {
+    let job_id = uuid.to_string();
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    let entry: JobDbEntry = jobs_db
+        .filter(job_id_col.eq(&job_id))
+        .first(&conn)
+        .optional()?
+        .ok_or(RetentionError::NotFound)?;
+
+    if !is_finished(&entry.state) {
+        return Err(RetentionError::NotFinished(entry.state));
+    }
+
+    let archive = if pg_db::list_databases()?.contains(&job_id) {
+        let path = dump_database(&job_id, archive_dir)?;
+        pg_db::drop_db(&job_id)?;
+        Some(path)
+    } else {
+        None
+    };
+
+    diesel::delete(jobs_db.filter(job_id_col.eq(&job_id))).execute(&conn)?;
+
+    Ok(ArchivedJob {
+        id: job_id,
+        archive,
+    })
+}
+
+/// Archive every finished job that has outlived the retention period.  A job
+/// that cannot be archived is logged and left for the next pass.
+pub fn archive_expired_jobs(
+    retention: &ConfigRetention,
+) -> Result<Vec<ArchivedJob>, Error> {
+    if retention.job_retention_days == 0 {
+        return Ok(vec![]);
+    }
+
+    let retention_ms =
+        (retention.job_retention_days as i64).saturating_mul(MS_PER_DAY);
+    let filter = JobListFilter {
+        created_before: Some(now_ms().saturating_sub(retention_ms)),
+        ..Default::default()
+    };
+
+    let job_list = status::list_jobs(&filter).map_err(|e| {
+        InternalError::new(
+            Some(InternalErrorCode::DbQuery),
+            format!("Could not list jobs: {:?}", e),
+        )
+    })?;
+
+    let mut archived = vec![];
+
+    for entry in job_list
+        .into_iter()
+        .filter(|j| j.created > 0 && is_finished(&j.state))
+    {
+        let uuid = match Uuid::parse_str(&entry.id) {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                error!("Invalid job id {}: {}", entry.id, e);
+                continue;
+            }
+        };
+
+        match archive_job(&uuid, &retention.archive_dir) {
+            Ok(job) => {
+                info!("Archived job {} to {:?}", job.id, job.archive);
+                archived.push(job);
+            }
+            Err(e) => error!("Could not archive job {}: {}", entry.id, e),
+        }
+    }
+
+    Ok(archived)
+}
+
+/// Start a thread that periodically archives expired jobs.  The retention
+/// configuration is re-read on every pass, so changes to it take effect
+/// without a restart.  With coordination, only the manager that is the
+/// coordinator archives jobs.
+pub fn start_retention_thread(
+    config: Arc<Mutex<Config>>,
+) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("job retention"))
+        .spawn(move || loop {
+            if shutdown::requested() {
+                return;
+            }
+
+            if !lease::is_coordinator() {
+                thread::sleep(RETENTION_CHECK_INTERVAL);
+                continue;
+            }
+
+            let retention =
+                config.lock().expect("config lock").retention.clone();
+
+            match archive_expired_jobs(&retention) {
+                Ok(archived) if !archived.is_empty() => {
+                    info!("Archived {} expired job(s)", archived.len())
+                }
+                Ok(_) => (),
+                Err(e) => error!("Error archiving expired jobs: {}", e),
+            }
+
+            thread::sleep(RETENTION_CHECK_INTERVAL);
+        })
+        .expect("start job retention thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{self, JobActionDbEntry};
+    use rebalancer::util;
+
+    #[test]
+    fn archive_job_test() {
+        let _guard = util::init_global_logger(None);
+        jobs::create_job_database().expect("create job database");
+        let conn =
+            pg_db::connect_or_create_db(REBALANCER_DB).expect("rebalancer db");
+
+        let running = Uuid::new_v4();
+        let finished = Uuid::new_v4();
+
+        for (uuid, state) in
+            &[(running, JobState::Running), (finished, JobState::Complete)]
+        {
+            diesel::insert_into(jobs_db)
+                .values(&JobDbEntry {
+                    id: uuid.to_string(),
+                    action: JobActionDbEntry::Evacuate,
+                    state: state.clone(),
+                    created: 1,
+                })
+                .execute(&conn)
+                .expect("insert job");
+        }
+
+        let archive_dir = std::env::temp_dir().join("rebalancer-archive-test");
+        let archive_dir = archive_dir.to_str().expect("archive dir");
+
+        match archive_job(&running, archive_dir) {
+            Err(RetentionError::NotFinished(JobState::Running)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match archive_job(&Uuid::new_v4(), archive_dir) {
+            Err(RetentionError::NotFound) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // This job never had a database of its own, so there is nothing to
+        // dump, but it is still removed from the jobs table.
+        let archived =
+            archive_job(&finished, archive_dir).expect("archive job");
+        assert!(archived.archive.is_none());
+
+        let remaining: Vec<JobDbEntry> = jobs_db
+            .filter(job_id_col.eq(finished.to_string()))
+            .load(&conn)
+            .expect("load jobs");
+        assert!(remaining.is_empty());
+    }
+}
@@ -0,0 +1,784 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Undoing the metadata changes of a finished job.
+//
+// Every change that an evacuate, create-copy or remove-copy job makes to an
+// object's metadata is recorded in the job's metadata_audit table, with the
+// object's sharks before and after the change.  If a job turns out to have
+// done harm, for instance by moving objects to a destination that has since
+// been found to be bad, a rollback job puts the sharks that each object had
+// before the job back.  The objects that are rolled back can be narrowed down
+// to those that the job put on a particular destination shark, or to a list
+// of object ids.
+//
+// An object is only rolled back if its metadata is still what the job left
+// it as, so that later changes (including those of later jobs) are never
+// undone, and only once every copy that the job removed from the object's
+// metadata has been checked to still be in place, in the way that a verify
+// job checks an object.  The copies that the job added are left where they
+// are: putting the metadata back only leaves them unreferenced.
+//
+// Each object ends up as one of:
+//
+//  * reverted: the object has the sharks that it had before the job.
+//  * changed: the object has been changed (or removed, or replaced) since the
+//    job, and was left alone.
+//  * unverified: a copy that the job removed could not be found as it should
+//    be, so the object was left alone.
+//  * failed: the object's metadata could not be read or written.
+//
+// The count of each is reported with the rollback job's status, and in the
+// `rollback_object_count` metric, and each object can be found in the job's
+// rollbackobjects table.
+
+use crate::config::Config;
+use crate::jobs::evacuate::{self, MetadataAuditEntry};
+use crate::jobs::status;
+use crate::jobs::verify::{self, VerifyObject, VerifyObjectStatus};
+use crate::jobs::watchdog::{self, spawn_supervised};
+use crate::jobs::JobState;
+use crate::metrics::metrics_rollback_object_inc;
+use crate::moray_client;
+use crate::pg_db;
+use crate::shutdown;
+use rebalancer::common;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam_channel as crossbeam;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use libmanta::moray::MantaObjectShark;
+use moray::client::MorayClient;
+use reqwest::Client;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+use uuid::Uuid;
+
+table! {
+    use diesel::sql_types::{Integer, Nullable, Text};
+    rollbackobjects (id) {
+        id -> Text,
+        shard -> Integer,
+        key -> Text,
+        status -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Integer, Nullable, Text};
+    rollback_config {
+        id -> Integer,
+        job_id -> Text,
+        dest_shark -> Nullable<Text>,
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Display,
+    EnumIter,
+    EnumString,
+    EnumVariantNames,
+    Eq,
+    Hash,
+    PartialEq,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum RollbackObjectStatus {
+    Reverted,
+    Changed,
+    Unverified,
+    Failed,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[table_name = "rollbackobjects"]
+pub struct RollbackObjectEntry {
+    pub id: String,
+    pub shard: i32,
+    pub key: String,
+
+    // One of RollbackObjectStatus.
+    pub status: String,
+
+    // Why the object was not reverted, if it was not.
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[table_name = "rollback_config"]
+pub struct RollbackDbConfig {
+    pub id: i32,
+    pub job_id: String,
+    pub dest_shark: Option<String>,
+}
+
+// What the job being rolled back did to one object: the sharks that the
+// object had before the job first changed it, and those that the job last
+// left it with.
+#[derive(Debug)]
+struct Revert {
+    object_id: String,
+    key: String,
+    shard: Option<u32>,
+    original_sharks: Value,
+    job_sharks: Value,
+}
+
+impl Revert {
+    fn new(entry: MetadataAuditEntry, shard: Option<u32>) -> Revert {
+        Revert {
+            object_id: entry.object_id,
+            key: entry.key,
+            shard,
+            original_sharks: entry.old_sharks,
+            job_sharks: entry.new_sharks,
+        }
+    }
+
+    // Whether the job left the object with a copy on `storage_id`.
+    fn job_added(&self, storage_id: &str) -> bool {
+        storage_ids(&self.job_sharks).contains(storage_id)
+    }
+}
+
+fn storage_ids(sharks: &Value) -> HashSet<String> {
+    sharks
+        .as_array()
+        .map(|sharks| {
+            sharks
+                .iter()
+                .filter_map(|s| s.get("manta_storage_id"))
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub struct RollbackJob {
+    // The job whose changes are rolled back, and its shark.
+    pub job_id: String,
+    pub shark: MantaObjectShark,
+
+    // Only roll back the objects that the job put on this shark.
+    dest_shark: Option<String>,
+
+    // Only roll back these objects.  Every object is rolled back if this is
+    // empty.
+    object_ids: Vec<String>,
+
+    config: Config,
+    db_name: String,
+    conn: Mutex<PgConnection>,
+    client: Client,
+    max_objects: Option<u32>,
+    counts: Mutex<HashMap<RollbackObjectStatus, u64>>,
+}
+
+// A job can only be rolled back once it has stopped changing metadata.
+fn check_rollback_state(job_id: &str, state: &JobState) -> Result<(), Error> {
+    match state {
+        JobState::Init
+        | JobState::Setup
+        | JobState::Queued
+        | JobState::Running => Err(InternalError::new(
+            Some(InternalErrorCode::JobBuilderError),
+            format!("Job {} is {} and can not be rolled back", job_id, state),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+impl RollbackJob {
+    pub fn new(
+        job_id: String,
+        dest_shark: Option<String>,
+        object_ids: Vec<String>,
+        config: &Config,
+        db_name: &str,
+        max_objects: Option<u32>,
+    ) -> Result<Self, Error> {
+        let uuid = Uuid::from_str(&job_id).map_err(Error::from)?;
+        let job_status = status::get_job(uuid).map_err(|e| {
+            Error::from(InternalError::new(
+                Some(InternalErrorCode::DbQuery),
+                format!("Could not find job with UUID {}: {:?}", job_id, e),
+            ))
+        })?;
+
+        check_rollback_state(&job_id, &job_status.state)?;
+
+        // Only jobs that change metadata keep an audit of their changes.
+        let shark = match job_status.config {
+            status::JobStatusConfig::Evacuate(conf) => conf.from_shark,
+            status::JobStatusConfig::CreateCopy(conf) => conf.shark,
+            status::JobStatusConfig::RemoveCopy(conf) => conf.shark,
+            _ => {
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::JobBuilderError),
+                    format!(
+                        "Job {} made no metadata changes to roll back",
+                        job_id
+                    ),
+                )
+                .into());
+            }
+        };
+
+        let conn = pg_db::create_and_connect_db(db_name)?;
+
+        // The shark of the job being rolled back is kept in the same config
+        // table as an evacuate job's, so that the rollback job's status can
+        // report it in the same way.
+        evacuate::create_config_table(&conn)?;
+        evacuate::update_evacuate_config_impl(&conn, &shark)?;
+        create_rollback_config_table(&conn)?;
+        create_rollbackobjects_table(&conn)?;
+
+        diesel::insert_into(rollback_config::table)
+            .values(&RollbackDbConfig {
+                id: 1,
+                job_id: job_id.clone(),
+                dest_shark: dest_shark.clone(),
+            })
+            .execute(&conn)
+            .map_err(Error::from)?;
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.verification.timeout_secs))
+            .build()?;
+
+        Ok(RollbackJob {
+            job_id,
+            shark,
+            dest_shark,
+            object_ids,
+            config: config.to_owned(),
+            db_name: db_name.to_string(),
+            conn: Mutex::new(conn),
+            client,
+            max_objects,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn run(self) -> Result<(), Error> {
+        let threads = self.config.options.max_metadata_update_threads.max(1);
+        let job = Arc::new(self);
+        let (revert_tx, revert_rx) = crossbeam::bounded(threads * 10);
+
+        let reader_job = Arc::clone(&job);
+        let reader = spawn_supervised(
+            &job.db_name,
+            "rollback_reader",
+            watchdog::any_time,
+            move || reader_job.read_audit(revert_tx),
+        )?;
+
+        let mut workers = vec![];
+        for i in 0..threads {
+            let worker_job = Arc::clone(&job);
+            let worker_rx = revert_rx.clone();
+            let drained = watchdog::drained(&worker_rx);
+            workers.push(spawn_supervised(
+                &job.db_name,
+                &format!("rollback_worker_{}", i),
+                move || drained() || shutdown::requested(),
+                move || worker_job.revert_objects(worker_rx),
+            )?);
+        }
+        drop(revert_rx);
+
+        let mut ret = Ok(());
+
+        reader
+            .join()
+            .expect("Rollback Reader Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining rollback reader: {}", e);
+                evacuate::set_run_error(&mut ret, e);
+            });
+
+        for worker in workers {
+            worker
+                .join()
+                .expect("Rollback Worker Thread")
+                .unwrap_or_else(|e| {
+                    error!("Error joining rollback worker: {}", e);
+                    evacuate::set_run_error(&mut ret, e);
+                });
+        }
+
+        for status in RollbackObjectStatus::iter() {
+            let count = job
+                .counts
+                .lock()
+                .expect("rollback counts lock")
+                .get(&status)
+                .copied()
+                .unwrap_or(0);
+            info!(
+                "Rollback of job {} found {} objects {}",
+                job.job_id, count, status
+            );
+        }
+
+        if ret.is_ok() && shutdown::requested() {
+            ret = Err(InternalError::new(
+                Some(InternalErrorCode::JobInterrupted),
+                "Job interrupted by manager shutdown",
+            )
+            .into());
+        }
+
+        ret
+    }
+
+    // Whether `revert` is one of the objects that are to be rolled back.
+    fn wanted(&self, revert: &Revert) -> bool {
+        match &self.dest_shark {
+            Some(dest) => revert.job_added(dest),
+            None => true,
+        }
+    }
+
+    // Walk the audit table of the job being rolled back, a chunk at a time,
+    // and pass each object that is to be rolled back on to the workers.  An
+    // object may have been changed more than once, by attempts that failed
+    // part way, so the entries are read in order of object and grouped.
+    fn read_audit(
+        &self,
+        revert_tx: crossbeam::Sender<Revert>,
+    ) -> Result<(), Error> {
+        use evacuate::metadata_audit::dsl as audit;
+
+        let source = pg_db::connect_db(&self.job_id)?;
+        let chunk_size = self.config.options.md_read_chunk_size.max(1) as i64;
+        let mut offset: i64 = 0;
+        let mut pending: Option<Revert> = None;
+        let mut found: u32 = 0;
+
+        let mut send = |revert: Revert| -> bool {
+            if !self.wanted(&revert) {
+                return true;
+            }
+
+            if revert_tx.send(revert).is_err() {
+                warn!("Rollback workers exited prematurely");
+                return false;
+            }
+
+            found += 1;
+            if let Some(max) = self.max_objects {
+                if found >= max {
+                    info!("Rollback job reached max_objects ({})", max);
+                    return false;
+                }
+            }
+            true
+        };
+
+        loop {
+            if shutdown::requested() {
+                info!("Job is stopping, stopping rollback");
+                return Ok(());
+            }
+
+            let mut query = audit::metadata_audit
+                .order((audit::object_id, audit::id))
+                .limit(chunk_size)
+                .offset(offset)
+                .into_boxed();
+            if !self.object_ids.is_empty() {
+                query = query.filter(audit::object_id.eq_any(&self.object_ids));
+            }
+
+            let entries: Vec<MetadataAuditEntry> =
+                query.load(&source).map_err(Error::from)?;
+            if entries.is_empty() {
+                break;
+            }
+            offset += entries.len() as i64;
+
+            let shards = object_shards(&source, &entries)?;
+
+            for entry in entries {
+                if let Some(revert) = pending.as_mut() {
+                    if revert.object_id == entry.object_id {
+                        revert.job_sharks = entry.new_sharks;
+                        continue;
+                    }
+                }
+
+                let shard = shards.get(&entry.object_id).copied();
+                if let Some(revert) = pending.replace(Revert::new(entry, shard))
+                {
+                    if !send(revert) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        if let Some(revert) = pending {
+            send(revert);
+        }
+
+        info!("Rollback reader thread exiting");
+        Ok(())
+    }
+
+    fn revert_objects(
+        &self,
+        revert_rx: crossbeam::Receiver<Revert>,
+    ) -> Result<(), Error> {
+        let mut clients: HashMap<u32, MorayClient> = HashMap::new();
+
+        while let Ok(revert) = revert_rx.recv() {
+            if shutdown::requested() {
+                break;
+            }
+
+            let (status, detail) = self.revert_object(&mut clients, &revert);
+            self.record(revert, status, detail)?;
+        }
+
+        Ok(())
+    }
+
+    fn revert_object(
+        &self,
+        clients: &mut HashMap<u32, MorayClient>,
+        revert: &Revert,
+    ) -> (RollbackObjectStatus, Option<String>) {
+        let shard = match revert.shard {
+            Some(shard) => shard,
+            None => {
+                return (
+                    RollbackObjectStatus::Failed,
+                    Some(String::from("the job has no record of its shard")),
+                )
+            }
+        };
+
+        if !clients.contains_key(&shard) {
+            match moray_client::create_client(shard, &self.config.domain_name) {
+                Ok(client) => {
+                    clients.insert(shard, client);
+                }
+                Err(e) => {
+                    return (
+                        RollbackObjectStatus::Failed,
+                        Some(format!(
+                            "could not get moray client for shard {}: {}",
+                            shard, e
+                        )),
+                    )
+                }
+            }
+        }
+        let mclient = clients.get_mut(&shard).expect("moray client");
+
+        let (mut fresh, etag) =
+            match moray_client::get_object(mclient, &revert.key) {
+                Ok(object) => object,
+                Err(e) => {
+                    return (
+                        RollbackObjectStatus::Failed,
+                        Some(format!("reading metadata: {}", e)),
+                    )
+                }
+            };
+
+        // The object may have been rolled back by an earlier rollback job, or
+        // by this one before it was interrupted.
+        if already_reverted(revert, &fresh) {
+            return (RollbackObjectStatus::Reverted, None);
+        }
+
+        if let Err(detail) = check_unchanged(revert, &fresh) {
+            return (RollbackObjectStatus::Changed, Some(detail));
+        }
+
+        if let Err(detail) = self.check_original_copies(shard, revert, &fresh) {
+            return (RollbackObjectStatus::Unverified, Some(detail));
+        }
+
+        fresh["sharks"] = revert.original_sharks.clone();
+
+        match moray_client::put_object(mclient, &fresh, &etag) {
+            Ok(()) => (RollbackObjectStatus::Reverted, None),
+            Err(e) if moray_client::is_etag_conflict(&e) => (
+                RollbackObjectStatus::Changed,
+                Some(String::from("the object changed while it was checked")),
+            ),
+            Err(e) => (
+                RollbackObjectStatus::Failed,
+                Some(format!("writing metadata: {}", e)),
+            ),
+        }
+    }
+
+    // Check each copy that the job removed from the object's metadata, on
+    // the shark that the job removed it from.
+    fn check_original_copies(
+        &self,
+        shard: u32,
+        revert: &Revert,
+        fresh: &Value,
+    ) -> Result<(), String> {
+        let object = VerifyObject::from_record(shard, fresh)?;
+        let current = storage_ids(&revert.job_sharks);
+
+        for storage_id in storage_ids(&revert.original_sharks)
+            .iter()
+            .filter(|s| !current.contains(*s))
+        {
+            let (status, detail) = verify::check_object(
+                &self.client,
+                self.config.verification.method,
+                storage_id,
+                &object,
+            );
+
+            if status != VerifyObjectStatus::Present {
+                return Err(format!(
+                    "copy on {} is {}: {}",
+                    storage_id,
+                    status,
+                    detail.unwrap_or_default()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        revert: Revert,
+        status: RollbackObjectStatus,
+        detail: Option<String>,
+    ) -> Result<(), Error> {
+        if status != RollbackObjectStatus::Reverted {
+            warn!(
+                "Object {} ({}) not rolled back, {}: {}",
+                revert.key,
+                revert.object_id,
+                status,
+                detail.as_ref().map(String::as_str).unwrap_or("")
+            );
+        }
+
+        *self
+            .counts
+            .lock()
+            .expect("rollback counts lock")
+            .entry(status)
+            .or_insert(0) += 1;
+        metrics_rollback_object_inc(&status.to_string());
+
+        let entry = RollbackObjectEntry {
+            id: revert.object_id,
+            shard: revert.shard.map(|s| s as i32).unwrap_or(-1),
+            key: revert.key,
+            status: status.to_string(),
+            detail,
+        };
+
+        let conn = self.conn.lock().expect("DB conn lock");
+        diesel::insert_into(rollbackobjects::table)
+            .values(&entry)
+            .on_conflict(rollbackobjects::id)
+            .do_nothing()
+            .execute(&*conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+fn already_reverted(revert: &Revert, fresh: &Value) -> bool {
+    let sharks = fresh.get("sharks").cloned().unwrap_or(Value::Null);
+    common::get_objectId_from_value(fresh)
+        .map(|id| id == revert.object_id)
+        .unwrap_or(false)
+        && storage_ids(&sharks) == storage_ids(&revert.original_sharks)
+}
+
+// Whether the object is still what the job left it as.
+fn check_unchanged(revert: &Revert, fresh: &Value) -> Result<(), String> {
+    let id = common::get_objectId_from_value(fresh)
+        .map_err(|e| format!("malformed object record: {}", e))?;
+    if id != revert.object_id {
+        return Err(format!("{} now refers to object {}", revert.key, id));
+    }
+
+    let sharks = fresh.get("sharks").cloned().unwrap_or(Value::Null);
+    if storage_ids(&sharks) != storage_ids(&revert.job_sharks) {
+        return Err(String::from("its sharks have changed since the job"));
+    }
+
+    Ok(())
+}
+
+// The shard of each of the objects of `entries`, from the record of the
+// objects of the job being rolled back.
+fn object_shards(
+    conn: &PgConnection,
+    entries: &[MetadataAuditEntry],
+) -> Result<HashMap<String, u32>, Error> {
+    use evacuate::evacuateobjects::dsl::{evacuateobjects, id, shard};
+
+    let ids: Vec<&str> = entries.iter().map(|e| e.object_id.as_str()).collect();
+    let shards: Vec<(String, i32)> = evacuateobjects
+        .select((id, shard))
+        .filter(id.eq_any(ids))
+        .load(conn)
+        .map_err(Error::from)?;
+
+    Ok(shards
+        .into_iter()
+        .map(|(object_id, object_shard)| (object_id, object_shard as u32))
+        .collect())
+}
+
+fn create_rollback_config_table(conn: &PgConnection) -> Result<usize, Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rollback_config(
+            id Integer PRIMARY KEY,
+            job_id TEXT NOT NULL,
+            dest_shark TEXT
+        );",
+    )
+    .map_err(Error::from)
+}
+
+fn create_rollbackobjects_table(conn: &PgConnection) -> Result<usize, Error> {
+    let status_strings = RollbackObjectStatus::variants();
+    let status_check = format!("'{}'", status_strings.join("', '"));
+
+    let create_query = format!(
+        "
+            CREATE TABLE IF NOT EXISTS rollbackobjects(
+                id TEXT PRIMARY KEY,
+                shard Integer NOT NULL,
+                key TEXT NOT NULL,
+                status TEXT CHECK(status IN ({})) NOT NULL,
+                detail TEXT
+            );
+        ",
+        status_check
+    );
+
+    conn.execute(&create_query).map_err(Error::from)
+}
+
+/// The configuration of the rollback job whose database `conn` is connected
+/// to.
+pub fn get_rollback_config(
+    conn: &PgConnection,
+) -> Result<RollbackDbConfig, Error> {
+    rollback_config::table
+        .first::<RollbackDbConfig>(conn)
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sharks(ids: &[&str]) -> Value {
+        Value::Array(
+            ids.iter()
+                .map(|id| json!({"datacenter": "dc", "manta_storage_id": id}))
+                .collect(),
+        )
+    }
+
+    fn revert() -> Revert {
+        Revert {
+            object_id: String::from("d5a3b5f4-3dfc-4e2e-9a53-2b7b1d0c7a1e"),
+            key: String::from("/owner/stor/object"),
+            shard: Some(1),
+            original_sharks: sharks(&["1.stor", "2.stor"]),
+            job_sharks: sharks(&["3.stor", "2.stor"]),
+        }
+    }
+
+    fn object(id: &str, object_sharks: &[&str]) -> Value {
+        json!({
+            "key": "/owner/stor/object",
+            "owner": "eb6fbdd1-8e4a-4d71-a7d5-25d4e1b5ada6",
+            "objectId": id,
+            "contentLength": 5,
+            "sharks": sharks(object_sharks),
+        })
+    }
+
+    #[test]
+    fn rollback_unchanged() {
+        let revert = revert();
+        let id = revert.object_id.clone();
+
+        // The order of the sharks does not matter.
+        assert!(
+            check_unchanged(&revert, &object(&id, &["2.stor", "3.stor"]))
+                .is_ok()
+        );
+
+        // A change to its sharks, or another object at its key, does.
+        assert!(check_unchanged(&revert, &object(&id, &["3.stor"])).is_err());
+        assert!(!already_reverted(
+            &revert,
+            &object(&id, &["3.stor", "2.stor"])
+        ));
+        assert!(already_reverted(
+            &revert,
+            &object(&id, &["1.stor", "2.stor"])
+        ));
+        assert!(check_unchanged(
+            &revert,
+            &object(
+                "0f2b6c22-8d1e-4a55-b3b0-4b8a3d1f9c77",
+                &["2.stor", "3.stor"]
+            )
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rollback_dest_filter() {
+        let revert = revert();
+        assert!(revert.job_added("3.stor"));
+        assert!(!revert.job_added("1.stor"));
+    }
+
+    #[test]
+    fn rollback_state() {
+        assert!(check_rollback_state("job", &JobState::Running).is_err());
+        assert!(check_rollback_state("job", &JobState::Queued).is_err());
+        assert!(check_rollback_state("job", &JobState::Complete).is_ok());
+        assert!(check_rollback_state("job", &JobState::Failed).is_ok());
+        assert!(check_rollback_state("job", &JobState::AwaitingConfirmation)
+            .is_ok());
+    }
+}
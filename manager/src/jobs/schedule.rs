@@ -0,0 +1,274 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Running jobs only within a daily window.
+//
+// Operators keep evacuations off peak traffic hours by starting them at night
+// and stopping them in the morning.  An evacuate, create-copy or remove-copy
+// job can instead be given a `schedule`: a start and end time of day, and the
+// offset from UTC that they are at.  The job is started as any other, but it
+// only posts assignments to agents while the window is open.  Outside of it,
+// the post of the next assignment waits until the window opens again, which
+// holds up the job behind it: once its queues are full, the job stops
+// scanning for more objects too.  Assignments that had already been posted
+// when the window closed are seen through, and their metadata updated, as
+// usual.
+//
+// A job that is waiting for its window still counts towards
+// options.max_concurrent_jobs.  The offset is fixed, so a window given in
+// local time moves by an hour when daylight saving time starts or ends.
+//
+// The schedule is recorded in the schedule_config table of the job's
+// database, so that a retry or resumed job keeps to the same window, and the
+// status of a job that has yet to finish says whether its window is open and
+// when that next changes.
+
+use rebalancer::error::Error;
+pub use rebalancer_client::jobs::JobSchedule;
+pub use rebalancer_client::status::JobScheduleStatus;
+
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use diesel::prelude::*;
+
+table! {
+    use diesel::sql_types::{Integer, Nullable, Text};
+    schedule_config (id) {
+        id -> Integer,
+        window_start -> Text,
+        window_end -> Text,
+        utc_offset -> Nullable<Text>,
+    }
+}
+
+#[derive(Insertable, AsChangeset, Queryable)]
+#[table_name = "schedule_config"]
+struct ScheduleDbConfig {
+    id: i32,
+    window_start: String,
+    window_end: String,
+    utc_offset: Option<String>,
+}
+
+static SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+// The longest that a job waiting for its window goes without checking whether
+// it is stopping.
+static MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// A job's schedule, as times of day in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScheduleWindow {
+    start: i64,
+    end: i64,
+    // Seconds east of UTC.
+    offset: i64,
+}
+
+impl ScheduleWindow {
+    pub fn new(schedule: &JobSchedule) -> Result<ScheduleWindow, String> {
+        schedule.validate()?;
+        let (start, end, offset) = schedule.minutes()?;
+
+        Ok(ScheduleWindow {
+            start: i64::from(start) * 60,
+            end: i64::from(end) * 60,
+            offset: i64::from(offset) * 60,
+        })
+    }
+
+    // Seconds after local midnight at `epoch_secs`.
+    fn time_of_day(&self, epoch_secs: u64) -> i64 {
+        (epoch_secs as i64 + self.offset).rem_euclid(SECS_PER_DAY)
+    }
+
+    /// Returns true if the window is open at `epoch_secs`.
+    pub fn is_open(&self, epoch_secs: u64) -> bool {
+        let now = self.time_of_day(epoch_secs);
+
+        if self.start < self.end {
+            now >= self.start && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    /// The seconds from `epoch_secs` until the window next opens, if it is
+    /// closed, or closes, if it is open.
+    pub fn secs_until_change(&self, epoch_secs: u64) -> u64 {
+        let edge = if self.is_open(epoch_secs) {
+            self.end
+        } else {
+            self.start
+        };
+
+        (edge - self.time_of_day(epoch_secs)).rem_euclid(SECS_PER_DAY) as u64
+    }
+
+    pub fn status(&self, epoch_secs: u64) -> JobScheduleStatus {
+        let next_change = epoch_secs + self.secs_until_change(epoch_secs);
+
+        JobScheduleStatus {
+            open: self.is_open(epoch_secs),
+            next_change: next_change as i64 * 1000,
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Where `window` is now.
+pub fn current_status(window: &ScheduleWindow) -> JobScheduleStatus {
+    window.status(now_secs())
+}
+
+/// Wait until `window` is open, or until `stopping` returns true, and return
+/// the time spent waiting.
+pub fn wait_for_window<F>(
+    job_id: &str,
+    window: &ScheduleWindow,
+    stopping: F,
+) -> Duration
+where
+    F: Fn() -> bool,
+{
+    let start = Instant::now();
+    let mut waited = false;
+
+    loop {
+        let now = now_secs();
+        if window.is_open(now) || stopping() {
+            break;
+        }
+
+        if !waited {
+            info!(
+                "Job {} is outside of its schedule, holding its assignments \
+                 for {} seconds",
+                job_id,
+                window.secs_until_change(now)
+            );
+            waited = true;
+        }
+        thread::sleep(MAX_WAIT);
+    }
+
+    if waited {
+        info!("Job {} is within its schedule, posting assignments", job_id);
+    }
+
+    start.elapsed()
+}
+
+pub fn create_schedule_config_table(
+    conn: &PgConnection,
+) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE schedule_config(
+        id Integer PRIMARY KEY,
+        window_start TEXT NOT NULL,
+        window_end TEXT NOT NULL,
+        utc_offset TEXT
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE schedule_config") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// Record the job's schedule.  There is only a single entry.
+pub fn record_schedule(
+    conn: &PgConnection,
+    schedule: &JobSchedule,
+) -> Result<usize, Error> {
+    use self::schedule_config::dsl::{id, schedule_config as schedule_table};
+
+    let value = ScheduleDbConfig {
+        id: 1,
+        window_start: schedule.start.clone(),
+        window_end: schedule.end.clone(),
+        utc_offset: schedule.utc_offset.clone(),
+    };
+
+    diesel::insert_into(schedule_table)
+        .values(&value)
+        .on_conflict(id)
+        .do_update()
+        .set(&value)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+/// The job's schedule.  Jobs without one have no entry, and jobs that were
+/// run before schedules were recorded have no table for them.
+pub fn get_schedule(conn: &PgConnection) -> Option<JobSchedule> {
+    use self::schedule_config::dsl::schedule_config as schedule_table;
+
+    schedule_table
+        .first::<ScheduleDbConfig>(conn)
+        .map(|c| JobSchedule {
+            start: c.window_start,
+            end: c.window_end,
+            utc_offset: c.utc_offset,
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str, offset: Option<&str>) -> ScheduleWindow {
+        ScheduleWindow::new(&JobSchedule {
+            start: start.to_string(),
+            end: end.to_string(),
+            utc_offset: offset.map(String::from),
+        })
+        .expect("valid schedule")
+    }
+
+    #[test]
+    fn schedule_window() {
+        // 2020-06-01 00:00:00 UTC.
+        let midnight: u64 = 1_590_969_600;
+        let hour: u64 = 3600;
+
+        let night = window("00:00", "06:00", None);
+        assert!(night.is_open(midnight));
+        assert!(night.is_open(midnight + 6 * hour - 1));
+        assert!(!night.is_open(midnight + 6 * hour));
+        assert_eq!(night.secs_until_change(midnight), 6 * hour);
+        assert_eq!(night.secs_until_change(midnight + 12 * hour), 12 * hour);
+
+        // A window that runs past midnight.
+        let late = window("22:00", "02:00", None);
+        assert!(late.is_open(midnight + 23 * hour));
+        assert!(late.is_open(midnight + hour));
+        assert!(!late.is_open(midnight + 12 * hour));
+        assert_eq!(late.secs_until_change(midnight + 23 * hour), 3 * hour);
+
+        // 00:00 to 06:00 at UTC-07:00 is 07:00 to 13:00 UTC.
+        let local = window("00:00", "06:00", Some("-07:00"));
+        assert!(!local.is_open(midnight));
+        assert!(local.is_open(midnight + 7 * hour));
+        assert!(!local.is_open(midnight + 13 * hour));
+
+        let status = local.status(midnight);
+        assert!(!status.open);
+        assert_eq!(status.next_change, ((midnight + 7 * hour) * 1000) as i64);
+    }
+}
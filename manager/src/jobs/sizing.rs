@@ -0,0 +1,283 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Adaptive sizing of assignments.
+//
+// Every assignment sent to a destination shark is normally filled with up to
+// options.max_tasks_per_assignment tasks.  That suits an agent that keeps up,
+// but a slow or struggling agent holds on to a large assignment for a long
+// time (and, if it fails, a large number of objects fail with it), while a
+// fast agent spends much of its time waiting for the next one.
+//
+// If `assignment_sizing.adaptive` is set, a job instead keeps a size for each
+// destination shark, starting at max_tasks_per_assignment, and adjusts it as
+// the shark's assignments complete:
+//
+//  * If more than `max_error_ratio` of an assignment's tasks failed, or the
+//    assignment could not be posted at all, the size is halved.
+//  * If the agent took longer than `target_assignment_secs` to complete the
+//    assignment, the size is reduced by a quarter.
+//  * If the agent took less than half of `target_assignment_secs`, the size
+//    is increased by a quarter.
+//
+// The size is always kept between `min_tasks_per_assignment` and
+// `max_tasks_per_assignment`.  Each shark's size depends only on how its own
+// agent has done, so one misbehaving destination does not shrink the
+// assignments sent to any other.
+
+use super::{AssignmentId, StorageId};
+use crate::config::ConfigAssignmentSizing;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct SizingState {
+    // Destination shark -> the number of tasks to put in its assignments.
+    sizes: HashMap<StorageId, usize>,
+
+    // Assignment -> the shark it was posted to, how many tasks it had, and
+    // when it was posted.
+    posted: HashMap<AssignmentId, (StorageId, usize, Instant)>,
+}
+
+enum ResizeBy {
+    Half,
+    Shrink,
+    Grow,
+}
+
+pub struct AssignmentSizer {
+    job_id: String,
+    config: ConfigAssignmentSizing,
+    state: Mutex<SizingState>,
+}
+
+impl AssignmentSizer {
+    pub fn new(job_id: &str, config: &ConfigAssignmentSizing) -> Self {
+        let min = config.min_tasks_per_assignment.max(1);
+        let max = config.max_tasks_per_assignment.max(min);
+
+        AssignmentSizer {
+            job_id: job_id.to_string(),
+            config: ConfigAssignmentSizing {
+                min_tasks_per_assignment: min,
+                max_tasks_per_assignment: max,
+                ..*config
+            },
+            state: Mutex::new(SizingState::default()),
+        }
+    }
+
+    fn clamp(&self, tasks: usize) -> usize {
+        tasks
+            .max(self.config.min_tasks_per_assignment)
+            .min(self.config.max_tasks_per_assignment)
+    }
+
+    /// The number of tasks to put in the next assignment for `shark`.  Until
+    /// the shark's size has been adjusted (and always, if sizing is not
+    /// adaptive) this is the job's own `max_tasks_per_assignment`.
+    pub fn max_tasks(
+        &self,
+        shark: &str,
+        max_tasks_per_assignment: usize,
+    ) -> usize {
+        if !self.config.adaptive {
+            return max_tasks_per_assignment;
+        }
+
+        let state = self.state.lock().expect("sizing lock");
+        state
+            .sizes
+            .get(shark)
+            .copied()
+            .unwrap_or_else(|| self.clamp(max_tasks_per_assignment))
+    }
+
+    // A shark whose size has not been adjusted yet is adjusted from the size
+    // of the assignment that it was just sent.
+    fn resize(
+        &self,
+        state: &mut SizingState,
+        shark: &str,
+        tasks: usize,
+        resize: ResizeBy,
+    ) {
+        let initial = self.clamp(tasks);
+        let size = state.sizes.entry(shark.to_string()).or_insert(initial);
+
+        let new_size = self.clamp(match resize {
+            ResizeBy::Half => *size / 2,
+            ResizeBy::Shrink => *size - *size / 4,
+            ResizeBy::Grow => *size + (*size / 4).max(1),
+        });
+
+        if new_size != *size {
+            debug!(
+                "Job {}: assignments for {} resized from {} to {} tasks",
+                self.job_id, shark, size, new_size
+            );
+            *size = new_size;
+        }
+    }
+
+    /// Record that an assignment of `tasks` tasks was posted to `shark`.
+    pub fn posted(&self, assignment_id: &str, shark: &str, tasks: usize) {
+        self.posted_at(assignment_id, shark, tasks, Instant::now())
+    }
+
+    fn posted_at(
+        &self,
+        assignment_id: &str,
+        shark: &str,
+        tasks: usize,
+        now: Instant,
+    ) {
+        if !self.config.adaptive {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("sizing lock");
+        state
+            .posted
+            .insert(assignment_id.to_string(), (shark.to_string(), tasks, now));
+    }
+
+    /// Record that an assignment of `tasks` tasks could not be posted to
+    /// `shark`.
+    pub fn post_failed(&self, shark: &str, tasks: usize) {
+        if !self.config.adaptive {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("sizing lock");
+        self.resize(&mut state, shark, tasks, ResizeBy::Half);
+    }
+
+    /// Record that the agent has completed an assignment, `failed` of whose
+    /// `total` tasks failed.
+    pub fn completed(&self, assignment_id: &str, total: usize, failed: usize) {
+        self.completed_at(assignment_id, total, failed, Instant::now())
+    }
+
+    fn completed_at(
+        &self,
+        assignment_id: &str,
+        total: usize,
+        failed: usize,
+        now: Instant,
+    ) {
+        if !self.config.adaptive {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("sizing lock");
+        let (shark, tasks, posted) = match state.posted.remove(assignment_id) {
+            Some(p) => p,
+            None => return,
+        };
+
+        let error_ratio = if total > 0 {
+            failed as f64 / total as f64
+        } else {
+            0.0
+        };
+        let elapsed = now.saturating_duration_since(posted);
+        let target = Duration::from_secs(self.config.target_assignment_secs);
+
+        let resize = if error_ratio > self.config.max_error_ratio {
+            ResizeBy::Half
+        } else if elapsed > target {
+            ResizeBy::Shrink
+        } else if elapsed < target / 2 {
+            ResizeBy::Grow
+        } else {
+            return;
+        };
+
+        self.resize(&mut state, &shark, tasks, resize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    fn adaptive() -> ConfigAssignmentSizing {
+        ConfigAssignmentSizing {
+            adaptive: true,
+            min_tasks_per_assignment: 10,
+            max_tasks_per_assignment: 100,
+            target_assignment_secs: 60,
+            max_error_ratio: 0.1,
+        }
+    }
+
+    #[test]
+    fn sizing_disabled() {
+        let sizer =
+            AssignmentSizer::new("job", &ConfigAssignmentSizing::default());
+
+        sizer.posted("a1", "1.stor.domain", 50);
+        sizer.post_failed("1.stor.domain", 50);
+        sizer.completed("a1", 50, 50);
+
+        assert_eq!(sizer.max_tasks("1.stor.domain", 50), 50);
+    }
+
+    #[test]
+    fn sizing_follows_agent() {
+        let sizer = AssignmentSizer::new("job", &adaptive());
+        let fast = "1.stor.domain";
+        let slow = "2.stor.domain";
+        let start = Instant::now();
+
+        assert_eq!(sizer.max_tasks(fast, 40), 40);
+        assert_eq!(sizer.max_tasks(slow, 40), 40);
+
+        // The job's own maximum is kept within the bounds.
+        assert_eq!(sizer.max_tasks(fast, 1000), 100);
+
+        // A fast agent gets larger assignments, up to the maximum.
+        for i in 0..20 {
+            let id = format!("fast-{}", i);
+            let tasks = sizer.max_tasks(fast, 40);
+            sizer.posted_at(&id, fast, tasks, start);
+            sizer.completed_at(&id, tasks, 0, start + secs(10));
+        }
+        assert_eq!(sizer.max_tasks(fast, 40), 100);
+
+        // A slow agent gets smaller ones.
+        sizer.posted_at("slow-1", slow, 40, start);
+        sizer.completed_at("slow-1", 40, 0, start + secs(90));
+        assert_eq!(sizer.max_tasks(slow, 40), 30);
+
+        // Within the target, the size is left alone.
+        sizer.posted_at("slow-2", slow, 30, start);
+        sizer.completed_at("slow-2", 30, 0, start + secs(45));
+        assert_eq!(sizer.max_tasks(slow, 40), 30);
+
+        // Errors halve the size, down to the minimum.
+        sizer.posted_at("slow-3", slow, 30, start);
+        sizer.completed_at("slow-3", 30, 10, start + secs(45));
+        assert_eq!(sizer.max_tasks(slow, 40), 15);
+        sizer.post_failed(slow, 15);
+        assert_eq!(sizer.max_tasks(slow, 40), 10);
+
+        // None of which affected the fast agent.
+        assert_eq!(sizer.max_tasks(fast, 40), 100);
+    }
+}
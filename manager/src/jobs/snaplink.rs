@@ -0,0 +1,269 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Objects with snaplinks.
+//
+// A snaplink is a second metadata entry, under another key and often another
+// owner, for the same object id and so for the same copies of the data.
+// Moving the object's data off a shark and updating only one of its entries
+// leaves the others naming a copy that is about to be removed, so the manager
+// has refused to start any job while `snaplink_cleanup_required` is set, and
+// jobs record the entries that they find for an object id that they already
+// have in their duplicates table, to be dealt with by hand.
+//
+// With options.resolve_snaplinks set, jobs run in spite of
+// `snaplink_cleanup_required`.  The first entry that a job finds for an
+// object id is moved as usual, and every other entry that it finds for the
+// same id is recorded here as a link of it, along with its shard and the
+// metadata and etag that it was found with, as well as in the duplicates
+// table.  (That includes an entry for the same key in another shard, which is
+// misplaced, but names the same copies all the same.)  An object that has
+// links is not marked complete once its own metadata has been updated.
+// Instead, once every assignment is done, the job updates the links of each
+// such object, with the links in each shard all put in a single batch, so
+// that either every one of them in a shard is updated or none is.  A batch
+// that fails is retried one link at a time, each re-read and rebased if it
+// conflicts.  The object is marked complete only once each of its links has
+// been updated, and as an error (`snaplink_update_failed`) otherwise; the
+// links that were not updated are kept for a retry job.  The links of an
+// object that was not moved (i.e. that was skipped, or failed) are left as
+// they are, as they still match it.
+//
+// A retry job, or a job resumed from one that was interrupted, takes on the
+// links of the job before it that had yet to be updated.
+
+use rebalancer::common::ObjectId;
+use rebalancer::error::Error;
+
+use crate::pg_db;
+
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use serde_json::Value;
+
+table! {
+    use diesel::sql_types::{Integer, Jsonb, Text};
+    snaplinks (id, key, shard) {
+        id -> Text,
+        key -> Text,
+        shard -> Integer,
+        object -> Jsonb,
+        etag -> Text,
+        status -> Text,
+    }
+}
+
+#[derive(Insertable, Queryable, Clone, Debug, PartialEq)]
+#[table_name = "snaplinks"]
+pub struct Snaplink {
+    pub id: ObjectId,
+    pub key: String,
+    pub shard: i32,
+    pub object: Value,
+    pub etag: String,
+    pub status: String,
+}
+
+#[derive(Display, EnumString, Debug, Clone, Copy, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum SnaplinkStatus {
+    // Found, and not yet updated.
+    Pending,
+
+    // Updated to match the object that it links to.
+    Updated,
+
+    // Could not be updated.  A retry job tries again.
+    Failed,
+
+    // Left as it was, because the object that it links to was not moved.
+    Skipped,
+}
+
+pub fn create_snaplinks_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE snaplinks(
+        id TEXT NOT NULL,
+        key TEXT NOT NULL,
+        shard Integer NOT NULL,
+        object Jsonb NOT NULL,
+        etag TEXT NOT NULL,
+        status TEXT NOT NULL,
+        PRIMARY KEY (id, key, shard)
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE snaplinks") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// Returns true if the entry under `key` in `shard` for an object whose entry
+/// is under `primary_key` in `primary_shard` is a link of it, rather than the
+/// same entry found again (as it is when a resumed job scans a shard again).
+pub fn is_link(
+    primary_key: &str,
+    primary_shard: i32,
+    key: &str,
+    shard: i32,
+) -> bool {
+    primary_key != key || primary_shard != shard
+}
+
+/// Record `link` as pending, unless it has been recorded already.
+pub fn record_link(
+    conn: &PgConnection,
+    link: &Snaplink,
+) -> Result<usize, Error> {
+    let link = Snaplink {
+        status: SnaplinkStatus::Pending.to_string(),
+        ..link.clone()
+    };
+
+    diesel::insert_into(snaplinks::table)
+        .values(&link)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+/// Those of `ids` that have links still to be updated.
+pub fn linked(
+    conn: &PgConnection,
+    ids: &[ObjectId],
+) -> Result<Vec<ObjectId>, Error> {
+    use self::snaplinks::dsl::{id, snaplinks as links_table, status};
+
+    links_table
+        .select(id)
+        .filter(id.eq_any(ids))
+        .filter(status.eq(SnaplinkStatus::Pending.to_string()))
+        .distinct()
+        .load::<ObjectId>(conn)
+        .map_err(Error::from)
+}
+
+/// The links still to be updated, by the id of the object that they link to.
+pub fn pending_links(
+    conn: &PgConnection,
+) -> Result<HashMap<ObjectId, Vec<Snaplink>>, Error> {
+    use self::snaplinks::dsl::{snaplinks as links_table, status};
+
+    let links = links_table
+        .filter(status.eq(SnaplinkStatus::Pending.to_string()))
+        .load::<Snaplink>(conn)?;
+
+    let mut pending: HashMap<ObjectId, Vec<Snaplink>> = HashMap::new();
+    for link in links.into_iter() {
+        pending.entry(link.id.clone()).or_default().push(link);
+    }
+
+    Ok(pending)
+}
+
+/// Set the status of `links`.
+pub fn mark_links(
+    conn: &PgConnection,
+    links: &[&Snaplink],
+    to_status: SnaplinkStatus,
+) -> Result<usize, Error> {
+    use self::snaplinks::dsl::{
+        id, key, shard, snaplinks as links_table, status,
+    };
+
+    let mut count = 0;
+    for link in links.iter() {
+        count += diesel::update(links_table)
+            .filter(id.eq(&link.id))
+            .filter(key.eq(&link.key))
+            .filter(shard.eq(link.shard))
+            .set(status.eq(to_status.to_string()))
+            .execute(conn)?;
+    }
+
+    Ok(count)
+}
+
+/// Take on the links of the job `old_job` that it did not update.  Jobs that
+/// were run before links were recorded have no table for them.
+pub fn carry_over(conn: &PgConnection, old_job: &str) -> Result<usize, Error> {
+    use self::snaplinks::dsl::{snaplinks as links_table, status};
+
+    let old_conn = pg_db::connect_db(old_job)?;
+    let links = match links_table
+        .filter(status.ne(SnaplinkStatus::Updated.to_string()))
+        .filter(status.ne(SnaplinkStatus::Skipped.to_string()))
+        .load::<Snaplink>(&old_conn)
+    {
+        Ok(links) => links,
+        Err(e) => {
+            debug!("Job {} has no snaplinks: {}", old_job, e);
+            return Ok(0);
+        }
+    };
+
+    let mut count = 0;
+    for link in links.iter() {
+        count += record_link(conn, link)?;
+    }
+
+    Ok(count)
+}
+
+/// `links` grouped by the shard that they are in, in the order given.
+pub fn by_shard(links: &[Snaplink]) -> HashMap<u32, Vec<&Snaplink>> {
+    let mut shards: HashMap<u32, Vec<&Snaplink>> = HashMap::new();
+
+    for link in links.iter() {
+        shards.entry(link.shard as u32).or_default().push(link);
+    }
+
+    shards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn link(shard: i32, key: &str) -> Snaplink {
+        Snaplink {
+            id: String::from("obj"),
+            key: key.to_string(),
+            shard,
+            object: serde_json::json!({ "key": key }),
+            etag: String::from("etag"),
+            status: SnaplinkStatus::Pending.to_string(),
+        }
+    }
+
+    #[test]
+    fn snaplink_by_shard() {
+        assert!(is_link("/a/stor/one", 1, "/b/stor/two", 1));
+        assert!(is_link("/a/stor/one", 1, "/a/stor/one", 2));
+        assert!(!is_link("/a/stor/one", 1, "/a/stor/one", 1));
+
+        let links = vec![link(1, "/a"), link(2, "/b"), link(1, "/c")];
+        let shards = by_shard(&links);
+
+        assert_eq!(shards.len(), 2);
+        let keys: Vec<&str> =
+            shards[&1].iter().map(|l| l.key.as_str()).collect();
+        assert_eq!(keys, vec!["/a", "/c"]);
+        assert_eq!(shards[&2][0].key, "/b");
+
+        assert_eq!(
+            SnaplinkStatus::from_str("pending").unwrap(),
+            SnaplinkStatus::Pending
+        );
+        assert_eq!(SnaplinkStatus::Failed.to_string(), "failed");
+    }
+}
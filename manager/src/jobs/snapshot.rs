@@ -0,0 +1,373 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Keeping what a job's metrics came to.
+//
+// The manager's metrics are scraped by Prometheus, but how long what it
+// scrapes is kept for is up to whoever runs Prometheus, and comparing jobs
+// that were run months apart (their error ratios, say, or how their
+// assignment times were distributed) should not depend on that.  So each job
+// takes a MetricsBaseline of the job-related counters and histograms (see
+// JOB_METRICS) when it starts running, and when it stops running records in
+// the job_metrics table of the rebalancer database how far each of them has
+// moved since.  Histograms are recorded as Prometheus exposes them: a
+// `<name>_bucket` sample for each bucket (labeled by its upper bound, `le`),
+// and `<name>_sum` and `<name>_count` samples.  The labels common to all of
+// the manager's metrics are left out, as are samples that did not move.  What
+// is recorded is reported as the `metrics` of the job's status.
+//
+// The metrics are kept for the manager as a whole, so the snapshot of a job
+// that ran alongside others includes what the others counted while it ran.
+// Gauges describe the manager at an instant rather than over a job's run, and
+// are not recorded.
+
+use super::REBALANCER_DB;
+use crate::metrics::{
+    ASSIGNMENT_POLL_COUNT, BANDWIDTH_WAIT_SECONDS, MD_CONFLICT_COUNT,
+    METADATA_UPDATE_TIME, MORAY_SHARD_ERROR_COUNT, MORAY_SHARD_TIME,
+    PLACEMENT_EXCLUDED_COUNT, RECORD_DISPOSITION_COUNT, ROLLBACK_OBJECT_COUNT,
+    SHARK_BYTES_COUNT, SHARK_OBJECT_COUNT, SKIP_COUNT, SOURCE_COUNT,
+    VERIFY_OBJECT_COUNT,
+};
+use crate::pg_db;
+use rebalancer::error::Error;
+use rebalancer::metrics::{
+    self as rebalancer_metrics, ASSIGNMENT_TIME, BYTES_COUNT, ERROR_COUNT,
+    OBJECT_COUNT, OBJECT_SIZE, OBJECT_SIZE_FAILED,
+};
+use rebalancer::util::now_ms;
+pub use rebalancer_client::status::{JobMetrics, MetricSample};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use diesel::prelude::*;
+use prometheus::proto::{MetricFamily, MetricType};
+
+table! {
+    use diesel::sql_types::{BigInt, Double, Text};
+    job_metrics (job_id, name, labels) {
+        job_id -> Text,
+        name -> Text,
+        labels -> Text,
+        value -> Double,
+        timestamp -> BigInt,
+    }
+}
+
+// The metrics that say something about a job's run.
+static JOB_METRICS: &[&str] = &[
+    OBJECT_COUNT,
+    ERROR_COUNT,
+    BYTES_COUNT,
+    ASSIGNMENT_TIME,
+    OBJECT_SIZE,
+    OBJECT_SIZE_FAILED,
+    SKIP_COUNT,
+    METADATA_UPDATE_TIME,
+    MORAY_SHARD_TIME,
+    MORAY_SHARD_ERROR_COUNT,
+    SHARK_OBJECT_COUNT,
+    SHARK_BYTES_COUNT,
+    SOURCE_COUNT,
+    PLACEMENT_EXCLUDED_COUNT,
+    RECORD_DISPOSITION_COUNT,
+    VERIFY_OBJECT_COUNT,
+    ROLLBACK_OBJECT_COUNT,
+    ASSIGNMENT_POLL_COUNT,
+    BANDWIDTH_WAIT_SECONDS,
+    MD_CONFLICT_COUNT,
+];
+
+// The most rows to insert with one statement.
+static INSERT_CHUNK_SIZE: usize = 1000;
+
+#[derive(Insertable, Queryable)]
+#[table_name = "job_metrics"]
+struct JobMetricEntry {
+    job_id: String,
+    name: String,
+    // The sample's labels, as a JSON object.
+    labels: String,
+    value: f64,
+    timestamp: i64,
+}
+
+// A sample's name, and its labels as a JSON object.
+type SampleKey = (String, String);
+
+/// The job-related metrics at the time a job started running.
+pub struct MetricsBaseline {
+    samples: HashMap<SampleKey, f64>,
+}
+
+impl MetricsBaseline {
+    pub fn capture() -> MetricsBaseline {
+        MetricsBaseline {
+            samples: samples(&prometheus::gather(), &const_label_names()),
+        }
+    }
+}
+
+// The names of the labels that every one of the manager's metrics has.
+fn const_label_names() -> HashSet<String> {
+    rebalancer_metrics::get_const_labels()
+        .lock()
+        .expect("const labels lock")
+        .as_ref()
+        .map(|labels| labels.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn sample_key(name: &str, labels: &BTreeMap<String, String>) -> SampleKey {
+    (
+        name.to_string(),
+        serde_json::to_string(labels).expect("serialize labels"),
+    )
+}
+
+// The current value of each sample of the job-related counters and
+// histograms in `families`, without the labels named in `skip_labels`.
+fn samples(
+    families: &[MetricFamily],
+    skip_labels: &HashSet<String>,
+) -> HashMap<SampleKey, f64> {
+    let mut samples = HashMap::new();
+
+    for family in families
+        .iter()
+        .filter(|f| JOB_METRICS.contains(&f.get_name()))
+    {
+        let name = family.get_name();
+
+        for metric in family.get_metric() {
+            let labels: BTreeMap<String, String> = metric
+                .get_label()
+                .iter()
+                .filter(|l| !skip_labels.contains(l.get_name()))
+                .map(|l| (l.get_name().to_string(), l.get_value().to_string()))
+                .collect();
+
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    samples.insert(
+                        sample_key(name, &labels),
+                        metric.get_counter().get_value(),
+                    );
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let bucket_name = format!("{}_bucket", name);
+
+                    for bucket in histogram.get_bucket() {
+                        let mut bucket_labels = labels.clone();
+                        bucket_labels.insert(
+                            String::from("le"),
+                            bucket.get_upper_bound().to_string(),
+                        );
+                        samples.insert(
+                            sample_key(&bucket_name, &bucket_labels),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+
+                    samples.insert(
+                        sample_key(&format!("{}_sum", name), &labels),
+                        histogram.get_sample_sum(),
+                    );
+                    samples.insert(
+                        sample_key(&format!("{}_count", name), &labels),
+                        histogram.get_sample_count() as f64,
+                    );
+                }
+                _ => (),
+            }
+        }
+    }
+
+    samples
+}
+
+// How far each sample has moved from `baseline` to `current`, leaving out
+// those that have not moved.
+fn deltas(
+    baseline: &HashMap<SampleKey, f64>,
+    current: &HashMap<SampleKey, f64>,
+) -> Vec<(SampleKey, f64)> {
+    let mut deltas: Vec<(SampleKey, f64)> = current
+        .iter()
+        .map(|(key, value)| {
+            let before = baseline.get(key).cloned().unwrap_or(0.0);
+            (key.clone(), value - before)
+        })
+        .filter(|(_, delta)| *delta != 0.0)
+        .collect();
+
+    deltas.sort_by(|a, b| a.0.cmp(&b.0));
+    deltas
+}
+
+pub fn create_metrics_table(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS job_metrics(
+            job_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            labels TEXT NOT NULL,
+            value DOUBLE PRECISION NOT NULL,
+            timestamp BIGINT NOT NULL,
+            PRIMARY KEY (job_id, name, labels)
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+/// Record how far the job-related metrics have moved since `baseline` was
+/// captured, as the metrics of the job `job_id`.  Returns the number of
+/// samples recorded.
+pub fn record_snapshot(
+    job_id: &str,
+    baseline: &MetricsBaseline,
+) -> Result<usize, Error> {
+    let current = samples(&prometheus::gather(), &const_label_names());
+    let timestamp = now_ms();
+
+    let entries: Vec<JobMetricEntry> = deltas(&baseline.samples, &current)
+        .into_iter()
+        .map(|((name, labels), value)| JobMetricEntry {
+            job_id: job_id.to_string(),
+            name,
+            labels,
+            value,
+            timestamp,
+        })
+        .collect();
+
+    if entries.is_empty() {
+        return Ok(0);
+    }
+
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    for chunk in entries.chunks(INSERT_CHUNK_SIZE) {
+        diesel::insert_into(job_metrics::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(&conn)
+            .map_err(Error::from)?;
+    }
+
+    Ok(entries.len())
+}
+
+/// What the metrics of the job `job_id` came to, if they were recorded.
+pub fn get_snapshot(job_id: &str) -> Result<Option<JobMetrics>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    let entries: Vec<JobMetricEntry> = job_metrics::table
+        .filter(job_metrics::job_id.eq(job_id))
+        .order((job_metrics::name, job_metrics::labels))
+        .load(&conn)
+        .map_err(Error::from)?;
+
+    let timestamp = match entries.first() {
+        Some(e) => e.timestamp,
+        None => return Ok(None),
+    };
+
+    let samples = entries
+        .into_iter()
+        .map(|e| MetricSample {
+            labels: serde_json::from_str(&e.labels).unwrap_or_default(),
+            name: e.name,
+            value: e.value,
+        })
+        .collect();
+
+    Ok(Some(JobMetrics { timestamp, samples }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Histogram, HistogramOpts, Opts, Registry};
+
+    fn labels(pairs: &[(&str, &str)]) -> String {
+        let labels: BTreeMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        serde_json::to_string(&labels).unwrap()
+    }
+
+    #[test]
+    fn snapshot_deltas() {
+        let registry = Registry::new();
+
+        let objects = CounterVec::new(
+            Opts::new(OBJECT_COUNT, "objects").const_label("service", "test"),
+            &["action"],
+        )
+        .unwrap();
+        let times = Histogram::with_opts(
+            HistogramOpts::new(ASSIGNMENT_TIME, "times")
+                .buckets(vec![1.0, 10.0]),
+        )
+        .unwrap();
+        let other = CounterVec::new(
+            Opts::new("not_a_job_metric", "other"),
+            &["action"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(objects.clone())).unwrap();
+        registry.register(Box::new(times.clone())).unwrap();
+        registry.register(Box::new(other.clone())).unwrap();
+
+        let skip: HashSet<String> =
+            vec![String::from("service")].into_iter().collect();
+
+        objects.with_label_values(&["evacuate"]).inc_by(5.0);
+        times.observe(0.5);
+        let baseline = samples(&registry.gather(), &skip);
+
+        objects.with_label_values(&["evacuate"]).inc_by(3.0);
+        objects.with_label_values(&["verify"]).inc();
+        other.with_label_values(&["evacuate"]).inc();
+        times.observe(5.0);
+        let current = samples(&registry.gather(), &skip);
+
+        let expected = vec![
+            (
+                (
+                    String::from("assignment_time_bucket"),
+                    labels(&[("le", "10")]),
+                ),
+                1.0,
+            ),
+            ((String::from("assignment_time_count"), labels(&[])), 1.0),
+            ((String::from("assignment_time_sum"), labels(&[])), 5.0),
+            (
+                (
+                    String::from("object_count"),
+                    labels(&[("action", "evacuate")]),
+                ),
+                3.0,
+            ),
+            (
+                (
+                    String::from("object_count"),
+                    labels(&[("action", "verify")]),
+                ),
+                1.0,
+            ),
+        ];
+
+        assert_eq!(deltas(&baseline, &current), expected);
+    }
+}
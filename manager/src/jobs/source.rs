@@ -0,0 +1,218 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Finding a job's objects somewhere other than the metadata tier.
+//
+// An evacuate, create-copy or remove-copy job finds the objects on its shark
+// by having sharkspotter scan the shards of the metadata tier.  A load test
+// that is to be repeated, or a dry run away from a full Manta deployment,
+// needs the same objects to be found every time, without a metadata tier to
+// scan.  Such a job can be given a `source` of `{"type": "file", "path":
+// ...}`: a snapshot, on the manager, of the records that sharkspotter would
+// have found, as newline delimited JSON in the form that sharkspotter reports
+// them (see SnapshotRecord).  The records are read in the order that they are
+// in the file, and handed to the job just as those of a scan are, so they are
+// classified, matched against the job's filter and checkpointed by shard in
+// the same way.  A line that can not be read as a record is logged and
+// skipped.  Once the whole file has been read, every shard that it had
+// records of is taken to have been scanned.
+//
+// Only the finding of objects is replaced.  The metadata of each object that
+// is moved is still updated in moray, so a snapshot is meant to be used with
+// a test deployment (or a sharks_file of test sharks).  A job resumed from
+// one that was interrupted reads the file again, leaving out the records of
+// the shards that the interrupted job settled, as a scan would.
+//
+// The source is recorded in the source_config table of the job's database,
+// so that a retry or resumed job keeps to it, and is reported in the job's
+// status config.
+
+use rebalancer::error::Error;
+pub use rebalancer_client::jobs::ObjectSource;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use diesel::prelude::*;
+use serde::Deserialize;
+use serde_json::Value;
+use sharkspotter::SharkspotterMessage;
+
+table! {
+    use diesel::sql_types::{Integer, Jsonb};
+    source_config (id) {
+        id -> Integer,
+        source -> Jsonb,
+    }
+}
+
+#[derive(Insertable, AsChangeset, Queryable)]
+#[table_name = "source_config"]
+struct SourceDbConfig {
+    id: i32,
+    source: Value,
+}
+
+pub fn create_source_config_table(conn: &PgConnection) -> Result<usize, Error> {
+    let create_query = "CREATE TABLE source_config(
+        id Integer PRIMARY KEY,
+        source Jsonb NOT NULL
+    );";
+
+    if let Err(e) = conn.execute("DROP TABLE source_config") {
+        debug!("Table doesn't exist: {}", e);
+    }
+
+    conn.execute(create_query).map_err(Error::from)
+}
+
+/// Record the job's source.  There is only a single entry.
+pub fn record_source(
+    conn: &PgConnection,
+    source: &ObjectSource,
+) -> Result<usize, Error> {
+    use self::source_config::dsl::{id, source_config as source_table};
+
+    let value = SourceDbConfig {
+        id: 1,
+        source: serde_json::to_value(source)?,
+    };
+
+    diesel::insert_into(source_table)
+        .values(&value)
+        .on_conflict(id)
+        .do_update()
+        .set(&value)
+        .execute(conn)
+        .map_err(Error::from)
+}
+
+/// The job's source.  Jobs that were not given one have no entry, and jobs
+/// that were run before sources were recorded have no table for them.
+pub fn get_source(conn: &PgConnection) -> Option<ObjectSource> {
+    use self::source_config::dsl::source_config as source_table;
+
+    source_table
+        .first::<SourceDbConfig>(conn)
+        .ok()
+        .and_then(|c| serde_json::from_value(c.source).ok())
+}
+
+/// A line of a snapshot file: a record as sharkspotter reports it.  The
+/// shark that the record was found for need not be given, since the job
+/// checks the record's sharks for its own.
+#[derive(Deserialize)]
+struct SnapshotRecord {
+    shard: u32,
+    etag: String,
+    manta_value: Value,
+    #[serde(default)]
+    shark: String,
+}
+
+/// Read a line of a snapshot file.
+pub fn parse_record(line: &str) -> Result<SharkspotterMessage, String> {
+    let record: SnapshotRecord =
+        serde_json::from_str(line).map_err(|e| e.to_string())?;
+
+    Ok(SharkspotterMessage {
+        manta_value: record.manta_value,
+        etag: record.etag,
+        shark: record.shark,
+        shard: record.shard,
+    })
+}
+
+/// The records of a snapshot file, each along with the number of the line
+/// that it is on, from 1.  Blank lines are passed over.
+pub struct SnapshotRecords<R: BufRead> {
+    lines: std::io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> SnapshotRecords<R> {
+    pub fn new(reader: R) -> Self {
+        SnapshotRecords {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for SnapshotRecords<R> {
+    type Item = Result<(usize, Result<SharkspotterMessage, String>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            self.line += 1;
+
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => return Some(Err(Error::from(e))),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Some(Ok((self.line, parse_record(&line))));
+        }
+        None
+    }
+}
+
+/// Open the snapshot file at `path`.
+pub fn open(path: &str) -> Result<SnapshotRecords<BufReader<File>>, Error> {
+    Ok(SnapshotRecords::new(BufReader::new(File::open(path)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn snapshot_records() {
+        let mut file = String::new();
+        for shard in 1..=2 {
+            let record = serde_json::json!({
+                "shard": shard,
+                "etag": format!("etag{}", shard),
+                "manta_value": {
+                    "objectId": format!("object{}", shard),
+                    "type": "object",
+                },
+            });
+            file.push_str(&format!("{}\n\n", record));
+        }
+        file.push_str("{\"shard\": 3}\n");
+
+        let records: Vec<(usize, Result<SharkspotterMessage, String>)> =
+            SnapshotRecords::new(Cursor::new(file))
+                .map(|r| r.expect("read line"))
+                .collect();
+
+        assert_eq!(records.len(), 3);
+        for (i, (line, record)) in records[..2].iter().enumerate() {
+            let record = record.as_ref().expect("snapshot record");
+            assert_eq!(*line, i * 2 + 1);
+            assert_eq!(record.shard, i as u32 + 1);
+            assert_eq!(record.etag, format!("etag{}", i + 1));
+            assert_eq!(
+                record.manta_value["objectId"],
+                format!("object{}", i + 1)
+            );
+            assert!(record.shark.is_empty());
+        }
+
+        // A record without an etag or metadata is not one.
+        assert_eq!(records[2].0, 5);
+        assert!(records[2].1.is_err());
+    }
+}
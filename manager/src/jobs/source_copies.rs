@@ -0,0 +1,348 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Objects with more than one copy on the shark being evacuated.
+//
+// The metadata of an object can name the shark that an evacuate job is
+// evacuating more than once.  Such an object used to be given a single
+// destination like any other, and its update then replaced every one of
+// those entries with it, so that the update was refused for naming the
+// destination twice (`duplicate_shark`), after the copy had been made.
+//
+// An evacuate job now plans the entries of such an object together: as it
+// places the object, it picks a destination for each of them, each of which
+// would be a valid destination with the others already in the object's
+// metadata, so that they are distinct and keep to the same rules about data
+// centers as any other.  If there are not enough of them, the object is
+// skipped with a reason of `no_second_destination`.
+//
+// The object is then copied to each destination in turn.  Once the agent on
+// one has made its copy, the object is not updated, but is given back to the
+// assignment manager to be sent to the next, or to another valid destination
+// if that one can no longer be used.  Once the last copy is made, the object
+// is updated once, with each entry replaced by the destination that was
+// copied to for it, so that the metadata never names a copy that has not
+// been made, and there is no earlier update for the last to conflict with.
+// The assignment manager waits for the objects that have copies still to be
+// made before it finishes.
+//
+// Plans are only held in memory.  An object that had only some of its copies
+// made by a job that was interrupted is planned anew by the job that resumes
+// it, or by a retry job.
+
+use rebalancer::common::{self, ObjectId};
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use crate::storinfo::StorageNode;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use libmanta::moray::MantaObjectShark;
+use serde_json::Value;
+
+/// The destinations of an object with more than one copy on the shark being
+/// evacuated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopyPlan {
+    /// The destination picked for each of the object's entries for the
+    /// evacuated shark as the object was first placed, in order.
+    pub destinations: Vec<StorageNode>,
+
+    /// The destinations that hold a copy so far, which need not be those
+    /// that were picked.
+    pub copied: Vec<StorageNode>,
+}
+
+impl CopyPlan {
+    /// The destination picked for the next copy.
+    pub fn next(&self) -> Option<&StorageNode> {
+        self.destinations.get(self.copied.len())
+    }
+
+    /// Returns true if the copy being made is not the last.
+    fn has_more(&self) -> bool {
+        self.copied.len() + 1 < self.destinations.len()
+    }
+}
+
+/// The plans of a job's objects that have more than one copy on the shark
+/// being evacuated.
+#[derive(Default)]
+pub struct SourceCopies {
+    plans: Mutex<HashMap<ObjectId, CopyPlan>>,
+}
+
+impl SourceCopies {
+    pub fn new() -> SourceCopies {
+        SourceCopies::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.lock().expect("source copies lock").is_empty()
+    }
+
+    /// Plan the copies of `id` anew, with none made yet.
+    pub fn plan(&self, id: &str, destinations: Vec<StorageNode>) {
+        self.plans.lock().expect("source copies lock").insert(
+            id.to_string(),
+            CopyPlan {
+                destinations,
+                copied: vec![],
+            },
+        );
+    }
+
+    pub fn get(&self, id: &str) -> Option<CopyPlan> {
+        self.plans
+            .lock()
+            .expect("source copies lock")
+            .get(id)
+            .cloned()
+    }
+
+    /// Record that `dest` holds a copy of `id`, if that copy was not the
+    /// last to be made.  Returns true if it was recorded, in which case the
+    /// object is to be sent on for its next copy rather than updated.
+    pub fn copied(&self, id: &str, dest: &StorageNode) -> bool {
+        let mut plans = self.plans.lock().expect("source copies lock");
+
+        match plans.get_mut(id) {
+            Some(plan) if plan.has_more() => {
+                plan.copied.push(dest.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The objects that are being copied and have further copies to be made
+    /// after that.
+    pub fn waiting(&self) -> Vec<ObjectId> {
+        self.plans
+            .lock()
+            .expect("source copies lock")
+            .iter()
+            .filter(|(_, plan)| plan.has_more())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Forget the plans of `ids`, whose objects have been given up on.
+    pub fn forget(&self, ids: &[ObjectId]) {
+        let mut plans = self.plans.lock().expect("source copies lock");
+
+        for id in ids {
+            plans.remove(id);
+        }
+    }
+}
+
+/// The number of entries for `storage_id` in the sharks of `object`.
+pub fn copies_on_shark(object: &Value, storage_id: &str) -> usize {
+    match object.get("sharks").and_then(Value::as_array) {
+        Some(sharks) => sharks
+            .iter()
+            .filter(|s| {
+                s.get("manta_storage_id").and_then(Value::as_str)
+                    == Some(storage_id)
+            })
+            .count(),
+        None => 0,
+    }
+}
+
+/// Replace the entries for `from` in `sharks` with `dests`, in order.  There
+/// must be exactly one of `dests` for each entry, and each of them must be on
+/// no other, so that the update does not name any shark twice.
+pub fn replace_copies(
+    sharks: &mut Vec<MantaObjectShark>,
+    from: &str,
+    dests: &[&StorageNode],
+) -> Result<(), Error> {
+    let entries = sharks.iter().filter(|s| s.manta_storage_id == from).count();
+
+    if entries != dests.len() {
+        let msg = format!(
+            "Found {} copies on {} while attempting to update metadata \
+             with {} destinations",
+            entries,
+            from,
+            dests.len()
+        );
+        return Err(InternalError::new(
+            Some(InternalErrorCode::DuplicateShark),
+            msg,
+        )
+        .into());
+    }
+
+    for (i, dest) in dests.iter().enumerate() {
+        let duplicate = dests[..i]
+            .iter()
+            .any(|d| d.manta_storage_id == dest.manta_storage_id)
+            || sharks
+                .iter()
+                .any(|s| s.manta_storage_id == dest.manta_storage_id);
+
+        if duplicate {
+            let msg = format!(
+                "Found duplicate shark {} while attempting to update \
+                 metadata of copies on {}",
+                dest.manta_storage_id, from
+            );
+            return Err(InternalError::new(
+                Some(InternalErrorCode::DuplicateShark),
+                msg,
+            )
+            .into());
+        }
+    }
+
+    let mut dests = dests.iter();
+    for shark in sharks.iter_mut() {
+        if shark.manta_storage_id != from {
+            continue;
+        }
+
+        let dest = dests.next().expect("one destination per copy");
+        shark.manta_storage_id = dest.manta_storage_id.clone();
+        shark.datacenter = dest.datacenter.clone();
+    }
+
+    Ok(())
+}
+
+/// `object` as it will be once the copies in `copied` have replaced the
+/// first of its entries for `from`.  This is what the destination of its next
+/// copy is checked against.
+pub fn with_copies(
+    object: &Value,
+    from: &str,
+    copied: &[StorageNode],
+) -> Result<Value, Error> {
+    let mut sharks = common::get_sharks_from_value(object)?;
+    let mut copied = copied.iter();
+
+    for shark in sharks.iter_mut() {
+        if shark.manta_storage_id != from {
+            continue;
+        }
+
+        match copied.next() {
+            Some(dest) => {
+                shark.manta_storage_id = dest.manta_storage_id.clone();
+                shark.datacenter = dest.datacenter.clone();
+            }
+            None => break,
+        }
+    }
+
+    let mut object = object.clone();
+    object["sharks"] = serde_json::to_value(sharks)?;
+
+    Ok(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: &str, dc: &str) -> StorageNode {
+        StorageNode {
+            available_mb: 1000,
+            percent_used: 10,
+            filesystem: String::from("/manta"),
+            datacenter: dc.to_string(),
+            manta_storage_id: id.to_string(),
+            timestamp: 0,
+        }
+    }
+
+    fn shark(id: &str, dc: &str) -> MantaObjectShark {
+        MantaObjectShark {
+            manta_storage_id: id.to_string(),
+            datacenter: dc.to_string(),
+        }
+    }
+
+    fn ids(sharks: &[MantaObjectShark]) -> Vec<&str> {
+        sharks.iter().map(|s| s.manta_storage_id.as_str()).collect()
+    }
+
+    #[test]
+    fn source_copies_replace() {
+        let a = node("2.stor", "dc2");
+        let b = node("3.stor", "dc3");
+        let sharks = vec![shark("1.stor", "dc1"), shark("1.stor", "dc1")];
+
+        let mut replaced = sharks.clone();
+        replace_copies(&mut replaced, "1.stor", &[&a, &b]).expect("replace");
+        assert_eq!(ids(&replaced), vec!["2.stor", "3.stor"]);
+        assert_eq!(replaced[1].datacenter, "dc3");
+
+        // Both entries going to one destination is the conflict that used
+        // to be found only once the copy was made.
+        let mut replaced = sharks.clone();
+        assert!(replace_copies(&mut replaced, "1.stor", &[&a, &a]).is_err());
+
+        // As is one going to a shark that already has a copy.
+        let mut replaced = vec![shark("1.stor", "dc1"), shark("2.stor", "dc2")];
+        assert!(replace_copies(&mut replaced, "1.stor", &[&a]).is_err());
+
+        // Every entry has to be replaced, by one destination each.
+        let mut replaced = sharks.clone();
+        assert!(replace_copies(&mut replaced, "1.stor", &[&a]).is_err());
+        assert!(replace_copies(&mut replaced, "4.stor", &[]).is_ok());
+        assert_eq!(ids(&replaced), ids(&sharks));
+    }
+
+    #[test]
+    fn source_copies_plan() {
+        let a = node("2.stor", "dc2");
+        let b = node("3.stor", "dc3");
+        let object = serde_json::json!({
+            "sharks": [
+                { "manta_storage_id": "1.stor", "datacenter": "dc1" },
+                { "manta_storage_id": "5.stor", "datacenter": "dc5" },
+                { "manta_storage_id": "1.stor", "datacenter": "dc1" },
+            ]
+        });
+        assert_eq!(copies_on_shark(&object, "1.stor"), 2);
+        assert_eq!(copies_on_shark(&object, "5.stor"), 1);
+        assert_eq!(copies_on_shark(&object, "2.stor"), 0);
+
+        let planned =
+            with_copies(&object, "1.stor", &[a.clone()]).expect("with copies");
+        assert_eq!(copies_on_shark(&planned, "1.stor"), 1);
+        assert_eq!(copies_on_shark(&planned, "2.stor"), 1);
+
+        let copies = SourceCopies::new();
+        assert!(copies.is_empty());
+        copies.plan("obj", vec![a.clone(), b.clone()]);
+        assert_eq!(copies.waiting(), vec![String::from("obj")]);
+        assert_eq!(copies.get("obj").and_then(|p| p.next().cloned()), Some(a));
+
+        // The first copy is recorded, and the object goes on to the next.
+        let c = node("4.stor", "dc4");
+        assert!(copies.copied("obj", &c));
+        assert!(copies.waiting().is_empty());
+        let plan = copies.get("obj").expect("plan");
+        assert_eq!(plan.copied, vec![c]);
+        assert_eq!(plan.next(), Some(&b));
+
+        // The last is not: the object is updated instead.
+        assert!(!copies.copied("obj", &b));
+        assert!(!copies.copied("other", &b));
+
+        copies.forget(&[String::from("obj")]);
+        assert!(copies.is_empty());
+    }
+}
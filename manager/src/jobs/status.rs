@@ -10,18 +10,45 @@
 
 use super::evacuate::EvacuateObjectStatus;
 
-use crate::jobs::evacuate::EvacuateJobDbConfig;
+use crate::jobs::breaker;
+use crate::jobs::checksum::{self, ChecksumSummary};
+use crate::jobs::confirmation;
+use crate::jobs::evacuate::{
+    self, AssignmentLifecycle, AssignmentSummary, CopyJobDbConfig,
+    DestLimitDbConfig, DownloadAttemptsEntry, EvacuateJobDbConfig,
+    EvacuateObject, HeaderMismatchEntry, MetadataAuditEntry, ScanCheckpoint,
+    SlowTaskEntry,
+};
+use crate::jobs::filter;
+use crate::jobs::lease;
+use crate::jobs::nofit::{self, NoFitSummary};
+use crate::jobs::placement::{self, PlacementTraceEntry};
+use crate::jobs::rollback::{self, RollbackObjectStatus};
+use crate::jobs::schedule::{self, JobScheduleStatus, ScheduleWindow};
+use crate::jobs::snapshot;
+use crate::jobs::source;
+use crate::jobs::tuning;
+use crate::jobs::verify::VerifyObjectStatus;
 use crate::jobs::{JobActionDbEntry, JobDbEntry, JobState, REBALANCER_DB};
 use crate::pg_db;
-use rebalancer::error::Error;
+use rebalancer::common::{DownloadAttempts, ObjectSkippedReason};
+use rebalancer::error::{Error, RebalancerError};
+pub use rebalancer_client::status::{
+    IngestionProgress, JobConfigCreateCopy, JobConfigEvacuate,
+    JobConfigRemoveCopy, JobConfigRollback, JobConfigVerify, JobPhases,
+    JobProgress, JobStatus, JobStatusConfig, JobStatusResults,
+    JobStatusResultsEvacuate, JobStatusResultsRollback, JobStatusResultsVerify,
+    PhaseProgress, PhaseState, ShardProgress,
+};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 use std::string::ToString;
 
 use diesel::prelude::*;
 use diesel::result::ConnectionError;
 use diesel::sql_query;
-use diesel::sql_types::{BigInt, Text};
+use diesel::sql_types::{BigInt, Integer, Nullable, Text};
 use inflector::cases::titlecase::to_title_case;
 use libmanta::moray::MantaObjectShark;
 use serde::{Deserialize, Serialize};
@@ -31,6 +58,31 @@ use uuid::Uuid;
 static STATUS_COUNT_QUERY: &str = "SELECT status, count(status) \
                                    FROM  evacuateobjects  GROUP BY status";
 
+static VERIFY_STATUS_COUNT_QUERY: &str = "SELECT status, count(status) \
+                                          FROM verifyobjects GROUP BY status";
+
+static ROLLBACK_STATUS_COUNT_QUERY: &str = "SELECT status, count(status) \
+                                            FROM rollbackobjects \
+                                            GROUP BY status";
+
+static SHARD_STATUS_COUNT_QUERY: &str =
+    "SELECT shard, status, count(*) AS count \
+                                         FROM evacuateobjects \
+                                         GROUP BY shard, status";
+
+static OUTSTANDING_ASSIGNMENTS_QUERY: &str =
+    "SELECT count(DISTINCT assignment_id) AS count \
+     FROM evacuateobjects \
+     WHERE status = 'assigned'";
+
+static SKIPPED_COUNT_QUERY: &str = "SELECT skipped_reason, count(*) \
+                                    FROM evacuateobjects \
+                                    WHERE status = 'skipped' \
+                                    GROUP BY skipped_reason";
+
+// The most skipped objects returned by a single request.
+pub static MAX_SKIPPED_LIMIT: i64 = 1000;
+
 #[derive(Debug, EnumString)]
 pub enum StatusError {
     DBExists,
@@ -46,31 +98,108 @@ struct StatusCount {
     count: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(tag = "action")]
-pub enum JobStatusConfig {
-    Evacuate(JobConfigEvacuate),
+#[derive(QueryableByName, Debug)]
+struct ShardStatusCount {
+    #[sql_type = "Integer"]
+    shard: i32,
+    #[sql_type = "Text"]
+    status: String,
+    #[sql_type = "BigInt"]
+    count: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum JobStatusResults {
-    Evacuate(JobStatusResultsEvacuate),
+#[derive(QueryableByName, Debug)]
+struct AssignmentCount {
+    #[sql_type = "BigInt"]
+    count: i64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct JobStatus {
-    pub config: JobStatusConfig,
-    pub results: JobStatusResults,
-    pub state: JobState,
+#[derive(QueryableByName, Debug)]
+struct SkippedCount {
+    #[sql_type = "Nullable<Text>"]
+    skipped_reason: Option<String>,
+    #[sql_type = "BigInt"]
+    count: i64,
 }
 
+/// An object that a job skipped, and why.  `skipped_reason` is in the same
+/// form as it is stored in the job's database (and accepted by
+/// `get_skipped_objects()`), e.g. `destination_unreachable` or
+/// `{http_status_code:404}`.
 #[derive(Debug, Deserialize, Serialize)]
-pub struct JobConfigEvacuate {
-    pub from_shark: MantaObjectShark,
+pub struct SkippedObject {
+    pub id: String,
+    pub key: Option<String>,
+    pub shard: i32,
+    pub dest_shark: String,
+    pub assignment_id: String,
+    pub skipped_reason: Option<String>,
+
+    // The code of `skipped_reason`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RebalancerError>,
+
+    // The agent's attempts at downloading the object, if the object failed
+    // on the agent and the agent reported them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadAttempts>,
+}
+
+impl From<EvacuateObject> for SkippedObject {
+    fn from(eobj: EvacuateObject) -> SkippedObject {
+        SkippedObject {
+            key: eobj
+                .object
+                .get("key")
+                .and_then(|k| k.as_str())
+                .map(String::from),
+            id: eobj.id,
+            shard: eobj.shard,
+            dest_shark: eobj.dest_shark,
+            assignment_id: eobj.assignment_id,
+            error: eobj.skipped_reason.as_ref().map(|r| r.rebalancer_error()),
+            skipped_reason: eobj.skipped_reason.map(|r| r.into_string()),
+            download: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "snake_case")]
+pub enum JobListOrder {
+    Asc,
+    Desc,
 }
 
-type JobStatusResultsEvacuate = HashMap<String, i64>;
+impl Default for JobListOrder {
+    fn default() -> Self {
+        JobListOrder::Asc
+    }
+}
+
+/// Restricts the jobs returned by `list_jobs()`.  Jobs are ordered by the time
+/// at which they were created.  `created_after` and `created_before` are in
+/// milliseconds since the epoch, and are inclusive.
+#[derive(Debug, Default)]
+pub struct JobListFilter {
+    pub state: Option<JobState>,
+    pub action: Option<JobActionDbEntry>,
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub order: JobListOrder,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SkippedSummary {
+    pub total: i64,
+    pub reasons: HashMap<String, i64>,
+
+    // The same objects, by the code of their reason.
+    #[serde(default)]
+    pub errors: HashMap<RebalancerError, i64>,
+}
 
 fn get_rebalancer_db_conn() -> Result<PgConnection, StatusError> {
     pg_db::connect_or_create_db(REBALANCER_DB).map_err(|e| {
@@ -151,6 +280,83 @@ fn get_evacaute_job_status(
     Ok(ret)
 }
 
+// The count of the objects of each of `statuses`, as counted by `query`, and
+// the total.
+fn get_object_status_counts(
+    uuid: &Uuid,
+    query: &str,
+    statuses: Vec<String>,
+) -> Result<HashMap<String, i64>, StatusError> {
+    let mut ret = HashMap::new();
+    let mut total_count: i64 = 0;
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    let status_counts: Vec<StatusCount> =
+        match sql_query(query).load::<StatusCount>(&conn) {
+            Ok(res) => res,
+            Err(e) => {
+                error!("Object status DB query: {}", e);
+                return Err(StatusError::LookupError);
+            }
+        };
+
+    for status_count in status_counts.iter() {
+        total_count += status_count.count;
+        ret.insert(to_title_case(&status_count.status), status_count.count);
+    }
+
+    for status_value in statuses {
+        ret.entry(to_title_case(&status_value)).or_insert(0);
+    }
+
+    ret.insert("Total".into(), total_count);
+
+    Ok(ret)
+}
+
+fn get_verify_job_status(
+    uuid: &Uuid,
+) -> Result<JobStatusResultsVerify, StatusError> {
+    get_object_status_counts(
+        uuid,
+        VERIFY_STATUS_COUNT_QUERY,
+        VerifyObjectStatus::iter().map(|s| s.to_string()).collect(),
+    )
+}
+
+fn get_rollback_job_status(
+    uuid: &Uuid,
+) -> Result<JobStatusResultsRollback, StatusError> {
+    get_object_status_counts(
+        uuid,
+        ROLLBACK_STATUS_COUNT_QUERY,
+        RollbackObjectStatus::iter()
+            .map(|s| s.to_string())
+            .collect(),
+    )
+}
+
+// A rollback job keeps the shark of the job that it rolls back in the same
+// config table as an evacuate job, and the rest of its configuration in its
+// rollback_config table.
+fn get_rollback_job_config(
+    uuid: &Uuid,
+) -> Result<JobConfigRollback, StatusError> {
+    let shark = get_evacuate_job_config(uuid)?.from_shark;
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    let config = rollback::get_rollback_config(&conn).map_err(|e| {
+        error!("Could not find rollback config ({}): {}", uuid, e);
+        StatusError::LookupError
+    })?;
+
+    Ok(JobConfigRollback {
+        job_id: config.job_id,
+        shark,
+        dest_shark: config.dest_shark,
+    })
+}
+
 fn get_evacuate_job_config(
     uuid: &Uuid,
 ) -> Result<JobConfigEvacuate, StatusError> {
@@ -173,17 +379,230 @@ fn get_evacuate_job_config(
             StatusError::Unknown
         })?;
 
-    Ok(JobConfigEvacuate { from_shark })
+    Ok(JobConfigEvacuate {
+        from_shark,
+        max_dest_utilization_percent: get_dest_limit(&conn),
+        schedule: schedule::get_schedule(&conn),
+        filter: filter::get_filter(&conn),
+        source: source::get_source(&conn),
+    })
+}
+
+// Jobs without a utilization ceiling have no entry, and jobs that were run
+// before ceilings were recorded have no table for them.
+fn get_dest_limit(conn: &PgConnection) -> Option<u32> {
+    use crate::jobs::evacuate::dest_limit_config::dsl::dest_limit_config;
+
+    dest_limit_config
+        .first::<DestLimitDbConfig>(conn)
+        .map(|c| c.max_dest_utilization_percent as u32)
+        .ok()
+}
+
+fn get_create_copy_job_config(
+    uuid: &Uuid,
+) -> Result<JobConfigCreateCopy, StatusError> {
+    use crate::jobs::evacuate::copy_config::dsl::copy_config as copy_table;
+
+    let evacuate_config = get_evacuate_job_config(uuid)?;
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    let config: CopyJobDbConfig = copy_table.first(&conn).map_err(|e| {
+        error!("Could not find copy config ({}): {}", uuid.to_string(), e);
+        StatusError::LookupError
+    })?;
+
+    Ok(JobConfigCreateCopy {
+        shark: evacuate_config.from_shark,
+        min_copies: config.min_copies.map(|n| n as u32),
+        max_dest_utilization_percent: evacuate_config
+            .max_dest_utilization_percent,
+        schedule: evacuate_config.schedule,
+        filter: evacuate_config.filter,
+        source: evacuate_config.source,
+    })
+}
+
+// A remove-copy job keeps its configuration in the same tables as a
+// create-copy job, and always has a minimum number of copies.
+fn get_remove_copy_job_config(
+    uuid: &Uuid,
+) -> Result<JobConfigRemoveCopy, StatusError> {
+    let config = get_create_copy_job_config(uuid)?;
+    let min_copies = config.min_copies.ok_or_else(|| {
+        error!("Remove-copy job {} has no min_copies", uuid.to_string());
+        StatusError::LookupError
+    })?;
+
+    Ok(JobConfigRemoveCopy {
+        shark: config.shark,
+        min_copies,
+        schedule: config.schedule,
+        filter: config.filter,
+        source: config.source,
+    })
+}
+
+fn phase_state(done: bool, started: bool) -> PhaseState {
+    if done {
+        PhaseState::Done
+    } else if started {
+        PhaseState::InProgress
+    } else {
+        PhaseState::Pending
+    }
+}
+
+// Work out how far a job in `state` has got from the number of objects of
+// each shard in each status and its scan checkpoints.  Every shard that a
+// job is to scan has a checkpoint from the start of the scan, but jobs that
+// do not scan (e.g. retry jobs) have none, and so only finish ingesting when
+// they finish altogether.
+fn build_job_progress(
+    state: &JobState,
+    counts: &[ShardStatusCount],
+    checkpoints: &[ScanCheckpoint],
+) -> JobProgress {
+    let mut shards: BTreeMap<i32, ShardProgress> = BTreeMap::new();
+
+    for checkpoint in checkpoints {
+        let shard = shards.entry(checkpoint.shard).or_insert(ShardProgress {
+            shard: checkpoint.shard,
+            ..Default::default()
+        });
+        shard.scanned = checkpoint.complete;
+    }
+
+    for count in counts {
+        let shard = shards.entry(count.shard).or_insert(ShardProgress {
+            shard: count.shard,
+            ..Default::default()
+        });
+        shard.objects += count.count;
+
+        match EvacuateObjectStatus::from_str(&count.status) {
+            Ok(EvacuateObjectStatus::Unprocessed)
+            | Ok(EvacuateObjectStatus::Assigned) => {
+                shard.copying += count.count
+            }
+            Ok(EvacuateObjectStatus::PostProcessing) => {
+                shard.metadata_update += count.count
+            }
+            Ok(EvacuateObjectStatus::Complete) => shard.complete += count.count,
+            Ok(EvacuateObjectStatus::Skipped) => shard.skipped += count.count,
+            Ok(EvacuateObjectStatus::Error) => shard.error += count.count,
+            Err(_) => warn!("Unknown object status: {}", count.status),
+        }
+    }
+
+    let shards: Vec<ShardProgress> =
+        shards.into_iter().map(|(_, s)| s).collect();
+    let sum = |f: fn(&ShardProgress) -> i64| shards.iter().map(f).sum::<i64>();
+    let objects = sum(|s| s.objects);
+    let copying_count = sum(|s| s.copying);
+    let updating_count = sum(|s| s.metadata_update);
+    let complete_count = sum(|s| s.complete);
+
+    let finished = *state == JobState::Complete
+        || *state == JobState::AwaitingConfirmation;
+    let shards_scanned = checkpoints.iter().filter(|c| c.complete).count();
+
+    let ingestion = IngestionProgress {
+        state: phase_state(
+            finished
+                || (!checkpoints.is_empty()
+                    && shards_scanned == checkpoints.len()),
+            objects > 0 || !checkpoints.is_empty(),
+        ),
+        shards_scanned,
+        shards: shards.len(),
+        objects,
+    };
+
+    let copying = PhaseProgress {
+        state: phase_state(
+            finished
+                || (ingestion.state == PhaseState::Done && copying_count == 0),
+            copying_count + updating_count + complete_count > 0,
+        ),
+        in_progress: copying_count,
+        done: updating_count + complete_count,
+    };
+
+    let metadata_update = PhaseProgress {
+        state: phase_state(
+            finished
+                || (copying.state == PhaseState::Done && updating_count == 0),
+            updating_count + complete_count > 0,
+        ),
+        in_progress: updating_count,
+        done: complete_count,
+    };
+
+    JobProgress {
+        phases: JobPhases {
+            ingestion,
+            copying,
+            metadata_update,
+        },
+        shards,
+        assignments: 0,
+    }
+}
+
+fn get_job_progress(
+    uuid: &Uuid,
+    state: &JobState,
+) -> Result<JobProgress, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    let counts: Vec<ShardStatusCount> = sql_query(SHARD_STATUS_COUNT_QUERY)
+        .load::<ShardStatusCount>(&conn)
+        .map_err(|e| {
+            error!("Shard status DB query: {}", e);
+            StatusError::LookupError
+        })?;
+
+    // Jobs run before scan checkpoints were recorded have none.
+    let checkpoints = evacuate::scan_checkpoint(&uuid.to_string())
+        .unwrap_or_else(|e| {
+            warn!("Could not get scan checkpoints of job {}: {}", uuid, e);
+            vec![]
+        });
+
+    let mut progress = build_job_progress(state, &counts, &checkpoints);
+
+    // The objects of an assignment are recorded as assigned as it is posted,
+    // and stay that way until the agent has finished with them.
+    progress.assignments = sql_query(OUTSTANDING_ASSIGNMENTS_QUERY)
+        .get_result::<AssignmentCount>(&conn)
+        .map(|c| c.count)
+        .unwrap_or_else(|e| {
+            warn!("Outstanding assignment query ({}): {}", uuid, e);
+            0
+        });
+
+    Ok(progress)
 }
 
 pub fn get_job_status(
     uuid: &Uuid,
     action: &JobActionDbEntry,
 ) -> Result<JobStatusResults, StatusError> {
+    // Create-copy and remove-copy jobs keep the same record of their objects
+    // as an evacuate job.
     match action {
-        JobActionDbEntry::Evacuate => {
+        JobActionDbEntry::Evacuate
+        | JobActionDbEntry::CreateCopy
+        | JobActionDbEntry::RemoveCopy => {
             Ok(JobStatusResults::Evacuate(get_evacaute_job_status(uuid)?))
         }
+        JobActionDbEntry::Verify => {
+            Ok(JobStatusResults::Verify(get_verify_job_status(uuid)?))
+        }
+        JobActionDbEntry::Rollback => {
+            Ok(JobStatusResults::Rollback(get_rollback_job_status(uuid)?))
+        }
         _ => unreachable!(),
     }
 }
@@ -196,6 +615,22 @@ fn get_job_config(
         JobActionDbEntry::Evacuate => {
             Ok(JobStatusConfig::Evacuate(get_evacuate_job_config(&uuid)?))
         }
+        JobActionDbEntry::CreateCopy => Ok(JobStatusConfig::CreateCopy(
+            get_create_copy_job_config(&uuid)?,
+        )),
+        JobActionDbEntry::RemoveCopy => Ok(JobStatusConfig::RemoveCopy(
+            get_remove_copy_job_config(&uuid)?,
+        )),
+        // A verify job keeps its shark in the same config table as an
+        // evacuate job.
+        JobActionDbEntry::Verify => {
+            Ok(JobStatusConfig::Verify(JobConfigVerify {
+                shark: get_evacuate_job_config(&uuid)?.from_shark,
+            }))
+        }
+        JobActionDbEntry::Rollback => {
+            Ok(JobStatusConfig::Rollback(get_rollback_job_config(&uuid)?))
+        }
         _ => unreachable!(),
     }
 }
@@ -204,20 +639,412 @@ pub fn get_job(uuid: Uuid) -> Result<JobStatus, StatusError> {
     let job_entry = get_job_db_entry(&uuid)?;
     let results = get_job_status(&uuid, &job_entry.action)?;
     let config = get_job_config(&uuid, &job_entry.action)?;
+    let confirmation = confirmation::get_confirmation(&job_entry.id)
+        .unwrap_or_else(|e| {
+            warn!("Could not get confirmation of job {}: {}", uuid, e);
+            None
+        });
+    let pause = if job_entry.state == JobState::Paused {
+        breaker::get_pause(&job_entry.id).unwrap_or_else(|e| {
+            warn!("Could not get pause of job {}: {}", uuid, e);
+            None
+        })
+    } else {
+        None
+    };
+    let metrics = snapshot::get_snapshot(&job_entry.id).unwrap_or_else(|e| {
+        warn!("Could not get metrics of job {}: {}", uuid, e);
+        None
+    });
+    let updates = tuning::get_updates(&job_entry.id).unwrap_or_else(|e| {
+        warn!("Could not get updates of job {}: {}", uuid, e);
+        vec![]
+    });
+    let progress = match job_entry.action {
+        JobActionDbEntry::Evacuate
+        | JobActionDbEntry::CreateCopy
+        | JobActionDbEntry::RemoveCopy => {
+            get_job_progress(&uuid, &job_entry.state)
+                .map(Some)
+                .unwrap_or_else(|e| {
+                    warn!("Could not get progress of job {}: {:?}", uuid, e);
+                    None
+                })
+        }
+        _ => None,
+    };
+    let checksums = match job_entry.action {
+        JobActionDbEntry::Evacuate | JobActionDbEntry::CreateCopy => {
+            get_checksum_summary(&uuid)
+        }
+        _ => None,
+    };
+    let no_fit = match job_entry.action {
+        JobActionDbEntry::Evacuate | JobActionDbEntry::CreateCopy => {
+            get_no_fit_summary(&uuid)
+        }
+        _ => None,
+    };
+    let schedule = get_schedule_status(&config, &job_entry.state);
 
     // get job config
     Ok(JobStatus {
         results,
         config,
         state: job_entry.state,
+        queue_position: None,
+        confirmation,
+        pause,
+        metrics,
+        updates,
+        progress,
+        checksums,
+        no_fit,
+        schedule,
+        owner: lease::owner(&job_entry.id),
+    })
+}
+
+// Where a job that has yet to finish is in its daily window, if it was given
+// one.
+fn get_schedule_status(
+    config: &JobStatusConfig,
+    state: &JobState,
+) -> Option<JobScheduleStatus> {
+    match state {
+        JobState::Init
+        | JobState::Setup
+        | JobState::Queued
+        | JobState::Running => (),
+        _ => return None,
+    }
+
+    let schedule = match config {
+        JobStatusConfig::Evacuate(c) => c.schedule.as_ref(),
+        JobStatusConfig::CreateCopy(c) => c.schedule.as_ref(),
+        JobStatusConfig::RemoveCopy(c) => c.schedule.as_ref(),
+        _ => None,
+    }?;
+
+    ScheduleWindow::new(schedule)
+        .map(|window| schedule::current_status(&window))
+        .ok()
+}
+
+// The checksum summary of a job, if it found any objects with a missing or
+// malformed checksum.  As with slow tasks, jobs that were run before
+// checksums were checked have no table for it.
+fn get_checksum_summary(uuid: &Uuid) -> Option<ChecksumSummary> {
+    let conn = get_job_db_conn_common(&uuid).ok()?;
+
+    match checksum::get_checksum_summary(&conn) {
+        Ok(summary) if summary != ChecksumSummary::default() => Some(summary),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("Checksum summary query ({}): {}", uuid, e);
+            None
+        }
+    }
+}
+
+// The summary of the objects of a job that fit on no destination, if there
+// were any.
+fn get_no_fit_summary(uuid: &Uuid) -> Option<NoFitSummary> {
+    let conn = get_job_db_conn_common(&uuid).ok()?;
+
+    nofit::get_no_fit_summary(&conn).unwrap_or_else(|e| {
+        debug!("No-fit summary query ({}): {}", uuid, e);
+        None
+    })
+}
+
+/// Returns true if `reason` names a reason that an object can be skipped for,
+/// in the form used by `get_skipped_objects()`.
+pub fn is_skipped_reason(reason: &str) -> bool {
+    evacuate::build_skipped_strings()
+        .iter()
+        .any(|r| r.as_str() == reason)
+}
+
+/// Returns up to `limit` of the objects that a job skipped, ordered by object
+/// id, starting `offset` objects in.  If `reason` is given, only objects that
+/// were skipped for that reason are returned.
+pub fn get_skipped_objects(
+    uuid: &Uuid,
+    reason: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SkippedObject>, StatusError> {
+    use crate::jobs::evacuate::evacuateobjects::dsl::{
+        evacuateobjects, id, skipped_reason, status,
+    };
+
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    let mut query = evacuateobjects
+        .filter(status.eq(EvacuateObjectStatus::Skipped))
+        .order(id)
+        .limit(limit)
+        .offset(offset)
+        .into_boxed();
+
+    if let Some(r) = reason {
+        query = query.filter(skipped_reason.eq(r.to_string()));
+    }
+
+    let objects = query.load::<EvacuateObject>(&conn).map_err(|e| {
+        error!("Skipped objects query ({}): {}", uuid, e);
+        StatusError::LookupError
+    })?;
+
+    let attempts = get_download_attempts(&conn, &objects);
+
+    Ok(objects
+        .into_iter()
+        .map(|eobj| {
+            let download = attempts.get(&eobj.id).copied();
+            SkippedObject {
+                download,
+                ..SkippedObject::from(eobj)
+            }
+        })
+        .collect())
+}
+
+// Jobs that were run before download attempts were recorded have no table
+// for them, and their skipped objects are reported without them.
+fn get_download_attempts(
+    conn: &PgConnection,
+    objects: &[EvacuateObject],
+) -> HashMap<String, DownloadAttempts> {
+    use crate::jobs::evacuate::download_attempts::dsl::{
+        download_attempts, id,
+    };
+
+    let ids: Vec<&str> = objects.iter().map(|o| o.id.as_str()).collect();
+
+    match download_attempts
+        .filter(id.eq_any(ids))
+        .load::<DownloadAttemptsEntry>(conn)
+    {
+        Ok(entries) => entries
+            .into_iter()
+            .map(|e| {
+                let attempts = DownloadAttempts {
+                    attempts: e.attempts as u32,
+                    elapsed_ms: e.elapsed_ms as u64,
+                };
+                (e.id, attempts)
+            })
+            .collect(),
+        Err(e) => {
+            debug!("Download attempts query: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Returns up to `limit` of the objects that agents were slow to move for a
+/// job, slowest first, starting `offset` objects in.
+pub fn get_slow_objects(
+    uuid: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<SlowTaskEntry>, StatusError> {
+    use crate::jobs::evacuate::slow_tasks::dsl::{elapsed_ms, id, slow_tasks};
+
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    // As with download attempts, jobs that were run before slow tasks were
+    // recorded have no table for them.
+    match slow_tasks
+        .order((elapsed_ms.desc(), id))
+        .limit(limit)
+        .offset(offset)
+        .load::<SlowTaskEntry>(&conn)
+    {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            debug!("Slow objects query ({}): {}", uuid, e);
+            Ok(vec![])
+        }
+    }
+}
+
+/// Returns up to `limit` of the objects whose copies a job found served with
+/// custom headers that differ from their metadata, ordered by object id,
+/// starting `offset` objects in.
+pub fn get_header_mismatches(
+    uuid: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<HeaderMismatchEntry>, StatusError> {
+    use crate::jobs::evacuate::header_mismatches::dsl::{
+        header_mismatches, id,
+    };
+
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    // As with slow tasks, jobs that were run before headers were checked
+    // have no table for them.
+    match header_mismatches
+        .order(id)
+        .limit(limit)
+        .offset(offset)
+        .load::<HeaderMismatchEntry>(&conn)
+    {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            debug!("Header mismatches query ({}): {}", uuid, e);
+            Ok(vec![])
+        }
+    }
+}
+
+/// Returns up to `limit` of the changes that a job made to object metadata,
+/// oldest first, starting `offset` changes in.
+pub fn get_metadata_audit(
+    uuid: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<MetadataAuditEntry>, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    // As with slow tasks, jobs that were run before metadata changes were
+    // audited (and verify jobs, which make none) have no table for them.
+    match evacuate::metadata_audit(&conn, limit, offset) {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            debug!("Metadata audit query ({}): {}", uuid, e);
+            Ok(vec![])
+        }
+    }
+}
+
+/// Returns up to `limit` of the decisions in a job's placement trace, oldest
+/// first, starting `offset` decisions in.  If `object` is given only the
+/// decisions about that object are returned.
+pub fn get_placement_trace(
+    uuid: &Uuid,
+    object: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PlacementTraceEntry>, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    // As with slow tasks, jobs that were run before placement was traced
+    // have no table for it.
+    match placement::get_placement_trace(&conn, object, limit, offset) {
+        Ok(entries) => Ok(entries),
+        Err(e) => {
+            debug!("Placement trace query ({}): {}", uuid, e);
+            Ok(vec![])
+        }
+    }
+}
+
+/// The number of objects that a job skipped, in total and for each reason.
+pub fn get_skipped_summary(uuid: &Uuid) -> Result<SkippedSummary, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    // See get_evacaute_job_status() regarding GROUP BY.
+    let skipped_counts: Vec<SkippedCount> = sql_query(SKIPPED_COUNT_QUERY)
+        .load::<SkippedCount>(&conn)
+        .map_err(|e| {
+            error!("Skipped count query ({}): {}", uuid, e);
+            StatusError::LookupError
+        })?;
+
+    let mut summary = SkippedSummary {
+        total: 0,
+        reasons: HashMap::new(),
+        errors: HashMap::new(),
+    };
+
+    for skipped_count in skipped_counts.into_iter() {
+        let reason = skipped_count
+            .skipped_reason
+            .unwrap_or_else(|| String::from("unknown"));
+        let error = ObjectSkippedReason::from_reason_string(&reason)
+            .map_or(RebalancerError::Unknown, |r| r.rebalancer_error());
+
+        summary.total += skipped_count.count;
+        *summary.reasons.entry(reason).or_insert(0) += skipped_count.count;
+        *summary.errors.entry(error).or_insert(0) += skipped_count.count;
+    }
+
+    Ok(summary)
+}
+
+/// Get everything known about one of a job's assignments, or None if the job
+/// has no such assignment.
+pub fn get_assignment(
+    uuid: &Uuid,
+    assignment_uuid: &Uuid,
+) -> Result<Option<AssignmentLifecycle>, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    evacuate::assignment_lifecycle(&conn, &assignment_uuid.to_string()).map_err(
+        |e| {
+            error!("Assignment lookup ({}/{}): {}", uuid, assignment_uuid, e);
+            StatusError::LookupError
+        },
+    )
+}
+
+/// Summarize up to `limit` of a job's assignments, starting `offset`
+/// assignments in.
+pub fn get_assignments(
+    uuid: &Uuid,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<AssignmentSummary>, StatusError> {
+    let conn = get_job_db_conn_common(&uuid)?;
+
+    evacuate::assignment_summaries(&conn, limit, offset).map_err(|e| {
+        error!("Assignment list ({}): {}", uuid, e);
+        StatusError::LookupError
     })
 }
 
-pub fn list_jobs() -> Result<Vec<JobDbEntry>, StatusError> {
-    use crate::jobs::jobs::dsl::jobs as jobs_db;
+pub fn list_jobs(
+    filter: &JobListFilter,
+) -> Result<Vec<JobDbEntry>, StatusError> {
+    use crate::jobs::jobs::dsl::{action, created, id, jobs as jobs_db, state};
 
     let conn = get_rebalancer_db_conn()?;
-    let job_list = match jobs_db.load::<JobDbEntry>(&conn) {
+    let mut query = jobs_db.into_boxed();
+
+    if let Some(s) = &filter.state {
+        query = query.filter(state.eq(s.to_string()));
+    }
+
+    if let Some(a) = &filter.action {
+        query = query.filter(action.eq(a.to_string()));
+    }
+
+    if let Some(after) = filter.created_after {
+        query = query.filter(created.ge(after));
+    }
+
+    if let Some(before) = filter.created_before {
+        query = query.filter(created.le(before));
+    }
+
+    query = match filter.order {
+        JobListOrder::Asc => query.order((created.asc(), id.asc())),
+        JobListOrder::Desc => query.order((created.desc(), id.desc())),
+    };
+
+    if let Some(limit) = filter.limit {
+        query = query.limit(limit);
+    }
+
+    if let Some(offset) = filter.offset {
+        query = query.offset(offset);
+    }
+
+    let job_list = match query.load::<JobDbEntry>(&conn) {
         Ok(list) => list,
         Err(e) => {
             error!("Error listing jobs: {}", e);
@@ -240,9 +1067,141 @@ mod tests {
 
     static NUM_OBJS: i64 = 200;
 
+    fn shard_count(shard: i32, status: &str, count: i64) -> ShardStatusCount {
+        ShardStatusCount {
+            shard,
+            status: status.to_string(),
+            count,
+        }
+    }
+
+    fn checkpoint(shard: i32, complete: bool) -> ScanCheckpoint {
+        ScanCheckpoint {
+            shard,
+            objects_scanned: 0,
+            last_object_id: String::new(),
+            complete,
+        }
+    }
+
+    #[test]
+    fn job_progress_test() {
+        let counts = vec![
+            shard_count(1, "complete", 10),
+            shard_count(1, "post_processing", 2),
+            shard_count(2, "assigned", 5),
+            shard_count(2, "skipped", 1),
+        ];
+        let checkpoints = vec![
+            checkpoint(1, true),
+            checkpoint(2, false),
+            checkpoint(3, false),
+        ];
+
+        let progress =
+            build_job_progress(&JobState::Running, &counts, &checkpoints);
+        let phases = &progress.phases;
+        assert_eq!(phases.ingestion.state, PhaseState::InProgress);
+        assert_eq!(phases.ingestion.shards_scanned, 1);
+        assert_eq!(phases.ingestion.shards, 3);
+        assert_eq!(phases.ingestion.objects, 18);
+        assert_eq!(phases.copying.state, PhaseState::InProgress);
+        assert_eq!(phases.copying.in_progress, 5);
+        assert_eq!(phases.copying.done, 12);
+        assert_eq!(phases.metadata_update.state, PhaseState::InProgress);
+        assert_eq!(phases.metadata_update.in_progress, 2);
+        assert_eq!(phases.metadata_update.done, 10);
+
+        assert_eq!(progress.shards.len(), 3);
+        assert_eq!(
+            progress.shards[1],
+            ShardProgress {
+                shard: 2,
+                scanned: false,
+                objects: 6,
+                copying: 5,
+                skipped: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(progress.shards[2].objects, 0);
+
+        // Once every shard is scanned and every object copied, only the
+        // metadata updates are left.
+        let counts = vec![
+            shard_count(1, "complete", 10),
+            shard_count(1, "post_processing", 2),
+        ];
+        let checkpoints = vec![checkpoint(1, true), checkpoint(2, true)];
+        let progress =
+            build_job_progress(&JobState::Running, &counts, &checkpoints);
+        let phases = &progress.phases;
+        assert_eq!(phases.ingestion.state, PhaseState::Done);
+        assert_eq!(phases.copying.state, PhaseState::Done);
+        assert_eq!(phases.metadata_update.state, PhaseState::InProgress);
+
+        // Nothing has started before the scan has.
+        let progress = build_job_progress(&JobState::Setup, &[], &[]);
+        assert_eq!(progress.phases.ingestion.state, PhaseState::Pending);
+        assert_eq!(progress.phases.copying.state, PhaseState::Pending);
+        assert_eq!(progress.phases.metadata_update.state, PhaseState::Pending);
+
+        // A job that does not scan is done ingesting when it is done.
+        let counts = vec![shard_count(4, "complete", 3)];
+        let progress = build_job_progress(&JobState::Complete, &counts, &[]);
+        assert_eq!(progress.phases.ingestion.state, PhaseState::Done);
+        assert_eq!(progress.phases.metadata_update.state, PhaseState::Done);
+    }
+
     #[test]
     fn list_job_test() {
-        assert!(list_jobs().is_ok());
+        assert!(list_jobs(&JobListFilter::default()).is_ok());
+    }
+
+    #[test]
+    fn list_job_filter_test() {
+        let _guard = util::init_global_logger(None);
+
+        let mut ids = vec![];
+        for _ in 0..3 {
+            let job = JobBuilder::new(Config::default())
+                .evacuate("fake_shark".to_string(), Some(1))
+                .commit()
+                .expect("job builder");
+            ids.push(job.get_id().to_string());
+        }
+
+        let filter = JobListFilter {
+            state: Some(JobState::Setup),
+            action: Some(JobActionDbEntry::Evacuate),
+            order: JobListOrder::Desc,
+            ..Default::default()
+        };
+        let newest_first = list_jobs(&filter).expect("list jobs");
+        assert!(newest_first.iter().all(|j| j.state == JobState::Setup));
+        assert!(newest_first
+            .windows(2)
+            .all(|pair| pair[0].created >= pair[1].created));
+
+        let created_after = newest_first
+            .iter()
+            .find(|j| j.id == ids[0])
+            .expect("first job")
+            .created;
+        let filter = JobListFilter {
+            created_after: Some(created_after),
+            ..Default::default()
+        };
+        let since_first = list_jobs(&filter).expect("list jobs");
+        assert!(ids.iter().all(|i| since_first.iter().any(|j| &j.id == i)));
+        assert!(since_first.iter().all(|j| j.created >= created_after));
+
+        let filter = JobListFilter {
+            limit: Some(1),
+            offset: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(list_jobs(&filter).expect("list jobs").len(), 1);
     }
 
     #[test]
@@ -282,7 +1241,10 @@ mod tests {
             .expect("diesel insert");
 
         let job_status = get_job(job_id).expect("get job status");
-        let JobStatusResults::Evacuate(evac_job_results) = job_status.results;
+        let evac_job_results = match job_status.results {
+            JobStatusResults::Evacuate(results) => results,
+            _ => panic!("not an evacuate job"),
+        };
         let count = *evac_job_results.get("Total").expect("Total count");
 
         assert_eq!(count, NUM_OBJS);
@@ -321,7 +1283,10 @@ mod tests {
             .expect("diesel insert");
 
         let job_status = get_job(job_id).expect("get job status");
-        let JobStatusResults::Evacuate(evac_job_results) = job_status.results;
+        let evac_job_results = match job_status.results {
+            JobStatusResults::Evacuate(results) => results,
+            _ => panic!("not an evacuate job"),
+        };
         let total_count = *evac_job_results.get("Total").expect("Total count");
         let post_processing_count = *evac_job_results
             .get("Post Processing")
@@ -330,4 +1295,77 @@ mod tests {
         assert_eq!(total_count, NUM_OBJS);
         assert_eq!(post_processing_count, 0);
     }
+
+    #[test]
+    fn get_skipped_test() {
+        use crate::jobs::evacuate::evacuateobjects::dsl::*;
+        use rebalancer::common::ObjectSkippedReason;
+
+        let _guard = util::init_global_logger(None);
+        let mut g = StdThreadGen::new(10);
+        let mut obj_vec = vec![];
+
+        let config = Config::default();
+        let job_builder = JobBuilder::new(config);
+        let job = job_builder
+            .evacuate("fake_shark".to_string(), Some(NUM_OBJS as u32))
+            .commit()
+            .expect("job builder");
+
+        let job_id = job.get_id();
+        let conn = pg_db::connect_db(&job_id.to_string()).expect("db connect");
+
+        for i in 0..NUM_OBJS {
+            let mut obj = EvacuateObject::arbitrary(&mut g);
+            obj.status = EvacuateObjectStatus::Skipped;
+            obj.skipped_reason = if i % 4 == 0 {
+                Some(ObjectSkippedReason::HTTPStatusCode(404))
+            } else {
+                Some(ObjectSkippedReason::DestinationUnreachable)
+            };
+            obj_vec.push(obj);
+        }
+
+        diesel::insert_into(evacuateobjects)
+            .values(obj_vec.clone())
+            .execute(&conn)
+            .expect("diesel insert");
+
+        let summary = get_skipped_summary(&job_id).expect("skipped summary");
+        assert_eq!(summary.total, NUM_OBJS);
+        assert_eq!(summary.reasons["{http_status_code:404}"], NUM_OBJS / 4);
+        assert_eq!(
+            summary.reasons["destination_unreachable"],
+            NUM_OBJS - NUM_OBJS / 4
+        );
+        assert_eq!(
+            summary.errors[&RebalancerError::ObjectMissing],
+            NUM_OBJS / 4
+        );
+        assert_eq!(
+            summary.errors[&RebalancerError::Unreachable],
+            NUM_OBJS - NUM_OBJS / 4
+        );
+
+        let reason = "{http_status_code:404}";
+        assert!(is_skipped_reason(reason));
+        assert!(!is_skipped_reason("not_a_reason"));
+
+        let first = get_skipped_objects(&job_id, Some(reason), 10, 0)
+            .expect("skipped objects");
+        let rest = get_skipped_objects(&job_id, Some(reason), NUM_OBJS, 10)
+            .expect("skipped objects");
+        assert_eq!(first.len(), 10);
+        assert_eq!(first.len() + rest.len(), (NUM_OBJS / 4) as usize);
+        assert!(first.last().unwrap().id < rest.first().unwrap().id);
+        let expected = Some(reason.to_string());
+        assert!(rest.iter().all(|o| o.skipped_reason == expected));
+        assert!(rest
+            .iter()
+            .all(|o| o.error == Some(RebalancerError::ObjectMissing)));
+
+        let all = get_skipped_objects(&job_id, None, NUM_OBJS, 0)
+            .expect("skipped objects");
+        assert_eq!(all.len(), NUM_OBJS as usize);
+    }
 }
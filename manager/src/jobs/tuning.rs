@@ -0,0 +1,246 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Changing how hard a running job works.
+//
+// A job takes its limits from the manager's configuration when it is created,
+// and getting it to work harder or more gently than that used to mean
+// stopping it and creating another.  Instead, some of those limits are kept
+// as JobTunables, which the job's threads look up as they go rather than once
+// when they start, and which PUT /jobs/<uuid> changes (see
+// evacuate::EvacuateJobUpdateMessage):
+//
+//  * max_sharks: how many destination sharks the job has assignments going to
+//    at once, and so how many assignments it has outstanding.
+//  * max_tasks_per_assignment: how many tasks the job puts in each
+//    assignment.  With adaptive assignment sizing, this is only where a
+//    destination's assignments start out (see the sizing module).
+//  * max_metadata_update_threads: how many threads update the metadata of
+//    the objects that have been moved.
+//  * The thresholds of the job's circuit breaker (see the breaker module).
+//
+// The job applies each change as it gets to it, for instance a new
+// assignment size from the next assignment it starts.  Each change, with the
+// value that it replaced, is recorded in the job_updates table of the
+// rebalancer database and reported as the `updates` of the job's status.
+
+use super::REBALANCER_DB;
+use crate::config::ConfigOptions;
+use crate::pg_db;
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+pub use rebalancer_client::status::JobUpdate;
+
+use std::sync::Mutex;
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+table! {
+    use diesel::sql_types::{BigInt, Text};
+    job_updates (job_id, timestamp, parameter) {
+        job_id -> Text,
+        parameter -> Text,
+        old_value -> Text,
+        new_value -> Text,
+        timestamp -> BigInt,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JobTunables {
+    pub max_sharks: usize,
+    pub max_tasks_per_assignment: usize,
+    pub max_metadata_update_threads: usize,
+}
+
+impl JobTunables {
+    pub fn new(options: &ConfigOptions) -> JobTunables {
+        JobTunables {
+            max_sharks: options.max_sharks,
+            max_tasks_per_assignment: options.max_tasks_per_assignment,
+            max_metadata_update_threads: options.max_metadata_update_threads,
+        }
+    }
+}
+
+/// The tunables of a running job, shared by its threads.
+pub struct Tunables {
+    current: Mutex<JobTunables>,
+}
+
+impl Tunables {
+    pub fn new(options: &ConfigOptions) -> Tunables {
+        Tunables {
+            current: Mutex::new(JobTunables::new(options)),
+        }
+    }
+
+    pub fn get(&self) -> JobTunables {
+        *self.current.lock().expect("tunables lock")
+    }
+
+    /// Change the tunables with `f`, returning what they were before.
+    pub fn update<F>(&self, f: F) -> JobTunables
+    where
+        F: FnOnce(&mut JobTunables),
+    {
+        let mut current = self.current.lock().expect("tunables lock");
+        let old = *current;
+        f(&mut current);
+        old
+    }
+}
+
+/// A change made to a running job.
+#[derive(Debug, Deserialize, Serialize, Insertable, Queryable, PartialEq)]
+#[table_name = "job_updates"]
+pub struct JobUpdateRecord {
+    pub job_id: String,
+    pub parameter: String,
+
+    // The values before and after the change, as JSON.
+    pub old_value: String,
+    pub new_value: String,
+
+    // Milliseconds since the epoch at which the change was made.
+    pub timestamp: i64,
+}
+
+impl From<JobUpdateRecord> for JobUpdate {
+    fn from(record: JobUpdateRecord) -> JobUpdate {
+        JobUpdate {
+            old_value: serde_json::from_str(&record.old_value)
+                .unwrap_or(Value::Null),
+            new_value: serde_json::from_str(&record.new_value)
+                .unwrap_or(Value::Null),
+            parameter: record.parameter,
+            timestamp: record.timestamp,
+        }
+    }
+}
+
+pub fn create_updates_table(conn: &PgConnection) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS job_updates(
+            job_id TEXT NOT NULL,
+            parameter TEXT NOT NULL,
+            old_value TEXT NOT NULL,
+            new_value TEXT NOT NULL,
+            timestamp BIGINT NOT NULL,
+            PRIMARY KEY (job_id, timestamp, parameter)
+        );",
+    )
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+/// Record that `parameter` of the job `job_id` was changed from `old` to
+/// `new`.
+pub fn record_update<T: Serialize>(
+    job_id: &str,
+    parameter: &str,
+    old: &T,
+    new: &T,
+) -> Result<(), Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+    let record = JobUpdateRecord {
+        job_id: job_id.to_string(),
+        parameter: parameter.to_string(),
+        old_value: serde_json::to_string(old)?,
+        new_value: serde_json::to_string(new)?,
+        timestamp: now_ms(),
+    };
+
+    diesel::insert_into(job_updates::table)
+        .values(&record)
+        .on_conflict_do_nothing()
+        .execute(&conn)
+        .map(|_| ())
+        .map_err(Error::from)
+}
+
+/// The changes made to the job `job_id` while it ran, oldest first.
+pub fn get_updates(job_id: &str) -> Result<Vec<JobUpdate>, Error> {
+    let conn = pg_db::connect_or_create_db(REBALANCER_DB)?;
+
+    let records: Vec<JobUpdateRecord> = job_updates::table
+        .filter(job_updates::job_id.eq(job_id))
+        .order((job_updates::timestamp, job_updates::parameter))
+        .load(&conn)
+        .map_err(Error::from)?;
+
+    Ok(records.into_iter().map(JobUpdate::from).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn tunables_update() {
+        let config = Config::default();
+        let tunables = Tunables::new(&config.options);
+        let initial = tunables.get();
+
+        assert_eq!(initial.max_sharks, config.options.max_sharks);
+
+        let old = tunables.update(|t| t.max_sharks = initial.max_sharks + 1);
+        assert_eq!(old, initial);
+        assert_eq!(tunables.get().max_sharks, initial.max_sharks + 1);
+        assert_eq!(
+            tunables.get().max_tasks_per_assignment,
+            initial.max_tasks_per_assignment
+        );
+    }
+
+    #[test]
+    fn update_validation() {
+        use crate::jobs::evacuate::{
+            CircuitBreakerUpdate, EvacuateJobUpdateMessage as Msg,
+        };
+
+        assert!(Msg::SetMaxSharks(10).validate().is_ok());
+        assert!(Msg::SetMaxSharks(0).validate().is_err());
+        assert!(Msg::SetMaxTasksPerAssignment(1_000_000).validate().is_err());
+        assert!(Msg::SetCircuitBreaker(CircuitBreakerUpdate::default())
+            .validate()
+            .is_err());
+        assert!(Msg::SetCircuitBreaker(CircuitBreakerUpdate {
+            max_error_rate: Some(1.5),
+            ..Default::default()
+        })
+        .validate()
+        .is_err());
+        assert!(Msg::SetCircuitBreaker(CircuitBreakerUpdate {
+            min_objects: Some(10),
+            ..Default::default()
+        })
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn update_from_record() {
+        let record = JobUpdateRecord {
+            job_id: String::from("job"),
+            parameter: String::from("circuit_breaker"),
+            old_value: String::from(r#"{"min_objects":1000}"#),
+            new_value: String::from("not json"),
+            timestamp: 1,
+        };
+
+        let update = JobUpdate::from(record);
+        assert_eq!(update.old_value, serde_json::json!({"min_objects": 1000}));
+        assert_eq!(update.new_value, Value::Null);
+    }
+}
@@ -0,0 +1,814 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Checking that a shark holds the objects it should, without agents.
+//
+// Evacuate and create-copy jobs can only be run where every storage node has
+// an agent to hand assignments to.  Before agents have been deployed
+// everywhere it is still useful to know whether a storage node actually holds
+// what the metadata tier says it does, for instance ahead of an evacuation, or
+// after a storage node has been restored.  A verify job finds the objects on
+// a shark just as an evacuate job does, but rather than moving them it asks
+// the shark's own HTTP front door about each one (with a HEAD request, or a
+// GET of which only the headers are read, according to
+// `verification.method`).  Nothing is written anywhere other than the job's
+// own database, so a verify job can be run at any time, and does not need
+// confirmation.
+//
+// Each object ends up as one of:
+//
+//  * present: the shark has the object, with the size the metadata gives it.
+//  * missing: the shark responded with a 404.
+//  * size_mismatch: the shark has the object, but its Content-Length is not
+//    the size that the metadata gives it.
+//  * unverifiable: the shark could not be reached, or responded with
+//    anything other than a 200 or a 404.
+//
+// The count of each is reported with the job's status, and in the
+// `verify_object_count` metric.  Objects that are not present are also
+// logged, and can be found in the job's verifyobjects table.
+//
+// A manager with `verification.agentless` set accepts verify jobs only, so
+// that it can be deployed for audits where no agents are yet running.
+//
+// The same requests are used by evacuate and create-copy jobs to check the
+// copies that they make: before the metadata is updated (see
+// `options.verify_before_update`), and, for a sample of objects, afterwards
+// to compare the custom headers that a copy is served with against those in
+// the object's metadata (see `options.header_check_percent` and
+// check_headers()).
+
+use crate::config::{Config, VerifyMethod};
+use crate::joblog;
+use crate::jobs::evacuate;
+use crate::jobs::record::{self, RecordDisposition};
+use crate::jobs::watchdog::{self, spawn_supervised};
+use crate::metrics::metrics_verify_object_inc;
+use crate::moray_client;
+use crate::pg_db;
+use crate::shutdown;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as crossbeam;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use libmanta::moray::MantaObjectShark;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sharkspotter::SharkspotterMessage;
+use strum::IntoEnumIterator;
+
+table! {
+    use diesel::sql_types::{Integer, Nullable, Text};
+    verifyobjects (id) {
+        id -> Text,
+        shard -> Integer,
+        owner -> Text,
+        key -> Text,
+        status -> Text,
+        detail -> Nullable<Text>,
+    }
+}
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Display,
+    EnumIter,
+    EnumString,
+    EnumVariantNames,
+    Eq,
+    Hash,
+    PartialEq,
+)]
+#[strum(serialize_all = "snake_case")]
+pub enum VerifyObjectStatus {
+    Present,
+    Missing,
+    SizeMismatch,
+    Unverifiable,
+}
+
+#[derive(Debug, Insertable, Queryable)]
+#[table_name = "verifyobjects"]
+pub struct VerifyObjectEntry {
+    pub id: String,
+    pub shard: i32,
+    pub owner: String,
+    pub key: String,
+
+    // One of VerifyObjectStatus.
+    pub status: String,
+
+    // Why the object is not present, if it is not.
+    pub detail: Option<String>,
+}
+
+// The fields of a metadata record that are needed to ask a shark about an
+// object.
+#[derive(Deserialize)]
+struct ObjectRecord {
+    key: String,
+    owner: String,
+
+    #[serde(alias = "contentLength", default)]
+    content_length: u64,
+
+    #[serde(alias = "objectId")]
+    object_id: String,
+
+    #[serde(default)]
+    headers: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct VerifyObject {
+    id: String,
+    shard: i32,
+    owner: String,
+    key: String,
+    content_length: u64,
+
+    // The custom (`m-`) headers in the object's metadata, by lower case
+    // name.
+    custom_headers: BTreeMap<String, String>,
+}
+
+/// A custom header that the front door of a shark does not serve an object
+/// with as the object's metadata has it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HeaderMismatch {
+    pub header: String,
+
+    // None if the metadata does not have the header.
+    pub expected: Option<String>,
+
+    // None if the shark did not serve the header.
+    pub actual: Option<String>,
+}
+
+fn is_custom_header(name: &str) -> bool {
+    name.starts_with("m-")
+}
+
+impl VerifyObject {
+    pub fn from_record(shard: u32, record: &Value) -> Result<Self, String> {
+        let record: ObjectRecord = serde_json::from_value(record.clone())
+            .map_err(|e| format!("malformed object record: {}", e))?;
+
+        if shard > std::i32::MAX as u32 {
+            return Err(format!("bad shard number {}", shard));
+        }
+
+        let custom_headers = record
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    v => v.to_string(),
+                };
+                (name.to_lowercase(), value)
+            })
+            .filter(|(name, _)| is_custom_header(name))
+            .collect();
+
+        Ok(VerifyObject {
+            id: record.object_id,
+            shard: shard as i32,
+            owner: record.owner,
+            key: record.key,
+            content_length: record.content_length,
+            custom_headers,
+        })
+    }
+}
+
+pub struct VerifyJob {
+    pub shark: MantaObjectShark,
+    config: Config,
+    db_name: String,
+    conn: Mutex<PgConnection>,
+    client: Client,
+    max_objects: Option<u32>,
+
+    // Set once the job has found max_objects objects.
+    limit_reached: AtomicBool,
+
+    counts: Mutex<HashMap<VerifyObjectStatus, u64>>,
+}
+
+impl VerifyJob {
+    pub fn new(
+        storage_id: String,
+        config: &Config,
+        db_name: &str,
+        max_objects: Option<u32>,
+    ) -> Result<Self, Error> {
+        let conn = pg_db::create_and_connect_db(db_name)?;
+
+        // The shark is kept in the same config table as an evacuate job's,
+        // so that the job's status can report it in the same way.
+        evacuate::create_config_table(&conn)?;
+        create_verifyobjects_table(&conn)?;
+
+        let shark = MantaObjectShark {
+            manta_storage_id: storage_id,
+            ..Default::default()
+        };
+        evacuate::update_evacuate_config_impl(&conn, &shark)?;
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.verification.timeout_secs))
+            .build()?;
+
+        Ok(VerifyJob {
+            shark,
+            config: config.to_owned(),
+            db_name: db_name.to_string(),
+            conn: Mutex::new(conn),
+            client,
+            max_objects,
+            limit_reached: AtomicBool::new(false),
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns true once the job should stop looking for more objects.
+    fn stopping(&self) -> bool {
+        shutdown::requested() || self.limit_reached.load(Ordering::SeqCst)
+    }
+
+    pub fn run(mut self) -> Result<(), Error> {
+        // Fill in the shark's datacenter.
+        self.shark = moray_client::get_manta_object_shark(
+            &self.shark.manta_storage_id,
+            &self.config.domain_name,
+        )?;
+
+        {
+            let conn = self.conn.lock().expect("DB conn lock");
+            evacuate::update_evacuate_config_impl(&conn, &self.shark)?;
+        }
+
+        let threads = self.config.verification.threads.max(1);
+        let job = Arc::new(self);
+        let (obj_tx, obj_rx) = crossbeam::bounded(threads * 10);
+
+        let scanner = start_scanner(Arc::clone(&job), obj_tx)?;
+
+        let mut checkers = vec![];
+        for i in 0..threads {
+            let checker_job = Arc::clone(&job);
+            let checker_rx = obj_rx.clone();
+            let drained = watchdog::drained(&checker_rx);
+            checkers.push(spawn_supervised(
+                &job.db_name,
+                &format!("verify_checker_{}", i),
+                move || drained() || shutdown::requested(),
+                move || checker_job.check_objects(checker_rx),
+            )?);
+        }
+        drop(obj_rx);
+
+        let mut ret = Ok(());
+
+        scanner
+            .join()
+            .expect("Verify Scanner Thread")
+            .unwrap_or_else(|e| {
+                error!("Error joining verify scanner: {}", e);
+                evacuate::set_run_error(&mut ret, e);
+            });
+
+        for checker in checkers {
+            checker
+                .join()
+                .expect("Verify Checker Thread")
+                .unwrap_or_else(|e| {
+                    error!("Error joining verify checker: {}", e);
+                    evacuate::set_run_error(&mut ret, e);
+                });
+        }
+
+        for status in VerifyObjectStatus::iter() {
+            let count = job
+                .counts
+                .lock()
+                .expect("verify counts lock")
+                .get(&status)
+                .copied()
+                .unwrap_or(0);
+            info!(
+                "Verify Job found {} objects {} on {}",
+                count, status, job.shark.manta_storage_id
+            );
+        }
+
+        if ret.is_ok() && shutdown::requested() {
+            ret = Err(InternalError::new(
+                Some(InternalErrorCode::JobInterrupted),
+                "Job interrupted by manager shutdown",
+            )
+            .into());
+        }
+
+        ret
+    }
+
+    // Pass the objects found by sharkspotter on to the checkers, until
+    // sharkspotter runs out of objects or the job is stopping.
+    fn translate(
+        &self,
+        ss_rx: crossbeam::Receiver<SharkspotterMessage>,
+        obj_tx: crossbeam::Sender<VerifyObject>,
+    ) -> Result<(), Error> {
+        let mut found: u32 = 0;
+
+        while let Ok(mut ss_msg) = ss_rx.recv() {
+            if shutdown::requested() {
+                info!("Job is stopping, stopping verify scan");
+                break;
+            }
+
+            // Directories, links and zero length objects have nothing on the
+            // shark to check, and malformed or oversized records can not be
+            // checked.
+            let disposition = record::classify(
+                &mut ss_msg.manta_value,
+                self.config.options.max_record_bytes,
+            );
+            if disposition != RecordDisposition::Object {
+                debug!("Not verifying record ({})", disposition);
+                continue;
+            }
+
+            let object = match VerifyObject::from_record(
+                ss_msg.shard,
+                &ss_msg.manta_value,
+            ) {
+                Ok(o) => o,
+                Err(e) => {
+                    warn!("Not verifying record: {}", e);
+                    continue;
+                }
+            };
+
+            if obj_tx.send(object).is_err() {
+                warn!("Verify checkers exited prematurely");
+                break;
+            }
+
+            found += 1;
+            if let Some(max) = self.max_objects {
+                if found >= max {
+                    info!("Verify job reached max_objects ({})", max);
+                    self.limit_reached.store(true, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+
+        info!("Verify translator thread exiting");
+        Ok(())
+    }
+
+    fn check_objects(
+        &self,
+        obj_rx: crossbeam::Receiver<VerifyObject>,
+    ) -> Result<(), Error> {
+        while let Ok(object) = obj_rx.recv() {
+            if shutdown::requested() {
+                break;
+            }
+
+            let (status, detail) = check_object(
+                &self.client,
+                self.config.verification.method,
+                &self.shark.manta_storage_id,
+                &object,
+            );
+            self.record(object, status, detail)?;
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        object: VerifyObject,
+        status: VerifyObjectStatus,
+        detail: Option<String>,
+    ) -> Result<(), Error> {
+        if status != VerifyObjectStatus::Present {
+            warn!(
+                "Object {} ({}/{}) is {} on {}: {}",
+                object.key,
+                object.owner,
+                object.id,
+                status,
+                self.shark.manta_storage_id,
+                detail.as_ref().map(String::as_str).unwrap_or("")
+            );
+        }
+
+        *self
+            .counts
+            .lock()
+            .expect("verify counts lock")
+            .entry(status)
+            .or_insert(0) += 1;
+        metrics_verify_object_inc(&status.to_string());
+
+        let entry = VerifyObjectEntry {
+            id: object.id,
+            shard: object.shard,
+            owner: object.owner,
+            key: object.key,
+            status: status.to_string(),
+            detail,
+        };
+
+        // An object with more than one key is found once for each of them,
+        // and only has to be checked once.
+        let conn = self.conn.lock().expect("DB conn lock");
+        diesel::insert_into(verifyobjects::table)
+            .values(&entry)
+            .on_conflict(verifyobjects::id)
+            .do_nothing()
+            .execute(&*conn)
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+}
+
+fn create_verifyobjects_table(conn: &PgConnection) -> Result<usize, Error> {
+    let status_strings = VerifyObjectStatus::variants();
+    let status_check = format!("'{}'", status_strings.join("', '"));
+
+    let create_query = format!(
+        "
+            CREATE TABLE IF NOT EXISTS verifyobjects(
+                id TEXT PRIMARY KEY,
+                shard Integer NOT NULL,
+                owner TEXT NOT NULL,
+                key TEXT NOT NULL,
+                status TEXT CHECK(status IN ({})) NOT NULL,
+                detail TEXT
+            );
+        ",
+        status_check
+    );
+
+    conn.execute(&create_query).map_err(Error::from)
+}
+
+fn start_scanner(
+    job: Arc<VerifyJob>,
+    obj_tx: crossbeam::Sender<VerifyObject>,
+) -> Result<thread::JoinHandle<Result<(), Error>>, Error> {
+    let config = sharkspotter::config::Config {
+        domain: job.config.domain_name.clone(),
+        min_shard: job.config.min_shard_num(),
+        max_shard: job.config.max_shard_num(),
+        sharks: vec![job.shark.manta_storage_id.clone()],
+        chunk_size: job.config.options.md_read_chunk_size as u64,
+        direct_db: true,
+        max_threads: job.config.options.max_md_read_threads,
+        ..Default::default()
+    };
+
+    debug!("Starting verify scanner thread: {:?}", &config);
+
+    let log = slog_scope::logger();
+    let (ss_tx, ss_rx) = crossbeam::bounded(10);
+    let job_id = job.db_name.clone();
+
+    spawn_supervised(&job_id, "verify_scanner", watchdog::any_time, move || {
+        let translator_job = Arc::clone(&job);
+        let translator = thread::Builder::new()
+            .name("verify_translator".to_string())
+            .spawn(joblog::inherit(move || {
+                translator_job.translate(ss_rx, obj_tx)
+            }))
+            .expect("Start verify translator thread");
+
+        // Once the translator stops early sharkspotter can no longer send it
+        // objects, which is not an error.
+        if let Err(e) = sharkspotter::run_multithreaded(&config, log, ss_tx) {
+            if !job.stopping() {
+                return Err(Error::from(e));
+            }
+        }
+
+        translator.join().expect("verify translator join")
+    })
+}
+
+// Ask the front door of `shark` for `object`.  The body of a GET response is
+// never read, and is discarded along with the response.
+fn request_object(
+    client: &Client,
+    method: VerifyMethod,
+    shark: &str,
+    object: &VerifyObject,
+) -> Result<Response, String> {
+    let url = format!("http://{}/{}/{}", shark, object.owner, object.id);
+    let request = match method {
+        VerifyMethod::Head => client.head(&url),
+        VerifyMethod::Get => client.get(&url),
+    };
+
+    request
+        .send()
+        .map_err(|e| format!("requesting {}: {}", url, e))
+}
+
+/// Ask the front door of `shark` whether it holds `object`.  This is also
+/// how a rollback job checks the copies that it relies on (see the rollback
+/// module).
+pub fn check_object(
+    client: &Client,
+    method: VerifyMethod,
+    shark: &str,
+    object: &VerifyObject,
+) -> (VerifyObjectStatus, Option<String>) {
+    let response = match request_object(client, method, shark, object) {
+        Ok(r) => r,
+        Err(e) => return (VerifyObjectStatus::Unverifiable, Some(e)),
+    };
+
+    match response.status() {
+        StatusCode::OK => (),
+        StatusCode::NOT_FOUND => {
+            return (
+                VerifyObjectStatus::Missing,
+                Some(String::from("storage node responded with 404")),
+            )
+        }
+        status => {
+            return (
+                VerifyObjectStatus::Unverifiable,
+                Some(format!("storage node responded with {}", status)),
+            )
+        }
+    }
+
+    let length = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    match length {
+        Some(len) if len != object.content_length => (
+            VerifyObjectStatus::SizeMismatch,
+            Some(format!(
+                "{} bytes on the storage node, {} in the metadata",
+                len, object.content_length
+            )),
+        ),
+        _ => (VerifyObjectStatus::Present, None),
+    }
+}
+
+/// Ask the front door of `shark` for `object`, and compare the custom (`m-`)
+/// headers that it is served with to those in the object's metadata.
+/// Returns each header that is missing, different, or served but not in the
+/// metadata, or an error if the shark did not serve the object at all.
+pub fn check_headers(
+    client: &Client,
+    method: VerifyMethod,
+    shark: &str,
+    object: &VerifyObject,
+) -> Result<Vec<HeaderMismatch>, String> {
+    let response = request_object(client, method, shark, object)?;
+
+    if response.status() != StatusCode::OK {
+        return Err(format!(
+            "storage node responded with {}",
+            response.status()
+        ));
+    }
+
+    let mut served: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in response.headers() {
+        if !is_custom_header(name.as_str()) {
+            continue;
+        }
+        let value = value.to_str().map(String::from).unwrap_or_else(|_| {
+            String::from_utf8_lossy(value.as_bytes()).into()
+        });
+        served.insert(name.as_str().to_string(), value);
+    }
+
+    let mut mismatches = vec![];
+    for (header, expected) in object.custom_headers.iter() {
+        let actual = served.remove(header);
+        if actual.as_ref() != Some(expected) {
+            mismatches.push(HeaderMismatch {
+                header: header.clone(),
+                expected: Some(expected.clone()),
+                actual,
+            });
+        }
+    }
+
+    // Whatever is left was served without being in the metadata.
+    for (header, actual) in served {
+        mismatches.push(HeaderMismatch {
+            header,
+            expected: None,
+            actual: Some(actual),
+        });
+    }
+
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // Answer a single request on a local port with `response`, and return
+    // the address to send the request to.
+    fn one_shot_server(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr").to_string();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept");
+            let mut buf = [0; 4096];
+            let _ = stream.read(&mut buf);
+            stream
+                .write_all(response.as_bytes())
+                .expect("write response");
+        });
+
+        addr
+    }
+
+    fn object(content_length: u64) -> VerifyObject {
+        VerifyObject {
+            id: String::from("d5a3b5f4-3dfc-4e2e-9a53-2b7b1d0c7a1e"),
+            shard: 1,
+            owner: String::from("eb6fbdd1-8e4a-4d71-a7d5-25d4e1b5ada6"),
+            key: String::from("/owner/stor/object"),
+            content_length,
+            custom_headers: BTreeMap::new(),
+        }
+    }
+
+    fn check(
+        response: &'static str,
+        content_length: u64,
+    ) -> VerifyObjectStatus {
+        let addr = one_shot_server(response);
+        let (status, _) = check_object(
+            &Client::new(),
+            VerifyMethod::Head,
+            &addr,
+            &object(content_length),
+        );
+        status
+    }
+
+    #[test]
+    fn verify_record() {
+        let record = serde_json::json!({
+            "key": "/owner/stor/object",
+            "owner": "eb6fbdd1-8e4a-4d71-a7d5-25d4e1b5ada6",
+            "objectId": "d5a3b5f4-3dfc-4e2e-9a53-2b7b1d0c7a1e",
+            "contentLength": 5,
+        });
+
+        let object = VerifyObject::from_record(2, &record).expect("object");
+        assert_eq!(object.id, "d5a3b5f4-3dfc-4e2e-9a53-2b7b1d0c7a1e");
+        assert_eq!(object.shard, 2);
+        assert_eq!(object.content_length, 5);
+
+        assert!(VerifyObject::from_record(2, &serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn verify_headers() {
+        let record = serde_json::json!({
+            "key": "/owner/stor/object",
+            "owner": "eb6fbdd1-8e4a-4d71-a7d5-25d4e1b5ada6",
+            "objectId": "d5a3b5f4-3dfc-4e2e-9a53-2b7b1d0c7a1e",
+            "contentLength": 5,
+            "headers": {
+                "M-Color": "blue",
+                "m-size": 3,
+                "m-shape": "round",
+                "content-type": "text/plain"
+            },
+        });
+        let object = VerifyObject::from_record(2, &record).expect("object");
+        assert_eq!(object.custom_headers.len(), 3);
+        assert_eq!(object.custom_headers["m-color"], "blue");
+        assert_eq!(object.custom_headers["m-size"], "3");
+
+        let addr = one_shot_server(
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nm-color: blue\r\n\
+             m-size: 4\r\nm-extra: yes\r\nConnection: close\r\n\r\n",
+        );
+        let mismatches =
+            check_headers(&Client::new(), VerifyMethod::Head, &addr, &object)
+                .expect("check headers");
+
+        let mismatch =
+            |header: &str, expected: Option<&str>, actual: Option<&str>| {
+                HeaderMismatch {
+                    header: header.to_string(),
+                    expected: expected.map(String::from),
+                    actual: actual.map(String::from),
+                }
+            };
+        assert_eq!(
+            mismatches,
+            vec![
+                mismatch("m-shape", Some("round"), None),
+                mismatch("m-size", Some("3"), Some("4")),
+                mismatch("m-extra", None, Some("yes")),
+            ]
+        );
+
+        let addr = one_shot_server(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+        );
+        assert!(check_headers(
+            &Client::new(),
+            VerifyMethod::Head,
+            &addr,
+            &object
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn verify_present() {
+        let status = check(
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n",
+            5,
+        );
+        assert_eq!(status, VerifyObjectStatus::Present);
+    }
+
+    #[test]
+    fn verify_size_mismatch() {
+        let status = check(
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\n",
+            5,
+        );
+        assert_eq!(status, VerifyObjectStatus::SizeMismatch);
+    }
+
+    #[test]
+    fn verify_missing() {
+        let status = check(
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+            5,
+        );
+        assert_eq!(status, VerifyObjectStatus::Missing);
+    }
+
+    #[test]
+    fn verify_unverifiable() {
+        let status = check(
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\
+             Connection: close\r\n\r\n",
+            5,
+        );
+        assert_eq!(status, VerifyObjectStatus::Unverifiable);
+
+        // Nothing is listening on the port of a listener that has gone.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("local addr")
+            .to_string();
+        let (status, _) =
+            check_object(&Client::new(), VerifyMethod::Head, &addr, &object(5));
+        assert_eq!(status, VerifyObjectStatus::Unverifiable);
+    }
+}
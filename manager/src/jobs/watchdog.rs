@@ -0,0 +1,378 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Supervision of the threads that make up a running job.
+//
+// An evacuate job is a set of threads connected by channels.  If one of them
+// panics, the join in EvacuateJob::run() would in turn panic the job thread,
+// and the job would be left in the running state forever with nothing to say
+// what happened.  Instead, each of these threads runs its body under
+// catch_unwind(), which is why the workspace is built with panic = "unwind"
+// (see Cargo.toml).  A panic is logged along with the job and thread it came
+// from, and then:
+//
+//  * If the thread holds no work of its own that would be lost (e.g. the
+//    assignment checker, which re-reads the assignment cache on every pass),
+//    its body is simply run again, up to MAX_RESTARTS times.
+//
+//  * Otherwise the thread exits with a WorkerPanic error.  Its channels are
+//    dropped as it unwinds, so the rest of the job shuts down, and the job is
+//    paused, with the thread and what it panicked with recorded as the reason
+//    (see the breaker module).  A retry job can then pick up where it left
+//    off.
+//
+// A thread that can not be restarted may also exit silently, returning before
+// the job is done with it, e.g. while there is still work on its way to it.
+// Each such thread is given a `finished` check of whether it may have
+// returned, which is usually that everything sent to it has been received
+// (see drained()), and a thread that returns before then is treated as if it
+// had panicked.
+//
+// Each of these threads logs where the thread that started it does, so that
+// a job's threads all log to the job's log file (see the joblog module).
+//
+// Panics anywhere else in the manager are not recovered from.  The manager's
+// panic hook (see install_panic_hook()) aborts on any panic that is not
+// raised inside of catch_panic(), which every catch_unwind() in the manager
+// goes through, just as it would if the manager were built with
+// panic = "abort".  Otherwise a thread such as the job scheduler or the lease
+// keeper would go away without a word, leaving a manager that looks healthy
+// but does none of that thread's work, along with any lock that the thread
+// held poisoned.
+
+use crate::joblog;
+use rebalancer::error::{Error, InternalError, InternalErrorCode};
+
+use crossbeam_channel::{Receiver, TryRecvError};
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+static MAX_RESTARTS: u32 = 3;
+static RESTART_DELAY: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // The number of calls to catch_panic() that this thread is inside of.
+    static CATCHING: Cell<u32> = Cell::new(0);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Restart {
+    Safe,
+    Unsafe,
+}
+
+/// The message a thread panicked with, if it can be recovered.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
+
+/// Run `f`, returning what it panicked with if it panics, rather than
+/// aborting the manager (see install_panic_hook()).
+pub fn catch_panic<F, R>(f: F) -> thread::Result<R>
+where
+    F: FnOnce() -> R,
+{
+    CATCHING.with(|c| c.set(c.get() + 1));
+    let ret = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(c.get() - 1));
+    ret
+}
+
+/// Abort the manager on any panic that is not caught by catch_panic(), once
+/// the panic has been reported as usual.  This must be called before any
+/// thread is started.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let caught = CATCHING.try_with(|c| c.get() > 0).unwrap_or(false);
+        if !caught {
+            process::abort();
+        }
+    }));
+}
+
+/// Returns true if the error is the result of a job's worker thread
+/// panicking, or exiting before it was finished.
+pub fn is_worker_panic(err: &Error) -> bool {
+    match err {
+        Error::Internal(e) => e.code == InternalErrorCode::WorkerPanic,
+        _ => false,
+    }
+}
+
+/// What a worker thread that a WorkerPanic error came from did, as it is
+/// recorded when the job is paused for it.
+pub fn worker_panic_detail(err: &Error) -> Option<String> {
+    match err {
+        Error::Internal(e) if e.code == InternalErrorCode::WorkerPanic => {
+            Some(e.message().to_string())
+        }
+        _ => None,
+    }
+}
+
+/// A `finished` check for a thread that may return whenever it likes, such as
+/// one that finds the job's objects.
+pub fn any_time() -> bool {
+    true
+}
+
+/// A `finished` check for a thread that works through what it is sent on
+/// `rx`, which is finished once no more can be sent to it and it has received
+/// everything that was.
+pub fn drained<T>(rx: &Receiver<T>) -> impl Fn() -> bool + Send + 'static
+where
+    T: Send + 'static,
+{
+    // Only try_recv() once the channel is empty, so that nothing that was
+    // still to be received is taken.
+    let rx = rx.clone();
+    move || {
+        rx.is_empty()
+            && match rx.try_recv() {
+                Err(TryRecvError::Disconnected) => true,
+                _ => false,
+            }
+    }
+}
+
+fn run_supervised<F>(
+    job_id: &str,
+    name: &str,
+    restart: Restart,
+    finished: &dyn Fn() -> bool,
+    mut body: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> Result<(), Error>,
+{
+    let mut restarts = 0;
+
+    loop {
+        let payload = match catch_panic(&mut body) {
+            Ok(Ok(())) if restart == Restart::Unsafe && !finished() => {
+                error!(
+                    "Job {}: {} thread exited before it was finished, \
+                     pausing job",
+                    job_id, name
+                );
+
+                return Err(InternalError::new(
+                    Some(InternalErrorCode::WorkerPanic),
+                    format!("{} thread exited before it was finished", name),
+                )
+                .into());
+            }
+            Ok(ret) => {
+                if let Err(e) = &ret {
+                    error!(
+                        "Job {}: {} thread exited with an error: {}",
+                        job_id, name, e
+                    );
+                }
+                return ret;
+            }
+            Err(payload) => payload,
+        };
+
+        let msg = panic_message(&*payload);
+        error!("Job {}: {} thread panicked: {}", job_id, name, msg);
+
+        if restart == Restart::Safe && restarts < MAX_RESTARTS {
+            restarts += 1;
+            warn!(
+                "Job {}: restarting {} thread (restart {} of {})",
+                job_id, name, restarts, MAX_RESTARTS
+            );
+            thread::sleep(RESTART_DELAY);
+            continue;
+        }
+
+        error!(
+            "Job {}: {} thread can not be safely restarted, pausing job",
+            job_id, name
+        );
+
+        return Err(InternalError::new(
+            Some(InternalErrorCode::WorkerPanic),
+            format!("{} thread panicked: {}", name, msg),
+        )
+        .into());
+    }
+}
+
+/// Spawn a thread whose body is run again if it panics.  Only use this for
+/// threads that can pick up where they left off without losing any work.
+pub fn spawn_restartable<F>(
+    job_id: &str,
+    name: &str,
+    body: F,
+) -> Result<thread::JoinHandle<Result<(), Error>>, Error>
+where
+    F: FnMut() -> Result<(), Error> + Send + 'static,
+{
+    let job_id = job_id.to_string();
+    let thread_name = name.to_string();
+
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(joblog::inherit(move || {
+            run_supervised(
+                &job_id,
+                &thread_name,
+                Restart::Safe,
+                &any_time,
+                body,
+            )
+        }))
+        .map_err(Error::from)
+}
+
+/// Spawn a thread which returns a WorkerPanic error if its body panics, or
+/// returns successfully before `finished` says that it may.
+pub fn spawn_supervised<F, D>(
+    job_id: &str,
+    name: &str,
+    finished: D,
+    body: F,
+) -> Result<thread::JoinHandle<Result<(), Error>>, Error>
+where
+    F: FnOnce() -> Result<(), Error> + Send + 'static,
+    D: Fn() -> bool + Send + 'static,
+{
+    let job_id = job_id.to_string();
+    let thread_name = name.to_string();
+    let mut body = Some(body);
+
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(joblog::inherit(move || {
+            run_supervised(
+                &job_id,
+                &thread_name,
+                Restart::Unsafe,
+                &finished,
+                || body.take().expect("supervised thread body")(),
+            )
+        }))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rebalancer::util;
+
+    #[test]
+    fn restartable_thread_restarted() {
+        let _guard = util::init_global_logger(None);
+        let mut attempts = 0;
+
+        let ret =
+            run_supervised("job", "test", Restart::Safe, &any_time, || {
+                attempts += 1;
+                if attempts < 3 {
+                    panic!("attempt {}", attempts);
+                }
+                Ok(())
+            });
+
+        assert!(ret.is_ok());
+        assert_eq!(attempts, 3);
+
+        let ret =
+            run_supervised("job", "test", Restart::Safe, &any_time, || {
+                panic!("always");
+            });
+
+        assert!(is_worker_panic(&ret.expect_err("restart limit")));
+    }
+
+    #[test]
+    fn caught_panics_counted() {
+        let _guard = util::init_global_logger(None);
+
+        let ret = catch_panic(|| {
+            assert!(catch_panic(|| panic!("inner")).is_err());
+            CATCHING.with(|c| assert_eq!(c.get(), 1));
+        });
+
+        assert!(ret.is_ok());
+        CATCHING.with(|c| assert_eq!(c.get(), 0));
+    }
+
+    #[test]
+    fn unsafe_thread_not_restarted() {
+        let _guard = util::init_global_logger(None);
+        let mut attempts = 0;
+
+        let ret =
+            run_supervised("job", "test", Restart::Unsafe, &any_time, || {
+                attempts += 1;
+                panic!("attempt {}", attempts);
+            });
+
+        let err = ret.expect_err("worker panic");
+        assert!(is_worker_panic(&err));
+        assert!(worker_panic_detail(&err)
+            .expect("detail")
+            .contains("attempt 1"));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn early_exit_is_worker_panic() {
+        let _guard = util::init_global_logger(None);
+        let (tx, rx) = crossbeam_channel::unbounded::<u32>();
+        let finished = drained(&rx);
+
+        tx.send(1).expect("send");
+        let ret = run_supervised(
+            "job",
+            "test",
+            Restart::Unsafe,
+            &finished,
+            || Ok(()),
+        );
+
+        let err = ret.expect_err("early exit");
+        assert!(is_worker_panic(&err));
+        assert!(worker_panic_detail(&err)
+            .expect("detail")
+            .contains("test thread exited before it was finished"));
+
+        // Once everything sent has been received and the sender is gone the
+        // thread is free to return.
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(1));
+        let ret = run_supervised(
+            "job",
+            "test",
+            Restart::Unsafe,
+            &finished,
+            || Ok(()),
+        );
+
+        assert!(ret.is_ok());
+    }
+}
@@ -22,11 +22,22 @@ extern crate strum_macros;
 #[macro_use]
 extern crate rebalancer;
 
+pub mod agent_client;
+pub mod alerts;
+pub mod compat;
 pub mod config;
+pub mod fleet;
+pub mod health;
+pub mod hooks;
+pub mod joblog;
 pub mod jobs;
 pub mod metrics;
+pub mod migrations;
 pub mod moray_client;
+pub mod notify;
+pub mod parquet;
 pub mod pg_db;
+pub mod shutdown;
 pub mod storinfo;
 
 #[cfg(test)]
@@ -19,27 +19,61 @@ extern crate rebalancer;
 
 mod gotham_json_util;
 
-use manager::config::Config;
-use manager::jobs::status::{JobStatus, StatusError};
+use manager::agent_client;
+use manager::alerts;
+use manager::compat::VersionInfo;
+use manager::config::{Config, HookEvent};
+use manager::fleet;
+use manager::health::ManagerHealth;
+use manager::hooks;
+use manager::jobs::bandwidth;
+use manager::jobs::confirmation::{self, ConfirmError, ConfirmJobPayload};
+use manager::jobs::corruption::{self, CorruptObject};
+use manager::jobs::estimate::{self, EstimatePayload};
+use manager::jobs::export::{self, ExportFormat};
+use manager::jobs::history;
+use manager::jobs::lease;
+use manager::jobs::plan::{self, Plan, PlanAction, PlanError, PlanPayload};
+use manager::jobs::projected;
+use manager::jobs::quarantine::{self, QuarantinePayload};
+use manager::jobs::queue::JobQueue;
+use manager::jobs::registry;
+use manager::jobs::retention::{self, RetentionError};
+use manager::jobs::source::{self, ObjectSource};
+use manager::jobs::status::{
+    self, JobListFilter, JobListOrder, JobStatus, StatusError,
+};
+use manager::jobs::watchdog;
 use manager::jobs::{
-    self, JobActionDbEntry, JobBuilder, JobDbEntry, JobPayload, JobState,
-    JobUpdateMessage,
+    self, JobActionDbEntry, JobBuilder, JobDbEntry, JobPayload, JobPriority,
+    JobState, JobUpdateMessage, JobUpdateRequest,
 };
 use manager::metrics::{metrics_init, metrics_request_inc};
-use manager::pg_db::{connect_db, REBALANCER_DB};
+use manager::notify::{self, JobEvent, JobEventKind};
+use manager::pg_db::{self, connect_db, REBALANCER_DB};
+use manager::shutdown;
+use manager::storinfo;
+use rebalancer::error::{ApiError, RebalancerError};
+use rebalancer::faults;
+use rebalancer::metrics::MetricsMode;
+use rebalancer::readiness;
+use rebalancer::registration::{AgentRegistration, RegistrationAck};
 use rebalancer::util;
 
 use std::collections::HashMap;
 use std::error::Error;
+use std::io::{self, Write};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::thread;
 
 use crate::gotham_json_util::JsonBody;
 use clap::{App, Arg, ArgMatches};
 use crossbeam_channel;
 use diesel::query_dsl::{QueryDsl, RunQueryDsl};
 use diesel::PgConnection;
-use futures::{future, Future};
+use futures::sync::mpsc;
+use futures::{future, Future, Sink, Stream};
 use gotham::handler::{Handler, HandlerFuture, IntoResponse, NewHandler};
 use gotham::helpers::http::response::create_response;
 use gotham::middleware::Middleware;
@@ -51,14 +85,13 @@ use gotham::state::{FromState, State};
 use hyper::header::HeaderValue;
 use hyper::{Body, Response, StatusCode};
 use lazy_static::lazy_static;
-use manager::jobs::evacuate::EvacuateJobUpdateMessage;
-use threadpool::ThreadPool;
+use manager::jobs::evacuate::{
+    self, CancelAssignmentError, EvacuateJobUpdateMessage,
+};
 use uuid::Uuid;
 
-static THREAD_COUNT: usize = 1;
-
 lazy_static! {
-    static ref UPDATE_CHANS: Mutex<HashMap<Uuid, crossbeam_channel::Sender<JobUpdateMessage>>> =
+    static ref UPDATE_CHANS: Mutex<HashMap<Uuid, crossbeam_channel::Sender<JobUpdateRequest>>> =
         Mutex::new(HashMap::new());
 }
 
@@ -67,6 +100,78 @@ struct GetJobParams {
     uuid: String,
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct GetAgentParams {
+    id: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SkippedQueryParams {
+    reason: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct SlowQueryParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct HeadersQueryParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct PlacementQueryParams {
+    object: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct AuditQueryParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct ExportQueryParams {
+    format: Option<String>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct AssignmentsQueryParams {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct JobListQueryParams {
+    state: Option<String>,
+    action: Option<String>,
+    created_after: Option<i64>,
+    created_before: Option<i64>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    order: Option<String>,
+}
+
+// The number of skipped objects returned if the request does not specify a
+// limit.
+static DEFAULT_SKIPPED_LIMIT: i64 = 100;
+
+// The size of the pieces that an export is sent in, and how many of them may
+// be waiting to be sent before the export waits for the client.
+static EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+static EXPORT_QUEUE_DEPTH: usize = 4;
+
+// How long to wait for a running job to apply an update.
+static UPDATE_REPLY_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(10);
+
 #[derive(Debug, Deserialize, Serialize)]
 struct JobList {
     jobs: Vec<String>,
@@ -77,6 +182,23 @@ struct UpdateJobParams {
     uuid: String,
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct PlanParams {
+    uuid: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct GetAssignmentParams {
+    uuid: String,
+    assignment_uuid: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct CancelAssignmentParams {
+    uuid: String,
+    assignment_uuid: String,
+}
+
 pub fn get_version() -> String {
     let version = env!("CARGO_PKG_VERSION");
     let name = env!("CARGO_PKG_NAME");
@@ -96,7 +218,7 @@ pub fn get_version() -> String {
 
 fn add_update_channel(
     uuid: Uuid,
-    update_tx: crossbeam_channel::Sender<JobUpdateMessage>,
+    update_tx: crossbeam_channel::Sender<JobUpdateRequest>,
 ) {
     let mut update_chans =
         UPDATE_CHANS.lock().expect("lock update chans hashmap");
@@ -124,7 +246,7 @@ fn remove_update_channel(uuid: Uuid) {
 
 fn get_update_channel(
     uuid: Uuid,
-) -> Result<crossbeam_channel::Sender<JobUpdateMessage>, String> {
+) -> Result<crossbeam_channel::Sender<JobUpdateRequest>, String> {
     let update_chans = UPDATE_CHANS.lock().expect("lock update chans hashmap");
     let chan = update_chans.get(&uuid).ok_or_else(|| {
         format!(
@@ -136,17 +258,69 @@ fn get_update_channel(
     Ok(chan.clone())
 }
 
+// Every refused request is answered with an ApiError, so that clients can
+// act on the code of the error rather than on its wording.
+fn error_response<S: Into<String>>(
+    state: &State,
+    status: StatusCode,
+    error: RebalancerError,
+    msg: S,
+) -> Response<Body> {
+    let body = serde_json::to_string(&ApiError::new(error, msg))
+        .expect("serialized API error");
+    create_response(state, status, mime::APPLICATION_JSON, body)
+}
+
 fn bad_request(state: &State, msg: String) -> Response<Body> {
     warn!("{}", msg);
-    create_response(state, StatusCode::BAD_REQUEST, mime::APPLICATION_JSON, msg)
+    error_response(
+        state,
+        StatusCode::BAD_REQUEST,
+        RebalancerError::InvalidRequest,
+        msg,
+    )
+}
+
+// What the request was for does not exist.  Most of these have always been
+// answered with a 400, which is kept for existing clients.
+fn missing(state: &State, status: StatusCode, msg: String) -> Response<Body> {
+    warn!("{}", msg);
+    error_response(state, status, RebalancerError::NotFound, msg)
+}
+
+fn unprocessable(state: &State, msg: String) -> Response<Body> {
+    warn!("{}", msg);
+    error_response(
+        state,
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RebalancerError::InvalidRequest,
+        msg,
+    )
+}
+
+fn conflict(state: &State, msg: String) -> Response<Body> {
+    warn!("{}", msg);
+    error_response(state, StatusCode::CONFLICT, RebalancerError::Conflict, msg)
+}
+
+// New jobs are refused while the manager is draining for a shutdown.
+fn shutting_down(state: &State) -> Response<Body> {
+    let msg = String::from("Manager is shutting down");
+    warn!("{}", msg);
+    error_response(
+        state,
+        StatusCode::SERVICE_UNAVAILABLE,
+        RebalancerError::Unavailable,
+        msg,
+    )
 }
 
 fn invalid_server_error(state: &State, msg: String) -> Response<Body> {
     error!("{}", msg);
-    create_response(
+    error_response(
         state,
         StatusCode::INTERNAL_SERVER_ERROR,
-        mime::APPLICATION_JSON,
+        RebalancerError::Internal,
         msg,
     )
 }
@@ -154,14 +328,38 @@ fn invalid_server_error(state: &State, msg: String) -> Response<Body> {
 type GetJobFuture =
     Box<dyn Future<Item = JobStatus, Error = StatusError> + Send>;
 
-fn get_job_status(uuid: Uuid) -> GetJobFuture {
+fn get_job_status(uuid: Uuid, queue: &JobQueue) -> GetJobFuture {
     Box::new(match jobs::status::get_job(uuid) {
-        Ok(status) => future::ok(status),
+        Ok(mut status) => {
+            if status.state == JobState::Queued {
+                status.queue_position = queue.position(&uuid);
+            }
+            future::ok(status)
+        }
         Err(e) => future::err(e),
     })
 }
 
-fn get_job(mut state: State) -> Box<HandlerFuture> {
+#[derive(Clone)]
+struct GetJobHandler {
+    queue: Arc<JobQueue>,
+}
+
+impl NewHandler for GetJobHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for GetJobHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        get_job(state, &self.queue)
+    }
+}
+
+fn get_job(mut state: State, queue: &JobQueue) -> Box<HandlerFuture> {
     metrics_request_inc(Some("get_job"));
     info!("Get Job Request");
     let get_job_params = GetJobParams::take_from(&mut state);
@@ -174,7 +372,7 @@ fn get_job(mut state: State) -> Box<HandlerFuture> {
         }
     };
 
-    Box::new(get_job_status(uuid).then(move |result| {
+    Box::new(get_job_status(uuid, queue).then(move |result| {
         match result {
             Ok(job_status) => {
                 let ret = match serde_json::to_string(&job_status) {
@@ -196,8 +394,9 @@ fn get_job(mut state: State) -> Box<HandlerFuture> {
                 error!("Get Status error: {:?}", e);
                 match e {
                     StatusError::DBExists => {
-                        ret = bad_request(
+                        ret = missing(
                             &state,
+                            StatusCode::BAD_REQUEST,
                             format!("Could not find job UUID: {}", uuid),
                         );
                     }
@@ -223,613 +422,3850 @@ fn get_job(mut state: State) -> Box<HandlerFuture> {
     }))
 }
 
-type JobListFuture =
-    Box<dyn Future<Item = Vec<JobDbEntry>, Error = StatusError> + Send>;
-
-fn get_job_list() -> JobListFuture {
-    Box::new(match jobs::status::list_jobs() {
-        Ok(list) => future::ok(list),
-        Err(e) => future::err(e),
-    })
+fn skipped_status_error(
+    state: &State,
+    uuid: &Uuid,
+    e: StatusError,
+) -> Response<Body> {
+    error!("Get Skipped error: {:?}", e);
+    match e {
+        StatusError::DBExists => missing(
+            state,
+            StatusCode::BAD_REQUEST,
+            format!("Could not find job UUID: {}", uuid),
+        ),
+        StatusError::LookupError | StatusError::Unknown => {
+            invalid_server_error(state, String::from("Internal Lookup Error"))
+        }
+    }
 }
 
-fn list_jobs(state: State) -> Box<HandlerFuture> {
-    metrics_request_inc(Some("list_jobs"));
-    info!("List Jobs Request");
-    let job_list_future = get_job_list();
-    Box::new(job_list_future.then(move |result| match result {
-        Ok(list) => {
-            let jobs = match serde_json::to_string(&list) {
-                Ok(j) => j,
-                Err(e) => {
-                    let msg = format!("Error Getting Job List: {}", e);
-                    let ret = invalid_server_error(&state, msg);
-                    return future::ok((state, ret));
-                }
-            };
-            let res = create_response(
+fn get_skipped(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_skipped"));
+    info!("Get Skipped Objects Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let query = SkippedQueryParams::take_from(&mut state);
+
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
+            return (state, res);
+        }
+    };
+
+    if let Some(reason) = query.reason.as_ref() {
+        if !status::is_skipped_reason(reason) {
+            let msg = format!("Unknown skipped reason: {}", reason);
+            let res = bad_request(&state, msg);
+            return (state, res);
+        }
+    }
+
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
+        );
+        let res = bad_request(&state, msg);
+        return (state, res);
+    }
+
+    let res = match status::get_skipped_objects(
+        &uuid,
+        query.reason.as_ref().map(String::as_str),
+        limit,
+        offset,
+    ) {
+        Ok(objects) => match serde_json::to_string(&objects) {
+            Ok(body) => create_response(
                 &state,
                 StatusCode::OK,
                 mime::APPLICATION_JSON,
-                jobs,
-            );
-            future::ok((state, res))
-        }
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Skipped Objects: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
+
+    (state, res)
+}
+
+fn get_slow(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_slow"));
+    info!("Get Slow Objects Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let query = SlowQueryParams::take_from(&mut state);
+
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
         Err(e) => {
-            let msg = format!("Error Getting Job List: {:#?}", e);
-            let ret = invalid_server_error(&state, msg);
-            future::ok((state, ret))
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
+            return (state, res);
         }
-    }))
+    };
+
+    // The same limits apply as to skipped objects.
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
+        );
+        let res = bad_request(&state, msg);
+        return (state, res);
+    }
+
+    let res = match status::get_slow_objects(&uuid, limit, offset) {
+        Ok(objects) => match serde_json::to_string(&objects) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Slow Objects: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
+
+    (state, res)
 }
 
-fn update_job(mut state: State) -> (State, Response<Body>) {
-    use crate::jobs::jobs::dsl::jobs as jobs_db;
+fn get_headers(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_headers"));
+    info!("Get Header Mismatches Request");
 
-    let db_conn = DBConnMiddlewareData::take_from(&mut state).db_conn;
-    let update_job_params = UpdateJobParams::take_from(&mut state);
-    let uuid =
-        Uuid::from_str(update_job_params.uuid.as_str()).expect("uuid from str");
-    let tx: crossbeam_channel::Sender<JobUpdateMessage>;
+    let params = GetJobParams::take_from(&mut state);
+    let query = HeadersQueryParams::take_from(&mut state);
 
-    let job_db_entry: JobDbEntry = match jobs_db
-        .find(update_job_params.uuid.as_str())
-        .first(&db_conn)
-    {
-        Ok(jdbe) => jdbe,
-        Err(_) => {
-            let msg = format!("Could not find job {}", uuid);
-            let res = bad_request(&state, msg);
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
             return (state, res);
         }
     };
 
-    if job_db_entry.state != JobState::Running {
-        let res = bad_request(
-            &state,
-            "Attempt to update job that is not running".into(),
+    // The same limits apply as to skipped objects.
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
+
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
         );
+        let res = bad_request(&state, msg);
         return (state, res);
     }
 
-    tx = match get_update_channel(uuid) {
-        Ok(t) => t,
+    let res = match status::get_header_mismatches(&uuid, limit, offset) {
+        Ok(objects) => match serde_json::to_string(&objects) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Header Mismatches: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
+
+    (state, res)
+}
+
+fn get_placement(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_placement"));
+    info!("Get Placement Trace Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let query = PlacementQueryParams::take_from(&mut state);
+
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
         Err(e) => {
-            let res = bad_request(&state, e);
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
             return (state, res);
         }
     };
 
-    #[allow(clippy::single_match)]
-    let update_message = match job_db_entry.action {
-        JobActionDbEntry::Evacuate => {
-            let evac_msg =
-                match state.json_body::<EvacuateJobUpdateMessage>().wait() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        let msg = format!(
-                            "Could not parse Evacuate Update \
-                             Message: {}",
-                            e
-                        );
-                        warn!("{}", msg);
-                        let res = create_response(
-                            &state,
-                            StatusCode::UNPROCESSABLE_ENTITY,
-                            mime::APPLICATION_JSON,
-                            msg,
-                        );
-                        return (state, res);
-                    }
-                };
+    // The same limits apply as to skipped objects.
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
 
-            if let Err(e) = evac_msg.validate() {
-                let res = bad_request(&state, e);
-                return (state, res);
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
+        );
+        let res = bad_request(&state, msg);
+        return (state, res);
+    }
+
+    let object = query.object.as_ref().map(String::as_str);
+    let res = match status::get_placement_trace(&uuid, object, limit, offset) {
+        Ok(entries) => match serde_json::to_string(&entries) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Placement Trace: {}", e);
+                invalid_server_error(&state, msg)
             }
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
 
-            JobUpdateMessage::Evacuate(evac_msg)
-        }
-        _ => {
-            let res = bad_request(&state, "payload action mismatch".into());
+    (state, res)
+}
+
+fn get_audit(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_audit"));
+    info!("Get Metadata Audit Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let query = AuditQueryParams::take_from(&mut state);
+
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
             return (state, res);
         }
     };
 
-    // Send update message down channel
-    if let Err(e) = tx.send(update_message) {
-        let res = invalid_server_error(
-            &state,
-            format!("could not communicate with job: {}", e),
-        );
+    // The same limits apply as to skipped objects.
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
 
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
+        );
+        let res = bad_request(&state, msg);
         return (state, res);
     }
 
-    let res =
-        create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, "");
+    let res = match status::get_metadata_audit(&uuid, limit, offset) {
+        Ok(entries) => match serde_json::to_string(&entries) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Metadata Audit: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
 
     (state, res)
 }
 
-#[derive(Clone)]
-struct JobRetryHandler {
-    tx: crossbeam_channel::Sender<jobs::Job>,
-    config: Arc<Mutex<Config>>,
+// Passes what an export writes to it on to the body of the response, a chunk
+// at a time.  Once the client has gone away writes fail, which stops the
+// export.
+struct ExportBodyWriter {
+    tx: Option<mpsc::Sender<Result<Vec<u8>, io::Error>>>,
+    buf: Vec<u8>,
 }
 
-impl NewHandler for JobRetryHandler {
-    type Instance = Self;
+impl ExportBodyWriter {
+    fn send(&mut self, item: Result<Vec<u8>, io::Error>) -> io::Result<()> {
+        let tx = self.tx.take().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "export client went away")
+        })?;
 
-    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
-        Ok(self.clone())
+        match tx.send(item).wait() {
+            Ok(tx) => {
+                self.tx = Some(tx);
+                Ok(())
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "export client went away",
+            )),
+        }
     }
-}
 
-impl Handler for JobRetryHandler {
-    fn handle(self, mut state: State) -> Box<HandlerFuture> {
-        let job_params = GetJobParams::take_from(&mut state);
-        let retry_uuid = job_params.uuid;
+    fn send_buf(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
 
-        info!("Retry Job {} Request", retry_uuid);
+        let chunk = std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(EXPORT_CHUNK_BYTES),
+        );
+        self.send(Ok(chunk))
+    }
+}
 
-        let config = self.config.lock().expect("config lock").clone();
-        let job_builder = match JobBuilder::new(config).retry(&retry_uuid) {
-            Ok(jb) => jb,
-            Err(e) => {
-                let error =
-                    invalid_server_error(&state, String::from(e.description()));
-                return Box::new(future::ok((state, error)));
-            }
-        };
+impl Write for ExportBodyWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= EXPORT_CHUNK_BYTES {
+            self.send_buf()?;
+        }
+        Ok(data.len())
+    }
 
-        let job = match job_builder.commit() {
-            Ok(j) => j,
-            Err(e) => {
-                let error =
-                    invalid_server_error(&state, String::from(e.description()));
-                return Box::new(future::ok((state, error)));
-            }
-        };
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buf()
+    }
+}
 
-        let job_uuid = job.get_id();
-        let uuid_response = format!("{}\n", &job_uuid);
+// Stream the outcome of every object in a job.  The export is written from a
+// thread of its own as the response is sent, so that it is never held in
+// memory in full.  If it fails part way through, the response is cut short
+// rather than ended, so that a client can not mistake it for a whole export.
+fn get_export(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_export"));
+    info!("Get Job Export Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let query = ExportQueryParams::take_from(&mut state);
 
-        if let Err(e) = self.tx.send(job) {
-            panic!("Tx error: {}", e);
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
+            return (state, res);
         }
+    };
 
-        let res = create_response(
-            &state,
-            StatusCode::OK,
-            mime::APPLICATION_JSON,
-            uuid_response,
-        );
+    // A Parquet file can not be read until it is complete, so it is left to
+    // rebalancer-adm, which writes it to a file.
+    let format = match query.format.as_ref().map(String::as_str) {
+        None | Some("csv") => ExportFormat::Csv,
+        Some("ndjson") => ExportFormat::Ndjson,
+        Some(f) => {
+            let msg = format!("Unsupported export format: {}", f);
+            let res = bad_request(&state, msg);
+            return (state, res);
+        }
+    };
 
-        Box::new(future::ok((state, res)))
-    }
-}
+    let conn = match export::open_job(&uuid.to_string()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Get Job Export error: {}", e);
+            let msg = format!("Could not find job UUID: {}", uuid);
+            let res = missing(&state, StatusCode::BAD_REQUEST, msg);
+            return (state, res);
+        }
+    };
 
-#[derive(Clone)]
-struct JobCreateHandler {
-    tx: crossbeam_channel::Sender<jobs::Job>,
-    config: Arc<Mutex<Config>>,
-}
+    let (tx, rx) = mpsc::channel(EXPORT_QUEUE_DEPTH);
+    let job_id = uuid.to_string();
+    let spawned = thread::Builder::new()
+        .name(format!("export {}", job_id))
+        .spawn(move || {
+            let mut out = ExportBodyWriter {
+                tx: Some(tx),
+                buf: Vec::with_capacity(EXPORT_CHUNK_BYTES),
+            };
 
-impl NewHandler for JobCreateHandler {
-    type Instance = Self;
+            match export::export_objects(&conn, format, &mut out) {
+                Ok(count) => {
+                    info!("Exported {} objects of job {}", count, job_id)
+                }
+                Err(e) => {
+                    error!("Export of job {} failed: {}", job_id, e);
+                    let err =
+                        io::Error::new(io::ErrorKind::Other, e.to_string());
+                    let _ = out.send(Err(err));
+                }
+            }
+        });
 
-    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
-        Ok(self.clone())
+    if let Err(e) = spawned {
+        let msg = format!("Could not start export: {}", e);
+        let res = invalid_server_error(&state, msg);
+        return (state, res);
     }
-}
 
-impl Handler for JobCreateHandler {
-    fn handle(self, mut state: State) -> Box<HandlerFuture> {
-        info!("Post Job Request");
+    let body = Body::wrap_stream(rx.then(|item| match item {
+        Ok(chunk) => chunk,
+        Err(()) => Err(io::Error::new(io::ErrorKind::Other, "export failed")),
+    }));
+    let mime = format
+        .content_type()
+        .parse::<mime::Mime>()
+        .expect("export content type");
 
-        let config = self.config.lock().expect("config lock").clone();
+    let res = create_response(&state, StatusCode::OK, mime, body);
+    (state, res)
+}
 
-        // If snaplinks are still in play then we immediately return failure.
-        if config.snaplink_cleanup_required {
-            let error = invalid_server_error(
-                &state,
-                String::from("Snaplink Cleanup Required"),
-            );
-            return Box::new(future::ok((state, error)));
+fn get_skipped_summary(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_skipped_summary"));
+    info!("Get Skipped Summary Request");
+
+    let params = GetJobParams::take_from(&mut state);
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
+            return (state, res);
         }
+    };
 
-        let job_builder = JobBuilder::new(config);
-        let payload = match state.json_body::<JobPayload>().wait() {
-            Ok(p) => p,
+    let res = match status::get_skipped_summary(&uuid) {
+        Ok(summary) => match serde_json::to_string(&summary) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
             Err(e) => {
-                error!("Payload error: {}", &e);
-                return Box::new(future::err((state, e)));
+                let msg = format!("Error Getting Skipped Summary: {}", e);
+                invalid_server_error(&state, msg)
             }
-        };
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
 
-        let ret = match payload {
-            JobPayload::Evacuate(evac_payload) => {
-                metrics_request_inc(Some("evacuate"));
+    (state, res)
+}
 
-                let max_objects = match evac_payload.max_objects {
-                    Some(val) => {
-                        if val == 0 {
-                            None
-                        } else {
-                            Some(val)
-                        }
-                    }
-                    None => {
-                        Some(10) // Default
-                    }
-                };
+fn get_assignments(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_assignments"));
+    info!("Get Assignments Request");
 
-                let job = match job_builder
-                    .evacuate(evac_payload.from_shark, max_objects)
-                    .commit()
-                {
-                    Ok(j) => j,
-                    Err(e) => {
-                        let error = invalid_server_error(
-                            &state,
-                            String::from(e.description()),
-                        );
-                        return Box::new(future::ok((state, error)));
-                    }
-                };
+    let params = GetJobParams::take_from(&mut state);
+    let query = AssignmentsQueryParams::take_from(&mut state);
 
-                let job_uuid = job.get_id();
-                let uuid_response = format!("{}\n", &job_uuid);
+    let uuid = match Uuid::parse_str(&params.uuid) {
+        Ok(id) => id,
+        Err(e) => {
+            let res = bad_request(&state, format!("Invalid UUID: {}", e));
+            return (state, res);
+        }
+    };
 
-                if let Some(update_tx) = &job.update_tx {
-                    add_update_channel(job_uuid, update_tx.clone());
-                }
+    // The same limits apply as to skipped objects.
+    let limit = query.limit.unwrap_or(DEFAULT_SKIPPED_LIMIT);
+    let offset = query.offset.unwrap_or(0);
 
-                if let Err(e) = self.tx.send(job) {
-                    panic!("Tx error: {}", e);
-                }
+    if limit < 0 || limit > status::MAX_SKIPPED_LIMIT || offset < 0 {
+        let msg = format!(
+            "limit must be between 0 and {}, and offset must not be negative",
+            status::MAX_SKIPPED_LIMIT
+        );
+        let res = bad_request(&state, msg);
+        return (state, res);
+    }
 
-                create_response(
-                    &state,
-                    StatusCode::OK,
-                    mime::APPLICATION_JSON,
-                    uuid_response,
-                )
+    let res = match status::get_assignments(&uuid, limit, offset) {
+        Ok(assignments) => match serde_json::to_string(&assignments) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Assignments: {}", e);
+                invalid_server_error(&state, msg)
             }
-        };
+        },
+        Err(e) => skipped_status_error(&state, &uuid, e),
+    };
 
-        Box::new(future::ok((state, ret)))
-    }
+    (state, res)
 }
 
-#[derive(NewMiddleware, Copy, Clone)]
-struct BaseMiddleware;
+fn get_assignment(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_assignment"));
 
-impl Middleware for BaseMiddleware {
-    fn call<Chain>(self, state: State, chain: Chain) -> Box<HandlerFuture>
-    where
-        Chain: FnOnce(State) -> Box<HandlerFuture> + Send + 'static,
-    {
-        let result = chain(state);
-        let f = result.then(move |result| {
-            let (state, mut response) = match result {
-                Ok((s, r)) => (s, r),
-                Err((s, err)) => {
-                    let res = err.into_response(&s);
-                    (s, res)
-                }
-            };
-            let headers = response.headers_mut();
-            let version_str = get_version();
-            let value = HeaderValue::from_str(&version_str).unwrap();
-            headers.insert("Server", value);
+    let params = GetAssignmentParams::take_from(&mut state);
+    let (job_uuid, assignment_uuid) = match (
+        Uuid::parse_str(&params.uuid),
+        Uuid::parse_str(&params.assignment_uuid),
+    ) {
+        (Ok(j), Ok(a)) => (j, a),
+        _ => {
+            let res = bad_request(&state, "Invalid UUID".into());
+            return (state, res);
+        }
+    };
 
-            future::ok((state, response))
-        });
+    info!(
+        "Get Assignment {} Request (job {})",
+        assignment_uuid, job_uuid
+    );
 
-        Box::new(f)
-    }
+    let res = match status::get_assignment(&job_uuid, &assignment_uuid) {
+        Ok(Some(lifecycle)) => match serde_json::to_string(&lifecycle) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error Getting Assignment: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Ok(None) => missing(
+            &state,
+            StatusCode::BAD_REQUEST,
+            format!("Could not find assignment {}", assignment_uuid),
+        ),
+        Err(e) => skipped_status_error(&state, &job_uuid, e),
+    };
+
+    (state, res)
 }
 
-#[derive(NewMiddleware, Copy, Clone)]
-struct DBConnMiddleware;
+type JobListFuture =
+    Box<dyn Future<Item = Vec<JobDbEntry>, Error = StatusError> + Send>;
 
-#[derive(StateData)]
-struct DBConnMiddlewareData {
-    db_conn: PgConnection,
+fn get_job_list(filter: &JobListFilter) -> JobListFuture {
+    Box::new(match jobs::status::list_jobs(filter) {
+        Ok(list) => future::ok(list),
+        Err(e) => future::err(e),
+    })
 }
 
-impl Middleware for DBConnMiddleware {
-    fn call<Chain>(self, mut state: State, chain: Chain) -> Box<HandlerFuture>
-    where
-        Chain: FnOnce(State) -> Box<HandlerFuture> + Send + 'static,
+fn job_list_filter(
+    params: JobListQueryParams,
+) -> Result<JobListFilter, String> {
+    let state = match params.state {
+        Some(s) => Some(
+            JobState::from_str(&s)
+                .map_err(|_| format!("Unknown job state: {}", s))?,
+        ),
+        None => None,
+    };
+
+    let action = match params.action {
+        Some(a) => Some(
+            JobActionDbEntry::from_str(&a)
+                .map_err(|_| format!("Unknown job action: {}", a))?,
+        ),
+        None => None,
+    };
+
+    let order = match params.order {
+        Some(o) => JobListOrder::from_str(&o)
+            .map_err(|_| format!("order must be asc or desc: {}", o))?,
+        None => JobListOrder::default(),
+    };
+
+    if params.limit.map_or(false, |l| l < 0)
+        || params.offset.map_or(false, |o| o < 0)
     {
-        let db_conn = match connect_db(REBALANCER_DB) {
-            Ok(dbc) => dbc,
-            Err(e) => {
-                let msg = format!("Could not connect to rebalancer DB: {}", e);
-                let res = invalid_server_error(&state, msg);
+        return Err(String::from("limit and offset must not be negative"));
+    }
+
+    Ok(JobListFilter {
+        state,
+        action,
+        created_after: params.created_after,
+        created_before: params.created_before,
+        limit: params.limit,
+        offset: params.offset,
+        order,
+    })
+}
+
+fn list_jobs(mut state: State) -> Box<HandlerFuture> {
+    metrics_request_inc(Some("list_jobs"));
+    info!("List Jobs Request");
+
+    let filter =
+        match job_list_filter(JobListQueryParams::take_from(&mut state)) {
+            Ok(f) => f,
+            Err(msg) => {
+                let res = bad_request(&state, msg);
                 return Box::new(future::ok((state, res)));
             }
         };
 
-        state.put(DBConnMiddlewareData { db_conn });
-        chain(state)
-    }
+    let job_list_future = get_job_list(&filter);
+    Box::new(job_list_future.then(move |result| match result {
+        Ok(list) => {
+            let jobs = match serde_json::to_string(&list) {
+                Ok(j) => j,
+                Err(e) => {
+                    let msg = format!("Error Getting Job List: {}", e);
+                    let ret = invalid_server_error(&state, msg);
+                    return future::ok((state, ret));
+                }
+            };
+            let res = create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                jobs,
+            );
+            future::ok((state, res))
+        }
+        Err(e) => {
+            let msg = format!("Error Getting Job List: {:#?}", e);
+            let ret = invalid_server_error(&state, msg);
+            future::ok((state, ret))
+        }
+    }))
 }
 
-fn router(config: Arc<Mutex<Config>>) -> Router {
-    let (tx, rx) = crossbeam_channel::bounded(5);
+fn update_job(mut state: State) -> (State, Response<Body>) {
+    use crate::jobs::jobs::dsl::jobs as jobs_db;
+
+    let db_conn = DBConnMiddlewareData::take_from(&mut state).db_conn;
+    let update_job_params = UpdateJobParams::take_from(&mut state);
+    let uuid =
+        Uuid::from_str(update_job_params.uuid.as_str()).expect("uuid from str");
+    let tx: crossbeam_channel::Sender<JobUpdateRequest>;
+
+    let job_db_entry: JobDbEntry = match jobs_db
+        .find(update_job_params.uuid.as_str())
+        .first(&db_conn)
+    {
+        Ok(jdbe) => jdbe,
+        Err(_) => {
+            let msg = format!("Could not find job {}", uuid);
+            let res = missing(&state, StatusCode::BAD_REQUEST, msg);
+            return (state, res);
+        }
+    };
+
+    if job_db_entry.state != JobState::Running {
+        let res = bad_request(
+            &state,
+            "Attempt to update job that is not running".into(),
+        );
+        return (state, res);
+    }
+
+    tx = match get_update_channel(uuid) {
+        Ok(t) => t,
+        Err(e) => {
+            // A job being run by another manager can only be updated there.
+            let res = match lease::remote_owner(&uuid.to_string()) {
+                Some(owner) => conflict(
+                    &state,
+                    format!("Job {} is running on manager {}", uuid, owner),
+                ),
+                None => bad_request(&state, e),
+            };
+            return (state, res);
+        }
+    };
+
+    #[allow(clippy::single_match)]
+    let update_message = match job_db_entry.action {
+        JobActionDbEntry::Evacuate
+        | JobActionDbEntry::CreateCopy
+        | JobActionDbEntry::RemoveCopy => {
+            let evac_msg =
+                match state.json_body::<EvacuateJobUpdateMessage>().wait() {
+                    Ok(p) => p,
+                    Err(e) => {
+                        let msg = format!(
+                            "Could not parse Evacuate Update \
+                             Message: {}",
+                            e
+                        );
+                        let res = unprocessable(&state, msg);
+                        return (state, res);
+                    }
+                };
+
+            if let Err(e) = evac_msg.validate() {
+                let res = bad_request(&state, e);
+                return (state, res);
+            }
+
+            JobUpdateMessage::Evacuate(evac_msg)
+        }
+        _ => {
+            let res = bad_request(&state, "payload action mismatch".into());
+            return (state, res);
+        }
+    };
+
+    // Send update message down channel, and wait for the job to say whether
+    // it applied it.
+    let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+    let request = JobUpdateRequest {
+        message: update_message,
+        reply: reply_tx,
+    };
+
+    if let Err(e) = tx.send(request) {
+        let res = invalid_server_error(
+            &state,
+            format!("could not communicate with job: {}", e),
+        );
+
+        return (state, res);
+    }
+
+    let res = match reply_rx.recv_timeout(UPDATE_REPLY_TIMEOUT) {
+        Ok(Ok(())) => {
+            create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, "")
+        }
+        Ok(Err(e)) => bad_request(&state, e),
+        Err(e) => invalid_server_error(
+            &state,
+            format!("job did not reply to update: {}", e),
+        ),
+    };
+
+    (state, res)
+}
+
+fn cancel_assignment(mut state: State) -> (State, Response<Body>) {
+    use crate::jobs::jobs::dsl::jobs as jobs_db;
+
+    metrics_request_inc(Some("cancel_assignment"));
+
+    let db_conn = DBConnMiddlewareData::take_from(&mut state).db_conn;
+    let params = CancelAssignmentParams::take_from(&mut state);
+
+    let (job_uuid, assignment_uuid) = match (
+        Uuid::parse_str(&params.uuid),
+        Uuid::parse_str(&params.assignment_uuid),
+    ) {
+        (Ok(j), Ok(a)) => (j.to_string(), a.to_string()),
+        _ => {
+            let res = bad_request(&state, "Invalid UUID".into());
+            return (state, res);
+        }
+    };
+
+    info!(
+        "Cancel Assignment {} Request (job {})",
+        assignment_uuid, job_uuid
+    );
+
+    let job_db_entry: JobDbEntry =
+        match jobs_db.find(job_uuid.as_str()).first(&db_conn) {
+            Ok(jdbe) => jdbe,
+            Err(_) => {
+                let msg = format!("Could not find job {}", job_uuid);
+                let res = missing(&state, StatusCode::BAD_REQUEST, msg);
+                return (state, res);
+            }
+        };
+
+    // Only a running job has an assignment checker that will collect the
+    // cancelled assignment from the agent and account for its objects.
+    if job_db_entry.state != JobState::Running {
+        let res = bad_request(
+            &state,
+            "Attempt to cancel assignment of job that is not running".into(),
+        );
+        return (state, res);
+    }
+
+    let res = match evacuate::cancel_assignment(&job_uuid, &assignment_uuid) {
+        Ok(()) => {
+            create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, "")
+        }
+        Err(CancelAssignmentError::NotFound) => missing(
+            &state,
+            StatusCode::BAD_REQUEST,
+            format!("Could not find assignment {}", assignment_uuid),
+        ),
+        Err(CancelAssignmentError::AlreadyComplete) => {
+            let msg =
+                format!("Assignment {} is already complete", assignment_uuid);
+            conflict(&state, msg)
+        }
+        Err(CancelAssignmentError::AgentError(msg)) => {
+            error!("{}", msg);
+            error_response(
+                &state,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                RebalancerError::Unreachable,
+                msg,
+            )
+        }
+    };
+
+    (state, res)
+}
+
+// A second evacuation of a shark only repeats the work of the first, and
+// has its metadata updates conflict with those of the first, so it is refused
+// with the uuid of the job that is already evacuating the shark.
+fn already_evacuating(
+    state: &State,
+    shark: &str,
+    active: Uuid,
+) -> Response<Body> {
+    warn!("Job {} is already evacuating {}", active, shark);
+    create_response(
+        state,
+        StatusCode::CONFLICT,
+        mime::APPLICATION_JSON,
+        format!("{}\n", active),
+    )
+}
+
+// Queue a newly committed job.  Failure here means that we could not record
+// the job's state, so the job is not run.
+fn queue_job(
+    queue: &JobQueue,
+    job: jobs::Job,
+    priority: JobPriority,
+) -> Result<(), String> {
+    let job_uuid = job.get_id();
+
+    queue.push(job, priority).map_err(|e| {
+        remove_update_channel(job_uuid);
+        format!("Could not queue job {}: {}", job_uuid, e)
+    })
+}
+
+// A max_objects of 0 means no limit, and one that is not given means 10.
+fn job_max_objects(max_objects: Option<u32>) -> Option<u32> {
+    match max_objects {
+        Some(0) => None,
+        Some(val) => Some(val),
+        None => Some(10), // Default
+    }
+}
+
+// A job that takes its destinations from a sharks file is refused up front
+// if the file cannot be used, rather than failing once it starts.
+fn check_sharks_file(config: &Config) -> Result<(), String> {
+    match &config.sharks_file {
+        Some(path) => storinfo::SharksFile::new(path).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+// As with the sharks file, a job that is to read its objects from a file is
+// refused up front if the file cannot be read.
+fn check_source(job_source: &Option<ObjectSource>) -> Result<(), String> {
+    match job_source {
+        Some(ObjectSource::File { path }) => source::open(path)
+            .map(|_| ())
+            .map_err(|e| format!("Could not open source {}: {}", path, e)),
+        _ => Ok(()),
+    }
+}
+
+// Narrow the job's copy of the configuration to the shards that the job
+// asked to scan, and set how many of them it scans at once.
+fn select_job_shards(
+    config: &mut Config,
+    min_shard: Option<u32>,
+    max_shard: Option<u32>,
+    shards: &[u32],
+    scan_parallelism: Option<u32>,
+) -> Result<(), String> {
+    if min_shard.is_some() || max_shard.is_some() || !shards.is_empty() {
+        config.select_shards(min_shard, max_shard, shards)?;
+    }
+
+    if let Some(parallelism) = scan_parallelism {
+        config.options.max_md_read_threads = parallelism as usize;
+    }
+
+    Ok(())
+}
+
+// Commit a newly built job and queue it, responding with its uuid.
+fn submit_job(
+    state: &State,
+    queue: &JobQueue,
+    builder: JobBuilder,
+    priority: JobPriority,
+) -> Response<Body> {
+    let job = match builder.commit() {
+        Ok(j) => j,
+        Err(e) => {
+            return invalid_server_error(state, String::from(e.description()))
+        }
+    };
+
+    let job_uuid = job.get_id();
+    let uuid_response = format!("{}\n", &job_uuid);
+
+    if let Some(update_tx) = &job.update_tx {
+        add_update_channel(job_uuid, update_tx.clone());
+    }
+
+    if let Err(e) = queue_job(queue, job, priority) {
+        return invalid_server_error(state, e);
+    }
+
+    create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        uuid_response,
+    )
+}
+
+// Start the job for a step of an evacuation plan, much as if it had been
+// posted, with the plan's max_fill_percentage.
+fn plan_step_submitter(
+    queue: Arc<JobQueue>,
+    config: Arc<Mutex<Config>>,
+) -> plan::SubmitStep {
+    Box::new(move |shark: &str, max_fill_percentage: u32| {
+        let mut config = config.lock().expect("config lock").clone();
+
+        if config.snaplink_cleanup_required && !config.options.resolve_snaplinks
+        {
+            return Err(String::from("Snaplink Cleanup Required"));
+        }
+
+        if config.verification.agentless {
+            return Err(String::from(
+                "The manager is in agent-less verification mode",
+            ));
+        }
+
+        if let Some(active) = queue.active_evacuation(shark) {
+            return Err(format!(
+                "Job {} is already evacuating {}",
+                active, shark
+            ));
+        }
+
+        config.max_fill_percentage = max_fill_percentage;
+        check_sharks_file(&config)?;
+
+        let job = JobBuilder::new(config)
+            .evacuate(shark.to_string(), None)
+            .commit()
+            .map_err(|e| e.to_string())?;
+        let job_uuid = job.get_id();
+
+        if let Some(update_tx) = &job.update_tx {
+            add_update_channel(job_uuid, update_tx.clone());
+        }
+
+        queue_job(&queue, job, JobPriority::default())?;
+        Ok(job_uuid)
+    })
+}
+
+// Recover the jobs that were left running or queued by a manager that did not
+// shut down gracefully, and queue a new job for each that was interrupted.
+// This is done at startup, and again whenever another manager's leases lapse.
+fn recover_and_resume_jobs(queue: &JobQueue, config: &Config) {
+    // Any job that is still running or queued according to the database was
+    // left that way by a manager that did not shut down gracefully.
+    if let Err(e) = jobs::recover_crashed_jobs() {
+        error!("Error recovering jobs: {}", e);
+    }
+
+    // Pick up where any jobs interrupted by the last shutdown left off.
+    match jobs::resume_interrupted_jobs(config) {
+        Ok(resumed) => {
+            for job in resumed {
+                if let Some(update_tx) = &job.update_tx {
+                    add_update_channel(job.get_id(), update_tx.clone());
+                }
+
+                if let Err(e) = queue_job(queue, job, JobPriority::default()) {
+                    error!("{}", e);
+                }
+            }
+        }
+        Err(e) => error!("Error resuming interrupted jobs: {}", e),
+    }
+}
+
+// Take over the jobs of managers whose leases have lapsed.
+fn lease_takeover(
+    queue: Arc<JobQueue>,
+    config: Arc<Mutex<Config>>,
+) -> lease::TakeOver {
+    Box::new(move || {
+        let config = config.lock().expect("config lock").clone();
+        recover_and_resume_jobs(&queue, &config);
+    })
+}
+
+// The list of sharks that a plan is made from and checked against, polling
+// storinfo for it if it has not been received yet.
+fn plan_sharks(
+    config: &Arc<Mutex<Config>>,
+) -> Result<Arc<storinfo::StorinfoSnapshot>, String> {
+    let (domain, storinfo_config) = {
+        let config = config.lock().expect("config lock");
+        (config.domain_name.clone(), config.storinfo)
+    };
+    let poller = storinfo::shared(&domain, &storinfo_config);
+
+    if poller.snapshot().is_none() {
+        poller.poll();
+    }
+
+    poller.snapshot().ok_or_else(|| {
+        String::from("No list of sharks has been received from storinfo")
+    })
+}
+
+fn plan_response(
+    state: &State,
+    result: Result<Plan, PlanError>,
+) -> Response<Body> {
+    match result {
+        Ok(plan) => match serde_json::to_string(&plan) {
+            Ok(body) => create_response(
+                state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error serializing plan: {}", e);
+                invalid_server_error(state, msg)
+            }
+        },
+        Err(PlanError::NotFound) => missing(
+            state,
+            StatusCode::BAD_REQUEST,
+            "Could not find plan".into(),
+        ),
+        Err(e @ PlanError::Invalid(_)) | Err(e @ PlanError::NotAllowed(..)) => {
+            bad_request(state, e.to_string())
+        }
+        Err(e @ PlanError::InsufficientCapacity { .. }) => error_response(
+            state,
+            StatusCode::CONFLICT,
+            RebalancerError::InsufficientSpace,
+            e.to_string(),
+        ),
+        Err(PlanError::Db(e)) => {
+            error!("Plan error: {}", e);
+            invalid_server_error(state, e.to_string())
+        }
+    }
+}
+
+// Every evacuation plan, with its steps.
+fn list_plans(state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("list_plans"));
+    info!("List Plans Request");
+
+    let res = match plan::list_plans() {
+        Ok(plans) => match serde_json::to_string(&plans) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error serializing plans: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        },
+        Err(e) => {
+            let msg = format!("Error Getting Plan List: {}", e);
+            invalid_server_error(&state, msg)
+        }
+    };
+
+    (state, res)
+}
+
+fn plan_uuid(state: &mut State) -> Result<Uuid, String> {
+    let params = PlanParams::take_from(state);
+    Uuid::parse_str(&params.uuid).map_err(|e| format!("Invalid UUID: {}", e))
+}
+
+fn get_plan(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_plan"));
+
+    let res = match plan_uuid(&mut state) {
+        Ok(uuid) => {
+            info!("Get Plan {} Request", uuid);
+            plan_response(&state, plan::get_plan(&uuid.to_string()))
+        }
+        Err(msg) => bad_request(&state, msg),
+    };
+
+    (state, res)
+}
+
+fn control_plan(
+    mut state: State,
+    action: PlanAction,
+) -> (State, Response<Body>) {
+    metrics_request_inc(Some("control_plan"));
+
+    let res = match plan_uuid(&mut state) {
+        Ok(uuid) => {
+            info!("Plan {} {} Request", uuid, action);
+            plan_response(&state, plan::control_plan(&uuid.to_string(), action))
+        }
+        Err(msg) => bad_request(&state, msg),
+    };
+
+    (state, res)
+}
+
+fn pause_plan(state: State) -> (State, Response<Body>) {
+    control_plan(state, PlanAction::Pause)
+}
+
+fn resume_plan(state: State) -> (State, Response<Body>) {
+    control_plan(state, PlanAction::Resume)
+}
+
+fn abort_plan(state: State) -> (State, Response<Body>) {
+    control_plan(state, PlanAction::Abort)
+}
+
+#[derive(Clone)]
+struct PlanCreateHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for PlanCreateHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for PlanCreateHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("create_plan"));
+        info!("Post Plan Request");
+
+        let payload = match state.json_body::<PlanPayload>().wait() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Payload error: {}", &e);
+                return Box::new(future::err((state, e)));
+            }
+        };
+
+        if let Err(e) = payload.validate() {
+            let res = bad_request(&state, e);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let snapshot = match plan_sharks(&self.config) {
+            Ok(s) => s,
+            Err(msg) => {
+                let res = invalid_server_error(&state, msg);
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        let max_fill_percentage =
+            self.config.lock().expect("config lock").max_fill_percentage;
+        let res = plan_response(
+            &state,
+            plan::create_plan(&payload, &snapshot.sharks, max_fill_percentage),
+        );
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct PlanExecuteHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for PlanExecuteHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for PlanExecuteHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("execute_plan"));
+
+        let uuid = match plan_uuid(&mut state) {
+            Ok(id) => id,
+            Err(msg) => {
+                let res = bad_request(&state, msg);
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        info!("Execute Plan {} Request", uuid);
+
+        if shutdown::requested() {
+            let res = shutting_down(&state);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let snapshot = match plan_sharks(&self.config) {
+            Ok(s) => s,
+            Err(msg) => {
+                let res = invalid_server_error(&state, msg);
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        let res = plan_response(
+            &state,
+            plan::execute_plan(&uuid.to_string(), &snapshot.sharks),
+        );
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+// What an evacuate job would do, without starting one.  The payload is read
+// with the same overrides of the configuration as that of the job.
+#[derive(Clone)]
+struct EstimateHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for EstimateHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for EstimateHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("estimate"));
+        info!("Post Estimate Request");
+
+        let payload = match state.json_body::<EstimatePayload>().wait() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Payload error: {}", &e);
+                return Box::new(future::err((state, e)));
+            }
+        };
+
+        if let Err(e) = payload.validate() {
+            let res = bad_request(&state, e);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let mut config = self.config.lock().expect("config lock").clone();
+        let job = &payload.job;
+
+        if let Some(pct) = job.max_fill_percentage {
+            config.max_fill_percentage = pct;
+        }
+
+        if job.sharks_file.is_some() {
+            config.sharks_file = job.sharks_file.clone();
+        }
+
+        if let Err(e) = select_job_shards(
+            &mut config,
+            job.min_shard,
+            job.max_shard,
+            &job.shards,
+            job.scan_parallelism,
+        )
+        .and_then(|_| check_source(&job.source))
+        {
+            let res = bad_request(&state, e);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let sharks = match &config.sharks_file {
+            Some(path) => match storinfo::SharksFile::new(path) {
+                Ok(file) => file.get_sharks().unwrap_or_default(),
+                Err(e) => {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+            },
+            None => match plan_sharks(&self.config) {
+                Ok(s) => s.sharks.clone(),
+                Err(msg) => {
+                    let res = invalid_server_error(&state, msg);
+                    return Box::new(future::ok((state, res)));
+                }
+            },
+        };
+
+        let res = match estimate::estimate(&payload, &config, &sharks) {
+            Ok(estimate) => match serde_json::to_string(&estimate) {
+                Ok(body) => create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::APPLICATION_JSON,
+                    body,
+                ),
+                Err(e) => {
+                    let msg = format!("Error serializing estimate: {}", e);
+                    invalid_server_error(&state, msg)
+                }
+            },
+            Err(e) => {
+                let msg = format!("Could not estimate evacuation: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct ConfigHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for ConfigHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for ConfigHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("get_config"));
+        info!("Get Config Request");
+
+        let effective = self.config.lock().expect("config lock").effective();
+        let res = match serde_json::to_string(&effective) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error serializing configuration: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+// Liveness check: the manager is up and answering requests.
+fn ping(state: State) -> (State, Response<Body>) {
+    let res = create_response(
+        &state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        "{\"status\":\"OK\"}",
+    );
+
+    (state, res)
+}
+
+// The list of sharks most recently received from storinfo, as every job sees
+// it.
+fn get_storinfo(state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_storinfo"));
+    info!("Get Storinfo Request");
+
+    let snapshot = match storinfo::shared_snapshot() {
+        Some(s) => s,
+        None => {
+            let res = missing(
+                &state,
+                StatusCode::NOT_FOUND,
+                "No list of sharks has been received from storinfo".into(),
+            );
+            return (state, res);
+        }
+    };
+
+    let res = match serde_json::to_string(&*snapshot) {
+        Ok(body) => create_response(
+            &state,
+            StatusCode::OK,
+            mime::APPLICATION_JSON,
+            body,
+        ),
+        Err(e) => {
+            let msg = format!("Error serializing storinfo snapshot: {}", e);
+            invalid_server_error(&state, msg)
+        }
+    };
+
+    (state, res)
+}
+
+fn agents_response<T: serde::Serialize>(
+    state: &State,
+    agents: &T,
+) -> Response<Body> {
+    match serde_json::to_string(agents) {
+        Ok(body) => {
+            create_response(state, StatusCode::OK, mime::APPLICATION_JSON, body)
+        }
+        Err(e) => {
+            let msg = format!("Error serializing agents: {}", e);
+            invalid_server_error(state, msg)
+        }
+    }
+}
+
+// Quarantine an agent by hand, so that no job chooses it as a destination.
+fn quarantine_agent(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("quarantine_agent"));
+
+    let params = GetAgentParams::take_from(&mut state);
+    info!("Quarantine Agent {} Request", params.id);
+
+    let payload = match state.json_body::<QuarantinePayload>().wait() {
+        Ok(p) => p,
+        Err(e) => {
+            let msg = format!("Could not parse quarantine payload: {}", e);
+            let res = unprocessable(&state, msg);
+            return (state, res);
+        }
+    };
+
+    let status = quarantine::shared().quarantine(&params.id, &payload);
+    let res = agents_response(&state, &status);
+
+    (state, res)
+}
+
+// Release an agent from quarantine, whether it was quarantined for its
+// failures or by hand.
+fn release_agent(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("release_agent"));
+
+    let params = GetAgentParams::take_from(&mut state);
+    info!("Release Agent {} Request", params.id);
+
+    let res = match quarantine::shared().release(&params.id) {
+        Some(status) => agents_response(&state, &status),
+        None => missing(
+            &state,
+            StatusCode::NOT_FOUND,
+            format!("Nothing is known of agent {}", params.id),
+        ),
+    };
+
+    (state, res)
+}
+
+// Register an agent, or renew its registration, so that jobs only send it what
+// it says that it can do.
+fn register_agent(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("register_agent"));
+
+    let params = GetAgentParams::take_from(&mut state);
+    debug!("Register Agent {} Request", params.id);
+
+    let registration = match state.json_body::<AgentRegistration>().wait() {
+        Ok(r) => r,
+        Err(e) => {
+            let msg = format!("Could not parse registration: {}", e);
+            let res = unprocessable(&state, msg);
+            return (state, res);
+        }
+    };
+
+    if registration.storage_id != params.id {
+        let msg = format!(
+            "Registration is for {}, not {}",
+            registration.storage_id, params.id
+        );
+        let res = bad_request(&state, msg);
+        return (state, res);
+    }
+
+    let (agent, new) = registry::shared().register(registration);
+    if new {
+        info!(
+            "Agent {} registered: version {}, boot id {}, {:?}",
+            params.id,
+            agent.registration.version,
+            agent.registration.boot_id,
+            agent.registration.capabilities
+        );
+    }
+
+    let ack = RegistrationAck {
+        manager_version: get_version(),
+    };
+    let res = agents_response(&state, &ack);
+
+    (state, res)
+}
+
+// Record a corrupt object that an agent's scrubber has found.
+fn report_corrupt(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("report_corrupt"));
+
+    let params = GetAgentParams::take_from(&mut state);
+    debug!("Report Corrupt Object on {} Request", params.id);
+
+    let object = match state.json_body::<CorruptObject>().wait() {
+        Ok(o) => o,
+        Err(e) => {
+            let msg = format!("Could not parse corrupt object: {}", e);
+            let res = unprocessable(&state, msg);
+            return (state, res);
+        }
+    };
+
+    let (report, new) = corruption::shared().report(&params.id, object);
+    if new {
+        error!(
+            "Agent {} reports that its copy of {}/{} is corrupt",
+            params.id, report.object.owner, report.object.object_id
+        );
+    }
+    let res = agents_response(&state, &report);
+
+    (state, res)
+}
+
+// List every corrupt copy that agents have reported, to be repaired.
+fn list_corrupt(state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("list_corrupt"));
+    info!("List Corrupt Objects Request");
+
+    let res = agents_response(&state, &corruption::shared().list());
+
+    (state, res)
+}
+
+// Forget the corrupt copies reported by an agent, once they are repaired.
+fn clear_corrupt(mut state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("clear_corrupt"));
+
+    let params = GetAgentParams::take_from(&mut state);
+    info!("Clear Corrupt Objects on {} Request", params.id);
+
+    let cleared = corruption::shared().clear(&params.id);
+    let body = serde_json::json!({ "cleared": cleared });
+    let res = agents_response(&state, &body);
+
+    (state, res)
+}
+
+// The managers sharing the jobs database, and the jobs that each holds.
+fn list_managers(state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("list_managers"));
+    info!("List Managers Request");
+
+    let res = match lease::list_managers() {
+        Ok(managers) => agents_response(&state, &managers),
+        Err(e) => {
+            let msg = format!("Could not list managers: {}", e);
+            invalid_server_error(&state, msg)
+        }
+    };
+
+    (state, res)
+}
+
+// The versions of the manager and of its API, so that clients can tell
+// whether they are compatible with it.
+fn version(state: State) -> (State, Response<Body>) {
+    metrics_request_inc(Some("get_version"));
+
+    let info = VersionInfo::new(&get_version());
+    let res = match serde_json::to_string(&info) {
+        Ok(body) => create_response(
+            &state,
+            StatusCode::OK,
+            mime::APPLICATION_JSON,
+            body,
+        ),
+        Err(e) => {
+            let msg = format!("Error serializing version: {}", e);
+            invalid_server_error(&state, msg)
+        }
+    };
+
+    (state, res)
+}
+
+#[derive(Clone)]
+struct HealthcheckHandler {
+    queue: Arc<JobQueue>,
+}
+
+impl NewHandler for HealthcheckHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for HealthcheckHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        let health = ManagerHealth::check(self.queue.running());
+
+        if !health.healthy {
+            warn!("Healthcheck failed: {:?}", health);
+        }
+
+        let code = if health.healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        let res = match serde_json::to_string(&health) {
+            Ok(body) => {
+                create_response(&state, code, mime::APPLICATION_JSON, body)
+            }
+            Err(e) => {
+                let msg = format!("Error serializing health: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct AlertsHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for AlertsHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for AlertsHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("get_alerts"));
+        info!("Get Alerts Request");
+
+        let alerts_config = self.config.lock().expect("config lock").alerts;
+        let labels = rebalancer::metrics::get_const_labels()
+            .lock()
+            .expect("metrics const labels")
+            .clone()
+            .unwrap_or_else(HashMap::new);
+
+        let res = create_response(
+            &state,
+            StatusCode::OK,
+            mime::TEXT_PLAIN,
+            alerts::alert_rules(&alerts_config, &labels),
+        );
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+// The history of the agent on a storage node across jobs, and whether jobs
+// avoid it because of it.
+#[derive(Clone)]
+struct AgentHistoryHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for AgentHistoryHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for AgentHistoryHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("get_agent_history"));
+        info!("Get Agent History Request");
+
+        let params = GetAgentParams::take_from(&mut state);
+        let history_config =
+            self.config.lock().expect("config lock").agent_history;
+
+        let res = match history::get_agent_history(&params.id, &history_config)
+        {
+            Ok(Some(report)) => match serde_json::to_string(&report) {
+                Ok(body) => create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::APPLICATION_JSON,
+                    body,
+                ),
+                Err(e) => {
+                    let msg = format!("Error serializing agent history: {}", e);
+                    invalid_server_error(&state, msg)
+                }
+            },
+            Ok(None) => missing(
+                &state,
+                StatusCode::NOT_FOUND,
+                format!("No history of agent {}", params.id),
+            ),
+            Err(e) => {
+                let msg = format!("Error getting agent history: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+// Probe every agent in the fleet for its health, version and uptime, along
+// with how posting assignments to each has gone, across every job, and which
+// are quarantined.
+#[derive(Clone)]
+struct AgentsHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for AgentsHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for AgentsHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("get_agents"));
+        info!("Get Agents Request");
+
+        let snapshot = match plan_sharks(&self.config) {
+            Ok(s) => s,
+            Err(msg) => {
+                warn!("{}", msg);
+                let res = error_response(
+                    &state,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    RebalancerError::Unavailable,
+                    msg,
+                );
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        let fleet = fleet::probe_fleet(&snapshot.sharks);
+        let res = agents_response(&state, &fleet);
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct DestinationsHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for DestinationsHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for DestinationsHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("get_destinations"));
+        info!("Get Destinations Request");
+
+        let concentration = self
+            .config
+            .lock()
+            .expect("config lock")
+            .destination_concentration_percentage;
+        let report = projected::shared().destination_load(concentration);
+
+        let res = match serde_json::to_string(&report) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(e) => {
+                let msg = format!("Error serializing destination load: {}", e);
+                invalid_server_error(&state, msg)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct JobArchiveHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for JobArchiveHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for JobArchiveHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("archive_job"));
+
+        let job_params = GetJobParams::take_from(&mut state);
+        info!("Archive Job {} Request", job_params.uuid);
+
+        let uuid = match Uuid::parse_str(&job_params.uuid) {
+            Ok(id) => id,
+            Err(e) => {
+                let res = bad_request(&state, format!("Invalid UUID: {}", e));
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        let archive_dir = self
+            .config
+            .lock()
+            .expect("config lock")
+            .retention
+            .archive_dir
+            .clone();
+
+        let res = match retention::archive_job(&uuid, &archive_dir) {
+            Ok(archived) => match serde_json::to_string(&archived) {
+                Ok(body) => create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::APPLICATION_JSON,
+                    body,
+                ),
+                Err(e) => {
+                    let msg = format!("Error serializing archived job: {}", e);
+                    invalid_server_error(&state, msg)
+                }
+            },
+            Err(RetentionError::NotFound) => missing(
+                &state,
+                StatusCode::BAD_REQUEST,
+                format!("Could not find job UUID: {}", uuid),
+            ),
+            Err(RetentionError::NotFinished(job_state)) => bad_request(
+                &state,
+                format!("Job {} has not finished ({})", uuid, job_state),
+            ),
+            Err(RetentionError::Archive(e)) => {
+                error!("Error archiving job {}: {}", uuid, e);
+                invalid_server_error(&state, e.to_string())
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct JobConfirmHandler {
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for JobConfirmHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for JobConfirmHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        metrics_request_inc(Some("confirm_job"));
+
+        let job_params = GetJobParams::take_from(&mut state);
+        info!("Confirm Job {} Request", job_params.uuid);
+
+        let uuid = match Uuid::parse_str(&job_params.uuid) {
+            Ok(id) => id,
+            Err(e) => {
+                let res = bad_request(&state, format!("Invalid UUID: {}", e));
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        let payload = match state.json_body::<ConfirmJobPayload>().wait() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Payload error: {}", &e);
+                return Box::new(future::err((state, e)));
+            }
+        };
+
+        let res = match confirmation::confirm_job(
+            &uuid,
+            &payload.confirmed_by,
+            payload.comment,
+        ) {
+            Ok(confirmed) => {
+                let mut event =
+                    JobEvent::new(&uuid.to_string(), JobEventKind::Confirmed);
+                event.confirmed_by = Some(confirmed.confirmed_by.clone());
+
+                {
+                    let config = self.config.lock().expect("config lock");
+                    notify::send(&config.notifications, event);
+
+                    // A job that had to be confirmed is only complete now.
+                    hooks::run(
+                        &config.hooks,
+                        &uuid.to_string(),
+                        HookEvent::Complete,
+                        None,
+                    );
+                }
+
+                match serde_json::to_string(&confirmed) {
+                    Ok(body) => create_response(
+                        &state,
+                        StatusCode::OK,
+                        mime::APPLICATION_JSON,
+                        body,
+                    ),
+                    Err(e) => {
+                        let msg =
+                            format!("Error serializing confirmation: {}", e);
+                        invalid_server_error(&state, msg)
+                    }
+                }
+            }
+            Err(ConfirmError::NotFound) => missing(
+                &state,
+                StatusCode::BAD_REQUEST,
+                format!("Could not find job UUID: {}", uuid),
+            ),
+            Err(e @ ConfirmError::NotAwaiting(_))
+            | Err(e @ ConfirmError::MissingIdentity) => {
+                bad_request(&state, format!("Job {}: {}", uuid, e))
+            }
+            Err(ConfirmError::Db(e)) => {
+                error!("Error confirming job {}: {}", uuid, e);
+                invalid_server_error(&state, e.to_string())
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct JobRetryHandler {
+    queue: Arc<JobQueue>,
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for JobRetryHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for JobRetryHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        let job_params = GetJobParams::take_from(&mut state);
+        let retry_uuid = job_params.uuid;
+
+        info!("Retry Job {} Request", retry_uuid);
+
+        if shutdown::requested() {
+            let res = shutting_down(&state);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let config = self.config.lock().expect("config lock").clone();
+
+        // Only verify jobs can be run without agents, and they can not be
+        // retried.
+        if config.verification.agentless {
+            let res = bad_request(
+                &state,
+                String::from(
+                    "The manager is in agent-less verification mode and can \
+                     not retry jobs",
+                ),
+            );
+            return Box::new(future::ok((state, res)));
+        }
+
+        let job_builder = match JobBuilder::new(config).retry(&retry_uuid) {
+            Ok(jb) => jb,
+            Err(e) => {
+                let error =
+                    invalid_server_error(&state, String::from(e.description()));
+                return Box::new(future::ok((state, error)));
+            }
+        };
+
+        let job = match job_builder.commit() {
+            Ok(j) => j,
+            Err(e) => {
+                let error =
+                    invalid_server_error(&state, String::from(e.description()));
+                return Box::new(future::ok((state, error)));
+            }
+        };
+
+        let job_uuid = job.get_id();
+        let uuid_response = format!("{}\n", &job_uuid);
+
+        if let Some(update_tx) = &job.update_tx {
+            add_update_channel(job_uuid, update_tx.clone());
+        }
+
+        if let Err(e) = queue_job(&self.queue, job, JobPriority::default()) {
+            let error = invalid_server_error(&state, e);
+            return Box::new(future::ok((state, error)));
+        }
+
+        let res = create_response(
+            &state,
+            StatusCode::OK,
+            mime::APPLICATION_JSON,
+            uuid_response,
+        );
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+#[derive(Clone)]
+struct JobCreateHandler {
+    queue: Arc<JobQueue>,
+    config: Arc<Mutex<Config>>,
+}
+
+impl NewHandler for JobCreateHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+impl Handler for JobCreateHandler {
+    fn handle(self, mut state: State) -> Box<HandlerFuture> {
+        info!("Post Job Request");
+
+        if shutdown::requested() {
+            let res = shutting_down(&state);
+            return Box::new(future::ok((state, res)));
+        }
+
+        let mut config = self.config.lock().expect("config lock").clone();
+
+        // If snaplinks are still in play then we immediately return failure,
+        // unless jobs are to update them along with the objects they link to.
+        if config.snaplink_cleanup_required && !config.options.resolve_snaplinks
+        {
+            let error = invalid_server_error(
+                &state,
+                String::from("Snaplink Cleanup Required"),
+            );
+            return Box::new(future::ok((state, error)));
+        }
+
+        let payload = match state.json_body::<JobPayload>().wait() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Payload error: {}", &e);
+                return Box::new(future::err((state, e)));
+            }
+        };
+
+        if config.verification.agentless && payload.needs_agents() {
+            let res = bad_request(
+                &state,
+                String::from(
+                    "The manager is in agent-less verification mode and only \
+                     accepts verify jobs",
+                ),
+            );
+            return Box::new(future::ok((state, res)));
+        }
+
+        let ret = match payload {
+            JobPayload::Evacuate(evac_payload) => {
+                metrics_request_inc(Some("evacuate"));
+
+                if let Err(e) = evac_payload.validate() {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                if !evac_payload.queue_if_active.unwrap_or(false) {
+                    if let Some(active) =
+                        self.queue.active_evacuation(&evac_payload.from_shark)
+                    {
+                        let res = already_evacuating(
+                            &state,
+                            &evac_payload.from_shark,
+                            active,
+                        );
+                        return Box::new(future::ok((state, res)));
+                    }
+                }
+
+                let max_objects = job_max_objects(evac_payload.max_objects);
+
+                // The destination utilization ceiling may be lowered (or
+                // raised) for an individual job.  Every destination shark
+                // calculation made on behalf of this job uses the job's copy
+                // of the configuration.
+                if let Some(pct) = evac_payload.max_fill_percentage {
+                    config.max_fill_percentage = pct;
+                }
+
+                if let Some(slow_source) = evac_payload.slow_source {
+                    config.options.slow_source = slow_source;
+                }
+
+                if let Some(require) = evac_payload.require_confirmation {
+                    config.options.require_confirmation = require;
+                }
+
+                if let Some(verify) = evac_payload.verify_before_update {
+                    config.options.verify_before_update = verify;
+                }
+
+                if let Some(direct) = evac_payload.direct_pull {
+                    config.options.direct_pull = direct;
+                }
+
+                if evac_payload.sharks_file.is_some() {
+                    config.sharks_file = evac_payload.sharks_file;
+                }
+
+                if let Some(trace) = evac_payload.trace_placement {
+                    config.options.trace_placement = trace;
+                }
+
+                if let Some(policy) = evac_payload.checksum_policy {
+                    config.options.checksum_policy = policy;
+                }
+
+                if let Err(e) = select_job_shards(
+                    &mut config,
+                    evac_payload.min_shard,
+                    evac_payload.max_shard,
+                    &evac_payload.shards,
+                    evac_payload.scan_parallelism,
+                ) {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                if let Err(e) = check_sharks_file(&config)
+                    .and_then(|_| check_source(&evac_payload.source))
+                {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                let builder = JobBuilder::new(config)
+                    .evacuate(evac_payload.from_shark, max_objects)
+                    .max_dest_utilization(
+                        evac_payload.max_dest_utilization_percent,
+                    )
+                    .schedule(evac_payload.schedule)
+                    .filter(evac_payload.filter)
+                    .source(evac_payload.source);
+                let priority = evac_payload.priority.unwrap_or_default();
+
+                submit_job(&state, &self.queue, builder, priority)
+            }
+            JobPayload::CreateCopy(copy_payload) => {
+                metrics_request_inc(Some("create_copy"));
+
+                if let Err(e) = copy_payload.validate() {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                let max_objects = job_max_objects(copy_payload.max_objects);
+
+                if let Some(pct) = copy_payload.max_fill_percentage {
+                    config.max_fill_percentage = pct;
+                }
+
+                if let Some(require) = copy_payload.require_confirmation {
+                    config.options.require_confirmation = require;
+                }
+
+                if let Some(verify) = copy_payload.verify_before_update {
+                    config.options.verify_before_update = verify;
+                }
+
+                if let Some(direct) = copy_payload.direct_pull {
+                    config.options.direct_pull = direct;
+                }
+
+                if copy_payload.sharks_file.is_some() {
+                    config.sharks_file = copy_payload.sharks_file;
+                }
+
+                if let Some(trace) = copy_payload.trace_placement {
+                    config.options.trace_placement = trace;
+                }
+
+                if let Some(policy) = copy_payload.checksum_policy {
+                    config.options.checksum_policy = policy;
+                }
+
+                if let Err(e) = select_job_shards(
+                    &mut config,
+                    copy_payload.min_shard,
+                    copy_payload.max_shard,
+                    &copy_payload.shards,
+                    copy_payload.scan_parallelism,
+                ) {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                if let Err(e) = check_sharks_file(&config)
+                    .and_then(|_| check_source(&copy_payload.source))
+                {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                let builder = JobBuilder::new(config)
+                    .create_copy(
+                        copy_payload.shark,
+                        copy_payload.min_copies,
+                        max_objects,
+                    )
+                    .max_dest_utilization(
+                        copy_payload.max_dest_utilization_percent,
+                    )
+                    .schedule(copy_payload.schedule)
+                    .filter(copy_payload.filter)
+                    .source(copy_payload.source);
+                let priority = copy_payload.priority.unwrap_or_default();
+
+                submit_job(&state, &self.queue, builder, priority)
+            }
+            JobPayload::RemoveCopy(remove_payload) => {
+                metrics_request_inc(Some("remove_copy"));
+
+                if let Err(e) = remove_payload.validate() {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                let max_objects = job_max_objects(remove_payload.max_objects);
+
+                if let Some(require) = remove_payload.require_confirmation {
+                    config.options.require_confirmation = require;
+                }
+
+                if let Err(e) = select_job_shards(
+                    &mut config,
+                    remove_payload.min_shard,
+                    remove_payload.max_shard,
+                    &remove_payload.shards,
+                    remove_payload.scan_parallelism,
+                ) {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                if let Err(e) = check_source(&remove_payload.source) {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                let builder = JobBuilder::new(config)
+                    .remove_copy(
+                        remove_payload.shark,
+                        remove_payload.min_copies,
+                        max_objects,
+                    )
+                    .schedule(remove_payload.schedule)
+                    .filter(remove_payload.filter)
+                    .source(remove_payload.source);
+                let priority = remove_payload.priority.unwrap_or_default();
+
+                submit_job(&state, &self.queue, builder, priority)
+            }
+            JobPayload::Verify(verify_payload) => {
+                metrics_request_inc(Some("verify"));
+
+                let max_objects = job_max_objects(verify_payload.max_objects);
+
+                // A verify job changes nothing, so there is nothing for an
+                // operator to sign off.
+                config.options.require_confirmation = false;
+
+                let builder = JobBuilder::new(config)
+                    .verify(verify_payload.shark, max_objects);
+                let priority = verify_payload.priority.unwrap_or_default();
+
+                submit_job(&state, &self.queue, builder, priority)
+            }
+            JobPayload::Rollback(rollback_payload) => {
+                metrics_request_inc(Some("rollback"));
+
+                if let Err(e) = rollback_payload.validate() {
+                    let res = bad_request(&state, e);
+                    return Box::new(future::ok((state, res)));
+                }
+
+                // A rollback is there to undo the whole of a job that went
+                // wrong, so unlike other jobs it is not limited to a few
+                // objects unless it is asked to be.
+                let max_objects =
+                    rollback_payload.max_objects.filter(|m| *m > 0);
+
+                if let Some(require) = rollback_payload.require_confirmation {
+                    config.options.require_confirmation = require;
+                }
+
+                let builder = JobBuilder::new(config).rollback(
+                    rollback_payload.job_id,
+                    rollback_payload.dest_shark,
+                    rollback_payload.object_ids,
+                    max_objects,
+                );
+                let priority = rollback_payload.priority.unwrap_or_default();
+
+                submit_job(&state, &self.queue, builder, priority)
+            }
+        };
+
+        Box::new(future::ok((state, ret)))
+    }
+}
+
+#[derive(NewMiddleware, Copy, Clone)]
+struct BaseMiddleware;
+
+impl Middleware for BaseMiddleware {
+    fn call<Chain>(self, state: State, chain: Chain) -> Box<HandlerFuture>
+    where
+        Chain: FnOnce(State) -> Box<HandlerFuture> + Send + 'static,
+    {
+        let result = chain(state);
+        let f = result.then(move |result| {
+            let (state, mut response) = match result {
+                Ok((s, r)) => (s, r),
+                Err((s, err)) => {
+                    let res = err.into_response(&s);
+                    (s, res)
+                }
+            };
+            let headers = response.headers_mut();
+            let version_str = get_version();
+            let value = HeaderValue::from_str(&version_str).unwrap();
+            headers.insert("Server", value);
+
+            future::ok((state, response))
+        });
+
+        Box::new(f)
+    }
+}
+
+#[derive(NewMiddleware, Copy, Clone)]
+struct DBConnMiddleware;
+
+#[derive(StateData)]
+struct DBConnMiddlewareData {
+    db_conn: PgConnection,
+}
+
+impl Middleware for DBConnMiddleware {
+    fn call<Chain>(self, mut state: State, chain: Chain) -> Box<HandlerFuture>
+    where
+        Chain: FnOnce(State) -> Box<HandlerFuture> + Send + 'static,
+    {
+        let db_conn = match connect_db(REBALANCER_DB) {
+            Ok(dbc) => dbc,
+            Err(e) => {
+                let msg = format!("Could not connect to rebalancer DB: {}", e);
+                let res = invalid_server_error(&state, msg);
+                return Box::new(future::ok((state, res)));
+            }
+        };
+
+        state.put(DBConnMiddlewareData { db_conn });
+        chain(state)
+    }
+}
+
+// Start jobs from the queue as slots become available.  The limit on the
+// number of concurrently running jobs is read from the configuration each time
+// so that it can be changed while the manager is running.
+fn start_job_scheduler(queue: Arc<JobQueue>, config: Arc<Mutex<Config>>) {
+    thread::Builder::new()
+        .name(String::from("job scheduler"))
+        .spawn(move || loop {
+            let job = queue.next(|| {
+                let max = config
+                    .lock()
+                    .expect("config lock")
+                    .options
+                    .max_concurrent_jobs;
+                std::cmp::max(max, 1)
+            });
+            let job_queue = Arc::clone(&queue);
+
+            thread::Builder::new()
+                .name(format!("job {}", job.get_id()))
+                .spawn(move || {
+                    let job_id = job.get_id();
+
+                    // This blocks until the job is complete.  If the user
+                    // wants to see the status of the job, they can issue a
+                    // request to:
+                    //      /jobs/<job uuid>
+                    //
+                    // The job's own threads are supervised, but if the job
+                    // thread itself panics we still need to release its
+                    // slot in the queue and record that it did not finish.
+                    match watchdog::catch_panic(|| job.run()) {
+                        Ok(Ok(())) => (),
+                        Ok(Err(e)) => warn!("Error running job: {}", e),
+                        Err(payload) => {
+                            error!(
+                                "Job {} panicked: {}",
+                                job_id,
+                                watchdog::panic_message(&*payload)
+                            );
+                            if let Err(e) = jobs::mark_job_stopped(job_id) {
+                                error!(
+                                    "Could not mark job {} stopped: {}",
+                                    job_id, e
+                                );
+                            }
+                        }
+                    }
+
+                    remove_update_channel(job_id);
+                    job_queue.finished(&job_id);
+                })
+                .expect("start job thread");
+        })
+        .expect("start job scheduler");
+}
+
+fn router(config: Arc<Mutex<Config>>, queue: Arc<JobQueue>) -> Router {
+    let job_create_handler = JobCreateHandler {
+        queue: Arc::clone(&queue),
+        config: Arc::clone(&config),
+    };
+
+    let job_retry_handler = JobRetryHandler {
+        queue: Arc::clone(&queue),
+        config: Arc::clone(&config),
+    };
+
+    let job_archive_handler = JobArchiveHandler {
+        config: Arc::clone(&config),
+    };
+
+    let job_confirm_handler = JobConfirmHandler {
+        config: Arc::clone(&config),
+    };
+
+    let get_job_handler = GetJobHandler {
+        queue: Arc::clone(&queue),
+    };
+
+    let healthcheck_handler = HealthcheckHandler {
+        queue: Arc::clone(&queue),
+    };
+
+    let config_handler = ConfigHandler {
+        config: Arc::clone(&config),
+    };
+
+    let alerts_handler = AlertsHandler {
+        config: Arc::clone(&config),
+    };
+
+    let agents_handler = AgentsHandler {
+        config: Arc::clone(&config),
+    };
+
+    let destinations_handler = DestinationsHandler {
+        config: Arc::clone(&config),
+    };
+
+    let agent_history_handler = AgentHistoryHandler {
+        config: Arc::clone(&config),
+    };
+
+    let plan_create_handler = PlanCreateHandler {
+        config: Arc::clone(&config),
+    };
+
+    let plan_execute_handler = PlanExecuteHandler {
+        config: Arc::clone(&config),
+    };
+
+    let estimate_handler = EstimateHandler {
+        config: Arc::clone(&config),
+    };
+
+    // Start the metrics server, or whatever pushes the metrics instead.
+    let metrics_config = config.lock().expect("lock config").metrics.clone();
+    metrics_init(metrics_config);
+
+    start_job_scheduler(queue, config);
+
+    let ps_builder = new_pipeline_set();
+    let (ps_builder, base) =
+        ps_builder.add(new_pipeline().add(BaseMiddleware).build());
+
+    let (ps_builder, db_conn) = ps_builder.add(
+        new_pipeline()
+            .add(DBConnMiddleware)
+            .add(BaseMiddleware)
+            .build(),
+    );
+
+    let ps = finalize_pipeline_set(ps_builder);
+
+    let base_pipeline = (base, ());
+    let db_conn_pipeline = (db_conn, ());
+
+    // By default we use the BasePipeline.  If a given route needs a specific
+    // pipeline, then specify it via `.with_pipeline_chain()`
+    let rtr = build_router(base_pipeline, ps, |route| {
+        route.with_pipeline_chain(db_conn_pipeline, |route| {
+            route
+                .put("/jobs/:uuid")
+                .with_path_extractor::<UpdateJobParams>()
+                .to(update_job);
+            route
+                .post("/jobs/:uuid/assignments/:assignment_uuid/cancel")
+                .with_path_extractor::<CancelAssignmentParams>()
+                .to(cancel_assignment);
+        });
+        route
+            .post("/jobs")
+            .to_new_handler(job_create_handler.clone());
+        route
+            .post("/jobs/:uuid/retry")
+            .with_path_extractor::<GetJobParams>()
+            .to_new_handler(job_retry_handler.clone());
+        route
+            .post("/jobs/:uuid/archive")
+            .with_path_extractor::<GetJobParams>()
+            .to_new_handler(job_archive_handler.clone());
+        route
+            .post("/jobs/:uuid/confirm")
+            .with_path_extractor::<GetJobParams>()
+            .to_new_handler(job_confirm_handler.clone());
+        route
+            .get("/jobs/:uuid")
+            .with_path_extractor::<GetJobParams>()
+            .to_new_handler(get_job_handler.clone());
+        route
+            .get("/jobs/:uuid/assignments")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<AssignmentsQueryParams>()
+            .to(get_assignments);
+        route
+            .get("/jobs/:uuid/assignments/:assignment_uuid")
+            .with_path_extractor::<GetAssignmentParams>()
+            .to(get_assignment);
+        route
+            .get("/jobs/:uuid/skipped")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<SkippedQueryParams>()
+            .to(get_skipped);
+        route
+            .get("/jobs/:uuid/skipped/summary")
+            .with_path_extractor::<GetJobParams>()
+            .to(get_skipped_summary);
+        route
+            .get("/jobs/:uuid/slow")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<SlowQueryParams>()
+            .to(get_slow);
+        route
+            .get("/jobs/:uuid/headers")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<HeadersQueryParams>()
+            .to(get_headers);
+        route
+            .get("/jobs/:uuid/placement")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<PlacementQueryParams>()
+            .to(get_placement);
+        route
+            .get("/jobs/:uuid/audit")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<AuditQueryParams>()
+            .to(get_audit);
+        route
+            .get("/jobs/:uuid/export")
+            .with_path_extractor::<GetJobParams>()
+            .with_query_string_extractor::<ExportQueryParams>()
+            .to(get_export);
+        route
+            .get("/jobs")
+            .with_query_string_extractor::<JobListQueryParams>()
+            .to(list_jobs);
+        route
+            .post("/plans")
+            .to_new_handler(plan_create_handler.clone());
+        route.get("/plans").to(list_plans);
+        route
+            .get("/plans/:uuid")
+            .with_path_extractor::<PlanParams>()
+            .to(get_plan);
+        route
+            .post("/plans/:uuid/execute")
+            .with_path_extractor::<PlanParams>()
+            .to_new_handler(plan_execute_handler.clone());
+        route
+            .post("/plans/:uuid/pause")
+            .with_path_extractor::<PlanParams>()
+            .to(pause_plan);
+        route
+            .post("/plans/:uuid/resume")
+            .with_path_extractor::<PlanParams>()
+            .to(resume_plan);
+        route
+            .post("/plans/:uuid/abort")
+            .with_path_extractor::<PlanParams>()
+            .to(abort_plan);
+        route
+            .post("/estimate")
+            .to_new_handler(estimate_handler.clone());
+        route.get("/config").to_new_handler(config_handler.clone());
+        route.get("/alerts").to_new_handler(alerts_handler.clone());
+        route
+            .get("/destinations")
+            .to_new_handler(destinations_handler.clone());
+        route.get("/agents").to_new_handler(agents_handler.clone());
+        route
+            .put("/agents/:id/registration")
+            .with_path_extractor::<GetAgentParams>()
+            .to(register_agent);
+        route
+            .put("/agents/:id/quarantine")
+            .with_path_extractor::<GetAgentParams>()
+            .to(quarantine_agent);
+        route
+            .delete("/agents/:id/quarantine")
+            .with_path_extractor::<GetAgentParams>()
+            .to(release_agent);
+        route
+            .post("/agents/:id/corrupt")
+            .with_path_extractor::<GetAgentParams>()
+            .to(report_corrupt);
+        route
+            .delete("/agents/:id/corrupt")
+            .with_path_extractor::<GetAgentParams>()
+            .to(clear_corrupt);
+        route.get("/corrupt").to(list_corrupt);
+        route
+            .get("/agents/:id/history")
+            .with_path_extractor::<GetAgentParams>()
+            .to_new_handler(agent_history_handler.clone());
+        route.get("/storinfo").to(get_storinfo);
+        route.get("/managers").to(list_managers);
+        route.get("/ping").to(ping);
+        route.get("/version").to(version);
+        #[cfg(feature = "faults")]
+        route
+            .request(
+                vec![
+                    hyper::Method::GET,
+                    hyper::Method::PUT,
+                    hyper::Method::DELETE,
+                ],
+                "/faults",
+            )
+            .to(faults::faults_handler);
+        route
+            .get("/healthcheck")
+            .to_new_handler(healthcheck_handler.clone());
+    });
+
+    info!("Rebalancer Online");
+
+    rtr
+}
+
+// Parse and validate the configuration, printing whatever is wrong with it.
+fn load_config(config_file: &Option<String>) -> Option<Config> {
+    let config = match Config::parse_config(config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Error parsing config file: {}", e);
+            return None;
+        }
+    };
+
+    if let Err(errors) = config.validate() {
+        println!("Invalid configuration:");
+        for e in errors.iter() {
+            println!("  {}", e);
+        }
+        return None;
+    }
+
+    Some(config)
+}
+
+fn main() {
+    let matches: ArgMatches = App::new("rebalancer")
+        .version("0.1.0")
+        .about("Rebalancer")
+        .arg(
+            Arg::with_name("config_file")
+                .short("c")
+                .long("config_file")
+                .takes_value(true)
+                .value_name("CONFIG_FILE")
+                .help("Specify the location of the config file"),
+        )
+        .arg(
+            Arg::with_name("validate_config")
+                .long("validate-config")
+                .takes_value(true)
+                .value_name("CONFIG_FILE")
+                .conflicts_with("config_file")
+                .help("Check a config file and exit without starting"),
+        )
+        .get_matches();
+
+    // Only the panics of a job's threads are recovered from.
+    watchdog::install_panic_hook();
+
+    if let Some(path) = matches.value_of("validate_config") {
+        match load_config(&Some(path.to_string())) {
+            Some(config) => {
+                for notice in config.notices.iter() {
+                    println!("{}", notice);
+                }
+                println!("{} is valid", path);
+                std::process::exit(0);
+            }
+            None => std::process::exit(1),
+        }
+    }
+
+    // Nothing is started until the whole configuration is known to be good.
+    let config_file = matches.value_of("config_file").map(|s| s.to_string());
+    let config = load_config(&config_file).unwrap_or_else(|| {
+        std::process::exit(1);
+    });
+
+    let _guard = util::init_global_logger(Some(config.log_level));
+
+    config.log_effective();
+    pg_db::configure(&config.database);
+    agent_client::configure(&config.agent_client);
+    quarantine::configure(&config.agent_quarantine);
+    bandwidth::set_max_bytes_per_second(
+        config.options.max_aggregate_bytes_per_second,
+    );
+    faults::load_env();
+    lease::configure(&config.coordination);
+
+    let config = Arc::new(Mutex::new(config));
+
+    info!("Initializing...");
+
+    if let Err(e) = jobs::create_job_database() {
+        error!("Error creating Jobs database: {}", e);
+        return;
+    }
+
+    let queue = Arc::new(JobQueue::new());
+
+    // The sharks that evacuation plans are emptying must be known before any
+    // job is resumed, so that none of them is chosen as a destination.
+    if let Err(e) = plan::refresh_draining() {
+        error!("Error reading evacuation plans: {}", e);
+        return;
+    }
+
+    let resume_config = config.lock().expect("lock config").clone();
+    recover_and_resume_jobs(&queue, &resume_config);
+
+    // With other managers sharing the jobs database, this one keeps up its
+    // leases on its jobs, and takes over theirs if they lapse.
+    let _lease_handle = if lease::enabled() {
+        Some(lease::start_lease_keeper(lease_takeover(
+            Arc::clone(&queue),
+            Arc::clone(&config),
+        )))
+    } else {
+        None
+    };
+
+    let _shutdown_handle = shutdown::start_signal_handler(Arc::clone(&queue));
+    let _retention_handle =
+        retention::start_retention_thread(Arc::clone(&config));
+    let _plan_handle = plan::start_plan_coordinator(plan_step_submitter(
+        Arc::clone(&queue),
+        Arc::clone(&config),
+    ));
+
+    let addr = format!(
+        "0.0.0.0:{}",
+        config.lock().expect("lock config").listen_port
+    );
+
+    // The database is ready by now, but the API and metrics servers are only
+    // started below.  There is only a metrics server to wait for in http
+    // mode.
+    let metrics_config = config.lock().expect("lock config").metrics.clone();
+    let mut listen_addrs = vec![addr.clone()];
+    if metrics_config.mode == MetricsMode::Http {
+        listen_addrs
+            .push(format!("{}:{}", metrics_config.host, metrics_config.port));
+    }
+    let _readiness_handle = readiness::notify_when_listening(listen_addrs);
+
+    let config_watcher_handle =
+        Config::start_config_watcher(Arc::clone(&config), config_file);
+
+    gotham::start_with_num_threads(addr, router(Arc::clone(&config), queue), 1);
+
+    config_watcher_handle.join().expect("join config watcher");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gotham::test::{TestResponse, TestServer};
+    use lazy_static::lazy_static;
+    use manager::jobs::{
+        CreateCopyJobPayload, EvacuateJobPayload, JobPayload,
+        RemoveCopyJobPayload, RollbackJobPayload, VerifyJobPayload,
+    };
+    use rebalancer::common::TaskAction;
+    use rebalancer::error::{Error, InternalError};
+    use rebalancer_client::jobs::JobListEntry;
+    use std::sync::Mutex;
+    use std::thread;
+
+    lazy_static! {
+        static ref INITIALIZED: Mutex<bool> = Mutex::new(false);
+    }
+
+    fn unit_test_init() {
+        let mut init = INITIALIZED.lock().unwrap();
+        if *init {
+            return;
+        }
+
+        *init = true;
+
+        thread::spawn(move || {
+            let _guard = util::init_global_logger(None);
+            loop {
+                std::thread::sleep(std::time::Duration::from_millis(1))
+            }
+        });
+    }
+
+    fn get_job_list(
+        test_server: &TestServer,
+    ) -> Result<Vec<JobListEntry>, Error> {
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs")
+            .perform()
+            .expect("Get Job List");
+
+        if !response.status().is_success() {
+            let msg = format!("client get error: {}", response.status());
+            return Err(InternalError::new(None, msg).into());
+        }
+
+        let jobs_ret = response.read_body().expect("response body");
+        serde_json::from_slice(&jobs_ret).map_err(Error::from)
+    }
+
+    #[test]
+    fn list_jobs_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+
+        for query in &["state=bogus", "action=bogus", "order=up", "limit=-1"] {
+            let url = format!("http://localhost:8888/jobs?{}", query);
+            let response =
+                test_server.client().get(url).perform().expect("client get");
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs?state=running&order=desc&limit=5")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    fn job_list_contains(jobs: &Vec<JobListEntry>, id: &str) -> bool {
+        jobs.iter().any(|j| j.id.to_string() == id)
+    }
+
+    fn test_server_init() -> (Arc<Mutex<Config>>, TestServer) {
+        let config = Mutex::new(
+            Config::parse_config(&Some("src/config.json".to_string()))
+                .expect("config"),
+        );
+        let config = Arc::new(config);
+        let queue = Arc::new(JobQueue::new());
+        let test_server = TestServer::new(router(Arc::clone(&config), queue))
+            .expect("test server");
+        (config, test_server)
+    }
+
+    fn put_update(
+        test_server: &TestServer,
+        uuid: &Uuid,
+        update_msg: &EvacuateJobUpdateMessage,
+    ) -> TestResponse {
+        let put_url =
+            format!("http://localhost:8888/jobs/{}", uuid.to_string());
+        let update_msg = serde_json::to_string(update_msg).unwrap();
+        test_server
+            .client()
+            .put(put_url, update_msg, mime::APPLICATION_JSON)
+            .perform()
+            .expect("put update")
+    }
+
+    fn create_job(test_server: &TestServer, job_payload: JobPayload) -> String {
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let ret = response.read_utf8_body().expect("response body");
+        let ret = ret.trim_end();
+        assert!(Uuid::parse_str(ret).is_ok());
+
+        ret.to_string()
+    }
+
+    #[test]
+    fn basic() {
+        unit_test_init();
+        let (config, test_server) = test_server_init();
+
+        // Create a Job manually so that we know one exists regardless of the
+        // ability of this API to create one, or the order in which tests are
+        // run.
+        let config = config.lock().expect("lock config").clone();
+        let job_builder = JobBuilder::new(config);
+        let job = job_builder
+            .evacuate(String::from("fake_storage_id"), None)
+            .commit()
+            .expect("Failed to create job");
+        let job_id = job.get_id().to_string();
+        let job_list = get_job_list(&test_server).expect("get job list");
+
+        assert!(job_list.len() > 0);
+        assert!(job_list_contains(&job_list, &job_id));
+
+        let get_job_uri = format!("http://localhost:8888/jobs/{}", job_id);
+        let response = test_server
+            .client()
+            .get(get_job_uri)
+            .perform()
+            .expect("get job status response");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let ret = response.read_utf8_body().expect("response body");
+        let pretty_response: JobStatus =
+            serde_json::from_str(&ret).expect("job status hash");
+        println!("{:#?}", pretty_response);
+    }
+
+    #[test]
+    fn post_test() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+            from_shark: String::from("fake_storage_id"),
+            max_objects: Some(10),
+            ..Default::default()
+        });
+
+        let job_id = create_job(&test_server, job_payload);
+        println!("{}", job_id);
+    }
+
+    #[test]
+    fn post_bad_max_fill_percentage() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+            from_shark: String::from("fake_storage_id"),
+            max_fill_percentage: Some(101),
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_unconfigured_shard() {
+        unit_test_init();
+        let (config, test_server) = test_server_init();
+        let max_shard = config.lock().expect("config lock").max_shard_num();
+        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+            from_shark: String::from("fake_storage_id"),
+            shards: vec![max_shard + 1],
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_missing_source_file() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+            from_shark: String::from("fake_storage_id"),
+            source: Some(ObjectSource::File {
+                path: format!("/var/tmp/{}.json", Uuid::new_v4()),
+            }),
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_create_copy_bad_min_copies() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::CreateCopy(CreateCopyJobPayload {
+            shark: String::from("fake_storage_id"),
+            min_copies: Some(1),
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        assert!(payload.contains("\"action\":\"create-copy\""));
+
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_remove_copy_bad_min_copies() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::RemoveCopy(RemoveCopyJobPayload {
+            shark: String::from("fake_storage_id"),
+            min_copies: 0,
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        assert!(payload.contains("\"action\":\"remove-copy\""));
+
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_rollback_bad_job_id() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let job_payload = JobPayload::Rollback(RollbackJobPayload {
+            job_id: String::from("not a uuid"),
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        assert!(payload.contains("\"action\":\"rollback\""));
+
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn post_agentless_evacuate() {
+        unit_test_init();
+        let (config, test_server) = test_server_init();
+        config.lock().expect("config lock").verification.agentless = true;
+
+        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+            from_shark: String::from("fake_storage_id"),
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&job_payload)
+            .expect("serde serialize payload");
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs",
+                payload,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let job_payload = JobPayload::Verify(VerifyJobPayload {
+            shark: String::from("fake_storage_id"),
+            ..Default::default()
+        });
+        assert!(!job_payload.needs_agents());
+
+        let job_payload = JobPayload::Rollback(RollbackJobPayload {
+            job_id: Uuid::new_v4().to_string(),
+            ..Default::default()
+        });
+        assert!(job_payload.needs_agents());
+    }
+
+    #[test]
+    fn get_config() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/config")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().expect("response body");
+        let config: serde_json::Value =
+            serde_json::from_str(&body).expect("config from body");
+
+        assert_eq!(config["domain_name"], "east.joyent.us");
+        assert!(config["options"].is_object());
+    }
+
+    #[test]
+    fn ping_ok() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/ping")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn get_version_info() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/version")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().expect("response body");
+        let info: VersionInfo =
+            serde_json::from_str(&body).expect("version from body");
+
+        assert_eq!(info, VersionInfo::new(&get_version()));
+    }
+
+    #[test]
+    fn get_alerts() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/alerts")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().expect("response body");
+        assert!(body.starts_with("groups:\n"));
+        assert!(body.contains("alert: RebalancerAssignmentsStalled"));
+    }
+
+    #[test]
+    fn get_destinations() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/destinations")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().expect("response body");
+        let report: serde_json::Value =
+            serde_json::from_str(&body).expect("destinations json");
+        assert!(report["destinations"].is_array());
+        assert!(report["concentration_percentage"].is_u64());
+    }
+
+    #[test]
+    fn get_unknown_agent_history() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let url = format!(
+            "http://localhost:8888/agents/{}.stor.domain/history",
+            Uuid::new_v4()
+        );
+        let response =
+            test_server.client().get(url).perform().expect("client get");
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn register_agent_capabilities() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let agent = format!("{}.stor.domain", Uuid::new_v4());
+        let url =
+            format!("http://localhost:8888/agents/{}/registration", agent);
+        let body = format!(
+            "{{\"storage_id\": \"{}\", \"version\": \"0.1.0\", \
+             \"boot_id\": \"{}\", \"heartbeat_secs\": 60, \
+             \"capabilities\": {{\"actions\": [\"copy\"], \
+             \"max_tasks_per_assignment\": 50}}}}",
+            agent,
+            Uuid::new_v4()
+        );
+
+        // A registration must be for the agent that it is sent for.
+        let other =
+            "http://localhost:8888/agents/other.stor.domain/registration";
+        let response = test_server
+            .client()
+            .put(other, body.clone(), mime::APPLICATION_JSON)
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = test_server
+            .client()
+            .put(url.as_str(), "{}", mime::APPLICATION_JSON)
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        assert!(registry::supports(&agent, TaskAction::Delete));
+        let response = test_server
+            .client()
+            .put(url.as_str(), body, mime::APPLICATION_JSON)
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::OK);
+        let ack = response.read_utf8_body().expect("registration body");
+        assert!(ack.contains("manager_version"));
+
+        assert!(registry::supports(&agent, TaskAction::Copy));
+        assert!(!registry::supports(&agent, TaskAction::Delete));
+        assert_eq!(registry::max_tasks(&agent, 200), 50);
+    }
+
+    #[test]
+    fn quarantine_agent_by_hand() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let agent = format!("{}.stor.domain", Uuid::new_v4());
+        let url = format!("http://localhost:8888/agents/{}/quarantine", agent);
+
+        // Nothing is known of an agent until it fails or is quarantined.
+        let response = test_server
+            .client()
+            .delete(url.as_str())
+            .perform()
+            .expect("client delete");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = test_server
+            .client()
+            .put(
+                url.as_str(),
+                "{\"reason\": \"disk replacement\"}",
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(quarantine::is_quarantined(&agent));
+        let body = response.read_utf8_body().expect("quarantine body");
+        assert!(body.contains("disk replacement"));
+
+        let response = test_server
+            .client()
+            .delete(url.as_str())
+            .perform()
+            .expect("client delete");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!quarantine::is_quarantined(&agent));
+    }
+
+    // The rules here only delay, so that they do not get in the way of any
+    // other test that happens to poll an assignment meanwhile.
+    #[cfg(feature = "faults")]
+    #[test]
+    fn inject_faults_by_hand() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let url = "http://localhost:8888/faults";
+        let rules = r#"[{"point": "manager_assignment_get", "fault": "delay",
+            "delay_ms": 1, "times": 1}]"#;
+
+        let response = test_server
+            .client()
+            .put(url, rules, mime::APPLICATION_JSON)
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::OK);
+        let status: serde_json::Value = serde_json::from_str(
+            &response.read_utf8_body().expect("faults body"),
+        )
+        .expect("faults");
+        assert_eq!(
+            status,
+            serde_json::json!([{
+                "point": "manager_assignment_get",
+                "fault": "delay",
+                "delay_ms": 1,
+                "every": 1,
+                "times": 1,
+                "hits": 0,
+                "injected": 0,
+            }])
+        );
+
+        // A fault that can not be injected where it is asked for is refused,
+        // and the rules are left as they were.
+        let response = test_server
+            .client()
+            .put(
+                url,
+                r#"[{"point": "moray_update", "fault": "corrupt"}]"#,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client put");
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: ApiError = serde_json::from_str(
+            &response.read_utf8_body().expect("error body"),
+        )
+        .expect("API error");
+        assert_eq!(body.error, RebalancerError::InvalidRequest);
+        assert_eq!(faults::shared().status().len(), 1);
+
+        let response = test_server
+            .client()
+            .delete(url)
+            .perform()
+            .expect("client delete");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(faults::shared().status().is_empty());
+    }
+
+    #[test]
+    fn report_corrupt_objects() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let agent = format!("{}.stor.domain", Uuid::new_v4());
+        let url = format!("http://localhost:8888/agents/{}/corrupt", agent);
+        let object_id = Uuid::new_v4().to_string();
+        let body = format!(
+            "{{\"owner\": \"{}\", \"object_id\": \"{}\", \
+             \"md5sum\": \"1B2M2Y8AsgTpgAmY7PhCfg==\", \
+             \"found_md5sum\": \"XUFAKrxLKna5cZ2REBfFkg==\", \
+             \"size\": 5, \"detected\": 1600000000}}",
+            Uuid::new_v4(),
+            object_id
+        );
+
+        let response = test_server
+            .client()
+            .post(url.as_str(), "{}", mime::APPLICATION_JSON)
+            .perform()
+            .expect("client post");
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = test_server
+            .client()
+            .post(url.as_str(), body, mime::APPLICATION_JSON)
+            .perform()
+            .expect("client post");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/corrupt")
+            .perform()
+            .expect("client get");
+        assert_eq!(response.status(), StatusCode::OK);
+        let listed = response.read_utf8_body().expect("corrupt body");
+        assert!(listed.contains(&object_id));
+        assert!(listed.contains(&agent));
+
+        let response = test_server
+            .client()
+            .delete(url.as_str())
+            .perform()
+            .expect("client delete");
+        assert_eq!(response.status(), StatusCode::OK);
+        let cleared = response.read_utf8_body().expect("clear body");
+        assert!(cleared.contains("\"cleared\":1"));
+        assert!(corruption::shared()
+            .list()
+            .iter()
+            .all(|r| r.storage_id != agent));
+    }
+
+    #[test]
+    fn get_storinfo() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/storinfo")
+            .perform()
+            .expect("client get");
+
+        // Until a job has started the poller there is no list of sharks.
+        if response.status() == StatusCode::NOT_FOUND {
+            return;
+        }
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.read_utf8_body().expect("response body");
+        let snapshot: serde_json::Value =
+            serde_json::from_str(&body).expect("storinfo json");
+        assert!(snapshot["sharks"].is_array());
+        assert!(snapshot["generation"].is_u64());
+    }
+
+    #[test]
+    fn get_skipped_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+
+        for query in &["reason=not_a_reason", "limit=-1", "offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/skipped?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs/not-a-uuid/skipped/summary")
+            .perform()
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn get_slow_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+
+        for query in &["limit=-1", "limit=1001", "offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/slow?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-    let job_create_handler = JobCreateHandler {
-        tx: tx.clone(),
-        config: Arc::clone(&config),
-    };
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
 
-    let job_retry_handler = JobRetryHandler { tx, config };
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs/not-a-uuid/slow")
+            .perform()
+            .expect("client get");
 
-    // Start the metrics server.
-    metrics_init(rebalancer::metrics::ConfigMetrics::default());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 
-    let pool = ThreadPool::new(THREAD_COUNT);
-    for _ in 0..THREAD_COUNT {
-        let thread_rx = rx.clone();
-        pool.execute(move || loop {
-            let job = match thread_rx.recv() {
-                Ok(j) => j,
-                Err(e) => {
-                    error!("Error receiving job message: {}", e);
-                    return;
-                }
-            };
-            let job_id = job.get_id();
+    #[test]
+    fn get_headers_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-            // This blocks until the job is complete.  If the user wants to
-            // see the status of the job, they can issue a request to:
-            //      /jobs/<job uuid>
-            if let Err(e) = job.run() {
-                warn!("Error running job: {}", e);
-            }
+        for query in &["limit=-1", "limit=1001", "offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/headers?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-            remove_update_channel(job_id);
-        });
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
     }
 
-    let ps_builder = new_pipeline_set();
-    let (ps_builder, base) =
-        ps_builder.add(new_pipeline().add(BaseMiddleware).build());
+    #[test]
+    fn get_placement_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-    let (ps_builder, db_conn) = ps_builder.add(
-        new_pipeline()
-            .add(DBConnMiddleware)
-            .add(BaseMiddleware)
-            .build(),
-    );
+        for query in &["limit=-1", "limit=1001", "object=a&offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/placement?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-    let ps = finalize_pipeline_set(ps_builder);
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
 
-    let base_pipeline = (base, ());
-    let db_conn_pipeline = (db_conn, ());
+    #[test]
+    fn get_assignments_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-    // By default we use the BasePipeline.  If a given route needs a specific
-    // pipeline, then specify it via `.with_pipeline_chain()`
-    let rtr = build_router(base_pipeline, ps, |route| {
-        route.with_pipeline_chain(db_conn_pipeline, |route| {
-            route
-                .put("/jobs/:uuid")
-                .with_path_extractor::<UpdateJobParams>()
-                .to(update_job);
-        });
-        route
-            .post("/jobs")
-            .to_new_handler(job_create_handler.clone());
-        route
-            .post("/jobs/:uuid/retry")
-            .with_path_extractor::<GetJobParams>()
-            .to_new_handler(job_retry_handler.clone());
-        route
-            .get("/jobs/:uuid")
-            .with_path_extractor::<GetJobParams>()
-            .to(get_job);
-        route.get("/jobs").to(list_jobs);
-    });
+        for query in &["limit=-1", "limit=1001", "offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/assignments?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-    info!("Rebalancer Online");
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
 
-    rtr
-}
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs/not-a-uuid/assignments")
+            .perform()
+            .expect("client get");
 
-fn main() {
-    let matches: ArgMatches = App::new("rebalancer")
-        .version("0.1.0")
-        .about("Rebalancer")
-        .arg(
-            Arg::with_name("config_file")
-                .short("c")
-                .long("config_file")
-                .takes_value(true)
-                .value_name("CONFIG_FILE")
-                .help("Specify the location of the config file"),
-        )
-        .get_matches();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 
-    let config_file = matches.value_of("config_file").map(|s| s.to_string());
-    let config = Config::parse_config(&config_file).unwrap_or_else(|e| {
-        println!("Error parsing config file: {}", e);
-        std::process::exit(1);
-    });
+    #[test]
+    fn get_audit_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-    let _guard = util::init_global_logger(Some(config.log_level));
+        for query in &["limit=-1", "limit=1001", "offset=-1"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/audit?{}",
+                Uuid::new_v4(),
+                query
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-    let config = Arc::new(Mutex::new(config));
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
 
-    info!("Initializing...");
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs/not-a-uuid/audit")
+            .perform()
+            .expect("client get");
 
-    if let Err(e) = jobs::create_job_database() {
-        error!("Error creating Jobs database: {}", e);
-        return;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    let addr = format!(
-        "0.0.0.0:{}",
-        config.lock().expect("lock config").listen_port
-    );
-
-    let config_watcher_handle =
-        Config::start_config_watcher(Arc::clone(&config), config_file);
+    #[test]
+    fn get_export_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-    gotham::start_with_num_threads(addr, router(Arc::clone(&config)), 1);
+        // Parquet is only exported by rebalancer-adm.
+        for format in &["parquet", "xml"] {
+            let url = format!(
+                "http://localhost:8888/jobs/{}/export?format={}",
+                Uuid::new_v4(),
+                format
+            );
+            let response =
+                test_server.client().get(url).perform().expect("client get");
 
-    config_watcher_handle.join().expect("join config watcher");
-}
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use gotham::test::{TestResponse, TestServer};
-    use lazy_static::lazy_static;
-    use manager::jobs::{EvacuateJobPayload, JobPayload};
-    use rebalancer::error::{Error, InternalError};
-    use std::sync::Mutex;
-    use std::thread;
+        let response = test_server
+            .client()
+            .get("http://localhost:8888/jobs/not-a-uuid/export")
+            .perform()
+            .expect("client get");
 
-    lazy_static! {
-        static ref INITIALIZED: Mutex<bool> = Mutex::new(false);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    fn unit_test_init() {
-        let mut init = INITIALIZED.lock().unwrap();
-        if *init {
-            return;
-        }
-
-        *init = true;
+    #[test]
+    fn archive_job_bad_params() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
 
-        thread::spawn(move || {
-            let _guard = util::init_global_logger(None);
-            loop {
-                std::thread::sleep(std::time::Duration::from_millis(1))
-            }
-        });
+        // Neither an invalid UUID nor a job that does not exist can be
+        // archived.
+        for job in &["not-a-uuid".to_string(), Uuid::new_v4().to_string()] {
+            let url = format!("http://localhost:8888/jobs/{}/archive", job);
+            let response = test_server
+                .client()
+                .post(url, "", mime::APPLICATION_JSON)
+                .perform()
+                .expect("client post");
+
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
     }
 
-    fn get_job_list(
-        test_server: &TestServer,
-    ) -> Result<Vec<JobDbEntry>, Error> {
+    #[test]
+    fn cancel_assignment_bad_uuid() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let url = format!(
+            "http://localhost:8888/jobs/{}/assignments/not-a-uuid/cancel",
+            Uuid::new_v4()
+        );
         let response = test_server
             .client()
-            .get("http://localhost:8888/jobs")
+            .post(url, "", mime::APPLICATION_JSON)
             .perform()
-            .expect("Get Job List");
-
-        if !response.status().is_success() {
-            let msg = format!("client get error: {}", response.status());
-            return Err(InternalError::new(None, msg).into());
-        }
+            .expect("client post");
 
-        let jobs_ret = response.read_body().expect("response body");
-        serde_json::from_slice(&jobs_ret).map_err(Error::from)
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    fn job_list_contains(jobs: &Vec<JobDbEntry>, id: &str) -> bool {
-        jobs.iter().any(|j| j.id.to_string() == id)
-    }
+    #[test]
+    fn confirm_job_bad_uuid() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let body = r#"{ "confirmed_by": "operator" }"#;
+        let response = test_server
+            .client()
+            .post(
+                "http://localhost:8888/jobs/not-a-uuid/confirm",
+                body,
+                mime::APPLICATION_JSON,
+            )
+            .perform()
+            .expect("client post");
 
-    fn test_server_init() -> (Arc<Mutex<Config>>, TestServer) {
-        let config = Mutex::new(
-            Config::parse_config(&Some("src/config.json".to_string()))
-                .expect("config"),
-        );
-        let config = Arc::new(config);
-        let test_server =
-            TestServer::new(router(Arc::clone(&config))).expect("test server");
-        (config, test_server)
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    fn put_update(
-        test_server: &TestServer,
-        uuid: &Uuid,
-        update_msg: &EvacuateJobUpdateMessage,
-    ) -> TestResponse {
-        let put_url =
-            format!("http://localhost:8888/jobs/{}", uuid.to_string());
-        let update_msg = serde_json::to_string(update_msg).unwrap();
-        test_server
+    #[test]
+    fn get_plan_bad_uuid() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+        let response = test_server
             .client()
-            .put(put_url, update_msg, mime::APPLICATION_JSON)
+            .get("http://localhost:8888/plans/not-a-uuid")
             .perform()
-            .expect("put update")
-    }
+            .expect("client get");
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-    fn create_job(test_server: &TestServer, job_payload: JobPayload) -> String {
-        let payload = serde_json::to_string(&job_payload)
-            .expect("serde serialize payload");
         let response = test_server
             .client()
             .post(
-                "http://localhost:8888/jobs",
-                payload,
+                "http://localhost:8888/plans/not-a-uuid/abort",
+                "",
                 mime::APPLICATION_JSON,
             )
             .perform()
             .expect("client post");
 
-        assert_eq!(response.status(), StatusCode::OK);
-
-        let ret = response.read_utf8_body().expect("response body");
-        let ret = ret.trim_end();
-        assert!(Uuid::parse_str(ret).is_ok());
-
-        ret.to_string()
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[test]
-    fn basic() {
+    fn post_plan_bad_payload() {
         unit_test_init();
-        let (config, test_server) = test_server_init();
+        let (_, test_server) = test_server_init();
 
-        // Create a Job manually so that we know one exists regardless of the
-        // ability of this API to create one, or the order in which tests are
-        // run.
-        let config = config.lock().expect("lock config").clone();
-        let job_builder = JobBuilder::new(config);
-        let job = job_builder
-            .evacuate(String::from("fake_storage_id"), None)
-            .commit()
-            .expect("Failed to create job");
-        let job_id = job.get_id().to_string();
-        let job_list = get_job_list(&test_server).expect("get job list");
+        // Sharks and a datacenter can not both be given.
+        let body = r#"{ "sharks": ["1.stor.domain"], "datacenter": "dc1" }"#;
+        let response = test_server
+            .client()
+            .post("http://localhost:8888/plans", body, mime::APPLICATION_JSON)
+            .perform()
+            .expect("client post");
 
-        assert!(job_list.len() > 0);
-        assert!(job_list_contains(&job_list, &job_id));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 
-        let get_job_uri = format!("http://localhost:8888/jobs/{}", job_id);
+    #[test]
+    fn post_estimate_bad_payload() {
+        unit_test_init();
+        let (_, test_server) = test_server_init();
+
+        let body = r#"{ "from_shark": "1.stor.domain",
+            "throughput_mb_per_sec": 0 }"#;
         let response = test_server
             .client()
-            .get(get_job_uri)
+            .post(
+                "http://localhost:8888/estimate",
+                body,
+                mime::APPLICATION_JSON,
+            )
             .perform()
-            .expect("get job status response");
-
-        assert_eq!(response.status(), StatusCode::OK);
+            .expect("client post");
 
-        let ret = response.read_utf8_body().expect("response body");
-        let pretty_response: JobStatus =
-            serde_json::from_str(&ret).expect("job status hash");
-        println!("{:#?}", pretty_response);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[test]
-    fn post_test() {
+    fn get_assignment_bad_uuid() {
         unit_test_init();
         let (_, test_server) = test_server_init();
-        let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
-            from_shark: String::from("fake_storage_id"),
-            max_objects: Some(10),
-        });
+        let url = format!(
+            "http://localhost:8888/jobs/{}/assignments/not-a-uuid",
+            Uuid::new_v4()
+        );
+        let response =
+            test_server.client().get(url).perform().expect("client get");
 
-        let job_id = create_job(&test_server, job_payload);
-        println!("{}", job_id);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[test]
@@ -849,9 +4285,13 @@ mod tests {
         // We just manually put the channel in the UPDATE_CHANS hash, so
         // didn't actually create a job so we don't expect the job lookup to
         // succeed.
-        let expected_body = format!("Could not find job {}", uuid);
+        let expected_body = ApiError::new(
+            RebalancerError::NotFound,
+            format!("Could not find job {}", uuid),
+        );
         let res = put_update(&test_server, &uuid, &update_msg);
-        let res_body = res.read_utf8_body().unwrap();
+        let res_body: ApiError =
+            serde_json::from_slice(&res.read_body().unwrap()).unwrap();
 
         assert_eq!(expected_body, res_body);
 
@@ -866,6 +4306,7 @@ mod tests {
         let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
             from_shark: String::from("fake_storage_id"),
             max_objects: Some(10),
+            ..Default::default()
         });
         let job_id = create_job(&test_server, job_payload);
         let mut count = 0;
@@ -892,12 +4333,15 @@ mod tests {
             break;
         }
 
-        let expected_body =
-            format!("Attempt to update job that is not running");
+        let expected_body = ApiError::new(
+            RebalancerError::InvalidRequest,
+            "Attempt to update job that is not running",
+        );
 
         let job_uuid = Uuid::parse_str(&job_id).unwrap();
         let res = put_update(&test_server, &job_uuid, &update_msg);
-        let res_body = res.read_utf8_body().unwrap();
+        let res_body: ApiError =
+            serde_json::from_slice(&res.read_body().unwrap()).unwrap();
 
         assert_eq!(res_body, expected_body);
     }
@@ -8,12 +8,18 @@
  * Copyright 2020 Joyent, Inc.
  */
 use lazy_static::lazy_static;
-use prometheus::{opts, register_counter_vec, register_gauge};
+use prometheus::{
+    opts, register_counter, register_counter_vec, register_gauge,
+    register_histogram_vec, Gauge,
+};
 use rebalancer::metrics::{
-    self, counter_vec_inc_by, gauge_dec, gauge_inc, gauge_set, Metrics,
-    MetricsMap, ERROR_COUNT, OBJECT_COUNT, REQUEST_COUNT,
+    self, counter_vec_inc_by, gauge_dec, gauge_get, gauge_inc, gauge_set,
+    histogram_observe, histogram_vec_observe, Metrics, MetricsMap, ERROR_COUNT,
+    OBJECT_COUNT, OBJECT_SIZE, OBJECT_SIZE_FAILED, OUTCOME_FAILURE,
+    OUTCOME_SUCCESS, REQUEST_COUNT,
 };
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::thread;
 
@@ -44,6 +50,14 @@ lazy_static! {
     static ref METRICS: Mutex<Option<MetricsMap>> = Mutex::new(None);
     static ref METRICS_INIT: Mutex<MetricsInit> =
         Mutex::new(MetricsInit::new());
+
+    // The number of running jobs using each destination shark.  The per shark
+    // metrics of a shark are only exported while it is in use by at least one
+    // job, which keeps the number of label values proportional to the number
+    // of sharks in active jobs rather than to every shark that has ever been
+    // a destination.
+    static ref ACTIVE_SHARKS: Mutex<HashMap<String, usize>> =
+        Mutex::new(HashMap::new());
 }
 
 // There will likely be several other strings of this nature defined as the
@@ -57,6 +71,157 @@ pub static SKIP_COUNT: &str = "skip_count";
 // Gauge for tracking the current number of active metadata update threads.
 pub static MD_THREAD_GAUGE: &str = "md_thread_gauge";
 
+// The depths of each stage of an evacuate job's pipeline, summed over all
+// running jobs:
+//  * Objects that have been discovered, but not yet received by the
+//    assignment manager.
+//  * Objects held in memory waiting to be placed, whether discovered or given
+//    back to be placed again, and those given back that have been spilled to
+//    the job's database instead (see jobs::overflow).
+//  * Assignments that have been posted to an agent which have not yet been
+//    reported as complete.
+//  * Assignments that the agent has completed which are waiting for a
+//    metadata update worker.
+pub static OBJECT_QUEUE_DEPTH: &str = "object_queue_depth";
+pub static RESIDENT_OBJECTS: &str = "resident_objects";
+pub static OVERFLOW_OBJECTS: &str = "overflow_objects";
+pub static ASSIGNMENTS_OUTSTANDING: &str = "assignments_outstanding";
+pub static MD_UPDATE_QUEUE_DEPTH: &str = "metadata_update_queue_depth";
+static PIPELINE_GAUGES: &[(&str, &str)] = &[
+    (
+        OBJECT_QUEUE_DEPTH,
+        "Objects discovered but not yet received by an assignment manager.",
+    ),
+    (
+        RESIDENT_OBJECTS,
+        "Objects held in memory waiting to be placed.",
+    ),
+    (
+        OVERFLOW_OBJECTS,
+        "Objects waiting to be placed again that were spilled to disk.",
+    ),
+    (
+        ASSIGNMENTS_OUTSTANDING,
+        "Assignments posted to an agent but not yet complete.",
+    ),
+    (
+        MD_UPDATE_QUEUE_DEPTH,
+        "Completed assignments waiting for a metadata update worker.",
+    ),
+];
+
+// Time taken to update the metadata of a single object, labeled by outcome.
+// For batched updates this is the time taken to apply all of the batches for
+// the object's assignment.
+pub static METADATA_UPDATE_TIME: &str = "metadata_update_time";
+
+// Time taken by, and errors from, the requests that a job makes of each
+// metadata shard, labeled by "shard" and by "op": reading the shard's records
+// (the time waited for each record that sharkspotter finds) or writing
+// updated metadata (each put, or batch of puts).  A job that stalls is
+// usually held up by one shard, and these show which.
+pub static MORAY_SHARD_TIME: &str = "moray_shard_time";
+pub static MORAY_SHARD_ERROR_COUNT: &str = "moray_shard_error_count";
+
+pub static MORAY_OP_READ: &str = "read";
+pub static MORAY_OP_WRITE: &str = "write";
+
+// Objects and bytes broken down by destination shark and by what has become
+// of them ("state"), which is one of the SHARK_* values below.
+pub static SHARK_OBJECT_COUNT: &str = "shark_object_count";
+pub static SHARK_BYTES_COUNT: &str = "shark_bytes_count";
+
+pub static SHARK_ASSIGNED: &str = "assigned";
+pub static SHARK_COMPLETED: &str = "completed";
+pub static SHARK_FAILED: &str = "failed";
+static SHARK_STATES: &[&str] = &[SHARK_ASSIGNED, SHARK_COMPLETED, SHARK_FAILED];
+
+// Objects added to assignments, broken down by whether the agent is to copy
+// them from another replica or from the shark being evacuated.
+pub static SOURCE_COUNT: &str = "source_count";
+
+pub static SOURCE_REPLICA: &str = "replica";
+pub static SOURCE_EVAC_SHARK: &str = "evacuating_shark";
+
+// The number of times a candidate destination shark was passed over for an
+// object because of where the object's other copies are, broken down by
+// "reason": the shark already holds a copy, or (when moving the copy to
+// another data center) the destination data center already holds one.
+pub static PLACEMENT_EXCLUDED_COUNT: &str = "placement_excluded_count";
+
+pub static PLACEMENT_REPLICA_ON_SHARK: &str = "replica_on_shark";
+pub static PLACEMENT_REPLICA_IN_DATACENTER: &str = "replica_in_datacenter";
+
+// Metadata records found on a shard that were not rebalanced because they are
+// not regular objects, broken down by "disposition" (see
+// jobs::record::RecordDisposition).
+pub static RECORD_DISPOSITION_COUNT: &str = "record_disposition_count";
+
+// Objects checked by verify jobs, broken down by "status" (see
+// jobs::verify::VerifyObjectStatus).
+pub static VERIFY_OBJECT_COUNT: &str = "verify_object_count";
+
+// Objects rolled back, or not, by rollback jobs, broken down by "status" (see
+// jobs::rollback::RollbackObjectStatus).
+pub static ROLLBACK_OBJECT_COUNT: &str = "rollback_object_count";
+
+// Copies whose custom headers were checked after their object's metadata was
+// updated, broken down by "result": whether the headers matched the
+// metadata, did not, or could not be checked (see
+// jobs::evacuate::EvacuateJob::check_dest_headers()).
+pub static HEADER_CHECK_COUNT: &str = "header_check_count";
+
+pub static HEADER_CHECK_MATCH: &str = "match";
+pub static HEADER_CHECK_MISMATCH: &str = "mismatch";
+pub static HEADER_CHECK_UNVERIFIABLE: &str = "unverifiable";
+
+// Requests currently in flight to agents, and the number of connections
+// checked out of the agent client pool broken down by "result": whether a
+// connection was available straight away, or the request had to wait for
+// one to be returned (see the agent_client module).
+pub static AGENT_REQUESTS_IN_FLIGHT: &str = "agent_requests_in_flight";
+pub static AGENT_CHECKOUT_COUNT: &str = "agent_checkout_count";
+
+pub static AGENT_CHECKOUT_IMMEDIATE: &str = "immediate";
+pub static AGENT_CHECKOUT_WAITED: &str = "waited";
+
+// Posts of assignments to agents that failed, broken down by "kind": whether
+// a connection could not be made, the post timed out, or the agent answered
+// with a 5xx status, and the number of agents currently quarantined for their
+// failures or by hand (see the jobs::quarantine module).
+pub static AGENT_POST_FAILURE_COUNT: &str = "agent_post_failure_count";
+pub static AGENTS_QUARANTINED: &str = "agents_quarantined";
+
+// Polls of agents for the status of their assignments, broken down by
+// "result": whether the assignment was complete (a useful poll), was not yet
+// complete, or the poll failed.  The ratio of useful polls to all polls is
+// also kept as a gauge (see the jobs::polling module).
+pub static ASSIGNMENT_POLL_COUNT: &str = "assignment_poll_count";
+pub static ASSIGNMENT_POLL_USEFUL_RATIO: &str = "assignment_poll_useful_ratio";
+
+pub static POLL_COMPLETE: &str = "complete";
+pub static POLL_NOT_READY: &str = "not_ready";
+pub static POLL_FAILED: &str = "failed";
+
+// Time that posts of assignments have spent waiting for the bandwidth budget
+// shared by every job (see the jobs::bandwidth module).
+pub static BANDWIDTH_WAIT_SECONDS: &str = "bandwidth_wait_seconds";
+
+// Metadata updates that conflicted with another change to their object,
+// broken down by "result": whether the update was rebased onto the object as
+// it was re-read and made, had already been made, no longer applied to the
+// object, or conflicted every time that it was retried.
+pub static MD_CONFLICT_COUNT: &str = "md_update_conflict_count";
+
+pub static MD_CONFLICT_REBASED: &str = "rebased";
+pub static MD_CONFLICT_ALREADY_APPLIED: &str = "already_applied";
+pub static MD_CONFLICT_OBSOLETE: &str = "obsolete";
+pub static MD_CONFLICT_GAVE_UP: &str = "gave_up";
+
+// All polls, and useful polls, since the manager started.
+static POLLS: AtomicU64 = AtomicU64::new(0);
+static USEFUL_POLLS: AtomicU64 = AtomicU64::new(0);
+
 // This method may come in handy if it is necessary to add more metrics to
 // our collector.
 pub fn metrics_get() -> &'static Mutex<Option<MetricsMap>> {
@@ -93,26 +258,311 @@ pub fn metrics_init(cfg: metrics::ConfigMetrics) {
         MD_THREAD_GAUGE,
         "Number of currently active metadata threads."
     )
-    .const_labels(labels))
+    .const_labels(labels.clone()))
     .expect("failed to register metadata thread gauge");
 
     metrics.insert(MD_THREAD_GAUGE, Metrics::MetricsGauge(md_thread_gauge));
 
+    for (name, help) in PIPELINE_GAUGES.iter() {
+        let gauge =
+            register_gauge!(opts!(*name, *help).const_labels(labels.clone()))
+                .expect("failed to register pipeline gauge");
+
+        metrics.insert(*name, Metrics::MetricsGauge(gauge));
+    }
+
+    let md_update_times = register_histogram_vec!(
+        histogram_opts!(METADATA_UPDATE_TIME, "Object metadata update time")
+            .const_labels(labels.clone()),
+        &["outcome"]
+    )
+    .expect("failed to register metadata_update_time histogram");
+
+    metrics.insert(
+        METADATA_UPDATE_TIME,
+        Metrics::MetricsHistogramVec(md_update_times),
+    );
+
+    let moray_shard_times = register_histogram_vec!(
+        histogram_opts!(MORAY_SHARD_TIME, "Metadata request time by shard")
+            .const_labels(labels.clone()),
+        &["shard", "op"]
+    )
+    .expect("failed to register moray_shard_time histogram");
+
+    metrics.insert(
+        MORAY_SHARD_TIME,
+        Metrics::MetricsHistogramVec(moray_shard_times),
+    );
+
+    let moray_shard_errors = register_counter_vec!(
+        opts!(MORAY_SHARD_ERROR_COUNT, "Metadata request errors by shard.")
+            .const_labels(labels.clone()),
+        &["shard", "op"]
+    )
+    .expect("failed to register moray_shard_error_count counter");
+
+    metrics.insert(
+        MORAY_SHARD_ERROR_COUNT,
+        Metrics::MetricsCounterVec(moray_shard_errors),
+    );
+
+    let shark_object_counter = register_counter_vec!(
+        opts!(SHARK_OBJECT_COUNT, "Objects by destination shark.")
+            .const_labels(labels.clone()),
+        &["shark", "state"]
+    )
+    .expect("failed to register shark_object_count counter");
+
+    metrics.insert(
+        SHARK_OBJECT_COUNT,
+        Metrics::MetricsCounterVec(shark_object_counter),
+    );
+
+    let source_counter = register_counter_vec!(
+        opts!(SOURCE_COUNT, "Objects by source shark.")
+            .const_labels(labels.clone()),
+        &["source"]
+    )
+    .expect("failed to register source_count counter");
+
+    metrics.insert(SOURCE_COUNT, Metrics::MetricsCounterVec(source_counter));
+
+    let placement_counter = register_counter_vec!(
+        opts!(
+            PLACEMENT_EXCLUDED_COUNT,
+            "Destination sharks passed over because of an object's replicas."
+        )
+        .const_labels(labels.clone()),
+        &["reason"]
+    )
+    .expect("failed to register placement_excluded_count counter");
+
+    metrics.insert(
+        PLACEMENT_EXCLUDED_COUNT,
+        Metrics::MetricsCounterVec(placement_counter),
+    );
+
+    let disposition_counter = register_counter_vec!(
+        opts!(
+            RECORD_DISPOSITION_COUNT,
+            "Metadata records not rebalanced because they are not objects."
+        )
+        .const_labels(labels.clone()),
+        &["disposition"]
+    )
+    .expect("failed to register record_disposition_count counter");
+
+    metrics.insert(
+        RECORD_DISPOSITION_COUNT,
+        Metrics::MetricsCounterVec(disposition_counter),
+    );
+
+    let verify_counter = register_counter_vec!(
+        opts!(VERIFY_OBJECT_COUNT, "Objects checked by verify jobs.")
+            .const_labels(labels.clone()),
+        &["status"]
+    )
+    .expect("failed to register verify_object_count counter");
+
+    metrics.insert(
+        VERIFY_OBJECT_COUNT,
+        Metrics::MetricsCounterVec(verify_counter),
+    );
+
+    let rollback_counter = register_counter_vec!(
+        opts!(ROLLBACK_OBJECT_COUNT, "Objects handled by rollback jobs.")
+            .const_labels(labels.clone()),
+        &["status"]
+    )
+    .expect("failed to register rollback_object_count counter");
+
+    metrics.insert(
+        ROLLBACK_OBJECT_COUNT,
+        Metrics::MetricsCounterVec(rollback_counter),
+    );
+
+    let header_check_counter = register_counter_vec!(
+        opts!(
+            HEADER_CHECK_COUNT,
+            "Copies whose custom headers were checked after a move."
+        )
+        .const_labels(labels.clone()),
+        &["result"]
+    )
+    .expect("failed to register header_check_count counter");
+
+    metrics.insert(
+        HEADER_CHECK_COUNT,
+        Metrics::MetricsCounterVec(header_check_counter),
+    );
+
+    let agent_requests_gauge = register_gauge!(opts!(
+        AGENT_REQUESTS_IN_FLIGHT,
+        "Number of requests currently in flight to agents."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register agent_requests_in_flight gauge");
+
+    metrics.insert(
+        AGENT_REQUESTS_IN_FLIGHT,
+        Metrics::MetricsGauge(agent_requests_gauge),
+    );
+
+    let agent_checkout_counter = register_counter_vec!(
+        opts!(
+            AGENT_CHECKOUT_COUNT,
+            "Connections checked out of the agent client pool."
+        )
+        .const_labels(labels.clone()),
+        &["result"]
+    )
+    .expect("failed to register agent_checkout_count counter");
+
+    metrics.insert(
+        AGENT_CHECKOUT_COUNT,
+        Metrics::MetricsCounterVec(agent_checkout_counter),
+    );
+
+    let post_failure_counter = register_counter_vec!(
+        opts!(
+            AGENT_POST_FAILURE_COUNT,
+            "Posts of assignments to agents that failed."
+        )
+        .const_labels(labels.clone()),
+        &["kind"]
+    )
+    .expect("failed to register agent_post_failure_count counter");
+
+    metrics.insert(
+        AGENT_POST_FAILURE_COUNT,
+        Metrics::MetricsCounterVec(post_failure_counter),
+    );
+
+    let quarantined_gauge = register_gauge!(opts!(
+        AGENTS_QUARANTINED,
+        "Agents that jobs are not choosing as destinations."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register agents_quarantined gauge");
+
+    metrics
+        .insert(AGENTS_QUARANTINED, Metrics::MetricsGauge(quarantined_gauge));
+
+    let poll_counter = register_counter_vec!(
+        opts!(ASSIGNMENT_POLL_COUNT, "Polls of agents for assignments.")
+            .const_labels(labels.clone()),
+        &["result"]
+    )
+    .expect("failed to register assignment_poll_count counter");
+
+    metrics.insert(
+        ASSIGNMENT_POLL_COUNT,
+        Metrics::MetricsCounterVec(poll_counter),
+    );
+
+    let poll_ratio_gauge = register_gauge!(opts!(
+        ASSIGNMENT_POLL_USEFUL_RATIO,
+        "Fraction of polls of agents that found the assignment complete."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register assignment_poll_useful_ratio gauge");
+
+    metrics.insert(
+        ASSIGNMENT_POLL_USEFUL_RATIO,
+        Metrics::MetricsGauge(poll_ratio_gauge),
+    );
+
+    let bandwidth_wait_counter = register_counter!(opts!(
+        BANDWIDTH_WAIT_SECONDS,
+        "Seconds that assignment posts waited for the bandwidth budget."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register bandwidth_wait_seconds counter");
+
+    metrics.insert(
+        BANDWIDTH_WAIT_SECONDS,
+        Metrics::MetricsCounter(bandwidth_wait_counter),
+    );
+
+    let md_conflict_counter = register_counter_vec!(
+        opts!(
+            MD_CONFLICT_COUNT,
+            "Metadata updates that conflicted with another update."
+        )
+        .const_labels(labels.clone()),
+        &["result"]
+    )
+    .expect("failed to register md_update_conflict_count counter");
+
+    metrics.insert(
+        MD_CONFLICT_COUNT,
+        Metrics::MetricsCounterVec(md_conflict_counter),
+    );
+
+    let shark_bytes_counter = register_counter_vec!(
+        opts!(SHARK_BYTES_COUNT, "Bytes by destination shark.")
+            .const_labels(labels),
+        &["shark", "state"]
+    )
+    .expect("failed to register shark_bytes_count counter");
+
+    metrics.insert(
+        SHARK_BYTES_COUNT,
+        Metrics::MetricsCounterVec(shark_bytes_counter),
+    );
+
     // Take the fully formed set of metrics and store it globally.
     let mut global_metrics = METRICS.lock().unwrap();
     *global_metrics = Some(metrics);
 
-    // Spawn a thread which runs our metrics server.
+    // Spawn a thread which runs our metrics server, or pushes the metrics.
     let ms = thread::Builder::new()
         .name(String::from("Rebalancer Manager Metrics"))
-        .spawn(move || {
-            metrics::start_server(&cfg.host, cfg.port, &slog_scope::logger())
-        });
+        .spawn(move || metrics::start_emitter(&cfg, &slog_scope::logger()));
 
     assert!(ms.is_ok());
     metrics_init.init = true;
 }
 
+/// One job's share of a gauge that is summed over all running jobs.  The job
+/// periodically reports its own value with set(), and the gauge is adjusted
+/// by the difference from what it last reported.  Whatever the job has
+/// reported is subtracted again when the GaugeShare is dropped.
+pub struct GaugeShare {
+    gauge: Option<Gauge>,
+    last: usize,
+}
+
+impl GaugeShare {
+    pub fn new(key: &str) -> GaugeShare {
+        let gauge =
+            METRICS
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|m| match m.get(key) {
+                    Some(Metrics::MetricsGauge(g)) => Some(g.clone()),
+                    _ => None,
+                });
+
+        GaugeShare { gauge, last: 0 }
+    }
+
+    pub fn set(&mut self, val: usize) {
+        if let Some(g) = &self.gauge {
+            g.add(val as f64 - self.last as f64);
+        }
+        self.last = val;
+    }
+}
+
+impl Drop for GaugeShare {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
 //
 // The following utility functions exist to ensure that a caller can not
 // inadvertently provide an erroneous key to the MetricsMap when updating
@@ -151,6 +601,245 @@ pub fn metrics_object_inc_by(action: Option<&str>, val: usize) {
     metrics_vec_inc_by(OBJECT_COUNT, action, val);
 }
 
+// Objects added to assignments, classified by source (either SOURCE_REPLICA
+// or SOURCE_EVAC_SHARK).
+pub fn metrics_source_inc(source: &str) {
+    metrics_vec_inc_by(SOURCE_COUNT, Some(source), 1);
+}
+
+// A destination shark passed over because of an object's replicas, classified
+// by reason (either PLACEMENT_REPLICA_ON_SHARK or
+// PLACEMENT_REPLICA_IN_DATACENTER).
+pub fn metrics_placement_excluded_inc(reason: &str) {
+    metrics_vec_inc_by(PLACEMENT_EXCLUDED_COUNT, Some(reason), 1);
+}
+
+// A metadata record that was not rebalanced, classified by its disposition.
+pub fn metrics_record_disposition_inc(disposition: &str) {
+    metrics_vec_inc_by(RECORD_DISPOSITION_COUNT, Some(disposition), 1);
+}
+
+// An object checked by a verify job.
+pub fn metrics_verify_object_inc(status: &str) {
+    metrics_vec_inc_by(VERIFY_OBJECT_COUNT, Some(status), 1);
+}
+
+// An object handled by a rollback job.
+pub fn metrics_rollback_object_inc(status: &str) {
+    metrics_vec_inc_by(ROLLBACK_OBJECT_COUNT, Some(status), 1);
+}
+
+// A copy whose custom headers were checked, classified by result (one of
+// HEADER_CHECK_MATCH, HEADER_CHECK_MISMATCH or HEADER_CHECK_UNVERIFIABLE).
+pub fn metrics_header_check_inc(result: &str) {
+    metrics_vec_inc_by(HEADER_CHECK_COUNT, Some(result), 1);
+}
+
+// A metadata update that conflicted with another update of its object,
+// classified by result (one of MD_CONFLICT_REBASED,
+// MD_CONFLICT_ALREADY_APPLIED, MD_CONFLICT_OBSOLETE or MD_CONFLICT_GAVE_UP).
+pub fn metrics_md_conflict_inc(result: &str) {
+    metrics_vec_inc_by(MD_CONFLICT_COUNT, Some(result), 1);
+}
+
+// The agent client pool may be used before metrics have been initialized
+// (e.g. in unit tests), so these do nothing until they are.
+
+// A connection checked out of the agent client pool, classified by result
+// (either AGENT_CHECKOUT_IMMEDIATE or AGENT_CHECKOUT_WAITED).
+pub fn metrics_agent_checkout_inc(result: &str) {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        counter_vec_inc_by(&metrics, AGENT_CHECKOUT_COUNT, Some(result), 1);
+    }
+}
+
+pub fn metrics_agent_requests_inc() {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        gauge_inc(&metrics, AGENT_REQUESTS_IN_FLIGHT);
+    }
+}
+
+pub fn metrics_agent_requests_dec() {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        gauge_dec(&metrics, AGENT_REQUESTS_IN_FLIGHT);
+    }
+}
+
+// A post of an assignment to an agent that failed, classified by kind.
+pub fn metrics_agent_post_failure_inc(kind: &str) {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        counter_vec_inc_by(&metrics, AGENT_POST_FAILURE_COUNT, Some(kind), 1);
+    }
+}
+
+pub fn metrics_agents_quarantined_set(agents: usize) {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        gauge_set(&metrics, AGENTS_QUARANTINED, agents);
+    }
+}
+
+// A poll of an agent for an assignment, classified by result (one of
+// POLL_COMPLETE, POLL_NOT_READY or POLL_FAILED).
+pub fn metrics_poll_inc(result: &str) {
+    let polls = POLLS.fetch_add(1, Ordering::SeqCst) + 1;
+    let useful = if result == POLL_COMPLETE {
+        USEFUL_POLLS.fetch_add(1, Ordering::SeqCst) + 1
+    } else {
+        USEFUL_POLLS.load(Ordering::SeqCst)
+    };
+
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        counter_vec_inc_by(&metrics, ASSIGNMENT_POLL_COUNT, Some(result), 1);
+        if let Some(Metrics::MetricsGauge(g)) =
+            metrics.get(ASSIGNMENT_POLL_USEFUL_RATIO)
+        {
+            g.set(useful as f64 / polls as f64);
+        }
+    }
+}
+
+// Time that the post of an assignment spent waiting for the bandwidth budget.
+pub fn metrics_bandwidth_wait_observe(secs: f64) {
+    if let Some(metrics) = METRICS.lock().unwrap().as_ref() {
+        if let Some(Metrics::MetricsCounter(c)) =
+            metrics.get(BANDWIDTH_WAIT_SECONDS)
+        {
+            c.inc_by(secs);
+        }
+    }
+}
+
+// The size of an object that was moved, or that could not be moved.
+pub fn metrics_object_size_observe(bytes: u64, failed: bool) {
+    let key = if failed {
+        OBJECT_SIZE_FAILED
+    } else {
+        OBJECT_SIZE
+    };
+    let metrics = METRICS.lock().unwrap().clone();
+    histogram_observe(&metrics.expect("metrics"), key, bytes as f64);
+}
+
+// The time in seconds taken to update the metadata of an object.
+pub fn metrics_md_update_observe(secs: f64, failed: bool) {
+    let outcome = if failed {
+        OUTCOME_FAILURE
+    } else {
+        OUTCOME_SUCCESS
+    };
+    let metrics = METRICS.lock().unwrap().clone();
+    histogram_vec_observe(
+        &metrics.expect("metrics"),
+        METADATA_UPDATE_TIME,
+        outcome,
+        secs,
+    );
+}
+
+// A request of a metadata shard that took `secs` seconds, classified by op
+// (either MORAY_OP_READ or MORAY_OP_WRITE).  A failed request is counted as
+// an error as well.
+pub fn metrics_moray_shard_observe(
+    shard: u32,
+    op: &str,
+    secs: f64,
+    failed: bool,
+) {
+    let metrics = match METRICS.lock().unwrap().clone() {
+        Some(m) => m,
+        None => return,
+    };
+    let shard = shard.to_string();
+
+    if let Some(Metrics::MetricsHistogramVec(h)) = metrics.get(MORAY_SHARD_TIME)
+    {
+        h.with_label_values(&[&shard, op]).observe(secs);
+    }
+
+    if failed {
+        metrics_moray_shard_error(&metrics, &shard, op);
+    }
+}
+
+// A request of a metadata shard that failed before it could be made, e.g.
+// because there was no client for the shard.
+pub fn metrics_moray_shard_error_inc(shard: u32, op: &str) {
+    if let Some(metrics) = METRICS.lock().unwrap().clone() {
+        metrics_moray_shard_error(&metrics, &shard.to_string(), op);
+    }
+}
+
+fn metrics_moray_shard_error(metrics: &MetricsMap, shard: &str, op: &str) {
+    if let Some(Metrics::MetricsCounterVec(c)) =
+        metrics.get(MORAY_SHARD_ERROR_COUNT)
+    {
+        c.with_label_values(&[shard, op]).inc();
+    }
+}
+
+// Objects and bytes for a destination shark, classified by state (one of
+// SHARK_ASSIGNED, SHARK_COMPLETED or SHARK_FAILED).  This has no effect
+// unless the shark has been registered with metrics_shark_add().
+pub fn metrics_shark_inc_by(
+    shark: &str,
+    state: &str,
+    objects: usize,
+    bytes: u64,
+) {
+    // Hold the lock throughout so that a concurrent metrics_shark_remove()
+    // can not drop the label values just before we recreate them.
+    let active = ACTIVE_SHARKS.lock().expect("active sharks lock");
+    if !active.contains_key(shark) {
+        return;
+    }
+
+    let metrics = METRICS.lock().unwrap().clone().expect("metrics");
+
+    if let Some(Metrics::MetricsCounterVec(c)) = metrics.get(SHARK_OBJECT_COUNT)
+    {
+        c.with_label_values(&[shark, state]).inc_by(objects as f64);
+    }
+
+    if let Some(Metrics::MetricsCounterVec(c)) = metrics.get(SHARK_BYTES_COUNT)
+    {
+        c.with_label_values(&[shark, state]).inc_by(bytes as f64);
+    }
+}
+
+// Called by a job the first time it considers a shark as a destination.
+pub fn metrics_shark_add(shark: &str) {
+    let mut active = ACTIVE_SHARKS.lock().expect("active sharks lock");
+    *active.entry(shark.to_string()).or_insert(0) += 1;
+}
+
+// Called by a job for each of its destination sharks when it finishes.  Once
+// no job is using a shark its per shark metrics are dropped.
+pub fn metrics_shark_remove(shark: &str) {
+    let mut active = ACTIVE_SHARKS.lock().expect("active sharks lock");
+
+    match active.get_mut(shark) {
+        Some(count) if *count > 1 => {
+            *count -= 1;
+            return;
+        }
+        Some(_) => {
+            active.remove(shark);
+        }
+        None => return,
+    }
+
+    let metrics = METRICS.lock().unwrap().clone().expect("metrics");
+
+    for key in [SHARK_OBJECT_COUNT, SHARK_BYTES_COUNT].iter() {
+        if let Some(Metrics::MetricsCounterVec(c)) = metrics.get(key) {
+            for state in SHARK_STATES.iter() {
+                // A given state may never have been incremented.
+                let _ = c.remove_label_values(&[shark, state]);
+            }
+        }
+    }
+}
+
 // Private method that directly accesses the metrics structure.
 fn metrics_vec_inc_by(key: &str, bucket: Option<&str>, val: usize) {
     let metrics = METRICS.lock().unwrap().clone();
@@ -167,6 +856,11 @@ pub fn metrics_gauge_inc(key: &str) {
     gauge_inc(&metrics.expect("metrics"), key);
 }
 
+pub fn metrics_gauge_get(key: &str) -> f64 {
+    let metrics = METRICS.lock().unwrap().clone();
+    gauge_get(&metrics.expect("metrics"), key)
+}
+
 pub fn metrics_gauge_set(key: &str, val: usize) {
     let metrics = METRICS.lock().unwrap().clone();
     gauge_set(&metrics.expect("metrics"), key, val);
@@ -0,0 +1,194 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Bringing the manager's databases up to date with the manager.
+//
+// Each new table or column of the jobs database used to be created by
+// whatever happened to run first after an upgrade, with statements written to
+// do nothing if it already existed, and a change that could not be written
+// that way meant deleting the database, along with the record of every job.
+// Instead, a database has a list of migrations, numbered from 1, and records
+// each one that has been applied to it in its schema_migrations table.  At
+// startup, migrate() applies those that have yet to be, in order, all in a
+// single transaction along with their entries, so that a database is never
+// left part of the way through a migration.  A transaction-level advisory
+// lock keeps two managers (or two tests) from migrating the same database at
+// once.
+//
+// Databases created before there were migrations already have some of them
+// applied, with nothing in schema_migrations to say so, so the migrations of
+// that time are written to do nothing where what they do has been done.
+// Later ones need not be.  A database that has had migrations applied that
+// this manager does not know of was migrated by a newer manager, which is
+// logged, and the database is used as it is.
+
+use rebalancer::error::Error;
+use rebalancer::util::now_ms;
+
+use std::collections::HashSet;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+// The key of the advisory lock held while migrating.
+static MIGRATION_LOCK: i64 = 0x7265_6261_6c61_6e63;
+
+table! {
+    use diesel::sql_types::{BigInt, Integer, Text};
+    schema_migrations (version) {
+        version -> Integer,
+        description -> Text,
+        applied -> BigInt,
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "schema_migrations"]
+struct AppliedMigration {
+    version: i32,
+    description: String,
+    // When the migration was applied, in ms since the epoch.
+    applied: i64,
+}
+
+/// A change to the schema of a database.
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub up: fn(&PgConnection) -> Result<(), Error>,
+}
+
+/// Apply each of `migrations` that has yet to be applied to the database
+/// `conn` is connected to, in order of their versions.  Returns the versions
+/// that were applied.
+pub fn migrate(
+    conn: &PgConnection,
+    migrations: &[Migration],
+) -> Result<Vec<i32>, Error> {
+    use self::schema_migrations::dsl::{
+        schema_migrations as migrations_table, version,
+    };
+
+    conn.transaction::<_, Error, _>(|| {
+        conn.execute(&format!(
+            "SELECT pg_advisory_xact_lock({})",
+            MIGRATION_LOCK
+        ))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations(
+                version Integer PRIMARY KEY,
+                description Text NOT NULL,
+                applied BigInt NOT NULL
+            );",
+        )?;
+
+        let applied: HashSet<i32> = migrations_table
+            .select(version)
+            .load(conn)?
+            .into_iter()
+            .collect();
+
+        let known: HashSet<i32> =
+            migrations.iter().map(|m| m.version).collect();
+        if let Some(newest) = applied.difference(&known).max() {
+            warn!(
+                "Database has migration {} applied, which this version does \
+                 not know of",
+                newest
+            );
+        }
+
+        let mut pending: Vec<&Migration> = migrations
+            .iter()
+            .filter(|m| !applied.contains(&m.version))
+            .collect();
+        pending.sort_by_key(|m| m.version);
+
+        let mut done = vec![];
+        for migration in pending {
+            info!(
+                "Applying database migration {}: {}",
+                migration.version, migration.description
+            );
+            (migration.up)(conn)?;
+
+            diesel::insert_into(migrations_table)
+                .values(&AppliedMigration {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                    applied: now_ms(),
+                })
+                .execute(conn)?;
+            done.push(migration.version);
+        }
+
+        Ok(done)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_db;
+    use rebalancer::util;
+    use uuid::Uuid;
+
+    fn create_widgets(conn: &PgConnection) -> Result<(), Error> {
+        conn.execute("CREATE TABLE widgets(id TEXT PRIMARY KEY);")?;
+        Ok(())
+    }
+
+    fn add_widget_size(conn: &PgConnection) -> Result<(), Error> {
+        conn.execute("ALTER TABLE widgets ADD COLUMN size BIGINT;")?;
+        Ok(())
+    }
+
+    static WIDGET_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 2,
+            description: "widget sizes",
+            up: add_widget_size,
+        },
+        Migration {
+            version: 1,
+            description: "widgets table",
+            up: create_widgets,
+        },
+    ];
+
+    #[test]
+    fn migrate_test() {
+        let _guard = util::init_global_logger(None);
+        let db_name = Uuid::new_v4().to_string();
+
+        {
+            let conn = pg_db::create_and_connect_db(&db_name)
+                .expect("create test database");
+
+            // Applied in order, whatever the order of the list.
+            let applied =
+                migrate(&conn, &WIDGET_MIGRATIONS[1..]).expect("migrate");
+            assert_eq!(applied, vec![1]);
+            let applied = migrate(&conn, WIDGET_MIGRATIONS).expect("migrate");
+            assert_eq!(applied, vec![2]);
+
+            // Neither is applied again.
+            let applied = migrate(&conn, WIDGET_MIGRATIONS).expect("migrate");
+            assert!(applied.is_empty());
+
+            // A database migrated further than this list is left as it is.
+            let applied =
+                migrate(&conn, &WIDGET_MIGRATIONS[1..]).expect("migrate");
+            assert!(applied.is_empty());
+        }
+
+        pg_db::drop_db(&db_name).expect("drop test database");
+    }
+}
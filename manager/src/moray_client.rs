@@ -16,6 +16,7 @@ use moray::{
 use rand::seq::SliceRandom;
 use rebalancer::common;
 use rebalancer::error::{Error, InternalError, InternalErrorCode};
+use rebalancer::faults::{self, FaultPoint};
 use serde_json::Value;
 use slog_scope;
 use std::net::{IpAddr, SocketAddr};
@@ -121,6 +122,34 @@ pub fn get_manta_object_shark(
     }
 }
 
+// Fetch the current value and etag of a manta object.
+pub fn get_object(
+    mclient: &mut MorayClient,
+    key: &str,
+) -> Result<(Value, String), Error> {
+    let opts = ObjectMethodOptions::default();
+    let mut ret: Option<(Value, String)> = None;
+
+    mclient.get_object(MANTA_BUCKET, key, &opts, |o| {
+        ret = Some((o.value.clone(), o._etag.clone()));
+        Ok(())
+    })?;
+
+    ret.ok_or_else(|| {
+        InternalError::new(
+            Some(InternalErrorCode::MetadataUpdateFailure),
+            format!("Could not find object with key {}", key),
+        )
+        .into()
+    })
+}
+
+// Moray reports a conditional put against a stale etag with an
+// EtagConflictError, which is only distinguishable by name.
+pub fn is_etag_conflict(err: &Error) -> bool {
+    err.to_string().contains("EtagConflictError")
+}
+
 pub fn put_object(
     mclient: &mut MorayClient,
     object: &Value,
@@ -138,6 +167,14 @@ pub fn put_object(
         opts
     );
 
+    if faults::failed(FaultPoint::MorayUpdate) {
+        return Err(InternalError::new(
+            Some(InternalErrorCode::MetadataUpdateFailure),
+            "Moray error injected by a fault",
+        )
+        .into());
+    }
+
     mclient
         .put_object(MANTA_BUCKET, &key, object.to_owned(), &opts, |o| {
             trace!("Object Updated: {}", o);
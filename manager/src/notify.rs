@@ -0,0 +1,249 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Job lifecycle notifications.
+//
+// If one or more webhooks are configured, a JSON event is POSTed to each of
+// them whenever a job changes state (including when a job is paused by its
+// circuit breaker), and whenever the number of objects that a job has failed
+// to move (i.e. skipped or errored) crosses one of the configured error
+// thresholds.  Delivery happens on a single background
+// thread so that events arrive in the order they were generated, and so that
+// a slow or unreachable webhook never holds up a job.  Delivery is best
+// effort: an event that can not be delivered after a few attempts is logged
+// and dropped.
+
+use crate::config::ConfigNotifications;
+use rebalancer::util::now_ms;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+static DELIVERY_ATTEMPTS: u32 = 3;
+static DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+static DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobEventKind {
+    Created,
+    Queued,
+    Running,
+    Complete,
+    Failed,
+    Stopped,
+    Interrupted,
+    Paused,
+    ErrorThreshold,
+    AwaitingConfirmation,
+    Confirmed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobEvent {
+    pub job_id: String,
+    pub event: JobEventKind,
+
+    /// Milliseconds since the epoch at which the event was generated.
+    pub timestamp: u64,
+
+    /// For ErrorThreshold events, the threshold that was crossed and the
+    /// number of objects that the job has failed to move so far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_objects: Option<u64>,
+
+    /// For Failed, Stopped, Interrupted and Paused events, the error that
+    /// caused the job to end.  For Paused events, this says which circuit
+    /// breaker limit the job went over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// For Confirmed events, who signed off the job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmed_by: Option<String>,
+}
+
+impl JobEvent {
+    pub fn new(job_id: &str, event: JobEventKind) -> JobEvent {
+        JobEvent {
+            job_id: job_id.to_string(),
+            event,
+            timestamp: now_ms() as u64,
+            threshold: None,
+            failed_objects: None,
+            error: None,
+            confirmed_by: None,
+        }
+    }
+}
+
+struct Delivery {
+    webhooks: Vec<String>,
+    event: JobEvent,
+}
+
+lazy_static! {
+    static ref DELIVERY_TX: Mutex<crossbeam_channel::Sender<Delivery>> =
+        Mutex::new(start_delivery_thread());
+}
+
+fn start_delivery_thread() -> crossbeam_channel::Sender<Delivery> {
+    let (tx, rx) = crossbeam_channel::unbounded::<Delivery>();
+
+    thread::Builder::new()
+        .name(String::from("job notifications"))
+        .spawn(move || {
+            let client = reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("notification client");
+
+            while let Ok(delivery) = rx.recv() {
+                for url in delivery.webhooks.iter() {
+                    deliver(&client, url, &delivery.event);
+                }
+            }
+        })
+        .expect("start job notification thread");
+
+    tx
+}
+
+fn deliver(client: &reqwest::Client, url: &str, event: &JobEvent) {
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let err = match client.post(url).json(event).send() {
+            Ok(resp) => {
+                if resp.status().is_success() {
+                    trace!(
+                        "Delivered {:?} event for job {} to {}",
+                        event.event,
+                        event.job_id,
+                        url
+                    );
+                    return;
+                }
+                format!("status {}", resp.status())
+            }
+            Err(e) => e.to_string(),
+        };
+
+        warn!(
+            "Attempt {} of {} to deliver {:?} event for job {} to {} \
+             failed: {}",
+            attempt, DELIVERY_ATTEMPTS, event.event, event.job_id, url, err
+        );
+
+        if attempt < DELIVERY_ATTEMPTS {
+            thread::sleep(DELIVERY_RETRY_DELAY);
+        }
+    }
+
+    error!(
+        "Giving up on delivering {:?} event for job {} to {}",
+        event.event, event.job_id, url
+    );
+}
+
+/// Queue an event for delivery to every configured webhook.  This never
+/// blocks on the webhooks themselves.
+pub fn send(config: &ConfigNotifications, event: JobEvent) {
+    if config.webhooks.is_empty() {
+        return;
+    }
+
+    debug!("Job {} event: {:?}", event.job_id, event.event);
+
+    let delivery = Delivery {
+        webhooks: config.webhooks.clone(),
+        event,
+    };
+
+    if let Err(e) = DELIVERY_TX.lock().expect("delivery lock").send(delivery) {
+        error!("Job notification thread has exited: {}", e);
+    }
+}
+
+/// Keeps a running count of the objects a job has failed to move and sends
+/// an ErrorThreshold event the first time that count reaches each of the
+/// configured thresholds.
+pub struct FailureTracker {
+    job_id: String,
+    config: ConfigNotifications,
+    failed: Mutex<u64>,
+}
+
+impl FailureTracker {
+    pub fn new(job_id: &str, config: &ConfigNotifications) -> FailureTracker {
+        FailureTracker {
+            job_id: job_id.to_string(),
+            config: config.clone(),
+            failed: Mutex::new(0),
+        }
+    }
+
+    pub fn add(&self, count: u64) {
+        if count == 0 || self.config.error_thresholds.is_empty() {
+            return;
+        }
+
+        let (before, after) = {
+            let mut failed = self.failed.lock().expect("failure count lock");
+            let before = *failed;
+            *failed = failed.saturating_add(count);
+            (before, *failed)
+        };
+
+        for threshold in self.crossed(before, after) {
+            let mut event =
+                JobEvent::new(&self.job_id, JobEventKind::ErrorThreshold);
+            event.threshold = Some(threshold);
+            event.failed_objects = Some(after);
+            send(&self.config, event);
+        }
+    }
+
+    fn crossed(&self, before: u64, after: u64) -> Vec<u64> {
+        let mut crossed: Vec<u64> = self
+            .config
+            .error_thresholds
+            .iter()
+            .filter(|t| before < **t && **t <= after)
+            .cloned()
+            .collect();
+
+        crossed.sort();
+        crossed.dedup();
+        crossed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_crossed_once() {
+        let config = ConfigNotifications {
+            webhooks: vec![],
+            error_thresholds: vec![1000, 10, 100, 10],
+        };
+        let tracker = FailureTracker::new("job", &config);
+
+        assert_eq!(tracker.crossed(0, 9), Vec::<u64>::new());
+        assert_eq!(tracker.crossed(9, 10), vec![10]);
+        assert_eq!(tracker.crossed(10, 11), Vec::<u64>::new());
+        assert_eq!(tracker.crossed(11, 5000), vec![100, 1000]);
+    }
+}
@@ -0,0 +1,546 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// A minimal Parquet file writer.
+//
+// Only what is needed to export flat tables is supported: required or
+// optional INT32, INT64 and UTF8 (BYTE_ARRAY) columns, PLAIN encoded and
+// uncompressed, with a single data page per column in each row group.  That
+// is enough for any Parquet reader (Spark, Athena, DuckDB, pyarrow, ...) to
+// load the file, and avoids pulling in the Arrow crates for what is a single
+// export path.  The file layout, and the Thrift compact protocol used to
+// encode its metadata, are described in
+// https://github.com/apache/parquet-format.
+
+use std::io::{self, Write};
+
+static MAGIC: &[u8] = b"PAR1";
+static CREATED_BY: &str = "manta-rebalancer";
+
+// Parquet enum values.
+const TYPE_INT32: i32 = 1;
+const TYPE_INT64: i32 = 2;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REPETITION_REQUIRED: i32 = 0;
+const REPETITION_OPTIONAL: i32 = 1;
+const CONVERTED_TYPE_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_TYPE_DATA_PAGE: i32 = 0;
+
+// Thrift compact protocol field types.
+const CT_I32: u8 = 5;
+const CT_I64: u8 = 6;
+const CT_BINARY: u8 = 8;
+const CT_LIST: u8 = 9;
+const CT_STRUCT: u8 = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnType {
+    Int32,
+    Int64,
+    Utf8,
+}
+
+impl ColumnType {
+    fn physical_type(self) -> i32 {
+        match self {
+            ColumnType::Int32 => TYPE_INT32,
+            ColumnType::Int64 => TYPE_INT64,
+            ColumnType::Utf8 => TYPE_BYTE_ARRAY,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+    pub optional: bool,
+}
+
+impl Column {
+    pub fn required(name: &str, column_type: ColumnType) -> Column {
+        Column {
+            name: name.to_string(),
+            column_type,
+            optional: false,
+        }
+    }
+
+    pub fn optional(name: &str, column_type: ColumnType) -> Column {
+        Column {
+            name: name.to_string(),
+            column_type,
+            optional: true,
+        }
+    }
+}
+
+/// A single value in a row.  Null is only allowed in optional columns.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Datum {
+    Null,
+    Int32(i32),
+    Int64(i64),
+    Utf8(String),
+}
+
+// Encoder for the Thrift compact protocol.  Only the types that Parquet's
+// metadata needs are implemented.
+#[derive(Default)]
+struct Compact {
+    buf: Vec<u8>,
+    last_field: i16,
+    parents: Vec<i16>,
+}
+
+impl Compact {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8 & 0x7f) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn zigzag32(v: i32) -> u64 {
+        u64::from(((v << 1) ^ (v >> 31)) as u32)
+    }
+
+    fn zigzag64(v: i64) -> u64 {
+        ((v << 1) ^ (v >> 63)) as u64
+    }
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let delta = id - self.last_field;
+        if delta > 0 && delta <= 15 {
+            self.buf.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.buf.push(field_type);
+            self.varint(Self::zigzag32(i32::from(id)));
+        }
+        self.last_field = id;
+    }
+
+    fn struct_begin(&mut self) {
+        self.parents.push(self.last_field);
+        self.last_field = 0;
+    }
+
+    fn struct_end(&mut self) {
+        self.buf.push(0);
+        self.last_field = self.parents.pop().unwrap_or(0);
+    }
+
+    fn struct_field_begin(&mut self, id: i16) {
+        self.field(id, CT_STRUCT);
+        self.struct_begin();
+    }
+
+    fn i32_field(&mut self, id: i16, v: i32) {
+        self.field(id, CT_I32);
+        self.i32(v);
+    }
+
+    fn i64_field(&mut self, id: i16, v: i64) {
+        self.field(id, CT_I64);
+        self.varint(Self::zigzag64(v));
+    }
+
+    fn binary_field(&mut self, id: i16, v: &[u8]) {
+        self.field(id, CT_BINARY);
+        self.binary(v);
+    }
+
+    fn list_field(&mut self, id: i16, elem_type: u8, size: usize) {
+        self.field(id, CT_LIST);
+        if size < 15 {
+            self.buf.push(((size as u8) << 4) | elem_type);
+        } else {
+            self.buf.push(0xf0 | elem_type);
+            self.varint(size as u64);
+        }
+    }
+
+    // List elements are written without a field header.
+    fn i32(&mut self, v: i32) {
+        self.varint(Self::zigzag32(v));
+    }
+
+    fn binary(&mut self, v: &[u8]) {
+        self.varint(v.len() as u64);
+        self.buf.extend_from_slice(v);
+    }
+}
+
+// Encode definition levels (which are all 0 or 1 for a flat schema) with the
+// RLE/bit-packed hybrid encoding, using only RLE runs, and prefix them with
+// their length as a data page requires.
+fn encode_levels(levels: &[bool]) -> Vec<u8> {
+    let mut runs = Compact::default();
+    let mut i = 0;
+
+    while i < levels.len() {
+        let value = levels[i];
+        let len = levels[i..].iter().take_while(|l| **l == value).count();
+
+        runs.varint((len as u64) << 1);
+        runs.buf.push(value as u8);
+        i += len;
+    }
+
+    let mut out = (runs.buf.len() as u32).to_le_bytes().to_vec();
+    out.extend_from_slice(&runs.buf);
+    out
+}
+
+// The values of a column in the current row group.
+#[derive(Default)]
+struct ColumnBuffer {
+    values: Vec<u8>,
+    levels: Vec<bool>,
+}
+
+struct ColumnChunkMeta {
+    column_type: ColumnType,
+    name: String,
+    num_values: i64,
+    size: i64,
+    data_page_offset: i64,
+}
+
+struct RowGroupMeta {
+    columns: Vec<ColumnChunkMeta>,
+    num_rows: i64,
+}
+
+/// Writes rows to a Parquet file.  Rows are buffered in memory until
+/// `row_group_size` of them have been written, and then written out as a row
+/// group.  The file is not valid until `finish()` has been called.
+pub struct ParquetWriter<W: Write> {
+    out: W,
+    offset: u64,
+    columns: Vec<Column>,
+    buffers: Vec<ColumnBuffer>,
+    row_group_size: usize,
+    rows_in_group: usize,
+    row_groups: Vec<RowGroupMeta>,
+    num_rows: i64,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    pub fn new(
+        mut out: W,
+        columns: Vec<Column>,
+        row_group_size: usize,
+    ) -> io::Result<ParquetWriter<W>> {
+        out.write_all(MAGIC)?;
+
+        let buffers = columns.iter().map(|_| ColumnBuffer::default()).collect();
+
+        Ok(ParquetWriter {
+            out,
+            offset: MAGIC.len() as u64,
+            columns,
+            buffers,
+            row_group_size: std::cmp::max(row_group_size, 1),
+            rows_in_group: 0,
+            row_groups: vec![],
+            num_rows: 0,
+        })
+    }
+
+    pub fn write_row(&mut self, row: &[Datum]) -> io::Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Expected {} values, found {}",
+                    self.columns.len(),
+                    row.len()
+                ),
+            ));
+        }
+
+        // Check the whole row before buffering any of it, so that a bad
+        // value can not leave the columns with different numbers of rows.
+        for (column, datum) in self.columns.iter().zip(row.iter()) {
+            let ok = match (column.column_type, datum) {
+                (_, Datum::Null) => column.optional,
+                (ColumnType::Int32, Datum::Int32(_)) => true,
+                (ColumnType::Int64, Datum::Int64(_)) => true,
+                (ColumnType::Utf8, Datum::Utf8(_)) => true,
+                _ => false,
+            };
+
+            if !ok {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value for {:?} column {}: {:?}",
+                        column.column_type, column.name, datum
+                    ),
+                ));
+            }
+        }
+
+        for ((column, datum), buffer) in self
+            .columns
+            .iter()
+            .zip(row.iter())
+            .zip(self.buffers.iter_mut())
+        {
+            if column.optional {
+                buffer.levels.push(*datum != Datum::Null);
+            }
+
+            match datum {
+                Datum::Null => (),
+                Datum::Int32(v) => buffer.values.extend(&v.to_le_bytes()),
+                Datum::Int64(v) => buffer.values.extend(&v.to_le_bytes()),
+                Datum::Utf8(s) => {
+                    buffer.values.extend(&(s.len() as u32).to_le_bytes());
+                    buffer.values.extend(s.as_bytes());
+                }
+            }
+        }
+
+        self.rows_in_group += 1;
+        self.num_rows += 1;
+
+        if self.rows_in_group >= self.row_group_size {
+            self.flush_row_group()?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.out.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn flush_row_group(&mut self) -> io::Result<()> {
+        if self.rows_in_group == 0 {
+            return Ok(());
+        }
+
+        let num_rows = self.rows_in_group as i64;
+        let buffers: Vec<ColumnBuffer> = self
+            .columns
+            .iter()
+            .map(|_| ColumnBuffer::default())
+            .collect();
+        let buffers = std::mem::replace(&mut self.buffers, buffers);
+        let mut chunks = vec![];
+
+        for (column, buffer) in self.columns.clone().iter().zip(buffers) {
+            let mut page = vec![];
+            if column.optional {
+                page.extend(encode_levels(&buffer.levels));
+            }
+            page.extend(buffer.values);
+
+            let mut header = Compact::default();
+            header.struct_begin();
+            header.i32_field(1, PAGE_TYPE_DATA_PAGE);
+            header.i32_field(2, page.len() as i32);
+            header.i32_field(3, page.len() as i32);
+            header.struct_field_begin(5);
+            header.i32_field(1, num_rows as i32);
+            header.i32_field(2, ENCODING_PLAIN);
+            header.i32_field(3, ENCODING_RLE);
+            header.i32_field(4, ENCODING_RLE);
+            header.struct_end();
+            header.struct_end();
+
+            let data_page_offset = self.offset as i64;
+            self.write(&header.buf)?;
+            self.write(&page)?;
+
+            chunks.push(ColumnChunkMeta {
+                column_type: column.column_type,
+                name: column.name.clone(),
+                num_values: num_rows,
+                size: (header.buf.len() + page.len()) as i64,
+                data_page_offset,
+            });
+        }
+
+        self.row_groups.push(RowGroupMeta {
+            columns: chunks,
+            num_rows,
+        });
+        self.rows_in_group = 0;
+
+        Ok(())
+    }
+
+    fn file_metadata(&self) -> Vec<u8> {
+        let mut meta = Compact::default();
+
+        meta.struct_begin();
+        meta.i32_field(1, 1);
+
+        // The schema is flattened, starting with a root element that has
+        // every column as a child.
+        meta.list_field(2, CT_STRUCT, self.columns.len() + 1);
+        meta.struct_begin();
+        meta.binary_field(4, b"schema");
+        meta.i32_field(5, self.columns.len() as i32);
+        meta.struct_end();
+
+        for column in self.columns.iter() {
+            let repetition = if column.optional {
+                REPETITION_OPTIONAL
+            } else {
+                REPETITION_REQUIRED
+            };
+
+            meta.struct_begin();
+            meta.i32_field(1, column.column_type.physical_type());
+            meta.i32_field(3, repetition);
+            meta.binary_field(4, column.name.as_bytes());
+            if column.column_type == ColumnType::Utf8 {
+                meta.i32_field(6, CONVERTED_TYPE_UTF8);
+            }
+            meta.struct_end();
+        }
+
+        meta.i64_field(3, self.num_rows);
+
+        meta.list_field(4, CT_STRUCT, self.row_groups.len());
+        for row_group in self.row_groups.iter() {
+            meta.struct_begin();
+            meta.list_field(1, CT_STRUCT, row_group.columns.len());
+            for chunk in row_group.columns.iter() {
+                meta.struct_begin();
+                meta.i64_field(2, chunk.data_page_offset);
+
+                meta.struct_field_begin(3);
+                meta.i32_field(1, chunk.column_type.physical_type());
+                meta.list_field(2, CT_I32, 2);
+                meta.i32(ENCODING_PLAIN);
+                meta.i32(ENCODING_RLE);
+                meta.list_field(3, CT_BINARY, 1);
+                meta.binary(chunk.name.as_bytes());
+                meta.i32_field(4, CODEC_UNCOMPRESSED);
+                meta.i64_field(5, chunk.num_values);
+                meta.i64_field(6, chunk.size);
+                meta.i64_field(7, chunk.size);
+                meta.i64_field(9, chunk.data_page_offset);
+                meta.struct_end();
+
+                meta.struct_end();
+            }
+
+            let total_size = row_group.columns.iter().map(|c| c.size).sum();
+            meta.i64_field(2, total_size);
+            meta.i64_field(3, row_group.num_rows);
+            meta.struct_end();
+        }
+
+        meta.binary_field(6, CREATED_BY.as_bytes());
+        meta.struct_end();
+
+        meta.buf
+    }
+
+    /// Write any buffered rows and the file footer, and return the
+    /// underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_row_group()?;
+
+        let meta = self.file_metadata();
+        self.write(&meta)?;
+        self.write(&(meta.len() as u32).to_le_bytes())?;
+        self.write(MAGIC)?;
+        self.out.flush()?;
+
+        Ok(self.out)
+    }
+
+    /// The number of rows written so far.
+    pub fn num_rows(&self) -> i64 {
+        self.num_rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_encoding() {
+        let mut c = Compact::default();
+        c.varint(300);
+        assert_eq!(c.buf, vec![0xac, 0x02]);
+
+        assert_eq!(Compact::zigzag32(0), 0);
+        assert_eq!(Compact::zigzag32(-1), 1);
+        assert_eq!(Compact::zigzag32(1), 2);
+        assert_eq!(Compact::zigzag64(-2), 3);
+
+        // Short field deltas are packed into the type byte, long ones are
+        // followed by the field id.
+        let mut c = Compact::default();
+        c.i32_field(1, 1);
+        c.i32_field(20, 1);
+        assert_eq!(c.buf, vec![0x15, 0x02, 0x05, 0x28, 0x02]);
+    }
+
+    #[test]
+    fn levels_run_length_encoded() {
+        let levels = [true, true, true, false, true];
+        assert_eq!(
+            encode_levels(&levels),
+            vec![6, 0, 0, 0, 0x06, 1, 0x02, 0, 0x02, 1]
+        );
+    }
+
+    #[test]
+    fn file_layout() {
+        let columns = vec![
+            Column::required("id", ColumnType::Utf8),
+            Column::optional("size", ColumnType::Int64),
+        ];
+        let mut writer =
+            ParquetWriter::new(vec![], columns, 2).expect("writer");
+
+        for (id, size) in &[("a", Some(1)), ("b", None), ("c", Some(3))] {
+            let size = size.map_or(Datum::Null, Datum::Int64);
+            writer
+                .write_row(&[Datum::Utf8(id.to_string()), size])
+                .expect("write row");
+        }
+
+        assert!(writer.write_row(&[Datum::Null, Datum::Null]).is_err());
+        assert!(writer.write_row(&[Datum::Int32(1), Datum::Null]).is_err());
+        assert_eq!(writer.num_rows(), 3);
+
+        let file = writer.finish().expect("finish");
+        let len = file.len();
+        let mut footer_len = [0u8; 4];
+        footer_len.copy_from_slice(&file[len - 8..len - 4]);
+        let footer_len = u32::from_le_bytes(footer_len) as usize;
+
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[len - 4..], MAGIC);
+        assert!(footer_len < len - 12);
+
+        // The footer ends with the created_by string and the closing stop
+        // field of the FileMetaData struct.
+        let footer = &file[len - 8 - footer_len..len - 8];
+        assert!(footer.ends_with(&[CREATED_BY.as_bytes(), &[0]].concat()));
+    }
+}
@@ -8,28 +8,138 @@
  * Copyright 2020 Joyent, Inc.
  */
 
+// The jobs table, and the database of each job, are kept in PostgreSQL.  By
+// default this is the server running in the manager zone, but any server can
+// be configured (see ConfigDatabase) so that large evacuations are not limited
+// to the manager zone's disk.  configure() must be called before anything
+// else in this module is used.
+
+use crate::config::ConfigDatabase;
 use rebalancer::error::Error;
 
+use std::sync::RwLock;
+use std::time::Duration;
+
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::result::ConnectionError;
 use diesel::sql_query;
+use lazy_static::lazy_static;
 
-static DB_URL: &str = "postgres://postgres:postgres@";
 pub static REBALANCER_DB: &str = "rebalancer";
 
+lazy_static! {
+    static ref DB_CONFIG: RwLock<ConfigDatabase> =
+        RwLock::new(ConfigDatabase::default());
+}
+
+// Percent-encode everything but the unreserved characters, so that a user
+// name or password can be put in a URL as is.
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Quote a value for a key=value connection string.
+fn conn_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Use the given PostgreSQL server from now on.
+pub fn configure(config: &ConfigDatabase) {
+    *DB_CONFIG.write().expect("db config lock") = config.clone();
+}
+
+fn server() -> String {
+    let config = DB_CONFIG.read().expect("db config lock");
+    let port = config.port.map_or(String::new(), |p| format!(":{}", p));
+
+    format!(
+        "postgres://{}:{}@{}{}",
+        url_encode(&config.user),
+        url_encode(&config.password),
+        config.host,
+        port
+    )
+}
+
+/// The URL used to connect to the specified database.
+pub fn db_url(db_name: &str) -> String {
+    format!("{}/{}", server(), db_name)
+}
+
+/// The connection string used to connect to the specified database with the
+/// postgres crate, which can not connect over a unix domain socket by
+/// default.  The local server is reached at localhost instead.
+pub fn db_conn_string(db_name: &str) -> String {
+    let config = DB_CONFIG.read().expect("db config lock");
+    let host = if config.host.is_empty() {
+        "localhost"
+    } else {
+        config.host.as_str()
+    };
+    let port = config
+        .port
+        .map_or(String::new(), |p| format!(" port={}", p));
+
+    format!(
+        "host={}{} user={} password={} dbname={}",
+        conn_quote(host),
+        port,
+        conn_quote(&config.user),
+        conn_quote(&config.password),
+        conn_quote(db_name)
+    )
+}
+
 pub fn connect_db(db_name: &str) -> Result<PgConnection, Error> {
-    let connect_url = format!("{}/{}", DB_URL, db_name);
-    PgConnection::establish(&connect_url).map_err(Error::from)
+    PgConnection::establish(&db_url(db_name)).map_err(Error::from)
+}
+
+/// Connect to the specified database, giving up if that takes longer than
+/// `timeout`, as does any statement run on the connection.
+pub fn connect_db_timeout(
+    db_name: &str,
+    timeout: Duration,
+) -> Result<PgConnection, Error> {
+    // connect_timeout is in whole seconds.
+    let url = format!(
+        "{}?connect_timeout={}",
+        db_url(db_name),
+        timeout.as_secs().max(1)
+    );
+    let conn = PgConnection::establish(&url)?;
+
+    conn.execute(&format!("SET statement_timeout = {}", timeout.as_millis()))?;
+    Ok(conn)
 }
 
 pub fn create_db(db_name: &str) -> Result<usize, Error> {
     let create_query = format!("CREATE DATABASE \"{}\"", db_name);
-    let conn = PgConnection::establish(&DB_URL)?;
+    let conn = PgConnection::establish(&server())?;
 
     conn.execute(&create_query).map_err(Error::from)
 }
 
+// Drop the specified database, if it exists.  This fails if anything is still
+// connected to it.
+pub fn drop_db(db_name: &str) -> Result<usize, Error> {
+    let drop_query = format!("DROP DATABASE IF EXISTS \"{}\"", db_name);
+    let conn = PgConnection::establish(&server())?;
+
+    conn.execute(&drop_query).map_err(Error::from)
+}
+
 table! {
     use diesel::sql_types::Text;
     pg_database (datname) {
@@ -45,7 +155,7 @@ struct PgDatabase {
 
 pub fn list_databases() -> Result<Vec<String>, Error> {
     let list_query = "SELECT datname FROM pg_database";
-    let conn = PgConnection::establish(&DB_URL)?;
+    let conn = PgConnection::establish(&server())?;
 
     sql_query(list_query)
         .load::<PgDatabase>(&conn)
@@ -9,16 +9,77 @@
  */
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures::Future;
 use hyper::HeaderMap;
-use manager::jobs::{EvacuateJobPayload, JobPayload};
+use inflector::cases::titlecase::to_title_case;
+use manager::jobs::estimate::EstimatePayload;
+use manager::jobs::evacuate::{EvacuateJobUpdateMessage, EvacuateObjectStatus};
+use manager::jobs::export::{self, ExportFormat};
+use manager::jobs::plan::PlanPayload;
+use manager::jobs::rollback::RollbackObjectStatus;
+use manager::jobs::verify::VerifyObjectStatus;
+use rebalancer_client::client::{ClientError, RebalancerClient, DEFAULT_URL};
+use rebalancer_client::compat::{self, Compatibility, VersionInfo};
+use rebalancer_client::error::ApiError;
+use rebalancer_client::jobs::{
+    ChecksumPolicy, ConfirmJobPayload, CreateCopyJobPayload,
+    EvacuateJobPayload, JobPayload, JobPriority, JobSchedule, JobState,
+    ObjectFilter, ObjectSource, RemoveCopyJobPayload, RollbackJobPayload,
+    VerifyJobPayload,
+};
+use rebalancer_client::status::{JobProgress, PhaseProgress};
+use rebalancer_client::status::{JobStatus, JobStatusConfig, JobStatusResults};
 use reqwest;
+use serde::Serialize;
 use serde_json::Value;
-use std::io::Write;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::{Command, Stdio};
 use std::result::Result;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use strum::IntoEnumIterator;
+use tokio::runtime::current_thread::Runtime;
 
 pub static JOBS_URL: &str = "http://localhost/jobs";
+pub static ALERTS_URL: &str = "http://localhost/alerts";
+pub static AGENTS_URL: &str = "http://localhost/agents";
+pub static DESTINATIONS_URL: &str = "http://localhost/destinations";
+pub static PLANS_URL: &str = "http://localhost/plans";
+pub static ESTIMATE_URL: &str = "http://localhost/estimate";
+pub static MANAGERS_URL: &str = "http://localhost/managers";
 pub static VERSION: &str = "0.1.0";
 
+// How often `job run --wait` gets the status of the job it is waiting for.
+static DEFAULT_RUN_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often `job watch` refreshes its display.
+static DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Clears the terminal and moves the cursor to its top left corner.
+static CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+// If set, rebalancer-adm does not check that the manager is compatible with
+// it before sending it anything.
+pub static SKIP_VERSION_CHECK_ENV: &str = "REBALANCER_ADM_SKIP_VERSION_CHECK";
+
+// The requests of the typed client are run to completion one at a time.
+fn runtime() -> Result<Runtime, String> {
+    Runtime::new().map_err(|e| format!("Failed to start runtime: {}", e))
+}
+
+fn run_request<T>(
+    request: impl Future<Item = T, Error = ClientError>,
+) -> Result<T, String> {
+    runtime()?.block_on(request).map_err(|e| e.to_string())
+}
+
+fn client() -> Result<RebalancerClient, String> {
+    RebalancerClient::new(DEFAULT_URL).map_err(|e| e.to_string())
+}
+
 fn output_common(response_headers: HeaderMap, message: String) {
     let version = match response_headers.get("server") {
         Some(v) => v.to_str().unwrap_or("unknown"),
@@ -31,6 +92,18 @@ fn output_common(response_headers: HeaderMap, message: String) {
 }
 
 fn post_common<T>(url: &str, body: T) -> Result<(), String>
+where
+    T: Into<reqwest::Body>,
+{
+    let (headers, job_uuid) = post_text(url, body)?;
+
+    output_common(headers, job_uuid);
+
+    Ok(())
+}
+
+// Post `body` to `url`, returning the headers and the body of the response.
+fn post_text<T>(url: &str, body: T) -> Result<(HeaderMap, String), String>
 where
     T: Into<reqwest::Body>,
 {
@@ -40,33 +113,75 @@ where
         .map_err(|e| e.to_string())?;
 
     // Send the request.
-    let mut response = match client.post(url).body(body).send() {
+    let response = match client.post(url).body(body).send() {
         Ok(resp) => resp,
         Err(e) => return Err(format!("Failed to post job: {}", &e)),
     };
 
+    response_text(response)
+}
+
+// Put `body` to `url`, returning the headers and the body of the response.
+fn put_text<T>(url: &str, body: T) -> Result<(HeaderMap, String), String>
+where
+    T: Into<reqwest::Body>,
+{
+    let client = reqwest::ClientBuilder::new()
+        .timeout(None)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let response = match client.put(url).body(body).send() {
+        Ok(resp) => resp,
+        Err(e) => return Err(format!("Failed to update job: {}", &e)),
+    };
+
+    response_text(response)
+}
+
+// Why the manager refused a request, with its error code if it gave one.
+fn refusal(what: &str, response: &mut reqwest::Response) -> String {
+    let status = response.status();
+
+    match response.json::<ApiError>() {
+        Ok(error) => format!("{}: {}: {}", what, status, error),
+        Err(_) => format!("{}: {}", what, status),
+    }
+}
+
+fn response_text(
+    mut response: reqwest::Response,
+) -> Result<(HeaderMap, String), String> {
     // Flag failure if we get a status code of anything other than 200.
     if !response.status().is_success() {
-        return Err(format!("Server response: {}", response.status()));
+        return Err(refusal("Server response", &mut response));
     }
 
     let headers = response.headers().clone();
 
     // Parse out the job uuid from the response payload.
-    let job_uuid = match response.text() {
-        Ok(j) => j,
-        Err(e) => return Err(format!("Failed to parse response: {}", e)),
-    };
-
-    output_common(headers, job_uuid);
-
-    Ok(())
+    match response.text() {
+        Ok(j) => Ok((headers, j)),
+        Err(e) => Err(format!("Failed to parse response: {}", e)),
+    }
 }
 
 // Common function used in order to get a list of jobs, or get specific job
 // information.  The contents of the response are evaluated and printed by
 // the caller.
 fn get_common(url: &str) -> Result<(), String> {
+    let (headers, v) = get_json(url)?;
+
+    let result = match serde_json::to_string_pretty(&v) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Failed to deserialize: {}", &e)),
+    };
+
+    output_common(headers, result);
+    Ok(())
+}
+
+fn get_json(url: &str) -> Result<(HeaderMap, Value), String> {
     // Create a client without a timeout.  We need to make a 'count()' query
     // to get accurate numbers for job status.  This can take a while and no
     // sense in timing out.  If the user doesn't want to wait, ctrl-c is
@@ -83,7 +198,7 @@ fn get_common(url: &str) -> Result<(), String> {
 
     // Flag failure if we get a status code of anything other than 200.
     if !response.status().is_success() {
-        return Err(format!("Failed to get job: {}", response.status()));
+        return Err(refusal("Failed to get job", &mut response));
     }
 
     let headers = response.headers().clone();
@@ -93,12 +208,56 @@ fn get_common(url: &str) -> Result<(), String> {
         Err(e) => return Err(format!("Failed to parse response body: {}", &e)),
     };
 
-    let result = match serde_json::to_string_pretty(&v) {
-        Ok(s) => s,
-        Err(e) => return Err(format!("Failed to deserialize: {}", &e)),
+    Ok((headers, v))
+}
+
+// Make sure that the manager speaks a version of the API that we understand
+// before sending it anything, rather than leaving the operator to make sense
+// of a response that we can not.  If the manager can not be reached at all,
+// the request for the command itself will say so.
+fn check_manager_version() -> Result<(), String> {
+    if std::env::var_os(SKIP_VERSION_CHECK_ENV).is_some() {
+        return Ok(());
+    }
+
+    let manager = match runtime()?.block_on(client()?.version()) {
+        Ok(v) => v,
+        Err(ClientError::Request(_)) => return Ok(()),
+        Err(e) => {
+            eprintln!("warning: unable to check the manager's version: {}", e);
+            return Ok(());
+        }
     };
 
-    output_common(headers, result);
+    match compat::check(&VersionInfo::new(VERSION), &manager) {
+        Compatibility::Compatible => Ok(()),
+        Compatibility::Warning(msg) => {
+            eprintln!("warning: {}", msg);
+            Ok(())
+        }
+        Compatibility::Incompatible(msg) => Err(format!(
+            "{} (set {} to skip this check)",
+            msg, SKIP_VERSION_CHECK_ENV
+        )),
+    }
+}
+
+// Print the manager's recommended Prometheus alerting rules.  Unlike the
+// other subcommands the response is printed as is, so that it can be
+// redirected straight into a rules file.
+fn alerts_get() -> Result<(), String> {
+    let mut response = reqwest::get(ALERTS_URL)
+        .map_err(|e| format!("Request failed: {}", &e))?;
+
+    if !response.status().is_success() {
+        return Err(refusal("Failed to get alerts", &mut response));
+    }
+
+    let rules = response
+        .text()
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    print!("{}", rules);
     Ok(())
 }
 
@@ -120,213 +279,2256 @@ fn job_get(matches: &ArgMatches) -> Result<(), String> {
         }
     });
 
-    get_common(&url)
-}
+    let (headers, mut v) = get_json(&url)?;
 
-fn job_retry(matches: &ArgMatches) -> Result<(), String> {
-    let uuid = matches.value_of("uuid").expect("retry uuid");
-    let url = format!("{}/{}/retry", JOBS_URL, uuid);
+    // The progress of the job is printed as tables of its own rather than
+    // with the rest of the status.
+    let progress = v.as_object_mut().and_then(|o| o.remove("progress"));
 
-    post_common(&url, vec![])
-}
+    let result = match serde_json::to_string_pretty(&v) {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Failed to deserialize: {}", &e)),
+    };
 
-fn job_create(matches: &ArgMatches) -> Result<(), String> {
-    match matches.subcommand() {
-        ("evacuate", Some(evac_matches)) => job_create_evacuate(evac_matches),
-        _ => unreachable!(),
+    output_common(headers, result);
+
+    if let Some(progress) = progress {
+        let progress: JobProgress = serde_json::from_value(progress)
+            .map_err(|e| format!("Failed to parse job progress: {}", e))?;
+        println!();
+        print!("{}", format_progress(&progress));
     }
+
+    Ok(())
 }
 
-// Post an evacuate job to the manager.  In the future, there may be more than
-// one kind of job that is supported, at which point, we should probably break
-// out the evacate-specific logic in to another subroutine that this function
-// calls.
-fn job_create_evacuate(matches: &ArgMatches) -> Result<(), String> {
-    // Get the storage id from the args.  Clap ensures that this argument is
-    // supplied to us before we even reach this point.
-    let shark = matches.value_of("shark").unwrap();
+fn format_phase(name: &str, phase: &PhaseProgress) -> String {
+    format!(
+        "  {:<17}{:<13}{} in progress, {} done\n",
+        name,
+        phase.state.to_string(),
+        phase.in_progress,
+        phase.done
+    )
+}
 
-    // Max objects is an optional argument.
-    let max_objects = match matches.value_of("max_objects") {
-        None => None,
-        Some(m) => match m.parse::<u32>() {
-            Ok(n) => Some(n),
-            Err(e) => {
-                return Err(format!(
-                    "Numeric value required for max_objects: {}",
-                    e
-                ));
-            }
-        },
-    };
+// Lay out where each phase of a job has got to, and a table of the job's
+// objects in each shard by where they have got to.
+fn format_progress(progress: &JobProgress) -> String {
+    let phases = &progress.phases;
+    let mut out = String::from("Phases:\n");
 
-    // Form the payload of the request.
-    let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
-        from_shark: shark.to_owned(),
-        max_objects,
-    });
+    out.push_str(&format!(
+        "  {:<17}{:<13}{} of {} shards scanned, {} objects found\n",
+        "ingestion",
+        phases.ingestion.state.to_string(),
+        phases.ingestion.shards_scanned,
+        phases.ingestion.shards,
+        phases.ingestion.objects
+    ));
+    out.push_str(&format_phase("copying", &phases.copying));
+    out.push_str(&format_phase("metadata update", &phases.metadata_update));
 
-    // Serialize it.
-    let payload: String =
-        serde_json::to_string(&job_payload).expect("Serialize job payload");
+    if progress.shards.is_empty() {
+        return out;
+    }
 
-    post_common(JOBS_URL, payload)
-}
+    out.push_str("\nShards:\n");
+    out.push_str(&format!(
+        "  {:>7} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+        "SHARD",
+        "SCANNED",
+        "OBJECTS",
+        "COPYING",
+        "METADATA",
+        "COMPLETE",
+        "SKIPPED",
+        "ERROR"
+    ));
 
-// The `job' subcommand currently requires one of three different primary
-// arguments.  While there are other arguments that might accompany the
-// ones listed below, those are parsed separately depending on which of
-// the pimary arguments are supplied.
-fn process_subcmd_job(job_matches: &ArgMatches) -> Result<(), String> {
-    match job_matches.subcommand() {
-        ("get", Some(get_matches)) => job_get(get_matches),
-        ("list", Some(_)) => get_common(JOBS_URL),
-        ("retry", Some(retry_matches)) => job_retry(retry_matches),
-        ("create", Some(create_matches)) => job_create(create_matches),
-        _ => unreachable!(),
+    for shard in progress.shards.iter() {
+        out.push_str(&format!(
+            "  {:>7} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            shard.shard,
+            if shard.scanned { "yes" } else { "no" },
+            shard.objects,
+            shard.copying,
+            shard.metadata_update,
+            shard.complete,
+            shard.skipped,
+            shard.error
+        ));
     }
+
+    out
 }
 
-fn main() -> Result<(), String> {
-    let evacuate_subcommand = App::new("evacuate")
-        .about("Create an evacuate job")
-        .arg(
-            Arg::with_name("shark")
-                .short("s")
-                .long("shark")
-                .takes_value(true)
-                .required(true)
-                .help("Specifies a shark on which to run a job"),
-        )
-        .arg(
-            Arg::with_name("max_objects")
-                .short("m")
-                .long("max_objects")
-                .takes_value(true)
-                .help("Maximum number of objects allowed in the job"),
-        );
+// The node-manta command that uploads an export to Manta.  It takes the
+// account and key to upload with from the MANTA_* environment variables, as
+// it does when it is run by hand.
+static MPUT: &str = "mput";
 
-    let matches = App::new("rebalancer-adm")
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .version(VERSION)
-        .about("Rebalancer client utility")
-        .subcommand(
-            App::new("job")
-                .setting(AppSettings::SubcommandRequiredElseHelp)
-                .about("Job operations")
-                // Get subcommand
-                .subcommand(
-                    App::new("get")
-                        .about("Get information on a specific job")
-                        .arg(
-                            Arg::with_name("uuid")
-                                .takes_value(true)
-                                .required(true)
-                                .help("Uuid of a job"),
-                        ),
-                )
-                // Retry subcommand
-                .subcommand(
-                    SubCommand::with_name("retry")
-                        .about("retry a previously run and completed job")
-                        .arg(
-                            Arg::with_name("uuid")
-                                .help("uuid of previous job")
-                                .required(true)
-                                .takes_value(true),
-                        ),
-                )
-                // List subcommand
-                .subcommand(
-                    App::new("list").about("List all known rebalancer jobs"),
-                )
-                // Create subcommand
-                .subcommand(
-                    App::new("create")
-                        .about("Create a rebalancer job")
-                        .setting(AppSettings::SubcommandRequiredElseHelp)
-                        // Create evacuate job
-                        .subcommand(evacuate_subcommand),
-                ),
-        )
-        .get_matches();
+// Write the outcome of every object in a job to a file, or to an object in
+// Manta.  Rather than going through the manager, this reads the job's
+// database directly, since the export of a large job can be many gigabytes.
+fn job_export(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("export uuid");
 
-    match matches.subcommand() {
-        ("job", Some(job_matches)) => process_subcmd_job(job_matches),
-        _ => unreachable!(),
-    }
+    // Clap restricts the format to one of the possible values.
+    let format = ExportFormat::from_str(
+        matches.value_of("format").expect("export format"),
+    )
+    .map_err(|e| format!("Invalid format: {}", e))?;
+
+    // Clap requires one or the other.
+    let (count, output) = match matches.value_of("manta_path") {
+        Some(path) => (job_export_manta(uuid, format, path)?, path),
+        None => {
+            let output = matches.value_of("output").expect("export output");
+            let file = File::create(output)
+                .map_err(|e| format!("Could not create {}: {}", output, e))?;
+
+            let count = export::export_job(uuid, format, BufWriter::new(file))
+                .map_err(|e| format!("Export failed: {}", e))?;
+            (count, output)
+        }
+    };
+
+    println!("Exported {} objects to {}", count, output);
+    Ok(())
 }
 
-#[cfg(test)]
-mod rebalancer_adm_tests {
-    use super::*;
-    use assert_cli;
-    use indoc::indoc;
+// Upload the export to `path` in Manta, as it is written, by piping it to
+// mput.  If the export fails part way through, mput is killed before it can
+// finish the upload, so that no partial export is left at `path`.
+fn job_export_manta(
+    uuid: &str,
+    format: ExportFormat,
+    path: &str,
+) -> Result<u64, String> {
+    let mut child = Command::new(MPUT)
+        .arg("-p")
+        .arg("-H")
+        .arg(format!("content-type: {}", format.content_type()))
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run {}: {}", MPUT, e))?;
 
-    #[test]
-    fn no_params() {
-        let usage = indoc!(
-            "
-            Rebalancer client utility
+    let mut stdin = BufWriter::new(child.stdin.take().expect("mput stdin"));
+    let count = match export::export_job(uuid, format, &mut stdin) {
+        Ok(count) => count,
+        Err(e) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("Export failed: {}", e));
+        }
+    };
 
-            USAGE:
-                rebalancer-adm <SUBCOMMAND>
+    // mput only finishes the upload once its input is closed.
+    drop(stdin);
 
-            FLAGS:
-                -h, --help       Prints help information
-                -V, --version    Prints version information
+    let status = child
+        .wait()
+        .map_err(|e| format!("Could not wait for {}: {}", MPUT, e))?;
 
-            SUBCOMMANDS:
-                help    Prints this message or the help of the given \
-                subcommand(s)
-                job     Job operations
-            "
-        );
+    if !status.success() {
+        return Err(format!("{} {} failed: {}", MPUT, path, status));
+    }
 
-        assert_cli::Assert::cargo_binary("rebalancer-adm")
-            .fails()
-            .stderr()
-            .contains(VERSION)
-            .and()
-            .stderr()
-            .contains(usage)
-            .unwrap();
+    Ok(count)
+}
+
+// List the jobs known to the manager, optionally restricted to those matching
+// the given filters.
+fn job_list(matches: &ArgMatches) -> Result<(), String> {
+    let mut params = vec![];
+    for param in &[
+        "state",
+        "action",
+        "created_after",
+        "created_before",
+        "limit",
+        "offset",
+        "order",
+    ] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
     }
 
-    #[test]
-    fn job_list_extra_params() {
-        let err_msg = indoc!(
-            "
-            error: Found argument 'extra' which wasn't expected, or isn't \
-            valid in this context
+    let url = reqwest::Url::parse_with_params(JOBS_URL, &params)
+        .map_err(|e| format!("Invalid request: {}", e))?;
 
-            USAGE:
-                rebalancer-adm job list
-            "
-        );
+    get_common(url.as_str())
+}
 
-        assert_cli::Assert::cargo_binary("rebalancer-adm")
-            .with_args(&["job", "list", "extra"])
-            .fails()
-            .and()
-            .stderr()
-            .contains(err_msg)
-            .unwrap();
+// List the objects that a job skipped, or with `--summary` the number that
+// were skipped for each reason.
+fn job_skipped(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("skipped uuid");
+
+    if matches.is_present("summary") {
+        let url = format!("{}/{}/skipped/summary", JOBS_URL, uuid);
+        return get_common(&url);
     }
 
-    #[test]
-    fn job_get_no_params() {
-        let err_msg = indoc!(
-            "
-            error: The following required arguments were not provided:
-                <uuid>
+    let mut params = vec![];
+    for param in &["reason", "limit", "offset"] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
+    }
 
-            USAGE:
-                rebalancer-adm job get <uuid>
-            "
-        );
+    // Skipped reasons such as `{http_status_code:404}` need to be encoded.
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/skipped", JOBS_URL, uuid),
+        &params,
+    )
+    .map_err(|e| format!("Invalid request: {}", e))?;
 
-        assert_cli::Assert::cargo_binary("rebalancer-adm")
+    get_common(url.as_str())
+}
+
+// List the objects that agents were slow to move for a job, slowest first.
+fn job_slow(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("slow uuid");
+
+    let mut params = vec![];
+    for param in &["limit", "offset"] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
+    }
+
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/slow", JOBS_URL, uuid),
+        &params,
+    )
+    .map_err(|e| format!("Invalid request: {}", e))?;
+
+    get_common(url.as_str())
+}
+
+// List the objects whose copies a job found served with custom headers that
+// differ from their metadata.
+fn job_headers(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("headers uuid");
+
+    let mut params = vec![];
+    for param in &["limit", "offset"] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
+    }
+
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/headers", JOBS_URL, uuid),
+        &params,
+    )
+    .map_err(|e| format!("Invalid request: {}", e))?;
+
+    get_common(url.as_str())
+}
+
+// List the decisions that a job recorded about where to put its objects,
+// oldest first.
+fn job_placement(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("placement uuid");
+
+    let mut params = vec![];
+    for param in &["object", "limit", "offset"] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
+    }
+
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/placement", JOBS_URL, uuid),
+        &params,
+    )
+    .map_err(|e| format!("Invalid request: {}", e))?;
+
+    get_common(url.as_str())
+}
+
+// List the changes that a job made to object metadata, oldest first.
+fn job_audit(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("audit uuid");
+
+    let mut params = vec![];
+    for param in &["limit", "offset"] {
+        if let Some(value) = matches.value_of(param) {
+            params.push((*param, value));
+        }
+    }
+
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/{}/audit", JOBS_URL, uuid),
+        &params,
+    )
+    .map_err(|e| format!("Invalid request: {}", e))?;
+
+    get_common(url.as_str())
+}
+
+fn job_retry(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("retry uuid");
+    let url = format!("{}/{}/retry", JOBS_URL, uuid);
+
+    post_common(&url, vec![])
+}
+
+// Ask the manager to archive a finished job's database and remove the job.
+fn job_archive(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("archive uuid");
+    let url = format!("{}/{}/archive", JOBS_URL, uuid);
+
+    post_common(&url, vec![])
+}
+
+// Sign off a job that is awaiting confirmation.
+fn job_confirm(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("confirm uuid");
+    let url = format!("{}/{}/confirm", JOBS_URL, uuid);
+
+    let payload = ConfirmJobPayload {
+        confirmed_by: matches.value_of("by").expect("confirm by").to_owned(),
+        comment: matches.value_of("comment").map(|c| c.to_owned()),
+    };
+
+    let payload: String =
+        serde_json::to_string(&payload).expect("Serialize confirmation");
+
+    post_common(&url, payload)
+}
+
+// Have a running job move the tasks that it has not yet started on a
+// destination to its other destinations, and give that destination no more.
+fn job_rebalance_backlog(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("rebalance-backlog uuid");
+    let url = format!("{}/{}", JOBS_URL, uuid);
+
+    let shark = matches.value_of("drain_dest").expect("drain destination");
+    let update = EvacuateJobUpdateMessage::DrainDestination(shark.to_owned());
+    let payload: String =
+        serde_json::to_string(&update).expect("Serialize job update");
+
+    let (headers, message) = put_text(&url, payload)?;
+    output_common(headers, message);
+
+    Ok(())
+}
+
+// Ask the manager to cancel a single assignment belonging to a running job.
+fn assignment_cancel(matches: &ArgMatches) -> Result<(), String> {
+    let job_uuid = matches.value_of("job_uuid").expect("job uuid");
+    let assignment_uuid = matches
+        .value_of("assignment_uuid")
+        .expect("assignment uuid");
+    let url = format!(
+        "{}/{}/assignments/{}/cancel",
+        JOBS_URL, job_uuid, assignment_uuid
+    );
+
+    post_common(&url, vec![])
+}
+
+fn process_subcmd_assignment(
+    assignment_matches: &ArgMatches,
+) -> Result<(), String> {
+    match assignment_matches.subcommand() {
+        ("cancel", Some(cancel_matches)) => assignment_cancel(cancel_matches),
+        _ => unreachable!(),
+    }
+}
+
+// Post to one of the plan routes (or for an estimate), and print the plan
+// that the manager responds with.
+fn post_plan<T>(url: &str, body: T) -> Result<(), String>
+where
+    T: Into<reqwest::Body>,
+{
+    let (headers, text) = post_text(url, body)?;
+
+    let plan: Value = serde_json::from_str(&text)
+        .map_err(|e| format!("Failed to parse response body: {}", e))?;
+    let result = serde_json::to_string_pretty(&plan)
+        .map_err(|e| format!("Failed to deserialize: {}", e))?;
+
+    output_common(headers, result);
+    Ok(())
+}
+
+// Ask the manager to plan the evacuation of some sharks, or of every shark
+// in a datacenter.  Nothing is evacuated until the plan is executed.
+fn plan_create(matches: &ArgMatches) -> Result<(), String> {
+    let payload = PlanPayload {
+        sharks: matches
+            .values_of("shark")
+            .map(|sharks| sharks.map(String::from).collect())
+            .unwrap_or_default(),
+        datacenter: matches.value_of("datacenter").map(String::from),
+        max_concurrent: numeric_arg(matches, "max_concurrent")?,
+        max_fill_percentage: numeric_arg(matches, "max_fill_percentage")?,
+    };
+
+    payload
+        .validate()
+        .map_err(|e| format!("Invalid plan: {}", e))?;
+
+    let payload: String =
+        serde_json::to_string(&payload).expect("Serialize plan payload");
+
+    post_plan(PLANS_URL, payload)
+}
+
+fn plan_get(matches: &ArgMatches) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("plan uuid");
+    let url = format!("{}/{}", PLANS_URL, uuid);
+
+    get_common(&url)
+}
+
+// Execute, pause, resume or abort a plan.
+fn plan_control(matches: &ArgMatches, action: &str) -> Result<(), String> {
+    let uuid = matches.value_of("uuid").expect("plan uuid");
+    let url = format!("{}/{}/{}", PLANS_URL, uuid, action);
+
+    post_plan(&url, vec![])
+}
+
+fn process_subcmd_plan(plan_matches: &ArgMatches) -> Result<(), String> {
+    match plan_matches.subcommand() {
+        ("create", Some(create_matches)) => plan_create(create_matches),
+        ("list", Some(_)) => get_common(PLANS_URL),
+        ("get", Some(get_matches)) => plan_get(get_matches),
+        (action, Some(action_matches)) => plan_control(action_matches, action),
+        _ => unreachable!(),
+    }
+}
+
+// The job described by the subcommand of `job create` or `job run`, checked
+// as the manager would check it.
+fn job_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    let job_payload = match matches.subcommand() {
+        ("evacuate", Some(evac_matches)) => evacuate_payload(evac_matches),
+        ("create-copy", Some(copy_matches)) => {
+            create_copy_payload(copy_matches)
+        }
+        ("remove-copy", Some(remove_matches)) => {
+            remove_copy_payload(remove_matches)
+        }
+        ("verify", Some(verify_matches)) => verify_payload(verify_matches),
+        ("rollback", Some(rollback_matches)) => {
+            rollback_payload(rollback_matches)
+        }
+        _ => unreachable!(),
+    }?;
+
+    job_payload
+        .validate()
+        .map_err(|e| format!("Invalid job: {}", e))?;
+
+    Ok(job_payload)
+}
+
+fn job_create(matches: &ArgMatches) -> Result<(), String> {
+    let payload = serde_json::to_string(&job_payload(matches)?)
+        .expect("Serialize job payload");
+
+    // With --dry_run the job is only checked, and what would have been sent
+    // is shown instead.
+    let dry_run = match matches.subcommand() {
+        (_, Some(sub_matches)) => sub_matches.is_present("dry_run"),
+        _ => false,
+    };
+
+    if dry_run {
+        let value: Value = serde_json::from_str(&payload)
+            .map_err(|e| format!("Failed to parse job payload: {}", e))?;
+        let pretty = serde_json::to_string_pretty(&value)
+            .map_err(|e| format!("Failed to serialize job payload: {}", e))?;
+        println!("{}", pretty);
+        return Ok(());
+    }
+
+    post_common(JOBS_URL, payload)
+}
+
+// Ask the manager what the evacuate job described by the arguments would
+// move, and where to, without creating it.
+fn job_estimate(matches: &ArgMatches) -> Result<(), String> {
+    let evac_matches = match matches.subcommand() {
+        ("evacuate", Some(evac_matches)) => evac_matches,
+        _ => unreachable!(),
+    };
+
+    let job = match evacuate_payload(evac_matches)? {
+        JobPayload::Evacuate(job) => job,
+        _ => unreachable!(),
+    };
+    let payload = EstimatePayload {
+        job,
+        throughput_mb_per_sec: numeric_arg(evac_matches, "throughput")?
+            .map(u64::from),
+    };
+
+    payload
+        .validate()
+        .map_err(|e| format!("Invalid estimate: {}", e))?;
+
+    let payload: String =
+        serde_json::to_string(&payload).expect("Serialize estimate payload");
+
+    post_plan(ESTIMATE_URL, payload)
+}
+
+/// How a job run by `job run` turned out.  Each has an exit code of its own,
+/// so that a script need not parse the summary to know what happened.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RunOutcome {
+    /// The job has not finished (and `--wait` was not given).
+    Running,
+    Complete,
+
+    /// The job completed, but some of its objects were skipped or came to
+    /// an error (or, for verify and rollback jobs, were not found as
+    /// expected).
+    CompleteWithSkips,
+
+    /// The job failed or was stopped.
+    Failed,
+
+    /// The job is paused, or awaiting confirmation, and will go no further
+    /// without an operator.
+    NeedsAttention,
+    TimedOut,
+}
+
+impl RunOutcome {
+    fn exit_code(self) -> i32 {
+        // 1 is left for rebalancer-adm's own errors, such as the manager
+        // not being reachable.
+        match self {
+            RunOutcome::Running | RunOutcome::Complete => 0,
+            RunOutcome::CompleteWithSkips => 2,
+            RunOutcome::Failed => 3,
+            RunOutcome::TimedOut => 4,
+            RunOutcome::NeedsAttention => 5,
+        }
+    }
+}
+
+/// What `job run` prints on its standard output, as a single line of JSON.
+#[derive(Debug, Serialize)]
+struct RunSummary {
+    job_id: String,
+    action: String,
+    state: JobState,
+    outcome: RunOutcome,
+    exit_code: i32,
+    elapsed_secs: u64,
+
+    /// The number of objects counted in `results` that were not dealt with
+    /// cleanly.
+    skipped: i64,
+    results: HashMap<String, i64>,
+}
+
+// The results of the objects that a job of the given kind did not deal with
+// cleanly, as they are named in its status.
+fn unclean_results(config: &JobStatusConfig) -> Vec<String> {
+    let statuses: Vec<String> = match config {
+        JobStatusConfig::Evacuate(_)
+        | JobStatusConfig::CreateCopy(_)
+        | JobStatusConfig::RemoveCopy(_) => vec![
+            EvacuateObjectStatus::Skipped.to_string(),
+            EvacuateObjectStatus::Error.to_string(),
+        ],
+        JobStatusConfig::Verify(_) => VerifyObjectStatus::iter()
+            .filter(|s| *s != VerifyObjectStatus::Present)
+            .map(|s| s.to_string())
+            .collect(),
+        JobStatusConfig::Rollback(_) => RollbackObjectStatus::iter()
+            .filter(|s| *s != RollbackObjectStatus::Reverted)
+            .map(|s| s.to_string())
+            .collect(),
+    };
+
+    statuses.iter().map(|s| to_title_case(s)).collect()
+}
+
+fn run_summary(
+    job_id: &str,
+    status: JobStatus,
+    elapsed: Duration,
+) -> RunSummary {
+    let action = match &status.config {
+        JobStatusConfig::Evacuate(_) => "evacuate",
+        JobStatusConfig::CreateCopy(_) => "create-copy",
+        JobStatusConfig::RemoveCopy(_) => "remove-copy",
+        JobStatusConfig::Verify(_) => "verify",
+        JobStatusConfig::Rollback(_) => "rollback",
+    };
+
+    let results = match status.results {
+        JobStatusResults::Evacuate(r)
+        | JobStatusResults::Verify(r)
+        | JobStatusResults::Rollback(r) => r,
+    };
+
+    let skipped: i64 = unclean_results(&status.config)
+        .iter()
+        .filter_map(|s| results.get(s))
+        .sum();
+
+    let outcome = match status.state {
+        JobState::Complete if skipped > 0 => RunOutcome::CompleteWithSkips,
+        JobState::Complete => RunOutcome::Complete,
+        JobState::Failed | JobState::Stopped => RunOutcome::Failed,
+        JobState::Paused | JobState::AwaitingConfirmation => {
+            RunOutcome::NeedsAttention
+        }
+        _ => RunOutcome::Running,
+    };
+
+    RunSummary {
+        job_id: job_id.to_string(),
+        action: action.to_string(),
+        state: status.state,
+        outcome,
+        exit_code: outcome.exit_code(),
+        elapsed_secs: elapsed.as_secs(),
+        skipped,
+        results,
+    }
+}
+
+fn get_job_status(job_id: &str) -> Result<JobStatus, String> {
+    run_request(client()?.get_job(job_id))
+}
+
+// Create a job and, with --wait, wait for it to finish.  Nothing but the
+// summary is printed on stdout, and rebalancer-adm exits with the code of
+// the job's outcome.
+fn job_run(matches: &ArgMatches) -> Result<(), String> {
+    let timeout = numeric_arg(matches, "timeout")?
+        .map(|t| Duration::from_secs(u64::from(t)));
+    let poll_interval = numeric_arg(matches, "poll_interval")?
+        .map_or(DEFAULT_RUN_POLL_INTERVAL, |p| Duration::from_secs(p.into()));
+    let wait = matches.is_present("wait");
+
+    let start = Instant::now();
+    let job_id = run_request(client()?.create_job(&job_payload(matches)?))?;
+
+    let summary = loop {
+        // A manager that is restarted part way through a job resumes it, so
+        // failing to get the status is only fatal once we are out of time.
+        let status = get_job_status(&job_id);
+        let timed_out = timeout.map_or(false, |t| start.elapsed() >= t);
+
+        match status {
+            Ok(status) => {
+                let mut summary = run_summary(&job_id, status, start.elapsed());
+
+                if !wait || summary.outcome != RunOutcome::Running {
+                    break summary;
+                }
+
+                if timed_out {
+                    summary.outcome = RunOutcome::TimedOut;
+                    summary.exit_code = summary.outcome.exit_code();
+                    break summary;
+                }
+            }
+            Err(e) if timed_out || !wait => return Err(e),
+            Err(e) => {
+                eprintln!("Could not get status of job {}: {}", job_id, e)
+            }
+        }
+
+        thread::sleep(poll_interval);
+    };
+
+    let out = serde_json::to_string(&summary)
+        .map_err(|e| format!("Failed to serialize summary: {}", e))?;
+    println!("{}", out);
+
+    if summary.exit_code != 0 {
+        std::process::exit(summary.exit_code);
+    }
+
+    Ok(())
+}
+
+// The number of objects that a job has finished with, one way or another.
+fn finished_objects(
+    summary: &RunSummary,
+    progress: Option<&JobProgress>,
+) -> i64 {
+    match progress {
+        Some(progress) => progress
+            .shards
+            .iter()
+            .map(|s| s.complete + s.skipped + s.error)
+            .sum(),
+        // Verify and rollback jobs only record objects once they are done
+        // with them.
+        None => summary
+            .results
+            .iter()
+            .filter(|(result, _)| result.as_str() != "Total")
+            .map(|(_, count)| count)
+            .sum(),
+    }
+}
+
+// Lay out one refresh of `job watch`.  `throughput` is the number of objects
+// a second that the job has finished with since the last refresh.
+fn watch_frame(
+    summary: &RunSummary,
+    progress: Option<&JobProgress>,
+    throughput: Option<f64>,
+) -> String {
+    let mut out = format!(
+        "Job {} ({}): {}, watched for {}s\n\n",
+        summary.job_id, summary.action, summary.state, summary.elapsed_secs
+    );
+
+    match throughput {
+        Some(t) => out.push_str(&format!("Throughput:   {:.1} objects/s\n", t)),
+        None => out.push_str("Throughput:   -\n"),
+    }
+    out.push_str(&format!("Not clean:    {}\n", summary.skipped));
+    if let Some(progress) = progress {
+        out.push_str(&format!(
+            "Assignments:  {} outstanding\n",
+            progress.assignments
+        ));
+    }
+
+    let mut results: Vec<(&String, &i64)> = summary.results.iter().collect();
+    results.sort();
+    out.push_str("\nObjects:\n");
+    for (result, count) in results {
+        out.push_str(&format!("  {:<17}{}\n", result, count));
+    }
+
+    if let Some(progress) = progress {
+        out.push('\n');
+        out.push_str(&format_progress(progress));
+    }
+
+    out
+}
+
+// Show how a job is getting on, refreshed every interval, until it goes no
+// further.  rebalancer-adm then exits with the code of the job's outcome, as
+// for `job run --wait`.
+fn job_watch(matches: &ArgMatches) -> Result<(), String> {
+    let job_id = matches.value_of("uuid").expect("watch uuid");
+    let interval = numeric_arg(matches, "interval")?
+        .map_or(DEFAULT_WATCH_INTERVAL, |i| Duration::from_secs(i.into()));
+
+    let start = Instant::now();
+    let mut last: Option<(Instant, i64)> = None;
+
+    let summary = loop {
+        // As with `job run --wait`, the manager may be restarted while the
+        // job is being watched.
+        let mut status = match get_job_status(job_id) {
+            Ok(status) => status,
+            Err(e) => {
+                eprintln!("Could not get status of job {}: {}", job_id, e);
+                thread::sleep(interval);
+                continue;
+            }
+        };
+
+        let now = Instant::now();
+        let progress = status.progress.take();
+        let summary = run_summary(job_id, status, start.elapsed());
+        let finished = finished_objects(&summary, progress.as_ref());
+        let throughput = last.map(|(then, count)| {
+            let secs = now.duration_since(then).as_secs_f64();
+            (finished - count) as f64 / secs.max(std::f64::EPSILON)
+        });
+        last = Some((now, finished));
+
+        print!(
+            "{}{}",
+            CLEAR_SCREEN,
+            watch_frame(&summary, progress.as_ref(), throughput)
+        );
+        std::io::stdout().flush().map_err(|e| e.to_string())?;
+
+        if summary.outcome != RunOutcome::Running {
+            break summary;
+        }
+
+        thread::sleep(interval);
+    };
+
+    println!();
+    println!("Job {} is {}", job_id, summary.state);
+
+    if summary.exit_code != 0 {
+        std::process::exit(summary.exit_code);
+    }
+
+    Ok(())
+}
+
+// An optional numeric argument.
+fn numeric_arg(
+    matches: &ArgMatches,
+    name: &str,
+) -> Result<Option<u32>, String> {
+    match matches.value_of(name) {
+        None => Ok(None),
+        Some(m) => m
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|e| format!("Numeric value required for {}: {}", name, e)),
+    }
+}
+
+// Clap restricts the priority to one of the possible values.
+fn priority_arg(matches: &ArgMatches) -> Option<JobPriority> {
+    match matches.value_of("priority") {
+        None => None,
+        Some("urgent") => Some(JobPriority::Urgent),
+        Some(_) => Some(JobPriority::Normal),
+    }
+}
+
+fn verify_before_update_arg(matches: &ArgMatches) -> Option<bool> {
+    if matches.is_present("verify_before_update") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn direct_pull_arg(matches: &ArgMatches) -> Option<bool> {
+    if matches.is_present("direct_pull") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn trace_placement_arg(matches: &ArgMatches) -> Option<bool> {
+    if matches.is_present("trace_placement") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+// Clap restricts the policy to one of the possible values.
+fn checksum_policy_arg(matches: &ArgMatches) -> Option<ChecksumPolicy> {
+    match matches.value_of("checksum_policy") {
+        None => None,
+        Some("fail") => Some(ChecksumPolicy::Fail),
+        Some("copy") => Some(ChecksumPolicy::Copy),
+        Some(_) => Some(ChecksumPolicy::Skip),
+    }
+}
+
+// The arguments that narrow the scan of a job to some of the shards, say how
+// many of them it scans at once, or have it read a file instead.
+fn shard_selection_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("min_shard")
+            .long("min_shard")
+            .takes_value(true)
+            .help("Only scan shards numbered this or higher"),
+        Arg::with_name("max_shard")
+            .long("max_shard")
+            .takes_value(true)
+            .help("Only scan shards numbered this or lower"),
+        Arg::with_name("scan_shard")
+            .long("scan_shard")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only scan this shard (may be repeated)"),
+        Arg::with_name("scan_parallelism")
+            .long("scan_parallelism")
+            .takes_value(true)
+            .help("The number of shards to scan at once"),
+        Arg::with_name("source_file")
+            .long("source_file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help(
+                "Read the objects from this snapshot file on the manager \
+                 rather than scanning the shards",
+            ),
+    ]
+}
+
+// Where a job finds its objects, if it was told.
+fn source_arg(matches: &ArgMatches) -> Option<ObjectSource> {
+    matches
+        .value_of("source_file")
+        .map(|path| ObjectSource::File {
+            path: path.to_owned(),
+        })
+}
+
+// The arguments that confine a job to a daily window.
+fn schedule_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("schedule_start")
+            .long("schedule_start")
+            .takes_value(true)
+            .value_name("HH:MM")
+            .requires("schedule_end")
+            .help("Only run the job from this time of day"),
+        Arg::with_name("schedule_end")
+            .long("schedule_end")
+            .takes_value(true)
+            .value_name("HH:MM")
+            .requires("schedule_start")
+            .help("Stop running the job at this time of day"),
+        Arg::with_name("schedule_utc_offset")
+            .long("schedule_utc_offset")
+            .takes_value(true)
+            .value_name("+HH:MM")
+            .requires("schedule_start")
+            .help("The offset from UTC of the schedule (default UTC)"),
+    ]
+}
+
+// Clap ensures that the start and end are given together.
+fn schedule_arg(matches: &ArgMatches) -> Option<JobSchedule> {
+    let start = matches.value_of("schedule_start")?;
+    let end = matches.value_of("schedule_end")?;
+
+    Some(JobSchedule {
+        start: start.to_owned(),
+        end: end.to_owned(),
+        utc_offset: matches.value_of("schedule_utc_offset").map(String::from),
+    })
+}
+
+// The arguments that confine a job to some of the objects that it finds.
+fn filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("owner")
+            .long("owner")
+            .takes_value(true)
+            .value_name("UUID")
+            .help("Only work on the objects of this owner"),
+        Arg::with_name("min_size")
+            .long("min_size")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Only work on objects of at least this size"),
+        Arg::with_name("max_size")
+            .long("max_size")
+            .takes_value(true)
+            .value_name("BYTES")
+            .help("Only work on objects of at most this size"),
+        Arg::with_name("created_after")
+            .long("created_after")
+            .takes_value(true)
+            .value_name("MS")
+            .help("Only work on objects created at or after this time"),
+        Arg::with_name("created_before")
+            .long("created_before")
+            .takes_value(true)
+            .value_name("MS")
+            .help("Only work on objects created at or before this time"),
+    ]
+}
+
+// The filter of the objects that a job works on, or None if no part of one
+// was given.
+fn filter_arg(matches: &ArgMatches) -> Result<Option<ObjectFilter>, String> {
+    fn parse<T: std::str::FromStr>(
+        matches: &ArgMatches,
+        name: &str,
+    ) -> Result<Option<T>, String>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match matches.value_of(name) {
+            None => Ok(None),
+            Some(v) => v.parse::<T>().map(Some).map_err(|e| {
+                format!("Numeric value required for {}: {}", name, e)
+            }),
+        }
+    }
+
+    let filter = ObjectFilter {
+        owner: matches.value_of("owner").map(String::from),
+        min_size: parse(matches, "min_size")?,
+        max_size: parse(matches, "max_size")?,
+        created_after: parse(matches, "created_after")?,
+        created_before: parse(matches, "created_before")?,
+    };
+
+    if filter == ObjectFilter::default() {
+        Ok(None)
+    } else {
+        Ok(Some(filter))
+    }
+}
+
+fn scan_shards_arg(matches: &ArgMatches) -> Result<Vec<u32>, String> {
+    matches
+        .values_of("scan_shard")
+        .map(|shards| {
+            shards
+                .map(|s| {
+                    s.parse::<u32>().map_err(|e| {
+                        format!("Numeric value required for scan_shard: {}", e)
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| Ok(vec![]))
+}
+
+// The create-copy job described by the arguments.
+fn create_copy_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    let shark = matches.value_of("shark").expect("create-copy shark");
+
+    let require_confirmation = if matches.is_present("require_confirmation") {
+        Some(true)
+    } else {
+        None
+    };
+
+    let job_payload = JobPayload::CreateCopy(CreateCopyJobPayload {
+        shark: shark.to_owned(),
+        min_copies: numeric_arg(matches, "min_copies")?,
+        max_objects: numeric_arg(matches, "max_objects")?,
+        max_fill_percentage: numeric_arg(matches, "max_fill_percentage")?,
+        max_dest_utilization_percent: numeric_arg(
+            matches,
+            "max_dest_utilization",
+        )?,
+        priority: priority_arg(matches),
+        require_confirmation,
+        verify_before_update: verify_before_update_arg(matches),
+        direct_pull: direct_pull_arg(matches),
+        sharks_file: matches.value_of("sharks_file").map(String::from),
+        trace_placement: trace_placement_arg(matches),
+        checksum_policy: checksum_policy_arg(matches),
+        min_shard: numeric_arg(matches, "min_shard")?,
+        max_shard: numeric_arg(matches, "max_shard")?,
+        shards: scan_shards_arg(matches)?,
+        scan_parallelism: numeric_arg(matches, "scan_parallelism")?,
+        schedule: schedule_arg(matches),
+        filter: filter_arg(matches)?,
+        source: source_arg(matches),
+    });
+
+    Ok(job_payload)
+}
+
+// The remove-copy job described by the arguments.
+fn remove_copy_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    let shark = matches.value_of("shark").expect("remove-copy shark");
+    let min_copies =
+        numeric_arg(matches, "min_copies")?.expect("remove-copy min_copies");
+
+    let require_confirmation = if matches.is_present("require_confirmation") {
+        Some(true)
+    } else {
+        None
+    };
+
+    let job_payload = JobPayload::RemoveCopy(RemoveCopyJobPayload {
+        shark: shark.to_owned(),
+        min_copies,
+        max_objects: numeric_arg(matches, "max_objects")?,
+        priority: priority_arg(matches),
+        require_confirmation,
+        min_shard: numeric_arg(matches, "min_shard")?,
+        max_shard: numeric_arg(matches, "max_shard")?,
+        shards: scan_shards_arg(matches)?,
+        scan_parallelism: numeric_arg(matches, "scan_parallelism")?,
+        schedule: schedule_arg(matches),
+        filter: filter_arg(matches)?,
+        source: source_arg(matches),
+    });
+
+    Ok(job_payload)
+}
+
+// The verify job described by the arguments.
+fn verify_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    let shark = matches.value_of("shark").expect("verify shark");
+
+    let job_payload = JobPayload::Verify(VerifyJobPayload {
+        shark: shark.to_owned(),
+        max_objects: numeric_arg(matches, "max_objects")?,
+        priority: priority_arg(matches),
+    });
+
+    Ok(job_payload)
+}
+
+// The rollback job described by the arguments.
+fn rollback_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    let job_id = matches.value_of("job").expect("rollback job");
+
+    let object_ids = matches
+        .values_of("object")
+        .map(|ids| ids.map(String::from).collect())
+        .unwrap_or_default();
+
+    let require_confirmation = if matches.is_present("require_confirmation") {
+        Some(true)
+    } else {
+        None
+    };
+
+    let job_payload = JobPayload::Rollback(RollbackJobPayload {
+        job_id: job_id.to_owned(),
+        dest_shark: matches.value_of("dest_shark").map(String::from),
+        object_ids,
+        max_objects: numeric_arg(matches, "max_objects")?,
+        priority: priority_arg(matches),
+        require_confirmation,
+    });
+
+    Ok(job_payload)
+}
+
+// The evacuate job described by the arguments.
+fn evacuate_payload(matches: &ArgMatches) -> Result<JobPayload, String> {
+    // Get the storage id from the args.  Clap ensures that this argument is
+    // supplied to us before we even reach this point.
+    let shark = matches.value_of("shark").unwrap();
+
+    // Max objects is an optional argument.
+    let max_objects = numeric_arg(matches, "max_objects")?;
+
+    // Optionally override the destination fill limit for this job.
+    let max_fill_percentage = numeric_arg(matches, "max_fill_percentage")?;
+
+    // And hold its destinations to a ceiling that a retry of it keeps.
+    let max_dest_utilization_percent =
+        numeric_arg(matches, "max_dest_utilization")?;
+
+    let priority = priority_arg(matches);
+
+    let slow_source = if matches.is_present("slow_source") {
+        Some(true)
+    } else {
+        None
+    };
+
+    let require_confirmation = if matches.is_present("require_confirmation") {
+        Some(true)
+    } else {
+        None
+    };
+
+    // Form the payload of the request.
+    let job_payload = JobPayload::Evacuate(EvacuateJobPayload {
+        from_shark: shark.to_owned(),
+        max_objects,
+        max_fill_percentage,
+        max_dest_utilization_percent,
+        priority,
+        slow_source,
+        require_confirmation,
+        verify_before_update: verify_before_update_arg(matches),
+        direct_pull: direct_pull_arg(matches),
+        sharks_file: matches.value_of("sharks_file").map(String::from),
+        trace_placement: trace_placement_arg(matches),
+        checksum_policy: checksum_policy_arg(matches),
+        min_shard: numeric_arg(matches, "min_shard")?,
+        max_shard: numeric_arg(matches, "max_shard")?,
+        shards: scan_shards_arg(matches)?,
+        scan_parallelism: numeric_arg(matches, "scan_parallelism")?,
+        schedule: schedule_arg(matches),
+        filter: filter_arg(matches)?,
+        source: source_arg(matches),
+        queue_if_active: if matches.is_present("queue_if_active") {
+            Some(true)
+        } else {
+            None
+        },
+    });
+
+    Ok(job_payload)
+}
+
+// The `job' subcommand currently requires one of three different primary
+// arguments.  While there are other arguments that might accompany the
+// ones listed below, those are parsed separately depending on which of
+// the pimary arguments are supplied.
+fn process_subcmd_job(job_matches: &ArgMatches) -> Result<(), String> {
+    match job_matches.subcommand() {
+        ("get", Some(get_matches)) => job_get(get_matches),
+        ("list", Some(list_matches)) => job_list(list_matches),
+        ("retry", Some(retry_matches)) => job_retry(retry_matches),
+        ("archive", Some(archive_matches)) => job_archive(archive_matches),
+        ("confirm", Some(confirm_matches)) => job_confirm(confirm_matches),
+        ("rebalance-backlog", Some(rebalance_matches)) => {
+            job_rebalance_backlog(rebalance_matches)
+        }
+        ("export", Some(export_matches)) => job_export(export_matches),
+        ("skipped", Some(skipped_matches)) => job_skipped(skipped_matches),
+        ("slow", Some(slow_matches)) => job_slow(slow_matches),
+        ("headers", Some(headers_matches)) => job_headers(headers_matches),
+        ("placement", Some(placement_matches)) => {
+            job_placement(placement_matches)
+        }
+        ("audit", Some(audit_matches)) => job_audit(audit_matches),
+        ("create", Some(create_matches)) => job_create(create_matches),
+        ("estimate", Some(estimate_matches)) => job_estimate(estimate_matches),
+        ("run", Some(run_matches)) => job_run(run_matches),
+        ("watch", Some(watch_matches)) => job_watch(watch_matches),
+        _ => unreachable!(),
+    }
+}
+
+fn main() -> Result<(), String> {
+    let evacuate_subcommand = App::new("evacuate")
+        .about("Create an evacuate job")
+        .arg(
+            Arg::with_name("shark")
+                .short("s")
+                .long("shark")
+                .takes_value(true)
+                .required(true)
+                .help("Specifies a shark on which to run a job"),
+        )
+        .arg(
+            Arg::with_name("max_objects")
+                .short("m")
+                .long("max_objects")
+                .takes_value(true)
+                .help("Maximum number of objects allowed in the job"),
+        )
+        .arg(
+            Arg::with_name("max_fill_percentage")
+                .short("f")
+                .long("max_fill_percentage")
+                .takes_value(true)
+                .help(
+                    "Maximum utilization percentage of destination sharks \
+                     for this job",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_dest_utilization")
+                .short("u")
+                .long("max_dest_utilization")
+                .takes_value(true)
+                .help(
+                    "Utilization percentage that this job never takes \
+                     destination sharks beyond, kept by a retry",
+                ),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .possible_values(&["normal", "urgent"])
+                .help("Priority of the job if it has to wait to be run"),
+        )
+        .arg(Arg::with_name("slow_source").long("slow_source").help(
+            "Read objects with no other copy from the evacuating \
+                     shark, a few at a time",
+        ))
+        .arg(
+            Arg::with_name("require_confirmation")
+                .long("require_confirmation")
+                .help("Wait for an operator to confirm the finished job"),
+        )
+        .arg(
+            Arg::with_name("verify_before_update")
+                .long("verify_before_update")
+                .help(
+                    "Check each copy on its destination before updating \
+                     the object's metadata",
+                ),
+        )
+        .arg(Arg::with_name("direct_pull").long("direct_pull").help(
+            "Fetch objects from the agents on their sources rather \
+                     than from the sources' web servers",
+        ))
+        .arg(
+            Arg::with_name("sharks_file")
+                .long("sharks_file")
+                .takes_value(true)
+                .help(
+                    "Take destination sharks from this file on the manager \
+                     instead of storinfo",
+                ),
+        )
+        .arg(
+            Arg::with_name("trace_placement")
+                .long("trace_placement")
+                .help("Record why each object was placed where it was"),
+        )
+        .arg(
+            Arg::with_name("checksum_policy")
+                .long("checksum_policy")
+                .takes_value(true)
+                .possible_values(&["fail", "skip", "copy"])
+                .help(
+                    "What to do with objects that have a missing or \
+                     malformed checksum",
+                ),
+        )
+        .args(&shard_selection_args())
+        .args(&schedule_args())
+        .args(&filter_args())
+        .arg(
+            Arg::with_name("queue_if_active")
+                .long("queue_if_active")
+                .help(
+                "Wait for another evacuate job of the shark to finish, rather \
+             than being refused",
+            ),
+        );
+
+    let create_copy_subcommand = App::new("create-copy")
+        .about("Create a job that adds a copy of objects on a shark")
+        .arg(
+            Arg::with_name("shark")
+                .short("s")
+                .long("shark")
+                .takes_value(true)
+                .required(true)
+                .help("Specifies a shark whose objects are to be copied"),
+        )
+        .arg(
+            Arg::with_name("min_copies")
+                .short("c")
+                .long("min_copies")
+                .takes_value(true)
+                .help("Only copy objects with fewer than this many copies"),
+        )
+        .arg(
+            Arg::with_name("max_objects")
+                .short("m")
+                .long("max_objects")
+                .takes_value(true)
+                .help("Maximum number of objects allowed in the job"),
+        )
+        .arg(
+            Arg::with_name("max_fill_percentage")
+                .short("f")
+                .long("max_fill_percentage")
+                .takes_value(true)
+                .help(
+                    "Maximum utilization percentage of destination sharks \
+                     for this job",
+                ),
+        )
+        .arg(
+            Arg::with_name("max_dest_utilization")
+                .short("u")
+                .long("max_dest_utilization")
+                .takes_value(true)
+                .help(
+                    "Utilization percentage that this job never takes \
+                     destination sharks beyond, kept by a retry",
+                ),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .possible_values(&["normal", "urgent"])
+                .help("Priority of the job if it has to wait to be run"),
+        )
+        .arg(
+            Arg::with_name("require_confirmation")
+                .long("require_confirmation")
+                .help("Wait for an operator to confirm the finished job"),
+        )
+        .arg(
+            Arg::with_name("verify_before_update")
+                .long("verify_before_update")
+                .help(
+                    "Check each copy on its destination before updating \
+                     the object's metadata",
+                ),
+        )
+        .arg(Arg::with_name("direct_pull").long("direct_pull").help(
+            "Fetch objects from the agents on their sources rather \
+                     than from the sources' web servers",
+        ))
+        .arg(
+            Arg::with_name("sharks_file")
+                .long("sharks_file")
+                .takes_value(true)
+                .help(
+                    "Take destination sharks from this file on the manager \
+                     instead of storinfo",
+                ),
+        )
+        .arg(
+            Arg::with_name("trace_placement")
+                .long("trace_placement")
+                .help("Record why each object was placed where it was"),
+        )
+        .arg(
+            Arg::with_name("checksum_policy")
+                .long("checksum_policy")
+                .takes_value(true)
+                .possible_values(&["fail", "skip", "copy"])
+                .help(
+                    "What to do with objects that have a missing or \
+                     malformed checksum",
+                ),
+        )
+        .args(&shard_selection_args())
+        .args(&schedule_args())
+        .args(&filter_args());
+
+    let remove_copy_subcommand = App::new("remove-copy")
+        .about("Create a job that removes extra copies")
+        .arg(
+            Arg::with_name("shark")
+                .short("s")
+                .long("shark")
+                .takes_value(true)
+                .required(true)
+                .help("Specifies a shark whose copies of objects are removed"),
+        )
+        .arg(
+            Arg::with_name("min_copies")
+                .short("c")
+                .long("min_copies")
+                .takes_value(true)
+                .required(true)
+                .help("Only remove copies with this many copies elsewhere"),
+        )
+        .arg(
+            Arg::with_name("max_objects")
+                .short("m")
+                .long("max_objects")
+                .takes_value(true)
+                .help("Maximum number of objects allowed in the job"),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .possible_values(&["normal", "urgent"])
+                .help("Priority of the job if it has to wait to be run"),
+        )
+        .arg(
+            Arg::with_name("require_confirmation")
+                .long("require_confirmation")
+                .help("Wait for an operator to confirm the finished job"),
+        )
+        .args(&shard_selection_args())
+        .args(&schedule_args())
+        .args(&filter_args());
+
+    let verify_subcommand = App::new("verify")
+        .about("Create a job that checks the objects on a shark")
+        .arg(
+            Arg::with_name("shark")
+                .short("s")
+                .long("shark")
+                .takes_value(true)
+                .required(true)
+                .help("Specifies a shark whose objects are to be checked"),
+        )
+        .arg(
+            Arg::with_name("max_objects")
+                .short("m")
+                .long("max_objects")
+                .takes_value(true)
+                .help("Maximum number of objects allowed in the job"),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .possible_values(&["normal", "urgent"])
+                .help("Priority of the job if it has to wait to be run"),
+        );
+
+    let rollback_subcommand = App::new("rollback")
+        .about("Create a job that rolls back the metadata changes of a job")
+        .arg(
+            Arg::with_name("job")
+                .short("j")
+                .long("job")
+                .takes_value(true)
+                .required(true)
+                .help("Specifies the uuid of the job to roll back"),
+        )
+        .arg(
+            Arg::with_name("dest_shark")
+                .short("d")
+                .long("dest_shark")
+                .takes_value(true)
+                .help("Only roll back the objects the job put on this shark"),
+        )
+        .arg(
+            Arg::with_name("object")
+                .short("o")
+                .long("object")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only roll back this object (may be repeated)"),
+        )
+        .arg(
+            Arg::with_name("max_objects")
+                .short("m")
+                .long("max_objects")
+                .takes_value(true)
+                .help("Maximum number of objects allowed in the job"),
+        )
+        .arg(
+            Arg::with_name("priority")
+                .short("p")
+                .long("priority")
+                .takes_value(true)
+                .possible_values(&["normal", "urgent"])
+                .help("Priority of the job if it has to wait to be run"),
+        )
+        .arg(
+            Arg::with_name("require_confirmation")
+                .long("require_confirmation")
+                .help("Wait for an operator to confirm the finished job"),
+        );
+
+    // Each of the plan subcommands other than create and list takes the uuid
+    // of a plan.
+    let plan_uuid_arg = Arg::with_name("uuid")
+        .takes_value(true)
+        .required(true)
+        .help("Uuid of a plan");
+
+    // Only `job create` can be asked not to create the job.
+    let dry_run_arg = Arg::with_name("dry_run").long("dry_run").help(
+        "Check the job and print what would be sent, without creating it",
+    );
+
+    let matches = App::new("rebalancer-adm")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .version(VERSION)
+        .about("Rebalancer client utility")
+        .subcommand(
+            App::new("job")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Job operations")
+                // Get subcommand
+                .subcommand(
+                    App::new("get")
+                        .about("Get information on a specific job")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        ),
+                )
+                // Retry subcommand
+                .subcommand(
+                    SubCommand::with_name("retry")
+                        .about("retry a previously run and completed job")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .help("uuid of previous job")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                )
+                // Archive subcommand
+                .subcommand(
+                    App::new("archive")
+                        .about("Archive a finished job and remove it")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        ),
+                )
+                // Confirm subcommand
+                .subcommand(
+                    App::new("confirm")
+                        .about("Sign off a job awaiting confirmation")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("by")
+                                .short("b")
+                                .long("by")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Who is confirming the job"),
+                        )
+                        .arg(
+                            Arg::with_name("comment")
+                                .short("c")
+                                .long("comment")
+                                .takes_value(true)
+                                .help("e.g. a change request number"),
+                        ),
+                )
+                // Rebalance backlog subcommand
+                .subcommand(
+                    App::new("rebalance-backlog")
+                        .about("Move a destination's unstarted tasks elsewhere")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a running job"),
+                        )
+                        .arg(
+                            Arg::with_name("drain_dest")
+                                .short("d")
+                                .long("drain-dest")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Storage id of the destination"),
+                        ),
+                )
+                // Export subcommand
+                .subcommand(
+                    App::new("export")
+                        .about("Export the outcome of every object in a job")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("format")
+                                .short("f")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(&["parquet", "csv", "ndjson"])
+                                .default_value("parquet")
+                                .help("Format of the exported file"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .takes_value(true)
+                                .required_unless("manta_path")
+                                .conflicts_with("manta_path")
+                                .help("File to write the export to"),
+                        )
+                        .arg(
+                            Arg::with_name("manta_path")
+                                .short("m")
+                                .long("manta-path")
+                                .takes_value(true)
+                                .help(
+                                    "Manta path to upload the export to, \
+                                     with mput",
+                                ),
+                        ),
+                )
+                // Skipped subcommand
+                .subcommand(
+                    App::new("skipped")
+                        .about("List the objects that a job skipped")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("summary")
+                                .short("s")
+                                .long("summary")
+                                .conflicts_with_all(&[
+                                    "reason", "limit", "offset",
+                                ])
+                                .help("Count the skipped objects by reason"),
+                        )
+                        .arg(
+                            Arg::with_name("reason")
+                                .short("r")
+                                .long("reason")
+                                .takes_value(true)
+                                .help("Only list objects skipped for reason"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of objects to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of objects to skip over"),
+                        ),
+                )
+                // Slow subcommand
+                .subcommand(
+                    App::new("slow")
+                        .about("List the objects that were slow to move")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of objects to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of objects to skip over"),
+                        ),
+                )
+                // Headers subcommand
+                .subcommand(
+                    App::new("headers")
+                        .about(
+                            "List the copies served with different custom \
+                             headers",
+                        )
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of objects to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of objects to skip over"),
+                        ),
+                )
+                // Placement subcommand
+                .subcommand(
+                    App::new("placement")
+                        .about("List why a job placed its objects where it did")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("object")
+                                .long("object")
+                                .takes_value(true)
+                                .help(
+                                    "Only list the decisions about this object",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of decisions to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of decisions to skip over"),
+                        ),
+                )
+                // Audit subcommand
+                .subcommand(
+                    App::new("audit")
+                        .about(
+                            "List the changes that a job made to object \
+                             metadata",
+                        )
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of changes to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of changes to skip over"),
+                        ),
+                )
+                // List subcommand
+                .subcommand(
+                    App::new("list")
+                        .about("List all known rebalancer jobs")
+                        .arg(
+                            Arg::with_name("state")
+                                .short("s")
+                                .long("state")
+                                .takes_value(true)
+                                .help("Only list jobs in this state"),
+                        )
+                        .arg(
+                            Arg::with_name("action")
+                                .short("a")
+                                .long("action")
+                                .takes_value(true)
+                                .help("Only list jobs of this action"),
+                        )
+                        .arg(
+                            Arg::with_name("created_after")
+                                .long("created_after")
+                                .takes_value(true)
+                                .help(
+                                    "Only list jobs created at or after this \
+                                     time (ms since the epoch)",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("created_before")
+                                .long("created_before")
+                                .takes_value(true)
+                                .help(
+                                    "Only list jobs created at or before \
+                                     this time (ms since the epoch)",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("l")
+                                .long("limit")
+                                .takes_value(true)
+                                .help("Maximum number of jobs to list"),
+                        )
+                        .arg(
+                            Arg::with_name("offset")
+                                .short("o")
+                                .long("offset")
+                                .takes_value(true)
+                                .help("Number of jobs to skip over"),
+                        )
+                        .arg(
+                            Arg::with_name("order")
+                                .long("order")
+                                .takes_value(true)
+                                .possible_values(&["asc", "desc"])
+                                .help("Order of creation time to list jobs in"),
+                        ),
+                )
+                // Estimate subcommand
+                .subcommand(
+                    App::new("estimate")
+                        .about(
+                            "Estimate what a job would move, and where to, \
+                             without creating it",
+                        )
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(
+                            evacuate_subcommand
+                                .clone()
+                                .about("Estimate an evacuate job")
+                                .arg(
+                                    Arg::with_name("throughput")
+                                        .long("throughput")
+                                        .takes_value(true)
+                                        .help(
+                                            "MB per second that the job's \
+                                             data would be moved at",
+                                        ),
+                                ),
+                        ),
+                )
+                // Create subcommand
+                .subcommand(
+                    App::new("create")
+                        .about("Create a rebalancer job")
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        // Create evacuate job
+                        .subcommand(
+                            evacuate_subcommand
+                                .clone()
+                                .arg(dry_run_arg.clone()),
+                        )
+                        // Create create-copy job
+                        .subcommand(
+                            create_copy_subcommand
+                                .clone()
+                                .arg(dry_run_arg.clone()),
+                        )
+                        // Create remove-copy job
+                        .subcommand(
+                            remove_copy_subcommand
+                                .clone()
+                                .arg(dry_run_arg.clone()),
+                        )
+                        // Create verify job
+                        .subcommand(
+                            verify_subcommand.clone().arg(dry_run_arg.clone()),
+                        )
+                        // Create rollback job
+                        .subcommand(
+                            rollback_subcommand.clone().arg(dry_run_arg),
+                        ),
+                )
+                // Run subcommand
+                .subcommand(
+                    App::new("run")
+                        .about(
+                            "Create a rebalancer job, and summarize how it \
+                             turns out",
+                        )
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .arg(
+                            Arg::with_name("wait")
+                                .long("wait")
+                                .help("Wait for the job to finish"),
+                        )
+                        .arg(
+                            Arg::with_name("timeout")
+                                .long("timeout")
+                                .takes_value(true)
+                                .requires("wait")
+                                .help("Seconds to wait for the job to finish"),
+                        )
+                        .arg(
+                            Arg::with_name("poll_interval")
+                                .long("poll_interval")
+                                .takes_value(true)
+                                .help(
+                                    "Seconds between checks of the job's \
+                                     status (default 30)",
+                                ),
+                        )
+                        .subcommand(evacuate_subcommand)
+                        .subcommand(create_copy_subcommand)
+                        .subcommand(remove_copy_subcommand)
+                        .subcommand(verify_subcommand)
+                        .subcommand(rollback_subcommand),
+                )
+                // Watch subcommand
+                .subcommand(
+                    App::new("watch")
+                        .about("Follow a job until it goes no further")
+                        .arg(
+                            Arg::with_name("uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of a job"),
+                        )
+                        .arg(
+                            Arg::with_name("interval")
+                                .short("i")
+                                .long("interval")
+                                .takes_value(true)
+                                .help(
+                                    "Seconds between refreshes of the \
+                                     display (default 5)",
+                                ),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("assignment")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Assignment operations")
+                // Cancel subcommand
+                .subcommand(
+                    App::new("cancel")
+                        .about("Cancel an assignment of a running job")
+                        .arg(
+                            Arg::with_name("job_uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of the job"),
+                        )
+                        .arg(
+                            Arg::with_name("assignment_uuid")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Uuid of the assignment"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("plan")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .about("Evacuation plan operations")
+                // Create subcommand
+                .subcommand(
+                    App::new("create")
+                        .about("Plan the evacuation of sharks or a datacenter")
+                        .arg(
+                            Arg::with_name("shark")
+                                .short("s")
+                                .long("shark")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required_unless("datacenter")
+                                .conflicts_with("datacenter")
+                                .help("A shark to evacuate (may be repeated)"),
+                        )
+                        .arg(
+                            Arg::with_name("datacenter")
+                                .short("d")
+                                .long("datacenter")
+                                .takes_value(true)
+                                .help("Evacuate every shark in a datacenter"),
+                        )
+                        .arg(
+                            Arg::with_name("max_concurrent")
+                                .short("c")
+                                .long("max_concurrent")
+                                .takes_value(true)
+                                .help(
+                                    "Number of sharks evacuated at once \
+                                     (default 1)",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("max_fill_percentage")
+                                .short("f")
+                                .long("max_fill_percentage")
+                                .takes_value(true)
+                                .help(
+                                    "Maximum utilization percentage of \
+                                     destination sharks for this plan",
+                                ),
+                        ),
+                )
+                .subcommand(App::new("list").about("List all evacuation plans"))
+                .subcommand(
+                    App::new("get")
+                        .about("Get the status of a plan and its jobs")
+                        .arg(plan_uuid_arg.clone()),
+                )
+                .subcommand(
+                    App::new("execute")
+                        .about("Start a plan that has been reviewed")
+                        .arg(plan_uuid_arg.clone()),
+                )
+                .subcommand(
+                    App::new("pause")
+                        .about("Start no more of a plan's jobs")
+                        .arg(plan_uuid_arg.clone()),
+                )
+                .subcommand(
+                    App::new("resume")
+                        .about("Carry on with a paused plan")
+                        .arg(plan_uuid_arg.clone()),
+                )
+                .subcommand(
+                    App::new("abort")
+                        .about("Drop the steps of a plan not yet started")
+                        .arg(plan_uuid_arg),
+                ),
+        )
+        .subcommand(
+            App::new("alerts")
+                .about("Print recommended Prometheus alerting rules"),
+        )
+        .subcommand(
+            App::new("destinations")
+                .about("Show data outstanding to each destination shark"),
+        )
+        .subcommand(
+            App::new("agents")
+                .about("Show the health and version of every agent"),
+        )
+        .subcommand(
+            App::new("managers")
+                .about("Show the managers sharing the jobs database"),
+        )
+        .get_matches();
+
+    // Exporting a job reads the job's database directly, without going
+    // through the manager.
+    let exporting = matches
+        .subcommand_matches("job")
+        .and_then(|m| m.subcommand_name())
+        == Some("export");
+
+    if !exporting {
+        check_manager_version()?;
+    }
+
+    match matches.subcommand() {
+        ("job", Some(job_matches)) => process_subcmd_job(job_matches),
+        ("assignment", Some(assignment_matches)) => {
+            process_subcmd_assignment(assignment_matches)
+        }
+        ("plan", Some(plan_matches)) => process_subcmd_plan(plan_matches),
+        ("alerts", Some(_)) => alerts_get(),
+        ("destinations", Some(_)) => get_common(DESTINATIONS_URL),
+        ("agents", Some(_)) => get_common(AGENTS_URL),
+        ("managers", Some(_)) => get_common(MANAGERS_URL),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod rebalancer_adm_tests {
+    use super::*;
+    use assert_cli;
+    use indoc::indoc;
+
+    #[test]
+    fn no_params() {
+        let usage = indoc!(
+            "
+            Rebalancer client utility
+
+            USAGE:
+                rebalancer-adm <SUBCOMMAND>
+
+            FLAGS:
+                -h, --help       Prints help information
+                -V, --version    Prints version information
+
+            SUBCOMMANDS:
+                agents          Show the health and version of every agent
+                alerts          Print recommended Prometheus alerting rules
+                assignment      Assignment operations
+                destinations    Show data outstanding to each destination shark
+                help            Prints this message or the help of the given \
+                subcommand(s)
+                job             Job operations
+                managers        Show the managers sharing the jobs database
+                plan            Evacuation plan operations
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .fails()
+            .stderr()
+            .contains(VERSION)
+            .and()
+            .stderr()
+            .contains(usage)
+            .unwrap();
+    }
+
+    #[test]
+    fn assignment_cancel_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <job_uuid>
+                <assignment_uuid>
+
+            USAGE:
+                rebalancer-adm assignment cancel <job_uuid> <assignment_uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["assignment", "cancel"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn plan_get_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+
+            USAGE:
+                rebalancer-adm plan get <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["plan", "get"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_list_extra_params() {
+        let err_msg = indoc!(
+            "
+            error: Found argument 'extra' which wasn't expected, or isn't \
+            valid in this context
+
+            USAGE:
+                rebalancer-adm job list [OPTIONS]
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "list", "extra"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_get_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+
+            USAGE:
+                rebalancer-adm job get <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
             .with_args(&["job", "get"])
             .fails()
             .and()
@@ -335,6 +2537,134 @@ mod rebalancer_adm_tests {
             .unwrap();
     }
 
+    #[test]
+    fn job_confirm_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+                --by <by>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "confirm"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_rebalance_backlog_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+                --drain-dest <drain_dest>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "rebalance-backlog"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_skipped_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "skipped"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_slow_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "slow"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_headers_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "headers"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_placement_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "placement"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_audit_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "audit"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
     #[test]
     fn job_create_no_params() {
         let err_msg = indoc!(
@@ -349,9 +2679,15 @@ mod rebalancer_adm_tests {
                 -V, --version    Prints version information
 
             SUBCOMMANDS:
-                evacuate    Create an evacuate job
-                help        Prints this message or the help of the given \
+                create-copy    Create a job that adds a copy of objects on a \
+                shark
+                evacuate       Create an evacuate job
+                help           Prints this message or the help of the given \
                 subcommand(s)
+                remove-copy    Create a job that removes extra copies
+                rollback       Create a job that rolls back the metadata \
+                changes of a job
+                verify         Create a job that checks the objects on a shark
             "
         );
 
@@ -384,4 +2720,336 @@ mod rebalancer_adm_tests {
             .contains(err_msg)
             .unwrap();
     }
+
+    #[test]
+    fn job_create_copy_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                --shark <shark>
+
+            USAGE:
+                rebalancer-adm job create create-copy [OPTIONS] --shark <shark>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "create", "create-copy"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_create_remove_copy_no_min_copies() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                --min_copies <min_copies>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "create", "remove-copy", "--shark", "1.stor"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_create_verify_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                --shark <shark>
+
+            USAGE:
+                rebalancer-adm job create verify [OPTIONS] --shark <shark>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "create", "verify"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_create_rollback_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                --job <job>
+
+            USAGE:
+                rebalancer-adm job create rollback [OPTIONS] --job <job>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "create", "rollback"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
+
+    #[test]
+    fn job_create_dry_run() {
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_env(
+                assert_cli::Environment::inherit()
+                    .insert(SKIP_VERSION_CHECK_ENV, "1"),
+            )
+            .with_args(&[
+                "job",
+                "create",
+                "evacuate",
+                "--shark",
+                "1.stor",
+                "--max_objects",
+                "5",
+                "--dry_run",
+            ])
+            .succeeds()
+            .and()
+            .stdout()
+            .contains("\"from_shark\": \"1.stor\"")
+            .unwrap();
+
+        // A job that the manager would refuse is refused before it is sent.
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_env(
+                assert_cli::Environment::inherit()
+                    .insert(SKIP_VERSION_CHECK_ENV, "1"),
+            )
+            .with_args(&[
+                "job",
+                "create",
+                "create-copy",
+                "--shark",
+                "1.stor",
+                "--min_copies",
+                "1",
+                "--dry_run",
+            ])
+            .fails()
+            .and()
+            .stderr()
+            .contains("Invalid job: min_copies must be at least 2")
+            .unwrap();
+    }
+
+    #[test]
+    fn job_progress_format() {
+        use rebalancer_client::status::{
+            IngestionProgress, JobPhases, PhaseState, ShardProgress,
+        };
+
+        let progress = JobProgress {
+            phases: JobPhases {
+                ingestion: IngestionProgress {
+                    state: PhaseState::Done,
+                    shards_scanned: 2,
+                    shards: 2,
+                    objects: 30,
+                },
+                copying: PhaseProgress {
+                    state: PhaseState::InProgress,
+                    in_progress: 5,
+                    done: 25,
+                },
+                metadata_update: PhaseProgress {
+                    state: PhaseState::InProgress,
+                    in_progress: 1,
+                    done: 23,
+                },
+            },
+            shards: vec![ShardProgress {
+                shard: 1,
+                scanned: true,
+                objects: 30,
+                copying: 5,
+                metadata_update: 1,
+                complete: 23,
+                skipped: 0,
+                error: 1,
+            }],
+            assignments: 3,
+        };
+
+        let out = format_progress(&progress);
+        assert!(out.contains(
+            "ingestion        done         2 of 2 shards scanned, 30 objects"
+        ));
+        assert!(out
+            .contains("copying          in_progress  5 in progress, 25 done"));
+        assert!(out
+            .contains("metadata update  in_progress  1 in progress, 23 done"));
+        assert!(out.contains(
+            "        1      yes         30          5          1         23"
+        ));
+    }
+
+    #[test]
+    fn job_run_summary() {
+        let status = |v: Value| -> JobStatus {
+            serde_json::from_value(v).expect("job status")
+        };
+        let shark = serde_json::json!({
+            "datacenter": "dc1",
+            "manta_storage_id": "1.stor.domain",
+        });
+        let elapsed = Duration::from_secs(90);
+
+        let evacuate = |state: &str, skipped: i64| {
+            status(serde_json::json!({
+                "config": { "action": "Evacuate", "from_shark": shark },
+                "results": {
+                    "Complete": 100,
+                    "Skipped": skipped,
+                    "Error": 0,
+                    "Total": 100 + skipped,
+                },
+                "state": state,
+            }))
+        };
+
+        let summary = run_summary("job", evacuate("Complete", 0), elapsed);
+        assert_eq!(summary.outcome, RunOutcome::Complete);
+        assert_eq!(summary.exit_code, 0);
+        assert_eq!(summary.action, "evacuate");
+        assert_eq!(summary.elapsed_secs, 90);
+
+        let summary = run_summary("job", evacuate("Complete", 3), elapsed);
+        assert_eq!(summary.outcome, RunOutcome::CompleteWithSkips);
+        assert_eq!(summary.skipped, 3);
+        assert_eq!(summary.exit_code, 2);
+
+        let summary = run_summary("job", evacuate("Stopped", 0), elapsed);
+        assert_eq!(summary.exit_code, RunOutcome::Failed.exit_code());
+
+        let summary = run_summary("job", evacuate("Paused", 0), elapsed);
+        assert_eq!(summary.exit_code, RunOutcome::NeedsAttention.exit_code());
+
+        let summary = run_summary("job", evacuate("Running", 0), elapsed);
+        assert_eq!(summary.outcome, RunOutcome::Running);
+
+        // Missing objects are what a verify job looks for, but they still
+        // make it more than a clean run.
+        let verify = status(serde_json::json!({
+            "config": { "action": "Verify", "shark": shark },
+            "results": {
+                "Present": 10,
+                "Missing": 1,
+                "Size Mismatch": 2,
+                "Unverifiable": 0,
+                "Total": 13,
+            },
+            "state": "Complete",
+        }));
+        let summary = run_summary("job", verify, elapsed);
+        assert_eq!(summary.outcome, RunOutcome::CompleteWithSkips);
+        assert_eq!(summary.skipped, 3);
+
+        let out = serde_json::to_value(&summary).expect("summary json");
+        assert_eq!(out["outcome"], "complete_with_skips");
+        assert_eq!(out["job_id"], "job");
+    }
+
+    #[test]
+    fn job_watch_frame() {
+        let mut status: JobStatus = serde_json::from_value(serde_json::json!({
+            "config": {
+                "action": "Evacuate",
+                "from_shark": {
+                    "datacenter": "dc1",
+                    "manta_storage_id": "1.stor.domain",
+                },
+            },
+            "results": {
+                "Assigned": 5,
+                "Complete": 20,
+                "Error": 1,
+                "Skipped": 2,
+                "Total": 28,
+            },
+            "state": "Running",
+            "progress": {
+                "phases": {
+                    "ingestion": {
+                        "state": "done",
+                        "shards_scanned": 1,
+                        "shards": 1,
+                        "objects": 28,
+                    },
+                    "copying": {
+                        "state": "in_progress",
+                        "in_progress": 5,
+                        "done": 23,
+                    },
+                    "metadata_update": {
+                        "state": "done",
+                        "in_progress": 0,
+                        "done": 20,
+                    },
+                },
+                "shards": [{
+                    "shard": 1,
+                    "scanned": true,
+                    "objects": 28,
+                    "copying": 5,
+                    "metadata_update": 0,
+                    "complete": 20,
+                    "skipped": 2,
+                    "error": 1,
+                }],
+                "assignments": 2,
+            },
+        }))
+        .expect("job status");
+
+        let progress = status.progress.take();
+        let summary = run_summary("job", status, Duration::from_secs(30));
+        assert_eq!(finished_objects(&summary, progress.as_ref()), 23);
+
+        let out = watch_frame(&summary, progress.as_ref(), Some(2.5));
+        assert!(out.starts_with("Job job (evacuate): running, watched for"));
+        assert!(out.contains("Throughput:   2.5 objects/s"));
+        assert!(out.contains("Not clean:    3"));
+        assert!(out.contains("Assignments:  2 outstanding"));
+        assert!(out.contains("  Assigned         5\n  Complete         20"));
+        assert!(out.contains("copying          in_progress  5 in progress"));
+
+        // The first refresh has nothing to measure throughput against.
+        let out = watch_frame(&summary, None, None);
+        assert!(out.contains("Throughput:   -"));
+        assert!(!out.contains("Assignments:"));
+    }
+
+    #[test]
+    fn job_watch_no_params() {
+        let err_msg = indoc!(
+            "
+            error: The following required arguments were not provided:
+                <uuid>
+            "
+        );
+
+        assert_cli::Assert::cargo_binary("rebalancer-adm")
+            .with_args(&["job", "watch"])
+            .fails()
+            .and()
+            .stderr()
+            .contains(err_msg)
+            .unwrap();
+    }
 }
@@ -0,0 +1,105 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Graceful shutdown of the manager.
+//
+// When SMF stops (or restarts) the manager it sends SIGTERM.  Rather than
+// exit with jobs part way through, the manager:
+//
+//  * Stops starting queued jobs, and moves them to the Interrupted state.
+//  * Asks every running evacuate job to stop scanning for objects and to stop
+//    creating assignments.  Assignments that have not yet been posted are
+//    discarded; their objects have not been recorded anywhere, so they will
+//    simply be found again.
+//  * Waits for the assignments that have already been posted to complete and
+//    for their metadata to be updated, so that no object is left assigned
+//    without anything tracking it.
+//  * Records how far each job's scan got, moves the job to the Interrupted
+//    state, and exits.
+//
+// On startup, a new evacuate job is queued for each interrupted job.  Objects
+// that were moved before the shutdown are no longer on the shark being
+// evacuated, so the new job only finds what is left.
+
+use crate::jobs::queue::JobQueue;
+
+use rebalancer::readiness;
+use signal_hook::{self, iterator::Signals};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+// How often to check whether the running jobs have finished draining.
+static DRAIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Returns true once the manager has been asked to shut down.
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+/// Ask the manager to shut down.  Running jobs notice this the next time
+/// they check, and no more jobs are started.
+pub fn request() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+fn draining(running: usize) -> String {
+    format!("Draining {} running job(s)", running)
+}
+
+/// Start a thread that handles SIGTERM by shutting down gracefully.  Once
+/// every running job has drained, the process exits.
+pub fn start_signal_handler(queue: Arc<JobQueue>) -> thread::JoinHandle<()> {
+    let signals =
+        Signals::new(&[signal_hook::SIGTERM]).expect("register SIGTERM");
+
+    thread::Builder::new()
+        .name(String::from("shutdown signal handler"))
+        .spawn(move || {
+            if signals.forever().next().is_none() {
+                return;
+            }
+
+            info!("SIGTERM received, shutting down");
+            request();
+
+            let queued = queue.interrupt_queued();
+            if queued > 0 {
+                info!("Interrupted {} queued job(s)", queued);
+            }
+
+            readiness::stopping(&draining(queue.running()));
+
+            let mut last_running = queue.running();
+            loop {
+                let running = queue.running();
+                if running == 0 {
+                    break;
+                }
+
+                // Only report progress when there is some to report.
+                if running != last_running {
+                    readiness::status(&draining(running));
+                    last_running = running;
+                }
+
+                debug!("Waiting for {} running job(s) to drain", running);
+                thread::sleep(DRAIN_CHECK_INTERVAL);
+            }
+
+            info!("All jobs have drained, exiting");
+            readiness::stopped();
+            std::process::exit(0);
+        })
+        .expect("start shutdown signal handler")
+}
@@ -8,19 +8,105 @@
  * Copyright 2020 Joyent, Inc.
  */
 
+// The list of sharks, from the storinfo service.
+//
+// A single poller is shared by every job in the manager.  It is started by
+// the first job to run, and from then on pages through storinfo's list of
+// storage nodes every `storinfo.poll_interval_secs`, spacing its requests at
+// least `storinfo.min_request_interval_ms` apart.  If a poll fails part way
+// through, the pages that it did get are kept and the next poll carries on
+// from where it failed, rather than starting over (unless the partial list
+// has grown too old to be combined with newer pages).
+//
+// Every complete list is kept as a snapshot, along with when it was completed
+// and a generation number, so that each job can tell whether there is
+// anything new since it last looked.  The latest snapshot is also returned
+// by GET /storinfo.
+//
+// With `storinfo.watch_changes` set, the poller also compares each snapshot
+// with the one before it and tells every running job about each shark that
+// has appeared in, or disappeared from, the list, so that the job's set of
+// destinations is updated as soon as it next picks them.
+//
+// The poll interval can be changed while the poller is running (see
+// set_poll_interval), and takes effect after the poll in progress.  The rest
+// of the configuration is fixed when the poller is started.
+//
+// Where there is no working storinfo service (e.g. in a lab), a job can be
+// given its destinations in a file instead (see SharksFile).
+
+use crate::config::ConfigStorinfo;
+use rebalancer::util::now_ms;
+
+use lazy_static::lazy_static;
 use quickcheck::{Arbitrary, Gen};
 use quickcheck_helpers::random::string as random_string;
 use rebalancer::error::Error;
 use reqwest::{self, Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
-};
-use std::thread::JoinHandle;
-use std::{thread, time};
-
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // When the poller last received a complete list of sharks from the
+    // storinfo service.
+    static ref LAST_UPDATE: Mutex<Option<Instant>> = Mutex::new(None);
+
+    // The poller shared by every job, started by the first of them.
+    static ref POLLER: Mutex<Option<Arc<StorinfoPoller>>> = Mutex::new(None);
+}
+
+// A partial list of sharks older than this is thrown away rather than
+// resumed: the records in it would be too stale next to those in the pages
+// that complete it.
+static MAX_RESUME_AGE: Duration = Duration::from_secs(120);
+
+/// How long it has been since the list of sharks was last received from the
+/// storinfo service, or None if it never has been.
+pub fn last_update_age() -> Option<Duration> {
+    LAST_UPDATE
+        .lock()
+        .expect("storinfo last update lock")
+        .map(|t| t.elapsed())
+}
+
+/// The poller shared by every job, starting it if it is not already running.
+pub fn shared(domain: &str, config: &ConfigStorinfo) -> Arc<StorinfoPoller> {
+    let mut poller = POLLER.lock().expect("storinfo poller lock");
+
+    if let Some(p) = poller.as_ref() {
+        return Arc::clone(p);
+    }
+
+    let p = Arc::new(StorinfoPoller::new(domain, config));
+    start_poller_thread(Arc::clone(&p));
+    *poller = Some(Arc::clone(&p));
+    p
+}
+
+/// Change the poll interval of the shared poller, if it has been started.
+pub fn set_poll_interval(secs: u64) {
+    if let Some(p) = POLLER.lock().expect("storinfo poller lock").as_ref() {
+        p.poll_interval_secs.store(secs, Ordering::Relaxed);
+    }
+}
+
+/// The latest snapshot taken by the shared poller, if it has been started
+/// and has received a complete list of sharks.
+pub fn shared_snapshot() -> Option<Arc<StorinfoSnapshot>> {
+    POLLER
+        .lock()
+        .expect("storinfo poller lock")
+        .as_ref()
+        .and_then(|p| p.snapshot())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
 pub struct StorageNode {
     #[serde(alias = "availableMB")]
     pub available_mb: u64,
@@ -52,11 +138,300 @@ impl Arbitrary for StorageNode {
     }
 }
 
-pub struct Storinfo {
-    sharks: Arc<Mutex<Option<Vec<StorageNode>>>>,
-    handle: Mutex<Option<JoinHandle<()>>>,
-    running: Arc<AtomicBool>,
+/// A complete list of sharks received from storinfo.
+#[derive(Debug, Serialize)]
+pub struct StorinfoSnapshot {
+    /// Sorted by available_mb, least first.
+    pub sharks: Vec<StorageNode>,
+
+    /// Milliseconds since the epoch at which the list was completed.
+    pub timestamp: u64,
+
+    /// Incremented with each complete list, starting from 1.
+    pub generation: u64,
+}
+
+/// A difference between two consecutive snapshots.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharkChange {
+    Added(StorageNode),
+    Removed(String),
+}
+
+// How far a poll that failed part way through got.
+#[derive(Default)]
+struct FetchProgress {
+    sharks: Vec<StorageNode>,
+    after_id: String,
+    started: Option<Instant>,
+}
+
+pub struct StorinfoPoller {
     host: String,
+    config: ConfigStorinfo,
+    poll_interval_secs: AtomicU64,
+    client: Client,
+    snapshot: RwLock<Option<Arc<StorinfoSnapshot>>>,
+    progress: Mutex<FetchProgress>,
+    last_request: Mutex<Option<Instant>>,
+    watchers: Mutex<Vec<crossbeam_channel::Sender<SharkChange>>>,
+}
+
+fn start_poller_thread(poller: Arc<StorinfoPoller>) {
+    thread::Builder::new()
+        .name(String::from("storinfo poller"))
+        .spawn(move || loop {
+            let interval = Duration::from_secs(
+                poller.poll_interval_secs.load(Ordering::Relaxed),
+            );
+            thread::sleep(interval);
+            poller.poll();
+            debug!("Sharks polled, sleeping for {:?}", interval);
+        })
+        .expect("start storinfo poller thread");
+}
+
+impl StorinfoPoller {
+    fn new(domain: &str, config: &ConfigStorinfo) -> StorinfoPoller {
+        StorinfoPoller {
+            host: format!("storinfo.{}", domain),
+            config: *config,
+            poll_interval_secs: AtomicU64::new(config.poll_interval_secs),
+            client: Client::new(),
+            snapshot: RwLock::new(None),
+            progress: Mutex::new(FetchProgress::default()),
+            last_request: Mutex::new(None),
+            watchers: Mutex::new(vec![]),
+        }
+    }
+
+    /// The latest complete list of sharks, or None if there has not been
+    /// one yet.
+    pub fn snapshot(&self) -> Option<Arc<StorinfoSnapshot>> {
+        self.snapshot
+            .read()
+            .expect("storinfo snapshot lock")
+            .as_ref()
+            .map(Arc::clone)
+    }
+
+    /// A channel on which the changes between snapshots are sent, if
+    /// `storinfo.watch_changes` is set.  The channel is forgotten once its
+    /// receiver is dropped.
+    pub fn watch(&self) -> Option<crossbeam_channel::Receiver<SharkChange>> {
+        if !self.config.watch_changes {
+            return None;
+        }
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.watchers
+            .lock()
+            .expect("storinfo watchers lock")
+            .push(tx);
+        Some(rx)
+    }
+
+    /// Fetch the list of sharks, resuming any poll that failed part way
+    /// through, and take a new snapshot if it is complete.  Returns false if
+    /// it is not.
+    pub fn poll(&self) -> bool {
+        // Holding the progress lock for the whole poll means that a job
+        // polling on startup and the poller thread do not fetch the same
+        // pages twice.
+        let mut progress =
+            self.progress.lock().expect("storinfo progress lock");
+        let page_size = self.config.page_size.max(1);
+        let res = fetch_pages(&mut progress, page_size, |after_id| {
+            self.fetch_page(page_size, after_id)
+        });
+
+        let mut sharks = match res {
+            Ok(s) => s,
+            Err(e) => {
+                error!(
+                    "Error requesting list of sharks from storinfo service \
+                     ({} received so far, will resume): {}",
+                    progress.sharks.len(),
+                    e
+                );
+                return false;
+            }
+        };
+        drop(progress);
+
+        sharks.sort_by(|a, b| a.available_mb.cmp(&b.available_mb));
+        debug!("storinfo updated with {} sharks", sharks.len());
+
+        self.take_snapshot(sharks);
+        *LAST_UPDATE.lock().expect("storinfo last update lock") =
+            Some(Instant::now());
+
+        true
+    }
+
+    // Replace the snapshot, and tell the watchers what has changed since
+    // the one before.  The first snapshot has nothing to compare with, so no
+    // job is told about it.
+    fn take_snapshot(&self, sharks: Vec<StorageNode>) {
+        let mut snapshot =
+            self.snapshot.write().expect("storinfo snapshot lock");
+        let (generation, changes) = match snapshot.as_ref() {
+            Some(prev) => {
+                (prev.generation + 1, diff_sharks(&prev.sharks, &sharks))
+            }
+            None => (1, vec![]),
+        };
+
+        *snapshot = Some(Arc::new(StorinfoSnapshot {
+            sharks,
+            timestamp: now_ms() as u64,
+            generation,
+        }));
+        drop(snapshot);
+
+        if self.config.watch_changes {
+            self.publish(&changes);
+        }
+    }
+
+    fn publish(&self, changes: &[SharkChange]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let mut watchers =
+            self.watchers.lock().expect("storinfo watchers lock");
+        watchers
+            .retain(|tx| changes.iter().all(|c| tx.send(c.clone()).is_ok()));
+    }
+
+    // Wait out what is left of the minimum interval since the last request.
+    fn rate_limit(&self) {
+        let min_interval =
+            Duration::from_millis(self.config.min_request_interval_ms);
+        let mut last_request =
+            self.last_request.lock().expect("storinfo request lock");
+
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < min_interval {
+                thread::sleep(min_interval - elapsed);
+            }
+        }
+
+        *last_request = Some(Instant::now());
+    }
+
+    fn fetch_page(
+        &self,
+        page_size: usize,
+        after_id: &str,
+    ) -> Result<Vec<StorageNode>, String> {
+        self.rate_limit();
+
+        let url = format!(
+            "http://{}/storagenodes?limit={}&after_id={}",
+            self.host, page_size, after_id
+        );
+        let mut response =
+            self.client.get(&url).send().map_err(|e| e.to_string())?;
+
+        trace!("Got picker response: {:#?}", response);
+
+        // Storinfo, or our connection to it, is sick.  Rather than take a
+        // snapshot of a partial list we leave the poll to be resumed.
+        if response.status() != StatusCode::OK {
+            return Err(format!("status {}", response.status()));
+        }
+
+        response
+            .json()
+            .map_err(|e| format!("picker response format: {}", e))
+    }
+}
+
+// Carry on with the pagination in `progress` until `get_page`, which is
+// given the id of the last shark received, returns a short page.  If it
+// fails, what has been received so far is left in `progress` for the next
+// attempt to carry on with.
+fn fetch_pages<F>(
+    progress: &mut FetchProgress,
+    page_size: usize,
+    mut get_page: F,
+) -> Result<Vec<StorageNode>, String>
+where
+    F: FnMut(&str) -> Result<Vec<StorageNode>, String>,
+{
+    if progress
+        .started
+        .map_or(false, |s| s.elapsed() > MAX_RESUME_AGE)
+    {
+        warn!(
+            "Discarding {} sharks from an old incomplete storinfo poll",
+            progress.sharks.len()
+        );
+        *progress = FetchProgress::default();
+    }
+
+    if progress.started.is_none() {
+        progress.started = Some(Instant::now());
+    } else {
+        info!(
+            "Resuming storinfo poll after {} sharks",
+            progress.sharks.len()
+        );
+    }
+
+    loop {
+        let page = get_page(&progress.after_id)?;
+        let short = page.len() < page_size;
+
+        // .last() returns None on an empty Vec, so we can just stop if that
+        // is the case.
+        match page.last() {
+            Some(s) => progress.after_id = s.manta_storage_id.clone(),
+            None => break,
+        }
+
+        progress.sharks.extend(page);
+
+        if short {
+            break;
+        }
+    }
+
+    Ok(mem::replace(progress, FetchProgress::default()).sharks)
+}
+
+// The sharks in `new` that are not in `old`, and those in `old` that are not
+// in `new`.
+fn diff_sharks(old: &[StorageNode], new: &[StorageNode]) -> Vec<SharkChange> {
+    let mut changes: Vec<SharkChange> = new
+        .iter()
+        .filter(|n| {
+            !old.iter().any(|o| o.manta_storage_id == n.manta_storage_id)
+        })
+        .map(|n| SharkChange::Added(n.clone()))
+        .collect();
+
+    changes.extend(
+        old.iter()
+            .filter(|o| {
+                !new.iter().any(|n| n.manta_storage_id == o.manta_storage_id)
+            })
+            .map(|o| SharkChange::Removed(o.manta_storage_id.clone())),
+    );
+
+    changes
+}
+
+/// A job's view of the shared poller.
+pub struct Storinfo {
+    poller: Arc<StorinfoPoller>,
+
+    // The generation of the last snapshot that this job was given.
+    seen: Mutex<u64>,
+    changes: Option<crossbeam_channel::Receiver<SharkChange>>,
 }
 
 ///
@@ -81,94 +456,71 @@ impl<'a> ChooseAlgorithm<'a> {
             ChooseAlgorithm::Default(algo) => algo.method(sharks),
         }
     }
+
+    /// Whether `shark` would be among those chosen.
+    pub fn admits(&self, shark: &StorageNode) -> bool {
+        match self {
+            ChooseAlgorithm::Default(algo) => algo.admits(shark),
+        }
+    }
 }
 
 impl DefaultChooseAlgorithm {
     fn method(&self, sharks: &[StorageNode]) -> Vec<StorageNode> {
-        let mut ret: Vec<StorageNode> = vec![];
-
-        // If the min_avail_mb is specified and the sharks available space is less
-        // than min_avail_mb skip it.
-        for s in sharks.iter() {
-            if let Some(min_avail_mb) = self.min_avail_mb {
-                if self.blacklist.contains(&s.datacenter)
-                    || s.available_mb < min_avail_mb
-                {
-                    continue;
-                }
+        sharks
+            .iter()
+            .filter(|s| self.admits(s))
+            .map(|s| s.to_owned())
+            .collect()
+    }
+
+    // If the min_avail_mb is specified and the sharks available space is less
+    // than min_avail_mb skip it.
+    fn admits(&self, s: &StorageNode) -> bool {
+        match self.min_avail_mb {
+            Some(min_avail_mb) => {
+                !self.blacklist.contains(&s.datacenter)
+                    && s.available_mb >= min_avail_mb
             }
-            ret.push(s.to_owned())
+            None => true,
         }
-
-        ret
     }
 }
 
 impl Storinfo {
-    pub fn new(domain: &str) -> Result<Self, Error> {
-        let storinfo_domain_name = format!("storinfo.{}", domain);
+    pub fn new(domain: &str, config: &ConfigStorinfo) -> Result<Self, Error> {
+        let poller = shared(domain, config);
+        let changes = poller.watch();
+
         Ok(Storinfo {
-            running: Arc::new(AtomicBool::new(true)),
-            handle: Mutex::new(None),
-            sharks: Arc::new(Mutex::new(Some(vec![]))),
-            host: storinfo_domain_name,
+            poller,
+            seen: Mutex::new(0),
+            changes,
         })
     }
 
-    /// Populate the storinfo's sharks field, and start the storinfo updater thread.
+    /// Make sure that there is a list of sharks for the job to start with,
+    /// polling storinfo now if the poller has not got one yet.
     pub fn start(&mut self) -> Result<(), Error> {
-        let client = Client::new();
-        let mut locked_sharks = self.sharks.lock().unwrap();
         // TODO: MANTA-4961, don't start job if picker cannot be reached.
-        *locked_sharks = Some(fetch_sharks(&client, &self.host));
-
-        let handle = Self::updater(
-            self.host.clone(),
-            Arc::clone(&self.sharks),
-            Arc::clone(&self.running),
-        );
-        let mut locked_handle = self.handle.lock().unwrap();
-        *locked_handle = Some(handle);
-        Ok(())
-    }
-
-    pub fn fini(&self) {
-        self.running.swap(false, Ordering::Relaxed);
-
-        if let Some(handle) = self.handle.lock().unwrap().take() {
-            handle.join().expect("failed to stop updater thread");
-        } else {
-            warn!("Updater thread not started");
+        if self.poller.snapshot().is_none() {
+            self.poller.poll();
         }
+        Ok(())
     }
 
-    /// Get the the Vec<sharks> from the storinfo service.
+    /// The sharks in the latest snapshot, or None if this job has already
+    /// been given it.
     pub fn get_sharks(&self) -> Option<Vec<StorageNode>> {
-        self.sharks.lock().unwrap().take()
-    }
-
-    fn updater(
-        host: String,
-        sharks: Arc<Mutex<Option<Vec<StorageNode>>>>,
-        running: Arc<AtomicBool>,
-    ) -> JoinHandle<()> {
-        let updater_sharks = Arc::clone(&sharks);
-        let keep_running = Arc::clone(&running);
-
-        thread::spawn(move || {
-            let sleep_time = time::Duration::from_secs(10);
-            let client = Client::new();
-            while keep_running.load(Ordering::Relaxed) {
-                thread::sleep(sleep_time);
+        let snapshot = self.poller.snapshot()?;
+        let mut seen = self.seen.lock().expect("storinfo seen lock");
 
-                let mut new_sharks = fetch_sharks(&client, &host);
-                new_sharks.sort_by(|a, b| a.available_mb.cmp(&b.available_mb));
+        if snapshot.generation <= *seen {
+            return None;
+        }
 
-                let mut old_sharks = updater_sharks.lock().unwrap();
-                *old_sharks = Some(new_sharks);
-                info!("Sharks updated, sleeping for {:?}", sleep_time);
-            }
-        })
+        *seen = snapshot.generation;
+        Some(snapshot.sharks.clone())
     }
 }
 
@@ -181,60 +533,335 @@ impl SharkSource for Storinfo {
             None => None,
         }
     }
+
+    fn changes(&self) -> Vec<SharkChange> {
+        match &self.changes {
+            Some(rx) => rx.try_iter().collect(),
+            None => vec![],
+        }
+    }
 }
 
-pub trait SharkSource: Sync + Send {
-    fn choose(&self, algo: &ChooseAlgorithm) -> Option<Vec<StorageNode>>;
+/// Destination sharks described by a JSON file rather than by storinfo: an
+/// array of objects with the `manta_storage_id`, `datacenter` and
+/// `available_mb` (and optionally `percent_used` and `filesystem`) of each
+/// shark.  The file is read again whenever it is modified, so that the
+/// capacities in it can be kept up to date by hand.
+pub struct SharksFile {
+    path: PathBuf,
+
+    // When the file had last been modified when it was last read.
+    modified: Mutex<Option<SystemTime>>,
+}
+
+#[derive(Deserialize)]
+struct FileShark {
+    manta_storage_id: String,
+    datacenter: String,
+    available_mb: u64,
+    #[serde(default)]
+    percent_used: u8,
+    #[serde(default)]
+    filesystem: String,
+}
+
+// The sharks in a sharks file, each with the time at which the file was
+// modified as its timestamp, so that space that jobs fill on them is counted
+// until the file is updated.  See jobs::projected.
+fn parse_sharks_file(
+    contents: &str,
+    modified: SystemTime,
+) -> Result<Vec<StorageNode>, String> {
+    let sharks: Vec<FileShark> =
+        serde_json::from_str(contents).map_err(|e| e.to_string())?;
+
+    if sharks.is_empty() {
+        return Err(String::from("no sharks listed"));
+    }
+
+    let timestamp = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut nodes: Vec<StorageNode> = vec![];
+    for s in sharks {
+        if nodes
+            .iter()
+            .any(|n| n.manta_storage_id == s.manta_storage_id)
+        {
+            return Err(format!(
+                "{} listed more than once",
+                s.manta_storage_id
+            ));
+        }
+
+        nodes.push(StorageNode {
+            available_mb: s.available_mb,
+            percent_used: s.percent_used,
+            filesystem: s.filesystem,
+            datacenter: s.datacenter,
+            manta_storage_id: s.manta_storage_id,
+            timestamp,
+        });
+    }
+
+    Ok(nodes)
 }
 
-fn fetch_sharks(client: &Client, host: &str) -> Vec<StorageNode> {
-    let mut new_sharks = vec![];
-    let mut done = false;
-    let mut after_id = String::new();
-    let base_url = format!("http://{}/storagenodes", host);
-    let limit = 100;
+impl SharksFile {
+    /// Check that `path` can be read and lists at least one shark.
+    pub fn new(path: &str) -> Result<SharksFile, String> {
+        let sharks = SharksFile {
+            path: PathBuf::from(path),
+            modified: Mutex::new(None),
+        };
+
+        sharks
+            .read()
+            .map_err(|e| format!("Invalid sharks file {}: {}", path, e))?;
+        Ok(sharks)
+    }
+
+    fn read(&self) -> Result<(SystemTime, Vec<StorageNode>), String> {
+        let modified = fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .map_err(|e| e.to_string())?;
+        let contents =
+            fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+
+        Ok((modified, parse_sharks_file(&contents, modified)?))
+    }
+
+    /// The sharks in the file, or None if it has not been modified since it
+    /// was last read.  A file that can no longer be read is logged, and the
+    /// sharks that were last read from it are kept.
+    pub fn get_sharks(&self) -> Option<Vec<StorageNode>> {
+        let mut last_modified =
+            self.modified.lock().expect("sharks file modified lock");
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+
+        if modified.is_some() && modified == *last_modified {
+            return None;
+        }
 
-    while !done {
-        let url = format!("{}?limit={}&after_id={}", base_url, limit, after_id);
-        let mut response = match client.get(&url).send() {
-            Ok(r) => r,
+        match self.read() {
+            Ok((modified, sharks)) => {
+                info!(
+                    "Read {} sharks from {}",
+                    sharks.len(),
+                    self.path.display()
+                );
+                *last_modified = Some(modified);
+                Some(sharks)
+            }
             Err(e) => {
-                error!(
-                    "Error requesting list of sharks from storinfo \
-                     service: {}",
+                warn!(
+                    "Could not read sharks file {}: {}",
+                    self.path.display(),
                     e
                 );
-                return vec![];
+                None
             }
-        };
+        }
+    }
+}
 
-        trace!("Got picker response: {:#?}", response);
+impl SharkSource for SharksFile {
+    fn choose(&self, algo: &ChooseAlgorithm) -> Option<Vec<StorageNode>> {
+        self.get_sharks().map(|s| algo.choose(&s))
+    }
+}
 
-        // Storinfo, or our connection to it, is sick.  So instead of
-        // breaking out of the loop and possibly returning partial results we
-        // return an empty Vec.
-        if response.status() != StatusCode::OK {
-            error!("Could not contact storinfo service {:#?}", response);
-            return vec![];
-        }
+pub trait SharkSource: Sync + Send {
+    fn choose(&self, algo: &ChooseAlgorithm) -> Option<Vec<StorageNode>>;
+
+    /// The sharks that have appeared or disappeared since this was last
+    /// called.  Sources that do not watch for changes have none.
+    fn changes(&self) -> Vec<SharkChange> {
+        vec![]
+    }
+}
 
-        let result: Vec<StorageNode> =
-            response.json().expect("picker response format");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // .last() returns None on an empty Vec, so we can just break out of the
-        // loop if that is the case.
-        match result.last() {
-            Some(r) => after_id = r.manta_storage_id.clone(),
-            None => break,
+    fn shark(id: &str) -> StorageNode {
+        StorageNode {
+            manta_storage_id: id.to_string(),
+            ..Default::default()
         }
+    }
+
+    fn page(ids: &[&str]) -> Vec<StorageNode> {
+        ids.iter().map(|id| shark(id)).collect()
+    }
+
+    #[test]
+    fn fetch_pages_resumes() {
+        let mut progress = FetchProgress::default();
+        let mut requested: Vec<String> = vec![];
+
+        // The second page fails, leaving the first to be resumed from.
+        let res = fetch_pages(&mut progress, 2, |after_id| {
+            requested.push(after_id.to_string());
+            match after_id {
+                "" => Ok(page(&["1", "2"])),
+                _ => Err(String::from("unavailable")),
+            }
+        });
+        assert!(res.is_err());
+        assert_eq!(progress.sharks.len(), 2);
+        assert_eq!(progress.after_id, "2");
+
+        let sharks = fetch_pages(&mut progress, 2, |after_id| {
+            requested.push(after_id.to_string());
+            match after_id {
+                "2" => Ok(page(&["3", "4"])),
+                "4" => Ok(page(&["5"])),
+                _ => panic!("unexpected after_id {}", after_id),
+            }
+        })
+        .expect("resumed poll");
+
+        let ids: Vec<&str> =
+            sharks.iter().map(|s| s.manta_storage_id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(requested, vec!["", "2", "2", "4"]);
 
-        if result.len() < limit {
-            done = true;
+        // A complete poll leaves nothing to resume.
+        assert!(progress.sharks.is_empty());
+        assert!(progress.started.is_none());
+
+        // A full last page is followed by an empty one.
+        let sharks = fetch_pages(&mut progress, 2, |after_id| match after_id {
+            "" => Ok(page(&["1", "2"])),
+            _ => Ok(vec![]),
+        })
+        .expect("poll");
+        assert_eq!(sharks.len(), 2);
+
+        // An old partial list is started over.
+        if let Some(old) =
+            Instant::now().checked_sub(MAX_RESUME_AGE + Duration::from_secs(1))
+        {
+            progress.sharks = page(&["1"]);
+            progress.after_id = String::from("1");
+            progress.started = Some(old);
+
+            let sharks = fetch_pages(&mut progress, 2, |after_id| {
+                assert_eq!(after_id, "");
+                Ok(page(&["9"]))
+            })
+            .expect("restarted poll");
+            assert_eq!(sharks, page(&["9"]));
         }
+    }
+
+    #[test]
+    fn diff_sharks_changes() {
+        let old = page(&["1", "2", "3"]);
+        let new = page(&["2", "3", "4"]);
+
+        assert_eq!(
+            diff_sharks(&old, &new),
+            vec![
+                SharkChange::Added(shark("4")),
+                SharkChange::Removed(String::from("1")),
+            ]
+        );
+        assert!(diff_sharks(&new, &new).is_empty());
+    }
 
-        new_sharks.extend(result);
+    #[test]
+    fn sharks_file() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1000);
+        let sharks = parse_sharks_file(
+            r#"[
+                {
+                    "manta_storage_id": "1.stor.domain",
+                    "datacenter": "dc1",
+                    "available_mb": 1000
+                },
+                {
+                    "manta_storage_id": "2.stor.domain",
+                    "datacenter": "dc2",
+                    "available_mb": 2000,
+                    "percent_used": 50
+                }
+            ]"#,
+            modified,
+        )
+        .expect("sharks file");
+
+        assert_eq!(sharks.len(), 2);
+        assert_eq!(sharks[1].percent_used, 50);
+        assert!(sharks.iter().all(|s| s.timestamp == 1_000_000));
+
+        assert!(parse_sharks_file("[]", modified).is_err());
+        assert!(parse_sharks_file("{}", modified).is_err());
+        assert!(parse_sharks_file(
+            r#"[
+                {"manta_storage_id": "1", "datacenter": "d", "available_mb": 1},
+                {"manta_storage_id": "1", "datacenter": "d", "available_mb": 2}
+            ]"#,
+            modified,
+        )
+        .unwrap_err()
+        .contains("more than once"));
+
+        // The file is only read again once it has been modified.
+        let path = std::env::temp_dir()
+            .join(format!("sharks-{}.json", std::process::id()));
+        let contents = r#"[
+            {"manta_storage_id": "1", "datacenter": "d", "available_mb": 1}
+        ]"#;
+        fs::write(&path, contents).expect("write sharks file");
+        let file = SharksFile::new(path.to_str().unwrap()).expect("file");
+        assert_eq!(file.get_sharks().expect("first read").len(), 1);
+        assert!(file.get_sharks().is_none());
+        fs::remove_file(&path).expect("remove sharks file");
+
+        assert!(SharksFile::new(path.to_str().unwrap()).is_err());
     }
 
-    debug!("storinfo updated with {} new sharks", new_sharks.len());
-    new_sharks
+    #[test]
+    fn storinfo_generations() {
+        let config = ConfigStorinfo {
+            watch_changes: true,
+            ..Default::default()
+        };
+        let poller = Arc::new(StorinfoPoller::new("fake.joyent.us", &config));
+        let rx = poller.watch().expect("watching");
+        let storinfo = Storinfo {
+            poller: Arc::clone(&poller),
+            seen: Mutex::new(0),
+            changes: poller.watch(),
+        };
+
+        assert!(storinfo.get_sharks().is_none());
+
+        let take = |ids: &[&str]| poller.take_snapshot(page(ids));
+
+        take(&["1", "2"]);
+        assert_eq!(storinfo.get_sharks().expect("first snapshot").len(), 2);
+        assert!(storinfo.get_sharks().is_none());
+        assert!(storinfo.changes().is_empty());
+
+        take(&["2", "3"]);
+        assert_eq!(
+            storinfo.changes(),
+            vec![
+                SharkChange::Added(shark("3")),
+                SharkChange::Removed(String::from("1")),
+            ]
+        );
+        assert_eq!(storinfo.get_sharks().expect("second snapshot").len(), 2);
+
+        // A watcher that has gone away is forgotten.
+        drop(rx);
+        take(&["3"]);
+        assert_eq!(poller.watchers.lock().unwrap().len(), 1);
+    }
 }
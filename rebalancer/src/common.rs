@@ -14,7 +14,9 @@ use std::io::Write;
 #[cfg(feature = "postgres")]
 use std::str::FromStr;
 
-use crate::error::{Error, InternalError, InternalErrorCode};
+use std::collections::BTreeMap;
+
+use crate::error::{Error, InternalError, InternalErrorCode, RebalancerError};
 use libmanta::moray::MantaObjectShark;
 use md5::{Digest, Md5};
 use quickcheck::{Arbitrary, Gen};
@@ -42,15 +44,99 @@ pub type ObjectId = String; // UUID
 pub struct AssignmentPayload {
     pub id: String,
     pub tasks: Vec<Task>,
+
+    // The order in which the manager would like the agent to process the
+    // tasks.  If it is not given, the agent uses its own default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<TaskOrder>,
+
+    // The content encodings that the agent may ask the sources of the
+    // assignment's objects to send them in, most preferred first.  If there
+    // are none, objects are sent as they are.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encodings: Vec<ContentEncoding>,
 }
 
 impl From<AssignmentPayload> for (String, Vec<Task>) {
     fn from(p: AssignmentPayload) -> (String, Vec<Task>) {
-        let AssignmentPayload { id, tasks } = p;
+        let AssignmentPayload { id, tasks, .. } = p;
         (id, tasks)
     }
 }
 
+/// The order in which an agent processes the tasks of an assignment.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Display,
+    EnumString,
+    Eq,
+    PartialEq,
+    Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum TaskOrder {
+    // The order that the tasks were sent in.
+    Received,
+
+    // The smallest objects first, so that as many objects as possible are
+    // moved early on.
+    SmallestFirst,
+
+    // The largest objects first, so that as much space as possible is freed
+    // early on.
+    LargestFirst,
+}
+
+impl Default for TaskOrder {
+    fn default() -> Self {
+        TaskOrder::Received
+    }
+}
+
+/// A compression that an object may be sent to an agent with, as named in
+/// the Accept-Encoding and Content-Encoding headers.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Display,
+    EnumString,
+    Eq,
+    PartialEq,
+    Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ContentEncoding {
+    Gzip,
+    Zstd,
+}
+
+impl TaskOrder {
+    /// Put `tasks` in this order.  The sort is stable, so tasks of the same
+    /// size keep the order that they were sent in, and tasks whose size is
+    /// not known go last either way.
+    pub fn sort(self, tasks: &mut [Task]) {
+        match self {
+            TaskOrder::Received => (),
+            TaskOrder::SmallestFirst => tasks.sort_by_key(|t| {
+                (t.content_length.is_none(), t.content_length.unwrap_or(0))
+            }),
+            TaskOrder::LargestFirst => tasks.sort_by_key(|t| {
+                (
+                    t.content_length.is_none(),
+                    std::cmp::Reverse(t.content_length.unwrap_or(0)),
+                )
+            }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Task {
     pub object_id: String, // or Uuid
@@ -60,12 +146,164 @@ pub struct Task {
 
     #[serde(default = "TaskStatus::default")]
     pub status: TaskStatus,
+
+    // The size of the object in bytes, which the agent uses to decide whether
+    // it has room for the assignment.  Managers that predate this do not send
+    // it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+
+    // How many times, and for how long, the agent tried to download the
+    // object.  This is only set by the agent, once it has processed the task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadAttempts>,
+
+    // What the agent found out about the task while processing it, if the
+    // task took longer than the agent's slow task threshold.  This is only
+    // set by the agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autopsy: Option<TaskAutopsy>,
+
+    // What the agent is to do with the object.  Managers that predate this
+    // only send copy tasks, and it is left out for them so that agents that
+    // predate it can still take copy tasks from a newer manager.
+    #[serde(default, skip_serializing_if = "TaskAction::is_copy")]
+    pub action: TaskAction,
+
+    // The generation of the object's data that its metadata described when
+    // the task was created (see object_generation()).  The agent only
+    // deletes its copy of an object if the copy is of this generation, so a
+    // delete task without one is refused.  Copy tasks do not use it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<String>,
+
+    // Copy the object without checking it against md5sum, because its
+    // metadata has no usable checksum and the job's checksum policy is to
+    // copy such objects anyway.  It is left out when false.  An agent that
+    // predates it checks the copy all the same, and fails the task.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unchecked: bool,
+
+    // Fetch the object from the rebalancer agent on the source storage node
+    // (GET /objects/<owner>/<object>) rather than from the node's web server.
+    // It is left out when false.  An agent that predates it fetches the
+    // object from the web server all the same.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub direct: bool,
+
+    // The content encodings that the object may be downloaded in, which are
+    // those of the task's assignment.  The agent sets these for each
+    // download, and they are never sent.
+    #[serde(skip)]
+    pub encodings: Vec<ContentEncoding>,
+}
+
+/// A token that identifies the data that an object's metadata describes, by
+/// its size and checksum.  A copy of the object whose data has been replaced,
+/// truncated or damaged since is of another generation.
+pub fn object_generation(md5sum: &str, content_length: u64) -> String {
+    format!("{}:{}", content_length, md5sum)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskAction {
+    // Download the object from `source` and put it in place on this agent.
+    Copy,
+
+    // Remove this agent's copy of the object.  `source` is this agent's own
+    // shark, and nothing is downloaded.
+    Delete,
+}
+
+impl Default for TaskAction {
+    fn default() -> Self {
+        TaskAction::Copy
+    }
+}
+
+impl TaskAction {
+    pub fn is_copy(&self) -> bool {
+        *self == TaskAction::Copy
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct DownloadAttempts {
+    pub attempts: u32,
+
+    // Milliseconds from the start of the first attempt to the end of the
+    // last.
+    pub elapsed_ms: u64,
+}
+
+/// The context of a slow task, to help work out why it was slow.  The
+/// details of the download are those of the last attempt at it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TaskAutopsy {
+    // Milliseconds from the start of the first download attempt to the end
+    // of the task, and of that, the time spent in each stage.
+    pub elapsed_ms: u64,
+    pub download_ms: u64,
+    pub verify_ms: u64,
+
+    // Milliseconds from sending the request for the object to receiving the
+    // headers of the response.
+    pub first_byte_ms: Option<u64>,
+
+    // The address that the source was reached at.
+    pub source_addr: Option<String>,
+
+    // The headers of the source's response.
+    pub source_headers: BTreeMap<String, String>,
+
+    // The bytes received from the source, and the bytes that it said it
+    // would send.
+    pub bytes: u64,
+    pub expected_bytes: Option<u64>,
+
+    // The encoding that the source sent the object in, if any, in which case
+    // the bytes above are of the encoded object, and `decoded_bytes` are
+    // those that it was decoded to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<ContentEncoding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded_bytes: Option<u64>,
 }
 
 impl Task {
     pub fn set_status(&mut self, status: TaskStatus) {
         self.status = status;
     }
+
+    /// An identifier for this task that is the same every time the task is
+    /// sent, regardless of its status.  The agent uses this to recognize an
+    /// assignment that is posted again (e.g. after the manager timed out
+    /// waiting for a response to the first post) as one that it already has.
+    pub fn idempotency_id(&self) -> String {
+        let mut hasher = Md5::new();
+
+        for field in &[
+            &self.object_id,
+            &self.owner,
+            &self.md5sum,
+            &self.source.manta_storage_id,
+        ] {
+            hasher.input(field.as_bytes());
+            hasher.input(&[0]);
+        }
+
+        // Copy tasks hash as they did before there were other actions.
+        if !self.action.is_copy() {
+            hasher.input(b"delete");
+        }
+
+        hasher
+            .result()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
 }
 
 impl Arbitrary for Task {
@@ -82,6 +320,14 @@ impl Arbitrary for Task {
             md5sum,
             source: MantaObjectShark::arbitrary(g),
             status: TaskStatus::arbitrary(g),
+            content_length: Some(u64::from(g.next_u32())),
+            download: None,
+            autopsy: None,
+            action: TaskAction::Copy,
+            generation: None,
+            unchecked: false,
+            direct: false,
+            encodings: vec![],
         }
     }
 }
@@ -135,12 +381,34 @@ pub enum ObjectSkippedReason {
     // Agent encountered a local filesystem error
     AgentFSError,
 
+    // The object that a delete task was for is not on the agent.
+    AgentObjectNotFound,
+
+    // The agent is not permitted to delete the object.
+    AgentPermissionDenied,
+
+    // The object that a delete task was for is in use, and could not be
+    // deleted.
+    AgentObjectBusy,
+
+    // The object on the agent is not of the generation that a delete task was
+    // for, or the task did not say which generation it was for.
+    GenerationMismatch,
+
     // The specified agent does not have that assignment
     AgentAssignmentNoEnt,
 
     // The agent is busy and cant accept assignments at this time.
     AgentBusy,
 
+    // The agent has registered with the manager, and does not support the
+    // action of the task.
+    AgentUnsupported,
+
+    // The assignment was cancelled by an operator before this task was
+    // processed.
+    AssignmentCancelled,
+
     // Internal Assignment Error
     AssignmentError,
 
@@ -150,15 +418,31 @@ pub enum ObjectSkippedReason {
     // The assignment was rejected by the agent.
     AssignmentRejected,
 
+    // The object's metadata has a missing or malformed checksum, and the
+    // job's checksum policy is to skip such objects.
+    BadChecksum,
+
     // Not enough space on destination SN
     DestinationInsufficientSpace,
 
     // Destination agent was not reachable
     DestinationUnreachable,
 
+    // The copy on the destination could not be confirmed before the object's
+    // metadata was updated to point at it.
+    DestinationUnverified,
+
     // MD5 Mismatch between the file on disk and the metadata.
     MD5Mismatch,
 
+    // The object is larger than the free space on every destination that the
+    // job could have given it to.
+    NoFitDestination,
+
+    // The object has more than one copy on the shark being evacuated, and
+    // there was no destination for each of them.
+    NoSecondDestination,
+
     // Catchall for unspecified network errors.
     NetworkError,
 
@@ -211,6 +495,104 @@ fn _osr_from_sql(ts: String) -> deserialize::Result<ObjectSkippedReason> {
 }
 
 impl ObjectSkippedReason {
+    /// Returns true if a download that failed for this reason might succeed
+    /// if it were attempted again: the network let us down, or the source
+    /// answered with an error that it expects to clear up (a timeout,
+    /// throttling or a server error).  Likewise a delete that failed because
+    /// the object was in use.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ObjectSkippedReason::NetworkError
+            | ObjectSkippedReason::SourceOtherError
+            | ObjectSkippedReason::AgentObjectBusy => true,
+            ObjectSkippedReason::HTTPStatusCode(sc) => {
+                *sc == 408 || *sc == 429 || (*sc >= 500 && *sc < 600)
+            }
+            _ => false,
+        }
+    }
+
+    /// The code of this reason, as reported to clients and in metrics.  An
+    /// HTTP status code is the code of the source's error, retryable or not
+    /// as above.
+    pub fn rebalancer_error(&self) -> RebalancerError {
+        match self {
+            ObjectSkippedReason::AgentFSError
+            | ObjectSkippedReason::AgentPermissionDenied => {
+                RebalancerError::Filesystem
+            }
+            ObjectSkippedReason::AgentObjectNotFound => {
+                RebalancerError::ObjectMissing
+            }
+            ObjectSkippedReason::AgentObjectBusy
+            | ObjectSkippedReason::AgentBusy => RebalancerError::Busy,
+            ObjectSkippedReason::GenerationMismatch => {
+                RebalancerError::GenerationMismatch
+            }
+            ObjectSkippedReason::AgentAssignmentNoEnt
+            | ObjectSkippedReason::AssignmentError
+            | ObjectSkippedReason::AssignmentMismatch
+            | ObjectSkippedReason::AssignmentRejected => {
+                RebalancerError::AssignmentLost
+            }
+            ObjectSkippedReason::AgentUnsupported => {
+                RebalancerError::Unsupported
+            }
+            ObjectSkippedReason::AssignmentCancelled => {
+                RebalancerError::Cancelled
+            }
+            ObjectSkippedReason::BadChecksum => RebalancerError::BadMetadata,
+            ObjectSkippedReason::DestinationInsufficientSpace => {
+                RebalancerError::InsufficientSpace
+            }
+            ObjectSkippedReason::DestinationUnreachable
+            | ObjectSkippedReason::NetworkError => RebalancerError::Unreachable,
+            ObjectSkippedReason::DestinationUnverified => {
+                RebalancerError::Unverified
+            }
+            ObjectSkippedReason::MD5Mismatch => {
+                RebalancerError::ChecksumMismatch
+            }
+            ObjectSkippedReason::NoFitDestination
+            | ObjectSkippedReason::NoSecondDestination
+            | ObjectSkippedReason::ObjectAlreadyOnDestShark
+            | ObjectSkippedReason::ObjectAlreadyInDatacenter => {
+                RebalancerError::NoDestination
+            }
+            ObjectSkippedReason::SourceIsEvacShark => RebalancerError::NoSource,
+            ObjectSkippedReason::SourceOtherError => {
+                RebalancerError::SourceError
+            }
+            ObjectSkippedReason::HTTPStatusCode(404)
+            | ObjectSkippedReason::HTTPStatusCode(410) => {
+                RebalancerError::ObjectMissing
+            }
+            ObjectSkippedReason::HTTPStatusCode(_) if self.is_retryable() => {
+                RebalancerError::SourceError
+            }
+            ObjectSkippedReason::HTTPStatusCode(_) => {
+                RebalancerError::SourceRefused
+            }
+        }
+    }
+
+    /// The reason that `into_string()` gave `reason`, if it is one.
+    pub fn from_reason_string(reason: &str) -> Option<ObjectSkippedReason> {
+        let matches: &[_] = &['{', '}'];
+        let mut parts = reason.trim_matches(matches).splitn(2, ':');
+        let parsed = ObjectSkippedReason::from_str(parts.next()?).ok()?;
+
+        match (parsed, parts.next()) {
+            (ObjectSkippedReason::HTTPStatusCode(_), Some(sc)) => {
+                sc.parse().ok().map(ObjectSkippedReason::HTTPStatusCode)
+            }
+            (ObjectSkippedReason::HTTPStatusCode(_), None) | (_, Some(_)) => {
+                None
+            }
+            (parsed, None) => Some(parsed),
+        }
+    }
+
     // The "Strum" crate already provides a "to_string()" method which we
     // want to use here.  This is for handling the special case of variants
     // with values/fields.
@@ -0,0 +1,279 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Checks that are applied to a configuration file, in its generic JSON form,
+// before it is deserialized into the strongly typed configuration structure
+// of the manager or agent.  Serde silently ignores keys that it does not know
+// about, which means that a misspelled or outdated tunable simply has no
+// effect.  Running the raw configuration through a ConfigSchema first allows
+// us to tell the operator about it.
+//
+// Once it has been deserialized, the values of the configuration are checked
+// with a ConfigCheck, against each other and against the system it is to run
+// on, so that a bad value is reported by the name of its key when the service
+// starts (or with --validate-config), instead of as a panic or a failure
+// somewhere later on.
+
+use std::fs::{self, OpenOptions};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+
+use serde_json::{Map, Value};
+
+static REDACTED: &str = "<redacted>";
+
+// Any key containing one of these is considered to hold a secret, and its
+// value is never logged or returned by the configuration end points.
+static SECRET_WORDS: &[&str] = &["password", "secret", "token", "private_key"];
+
+pub struct ConfigSchema {
+    /// Every key that may appear in the configuration, as a dot separated
+    /// path from the top level (e.g. "options.max_sharks").  The contents of
+    /// arrays are not checked.
+    pub known: &'static [&'static str],
+
+    /// Pairs of (deprecated key, replacement key).
+    pub deprecated: &'static [(&'static str, &'static str)],
+}
+
+impl ConfigSchema {
+    /// Move the values of any deprecated keys to their replacements, and
+    /// return a notice for each deprecated or unknown key encountered.
+    pub fn normalize(&self, config: &mut Value) -> Vec<String> {
+        let mut notices = vec![];
+
+        for (old, new) in self.deprecated.iter() {
+            let val = match take_key(config, old) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            if config.pointer(&to_pointer(new)).is_some() {
+                notices.push(format!(
+                    "Ignoring deprecated configuration key '{}' because \
+                     '{}' is also set",
+                    old, new
+                ));
+            } else {
+                notices.push(format!(
+                    "Configuration key '{}' is deprecated, use '{}' instead",
+                    old, new
+                ));
+                set_key(config, new, val);
+            }
+        }
+
+        self.find_unknown(config, "", &mut notices);
+
+        notices
+    }
+
+    fn find_unknown(
+        &self,
+        value: &Value,
+        prefix: &str,
+        notices: &mut Vec<String>,
+    ) {
+        let map = match value {
+            Value::Object(m) => m,
+            _ => return,
+        };
+
+        for (key, val) in map.iter() {
+            let path = if prefix.is_empty() {
+                key.to_owned()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+
+            if self.known.contains(&path.as_str()) {
+                self.find_unknown(val, &path, notices);
+            } else {
+                notices.push(format!(
+                    "Ignoring unknown configuration key '{}'",
+                    path
+                ));
+            }
+        }
+    }
+}
+
+/// The problems found with the values of a configuration, each prefixed with
+/// the key that it is about.
+#[derive(Debug, Default)]
+pub struct ConfigCheck {
+    errors: Vec<String>,
+}
+
+impl ConfigCheck {
+    /// Record `msg` about `key` unless `ok`.
+    pub fn ensure(&mut self, ok: bool, key: &str, msg: &str) {
+        if !ok {
+            self.error(key, msg);
+        }
+    }
+
+    pub fn error(&mut self, key: &str, msg: &str) {
+        self.errors.push(format!("{}: {}", key, msg));
+    }
+
+    pub fn port(&mut self, key: &str, port: u16) {
+        self.ensure(port != 0, key, "must be a port number from 1 to 65535");
+    }
+
+    pub fn percentage(&mut self, key: &str, value: u32) {
+        self.ensure(
+            value >= 1 && value <= 100,
+            key,
+            &format!("must be a percentage from 1 to 100, not {}", value),
+        );
+    }
+
+    pub fn domain_name(&mut self, key: &str, name: &str) {
+        self.ensure(
+            is_domain_name(name),
+            key,
+            &format!("'{}' is not a valid domain name", name),
+        );
+    }
+
+    /// An address of the form host:port, where the host is a name or an IP
+    /// address.
+    pub fn host_port(&mut self, key: &str, addr: &str) {
+        let ok = addr.parse::<SocketAddr>().is_ok()
+            || match addr.rfind(':') {
+                Some(i) => {
+                    is_domain_name(&addr[..i])
+                        && addr[i + 1..]
+                            .parse::<u16>()
+                            .map_or(false, |p| p != 0)
+                }
+                None => false,
+            };
+
+        self.ensure(
+            ok,
+            key,
+            &format!("'{}' is not of the form host:port", addr),
+        );
+    }
+
+    /// A directory that the service will create files in.  One that does not
+    /// exist yet is created when it is first needed, so it is then the
+    /// nearest directory above it that must be writable.
+    pub fn writable_dir(&mut self, key: &str, path: &str) {
+        if path.is_empty() {
+            self.error(key, "must not be empty");
+            return;
+        }
+
+        let mut dir = Path::new(path);
+        while !dir.exists() {
+            dir = match dir.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => Path::new("."),
+            };
+        }
+
+        if !dir.is_dir() {
+            self.error(key, &format!("{} is not a directory", dir.display()));
+            return;
+        }
+
+        let probe =
+            dir.join(format!(".rebalancer-config-check-{}", process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => {
+                let _ = fs::remove_file(&probe);
+            }
+            Err(e) => self.error(
+                key,
+                &format!("{} is not writable: {}", dir.display(), e),
+            ),
+        }
+    }
+
+    /// Returns every problem found, if there were any.
+    pub fn finish(self) -> Result<(), Vec<String>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Returns true if `name` is a valid DNS name: up to 253 characters, in
+/// labels of 1 to 63 letters, digits and hyphens which neither start nor end
+/// with a hyphen.
+pub fn is_domain_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Replace the value of every key that looks like it holds a secret.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, val) in map.iter_mut() {
+                let lower = key.to_lowercase();
+                if SECRET_WORDS.iter().any(|w| lower.contains(w)) {
+                    *val = Value::String(REDACTED.to_string());
+                } else {
+                    redact(val);
+                }
+            }
+        }
+        Value::Array(vals) => vals.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+fn to_pointer(path: &str) -> String {
+    format!("/{}", path.replace('.', "/"))
+}
+
+fn take_key(config: &mut Value, path: &str) -> Option<Value> {
+    let (parent, key) = match path.rfind('.') {
+        Some(i) => {
+            (config.pointer_mut(&to_pointer(&path[..i]))?, &path[i + 1..])
+        }
+        None => (config, path),
+    };
+
+    parent.as_object_mut()?.remove(key)
+}
+
+fn set_key(config: &mut Value, path: &str, val: Value) {
+    let mut parts: Vec<&str> = path.split('.').collect();
+    let key = parts.pop().expect("configuration key");
+    let mut cur = config;
+
+    for part in parts {
+        cur = match cur {
+            Value::Object(m) => m
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(Map::new())),
+            _ => return,
+        };
+    }
+
+    if let Value::Object(m) = cur {
+        m.insert(key.to_string(), val);
+    }
+}
@@ -10,6 +10,8 @@
 
 use std::fmt;
 
+pub use rebalancer_client::error::{ApiError, ErrorHandling, RebalancerError};
+
 #[derive(Debug)]
 pub enum Error {
     Internal(InternalError),
@@ -93,6 +95,23 @@ impl From<diesel::ConnectionError> for Error {
     }
 }
 
+impl Error {
+    /// The code of this error, as reported to clients and in metrics.
+    pub fn rebalancer_error(&self) -> RebalancerError {
+        match self {
+            Error::Internal(e) => e.code.rebalancer_error(),
+            Error::IoError(_) => RebalancerError::Filesystem,
+            Error::Hyper(_) | Error::Reqwest(_) => RebalancerError::Unreachable,
+            Error::Diesel(_) | Error::DieselConnection(_) => {
+                RebalancerError::Database
+            }
+            Error::SerdeJson(_) | Error::ParseInt(_) | Error::ParseUuid(_) => {
+                RebalancerError::InvalidRequest
+            }
+        }
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -131,6 +150,46 @@ pub enum InternalErrorCode {
     JobBuilderError,       // Errors building a Job
     MaxObjectsLimit,       // The max_objects limit has been reached
     DbQuery,               // Unexpected result from a database query
+    WorkerPanic,           // A job's worker thread panicked or exited early
+    JobInterrupted,        // A job was stopped early for a shutdown
+    JobPaused,             // A job was stopped early by its circuit breaker
+    JobArchive,            // Could not archive a job's database
+    FaultInjected,         // An answer dropped by an injected fault
+    JobLeased,             // A job is leased to another manager
+}
+
+impl InternalErrorCode {
+    pub fn rebalancer_error(self) -> RebalancerError {
+        match self {
+            InternalErrorCode::StorinfoError
+            | InternalErrorCode::IpLookupError
+            | InternalErrorCode::BadMorayClient
+            | InternalErrorCode::FaultInjected => RebalancerError::Unreachable,
+            InternalErrorCode::AssignmentLookupError
+            | InternalErrorCode::AssignmentGetError => {
+                RebalancerError::AssignmentLost
+            }
+            InternalErrorCode::SharkNotFound => RebalancerError::NotFound,
+            InternalErrorCode::DuplicateShark
+            | InternalErrorCode::BadMantaObject => RebalancerError::BadMetadata,
+            InternalErrorCode::MetadataUpdateFailure => {
+                RebalancerError::MetadataUpdateFailed
+            }
+            InternalErrorCode::JobBuilderError => {
+                RebalancerError::InvalidRequest
+            }
+            InternalErrorCode::MaxObjectsLimit => RebalancerError::LimitReached,
+            InternalErrorCode::DbQuery | InternalErrorCode::JobArchive => {
+                RebalancerError::Database
+            }
+            InternalErrorCode::JobInterrupted => RebalancerError::Unavailable,
+            InternalErrorCode::JobLeased => RebalancerError::Conflict,
+            InternalErrorCode::JobPaused => RebalancerError::Paused,
+            InternalErrorCode::Other
+            | InternalErrorCode::Crossbeam
+            | InternalErrorCode::WorkerPanic => RebalancerError::Internal,
+        }
+    }
 }
 
 impl fmt::Display for InternalError {
@@ -154,6 +213,10 @@ impl InternalError {
 
         InternalError { msg, code }
     }
+
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,459 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Injecting faults, to rehearse how the manager and the agent handle them.
+//
+// The only way to see how a job copes with a slow agent, an answer that never
+// arrives, a download that comes back corrupt, or moray refusing an update
+// used to be to break the network or the services themselves.  A build with
+// the `faults` feature instead injects them at fixed points of the pipelines,
+// according to a list of rules:
+//
+//  * agent_download: the agent's download of an object from its source.  A
+//    delay, a drop (the download fails for a transient reason, see the retry
+//    module) or corrupt (the object is downloaded, and then a byte of it is
+//    changed, so that it fails verification).
+//  * agent_assignment_post: the agent's answer to a POST /assignments, after
+//    the assignment has been saved.  A delay.
+//  * agent_assignment_get: the agent's answer to a GET /assignments/<uuid>.
+//    A delay.
+//  * manager_assignment_post: the agent's answer to the manager's post of an
+//    assignment.  A delay, or a drop: the agent has been sent the
+//    assignment, but its answer is thrown away, as though it never came.
+//  * manager_assignment_get: the agent's answer to the manager's poll of an
+//    assignment.  A delay, or a drop.
+//  * moray_update: the manager's update of an object's metadata.  A delay
+//    before the update, or an error in place of it.
+//
+// Each rule names a point and a fault, and is applied every `every` times
+// that the point is reached (every time, by default), at most `times` times
+// (without limit, by default).  A delay lasts `delay_ms`, and holds up
+// whatever thread reached the point.  Where more than one rule for a point
+// applies, the delays are added up, and only the first of the other faults
+// is injected.
+//
+// The rules are read at startup from the REBALANCER_FAULTS environment
+// variable, as a JSON list, and may be replaced with a PUT /faults to the
+// manager or the agent, seen with a GET (along with how many times each has
+// come into play), and removed with a DELETE.  A build without the feature
+// injects nothing, and has no /faults; a REBALANCER_FAULTS set for it is
+// logged and ignored.
+
+use crate::common::{ContentEncoding, ObjectSkippedReason, TaskAutopsy};
+use crate::error::{
+    ApiError, Error, InternalError, InternalErrorCode, RebalancerError,
+};
+use crate::transfer::Transfer;
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::{future, Future, Stream};
+use gotham::handler::{HandlerFuture, IntoHandlerError};
+use gotham::helpers::http::response::{create_empty_response, create_response};
+use gotham::state::{FromState, State};
+use hyper::{Body, Method, Response, StatusCode};
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+
+/// The environment variable that the rules are read from at startup.
+pub static FAULTS_ENV: &str = "REBALANCER_FAULTS";
+
+#[derive(
+    Clone, Copy, Debug, Deserialize, Display, Eq, Hash, PartialEq, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum FaultPoint {
+    AgentDownload,
+    AgentAssignmentPost,
+    AgentAssignmentGet,
+    ManagerAssignmentPost,
+    ManagerAssignmentGet,
+    MorayUpdate,
+}
+
+#[derive(
+    Clone, Copy, Debug, Deserialize, Display, Eq, Hash, PartialEq, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum FaultKind {
+    Delay,
+    Drop,
+    Corrupt,
+    Error,
+}
+
+impl FaultPoint {
+    /// Whether `fault` can be injected at this point.
+    pub fn allows(self, fault: FaultKind) -> bool {
+        match (self, fault) {
+            (_, FaultKind::Delay)
+            | (FaultPoint::AgentDownload, FaultKind::Drop)
+            | (FaultPoint::AgentDownload, FaultKind::Corrupt)
+            | (FaultPoint::ManagerAssignmentPost, FaultKind::Drop)
+            | (FaultPoint::ManagerAssignmentGet, FaultKind::Drop)
+            | (FaultPoint::MorayUpdate, FaultKind::Error) => true,
+            _ => false,
+        }
+    }
+}
+
+fn default_every() -> u64 {
+    1
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultRule {
+    pub point: FaultPoint,
+    pub fault: FaultKind,
+
+    /// How long a delay lasts.
+    #[serde(default)]
+    pub delay_ms: u64,
+
+    /// The fault is injected every `every` times that the point is reached.
+    #[serde(default = "default_every")]
+    pub every: u64,
+
+    /// The most times that the fault is injected.  None if there is no
+    /// limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<u64>,
+}
+
+impl FaultRule {
+    pub fn check(&self) -> Result<(), String> {
+        if !self.point.allows(self.fault) {
+            return Err(format!(
+                "A {} fault can not be injected at {}",
+                self.fault, self.point
+            ));
+        }
+
+        if self.every == 0 {
+            return Err(String::from("every must be at least 1"));
+        }
+
+        if self.fault == FaultKind::Delay && self.delay_ms == 0 {
+            return Err(format!("A delay at {} needs a delay_ms", self.point));
+        }
+
+        Ok(())
+    }
+}
+
+/// A rule, along with how often it has come into play.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FaultStatus {
+    #[serde(flatten)]
+    pub rule: FaultRule,
+
+    /// The times that the rule's point has been reached.
+    pub hits: u64,
+
+    /// The times that the fault has been injected.
+    pub injected: u64,
+}
+
+/// Parse a JSON list of rules, and check each of them.
+pub fn parse_rules(text: &str) -> Result<Vec<FaultRule>, String> {
+    let rules: Vec<FaultRule> = serde_json::from_str(text)
+        .map_err(|e| format!("Could not parse fault rules: {}", e))?;
+
+    for rule in rules.iter() {
+        rule.check()?;
+    }
+
+    Ok(rules)
+}
+
+#[derive(Default)]
+pub struct FaultInjector {
+    rules: Mutex<Vec<FaultStatus>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        FaultInjector::default()
+    }
+
+    /// Replace the rules with `rules`, which have been checked.
+    pub fn set(&self, rules: Vec<FaultRule>) {
+        *self.rules.lock().expect("fault rules lock") = rules
+            .into_iter()
+            .map(|rule| FaultStatus {
+                rule,
+                hits: 0,
+                injected: 0,
+            })
+            .collect();
+    }
+
+    /// Remove every rule.  Returns how many there were.
+    pub fn clear(&self) -> usize {
+        let mut rules = self.rules.lock().expect("fault rules lock");
+        let cleared = rules.len();
+        rules.clear();
+        cleared
+    }
+
+    pub fn status(&self) -> Vec<FaultStatus> {
+        self.rules.lock().expect("fault rules lock").clone()
+    }
+
+    /// Reach `point`, waiting out any delay that applies there.  Returns the
+    /// fault that the caller is to inject, if any, which is never a delay.
+    pub fn inject(&self, point: FaultPoint) -> Option<FaultKind> {
+        let mut delay_ms = 0;
+        let mut fault = None;
+
+        {
+            let mut rules = self.rules.lock().expect("fault rules lock");
+            for st in rules.iter_mut().filter(|st| st.rule.point == point) {
+                st.hits += 1;
+
+                if st.hits % st.rule.every.max(1) != 0
+                    || st.rule.times.map_or(false, |t| st.injected >= t)
+                {
+                    continue;
+                }
+
+                match st.rule.fault {
+                    FaultKind::Delay => delay_ms += st.rule.delay_ms,
+                    _ if fault.is_some() => continue,
+                    other => fault = Some(other),
+                }
+                st.injected += 1;
+            }
+        }
+
+        if delay_ms > 0 {
+            warn!("Injecting a {}ms delay at {}", delay_ms, point);
+            thread::sleep(Duration::from_millis(delay_ms));
+        }
+
+        if let Some(f) = fault {
+            warn!("Injecting a {} fault at {}", f, point);
+        }
+
+        fault
+    }
+}
+
+lazy_static! {
+    static ref SHARED: Arc<FaultInjector> = Arc::new(FaultInjector::new());
+}
+
+/// Whether this build injects faults.
+pub fn enabled() -> bool {
+    cfg!(feature = "faults")
+}
+
+/// The injector shared by everything in this process.
+pub fn shared() -> Arc<FaultInjector> {
+    Arc::clone(&SHARED)
+}
+
+/// Reach `point` with the shared injector, if this build injects faults.
+/// See FaultInjector::inject().
+#[cfg(feature = "faults")]
+pub fn inject(point: FaultPoint) -> Option<FaultKind> {
+    SHARED.inject(point)
+}
+
+#[cfg(not(feature = "faults"))]
+#[inline]
+pub fn inject(_point: FaultPoint) -> Option<FaultKind> {
+    None
+}
+
+/// Whether an error is to be returned at `point` in place of whatever would
+/// have been done there.
+pub fn failed(point: FaultPoint) -> bool {
+    inject(point) == Some(FaultKind::Error)
+}
+
+/// The `answer` that reached `point`, unless it is to be dropped, in which
+/// case it is an error, as though it never came.
+pub fn answer<T>(point: FaultPoint, answer: T) -> Result<T, Error> {
+    match inject(point) {
+        Some(FaultKind::Drop) => {
+            let msg =
+                format!("Answer dropped by a fault injected at {}", point);
+            Err(
+                InternalError::new(Some(InternalErrorCode::FaultInjected), msg)
+                    .into(),
+            )
+        }
+        _ => Ok(answer),
+    }
+}
+
+/// Read the rules from REBALANCER_FAULTS, if it is set.  The rules are
+/// ignored if any of them are not valid.
+pub fn load_env() {
+    let text = match env::var(FAULTS_ENV) {
+        Ok(t) => t,
+        Err(_) => return,
+    };
+
+    if !enabled() {
+        warn!("Ignoring {}: this build does not inject faults", FAULTS_ENV);
+        return;
+    }
+
+    match parse_rules(&text) {
+        Ok(rules) => {
+            warn!("Injecting faults from {}: {:?}", FAULTS_ENV, rules);
+            shared().set(rules);
+        }
+        Err(e) => error!("Ignoring {}: {}", FAULTS_ENV, e),
+    }
+}
+
+// Change a byte of the file at `path`, or add one if it is empty.
+fn corrupt_file(path: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut byte = [0u8; 1];
+
+    if file.read(&mut byte)? == 1 {
+        file.seek(SeekFrom::Start(0))?;
+    }
+    file.write_all(&[!byte[0]])
+}
+
+/// A transfer that injects the faults of agent_download in to the downloads
+/// of another.
+pub struct FaultyTransfer {
+    inner: Arc<dyn Transfer>,
+    injector: Arc<FaultInjector>,
+}
+
+impl FaultyTransfer {
+    pub fn new(
+        inner: Arc<dyn Transfer>,
+        injector: Arc<FaultInjector>,
+    ) -> FaultyTransfer {
+        FaultyTransfer { inner, injector }
+    }
+}
+
+impl Transfer for FaultyTransfer {
+    fn fetch(
+        &self,
+        uri: &str,
+        path: &str,
+        encodings: &[ContentEncoding],
+        autopsy: &mut TaskAutopsy,
+    ) -> Result<u64, ObjectSkippedReason> {
+        match self.injector.inject(FaultPoint::AgentDownload) {
+            Some(FaultKind::Drop) => Err(ObjectSkippedReason::NetworkError),
+            Some(FaultKind::Corrupt) => {
+                let bytes = self.inner.fetch(uri, path, encodings, autopsy)?;
+                corrupt_file(path).map_err(|e| {
+                    error!("Could not corrupt {}: {}", path, e);
+                    ObjectSkippedReason::AgentFSError
+                })?;
+                Ok(bytes)
+            }
+            _ => self.inner.fetch(uri, path, encodings, autopsy),
+        }
+    }
+}
+
+/// `transfers`, with the faults of agent_download injected in to them if
+/// this build injects faults.
+pub fn transfers(transfers: Vec<Arc<dyn Transfer>>) -> Vec<Arc<dyn Transfer>> {
+    if !enabled() {
+        return transfers;
+    }
+
+    transfers
+        .into_iter()
+        .map(|tr| {
+            Arc::new(FaultyTransfer::new(tr, shared())) as Arc<dyn Transfer>
+        })
+        .collect()
+}
+
+fn status_response(state: &State) -> Response<Body> {
+    create_response(
+        state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec(&shared().status()).expect("serialized faults"),
+    )
+}
+
+/// GET, PUT and DELETE /faults, on the manager and the agent alike.  These
+/// are only routed in builds that inject faults.
+pub fn faults_handler(mut state: State) -> Box<HandlerFuture> {
+    let method = Method::borrow_from(&state).clone();
+
+    let f = Body::take_from(&mut state)
+        .concat2()
+        .then(move |full_body| {
+            let body = match full_body {
+                Ok(b) => b,
+                Err(e) => return future::err((state, e.into_handler_error())),
+            };
+
+            let res = match method {
+                Method::GET => status_response(&state),
+                Method::PUT => {
+                    let rules = std::str::from_utf8(&body)
+                        .map_err(|e| e.to_string())
+                        .and_then(parse_rules);
+
+                    match rules {
+                        Ok(rules) => {
+                            warn!("Injecting faults: {:?}", rules);
+                            shared().set(rules);
+                            status_response(&state)
+                        }
+                        Err(e) => {
+                            warn!("{}", e);
+                            let body = ApiError::new(
+                                RebalancerError::InvalidRequest,
+                                e,
+                            );
+                            create_response(
+                                &state,
+                                StatusCode::UNPROCESSABLE_ENTITY,
+                                mime::APPLICATION_JSON,
+                                serde_json::to_vec(&body)
+                                    .expect("serialized API error"),
+                            )
+                        }
+                    }
+                }
+                Method::DELETE => {
+                    let cleared = shared().clear();
+                    info!("Removed {} fault rules", cleared);
+                    status_response(&state)
+                }
+                _ => create_empty_response(
+                    &state,
+                    StatusCode::METHOD_NOT_ALLOWED,
+                ),
+            };
+
+            future::ok((state, res))
+        });
+
+    Box::new(f)
+}
@@ -24,5 +24,17 @@ pub mod util;
 
 pub mod agent_test_util;
 pub mod common;
+pub mod config_schema;
 pub mod error;
+pub mod faults;
 pub mod libagent;
+pub mod migrations;
+pub mod readiness;
+pub mod reaper;
+pub mod registration;
+pub mod retry;
+pub mod sampler;
+pub mod scheduler;
+pub mod scrubber;
+pub mod throttle;
+pub mod transfer;
@@ -9,34 +9,59 @@
  */
 use std::cmp::min;
 use std::collections::{HashMap, HashSet};
-use std::ffi::{OsStr, OsString};
+use std::ffi::{CString, OsStr, OsString};
 use std::fs;
 use std::fs::File;
+use std::net::IpAddr;
 use std::path::Path;
+use std::process::Command;
 use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 use thread_id;
 
 use futures::future;
 use futures::future::*;
 use futures::stream::*;
 
+use gotham::handler::assets::FileHandler;
 use gotham::handler::{Handler, HandlerFuture, IntoHandlerError, NewHandler};
 use gotham::helpers::http::response::{create_empty_response, create_response};
 use gotham::router::{builder::*, Router};
 use gotham::state::{FromState, State};
 use gotham_derive::{StateData, StaticResponseExtender};
 
-use hyper::{Body, Chunk, Method};
+use hyper::{Body, Chunk, Method, Response};
 use joyent_rust_utils::file::calculate_md5;
 use libmanta::moray::MantaObjectShark;
-
-use crate::common::{AssignmentPayload, ObjectSkippedReason, Task, TaskStatus};
+use prometheus::{
+    opts, register_counter, register_counter_vec, register_gauge,
+    register_histogram, register_histogram_vec,
+};
+
+use crate::common::{
+    object_generation, AssignmentPayload, ContentEncoding, DownloadAttempts,
+    ObjectSkippedReason, Task, TaskAction, TaskAutopsy, TaskOrder, TaskStatus,
+};
+use crate::config_schema::{self, ConfigCheck, ConfigSchema};
+use crate::error::RebalancerError;
+use crate::faults::{self, FaultPoint};
 use crate::metrics::{self, *};
-
-use reqwest::{Client, StatusCode};
+use crate::migrations::{self, add_column, Migration};
+use crate::readiness;
+use crate::reaper::{ConfigReaper, Reaper, REAPED_BYTES, REAPED_COUNT};
+use crate::registration::{AgentCapabilities, ConfigRegistration, Registrar};
+use crate::retry::ConfigRetry;
+use crate::sampler::{ConfigSampler, Sampler, SAMPLE_VERIFY_COUNT};
+use crate::scheduler::{Claim, TaskBoard};
+use crate::scrubber::{ConfigScrubber, Scrubber, SCRUB_COUNT};
+use crate::throttle::CpuThrottle;
+use crate::transfer::{self, ConfigTransfer, Transfer};
+
+use reqwest::StatusCode;
 use rusqlite;
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
 use threadpool::ThreadPool;
 use uuid::Uuid;
 use walkdir::WalkDir;
@@ -46,14 +71,220 @@ type Assignments = HashMap<String, Arc<RwLock<Assignment>>>;
 static REBALANCER_SCHEDULED_DIR: &str = "/var/tmp/rebalancer/scheduled";
 static REBALANCER_FINISHED_DIR: &str = "/var/tmp/rebalancer/completed";
 static REBALANCER_TEMP_DIR: &str = "/manta/rebalancer";
-
-#[derive(Clone, Default, Deserialize)]
+static REBALANCER_SCRUB_LEDGER: &str = "/var/tmp/rebalancer/scrub.db";
+static ZFS: &str = "/usr/sbin/zfs";
+
+// The port that agents listen on unless they are configured otherwise, which
+// is also the one that the manager and other agents reach them at.
+static AGENT_PORT: u16 = 7878;
+
+// Metrics that are exclusively used by the rebalancer agent.  These are
+// registered alongside the common metrics when the metrics server is started.
+pub static DOWNLOAD_TIME: &str = "download_time";
+pub static VERIFY_TIME: &str = "verify_time";
+pub static VERIFY_QUEUE_DEPTH: &str = "verify_queue_depth";
+pub static DOWNLOAD_RETRY_COUNT: &str = "download_retry_count";
+pub static SLOW_TASK_COUNT: &str = "slow_task_count";
+pub static TASK_RECEIVED_COUNT: &str = "task_received_count";
+pub static TASK_COMPLETE_COUNT: &str = "task_complete_count";
+pub static TASK_FAILED_COUNT: &str = "task_failed_count";
+pub static DOWNLOAD_BYTES: &str = "download_bytes";
+pub static TRANSFER_BYTES: &str = "transfer_bytes";
+pub static ACTIVE_ASSIGNMENTS: &str = "active_assignments";
+pub static STAGING_BYTES: &str = "staging_bytes";
+
+// How often the space taken up in the staging directory is measured.
+static STAGING_BYTES_INTERVAL: Duration = Duration::from_secs(10);
+
+// Every key that the agent understands.  This must be kept in sync with the
+// AgentConfig, ConfigServer, ConfigMetrics, ConfigRetry, ConfigSampler,
+// ConfigTransfer, ConfigReaper and ConfigScrubber structures.
+static CONFIG_SCHEMA: ConfigSchema = ConfigSchema {
+    known: &[
+        "server",
+        "server.host",
+        "server.port",
+        "server.workers",
+        "server.workers_per_assignment",
+        "server.max_workers_per_assignment",
+        "server.verify_workers_per_assignment",
+        "server.verify_queue_depth",
+        "server.max_cpu_percent",
+        "server.min_staging_free_mb",
+        "server.zfs_quota_aware",
+        "server.space_headroom_percent",
+        "server.slow_task_secs",
+        "server.task_order",
+        "server.max_tasks_per_assignment",
+        "metrics",
+        "metrics.host",
+        "metrics.port",
+        "metrics.datacenter",
+        "metrics.service",
+        "metrics.server",
+        "metrics.mode",
+        "metrics.push_url",
+        "metrics.statsd_address",
+        "metrics.push_interval_secs",
+        "retry",
+        "retry.max_attempts",
+        "retry.initial_backoff_ms",
+        "retry.max_backoff_ms",
+        "sampler",
+        "sampler.sample_percent",
+        "sampler.delay_secs",
+        "sampler.range_bytes",
+        "sampler.max_pending",
+        "transfer",
+        "transfer.backend",
+        "transfer.connections_per_source",
+        "transfer.pipeline_depth",
+        "transfer.timeout_secs",
+        "reaper",
+        "reaper.interval_secs",
+        "reaper.retention_secs",
+        "reaper.max_age_secs",
+        "reaper.stale_download_secs",
+        "registration",
+        "registration.manager_url",
+        "registration.storage_id",
+        "registration.heartbeat_secs",
+        "scrubber",
+        "scrubber.interval_secs",
+        "scrubber.max_iops",
+        "scrubber.read_bytes",
+    ],
+    deprecated: &[],
+};
+
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct AgentConfig {
     pub server: ConfigServer,
     pub metrics: ConfigMetrics,
+    #[serde(default)]
+    pub retry: ConfigRetry,
+    #[serde(default)]
+    pub sampler: ConfigSampler,
+    #[serde(default)]
+    pub transfer: ConfigTransfer,
+    #[serde(default)]
+    pub reaper: ConfigReaper,
+    #[serde(default)]
+    pub registration: ConfigRegistration,
+    #[serde(default)]
+    pub scrubber: ConfigScrubber,
+}
+
+impl AgentConfig {
+    /// The configuration as it is actually in effect, after defaults have
+    /// been applied, with any secrets redacted.
+    pub fn effective(&self) -> Value {
+        let mut value = serde_json::to_value(self).unwrap_or(Value::Null);
+        config_schema::redact(&mut value);
+        value
+    }
+
+    /// Check the values of the configuration against each other and against
+    /// this zone, returning a message for each one that is wrong.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut check = ConfigCheck::default();
+        let server = &self.server;
+
+        check.ensure(
+            server.host.parse::<IpAddr>().is_ok(),
+            "server.host",
+            &format!("'{}' is not an IP address", server.host),
+        );
+        check.port("server.port", server.port);
+        self.metrics.check(&mut check);
+        check.ensure(
+            self.metrics.mode != MetricsMode::Http
+                || self.metrics.port != server.port,
+            "metrics.port",
+            "must not be the same as server.port",
+        );
+
+        for (key, value) in &[
+            ("server.workers", server.workers),
+            (
+                "server.workers_per_assignment",
+                server.workers_per_assignment,
+            ),
+            (
+                "server.verify_workers_per_assignment",
+                server.verify_workers_per_assignment,
+            ),
+            ("server.verify_queue_depth", server.verify_queue_depth),
+            (
+                "server.max_workers_per_assignment",
+                server.max_workers_per_assignment.unwrap_or(1),
+            ),
+            (
+                "server.max_tasks_per_assignment",
+                server.max_tasks_per_assignment.unwrap_or(1),
+            ),
+            (
+                "transfer.connections_per_source",
+                self.transfer.connections_per_source,
+            ),
+            ("transfer.pipeline_depth", self.transfer.pipeline_depth),
+        ] {
+            check.ensure(*value >= 1, key, "must be at least 1");
+        }
+        if let Some(percent) = server.max_cpu_percent {
+            check.percentage("server.max_cpu_percent", u32::from(percent));
+        }
+
+        check.ensure(
+            self.retry.max_attempts >= 1,
+            "retry.max_attempts",
+            "must be at least 1",
+        );
+        check.ensure(
+            self.sampler.sample_percent >= 0.0
+                && self.sampler.sample_percent <= 100.0,
+            "sampler.sample_percent",
+            "must be a percentage from 0 to 100",
+        );
+        check.ensure(
+            self.reaper.stale_download_secs >= 60,
+            "reaper.stale_download_secs",
+            "must be at least 60",
+        );
+        check.ensure(
+            self.scrubber.read_bytes >= 1,
+            "scrubber.read_bytes",
+            "must be at least 1",
+        );
+
+        let registration = &self.registration;
+        check.ensure(
+            registration.manager_url.is_some()
+                == registration.storage_id.is_some(),
+            "registration.storage_id",
+            "must be set if and only if registration.manager_url is",
+        );
+        check.ensure(
+            registration.heartbeat_secs >= 1,
+            "registration.heartbeat_secs",
+            "must be at least 1",
+        );
+
+        // The directories that the agent keeps its assignments and downloads
+        // in are not configurable, but it cannot run without them.
+        for dir in &[
+            REBALANCER_SCHEDULED_DIR,
+            REBALANCER_FINISHED_DIR,
+            REBALANCER_TEMP_DIR,
+        ] {
+            check.writable_dir(dir, dir);
+        }
+
+        check.finish()
+    }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ConfigServer {
     // The IP address on which the agent should listen for incoming connections.
     pub host: String,
@@ -61,17 +292,92 @@ pub struct ConfigServer {
     pub port: u16,
     // Maximum number of concurrent assignments.
     pub workers: usize,
-    // Maximum number of worker threads per assignment.
+    // The number of threads that download objects, for each assignment that
+    // may be processed concurrently.  The threads are shared between all of
+    // the assignments being processed.
     pub workers_per_assignment: usize,
+    // Maximum number of download threads that may work on a single
+    // assignment at once.  If this is not set, the threads that other
+    // assignments leave idle may all work on one assignment.
+    #[serde(default)]
+    pub max_workers_per_assignment: Option<usize>,
+    // Maximum number of worker threads per assignment that verify the
+    // checksums of downloaded objects.
+    #[serde(default = "default_verify_workers_per_assignment")]
+    pub verify_workers_per_assignment: usize,
+    // Maximum number of downloaded objects per assignment that may be waiting
+    // for verification.  Once it is reached, download workers block until a
+    // verify worker catches up.
+    #[serde(default = "default_verify_queue_depth")]
+    pub verify_queue_depth: usize,
+    // Optional ceiling (as a percentage of the total CPU capacity of the
+    // machine) on the CPU that the agent may consume while processing
+    // assignments.
+    #[serde(default)]
+    pub max_cpu_percent: Option<u8>,
+    // The free space (in MB) below which the staging area that objects are
+    // downloaded in to is considered too full for the agent to be healthy.
+    #[serde(default = "default_min_staging_free_mb")]
+    pub min_staging_free_mb: u64,
+    // Also ask ZFS how much space is available to the dataset that the
+    // staging area is on.  statvfs does not take the quotas and reservations
+    // of a nested dataset's ancestors into account, so it can report far
+    // more space than can actually be written.
+    #[serde(default)]
+    pub zfs_quota_aware: bool,
+    // The space that an assignment needs in order to be accepted, as a
+    // percentage over the total size of its objects.
+    #[serde(default = "default_space_headroom_percent")]
+    pub space_headroom_percent: u64,
+    // Optional number of seconds beyond which a task is considered slow, and
+    // the context of it is reported along with the assignment.
+    #[serde(default)]
+    pub slow_task_secs: Option<u64>,
+    // The order in which the tasks of an assignment are processed, unless
+    // the manager asks for another.
+    #[serde(default)]
+    pub task_order: TaskOrder,
+    // Optional limit on the number of tasks in an assignment.  A larger
+    // assignment is refused, and the limit is reported to the manager when
+    // the agent registers with it.
+    #[serde(default)]
+    pub max_tasks_per_assignment: Option<usize>,
+}
+
+fn default_verify_workers_per_assignment() -> usize {
+    1
+}
+
+fn default_verify_queue_depth() -> usize {
+    16
+}
+
+fn default_min_staging_free_mb() -> u64 {
+    1024
+}
+
+fn default_space_headroom_percent() -> u64 {
+    10
 }
 
 impl Default for ConfigServer {
     fn default() -> Self {
         Self {
             host: "0.0.0.0".into(),
-            port: 7878,
+            port: AGENT_PORT,
             workers: 1,
             workers_per_assignment: 1,
+            max_workers_per_assignment: None,
+            verify_workers_per_assignment:
+                default_verify_workers_per_assignment(),
+            verify_queue_depth: default_verify_queue_depth(),
+            max_cpu_percent: None,
+            min_staging_free_mb: default_min_staging_free_mb(),
+            zfs_quota_aware: false,
+            space_headroom_percent: default_space_headroom_percent(),
+            slow_task_secs: None,
+            task_order: TaskOrder::default(),
+            max_tasks_per_assignment: None,
         }
     }
 }
@@ -89,6 +395,22 @@ pub struct AgentAssignmentStats {
     pub failed: usize,
     pub complete: usize,
     pub total: usize,
+
+    // The agent's estimate of the seconds until a running assignment
+    // completes, given with each response to a request for the assignment.
+    // This is a hint for clients as to when the assignment is worth asking
+    // about again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eta_secs: Option<u64>,
+
+    // The tasks that took longer than the agent's slow task threshold,
+    // whether they failed or not, each with its autopsy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub slow_tasks: Vec<Task>,
+
+    // When the agent started processing the assignment.
+    #[serde(skip)]
+    started: Option<Instant>,
 }
 
 impl AgentAssignmentStats {
@@ -98,6 +420,28 @@ impl AgentAssignmentStats {
             failed: 0,
             complete: 0,
             total,
+            eta_secs: None,
+            slow_tasks: vec![],
+            started: None,
+        }
+    }
+
+    /// Estimate, from the rate at which its tasks have been completed so
+    /// far, how long a running assignment has left.
+    pub fn estimate_remaining(&self) -> Option<Duration> {
+        match (&self.state, self.started) {
+            (AgentAssignmentState::Running, Some(started)) => {
+                if self.complete == 0 {
+                    return None;
+                }
+                let remaining = self.total.saturating_sub(self.complete);
+                Some(
+                    started
+                        .elapsed()
+                        .mul_f64(remaining as f64 / self.complete as f64),
+                )
+            }
+            _ => None,
         }
     }
 }
@@ -113,42 +457,203 @@ struct GetAssignmentParams {
     uuid: String,
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct ObjectParams {
+    owner: String,
+    object: String,
+}
+
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct HeldAssignmentsParams {
+    since: Option<String>,
+}
+
+/// The response to GET /assignments: the assignments that the agent has
+/// accepted and not yet finished, which it works through even if it is
+/// restarted.  `boot_id` identifies this run of the agent, and `restarted`
+/// is false only if the request gave it as `since`, i.e. the agent has not
+/// been restarted since the client last asked.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentAssignmentList {
+    pub boot_id: String,
+    pub restarted: bool,
+    pub assignments: Vec<Assignment>,
+}
+
+/// Why the agent refused an assignment.  This is given as the body of the
+/// response to the post of the assignment.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "error")]
+pub enum AssignmentRejection {
+    // There is not enough free space for the objects in the assignment
+    // (response status 507).
+    InsufficientSpace { required_mb: u64, available_mb: u64 },
+}
+
 #[derive(Clone)]
 pub struct Agent {
     assignments: Arc<Mutex<Assignments>>,
     quiescing: Arc<Mutex<HashSet<String>>>,
+    // Assignments that a client has asked us to abandon.  Workers check this
+    // before picking up each task.
+    cancelled: Arc<Mutex<HashSet<String>>>,
+    // The number of bytes set aside for each assignment that has been
+    // accepted but not finished yet.
+    reservations: Arc<Mutex<HashMap<String, u64>>>,
     tx: Arc<Mutex<mpsc::Sender<String>>>,
     metrics: Arc<Mutex<Option<MetricsMap>>>,
+    space_headroom_percent: u64,
+    zfs_quota_aware: bool,
+    task_order: TaskOrder,
+    max_tasks: Option<usize>,
+    // A new id for each run of the agent, so that clients can tell that it
+    // has been restarted.
+    boot_id: String,
+    started: Instant,
+    reaper: Arc<Reaper>,
 }
 
 impl Agent {
     pub fn new(
         tx: Arc<Mutex<mpsc::Sender<String>>>,
         metrics: Arc<Mutex<Option<MetricsMap>>>,
+        space_headroom_percent: u64,
+        zfs_quota_aware: bool,
+        task_order: TaskOrder,
+        max_tasks: Option<usize>,
+        reaper: Arc<Reaper>,
     ) -> Agent {
         let assignments = Arc::new(Mutex::new(Assignments::new()));
         let quiescing = Arc::new(Mutex::new(HashSet::new()));
+        let cancelled = Arc::new(Mutex::new(HashSet::new()));
+        let reservations = Arc::new(Mutex::new(HashMap::new()));
         Agent {
             assignments,
             quiescing,
+            cancelled,
+            reservations,
             tx,
             metrics,
+            space_headroom_percent,
+            zfs_quota_aware,
+            task_order,
+            max_tasks,
+            boot_id: Uuid::new_v4().to_string(),
+            started: Instant::now(),
+            reaper,
         }
     }
 
-    fn read_config<F: AsRef<OsStr> + ?Sized>(f: &F) -> AgentConfig {
-        let s = match fs::read(Path::new(&f)) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Failed to read config file: {}", e);
-                std::process::exit(1);
+    // Space, in bytes, still to be taken up by the assignments that have been
+    // accepted.  Objects that have already been processed either take up
+    // space of their own or have failed, so the reservation for a running
+    // assignment shrinks as its tasks complete.
+    fn reserved_bytes(&self, reservations: &HashMap<String, u64>) -> u64 {
+        let assignments = self.assignments.lock().unwrap();
+
+        reservations
+            .iter()
+            .map(|(uuid, bytes)| match assignments.get(uuid) {
+                Some(a) => {
+                    let stats = &a.read().unwrap().stats;
+                    if stats.total == 0 {
+                        return 0;
+                    }
+                    let remaining = stats.total.saturating_sub(stats.complete);
+                    (u128::from(*bytes) * remaining as u128
+                        / stats.total as u128) as u64
+                }
+                None => *bytes,
+            })
+            .sum()
+    }
+
+    // Set aside space for an assignment of `bytes` bytes, unless the staging
+    // area does not have enough free space for it (plus headroom) over and
+    // above what has already been set aside for others.  If the free space
+    // can not be determined, the assignment is accepted.
+    fn reserve_space(
+        &self,
+        uuid: &str,
+        bytes: u64,
+    ) -> Result<(), AssignmentRejection> {
+        let mut reservations = self.reservations.lock().unwrap();
+
+        if let Some(free_mb) =
+            free_space_mb(REBALANCER_TEMP_DIR, self.zfs_quota_aware)
+        {
+            let mb = 1024 * 1024;
+            let reserved_mb = self.reserved_bytes(&reservations) / mb;
+            let available_mb = free_mb.saturating_sub(reserved_mb);
+            let required = u128::from(bytes)
+                * u128::from(100 + self.space_headroom_percent)
+                / 100;
+            let required_mb =
+                ((required + u128::from(mb) - 1) / u128::from(mb)) as u64;
+
+            if required_mb > available_mb {
+                return Err(AssignmentRejection::InsufficientSpace {
+                    required_mb,
+                    available_mb,
+                });
             }
-        };
+        }
+
+        reservations.insert(uuid.to_string(), bytes);
+        Ok(())
+    }
 
-        toml::from_slice(&s).unwrap_or_else(|e| {
-            eprintln!("Failed to parse config file: {}", e);
+    // Returns the configuration in `f`, along with a notice for each of its
+    // keys which is unknown or deprecated.
+    fn parse_config<F: AsRef<OsStr> + ?Sized>(
+        f: &F,
+    ) -> Result<(AgentConfig, Vec<String>), String> {
+        let s = fs::read(Path::new(&f))
+            .map_err(|e| format!("Failed to read config file: {}", e))?;
+
+        // Go through the generic form of the configuration first so that
+        // keys which are unknown or deprecated can be reported.
+        let mut raw = toml::from_slice::<toml::Value>(&s)
+            .map_err(|e| e.to_string())
+            .and_then(|t| serde_json::to_value(t).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to parse config file: {}", e))?;
+
+        let notices = CONFIG_SCHEMA.normalize(&mut raw);
+
+        serde_json::from_value(raw)
+            .map(|config| (config, notices))
+            .map_err(|e| format!("Failed to parse config file: {}", e))
+    }
+
+    // Nothing is started until the whole configuration is known to be good.
+    fn read_config<F: AsRef<OsStr> + ?Sized>(f: &F) -> AgentConfig {
+        let (config, notices) = Agent::parse_config(f).unwrap_or_else(|e| {
+            eprintln!("{}", e);
             std::process::exit(1);
-        })
+        });
+
+        for notice in notices {
+            warn!("{}", notice);
+        }
+
+        if let Err(errors) = config.validate() {
+            eprintln!("Invalid configuration:");
+            for e in errors.iter() {
+                eprintln!("  {}", e);
+            }
+            std::process::exit(1);
+        }
+
+        config
+    }
+
+    /// Check the configuration file at `path` without starting the agent,
+    /// returning the notices about it if it is valid, or whatever is wrong
+    /// with it if it is not.
+    pub fn check_config(path: &str) -> Result<Vec<String>, Vec<String>> {
+        let (config, notices) =
+            Agent::parse_config(path).map_err(|e| vec![e])?;
+        config.validate().map(|()| notices)
     }
 
     pub fn run(cfg_path: Option<&str>) {
@@ -156,7 +661,20 @@ impl Agent {
             Some(c) => Agent::read_config(c),
             None => AgentConfig::default(),
         };
+        info!("Effective configuration: {}", config.effective());
+
         let addr = format!("{}:{}", config.server.host, config.server.port);
+        let mut listen_addrs = vec![addr.clone()];
+
+        // There is only a metrics server to wait for in http mode.
+        if config.metrics.mode == MetricsMode::Http {
+            listen_addrs.push(format!(
+                "{}:{}",
+                config.metrics.host, config.metrics.port
+            ));
+        }
+        let _readiness_handle = readiness::notify_when_listening(listen_addrs);
+
         info!("Listening for requests at {}", addr);
         gotham::start(addr, router(process_task, Some(config)));
     }
@@ -199,6 +717,11 @@ pub struct Assignment {
 
     #[serde(skip_serializing, skip_deserializing, default)]
     pub tasks: Vec<Task>,
+
+    // The content encodings that the manager asked for the assignment's
+    // objects to be downloaded in, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encodings: Vec<ContentEncoding>,
 }
 
 impl Assignment {
@@ -207,6 +730,7 @@ impl Assignment {
             uuid: uuid.to_string(),
             stats: AgentAssignmentStats::new(v.len()),
             tasks: v,
+            encodings: vec![],
         }
     }
 }
@@ -218,6 +742,7 @@ impl Assignment {
 #[derive(Clone)]
 pub enum AssignmentOpErr {
     DoesNotExist,
+    AlreadyComplete,
     InternalError(String),
 }
 
@@ -225,6 +750,7 @@ impl AssignmentOpErr {
     pub fn to_http_status_code(&self) -> StatusCode {
         match self {
             AssignmentOpErr::DoesNotExist => StatusCode::NOT_FOUND,
+            AssignmentOpErr::AlreadyComplete => StatusCode::CONFLICT,
             AssignmentOpErr::InternalError(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
@@ -286,64 +812,144 @@ fn discover_saved_assignments(agent: &Agent) {
     }
 }
 
+// The baseline tables of a saved assignment, as saved by agents that predate
+// task actions.
+fn create_assignment_tables(
+    conn: &rusqlite::Connection,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "create table if not exists tasks (
+        object_id text primary key not null unique,
+        owner text not null,
+        md5sum text not null,
+        datacenter text not null,
+        manta_storage_id text not null,
+        status text not null
+	)",
+        rusqlite::params![],
+    )?;
+    conn.execute(
+        "create table if not exists stats (stats text not null)",
+        rusqlite::params![],
+    )?;
+    Ok(())
+}
+
+fn add_task_actions(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(conn, "tasks", "action", "text")
+}
+
+fn add_task_generations(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(conn, "tasks", "generation", "text")
+}
+
+fn add_task_unchecked(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(conn, "tasks", "unchecked", "integer")
+}
+
+fn add_task_direct(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(conn, "tasks", "direct", "integer")
+}
+
+// The content encodings that the objects may be downloaded in, so that an
+// assignment resumed after a restart is downloaded as it would have been.
+fn create_encodings_table(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "create table if not exists encodings (encoding text not null)",
+        rusqlite::params![],
+    )?;
+    Ok(())
+}
+
+// The migrations of a saved assignment (see the migrations module).  Each of
+// these predates migrations, and does nothing where it has already been done.
+// Columns added to the tasks table are left nullable, since the tasks of an
+// assignment saved without them have nothing in them: an assignment saved by
+// an agent that predates task actions only has copies, and one saved before
+// there were generations, unchecked tasks or direct tasks has none of them.
+static ASSIGNMENT_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "tasks and stats tables",
+        up: create_assignment_tables,
+    },
+    Migration {
+        version: 2,
+        description: "task actions",
+        up: add_task_actions,
+    },
+    Migration {
+        version: 3,
+        description: "task generations",
+        up: add_task_generations,
+    },
+    Migration {
+        version: 4,
+        description: "unchecked tasks",
+        up: add_task_unchecked,
+    },
+    Migration {
+        version: 5,
+        description: "direct tasks",
+        up: add_task_direct,
+    },
+    Migration {
+        version: 6,
+        description: "content encodings",
+        up: create_encodings_table,
+    },
+];
+
+// Open the saved assignment at `path`, bringing it up to date with this
+// agent.
+fn assignment_open(path: &str) -> Result<rusqlite::Connection, String> {
+    let mut conn = rusqlite::Connection::open(path)
+        .map_err(|e| format!("DB error opening {}: {}", path, e))?;
+
+    migrations::migrate(&mut conn, ASSIGNMENT_MIGRATIONS)
+        .map_err(|e| format!("DB error migrating {}: {}", path, e))?;
+    Ok(conn)
+}
+
 fn assignment_save(
     uuid: &str,
     path: &str,
     assignment: Arc<RwLock<Assignment>>,
 ) {
-    let mut conn =
-        match rusqlite::Connection::open(format!("{}/{}", path, uuid)) {
-            Ok(conn) => conn,
-            Err(e) => panic!("DB error opening {}/{}: {}", path, uuid, e),
-        };
+    let mut conn = match assignment_open(&format!("{}/{}", path, uuid)) {
+        Ok(conn) => conn,
+        Err(e) => panic!("{}", e),
+    };
 
     let assn = assignment.read().unwrap();
     let tasklist = &assn.tasks;
     let stats = &assn.stats;
+    let encodings = &assn.encodings;
 
     // Create a transaction.  All database operations within this function
-    // will be part of this transaction.  This includes the creation of both
-    // the `tasks' and the `stats' table and the insertion of data in to each.
+    // will be part of this transaction.  This includes the insertion of data
+    // in to each of the tables, which were created when the database was
+    // opened.
     let transaction = conn.transaction().unwrap();
 
-    // Create the table for our tasks.
-    match transaction.execute(
-        "create table if not exists tasks (
-        object_id text primary key not null unique,
-        owner text not null,
-        md5sum text not null,
-        datacenter text not null,
-        manta_storage_id text not null,
-        status text not null
-	)",
-        rusqlite::params![],
-    ) {
-        Ok(_) => (),
-        Err(e) => panic!("Database creation error: {}", e),
-    }
-
-    // Create the table for our stats.
-    match transaction.execute(
-        "create table if not exists stats (stats text not null)",
-        rusqlite::params![],
-    ) {
-        Ok(_) => (),
-        Err(e) => panic!("Database creation error: {}", e),
-    }
-
     // Populate the task table with the tasks in this assignment.
     for task in tasklist.iter() {
         match transaction.execute(
             "INSERT INTO tasks
-            (object_id, owner, md5sum, datacenter, manta_storage_id, status)
-            values (?1, ?2, ?3, ?4, ?5, ?6)",
+            (object_id, owner, md5sum, datacenter, manta_storage_id, status,
+            action, generation, unchecked, direct)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
             rusqlite::params![
                 task.object_id,
                 task.owner,
                 task.md5sum,
                 task.source.datacenter,
                 task.source.manta_storage_id,
-                serde_json::to_vec(&task.status).unwrap()
+                serde_json::to_vec(&task.status).unwrap(),
+                serde_json::to_vec(&task.action).unwrap(),
+                task.generation,
+                task.unchecked,
+                task.direct
             ],
         ) {
             Ok(_) => (),
@@ -362,6 +968,23 @@ fn assignment_save(
         Err(e) => panic!("Task insertion error on assignment {}: {}", &uuid, e),
     };
 
+    // And the encodings table with the content encodings that the objects
+    // may be downloaded in.
+    for encoding in encodings.iter() {
+        match transaction.execute(
+            "INSERT INTO encodings values (?1)",
+            rusqlite::params![encoding.to_string()],
+        ) {
+            Ok(_) => (),
+            Err(e) => {
+                panic!(
+                    "Encoding insertion error on assignment {}: {}",
+                    &uuid, e
+                )
+            }
+        };
+    }
+
     // Finally, kick off the transaction as a whole.  Up until this point,
     // nothing has been committed to the database.  If this does not complete
     // successfully, we likely have a systemic problem that retrying or
@@ -394,14 +1017,15 @@ fn assignment_recall<S: Into<String>>(
         .into_string()
         .unwrap();
 
-    let conn = match rusqlite::Connection::open(path) {
-        Ok(conn) => conn,
-        Err(e) => return Err(format!("DB error {}", e)),
-    };
+    let conn = assignment_open(&path)?;
 
+    // The tasks of an assignment saved by an agent that predates one of the
+    // columns of the tasks table have nothing in it (see
+    // ASSIGNMENT_MIGRATIONS), and a task with no action is a copy.
     let mut stmt = match conn.prepare(
         "SELECT object_id, owner, md5sum,
-	   datacenter, manta_storage_id, status FROM tasks",
+	   datacenter, manta_storage_id, status, action, generation, unchecked,
+	   direct FROM tasks ORDER BY rowid",
     ) {
         Ok(s) => s,
         Err(e) => return Err(format!("Query creation error: {}", e)),
@@ -420,12 +1044,25 @@ fn assignment_recall<S: Into<String>>(
         let s = String::from_utf8(data).unwrap();
         let status: TaskStatus = serde_json::from_str(&s).unwrap();
 
+        let action_data: Option<Vec<u8>> = row.get(6)?;
+        let action: TaskAction = action_data
+            .map(|a| serde_json::from_slice(&a).unwrap())
+            .unwrap_or_default();
+
         let t = Task {
             object_id: row.get(0)?,
             owner: row.get(1)?,
             md5sum: row.get(2)?,
             source,
             status,
+            content_length: None,
+            download: None,
+            autopsy: None,
+            action,
+            generation: row.get(7)?,
+            unchecked: row.get::<_, Option<bool>>(8)?.unwrap_or(false),
+            direct: row.get::<_, Option<bool>>(9)?.unwrap_or(false),
+            encodings: vec![],
         };
         Ok(t)
     }) {
@@ -465,6 +1102,43 @@ fn assignment_recall<S: Into<String>>(
     let mut assignment = Assignment::new(tasks, &uuid);
     assignment.stats = stats[0].clone();
 
+    // Assignments saved before there were encodings have none, and are
+    // downloaded as they are.
+    stmt = match conn.prepare("SELECT encoding FROM encodings") {
+        Ok(s) => s,
+        Err(e) => return Err(format!("Query creation error: {}", e)),
+    };
+
+    let encodings = match stmt
+        .query_map(rusqlite::params![], |row| row.get::<_, String>(0))
+    {
+        Ok(iter) => iter,
+        Err(e) => return Err(format!("Query execution error: {}", e)),
+    };
+
+    assignment.encodings = encodings
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.parse::<ContentEncoding>().ok())
+        .collect();
+
+    // The saved stats of an assignment that has not been finished are the
+    // ones that it was received with, but the outcome of each of its tasks
+    // that was finished before the agent was restarted has been journaled
+    // (see journal_task()).
+    if let AgentAssignmentState::Scheduled = assignment.stats.state {
+        let finished = assignment
+            .tasks
+            .iter()
+            .filter(|t| t.status != TaskStatus::Pending);
+
+        for t in finished {
+            assignment.stats.complete += 1;
+            if let TaskStatus::Failed(_) = t.status {
+                assignment.stats.failed += 1;
+            }
+        }
+    }
+
     Ok(Arc::new(RwLock::new(assignment)))
 }
 
@@ -524,6 +1198,9 @@ fn handle_delete_assignment_error(
         AssignmentOpErr::InternalError(s) => {
             create_response(&state, status, mime::APPLICATION_JSON, s)
         }
+        AssignmentOpErr::AlreadyComplete => {
+            create_empty_response(&state, status)
+        }
     };
     Box::new(future::ok((state, res)))
 }
@@ -552,14 +1229,45 @@ fn delete_assignment_impl(uuid: &str) -> Result<(), AssignmentOpErr> {
     }
 }
 
-// Given a client specified uuid, delete its corresponding assignment on disk.
-fn delete_assignment(mut state: State) -> Box<HandlerFuture> {
+// Mark an assignment that is either scheduled or currently running as
+// cancelled.  Any worker processing it will finish the task that it is on and
+// then stop.  Once all workers have stopped, every task that was not reached is
+// reported back as failed with a reason of AssignmentCancelled so that the
+// manager will not mistake it for having been transferred.  The set of
+// cancelled assignments is held while we look for the assignment, so that an
+// assignment finishing at the same time either is found to be finished here,
+// or has its entry removed once it is (see process_assignment()).
+fn cancel_assignment_impl(
+    agent: &Agent,
+    uuid: &str,
+) -> Result<(), AssignmentOpErr> {
+    let scheduled = format!("{}/{}", REBALANCER_SCHEDULED_DIR, &uuid);
+    let finished = format!("{}/{}", REBALANCER_FINISHED_DIR, &uuid);
+    let mut cancelled = agent.cancelled.lock().unwrap();
+
+    if Path::new(&finished).exists() {
+        return Err(AssignmentOpErr::AlreadyComplete);
+    }
+
+    if !Path::new(&scheduled).exists() {
+        return Err(AssignmentOpErr::DoesNotExist);
+    }
+
+    info!("Cancelling assignment {}.", uuid);
+    cancelled.insert(uuid.to_owned());
+    Ok(())
+}
+
+fn cancel_assignment_handler(
+    agent: Agent,
+    mut state: State,
+) -> Box<HandlerFuture> {
     let assignment_params = GetAssignmentParams::take_from(&mut state);
     let uuid = match Uuid::parse_str(&assignment_params.uuid) {
         Ok(id) => id.to_string(),
         Err(e) => {
             // Mal-formed uuid.
-            let msg = format!("Mal-formed delete request: {}", e);
+            let msg = format!("Mal-formed cancel request: {}", e);
             info!("{}", &msg);
             let res = create_response(
                 &state,
@@ -571,88 +1279,546 @@ fn delete_assignment(mut state: State) -> Box<HandlerFuture> {
         }
     };
 
-    match delete_assignment_impl(&uuid) {
-        Ok(_) => {
-            // Successful delete.
-            let res = create_empty_response(&state, StatusCode::OK);
-            Box::new(future::ok((state, res)))
-        }
-        Err(e) => handle_delete_assignment_error(state, e, &uuid),
+    if let Some(m) = agent.metrics.lock().unwrap().clone() {
+        counter_vec_inc(&m, REQUEST_COUNT, Some("cancel"));
     }
-}
 
-fn post_assignment_handler(
-    agent: Agent,
-    mut state: State,
-) -> Box<HandlerFuture> {
-    let f = Body::take_from(&mut state)
-        .concat2()
-        .then(move |full_body| match full_body {
-            Ok(valid_body) => {
-                // Ceremony for parsing the information needed to create an
-                // an assignment out of the message body.
-                let (uuid, v) = match validate_assignment(&valid_body) {
-                    Ok(uv) => uv,
-                    Err(e) => {
-                        let res = create_empty_response(
-                            &state,
-                            StatusCode::BAD_REQUEST,
-                        );
+    let res = match cancel_assignment_impl(&agent, &uuid) {
+        Ok(_) => create_empty_response(&state, StatusCode::OK),
+        Err(e) => create_empty_response(&state, e.to_http_status_code()),
+    };
 
-                        if let Some(m) = agent.metrics.lock().unwrap().clone() {
-                            counter_vec_inc(&m, ERROR_COUNT, Some(&e));
-                        }
-                        return future::ok((state, res));
-                    }
-                };
+    Box::new(future::ok((state, res)))
+}
 
-                // Ensure that an asignment with this uuid is not already
-                // currently in flight.  If there is one, do not allow this
-                // assignment to proceed.
-                if agent.assignment_exists(&uuid) {
-                    let res =
-                        create_empty_response(&state, StatusCode::CONFLICT);
+// Return the effective configuration that the agent was started with.
+#[derive(Clone)]
+struct ConfigHandler(String);
 
-                    if let Some(m) = agent.metrics.lock().unwrap().clone() {
-                        counter_vec_inc(&m, ERROR_COUNT, Some("conflict"));
-                    }
+impl Handler for ConfigHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        let res = create_response(
+            &state,
+            StatusCode::OK,
+            mime::APPLICATION_JSON,
+            self.0,
+        );
+        Box::new(future::ok((state, res)))
+    }
+}
 
-                    return future::ok((state, res));
-                }
+impl NewHandler for ConfigHandler {
+    type Instance = Self;
 
-                let assignment =
-                    Arc::new(RwLock::new(Assignment::new(v, &uuid)));
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
 
-                info!("Received assignment {}.", &uuid);
-                debug!("Received assignment: {:#?}", &assignment);
+// Report what the sampler has found (see the sampler module).
+#[derive(Clone)]
+struct SamplesHandler(Arc<Sampler>);
 
-                // Before we even process the assignment, save it to persistent
-                // storage.
-                assignment_save(&uuid, REBALANCER_SCHEDULED_DIR, assignment);
+impl Handler for SamplesHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        let res = match serde_json::to_string(&self.0.report()) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(_) => {
+                create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+        Box::new(future::ok((state, res)))
+    }
+}
 
-                // Assignment has been saved.  Remove its id from the the table.
-                agent.quiescing.lock().unwrap().remove(&uuid);
+impl NewHandler for SamplesHandler {
+    type Instance = Self;
 
-                // Create a response containing our newly initialized stats.
-                // This serves as confirmation to the client that we recieved
-                // their request correctly and are working on it.
-                let res = create_response(
-                    &state,
-                    StatusCode::OK,
-                    mime::APPLICATION_JSON,
-                    serde_json::to_vec(&uuid)
-                        .expect("serialized assignment id"),
-                );
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
 
-                // Signal the workers that there is a new assignent ready for
-                // processing.
-                assignment_signal(&agent, &uuid);
-                future::ok((state, res))
-            }
+// Report what the scrubber has found (see the scrubber module).
+#[derive(Clone)]
+struct ScrubHandler(Arc<Scrubber>);
+
+impl Handler for ScrubHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        let res = match serde_json::to_string(&self.0.report()) {
+            Ok(body) => create_response(
+                &state,
+                StatusCode::OK,
+                mime::APPLICATION_JSON,
+                body,
+            ),
+            Err(_) => {
+                create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+        Box::new(future::ok((state, res)))
+    }
+}
+
+impl NewHandler for ScrubHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+// Serve this storage node's copy of an object to an agent that is fetching
+// it directly (see Task::direct), rather than through the node's web server,
+// which may be what is failing on a storage node that is being evacuated.
+// Only objects whose owner and id are both uuids are served, which leaves out
+// the staging area.
+fn get_object(mut state: State) -> Box<HandlerFuture> {
+    let ObjectParams { owner, object } = ObjectParams::take_from(&mut state);
+
+    if Uuid::parse_str(&owner).is_err() || Uuid::parse_str(&object).is_err() {
+        let res = create_empty_response(&state, StatusCode::NOT_FOUND);
+        return Box::new(future::ok((state, res)));
+    }
+
+    FileHandler::new(manta_file_path(&owner, &object)).handle(state)
+}
+
+// Liveness check: the agent is up and answering requests.
+fn ping(state: State) -> (State, Response<Body>) {
+    let res = create_response(
+        &state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        "{\"status\":\"OK\"}",
+    );
+
+    (state, res)
+}
+
+// The health of the agent, as reported by GET /healthcheck.  The agent is
+// healthy as long as the staging area that objects are downloaded in to has
+// enough free space for it to keep processing assignments.  Its version, and
+// how long this run of it has been up, are reported for the manager's view of
+// the fleet.
+#[derive(Debug, Serialize)]
+struct AgentHealth {
+    healthy: bool,
+    staging_free_mb: Option<u64>,
+    min_staging_free_mb: u64,
+    assignments_scheduled: usize,
+    assignments_running: usize,
+    version: String,
+    boot_id: String,
+    uptime_secs: u64,
+}
+
+// The version of the agent, with the stamp of the build that it came from.
+fn agent_version() -> String {
+    let buildstamp = option_env!("STAMP").unwrap_or("no-STAMP");
+
+    format!("{} ({})", env!("CARGO_PKG_VERSION"), buildstamp)
+}
+
+// Free space, in MB, available to unprivileged users on the file system
+// containing the given path.
+fn statvfs_free_mb(path: &str) -> Option<u64> {
+    let cpath = CString::new(path).ok()?;
+    let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(cpath.as_ptr(), &mut st) } != 0 {
+        return None;
+    }
+
+    Some(st.f_bavail as u64 * st.f_frsize as u64 / (1024 * 1024))
+}
+
+// Space, in MB, available to the ZFS dataset containing the given path.  The
+// "available" property accounts for the quotas and reservations of the
+// dataset and every one of its ancestors, as well as the reservations of
+// other datasets in the pool.
+fn zfs_available_mb(path: &str) -> Option<u64> {
+    let output = Command::new(ZFS)
+        .args(&["get", "-Hp", "-o", "value", "available", path])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|bytes| bytes / (1024 * 1024))
+}
+
+// Free space, in MB, that objects can be written to at the given path.  If
+// zfs_quota_aware is set, this is the lesser of what statvfs and ZFS report.
+// Should ZFS not be able to tell us (e.g. the path is not on ZFS), statvfs is
+// used alone.
+fn free_space_mb(path: &str, zfs_quota_aware: bool) -> Option<u64> {
+    let statvfs_free = statvfs_free_mb(path);
+
+    if !zfs_quota_aware {
+        return statvfs_free;
+    }
+
+    match (statvfs_free, zfs_available_mb(path)) {
+        (Some(s), Some(z)) => Some(min(s, z)),
+        (s, None) => {
+            warn!("Could not get ZFS available space for {}", path);
+            s
+        }
+        (None, z) => z,
+    }
+}
+
+#[derive(Clone)]
+struct HealthcheckHandler {
+    agent: Agent,
+    min_staging_free_mb: u64,
+    zfs_quota_aware: bool,
+}
+
+impl HealthcheckHandler {
+    fn check(&self) -> AgentHealth {
+        let mut scheduled = 0;
+        let mut running = 0;
+
+        for assignment in self.agent.assignments.lock().unwrap().values() {
+            match assignment.read().unwrap().stats.state {
+                AgentAssignmentState::Scheduled => scheduled += 1,
+                AgentAssignmentState::Running => running += 1,
+                AgentAssignmentState::Complete(_) => (),
+            }
+        }
+
+        let staging_free_mb =
+            free_space_mb(REBALANCER_TEMP_DIR, self.zfs_quota_aware);
+
+        AgentHealth {
+            healthy: staging_free_mb
+                .map_or(false, |free| free >= self.min_staging_free_mb),
+            staging_free_mb,
+            min_staging_free_mb: self.min_staging_free_mb,
+            assignments_scheduled: scheduled,
+            assignments_running: running,
+            version: agent_version(),
+            boot_id: self.agent.boot_id.clone(),
+            uptime_secs: self.agent.started.elapsed().as_secs(),
+        }
+    }
+}
+
+impl Handler for HealthcheckHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        let health = self.check();
+
+        if !health.healthy {
+            warn!("Healthcheck failed: {:?}", health);
+        }
+
+        let code = if health.healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        let res = match serde_json::to_string(&health) {
+            Ok(body) => {
+                create_response(&state, code, mime::APPLICATION_JSON, body)
+            }
+            Err(_) => {
+                create_empty_response(&state, StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+
+        Box::new(future::ok((state, res)))
+    }
+}
+
+impl NewHandler for HealthcheckHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+// The assignments that the agent holds are those saved in the scheduled
+// directory, which are yet to be finished.  Those being processed are taken
+// from memory, the rest from disk.
+fn held_assignments(agent: &Agent) -> Vec<Assignment> {
+    let mut uuids: Vec<String> = WalkDir::new(REBALANCER_SCHEDULED_DIR)
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    uuids.sort();
+
+    uuids
+        .iter()
+        .filter_map(|uuid| {
+            assignment_get(&agent.assignments, uuid)
+                .or_else(|| {
+                    assignment_recall(format!(
+                        "{}/{}",
+                        REBALANCER_SCHEDULED_DIR, uuid
+                    ))
+                    .ok()
+                })
+                .map(|a| {
+                    // Only the stats are returned, so the tasks are not
+                    // copied.
+                    let a = a.read().unwrap();
+                    Assignment {
+                        uuid: a.uuid.clone(),
+                        stats: a.stats.clone(),
+                        tasks: vec![],
+                    }
+                })
+        })
+        .collect()
+}
+
+fn get_held_assignments_handler(
+    agent: Agent,
+    mut state: State,
+) -> Box<HandlerFuture> {
+    let params = HeldAssignmentsParams::take_from(&mut state);
+    let list = AgentAssignmentList {
+        restarted: params.since.as_ref() != Some(&agent.boot_id),
+        boot_id: agent.boot_id.clone(),
+        assignments: held_assignments(&agent),
+    };
+
+    let res = create_response(
+        &state,
+        StatusCode::OK,
+        mime::APPLICATION_JSON,
+        serde_json::to_vec(&list).expect("serialized assignments"),
+    );
+    Box::new(future::ok((state, res)))
+}
+
+#[derive(Clone)]
+struct HeldHandler(Agent);
+
+impl Handler for HeldHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        get_held_assignments_handler(self.0, state)
+    }
+}
+
+impl NewHandler for HeldHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+// Cancelling an assignment requires access to the agent's shared state, so
+// unlike deletion it needs a handler of its own.
+#[derive(Clone)]
+struct CancelHandler(Agent);
+
+impl Handler for CancelHandler {
+    fn handle(self, state: State) -> Box<HandlerFuture> {
+        cancel_assignment_handler(self.0, state)
+    }
+}
+
+impl NewHandler for CancelHandler {
+    type Instance = Self;
+
+    fn new_handler(&self) -> gotham::error::Result<Self::Instance> {
+        Ok(self.clone())
+    }
+}
+
+// Given a client specified uuid, delete its corresponding assignment on disk.
+fn delete_assignment(mut state: State) -> Box<HandlerFuture> {
+    let assignment_params = GetAssignmentParams::take_from(&mut state);
+    let uuid = match Uuid::parse_str(&assignment_params.uuid) {
+        Ok(id) => id.to_string(),
+        Err(e) => {
+            // Mal-formed uuid.
+            let msg = format!("Mal-formed delete request: {}", e);
+            info!("{}", &msg);
+            let res = create_response(
+                &state,
+                StatusCode::BAD_REQUEST,
+                mime::APPLICATION_JSON,
+                msg,
+            );
+            return Box::new(future::ok((state, res)));
+        }
+    };
+
+    match delete_assignment_impl(&uuid) {
+        Ok(_) => {
+            // Successful delete.
+            let res = create_empty_response(&state, StatusCode::OK);
+            Box::new(future::ok((state, res)))
+        }
+        Err(e) => handle_delete_assignment_error(state, e, &uuid),
+    }
+}
+
+fn post_assignment_handler(
+    agent: Agent,
+    mut state: State,
+) -> Box<HandlerFuture> {
+    let f = Body::take_from(&mut state)
+        .concat2()
+        .then(move |full_body| match full_body {
+            Ok(valid_body) => {
+                // Ceremony for parsing the information needed to create an
+                // an assignment out of the message body.
+                let (uuid, v, encodings) =
+                    match validate_assignment(&agent, &valid_body) {
+                        Ok(uve) => uve,
+                        Err(e) => {
+                            warn!("Rejecting malformed assignment: {}", e);
+                            let res = create_empty_response(
+                                &state,
+                                StatusCode::BAD_REQUEST,
+                            );
+
+                            if let Some(m) =
+                                agent.metrics.lock().unwrap().clone()
+                            {
+                                counter_vec_inc(
+                                    &m,
+                                    ERROR_COUNT,
+                                    Some(
+                                        &RebalancerError::InvalidRequest
+                                            .to_string(),
+                                    ),
+                                );
+                            }
+                            return future::ok((state, res));
+                        }
+                    };
+
+                // Ensure that an asignment with this uuid is not already
+                // currently in flight.  If there is one, do not allow this
+                // assignment to proceed.  If it is the same assignment being
+                // posted again, acknowledge it as though it were new rather
+                // than processing it a second time.
+                if agent.assignment_exists(&uuid) {
+                    if is_duplicate_assignment(&agent, &uuid, &v) {
+                        info!("Assignment {} received again.", &uuid);
+
+                        let res = create_response(
+                            &state,
+                            StatusCode::OK,
+                            mime::APPLICATION_JSON,
+                            serde_json::to_vec(&uuid)
+                                .expect("serialized assignment id"),
+                        );
+                        return future::ok((state, res));
+                    }
+
+                    let res =
+                        create_empty_response(&state, StatusCode::CONFLICT);
+
+                    if let Some(m) = agent.metrics.lock().unwrap().clone() {
+                        counter_vec_inc(
+                            &m,
+                            ERROR_COUNT,
+                            Some(&RebalancerError::Conflict.to_string()),
+                        );
+                    }
+
+                    return future::ok((state, res));
+                }
+
+                // Delete tasks free space rather than use it.
+                let bytes = v
+                    .iter()
+                    .filter(|t| t.action.is_copy())
+                    .filter_map(|t| t.content_length)
+                    .fold(0, u64::saturating_add);
+
+                if let Err(rejection) = agent.reserve_space(&uuid, bytes) {
+                    warn!("Rejecting assignment {}: {:?}", &uuid, rejection);
+
+                    let res = create_response(
+                        &state,
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        mime::APPLICATION_JSON,
+                        serde_json::to_vec(&rejection)
+                            .expect("serialized rejection"),
+                    );
+
+                    if let Some(m) = agent.metrics.lock().unwrap().clone() {
+                        counter_vec_inc(
+                            &m,
+                            ERROR_COUNT,
+                            Some(
+                                &RebalancerError::InsufficientSpace.to_string(),
+                            ),
+                        );
+                    }
+
+                    return future::ok((state, res));
+                }
+
+                if let Some(m) = agent.metrics.lock().unwrap().clone() {
+                    counter_inc_by(&m, TASK_RECEIVED_COUNT, v.len() as u64);
+                }
+
+                let mut assignment = Assignment::new(v, &uuid);
+                assignment.encodings = encodings;
+                let assignment = Arc::new(RwLock::new(assignment));
+
+                info!("Received assignment {}.", &uuid);
+                debug!("Received assignment: {:#?}", &assignment);
+
+                // Before we even process the assignment, save it to persistent
+                // storage.
+                assignment_save(&uuid, REBALANCER_SCHEDULED_DIR, assignment);
+
+                // Assignment has been saved.  Remove its id from the the table.
+                agent.quiescing.lock().unwrap().remove(&uuid);
+
+                faults::inject(FaultPoint::AgentAssignmentPost);
+
+                // Create a response containing our newly initialized stats.
+                // This serves as confirmation to the client that we recieved
+                // their request correctly and are working on it.
+                let res = create_response(
+                    &state,
+                    StatusCode::OK,
+                    mime::APPLICATION_JSON,
+                    serde_json::to_vec(&uuid)
+                        .expect("serialized assignment id"),
+                );
+
+                // Signal the workers that there is a new assignent ready for
+                // processing.
+                assignment_signal(&agent, &uuid);
+                future::ok((state, res))
+            }
 
             Err(e) => {
                 if let Some(m) = agent.metrics.lock().unwrap().clone() {
-                    counter_vec_inc(&m, ERROR_COUNT, Some(&e.to_string()));
+                    counter_vec_inc(
+                        &m,
+                        ERROR_COUNT,
+                        Some(&RebalancerError::Unreachable.to_string()),
+                    );
                 }
 
                 future::err((state, e.into_handler_error()))
@@ -661,6 +1827,32 @@ fn post_assignment_handler(
     Box::new(f)
 }
 
+// Returns true if the agent already has an assignment with this uuid and the
+// same set of tasks, i.e. the manager has posted the same assignment again
+// (for example because it timed out waiting for our response to the first
+// post).  An assignment that is still being saved to disk can not be
+// compared yet, so it is not considered a duplicate.
+fn is_duplicate_assignment(agent: &Agent, uuid: &str, tasks: &[Task]) -> bool {
+    let existing = match get_assignment_impl(agent, uuid) {
+        Some(a) => a,
+        None => return false,
+    };
+
+    let mut received: Vec<String> =
+        tasks.iter().map(Task::idempotency_id).collect();
+    let mut known: Vec<String> = existing
+        .read()
+        .unwrap()
+        .tasks
+        .iter()
+        .map(Task::idempotency_id)
+        .collect();
+
+    received.sort();
+    known.sort();
+    received == known
+}
+
 fn empty_response(state: State, code: StatusCode) -> Box<HandlerFuture> {
     let res = create_empty_response(&state, code);
     Box::new(future::ok((state, res)))
@@ -719,9 +1911,22 @@ fn get_assignment_handler(
         }
     };
 
+    faults::inject(FaultPoint::AgentAssignmentGet);
+
     let res = match get_assignment_impl(&agent, &uuid) {
         Some(a) => {
-            let assignment = a.read().unwrap();
+            let mut assignment = a.write().unwrap();
+
+            // Once the results of a completed assignment have been handed
+            // over, the reaper may remove it after its retention.
+            if let AgentAssignmentState::Complete(_) = assignment.stats.state {
+                agent.reaper.reported(&uuid);
+            }
+
+            assignment.stats.eta_secs = assignment
+                .stats
+                .estimate_remaining()
+                .map(|eta| eta.as_secs());
             create_response(
                 &state,
                 StatusCode::OK,
@@ -743,8 +1948,15 @@ fn get_assignment_handler(
 // needed by the agent to get the process started.  The pieces of the payload
 // go separate ways after this point, so they are separated out here in a tuple
 // to save the caller from the monotony of accessing each (private) member of
-// the structure by hand.
-fn validate_assignment(body: &Chunk) -> Result<(String, Vec<Task>), String> {
+// the structure by hand.  The tasks are put in the order that the manager asks
+// for, or in the agent's own order if it does not ask for one, before the
+// assignment is saved, so that the order survives a restart of the agent.
+// The content encodings that the objects may be downloaded in go along with
+// them.
+fn validate_assignment(
+    agent: &Agent,
+    body: &Chunk,
+) -> Result<(String, Vec<Task>, Vec<ContentEncoding>), String> {
     let payload: AssignmentPayload =
         match serde_json::from_slice(&body.to_vec()) {
             Ok(p) => p,
@@ -753,7 +1965,23 @@ fn validate_assignment(body: &Chunk) -> Result<(String, Vec<Task>), String> {
             }
         };
 
-    Ok(<(String, Vec<Task>)>::from(payload))
+    let order = payload.order.unwrap_or(agent.task_order);
+    let encodings = payload.encodings.clone();
+    let (uuid, mut tasks) = <(String, Vec<Task>)>::from(payload);
+
+    if let Some(max) = agent.max_tasks {
+        if tasks.len() > max {
+            return Err(format!(
+                "Assignment has {} tasks, more than the {} allowed",
+                tasks.len(),
+                max
+            ));
+        }
+    }
+
+    order.sort(&mut tasks);
+
+    Ok((uuid, tasks, encodings))
 }
 
 impl Handler for Agent {
@@ -824,70 +2052,36 @@ fn file_remove(file_path: &str) {
     }
 }
 
-fn file_create(file_path: &str) -> File {
-    match File::create(&file_path) {
-        Err(e) => panic!("Error creating file {}", e),
-        Ok(file) => file,
-    }
-}
-
-// TODO: Make this return an actual result.
-fn download(
-    uri: &str,
-    owner: &str,
-    object: &str,
-    csum: &str,
-    client: &Client,
-) -> Result<u64, ObjectSkippedReason> {
-    let mut response = match client.get(uri).send() {
-        Ok(resp) => resp,
-        Err(e) => {
-            error!("Request failed: {}", &e);
-            return Err(ObjectSkippedReason::SourceOtherError);
-        }
-    };
-
-    let status = response.status();
-    let msg = format!("Download response for {} is {}", uri, status);
-    if status != reqwest::StatusCode::OK {
-        error!("{}", msg);
-        return Err(ObjectSkippedReason::HTTPStatusCode(status.into()));
-    }
-
-    trace!("{}", msg);
-
-    let tmp_path = manta_tmp_path(owner, object);
-    let mut file = file_create(&tmp_path);
-
-    let bytes = match std::io::copy(&mut response, &mut file) {
-        Ok(b) => b,
-        Err(e) => {
-            error!("Failed to complete object download: {}:{}", uri, e);
-            return Err(ObjectSkippedReason::AgentFSError);
-        }
-    };
-
-    if calculate_md5(&tmp_path) == csum {
-        Ok(bytes)
-    } else {
-        error!("Checksum failed for {}/{}.", owner, object);
-        Err(ObjectSkippedReason::MD5Mismatch)
-    }
-}
-
+// The download stage of the task pipeline.  The object is fetched from the
+// source storage node in to its temporary location and the task is left in
+// the Pending state, which tells the pipeline to hand it over to the verify
+// stage (see `verify_task()').  If the task is finished here, either because
+// the object is already present or because the download failed, its status is
+// set accordingly and it skips verification altogether.  Alternate task
+// processors supplied to `router()' must follow the same convention.  Delete
+// tasks are finished here too (see `delete_task()').
 pub fn process_task(
     task: &mut Task,
-    client: &Client,
+    transfer: &dyn Transfer,
     metrics: &Option<MetricsMap>,
 ) {
+    if task.action == TaskAction::Delete {
+        delete_task(task);
+        return;
+    }
+
     let file_path = manta_file_path(&task.owner, &task.object_id);
     let path = Path::new(&file_path);
 
     // If the file exists and the checksum matches, then
     // short-circuit this operation and return.  There is
     // no need to download anything.  Mark the task as
-    // complete and move on.
-    if path.exists() && calculate_md5(&file_path) == task.md5sum {
+    // complete and move on.  An unchecked task has no checksum to compare
+    // with, so the object is always downloaded.
+    if !task.unchecked
+        && path.exists()
+        && calculate_md5(&file_path) == task.md5sum
+    {
         task.set_status(TaskStatus::Complete);
         info!(
             "Checksum passed -- no need to download: {}/{}",
@@ -897,47 +2091,172 @@ pub fn process_task(
     }
 
     // Put it all together.  The format of the url is:
-    // http://<storage id>/<owner id>/<object id>
-    let url = format!(
-        "http://{}/{}/{}",
-        &task.source.manta_storage_id, &task.owner, &task.object_id
-    );
+    // http://<storage id>/<owner id>/<object id>, or for a direct task
+    // http://<storage id>:<agent port>/objects/<owner id>/<object id>.
+    let url = if task.direct {
+        format!(
+            "http://{}:{}/objects/{}/{}",
+            &task.source.manta_storage_id,
+            AGENT_PORT,
+            &task.owner,
+            &task.object_id
+        )
+    } else {
+        format!(
+            "http://{}/{}/{}",
+            &task.source.manta_storage_id, &task.owner, &task.object_id
+        )
+    };
 
     let tmp_path = manta_tmp_path(&task.owner, &task.object_id);
 
     // Reach out to the storage node to download
     // the object.
-    let status = match download(
-        &url,
-        &task.owner,
-        &task.object_id,
-        &task.md5sum,
-        client,
-    ) {
+    let autopsy = task.autopsy.get_or_insert_with(TaskAutopsy::default);
+    match transfer.fetch(&url, &tmp_path, &task.encodings, autopsy) {
         Ok(bytes) => {
+            // The bytes downloaded are those of the object itself, while the
+            // bytes transferred are those that the source sent, which are
+            // fewer if it compressed the object.
             if let Some(m) = metrics {
+                let encoding = autopsy.content_encoding.map_or_else(
+                    || String::from("identity"),
+                    |e| e.to_string(),
+                );
+
                 counter_inc_by(m, BYTES_COUNT, bytes);
+                counter_vec_inc_by(
+                    m,
+                    DOWNLOAD_BYTES,
+                    Some(OUTCOME_SUCCESS),
+                    bytes as usize,
+                );
+                counter_vec_inc_by(
+                    m,
+                    TRANSFER_BYTES,
+                    Some(&encoding),
+                    autopsy.bytes as usize,
+                );
             }
 
             info!(
                 "owner: {}, object: {}, bytes: {}",
                 &task.owner, &task.object_id, bytes
             );
-
-            // Upon successful download, move the temprorary object to its
-            // rightful location (i.e. /manta/account/object).
-            let manta_path = manta_file_path(&task.owner, &task.object_id);
-            file_move(&tmp_path, &manta_path);
-            TaskStatus::Complete
         }
         Err(e) => {
             // If we failed to complete the download, remove the temporary
             // file so that these kinds of things do not pile up.  It is
             // worth mentioning that in all failure cases except one there
             // will a partially downloaded object that requires clean-up.
+            // What was received of it is counted all the same, since it took
+            // up the network and the staging directory.
+            if let Some(m) = metrics {
+                let partial =
+                    fs::metadata(&tmp_path).map(|md| md.len()).unwrap_or(0);
+                counter_vec_inc_by(
+                    m,
+                    DOWNLOAD_BYTES,
+                    Some(OUTCOME_FAILURE),
+                    partial as usize,
+                );
+            }
             file_remove(&tmp_path);
-            TaskStatus::Failed(e)
+            task.set_status(TaskStatus::Failed(e));
+        }
+    }
+}
+
+// Remove this agent's copy of the object.  Each way in which this can fail is
+// reported as a different reason, so that the manager can tell a copy that is
+// already gone from one that could not be removed.
+fn delete_task(task: &mut Task) {
+    let status = match delete_object(task) {
+        Ok(()) => {
+            info!("Removed {}/{}", &task.owner, &task.object_id);
+            TaskStatus::Complete
+        }
+        Err(reason) => TaskStatus::Failed(reason),
+    };
+
+    task.set_status(status);
+}
+
+// The copy is only removed if it is of the generation that the task is for,
+// and the removal is made durable by syncing the directory that it was in.
+fn delete_object(task: &Task) -> Result<(), ObjectSkippedReason> {
+    let file_path = manta_file_path(&task.owner, &task.object_id);
+
+    let size = fs::metadata(&file_path)
+        .map_err(|e| delete_error(&file_path, e))?
+        .len();
+    let generation = object_generation(&calculate_md5(&file_path), size);
+
+    match &task.generation {
+        Some(g) if *g == generation => (),
+        Some(g) => {
+            error!(
+                "Not removing {}: it is of generation {}, not {}",
+                &file_path, generation, g
+            );
+            return Err(ObjectSkippedReason::GenerationMismatch);
         }
+        None => {
+            error!("Not removing {}: no generation given", &file_path);
+            return Err(ObjectSkippedReason::GenerationMismatch);
+        }
+    }
+
+    fs::remove_file(&file_path).map_err(|e| delete_error(&file_path, e))?;
+
+    let dir = Path::new(&file_path).parent().expect("object directory");
+    File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| delete_error(&file_path, e))
+}
+
+// The reason that a delete task failed with `e`.
+fn delete_error(file_path: &str, e: std::io::Error) -> ObjectSkippedReason {
+    let busy = [libc::EBUSY, libc::ETXTBSY];
+    let reason = match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            ObjectSkippedReason::AgentObjectNotFound
+        }
+        std::io::ErrorKind::PermissionDenied => {
+            ObjectSkippedReason::AgentPermissionDenied
+        }
+        _ if e.raw_os_error().map_or(false, |n| busy.contains(&n)) => {
+            ObjectSkippedReason::AgentObjectBusy
+        }
+        _ => ObjectSkippedReason::AgentFSError,
+    };
+
+    warn!("Error removing file {}: {} ({})", file_path, e, reason);
+    reason
+}
+
+// The verify stage of the task pipeline.  Calculate the checksum of an object
+// that the download stage has written to its temporary location and, if it
+// matches, move the object to its rightful location (i.e.
+// /manta/account/object).  An unchecked task's object is moved regardless.
+fn verify_task(task: &mut Task) {
+    let tmp_path = manta_tmp_path(&task.owner, &task.object_id);
+
+    if task.unchecked {
+        warn!(
+            "Not checking {}/{}: no usable checksum",
+            &task.owner, &task.object_id
+        );
+    }
+
+    let status = if task.unchecked || calculate_md5(&tmp_path) == task.md5sum {
+        let manta_path = manta_file_path(&task.owner, &task.object_id);
+        file_move(&tmp_path, &manta_path);
+        TaskStatus::Complete
+    } else {
+        error!("Checksum failed for {}/{}.", &task.owner, &task.object_id);
+        file_remove(&tmp_path);
+        TaskStatus::Failed(ObjectSkippedReason::MD5Mismatch)
     };
 
     task.set_status(status);
@@ -958,71 +2277,392 @@ fn assignment_get(
     }
 }
 
-fn process_assignment_impl(
+// A task that has made it through the download stage and is waiting to be
+// verified.  The index is that of the task within the assignment, and
+// `started' is when the first attempt at downloading it began.
+struct VerifyRequest {
+    index: usize,
+    task: Task,
+    started: Instant,
+}
+
+// The worker pool used to verify the tasks of a single assignment at a time.
+// Download workers are largely bound by the network, while verify workers
+// spend their time calculating checksums.  Keeping them in separate pools,
+// joined by a bounded queue, means that hashing a large object does not leave
+// a download slot idle, and a burst of small downloads can not starve the
+// hashers.  The queue bound keeps the download stage from running arbitrarily
+// far ahead of verification, filling the temporary directory as it goes.
+// The download workers themselves are shared by all of the assignments being
+// processed (see scheduler.rs).
+struct TaskPipeline {
+    verifiers: ThreadPool,
+    queue_depth: usize,
+    sampler: Arc<Sampler>,
+    scrubber: Arc<Scrubber>,
+    slow_task: Option<Duration>,
+}
+
+impl TaskPipeline {
+    fn new(
+        verify_workers: usize,
+        queue_depth: usize,
+        sampler: Arc<Sampler>,
+        scrubber: Arc<Scrubber>,
+        slow_task: Option<Duration>,
+    ) -> TaskPipeline {
+        TaskPipeline {
+            verifiers: ThreadPool::new(verify_workers),
+            queue_depth,
+            sampler,
+            scrubber,
+            slow_task,
+        }
+    }
+}
+
+// What a download worker needs to know of the assignment that a task that it
+// has claimed belongs to.
+#[derive(Clone)]
+struct DownloadContext {
     assignment: Arc<RwLock<Assignment>>,
-    uuid: &str,
-    f: fn(&mut Task, &Client, &Option<MetricsMap>),
     failures: Arc<Mutex<Vec<Task>>>,
+    verify: mpsc::SyncSender<VerifyRequest>,
+}
+
+// Once a task is finished with, keep what was learned of it while it was
+// processed only if it took longer than the slow task threshold.
+fn autopsy_if_slow(t: &mut Task, started: Instant, slow: Option<Duration>) {
+    let elapsed = started.elapsed();
+
+    match slow {
+        Some(threshold) if elapsed >= threshold => {
+            let autopsy = t.autopsy.get_or_insert_with(TaskAutopsy::default);
+            autopsy.elapsed_ms = elapsed.as_millis() as u64;
+
+            warn!(
+                "Task {}/{} took {}ms (download {}ms, verify {}ms)",
+                t.owner,
+                t.object_id,
+                autopsy.elapsed_ms,
+                autopsy.download_ms,
+                autopsy.verify_ms
+            );
+        }
+        _ => t.autopsy = None,
+    }
+}
+
+// Record the outcome of a finished task in the saved copy of its assignment,
+// so that an agent that is restarted part of the way through the assignment
+// picks it up where it left off rather than processing all of its tasks
+// again.  The tasks of an assignment are saved in order, so the row of a
+// task is one more than its index.  The file is never created here, as an
+// assignment that is no longer scheduled has nothing to resume.  A task
+// whose outcome could not be recorded is only processed again after a
+// restart, so an error is just logged.
+fn journal_task(uuid: &str, index: usize, task: &Task) {
+    let path = format!("{}/{}", REBALANCER_SCHEDULED_DIR, uuid);
+    let res = rusqlite::Connection::open_with_flags(
+        &path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
+    )
+    .and_then(|conn| {
+        conn.execute(
+            "UPDATE tasks SET status = ?1 WHERE rowid = ?2",
+            rusqlite::params![
+                serde_json::to_vec(&task.status).unwrap(),
+                (index + 1) as i64
+            ],
+        )
+    });
+
+    if let Err(e) = res {
+        warn!(
+            "Unable to journal task {}/{} of assignment {}: {}",
+            task.owner, task.object_id, uuid, e
+        );
+    }
+}
+
+// Record the final outcome of a task, whichever stage it finished in.
+fn task_finished(
+    assignment: &Arc<RwLock<Assignment>>,
+    index: usize,
+    t: Task,
+    failures: &Arc<Mutex<Vec<Task>>>,
     metrics: &Option<MetricsMap>,
-    client: &Client,
-    next: Arc<Mutex<usize>>,
 ) {
-    let len = assignment.read().unwrap().tasks.len();
+    // Update the total number of objects that have been processed, whether
+    // successful or not.
+    if let Some(m) = metrics.clone() {
+        // Note: The rebalncer agent does not currently break down this
+        // metric by anything meaningful, so we don't supply a bucket.
+        // That is, the only thing we track here is the total number of
+        // objects processed.
+        counter_vec_inc(&m, OBJECT_COUNT, None);
+    }
+
+    let tmp = &mut assignment.write().unwrap();
+
+    // Update our stats.
+    tmp.stats.complete += 1;
+
+    if t.status == TaskStatus::Complete {
+        if let Some(m) = metrics {
+            counter_inc_by(m, TASK_COMPLETE_COUNT, 1);
+        }
+    }
+
+    if let TaskStatus::Failed(e) = t.status {
+        if let Some(m) = metrics.clone() {
+            counter_vec_inc(
+                &m,
+                ERROR_COUNT,
+                Some(&e.rebalancer_error().to_string()),
+            );
+
+            // The class of the error is its reason without any status code,
+            // e.g. `http_status_code', which keeps the number of labels down.
+            counter_vec_inc(&m, TASK_FAILED_COUNT, Some(&e.to_string()));
+        }
+        tmp.stats.failed += 1;
+        failures.lock().unwrap().push(t.clone());
+    }
+
+    if t.autopsy.is_some() {
+        if let Some(m) = metrics {
+            counter_inc_by(m, SLOW_TASK_COUNT, 1);
+        }
+        tmp.stats.slow_tasks.push(t.clone());
+    }
+
+    // The assignment is locked while the outcome is journaled, so that its
+    // tasks are written out one at a time.
+    journal_task(&tmp.uuid, index, &t);
+
+    // Update the task in the assignment.
+    tmp.tasks[index] = t;
+}
 
+// Download workers claim tasks from whichever of the assignments being
+// processed have any left, for as long as the agent runs.
+#[allow(clippy::too_many_arguments)]
+fn download_worker(
+    board: &TaskBoard<DownloadContext>,
+    f: fn(&mut Task, &dyn Transfer, &Option<MetricsMap>),
+    metrics: &Option<MetricsMap>,
+    transfer: &dyn Transfer,
+    throttle: &Option<Arc<CpuThrottle>>,
+    cancelled: &Arc<Mutex<HashSet<String>>>,
+    retry: ConfigRetry,
+    slow_task: Option<Duration>,
+) {
     loop {
-        // Obtain the index of the next unprocessed task in the vector.  This
-        // will allow multiple workers to find the next available task in
-        // constant time without having to perform the practically negligible
-        // but tedious operation(s) of checking the status of a few before
-        // finally arriving at one that has not yet been processed.
-        let index: usize = {
-            let mut guard = next.lock().unwrap();
-            if *guard == len {
-                break;
-            }
+        let claim = board.claim(|id| cancelled.lock().unwrap().contains(id));
+        download_task(
+            &claim, f, metrics, transfer, throttle, cancelled, retry, slow_task,
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn download_task(
+    claim: &Claim<DownloadContext>,
+    f: fn(&mut Task, &dyn Transfer, &Option<MetricsMap>),
+    metrics: &Option<MetricsMap>,
+    transfer: &dyn Transfer,
+    throttle: &Option<Arc<CpuThrottle>>,
+    cancelled: &Arc<Mutex<HashSet<String>>>,
+    retry: ConfigRetry,
+    slow_task: Option<Duration>,
+) {
+    let uuid = &claim.id;
+    let index = claim.index;
+    let DownloadContext {
+        assignment,
+        failures,
+        verify,
+    } = &claim.context;
+
+    let mut t = {
+        let assignment = assignment.read().unwrap();
+        let mut t = assignment.tasks[index].clone();
+        t.encodings = assignment.encodings.clone();
+        t
+    };
+
+    // The task was finished with before the agent was restarted.
+    if t.status != TaskStatus::Pending {
+        return;
+    }
+
+    trace!(
+        "Processing task: assignment: {}, owner: {}, object: {}",
+        &uuid,
+        &t.owner,
+        &t.object_id
+    );
+
+    // Process the task, retrying the download for as long as it fails
+    // for a reason that might clear up on its own.
+    let first_start = Instant::now();
+    let mut attempts = 0;
 
-            let i = *guard;
-            *guard += 1;
-            i
+    loop {
+        attempts += 1;
+
+        let start = Instant::now();
+        f(&mut t, transfer, &metrics);
+
+        if let Some(m) = metrics {
+            let outcome = if t.status == TaskStatus::Pending {
+                OUTCOME_SUCCESS
+            } else {
+                OUTCOME_FAILURE
+            };
+            histogram_vec_observe(
+                m,
+                DOWNLOAD_TIME,
+                outcome,
+                start.elapsed().as_secs_f64(),
+            );
+        }
+
+        if let Some(th) = throttle {
+            th.pace();
+        }
+
+        let reason = match &t.status {
+            TaskStatus::Failed(r) if retry.should_retry(attempts, r) => *r,
+            _ => break,
         };
 
-        let mut t = assignment.read().unwrap().tasks[index].clone();
+        if cancelled.lock().unwrap().contains(uuid) {
+            break;
+        }
 
-        trace!(
-            "Processing task: assignment: {}, owner: {}, object: {}",
-            &uuid,
-            &t.owner,
-            &t.object_id
+        let backoff = retry.backoff(attempts);
+        debug!(
+            "Download of {}/{} failed ({}) on attempt {} of {}, \
+             retrying in {}ms",
+            t.owner,
+            t.object_id,
+            reason.into_string(),
+            attempts,
+            retry.max_attempts,
+            backoff.as_millis()
         );
 
-        // Process the task.
-        f(&mut t, client, &metrics);
+        if let Some(m) = metrics {
+            counter_inc_by(m, DOWNLOAD_RETRY_COUNT, 1);
+        }
 
-        // Update the total number of objects that have been processed, whether
-        // successful or not.
-        if let Some(m) = metrics.clone() {
-            // Note: The rebalncer agent does not currently break down this
-            // metric by anything meaningful, so we don't supply a bucket.
-            // That is, the only thing we track here is the total number of
-            // objects processed.
-            counter_vec_inc(&m, OBJECT_COUNT, None);
+        thread::sleep(backoff);
+        t.set_status(TaskStatus::Pending);
+    }
+
+    let download_ms = first_start.elapsed().as_millis() as u64;
+    t.download = Some(DownloadAttempts {
+        attempts,
+        elapsed_ms: download_ms,
+    });
+    if let Some(autopsy) = t.autopsy.as_mut() {
+        autopsy.download_ms = download_ms;
+    }
+
+    if t.status != TaskStatus::Pending {
+        autopsy_if_slow(&mut t, first_start, slow_task);
+        task_finished(assignment, index, t, failures, metrics);
+        return;
+    }
+
+    // Hand the task off to the verify stage.  If the queue is full, this
+    // blocks until a verify worker has made room for it.
+    if let Some(m) = metrics {
+        gauge_inc(m, VERIFY_QUEUE_DEPTH);
+    }
+
+    let request = VerifyRequest {
+        index,
+        task: t,
+        started: first_start,
+    };
+
+    if let Err(e) = verify.send(request) {
+        // All verify workers have exited, which only happens if one of
+        // them panicked.  Fail the task rather than leaving it pending.
+        if let Some(m) = metrics {
+            gauge_dec(m, VERIFY_QUEUE_DEPTH);
         }
 
-        let tmp = &mut assignment.write().unwrap();
+        let mut t = e.0.task;
+        error!(
+            "Unable to queue {}/{} for verification",
+            t.owner, t.object_id
+        );
+        file_remove(&manta_tmp_path(&t.owner, &t.object_id));
+        t.set_status(TaskStatus::Failed(ObjectSkippedReason::AgentFSError));
+        autopsy_if_slow(&mut t, first_start, slow_task);
+        task_finished(assignment, index, t, failures, metrics);
+    }
+}
+
+// Verify workers run until every task of the assignment has been downloaded
+// (or given up on) and the queue has been drained.  Tasks that were already
+// downloaded when an assignment is cancelled are still verified, as they are
+// considered to be in flight.
+#[allow(clippy::too_many_arguments)]
+fn verify_worker(
+    assignment: Arc<RwLock<Assignment>>,
+    uuid: &str,
+    failures: Arc<Mutex<Vec<Task>>>,
+    metrics: &Option<MetricsMap>,
+    throttle: &Option<Arc<CpuThrottle>>,
+    queue: Arc<Mutex<mpsc::Receiver<VerifyRequest>>>,
+    sampler: &Sampler,
+    scrubber: &Scrubber,
+    slow_task: Option<Duration>,
+) {
+    loop {
+        let VerifyRequest {
+            index,
+            mut task,
+            started,
+        } = match queue.lock().unwrap().recv() {
+            Ok(r) => r,
+            Err(_) => break,
+        };
 
-        // Update our stats.
-        tmp.stats.complete += 1;
+        if let Some(m) = metrics {
+            gauge_dec(m, VERIFY_QUEUE_DEPTH);
+        }
 
-        if let TaskStatus::Failed(e) = t.status {
-            if let Some(m) = metrics.clone() {
-                counter_vec_inc(&m, ERROR_COUNT, Some(&e.to_string()));
-            }
-            tmp.stats.failed += 1;
-            failures.lock().unwrap().push(t.clone());
+        let start = Instant::now();
+        verify_task(&mut task);
+
+        if let Some(m) = metrics {
+            histogram_observe(m, VERIFY_TIME, start.elapsed().as_secs_f64());
+        }
+
+        if let Some(autopsy) = task.autopsy.as_mut() {
+            autopsy.verify_ms = start.elapsed().as_millis() as u64;
+        }
+        autopsy_if_slow(&mut task, started, slow_task);
+
+        if task.status == TaskStatus::Complete {
+            let path = manta_file_path(&task.owner, &task.object_id);
+            sampler.offer(uuid, &task, &path);
+            scrubber.record(&task);
+        }
+
+        // If we have exceeded our share of the CPU, back off before picking
+        // up the next task.
+        if let Some(th) = throttle {
+            th.pace();
         }
 
-        // Update the task in the assignment.
-        tmp.tasks[index] = t;
+        task_finished(&assignment, index, task, &failures, metrics);
     }
 }
 
@@ -1043,14 +2683,14 @@ fn process_assignment_impl(
 // intention of modifying it and further, the same thread invoking this function
 // is the only one that will clean up the assignment when we have finished
 // processing it, by calling `assignment_complete()'.
-#[allow(clippy::mutex_atomic)]
 fn process_assignment(
     assignments: Arc<Mutex<Assignments>>,
     uuid: String,
-    f: fn(&mut Task, &Client, &Option<MetricsMap>),
     metrics: Option<MetricsMap>,
-    client: &Client,
-    pool: &ThreadPool,
+    board: &TaskBoard<DownloadContext>,
+    pipeline: &TaskPipeline,
+    throttle: &Option<Arc<CpuThrottle>>,
+    cancelled: &Arc<Mutex<HashSet<String>>>,
 ) {
     // If we are unsuccessful in loading the assignment from disk, there is
     // nothing left to do here, other than return.
@@ -1061,34 +2701,105 @@ fn process_assignment(
 
     let assignment = assignment_get(&assignments, &uuid).unwrap();
     let len = assignment.read().unwrap().tasks.len();
-    let failures = Arc::new(Mutex::new(Vec::new()));
-    let next = Arc::new(Mutex::new(0));
 
-    assignment.write().unwrap().stats.state = AgentAssignmentState::Running;
+    // Tasks that were finished before the agent was restarted are not
+    // processed again, but those of them that failed are still reported.
+    let failures: Vec<Task> = assignment
+        .read()
+        .unwrap()
+        .tasks
+        .iter()
+        .filter(|t| match t.status {
+            TaskStatus::Failed(_) => true,
+            _ => false,
+        })
+        .cloned()
+        .collect();
+    let failures = Arc::new(Mutex::new(failures));
+
+    {
+        let stats = &mut assignment.write().unwrap().stats;
+        stats.state = AgentAssignmentState::Running;
+        stats.started = Some(Instant::now());
+    }
 
     info!("Begin processing assignment {}.", &uuid);
 
-    let active_workers = min(len, pool.max_count());
+    if let Some(m) = &metrics {
+        gauge_inc(m, ACTIVE_ASSIGNMENTS);
+    }
+
+    let verify_workers = min(len, pipeline.verifiers.max_count());
+    let (vtx, vrx) = mpsc::sync_channel(pipeline.queue_depth);
+    let vrx = Arc::new(Mutex::new(vrx));
 
-    let start = std::time::Instant::now();
+    let start = Instant::now();
 
-    for _ in 0..active_workers {
+    for _ in 0..verify_workers {
         let asn = Arc::clone(&assignment);
-        let fl = Arc::clone(&failures);
         let id = uuid.clone();
+        let fl = Arc::clone(&failures);
         let me = metrics.clone();
-        let cl = client.clone();
-        let ne = Arc::clone(&next);
-        pool.execute(move || {
-            process_assignment_impl(asn, &id, f, fl, &me, &cl, ne);
+        let th = throttle.clone();
+        let rx = Arc::clone(&vrx);
+        let sa = Arc::clone(&pipeline.sampler);
+        let sc = Arc::clone(&pipeline.scrubber);
+        let sl = pipeline.slow_task;
+        pipeline.verifiers.execute(move || {
+            verify_worker(asn, &id, fl, &me, &th, rx, &sa, &sc, sl);
         });
     }
-    pool.join();
+
+    // Once every task has been claimed from the board and finished with (or
+    // the assignment is cancelled and those that were claimed have been
+    // finished with), the assignment is taken off the board.  The last of
+    // the senders is dropped along with it, and the verify workers exit as
+    // soon as they have drained the queue.  Likewise, should every verify
+    // worker go away, the download workers find out the next time they try
+    // to queue a task.
+    let downloaded = board.post(
+        &uuid,
+        len,
+        DownloadContext {
+            assignment: Arc::clone(&assignment),
+            failures: Arc::clone(&failures),
+            verify: vtx,
+        },
+    );
+    drop(vrx);
+    let _ = downloaded.recv();
+    pipeline.verifiers.join();
+
+    // If the assignment was cancelled, anything that the workers did not get
+    // to is still pending.  Report those tasks as failed so that the manager
+    // does not update their metadata, and can send them elsewhere.
+    if cancelled.lock().unwrap().remove(&uuid) {
+        let tmp = &mut assignment.write().unwrap();
+        let mut count = 0;
+
+        for t in tmp.tasks.iter_mut() {
+            if t.status == TaskStatus::Pending {
+                t.set_status(TaskStatus::Failed(
+                    ObjectSkippedReason::AssignmentCancelled,
+                ));
+                failures.lock().unwrap().push(t.clone());
+                count += 1;
+            }
+        }
+
+        tmp.stats.failed += count;
+        tmp.stats.complete += count;
+        info!(
+            "Assignment {} cancelled with {} tasks unprocessed.",
+            &uuid, count
+        );
+    }
 
     let done = start.elapsed().as_secs_f64();
 
     if let Some(m) = metrics.clone() {
         histogram_observe(&m, ASSIGNMENT_TIME, done);
+        gauge_dec(&m, ACTIVE_ASSIGNMENTS);
     }
 
     let failed = if failures.lock().unwrap().is_empty() {
@@ -1104,22 +2815,240 @@ fn process_assignment(
         "Finished processing assignment {} in {} seconds.",
         uuid, done
     );
-    assignment_complete(assignments, uuid);
+    assignment_complete(assignments, uuid.clone());
+
+    // A cancellation that arrived after the workers had finished is of no
+    // consequence, but its entry would otherwise never be removed.
+    cancelled.lock().unwrap().remove(&uuid);
+}
+
+// The number of bytes taken up by the files in the staging directory `dir`.
+// Objects are downloaded there, and stay there until they have been verified
+// and moved in to place (or removed), so this is the data that the agent has
+// on hand that is not yet of any use.  Subdirectories are not counted.
+fn staging_bytes_used(dir: &str) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Unable to read staging directory {}: {}", dir, e);
+            return 0;
+        }
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
 }
 
 fn agent_start_metrics_server(config: &AgentConfig) -> MetricsMap {
-    let agent_metrics = metrics::register_metrics(&config.metrics);
-    let metrics_host = config.metrics.host.clone();
-    let metrics_port = config.metrics.port;
+    let mut agent_metrics = metrics::register_metrics(&config.metrics);
+
+    let labels: HashMap<String, String> = metrics::get_const_labels()
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(HashMap::new);
+
+    // Now create and register additional metrics exclusively used by the
+    // rebalancer agent, one set for each stage of the task pipeline.
+    let download_times = register_histogram_vec!(
+        histogram_opts!(DOWNLOAD_TIME, "Object download time")
+            .const_labels(labels.clone()),
+        &["outcome"]
+    )
+    .expect("failed to register download_time histogram");
+
+    agent_metrics
+        .insert(DOWNLOAD_TIME, Metrics::MetricsHistogramVec(download_times));
+
+    let verify_times = register_histogram!(histogram_opts!(
+        VERIFY_TIME,
+        "Object checksum verification time"
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register verify_time histogram");
+
+    agent_metrics.insert(VERIFY_TIME, Metrics::MetricsHistogram(verify_times));
+
+    let verify_queue = register_gauge!(opts!(
+        VERIFY_QUEUE_DEPTH,
+        "Number of downloaded objects waiting to be verified."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register verify_queue_depth gauge");
+
+    agent_metrics
+        .insert(VERIFY_QUEUE_DEPTH, Metrics::MetricsGauge(verify_queue));
+
+    let download_retries = register_counter!(opts!(
+        DOWNLOAD_RETRY_COUNT,
+        "Number of times that a failed download was attempted again."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register download_retry_count counter");
+
+    agent_metrics.insert(
+        DOWNLOAD_RETRY_COUNT,
+        Metrics::MetricsCounter(download_retries),
+    );
+
+    let sample_verifies = register_counter_vec!(
+        opts!(
+            SAMPLE_VERIFY_COUNT,
+            "Sampled objects checked against their source, by outcome."
+        )
+        .const_labels(labels.clone()),
+        &["outcome"]
+    )
+    .expect("failed to register sample_verify_count counter");
+
+    agent_metrics.insert(
+        SAMPLE_VERIFY_COUNT,
+        Metrics::MetricsCounterVec(sample_verifies),
+    );
+
+    let slow_tasks = register_counter!(opts!(
+        SLOW_TASK_COUNT,
+        "Number of tasks that took longer than the slow task threshold."
+    )
+    .const_labels(labels))
+    .expect("failed to register slow_task_count counter");
+
+    agent_metrics.insert(SLOW_TASK_COUNT, Metrics::MetricsCounter(slow_tasks));
+
+    let tasks_received = register_counter!(opts!(
+        TASK_RECEIVED_COUNT,
+        "Number of tasks received in assignments."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register task_received_count counter");
+
+    agent_metrics
+        .insert(TASK_RECEIVED_COUNT, Metrics::MetricsCounter(tasks_received));
+
+    let tasks_complete = register_counter!(opts!(
+        TASK_COMPLETE_COUNT,
+        "Number of tasks completed."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register task_complete_count counter");
+
+    agent_metrics
+        .insert(TASK_COMPLETE_COUNT, Metrics::MetricsCounter(tasks_complete));
+
+    let tasks_failed = register_counter_vec!(
+        opts!(TASK_FAILED_COUNT, "Number of tasks failed, by error class.")
+            .const_labels(labels.clone()),
+        &["error"]
+    )
+    .expect("failed to register task_failed_count counter");
+
+    agent_metrics
+        .insert(TASK_FAILED_COUNT, Metrics::MetricsCounterVec(tasks_failed));
+
+    let download_bytes = register_counter_vec!(
+        opts!(
+            DOWNLOAD_BYTES,
+            "Bytes downloaded, by the outcome of the download."
+        )
+        .const_labels(labels.clone()),
+        &["outcome"]
+    )
+    .expect("failed to register download_bytes counter");
+
+    agent_metrics
+        .insert(DOWNLOAD_BYTES, Metrics::MetricsCounterVec(download_bytes));
+
+    let transfer_bytes = register_counter_vec!(
+        opts!(
+            TRANSFER_BYTES,
+            "Bytes received from sources for successful downloads, by the \
+             content encoding that they were sent in."
+        )
+        .const_labels(labels.clone()),
+        &["encoding"]
+    )
+    .expect("failed to register transfer_bytes counter");
+
+    agent_metrics
+        .insert(TRANSFER_BYTES, Metrics::MetricsCounterVec(transfer_bytes));
+
+    let active_assignments = register_gauge!(opts!(
+        ACTIVE_ASSIGNMENTS,
+        "Number of assignments being processed."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register active_assignments gauge");
+
+    agent_metrics.insert(
+        ACTIVE_ASSIGNMENTS,
+        Metrics::MetricsGauge(active_assignments),
+    );
+
+    let staging_bytes = register_gauge!(opts!(
+        STAGING_BYTES,
+        "Bytes of objects being downloaded, or waiting to be verified, in \
+         the staging directory."
+    )
+    .const_labels(labels.clone()))
+    .expect("failed to register staging_bytes gauge");
+
+    agent_metrics.insert(STAGING_BYTES, Metrics::MetricsGauge(staging_bytes));
+
+    let reaped_count = register_counter_vec!(
+        opts!(
+            REAPED_COUNT,
+            "Completed assignments and stale downloads removed by the reaper."
+        )
+        .const_labels(labels.clone()),
+        &["kind"]
+    )
+    .expect("failed to register reaped_count counter");
+
+    agent_metrics
+        .insert(REAPED_COUNT, Metrics::MetricsCounterVec(reaped_count));
+
+    let reaped_bytes = register_counter_vec!(
+        opts!(
+            REAPED_BYTES,
+            "Bytes of files removed by the reaper, by kind."
+        )
+        .const_labels(labels.clone()),
+        &["kind"]
+    )
+    .expect("failed to register reaped_bytes counter");
+
+    agent_metrics
+        .insert(REAPED_BYTES, Metrics::MetricsCounterVec(reaped_bytes));
+
+    let scrubbed = register_counter_vec!(
+        opts!(SCRUB_COUNT, "Objects checked by the scrubber, by outcome.")
+            .const_labels(labels),
+        &["outcome"]
+    )
+    .expect("failed to register scrub_count counter");
+
+    agent_metrics.insert(SCRUB_COUNT, Metrics::MetricsCounterVec(scrubbed));
+
+    let staging_metrics = agent_metrics.clone();
+    thread::Builder::new()
+        .name(String::from("Staging Usage"))
+        .spawn(move || loop {
+            let bytes = staging_bytes_used(REBALANCER_TEMP_DIR);
+            gauge_set(&staging_metrics, STAGING_BYTES, bytes as usize);
+            thread::sleep(STAGING_BYTES_INTERVAL);
+        })
+        .expect("failed to start staging usage thread");
+
+    let metrics_config = config.metrics.clone();
 
     let ms = thread::Builder::new()
         .name(String::from("Rebalancer Metrics"))
         .spawn(move || {
-            metrics::start_server(
-                &metrics_host,
-                metrics_port,
-                &slog_scope::logger(),
-            )
+            metrics::start_emitter(&metrics_config, &slog_scope::logger())
         });
 
     assert!(ms.is_ok());
@@ -1130,29 +3059,114 @@ fn agent_start_metrics_server(config: &AgentConfig) -> MetricsMap {
 // consumers, namely the rebalancer zone test framework.
 #[allow(clippy::many_single_char_names)]
 pub fn router(
-    f: fn(&mut Task, &Client, &Option<MetricsMap>),
+    f: fn(&mut Task, &dyn Transfer, &Option<MetricsMap>),
     config: Option<AgentConfig>,
 ) -> Router {
+    let effective = config.clone().unwrap_or_default().effective().to_string();
+
     build_simple_router(|route| {
         let mut agent_metrics: Option<MetricsMap> = None;
         let mut workers = 1;
         let mut workers_per_assignment = 1;
+        let mut max_workers_per_assignment = None;
+        let mut verify_workers_per_assignment = 1;
+        let mut verify_queue_depth = default_verify_queue_depth();
+        let mut throttle: Option<Arc<CpuThrottle>> = None;
+        let mut min_staging_free_mb = default_min_staging_free_mb();
+        let mut zfs_quota_aware = false;
+        let mut space_headroom_percent = default_space_headroom_percent();
+        let mut task_order = TaskOrder::default();
+        let mut max_tasks = None;
+        let mut retry = ConfigRetry::default();
+        let mut sampler_config = ConfigSampler::default();
+        let mut transfer_config = ConfigTransfer::default();
+        let mut reaper_config = ConfigReaper::default();
+        let mut registration_config = ConfigRegistration::default();
+        let mut scrubber_config = ConfigScrubber::default();
+        let mut slow_task = None;
 
         if let Some(c) = config {
             agent_metrics = Some(agent_start_metrics_server(&c));
             workers = c.server.workers;
             workers_per_assignment = c.server.workers_per_assignment;
+            max_workers_per_assignment = c.server.max_workers_per_assignment;
+            verify_workers_per_assignment =
+                c.server.verify_workers_per_assignment;
+            verify_queue_depth = c.server.verify_queue_depth;
+            min_staging_free_mb = c.server.min_staging_free_mb;
+            zfs_quota_aware = c.server.zfs_quota_aware;
+            space_headroom_percent = c.server.space_headroom_percent;
+            task_order = c.server.task_order;
+            max_tasks = c.server.max_tasks_per_assignment;
+            retry = c.retry;
+            sampler_config = c.sampler;
+            transfer_config = c.transfer;
+            reaper_config = c.reaper;
+            registration_config = c.registration;
+            scrubber_config = c.scrubber;
+            slow_task = c.server.slow_task_secs.map(Duration::from_secs);
+
+            if let Some(pct) = c.server.max_cpu_percent {
+                assert!(pct > 0 && pct <= 100);
+                throttle = Some(Arc::new(CpuThrottle::new(pct)));
+            }
         }
 
         assert!(workers > 0 && workers_per_assignment > 0);
+        assert!(max_workers_per_assignment != Some(0));
+        assert!(verify_workers_per_assignment > 0 && verify_queue_depth > 0);
+
+        // With a CPU ceiling in place, there is no sense in running more
+        // verify threads than the number of CPUs that we are entitled to,
+        // since that is where the bulk of the hashing happens.  Pacing takes
+        // care of the rest.
+        if let Some(th) = &throttle {
+            let allowed =
+                th.max_workers(workers * verify_workers_per_assignment);
+            let per_assignment = std::cmp::max(1, allowed / workers);
+
+            if per_assignment < verify_workers_per_assignment {
+                info!(
+                    "CPU ceiling limits verify workers per assignment from {} \
+                     to {}",
+                    verify_workers_per_assignment, per_assignment
+                );
+                verify_workers_per_assignment = per_assignment;
+            }
+        }
+
+        let reaper = Arc::new(Reaper::new(
+            reaper_config,
+            REBALANCER_FINISHED_DIR,
+            REBALANCER_TEMP_DIR,
+        ));
 
         let (w, r): (mpsc::Sender<String>, mpsc::Receiver<String>) =
             mpsc::channel();
         let tx = Arc::new(Mutex::new(w));
         let rx = Arc::new(Mutex::new(r));
-        let agent = Agent::new(tx, Arc::new(Mutex::new(agent_metrics.clone())));
+        let agent = Agent::new(
+            tx,
+            Arc::new(Mutex::new(agent_metrics.clone())),
+            space_headroom_percent,
+            zfs_quota_aware,
+            task_order,
+            max_tasks,
+            Arc::clone(&reaper),
+        );
         let pool = ThreadPool::new(workers);
 
+        let sampler = Arc::new(Sampler::new(sampler_config));
+        if sampler.enabled() {
+            let sa = Arc::clone(&sampler);
+            let m = agent_metrics.clone();
+            let client = reqwest::Client::new();
+            thread::Builder::new()
+                .name(String::from("Rebalancer Sampler"))
+                .spawn(move || sa.run(&client, &m))
+                .expect("failed to start sampler thread");
+        }
+
         create_dir(REBALANCER_SCHEDULED_DIR);
         create_dir(REBALANCER_FINISHED_DIR);
 
@@ -1165,12 +3179,77 @@ pub fn router(
 
         create_dir(REBALANCER_TEMP_DIR);
 
+        // Corrupt objects are reported to the manager that the agent
+        // registers with, if any.
+        let scrubber = Arc::new(Scrubber::new(
+            scrubber_config,
+            "/manta",
+            REBALANCER_SCRUB_LEDGER,
+            registration_config.agent_url("corrupt"),
+        ));
+        if scrubber.enabled() {
+            let sc = Arc::clone(&scrubber);
+            let m = agent_metrics.clone();
+            let client = reqwest::Client::new();
+            thread::Builder::new()
+                .name(String::from("Rebalancer Scrubber"))
+                .spawn(move || sc.run(&client, &m))
+                .expect("failed to start scrubber thread");
+        }
+
+        if reaper.enabled() {
+            let re = Arc::clone(&reaper);
+            let m = agent_metrics.clone();
+            thread::Builder::new()
+                .name(String::from("Rebalancer Reaper"))
+                .spawn(move || re.run(&m))
+                .expect("failed to start reaper thread");
+        }
+
+        // The download workers are shared by all of the assignments that are
+        // being processed, so that the workers that one assignment has no
+        // more tasks for can help with those of the others.
+        let download_workers = workers * workers_per_assignment;
+        let board = Arc::new(TaskBoard::new(
+            max_workers_per_assignment.unwrap_or(download_workers),
+        ));
+
+        faults::load_env();
+        let transfers = faults::transfers(transfer::worker_transfers(
+            &transfer_config,
+            download_workers,
+        ));
+
+        for (i, tr) in transfers.into_iter().enumerate() {
+            let bo = Arc::clone(&board);
+            let m = agent_metrics.clone();
+            let th = throttle.clone();
+            let ca = Arc::clone(&agent.cancelled);
+            thread::Builder::new()
+                .name(format!("Rebalancer Download {}", i))
+                .spawn(move || {
+                    download_worker(
+                        &bo, f, &m, &*tr, &th, &ca, retry, slow_task,
+                    )
+                })
+                .expect("failed to start download thread");
+        }
+
         for _ in 0..workers {
             let rx = Arc::clone(&rx);
             let assignments = Arc::clone(&agent.assignments);
             let m = agent_metrics.clone();
-            let client = reqwest::Client::new();
-            let worker_pool = ThreadPool::new(workers_per_assignment);
+            let bo = Arc::clone(&board);
+            let pipeline = TaskPipeline::new(
+                verify_workers_per_assignment,
+                verify_queue_depth,
+                Arc::clone(&sampler),
+                Arc::clone(&scrubber),
+                slow_task,
+            );
+            let th = throttle.clone();
+            let ca = Arc::clone(&agent.cancelled);
+            let rs = Arc::clone(&agent.reservations);
 
             pool.execute(move || loop {
                 let uuid = match rx.lock().unwrap().recv() {
@@ -1182,17 +3261,70 @@ pub fn router(
                 };
                 process_assignment(
                     Arc::clone(&assignments),
-                    uuid,
-                    f,
+                    uuid.clone(),
                     m.clone(),
-                    &client,
-                    &worker_pool,
+                    &bo,
+                    &pipeline,
+                    &th,
+                    &ca,
                 );
+
+                // One way or another, the assignment is finished with, and
+                // the space set aside for it is free for others.
+                rs.lock().unwrap().remove(&uuid);
             });
         }
 
         discover_saved_assignments(&agent);
 
+        // Register only once the assignments saved by an earlier run have
+        // been taken up, so that the manager sees this run's boot id when the
+        // agent is ready for more.
+        if let Some(registrar) = Registrar::new(
+            &registration_config,
+            &agent_version(),
+            &agent.boot_id,
+            AgentCapabilities::new(max_tasks),
+        ) {
+            let client = reqwest::Client::new();
+            thread::Builder::new()
+                .name(String::from("Rebalancer Registrar"))
+                .spawn(move || registrar.run(&client))
+                .expect("failed to start registrar thread");
+        }
+
+        route
+            .get("/config")
+            .to_new_handler(ConfigHandler(effective));
+
+        route.get("/ping").to(ping);
+
+        #[cfg(feature = "faults")]
+        route
+            .request(vec![Method::GET, Method::PUT, Method::DELETE], "/faults")
+            .to(faults::faults_handler);
+
+        route
+            .get("/objects/:owner/:object")
+            .with_path_extractor::<ObjectParams>()
+            .to(get_object);
+
+        route
+            .get("/samples")
+            .to_new_handler(SamplesHandler(Arc::clone(&sampler)));
+
+        route
+            .get("/scrub")
+            .to_new_handler(ScrubHandler(Arc::clone(&scrubber)));
+
+        route
+            .get("/healthcheck")
+            .to_new_handler(HealthcheckHandler {
+                agent: agent.clone(),
+                min_staging_free_mb,
+                zfs_quota_aware,
+            });
+
         route.scope("/assignments", |route| {
             // Associations allow a single path to be matched to multiple
             // HTTP verbs with each delegating to the handler of our choice.
@@ -1208,6 +3340,16 @@ pub fn router(
                     .to_new_handler(agent.clone());
             });
 
+            route
+                .post("/:uuid/cancel")
+                .with_path_extractor::<GetAssignmentParams>()
+                .to_new_handler(CancelHandler(agent.clone()));
+
+            route
+                .get("")
+                .with_query_string_extractor::<HeldAssignmentsParams>()
+                .to_new_handler(HeldHandler(agent.clone()));
+
             route.post("").to_new_handler(agent.clone());
         })
     })
@@ -1,8 +1,10 @@
 // Copyright 2020 Joyent, Inc.
 
 use std::collections::HashMap;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
 use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
 use gethostname::gethostname;
 use hyper::header::{HeaderValue, CONTENT_TYPE};
@@ -13,12 +15,15 @@ use hyper::Body;
 use hyper::StatusCode;
 use hyper::{Request, Response};
 use lazy_static::lazy_static;
+use prometheus::proto::{MetricFamily, MetricType};
 use prometheus::{
     opts, register_counter, register_counter_vec, register_histogram, Counter,
-    CounterVec, Encoder, Gauge, Histogram, TextEncoder,
+    CounterVec, Encoder, Gauge, Histogram, HistogramVec, TextEncoder,
 };
-use serde_derive::Deserialize;
-use slog::{error, info, Logger};
+use serde_derive::{Deserialize, Serialize};
+use slog::{error, info, warn, Logger};
+
+use crate::config_schema::ConfigCheck;
 
 pub type MetricsMap = HashMap<&'static str, Metrics>;
 
@@ -27,8 +32,37 @@ pub static ERROR_COUNT: &str = "error_count";
 pub static REQUEST_COUNT: &str = "request_count";
 pub static BYTES_COUNT: &str = "bytes_count";
 pub static ASSIGNMENT_TIME: &str = "assignment_time";
+pub static OBJECT_SIZE: &str = "object_size_bytes";
+pub static OBJECT_SIZE_FAILED: &str = "object_size_failed_bytes";
+
+// Values of the "outcome" label of per task timing histograms.
+pub static OUTCOME_SUCCESS: &str = "success";
+pub static OUTCOME_FAILURE: &str = "failure";
+
+// The largest statsd datagram sent.  This keeps each one within the MTU of
+// an ethernet link once the IP and UDP headers are added.
+const STATSD_MAX_DATAGRAM: usize = 1432;
+
+/// How the metrics are made available.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsMode {
+    /// Served over HTTP to be scraped.
+    Http,
+    /// Pushed to a Prometheus Pushgateway.
+    Pushgateway,
+    /// Sent as statsd packets over UDP.
+    Statsd,
+}
 
-#[derive(Clone, Deserialize)]
+impl Default for MetricsMode {
+    fn default() -> Self {
+        MetricsMode::Http
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct ConfigMetrics {
     /// Rebalancer metrics server address
     pub host: String,
@@ -37,6 +71,13 @@ pub struct ConfigMetrics {
     pub datacenter: String,
     pub service: String,
     pub server: String,
+    pub mode: MetricsMode,
+    /// Base URL of the Pushgateway, in pushgateway mode
+    pub push_url: String,
+    /// Address (host:port) of the statsd server, in statsd mode
+    pub statsd_address: String,
+    /// Seconds between pushes, in pushgateway and statsd modes
+    pub push_interval_secs: u64,
 }
 
 impl Default for ConfigMetrics {
@@ -47,7 +88,36 @@ impl Default for ConfigMetrics {
             datacenter: "development".into(),
             service: "1.rebalancer.localhost".into(),
             server: "127.0.0.1".into(),
+            mode: MetricsMode::default(),
+            push_url: String::new(),
+            statsd_address: String::new(),
+            push_interval_secs: 15,
+        }
+    }
+}
+
+impl ConfigMetrics {
+    /// Check the settings used by the configured mode.
+    pub fn check(&self, check: &mut ConfigCheck) {
+        match self.mode {
+            MetricsMode::Http => check.port("metrics.port", self.port),
+            MetricsMode::Pushgateway => check.ensure(
+                self.push_url.starts_with("http://")
+                    || self.push_url.starts_with("https://"),
+                "metrics.push_url",
+                "must be the http:// or https:// URL of a Pushgateway in \
+                 pushgateway mode",
+            ),
+            MetricsMode::Statsd => {
+                check.host_port("metrics.statsd_address", &self.statsd_address)
+            }
         }
+
+        check.ensure(
+            self.mode == MetricsMode::Http || self.push_interval_secs >= 1,
+            "metrics.push_interval_secs",
+            "must be at least 1",
+        );
     }
 }
 
@@ -61,6 +131,7 @@ pub enum Metrics {
     MetricsCounter(Counter),
     MetricsGauge(Gauge),
     MetricsHistogram(Histogram),
+    MetricsHistogramVec(HistogramVec),
 }
 
 lazy_static! {
@@ -113,6 +184,20 @@ pub fn gauge_set<S: ::std::hash::BuildHasher>(
     }
 }
 
+pub fn gauge_get<S: ::std::hash::BuildHasher>(
+    metrics: &HashMap<&'static str, Metrics, S>,
+    key: &str,
+) -> f64 {
+    match metrics.get(key) {
+        Some(Metrics::MetricsGauge(g)) => g.get(),
+        Some(_) => 0.0,
+        None => {
+            error!(slog_scope::logger(), "Invalid metric: {}", key);
+            0.0
+        }
+    }
+}
+
 #[allow(irrefutable_let_patterns)]
 pub fn counter_vec_inc<S: ::std::hash::BuildHasher>(
     metrics: &HashMap<&'static str, Metrics, S>,
@@ -180,12 +265,65 @@ pub fn histogram_observe<S: ::std::hash::BuildHasher>(
     }
 }
 
+pub fn histogram_vec_observe<S: ::std::hash::BuildHasher>(
+    metrics: &HashMap<&'static str, Metrics, S>,
+    key: &str,
+    label: &str,
+    val: f64,
+) {
+    match metrics.get(key) {
+        Some(metric) => {
+            if let Metrics::MetricsHistogramVec(h) = metric {
+                h.with_label_values(&[label]).observe(val);
+            }
+        }
+        None => error!(slog_scope::logger(), "Invalid metric: {}", key),
+    }
+}
+
 // It would be nice if this could be a HashMap<&str, &str>, however Prometheus
 // requires the type HashMap<String, String>, for const_labels, so here we are.
 pub fn get_const_labels() -> &'static Mutex<Option<HashMap<String, String>>> {
     &METRICS_LABELS
 }
 
+// Change the values of the service, server and datacenter labels.  The
+// metrics already registered keep the values that they were registered with,
+// and are given the new ones as they are gathered (see gather()).
+pub fn set_const_labels(config: &ConfigMetrics) {
+    let mut labels = METRICS_LABELS.lock().unwrap();
+
+    if let Some(labels) = labels.as_mut() {
+        labels.insert("service".to_string(), config.service.clone());
+        labels.insert("server".to_string(), config.server.clone());
+        labels.insert("datacenter".to_string(), config.datacenter.clone());
+    }
+}
+
+// Gather all metrics from the default registry, with the constant labels
+// set to their current values.
+pub fn gather() -> Vec<MetricFamily> {
+    let mut families = prometheus::gather();
+    let labels = match METRICS_LABELS.lock().unwrap().clone() {
+        Some(l) => l,
+        None => return families,
+    };
+
+    for family in families.iter_mut() {
+        for metric in family.mut_metric().iter_mut() {
+            for label in metric.mut_label().iter_mut() {
+                if let Some(value) = labels.get(label.get_name()) {
+                    if label.get_value() != value {
+                        label.set_value(value.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    families
+}
+
 // Given the service configuration information, create (i.e. register) the
 // desired metrics with prometheus.
 pub fn register_metrics(labels: &ConfigMetrics) -> MetricsMap {
@@ -236,6 +374,8 @@ pub fn register_metrics(labels: &ConfigMetrics) -> MetricsMap {
     // different kinds of possible errors that a given application could
     // encounter and in the event that there are too many possibilities, only
     // track certain error types and maintain the rest in a generic bucket.
+    // The manager and agent label it with the code of each error (see
+    // RebalancerError), of which there are a fixed number.
     let error_counter = register_counter_vec!(
         opts!(ERROR_COUNT, "Errors encountered.")
             .const_labels(const_labels.clone()),
@@ -257,12 +397,41 @@ pub fn register_metrics(labels: &ConfigMetrics) -> MetricsMap {
         ASSIGNMENT_TIME,
         "Assignment completion time"
     )
-    .const_labels(const_labels))
+    .const_labels(const_labels.clone()))
     .expect("failed to register assignment_times counter");
 
     metrics
         .insert(ASSIGNMENT_TIME, Metrics::MetricsHistogram(assignment_times));
 
+    // The size distribution of the objects that have been moved, and
+    // separately of those that could not be.  The buckets go up by a factor
+    // of ten from 1KB to 100GB.
+    let size_buckets = prometheus::exponential_buckets(1_000.0, 10.0, 9)
+        .expect("object size buckets");
+
+    let object_sizes = register_histogram!(histogram_opts!(
+        OBJECT_SIZE,
+        "Size of objects moved",
+        size_buckets.clone()
+    )
+    .const_labels(const_labels.clone()))
+    .expect("failed to register object_size_bytes histogram");
+
+    metrics.insert(OBJECT_SIZE, Metrics::MetricsHistogram(object_sizes));
+
+    let failed_object_sizes = register_histogram!(histogram_opts!(
+        OBJECT_SIZE_FAILED,
+        "Size of objects that could not be moved",
+        size_buckets
+    )
+    .const_labels(const_labels))
+    .expect("failed to register object_size_failed_bytes histogram");
+
+    metrics.insert(
+        OBJECT_SIZE_FAILED,
+        Metrics::MetricsHistogram(failed_object_sizes),
+    );
+
     metrics
 }
 
@@ -279,7 +448,7 @@ pub fn start_server(address: &str, port: u16, log: &Logger) {
         .serve(move || {
             service_fn_ok(move |_: Request<Body>| {
                 // Gather all metrics from the default registry.
-                let metric_families = prometheus::gather();
+                let metric_families = gather();
                 let mut buffer = vec![];
 
                 // Convert the MetricFamily message into text format and store
@@ -306,3 +475,212 @@ pub fn start_server(address: &str, port: u16, log: &Logger) {
 
     rt::run(server);
 }
+
+// Make the metrics available in the configured mode.  In http mode this
+// starts the metrics server, and in the other modes it pushes all registered
+// metrics every `push_interval_secs`.  None of these return.
+pub fn start_emitter(config: &ConfigMetrics, log: &Logger) {
+    let interval = Duration::from_secs(config.push_interval_secs.max(1));
+
+    match config.mode {
+        MetricsMode::Http => start_server(&config.host, config.port, log),
+        MetricsMode::Pushgateway => {
+            if config.push_url.is_empty() {
+                error!(log, "metrics mode is pushgateway but no push_url");
+                return;
+            }
+            push_gateway_loop(&config.push_url, interval, log)
+        }
+        MetricsMode::Statsd => {
+            if config.statsd_address.is_empty() {
+                error!(log, "metrics mode is statsd but no statsd_address");
+                return;
+            }
+            statsd_loop(&config.statsd_address, interval, log)
+        }
+    }
+}
+
+// Push the metrics to a Pushgateway, grouped under this zone's hostname.
+// Each push replaces the whole group, so a metric that is no longer
+// registered disappears from the gateway too.
+fn push_gateway_loop(push_url: &str, interval: Duration, log: &Logger) {
+    let hostname = gethostname()
+        .into_string()
+        .unwrap_or_else(|_| String::from("unknown"));
+    let url = format!(
+        "{}/metrics/job/rebalancer/instance/{}",
+        push_url.trim_end_matches('/'),
+        hostname
+    );
+    let client = match reqwest::Client::builder().timeout(interval).build() {
+        Ok(c) => c,
+        Err(e) => {
+            error!(log, "failed to create pushgateway client"; "error" => %e);
+            return;
+        }
+    };
+    let encoder = TextEncoder::new();
+
+    info!(log, "pushing metrics"; "url" => &url);
+
+    loop {
+        let mut buffer = vec![];
+        encoder.encode(&gather(), &mut buffer).unwrap();
+
+        match client
+            .put(&url)
+            .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+            .body(buffer)
+            .send()
+        {
+            Ok(resp) => {
+                if !resp.status().is_success() {
+                    warn!(log, "metrics push rejected";
+                        "status" => %resp.status());
+                }
+            }
+            Err(e) => warn!(log, "metrics push failed"; "error" => %e),
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+// Send the metrics to a statsd server.  Counters are sent as the amount that
+// they have gone up by since the last push, gauges as their value, and
+// histograms as the count and sum of their observations (counters too).
+fn statsd_loop(address: &str, interval: Duration, log: &Logger) {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|s| s.connect(address).map(|_| s))
+    {
+        Ok(s) => s,
+        Err(e) => {
+            error!(log, "failed to create statsd socket";
+                "address" => address, "error" => %e);
+            return;
+        }
+    };
+    let mut last_counts: HashMap<String, f64> = HashMap::new();
+
+    info!(log, "sending metrics to statsd"; "address" => address);
+
+    loop {
+        let lines = statsd_lines(&gather(), &mut last_counts);
+
+        for datagram in statsd_datagrams(&lines) {
+            if let Err(e) = socket.send(datagram.as_bytes()) {
+                warn!(log, "statsd send failed"; "error" => %e);
+                break;
+            }
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+// The statsd lines for the metric families, updating `last_counts` with the
+// value of each counter sent.
+fn statsd_lines(
+    families: &[MetricFamily],
+    last_counts: &mut HashMap<String, f64>,
+) -> Vec<String> {
+    // The constant labels are the same for every metric, so they are left out
+    // of the names.
+    let const_labels: Vec<String> = METRICS_LABELS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|l| l.keys().cloned().collect())
+        .unwrap_or_default();
+    let mut lines = vec![];
+
+    let mut delta = |name: String, value: f64, lines: &mut Vec<String>| {
+        let last = last_counts.insert(name.clone(), value).unwrap_or(0.0);
+        // A counter that went down was reset, so all of it is new.
+        let diff = if value >= last { value - last } else { value };
+        if diff > 0.0 {
+            lines.push(format!("{}:{}|c", name, diff));
+        }
+    };
+
+    for family in families {
+        for metric in family.get_metric() {
+            let mut name =
+                format!("rebalancer.{}", statsd_escape(family.get_name()));
+            for label in metric.get_label() {
+                if const_labels.iter().any(|c| c == label.get_name()) {
+                    continue;
+                }
+                name.push('.');
+                name.push_str(&statsd_escape(label.get_name()));
+                name.push('.');
+                name.push_str(&statsd_escape(label.get_value()));
+            }
+
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    delta(name, metric.get_counter().get_value(), &mut lines)
+                }
+                MetricType::GAUGE => lines.push(format!(
+                    "{}:{}|g",
+                    name,
+                    metric.get_gauge().get_value()
+                )),
+                MetricType::HISTOGRAM => {
+                    let h = metric.get_histogram();
+                    delta(
+                        format!("{}.count", name),
+                        h.get_sample_count() as f64,
+                        &mut lines,
+                    );
+                    delta(
+                        format!("{}.sum", name),
+                        h.get_sample_sum(),
+                        &mut lines,
+                    );
+                }
+                _ => (),
+            }
+        }
+    }
+
+    lines
+}
+
+// Pack the lines in to as few datagrams as will hold them, one per line.
+fn statsd_datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams = vec![];
+    let mut current = String::new();
+
+    for line in lines {
+        if !current.is_empty()
+            && current.len() + 1 + line.len() > STATSD_MAX_DATAGRAM
+        {
+            datagrams.push(std::mem::replace(&mut current, String::new()));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+// Replace the characters that statsd gives a meaning to in a name.
+fn statsd_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,130 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Bringing the agent's sqlite databases up to date with the agent.
+//
+// These are the manager's migrations (see the manager's migrations module)
+// for the files that the agent keeps: each saved assignment, and the
+// scrubber's ledger.  A database has a list of migrations, numbered from 1,
+// and records each one that has been applied to it in its schema_migrations
+// table.  migrate() applies those that have yet to be, in order, all in a
+// single transaction along with their entries, which takes the database's
+// write lock before it looks at what has been applied, so that two threads
+// never migrate the same file at once.
+//
+// An assignment saved by an agent that predates migrations already has some
+// of them applied, with no schema_migrations table to say so, so the
+// migrations of that time are written to do nothing where what they do has
+// been done (see add_column()).  Later ones need not be.  A database that has
+// had migrations applied that this agent does not know of was migrated by a
+// newer agent, which is logged, and the database is used as it is.
+
+use crate::util::now_ms;
+
+use std::collections::HashSet;
+
+use rusqlite::{Connection, TransactionBehavior};
+
+/// A change to the schema of a database.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// Add `column`, declared as `decl`, to `table`, unless it is already there.
+pub fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    if conn
+        .prepare(&format!("SELECT {} FROM {}", column, table))
+        .is_ok()
+    {
+        return Ok(());
+    }
+
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+        rusqlite::params![],
+    )?;
+    Ok(())
+}
+
+/// Apply each of `migrations` that has yet to be applied to `conn`, in order
+/// of their versions.  Returns the versions that were applied.
+pub fn migrate(
+    conn: &mut Connection,
+    migrations: &[Migration],
+) -> rusqlite::Result<Vec<i64>> {
+    let transaction =
+        conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied INTEGER NOT NULL
+        )",
+        rusqlite::params![],
+    )?;
+
+    let mut applied = HashSet::new();
+    {
+        let mut stmt =
+            transaction.prepare("SELECT version FROM schema_migrations")?;
+        let versions =
+            stmt.query_map(rusqlite::params![], |row| row.get::<_, i64>(0))?;
+
+        for v in versions {
+            applied.insert(v?);
+        }
+    }
+
+    let known: HashSet<i64> = migrations.iter().map(|m| m.version).collect();
+    if let Some(newest) = applied.difference(&known).max() {
+        warn!(
+            "Database has migration {} applied, which this version does not \
+             know of",
+            newest
+        );
+    }
+
+    let mut pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect();
+    pending.sort_by_key(|m| m.version);
+
+    let mut done = vec![];
+    for migration in pending {
+        debug!(
+            "Applying database migration {}: {}",
+            migration.version, migration.description
+        );
+        (migration.up)(&transaction)?;
+
+        transaction.execute(
+            "INSERT INTO schema_migrations (version, description, applied)
+            VALUES (?1, ?2, ?3)",
+            rusqlite::params![
+                migration.version,
+                migration.description,
+                now_ms()
+            ],
+        )?;
+        done.push(migration.version);
+    }
+
+    transaction.commit()?;
+    Ok(done)
+}
@@ -0,0 +1,178 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Telling the service manager when the manager or agent is ready.
+//
+// The API server and the metrics server are each started on a thread of
+// their own, and both are started late: the manager sets up its database and
+// recovers and resumes jobs first.  A service manager that takes the process
+// being started for it being ready would send requests its way that it is not
+// yet able to serve.  Instead, once the database is set up, the process waits
+// until it can connect to each of the servers that it is starting, and only
+// then says that it is ready:
+//
+//  * Under systemd (with Type=notify), NOTIFY_SOCKET names the socket to send
+//    "READY=1" to, as sd_notify(3) would.
+//  * Under SMF, a contract service is online once its start method exits.
+//    The start method (smf/methods/rebalancer-start) starts the process with
+//    REBALANCER_READY_FILE naming a file to create once it is ready, and only
+//    exits once that file has appeared, so the service stays offline until
+//    then.  If the process exits first, or the file does not appear in time,
+//    the start method fails and SMF takes the service to maintenance.
+//
+// While shutting down, the process reports its progress in draining (see
+// status() and stopping()): systemd shows it as the service's status, and
+// under SMF it can be read from the ready file, which is removed just before
+// the process exits.
+
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixDatagram;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub static NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+pub static READY_FILE_ENV: &str = "REBALANCER_READY_FILE";
+
+// How long to wait for the servers to start listening before giving up.
+static LISTEN_TIMEOUT: Duration = Duration::from_secs(60);
+
+// How often to try to connect to a server that is not yet listening.
+static LISTEN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Start a thread that says that the process is ready once each of the
+/// servers at `addrs` (as "<host>:<port>") can be connected to.  A server
+/// listening on the unspecified address is connected to on the loopback
+/// address.  If the servers are not all listening within a minute, the
+/// process never says that it is ready, and the service manager deals with
+/// it as it would with any other service that failed to start.
+pub fn notify_when_listening(addrs: Vec<String>) -> thread::JoinHandle<()> {
+    thread::Builder::new()
+        .name(String::from("readiness"))
+        .spawn(move || {
+            let deadline = Instant::now() + LISTEN_TIMEOUT;
+
+            for addr in addrs.iter() {
+                if let Err(e) = wait_for_listener(addr, deadline) {
+                    error!("Not notifying readiness: {}", e);
+                    return;
+                }
+            }
+
+            ready();
+        })
+        .expect("start readiness thread")
+}
+
+/// Say that the process is ready to serve requests.
+pub fn ready() {
+    info!("Ready");
+    notify("READY=1\nSTATUS=Ready");
+    write_ready_file("ready");
+}
+
+/// Say that the process is shutting down.
+pub fn stopping(status: &str) {
+    info!("Stopping: {}", status);
+    notify(&format!("STOPPING=1\nSTATUS={}", status));
+    write_ready_file(&format!("stopping: {}", status));
+}
+
+/// Report how a shutdown is getting on, e.g. how much is left to drain.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status));
+    write_ready_file(&format!("stopping: {}", status));
+}
+
+/// Say, just before exiting, that the process is no longer running.
+pub fn stopped() {
+    if let Ok(path) = env::var(READY_FILE_ENV) {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Error removing ready file {}: {}", path, e);
+        }
+    }
+}
+
+fn wait_for_listener(addr: &str, deadline: Instant) -> Result<(), String> {
+    let target = connectable(addr)?;
+
+    loop {
+        if TcpStream::connect_timeout(&target, CONNECT_TIMEOUT).is_ok() {
+            debug!("{} is listening", addr);
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            return Err(format!("{} is not listening", addr));
+        }
+
+        thread::sleep(LISTEN_CHECK_INTERVAL);
+    }
+}
+
+// The address at which to connect to a server listening on `addr`.
+fn connectable(addr: &str) -> Result<SocketAddr, String> {
+    let mut target = addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolving {}: {}", addr, e))?
+        .next()
+        .ok_or_else(|| format!("{} has no address", addr))?;
+
+    if target.ip().is_unspecified() {
+        target.set_ip(if target.is_ipv4() {
+            [127, 0, 0, 1].into()
+        } else {
+            [0, 0, 0, 0, 0, 0, 0, 1].into()
+        });
+    }
+
+    Ok(target)
+}
+
+// Send `state` to systemd, if it is listening for it.
+fn notify(state: &str) {
+    let path = match env::var(NOTIFY_SOCKET_ENV) {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    // The standard library does not support sockets in the abstract
+    // namespace.
+    if path.starts_with('@') {
+        warn!("Not notifying abstract socket {}", path);
+        return;
+    }
+
+    if let Err(e) = UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to(state.as_bytes(), &path))
+    {
+        warn!("Error notifying {}: {}", path, e);
+    }
+}
+
+// Replace the contents of the ready file, if the start method asked for one.
+// The file is written in full before it is put in place, so the start method
+// never sees a partly written one.
+fn write_ready_file(contents: &str) {
+    let path = match env::var(READY_FILE_ENV) {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    let tmp = format!("{}.tmp", path);
+    if let Err(e) = fs::write(&tmp, format!("{}\n", contents))
+        .and_then(|_| fs::rename(&tmp, &path))
+    {
+        warn!("Error writing ready file {}: {}", path, e);
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Clearing away what the agent has finished with.
+//
+// Each assignment that the agent completes is kept in the completed directory
+// so that the manager can get its results, and the manager never deletes it
+// once it has them.  Left alone, these pile up for as long as the agent runs,
+// as do any objects left behind in the staging directory by a download that
+// never finished (e.g. one whose worker panicked part way through).  The
+// staging directory is emptied when the agent starts, but the completed
+// directory is not.
+//
+// Every `reaper.interval_secs` (unless it is 0), the reaper:
+//
+//  * Removes each completed assignment that has been reported to a client
+//    (i.e. got with GET /assignments/<uuid> once it was complete) at least
+//    `reaper.retention_secs` ago.  The manager only asks again for an
+//    assignment that it already has the results of if it is restarted part
+//    way through processing them, so the retention need only cover that.
+//  * Removes each completed assignment that was completed at least
+//    `reaper.max_age_secs` ago, whether or not it was reported.  Which
+//    assignments have been reported is only held in memory, so this is what
+//    clears away those that were reported before the agent was restarted.
+//  * Removes each file in the staging directory that has not been written to
+//    for `reaper.stale_download_secs`.  An object that is being downloaded is
+//    written to all along, and one that has been downloaded only waits to be
+//    verified for as long as it takes to verify those queued ahead of it, so
+//    this should be well beyond the time that verifying a full queue takes.
+//
+// What is removed is counted in the `reaped_count` and `reaped_bytes`
+// metrics, labeled by `kind` (`assignment` or `download`).
+
+use crate::metrics::{counter_vec_inc_by, MetricsMap};
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_derive::{Deserialize, Serialize};
+
+pub static REAPED_COUNT: &str = "reaped_count";
+pub static REAPED_BYTES: &str = "reaped_bytes";
+
+static DEFAULT_INTERVAL_SECS: u64 = 3600;
+static DEFAULT_RETENTION_SECS: u64 = 24 * 3600;
+static DEFAULT_MAX_AGE_SECS: u64 = 7 * 24 * 3600;
+static DEFAULT_STALE_DOWNLOAD_SECS: u64 = 3600;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigReaper {
+    // The time between passes of the reaper.  If this is 0, nothing is ever
+    // reaped.
+    pub interval_secs: u64,
+    // How long a completed assignment is kept after it was first reported.
+    pub retention_secs: u64,
+    // How long a completed assignment is kept after it was completed,
+    // whether or not it was reported.
+    pub max_age_secs: u64,
+    // How long a file in the staging directory may go without being written
+    // to before it is taken to be left over from a download that never
+    // finished.
+    pub stale_download_secs: u64,
+}
+
+impl Default for ConfigReaper {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            retention_secs: DEFAULT_RETENTION_SECS,
+            max_age_secs: DEFAULT_MAX_AGE_SECS,
+            stale_download_secs: DEFAULT_STALE_DOWNLOAD_SECS,
+        }
+    }
+}
+
+/// The number of files that one pass of the reaper removed, and the bytes
+/// that they took up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reaped {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+pub struct Reaper {
+    config: ConfigReaper,
+    finished_dir: String,
+    staging_dir: String,
+    // When each completed assignment was first reported.
+    reported: Mutex<HashMap<String, Instant>>,
+}
+
+impl Reaper {
+    pub fn new(
+        config: ConfigReaper,
+        finished_dir: &str,
+        staging_dir: &str,
+    ) -> Reaper {
+        Reaper {
+            config,
+            finished_dir: finished_dir.to_string(),
+            staging_dir: staging_dir.to_string(),
+            reported: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.interval_secs > 0
+    }
+
+    /// Note that the completed assignment `uuid` has been reported to a
+    /// client.  Only the first report counts towards its retention.
+    pub fn reported(&self, uuid: &str) {
+        self.reported
+            .lock()
+            .unwrap()
+            .entry(uuid.to_string())
+            .or_insert_with(Instant::now);
+    }
+
+    /// Reap at each interval.  This does not return.
+    pub fn run(&self, metrics: &Option<MetricsMap>) {
+        let interval = Duration::from_secs(self.config.interval_secs);
+
+        loop {
+            thread::sleep(interval);
+            self.reap(metrics);
+        }
+    }
+
+    /// Make one pass over the completed and staging directories, returning
+    /// what was removed from each.
+    pub fn reap(&self, metrics: &Option<MetricsMap>) -> (Reaped, Reaped) {
+        let assignments = self.reap_assignments();
+        let downloads = self.reap_downloads();
+
+        for (kind, reaped) in
+            &[("assignment", assignments), ("download", downloads)]
+        {
+            if reaped.files == 0 {
+                continue;
+            }
+
+            info!(
+                "Reaped {} {} file(s) taking up {} bytes",
+                reaped.files, kind, reaped.bytes
+            );
+
+            if let Some(m) = metrics {
+                counter_vec_inc_by(m, REAPED_COUNT, Some(*kind), reaped.files);
+                counter_vec_inc_by(
+                    m,
+                    REAPED_BYTES,
+                    Some(*kind),
+                    reaped.bytes as usize,
+                );
+            }
+        }
+
+        (assignments, downloads)
+    }
+
+    fn reap_assignments(&self) -> Reaped {
+        let retention = Duration::from_secs(self.config.retention_secs);
+        let max_age = Duration::from_secs(self.config.max_age_secs);
+        let mut reaped = Reaped::default();
+        let mut remaining = HashSet::new();
+
+        // Hold the lock throughout, so that an assignment can not be
+        // reported in between our deciding to keep it and forgetting that
+        // it was reported.
+        let mut reported = self.reported.lock().unwrap();
+
+        for (path, uuid, age, size) in files_in(&self.finished_dir) {
+            let retained = match reported.get(&uuid) {
+                Some(at) => at.elapsed() < retention,
+                None => true,
+            };
+
+            if retained && age < max_age {
+                remaining.insert(uuid);
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    debug!("Reaped completed assignment {}", uuid);
+                    reaped.files += 1;
+                    reaped.bytes += size;
+                }
+                Err(e) => {
+                    warn!("Unable to reap assignment {}: {}", uuid, e);
+                    remaining.insert(uuid);
+                }
+            }
+        }
+
+        // Forget those that are gone, whether reaped by us or deleted by a
+        // client.
+        reported.retain(|uuid, _| remaining.contains(uuid));
+        reaped
+    }
+
+    fn reap_downloads(&self) -> Reaped {
+        let stale = Duration::from_secs(self.config.stale_download_secs);
+        let mut reaped = Reaped::default();
+
+        for (path, name, age, size) in files_in(&self.staging_dir) {
+            if age < stale {
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    info!("Reaped stale download {}", name);
+                    reaped.files += 1;
+                    reaped.bytes += size;
+                }
+                Err(e) => warn!("Unable to reap download {}: {}", name, e),
+            }
+        }
+
+        reaped
+    }
+}
+
+// The path, name, time since it was last modified and size of each file in
+// `dir`.  Subdirectories, and anything that can not be looked at, are left
+// out.
+fn files_in(dir: &str) -> Vec<(String, String, Duration, u64)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Unable to read directory {}: {}", dir, e);
+            return vec![];
+        }
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+
+            // A file modified in the future (by our clock) is taken to have
+            // only just been modified.
+            let age = metadata
+                .modified()
+                .ok()?
+                .elapsed()
+                .unwrap_or_else(|_| Duration::from_secs(0));
+
+            Some((
+                entry.path().to_string_lossy().to_string(),
+                entry.file_name().to_string_lossy().to_string(),
+                age,
+                metadata.len(),
+            ))
+        })
+        .collect()
+}
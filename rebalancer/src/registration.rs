@@ -0,0 +1,218 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Telling the manager what this agent is, and what it can do.
+//
+// The manager used to take every agent to be able to do whatever it asked of
+// it, so that during a fleet update an agent that had yet to be upgraded was
+// sent tasks that it did not understand, and failed them.  With
+// `registration.manager_url` and `registration.storage_id` set, the agent
+// registers with the manager when it starts, and again every
+// `registration.heartbeat_secs`, with a PUT to
+// /agents/<storage_id>/registration.  The registration gives the agent's
+// version, the boot id of this run of it, and its capabilities: the actions
+// that its tasks may have, the most tasks that it takes in a single
+// assignment (`server.max_tasks_per_assignment`), if there is a limit, the
+// content encodings that it can download objects in, and whether it serves
+// its objects to other agents that fetch them directly.  The manager answers
+// with its own version.  The scrubber (see the scrubber module) reports the
+// corrupt objects that it finds to the same manager.
+//
+// A manager that has no registration for an agent (because the agent
+// predates registration, or is not configured to register) sends it whatever
+// it would have before.  A failure to register is logged, once until the
+// agent next registers, but never stops the agent.
+
+use crate::common::{ContentEncoding, TaskAction};
+
+use std::thread;
+use std::time::Duration;
+
+use serde_derive::{Deserialize, Serialize};
+
+static DEFAULT_HEARTBEAT_SECS: u64 = 60;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigRegistration {
+    // The base URL of the manager, e.g. http://rebalancer.example.com.  If
+    // this is not set, the agent does not register.
+    pub manager_url: Option<String>,
+    // The storage id of the storage node that the agent is on, as the
+    // manager knows it from storinfo.
+    pub storage_id: Option<String>,
+    // The time between registrations.
+    pub heartbeat_secs: u64,
+}
+
+impl ConfigRegistration {
+    /// The URL of `resource` of this agent on the manager, i.e.
+    /// <manager_url>/agents/<storage_id>/<resource>, unless the agent is not
+    /// configured to register.
+    pub fn agent_url(&self, resource: &str) -> Option<String> {
+        let base = self.manager_url.as_ref()?;
+        let storage_id = self.storage_id.as_ref()?;
+
+        Some(format!(
+            "{}/agents/{}/{}",
+            base.trim_end_matches('/'),
+            storage_id,
+            resource
+        ))
+    }
+}
+
+impl Default for ConfigRegistration {
+    fn default() -> Self {
+        Self {
+            manager_url: None,
+            storage_id: None,
+            heartbeat_secs: DEFAULT_HEARTBEAT_SECS,
+        }
+    }
+}
+
+/// What an agent can be asked to do.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AgentCapabilities {
+    /// The actions that the agent's tasks may have.
+    pub actions: Vec<TaskAction>,
+
+    /// The most tasks that the agent takes in a single assignment.  None if
+    /// there is no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tasks_per_assignment: Option<usize>,
+
+    /// The content encodings that the agent can download objects in, most
+    /// preferred first.  Agents that predate compressed transfers have none.
+    #[serde(default)]
+    pub encodings: Vec<ContentEncoding>,
+
+    /// The agent serves the objects on its storage node with
+    /// GET /objects/<owner>/<object>, for direct tasks.
+    #[serde(default)]
+    pub serves_objects: bool,
+}
+
+impl AgentCapabilities {
+    /// The capabilities of this agent.
+    pub fn new(max_tasks_per_assignment: Option<usize>) -> AgentCapabilities {
+        AgentCapabilities {
+            actions: vec![TaskAction::Copy, TaskAction::Delete],
+            max_tasks_per_assignment,
+            encodings: vec![ContentEncoding::Zstd, ContentEncoding::Gzip],
+            serves_objects: true,
+        }
+    }
+
+    pub fn supports(&self, action: TaskAction) -> bool {
+        self.actions.contains(&action)
+    }
+}
+
+/// The body of a registration.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AgentRegistration {
+    pub storage_id: String,
+    pub version: String,
+    pub boot_id: String,
+
+    /// How often the agent registers, so that the manager can tell when it
+    /// has stopped.
+    pub heartbeat_secs: u64,
+
+    pub capabilities: AgentCapabilities,
+}
+
+/// The manager's answer to a registration.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RegistrationAck {
+    pub manager_version: String,
+}
+
+pub struct Registrar {
+    url: String,
+    interval: Duration,
+    registration: AgentRegistration,
+}
+
+impl Registrar {
+    /// A registrar for this run of the agent, unless it is not configured to
+    /// register.
+    pub fn new(
+        config: &ConfigRegistration,
+        version: &str,
+        boot_id: &str,
+        capabilities: AgentCapabilities,
+    ) -> Option<Registrar> {
+        let url = config.agent_url("registration")?;
+        let storage_id = config.storage_id.as_ref()?;
+
+        Some(Registrar {
+            url,
+            interval: Duration::from_secs(config.heartbeat_secs),
+            registration: AgentRegistration {
+                storage_id: storage_id.clone(),
+                version: version.to_string(),
+                boot_id: boot_id.to_string(),
+                heartbeat_secs: config.heartbeat_secs,
+                capabilities,
+            },
+        })
+    }
+
+    fn register(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<RegistrationAck, String> {
+        let mut response = client
+            .put(&self.url)
+            .json(&self.registration)
+            .send()
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("manager answered {}", response.status()));
+        }
+
+        response
+            .json::<RegistrationAck>()
+            .map_err(|e| e.to_string())
+    }
+
+    /// Register at each heartbeat.  This does not return.
+    pub fn run(&self, client: &reqwest::Client) {
+        let mut registered = false;
+        let mut failing = false;
+
+        loop {
+            match self.register(client) {
+                Ok(ack) => {
+                    if !registered || failing {
+                        info!(
+                            "Registered as {} with manager {}",
+                            self.registration.storage_id, ack.manager_version
+                        );
+                    }
+                    registered = true;
+                    failing = false;
+                }
+                Err(e) => {
+                    if !failing {
+                        warn!("Could not register with {}: {}", self.url, e);
+                    }
+                    failing = true;
+                }
+            }
+
+            thread::sleep(self.interval);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Retrying failed downloads.
+//
+// Many of the reasons that a download fails are transient: the source shark
+// was briefly overloaded and answered with a 503, a connection was reset, the
+// request timed out and so on.  Others are not, and no amount of retrying will
+// turn a 404 or a checksum mismatch in to a success.  An agent configured with
+// `retry.max_attempts` greater than 1 tries a download again when it fails for
+// one of the retryable reasons (see ObjectSkippedReason::is_retryable()),
+// waiting a little longer each time:
+//
+//  * Before the second attempt it waits about `retry.initial_backoff_ms`.
+//  * Each wait after that is about twice as long as the one before it, but
+//    never more than about `retry.max_backoff_ms`.
+//
+// Each wait is randomly somewhere between half of and the full backoff, so
+// that the workers of an agent (and the agents of a region) that all failed
+// against the same shark at once do not all come back to it at once as well.
+//
+// However many attempts it took, the number of attempts and the time spent on
+// them are recorded with the task, so that what the manager is told about a
+// failed object distinguishes one that failed after several attempts over
+// several minutes from one that failed immediately.
+
+use crate::common::ObjectSkippedReason;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_derive::{Deserialize, Serialize};
+
+static DEFAULT_MAX_ATTEMPTS: u32 = 1;
+static DEFAULT_INITIAL_BACKOFF_MS: u64 = 1000;
+static DEFAULT_MAX_BACKOFF_MS: u64 = 60_000;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigRetry {
+    // The number of attempts to make at downloading each object, including
+    // the first.  If this is 1, failed downloads are not retried.
+    pub max_attempts: u32,
+    // The approximate time to wait before the second attempt.
+    pub initial_backoff_ms: u64,
+    // The approximate longest time to wait between two attempts.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ConfigRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+        }
+    }
+}
+
+impl ConfigRetry {
+    /// Returns true if a download that has been attempted `attempts` times,
+    /// and last failed for `reason`, should be attempted again.
+    pub fn should_retry(
+        &self,
+        attempts: u32,
+        reason: &ObjectSkippedReason,
+    ) -> bool {
+        attempts < self.max_attempts && reason.is_retryable()
+    }
+
+    /// How long to wait after a download has failed `attempts` times before
+    /// attempting it again.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(32);
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff_ms);
+
+        let half = backoff / 2;
+        Duration::from_millis(backoff - half + jitter(half))
+    }
+}
+
+// A number from 0 to `max`.  This need not be any good as a random number,
+// just different enough from one worker to the next.
+fn jitter(max: u64) -> u64 {
+    if max == 0 {
+        return 0;
+    }
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    u64::from(nanos) % (max + 1)
+}
@@ -0,0 +1,414 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Checking up on objects some time after they were moved.
+//
+// Each object that the agent moves is verified against its checksum before it
+// is put in place, but that says nothing of what becomes of it afterwards.  A
+// bad disk or a misbehaving controller on the destination can corrupt an
+// object that has only just landed, and by the time anyone tries to read it
+// the evacuated shark may be long gone, along with the copy that it had.
+//
+// An agent configured with `sampler.sample_percent` greater than 0 sets aside
+// that share (at random) of the objects that it has moved and, about
+// `sampler.delay_secs` later, compares a randomly chosen range of up to
+// `sampler.range_bytes` bytes of its own copy with the same range of the copy
+// on the source.  Reading a range rather than the whole object keeps the cost
+// to both the source and the destination low, while still catching a copy
+// that has been truncated or has had any part of it scribbled over, given
+// enough samples.  A sample whose bytes (or size) differ from the source is
+// logged, counted in the `sample_verify_count` metric and reported by
+// GET /samples, while the source still has a good copy to recover from.  The
+// delay should be kept well short of the time that the evacuated shark is
+// expected to be around for.
+//
+// Samples waiting to be checked are only held in memory, so they are lost if
+// the agent restarts.  That is of little consequence for a sample, and no more
+// than `sampler.max_pending` are held at once.
+
+use crate::common::Task;
+use crate::metrics::{counter_vec_inc, MetricsMap};
+
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Client, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub static SAMPLE_VERIFY_COUNT: &str = "sample_verify_count";
+
+static DEFAULT_SAMPLE_PERCENT: f64 = 0.0;
+static DEFAULT_DELAY_SECS: u64 = 600;
+static DEFAULT_RANGE_BYTES: u64 = 1024 * 1024;
+static DEFAULT_MAX_PENDING: usize = 10_000;
+
+// The number of mismatches that GET /samples reports.  Older ones are only
+// to be found in the log.
+static MAX_REPORTED_MISMATCHES: usize = 1000;
+
+// How long the sampler sleeps for when there is no sample due.
+static IDLE_SLEEP: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigSampler {
+    // The percentage of moved objects to check up on.  If this is 0, no
+    // objects are checked.
+    pub sample_percent: f64,
+    // How long after an object was moved it is checked.
+    pub delay_secs: u64,
+    // The most bytes of each sampled object that are compared with the
+    // source.
+    pub range_bytes: u64,
+    // The most samples that may be waiting to be checked at once.  Objects
+    // that would be sampled once this is reached are not.
+    pub max_pending: usize,
+}
+
+impl Default for ConfigSampler {
+    fn default() -> Self {
+        Self {
+            sample_percent: DEFAULT_SAMPLE_PERCENT,
+            delay_secs: DEFAULT_DELAY_SECS,
+            range_bytes: DEFAULT_RANGE_BYTES,
+            max_pending: DEFAULT_MAX_PENDING,
+        }
+    }
+}
+
+/// A sampled object whose copy on this agent no longer matches the source.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SampleMismatch {
+    pub assignment: String,
+    pub owner: String,
+    pub object_id: String,
+    pub source: String,
+    // What differed.
+    pub detail: String,
+    // Seconds since the epoch at which the mismatch was found.
+    pub detected: u64,
+}
+
+/// What the sampler has found so far, as reported by GET /samples.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SamplerReport {
+    // Samples waiting to be checked.
+    pub pending: usize,
+    pub matched: u64,
+    pub mismatched: u64,
+    // Samples that could not be checked, e.g. because the object has since
+    // been deleted, or the source could not be reached.
+    pub unverifiable: u64,
+    // The most recent mismatches, oldest first.
+    pub mismatches: Vec<SampleMismatch>,
+}
+
+struct Sample {
+    due: Instant,
+    assignment: String,
+    owner: String,
+    object_id: String,
+    source: String,
+    // Where the object was put on this agent.
+    path: String,
+}
+
+enum SampleOutcome {
+    Match,
+    Mismatch(String),
+    Unverifiable(String),
+}
+
+#[derive(Default)]
+struct SamplerState {
+    pending: VecDeque<Sample>,
+    report: SamplerReport,
+}
+
+pub struct Sampler {
+    config: ConfigSampler,
+    state: Mutex<SamplerState>,
+}
+
+impl Sampler {
+    pub fn new(config: ConfigSampler) -> Sampler {
+        Sampler {
+            config,
+            state: Mutex::new(SamplerState::default()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.sample_percent > 0.0
+    }
+
+    /// Consider the object of `task`, which has just been moved to `path` as
+    /// part of `assignment`, for a later check.
+    pub fn offer(&self, assignment: &str, task: &Task, path: &str) {
+        if !self.enabled() {
+            return;
+        }
+
+        // A random number from 0 to 1, good to about a millionth.
+        let roll = random_below(1_000_000) as f64 / 1_000_000.0;
+        if roll * 100.0 >= self.config.sample_percent {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.pending.len() >= self.config.max_pending {
+            return;
+        }
+
+        state.pending.push_back(Sample {
+            due: Instant::now() + Duration::from_secs(self.config.delay_secs),
+            assignment: assignment.to_string(),
+            owner: task.owner.clone(),
+            object_id: task.object_id.clone(),
+            source: task.source.manta_storage_id.clone(),
+            path: path.to_string(),
+        });
+    }
+
+    pub fn report(&self) -> SamplerReport {
+        let state = self.state.lock().unwrap();
+        let mut report = state.report.clone();
+        report.pending = state.pending.len();
+        report
+    }
+
+    // The oldest sample, if it is due.  Samples are all given the same delay,
+    // so they fall due in the order in which they were taken.
+    fn next_due(&self) -> Option<Sample> {
+        let mut state = self.state.lock().unwrap();
+        match state.pending.front() {
+            Some(s) if s.due <= Instant::now() => state.pending.pop_front(),
+            _ => None,
+        }
+    }
+
+    /// Check samples as they fall due.  This does not return.
+    pub fn run(&self, client: &Client, metrics: &Option<MetricsMap>) {
+        loop {
+            let sample = match self.next_due() {
+                Some(s) => s,
+                None => {
+                    thread::sleep(IDLE_SLEEP);
+                    continue;
+                }
+            };
+
+            let outcome = self.check(&sample, client);
+            self.record(&sample, outcome, metrics);
+        }
+    }
+
+    fn record(
+        &self,
+        sample: &Sample,
+        outcome: SampleOutcome,
+        metrics: &Option<MetricsMap>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let report = &mut state.report;
+
+        let label = match outcome {
+            SampleOutcome::Match => {
+                debug!(
+                    "Sample of {}/{} matches the source",
+                    sample.owner, sample.object_id
+                );
+                report.matched += 1;
+                "match"
+            }
+            SampleOutcome::Mismatch(detail) => {
+                error!(
+                    "Sample of {}/{} (assignment {}) does not match the copy \
+                     on {}: {}",
+                    sample.owner,
+                    sample.object_id,
+                    sample.assignment,
+                    sample.source,
+                    detail
+                );
+                report.mismatched += 1;
+                report.mismatches.push(SampleMismatch {
+                    assignment: sample.assignment.clone(),
+                    owner: sample.owner.clone(),
+                    object_id: sample.object_id.clone(),
+                    source: sample.source.clone(),
+                    detail,
+                    detected: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0),
+                });
+
+                let excess = report
+                    .mismatches
+                    .len()
+                    .saturating_sub(MAX_REPORTED_MISMATCHES);
+                report.mismatches.drain(..excess);
+                "mismatch"
+            }
+            SampleOutcome::Unverifiable(detail) => {
+                info!(
+                    "Could not check sample of {}/{}: {}",
+                    sample.owner, sample.object_id, detail
+                );
+                report.unverifiable += 1;
+                "unverifiable"
+            }
+        };
+
+        if let Some(m) = metrics {
+            counter_vec_inc(m, SAMPLE_VERIFY_COUNT, Some(label));
+        }
+    }
+
+    // Compare a random range of the object on this agent with the same
+    // range of the object on the source.
+    fn check(&self, sample: &Sample, client: &Client) -> SampleOutcome {
+        let mut file = match File::open(&sample.path) {
+            Ok(f) => f,
+            Err(e) => {
+                return SampleOutcome::Unverifiable(format!(
+                    "opening {}: {}",
+                    sample.path, e
+                ))
+            }
+        };
+
+        let size = match file.metadata() {
+            Ok(m) => m.len(),
+            Err(e) => {
+                return SampleOutcome::Unverifiable(format!(
+                    "reading the size of {}: {}",
+                    sample.path, e
+                ))
+            }
+        };
+
+        if size == 0 {
+            return SampleOutcome::Unverifiable(String::from("empty object"));
+        }
+
+        let length = min(self.config.range_bytes.max(1), size);
+        let offset = random_below(size - length + 1);
+
+        let mut local = vec![0; length as usize];
+        if let Err(e) = file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut local))
+        {
+            return SampleOutcome::Unverifiable(format!(
+                "reading {}: {}",
+                sample.path, e
+            ));
+        }
+
+        let url = format!(
+            "http://{}/{}/{}",
+            sample.source, sample.owner, sample.object_id
+        );
+        let range = format!("bytes={}-{}", offset, offset + length - 1);
+
+        let mut response = match client.get(&url).header(RANGE, range).send() {
+            Ok(r) => r,
+            Err(e) => {
+                return SampleOutcome::Unverifiable(format!(
+                    "requesting {}: {}",
+                    url, e
+                ))
+            }
+        };
+
+        // A source that does not support ranges sends the whole object, in
+        // which case the bytes leading up to the range are skipped.
+        let skip = match response.status() {
+            StatusCode::PARTIAL_CONTENT => 0,
+            StatusCode::OK => offset,
+            status => {
+                return SampleOutcome::Unverifiable(format!(
+                    "source responded with {}",
+                    status
+                ))
+            }
+        };
+
+        if let Some(total) = content_range_total(&response) {
+            if total != size {
+                return SampleOutcome::Mismatch(format!(
+                    "{} bytes here, {} bytes on the source",
+                    size, total
+                ));
+            }
+        }
+
+        let mut remote = Vec::with_capacity(local.len());
+        let read = io::copy(&mut (&mut response).take(skip), &mut io::sink())
+            .and_then(|_| {
+                (&mut response).take(length).read_to_end(&mut remote)
+            });
+
+        if let Err(e) = read {
+            return SampleOutcome::Unverifiable(format!(
+                "reading from {}: {}",
+                url, e
+            ));
+        }
+
+        if remote != local {
+            return SampleOutcome::Mismatch(format!(
+                "bytes {} to {} differ",
+                offset,
+                offset + length - 1
+            ));
+        }
+
+        SampleOutcome::Match
+    }
+}
+
+// The total size of the object, from a "bytes <first>-<last>/<total>"
+// Content-Range header.
+fn content_range_total(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+// A random number from 0 to `max` - 1.
+fn random_below(max: u64) -> u64 {
+    if max <= 1 {
+        return 0;
+    }
+
+    let bytes = Uuid::new_v4();
+    let mut n = 0u64;
+    for b in bytes.as_bytes().iter().take(8) {
+        n = (n << 8) | u64::from(*b);
+    }
+
+    n % max
+}
@@ -0,0 +1,179 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Sharing the agent's download workers between the assignments that it is
+// processing.
+//
+// Were each assignment given download workers of its own, the workers of an
+// assignment that is down to its last few (or its last, slow) tasks would sit
+// idle while other assignments still had plenty of tasks waiting.  Instead,
+// each assignment that is being processed posts its tasks to a TaskBoard, and
+// a single pool of download workers claims tasks from whichever assignments
+// have any left.  Assignments take turns at having their tasks claimed, so
+// one large assignment does not hold up the others, and no assignment has
+// more of its tasks claimed at once than the board's per-assignment limit.
+//
+// An assignment stays on the board until each of its tasks has been claimed
+// and finished with, or, if it is cancelled, until the tasks that had been
+// claimed are finished with.  Its tasks that were never claimed are left for
+// whoever posted the assignment to deal with.
+
+use std::sync::{mpsc, Condvar, Mutex};
+use std::time::Duration;
+
+// How long an idle worker waits before looking for cancelled assignments
+// again, if nothing else wakes it first.
+static IDLE_WAIT: Duration = Duration::from_secs(1);
+
+struct Posting<C> {
+    id: String,
+    context: C,
+    len: usize,
+    // The index of the next task to be claimed.
+    next: usize,
+    // The number of tasks that have been claimed but not finished with.
+    active: usize,
+    // Dropped, waking whoever posted the assignment, once the assignment is
+    // taken off the board.
+    _done: mpsc::Sender<()>,
+}
+
+impl<C> Posting<C> {
+    fn finished(&self) -> bool {
+        self.next >= self.len && self.active == 0
+    }
+}
+
+/// The tasks of the assignments being processed, with `C` being whatever a
+/// worker needs to know about an assignment in order to process its tasks.
+pub struct TaskBoard<C> {
+    // The assignments on the board, in the order in which they take their
+    // turn at having a task claimed.
+    postings: Mutex<Vec<Posting<C>>>,
+    changed: Condvar,
+    limit: usize,
+}
+
+/// A task claimed from the board.  The task is finished with once the claim
+/// is dropped.
+pub struct Claim<'a, C> {
+    board: &'a TaskBoard<C>,
+    pub id: String,
+    // The index of the task within its assignment.
+    pub index: usize,
+    pub context: C,
+}
+
+impl<'a, C> Drop for Claim<'a, C> {
+    fn drop(&mut self) {
+        self.board.finish(&self.id);
+    }
+}
+
+impl<C> TaskBoard<C> {
+    /// A board on which no more than `limit` tasks of any one assignment may
+    /// be claimed at once.
+    pub fn new(limit: usize) -> TaskBoard<C> {
+        assert!(limit > 0);
+
+        TaskBoard {
+            postings: Mutex::new(Vec::new()),
+            changed: Condvar::new(),
+            limit,
+        }
+    }
+
+    /// Post the `len` tasks of the assignment `id`.  The receiver that is
+    /// returned is disconnected once the assignment is taken off the board.
+    pub fn post(&self, id: &str, len: usize, context: C) -> mpsc::Receiver<()> {
+        let (done, finished) = mpsc::channel();
+
+        if len > 0 {
+            self.postings.lock().unwrap().push(Posting {
+                id: id.to_string(),
+                context,
+                len,
+                next: 0,
+                active: 0,
+                _done: done,
+            });
+            self.changed.notify_all();
+        }
+
+        finished
+    }
+
+    fn finish(&self, id: &str) {
+        let mut postings = self.postings.lock().unwrap();
+
+        if let Some(pos) = postings.iter().position(|p| p.id == id) {
+            postings[pos].active -= 1;
+            if postings[pos].finished() {
+                postings.remove(pos);
+            }
+        }
+
+        // A worker may be waiting for this assignment to fall below its
+        // limit.
+        self.changed.notify_all();
+    }
+}
+
+impl<C: Clone> TaskBoard<C> {
+    /// Claim the next task, waiting for one if there is none to be had.  No
+    /// more tasks are handed out from an assignment for which `cancelled`
+    /// returns true.
+    pub fn claim<F>(&self, cancelled: F) -> Claim<C>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut postings = self.postings.lock().unwrap();
+
+        loop {
+            for p in postings.iter_mut() {
+                if p.next < p.len && cancelled(&p.id) {
+                    info!(
+                        "Assignment {} cancelled, no longer handing out its \
+                         tasks.",
+                        p.id
+                    );
+                    p.next = p.len;
+                }
+            }
+            postings.retain(|p| !p.finished());
+
+            let limit = self.limit;
+            if let Some(pos) = postings
+                .iter()
+                .position(|p| p.next < p.len && p.active < limit)
+            {
+                // The assignment goes to the back of the line, so that the
+                // next task claimed is another assignment's, if it has any.
+                let mut posting = postings.remove(pos);
+                let index = posting.next;
+                posting.next += 1;
+                posting.active += 1;
+
+                let claim = Claim {
+                    board: self,
+                    id: posting.id.clone(),
+                    index,
+                    context: posting.context.clone(),
+                };
+                postings.push(posting);
+
+                return claim;
+            }
+
+            postings =
+                self.changed.wait_timeout(postings, IDLE_WAIT).unwrap().0;
+        }
+    }
+}
@@ -0,0 +1,525 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// Finding objects that have rotted on this storage node.
+//
+// The sampler (see the sampler module) only checks up on an object once, soon
+// after it was moved, and only while the source still has a copy to compare
+// it with.  A disk can go bad long after that, and nothing notices until the
+// object is read.  With `scrubber.interval_secs` set above 0, the agent keeps
+// a ledger of each object that it verifies and puts in place, along with the
+// MD5 checksum that it was verified against, and every
+// `scrubber.interval_secs` it reads each object in the ledger from the local
+// object store again and recomputes its checksum.
+//
+// The scrubber is meant to stay out of the way of everything else on the
+// storage node: the objects are read `scrubber.read_bytes` at a time, one
+// object after another, and no more than `scrubber.max_iops` reads are made a
+// second.  A pass over a large ledger can take days, which is the point.
+//
+// An object whose checksum no longer matches is logged, counted in the
+// `scrub_count` metric (labeled by `outcome`, one of `match`, `corrupt`,
+// `missing` or `unreadable`) and reported by GET /scrub.  If the agent
+// registers with a manager (see the registration module), each corrupt object
+// is also posted to the manager, with POST /agents/<storage_id>/corrupt, once
+// for every pass that finds it, so that a manager that was restarted learns of
+// it again.  An object that is no longer in the object store (e.g. because it
+// has been deleted, or evacuated to another storage node) is dropped from the
+// ledger.
+//
+// Only objects that were put in place while the scrubber was enabled are in
+// the ledger, which is kept on disk so that it outlives the agent.
+
+use crate::common::Task;
+use crate::metrics::{counter_vec_inc, MetricsMap};
+use crate::migrations::{self, Migration};
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use md5::{Digest, Md5};
+use reqwest::Client;
+use serde_derive::{Deserialize, Serialize};
+
+pub static SCRUB_COUNT: &str = "scrub_count";
+
+static DEFAULT_INTERVAL_SECS: u64 = 0;
+static DEFAULT_MAX_IOPS: u32 = 20;
+static DEFAULT_READ_BYTES: u64 = 128 * 1024;
+
+// The number of corrupt objects that GET /scrub reports.  Older ones are only
+// to be found in the log.
+static MAX_REPORTED_CORRUPT: usize = 1000;
+
+// The number of objects read from the ledger at a time during a pass.
+static LEDGER_BATCH: i64 = 1000;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigScrubber {
+    // The time between passes of the scrubber.  If this is 0, nothing is
+    // scrubbed, and no ledger is kept.
+    pub interval_secs: u64,
+    // The most reads of `read_bytes` made each second.
+    pub max_iops: u32,
+    // The size of each read.
+    pub read_bytes: u64,
+}
+
+impl Default for ConfigScrubber {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_INTERVAL_SECS,
+            max_iops: DEFAULT_MAX_IOPS,
+            read_bytes: DEFAULT_READ_BYTES,
+        }
+    }
+}
+
+/// An object on this storage node whose checksum no longer matches the one
+/// that it was put in place with, as reported by GET /scrub and posted to
+/// the manager.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CorruptObject {
+    pub owner: String,
+    pub object_id: String,
+    // The checksum that the object was put in place with, and the one that
+    // it has now.
+    pub md5sum: String,
+    pub found_md5sum: String,
+    pub size: u64,
+    // Seconds since the epoch at which the corruption was found.
+    pub detected: u64,
+}
+
+/// What the scrubber has found since the agent started, as reported by
+/// GET /scrub.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScrubberReport {
+    pub passes: u64,
+    pub running: bool,
+    // Seconds since the epoch at which the last pass to finish did.
+    pub last_pass_finished: Option<u64>,
+    pub matched: u64,
+    pub corrupt: u64,
+    pub missing: u64,
+    // Objects that could not be read, other than because they were missing.
+    pub unreadable: u64,
+    // The most recently found corrupt objects, oldest first.
+    pub corrupt_objects: Vec<CorruptObject>,
+}
+
+/// The outcome of one pass of the scrubber.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scrubbed {
+    pub matched: u64,
+    pub corrupt: u64,
+    pub missing: u64,
+    pub unreadable: u64,
+}
+
+enum ScrubOutcome {
+    Match,
+    Corrupt(CorruptObject),
+    Missing,
+    Unreadable(String),
+}
+
+struct LedgerEntry {
+    rowid: i64,
+    owner: String,
+    object_id: String,
+    md5sum: String,
+}
+
+pub struct Scrubber {
+    config: ConfigScrubber,
+    // The directory that objects are kept in, as <owner>/<object>.
+    root: String,
+    ledger: Mutex<Option<rusqlite::Connection>>,
+    // Where corrupt objects are posted, if anywhere.
+    report_url: Option<String>,
+    report: Mutex<ScrubberReport>,
+}
+
+impl Scrubber {
+    pub fn new(
+        config: ConfigScrubber,
+        root: &str,
+        ledger_path: &str,
+        report_url: Option<String>,
+    ) -> Scrubber {
+        let ledger = if config.interval_secs > 0 {
+            open_ledger(ledger_path)
+        } else {
+            None
+        };
+
+        Scrubber {
+            config,
+            root: root.to_string(),
+            ledger: Mutex::new(ledger),
+            report_url,
+            report: Mutex::new(ScrubberReport::default()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.ledger.lock().unwrap().is_some()
+    }
+
+    /// Add the object of `task`, which has just been verified and put in
+    /// place, to the ledger.  An unchecked task's object has no checksum to
+    /// scrub it against.
+    pub fn record(&self, task: &Task) {
+        if task.unchecked {
+            return;
+        }
+
+        let ledger = self.ledger.lock().unwrap();
+        let conn = match ledger.as_ref() {
+            Some(c) => c,
+            None => return,
+        };
+
+        if let Err(e) = conn.execute(
+            "INSERT OR REPLACE INTO objects (owner, object_id, md5sum)
+                VALUES (?1, ?2, ?3)",
+            rusqlite::params![task.owner, task.object_id, task.md5sum],
+        ) {
+            warn!(
+                "Unable to add {}/{} to the scrub ledger: {}",
+                task.owner, task.object_id, e
+            );
+        }
+    }
+
+    pub fn report(&self) -> ScrubberReport {
+        self.report.lock().unwrap().clone()
+    }
+
+    /// Scrub at each interval.  This does not return.
+    pub fn run(&self, client: &Client, metrics: &Option<MetricsMap>) {
+        let interval = Duration::from_secs(self.config.interval_secs);
+
+        loop {
+            thread::sleep(interval);
+            let scrubbed = self.scrub(client, metrics);
+            info!(
+                "Scrubbed objects: {} matched, {} corrupt, {} missing, {} \
+                 unreadable",
+                scrubbed.matched,
+                scrubbed.corrupt,
+                scrubbed.missing,
+                scrubbed.unreadable
+            );
+        }
+    }
+
+    /// Make one pass over the ledger.
+    pub fn scrub(
+        &self,
+        client: &Client,
+        metrics: &Option<MetricsMap>,
+    ) -> Scrubbed {
+        let mut scrubbed = Scrubbed::default();
+        let mut after = 0;
+
+        self.report.lock().unwrap().running = true;
+
+        loop {
+            let batch = self.ledger_batch(after);
+            let last = match batch.last() {
+                Some(entry) => entry.rowid,
+                None => break,
+            };
+
+            for entry in batch.iter() {
+                let outcome = self.check(entry);
+                self.record_outcome(
+                    entry,
+                    outcome,
+                    &mut scrubbed,
+                    client,
+                    metrics,
+                );
+            }
+
+            after = last;
+        }
+
+        let mut report = self.report.lock().unwrap();
+        report.running = false;
+        report.passes += 1;
+        report.last_pass_finished = Some(now_secs());
+
+        scrubbed
+    }
+
+    // The entries of the ledger after the row `after`, in order.
+    fn ledger_batch(&self, after: i64) -> Vec<LedgerEntry> {
+        let ledger = self.ledger.lock().unwrap();
+        let conn = match ledger.as_ref() {
+            Some(c) => c,
+            None => return vec![],
+        };
+
+        let read = conn
+            .prepare(
+                "SELECT rowid, owner, object_id, md5sum FROM objects
+                    WHERE rowid > ?1 ORDER BY rowid LIMIT ?2",
+            )
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map(
+                    rusqlite::params![after, LEDGER_BATCH],
+                    |row| {
+                        Ok(LedgerEntry {
+                            rowid: row.get(0)?,
+                            owner: row.get(1)?,
+                            object_id: row.get(2)?,
+                            md5sum: row.get(3)?,
+                        })
+                    },
+                )?;
+
+                let mut batch = vec![];
+                for entry in rows {
+                    batch.push(entry?);
+                }
+                Ok(batch)
+            });
+
+        match read {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!("Unable to read the scrub ledger: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    fn forget(&self, entry: &LedgerEntry) {
+        let ledger = self.ledger.lock().unwrap();
+        if let Some(conn) = ledger.as_ref() {
+            if let Err(e) = conn.execute(
+                "DELETE FROM objects WHERE rowid = ?1",
+                rusqlite::params![entry.rowid],
+            ) {
+                warn!(
+                    "Unable to remove {}/{} from the scrub ledger: {}",
+                    entry.owner, entry.object_id, e
+                );
+            }
+        }
+    }
+
+    fn record_outcome(
+        &self,
+        entry: &LedgerEntry,
+        outcome: ScrubOutcome,
+        scrubbed: &mut Scrubbed,
+        client: &Client,
+        metrics: &Option<MetricsMap>,
+    ) {
+        let label = match outcome {
+            ScrubOutcome::Match => {
+                scrubbed.matched += 1;
+                self.report.lock().unwrap().matched += 1;
+                "match"
+            }
+            ScrubOutcome::Corrupt(object) => {
+                error!(
+                    "Object {}/{} is corrupt: its checksum is {}, not {}",
+                    object.owner,
+                    object.object_id,
+                    object.found_md5sum,
+                    object.md5sum
+                );
+                scrubbed.corrupt += 1;
+                self.post_corrupt(client, &object);
+
+                let mut report = self.report.lock().unwrap();
+                report.corrupt += 1;
+                report.corrupt_objects.push(object);
+
+                let excess = report
+                    .corrupt_objects
+                    .len()
+                    .saturating_sub(MAX_REPORTED_CORRUPT);
+                report.corrupt_objects.drain(..excess);
+                "corrupt"
+            }
+            ScrubOutcome::Missing => {
+                debug!(
+                    "Object {}/{} is gone, removing it from the scrub ledger",
+                    entry.owner, entry.object_id
+                );
+                scrubbed.missing += 1;
+                self.forget(entry);
+                self.report.lock().unwrap().missing += 1;
+                "missing"
+            }
+            ScrubOutcome::Unreadable(detail) => {
+                warn!(
+                    "Could not scrub {}/{}: {}",
+                    entry.owner, entry.object_id, detail
+                );
+                scrubbed.unreadable += 1;
+                self.report.lock().unwrap().unreadable += 1;
+                "unreadable"
+            }
+        };
+
+        if let Some(m) = metrics {
+            counter_vec_inc(m, SCRUB_COUNT, Some(label));
+        }
+    }
+
+    // Tell the manager about a corrupt object, if there is a manager to tell.
+    // A failure is only logged, since the next pass tells it again.
+    fn post_corrupt(&self, client: &Client, object: &CorruptObject) {
+        let url = match self.report_url.as_ref() {
+            Some(u) => u,
+            None => return,
+        };
+
+        match client.post(url).json(object).send() {
+            Ok(ref r) if r.status().is_success() => (),
+            Ok(r) => warn!(
+                "Reporting corrupt object {}/{}: manager answered {}",
+                object.owner,
+                object.object_id,
+                r.status()
+            ),
+            Err(e) => warn!(
+                "Reporting corrupt object {}/{}: {}",
+                object.owner, object.object_id, e
+            ),
+        }
+    }
+
+    // Recompute the checksum of an object, at no more than the configured
+    // rate of reads.
+    fn check(&self, entry: &LedgerEntry) -> ScrubOutcome {
+        let path = format!("{}/{}/{}", self.root, entry.owner, entry.object_id);
+
+        let mut file = match File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return ScrubOutcome::Missing
+            }
+            Err(e) => {
+                return ScrubOutcome::Unreadable(format!(
+                    "opening {}: {}",
+                    path, e
+                ))
+            }
+        };
+
+        let mut hasher = Md5::new();
+        let mut buf = vec![0; self.config.read_bytes.max(1) as usize];
+        let mut size = 0;
+
+        loop {
+            let n = match file.read(&mut buf) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                    continue
+                }
+                Err(e) => {
+                    return ScrubOutcome::Unreadable(format!(
+                        "reading {}: {}",
+                        path, e
+                    ))
+                }
+            };
+
+            self.pace();
+            if n == 0 {
+                break;
+            }
+
+            hasher.input(&buf[..n]);
+            size += n as u64;
+        }
+
+        let found_md5sum = base64::encode(&hasher.result());
+        if found_md5sum == entry.md5sum {
+            return ScrubOutcome::Match;
+        }
+
+        ScrubOutcome::Corrupt(CorruptObject {
+            owner: entry.owner.clone(),
+            object_id: entry.object_id.clone(),
+            md5sum: entry.md5sum.clone(),
+            found_md5sum,
+            size,
+            detected: now_secs(),
+        })
+    }
+
+    // Wait out the rest of this read's share of a second.
+    fn pace(&self) {
+        if self.config.max_iops > 0 {
+            thread::sleep(Duration::from_secs(1) / self.config.max_iops);
+        }
+    }
+}
+
+fn create_objects_table(conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS objects (
+            owner TEXT NOT NULL,
+            object_id TEXT NOT NULL,
+            md5sum TEXT NOT NULL,
+            PRIMARY KEY (owner, object_id)
+        )",
+        rusqlite::params![],
+    )?;
+    Ok(())
+}
+
+// The migrations of the ledger (see the migrations module).
+static LEDGER_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "objects table",
+    up: create_objects_table,
+}];
+
+// Open the ledger at `path`, creating it if need be.  The scrubber is
+// disabled if it can not be opened.
+fn open_ledger(path: &str) -> Option<rusqlite::Connection> {
+    let opened = rusqlite::Connection::open(path).and_then(|mut conn| {
+        migrations::migrate(&mut conn, LEDGER_MIGRATIONS)?;
+        Ok(conn)
+    });
+
+    match opened {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            error!(
+                "Unable to open the scrub ledger {}, the scrubber is \
+                 disabled: {}",
+                path, e
+            );
+            None
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,189 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+use std::cmp::{max, min};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The length of time over which CPU usage is measured before the accounting
+// starts over.  Keeping this short means that a burst of hashing early on in
+// the life of the agent does not earn it a long stretch of unthrottled work
+// later, and vice versa.
+static CPU_WINDOW: Duration = Duration::from_secs(5);
+
+// Upper bound on how long a single worker will be put to sleep at once.  This
+// keeps workers responsive to the window being reset by their peers.
+static MAX_PACE_SLEEP: Duration = Duration::from_secs(1);
+
+struct CpuWindow {
+    wall_start: Instant,
+    cpu_start: Duration,
+}
+
+// The rebalancer agent shares the storage node with the services that
+// actually serve objects to clients.  Most of the CPU that the agent burns
+// goes to calculating checksums of downloaded objects, which it will happily
+// do as fast as it can.  The CpuThrottle measures the CPU time consumed by
+// the agent process and, whenever it has exceeded its share of the machine
+// over the current window, puts the worker that notices the overage to sleep
+// until usage falls back under the ceiling.
+pub struct CpuThrottle {
+    // Fraction of the total CPU capacity of the machine that we may consume.
+    limit: f64,
+    ncpus: f64,
+    window: Mutex<CpuWindow>,
+}
+
+impl CpuThrottle {
+    pub fn new(limit_percent: u8) -> CpuThrottle {
+        CpuThrottle::with_cpus(
+            limit_percent,
+            online_cpus(),
+            Instant::now(),
+            process_cpu_time(),
+        )
+    }
+
+    // A throttle for a machine with `ncpus` CPUs, whose first window starts at
+    // `wall_start` with the process having used `cpu_start` of CPU time.
+    fn with_cpus(
+        limit_percent: u8,
+        ncpus: usize,
+        wall_start: Instant,
+        cpu_start: Duration,
+    ) -> CpuThrottle {
+        assert!(limit_percent > 0 && limit_percent <= 100);
+
+        CpuThrottle {
+            limit: f64::from(limit_percent) / 100.0,
+            ncpus: ncpus as f64,
+            window: Mutex::new(CpuWindow {
+                wall_start,
+                cpu_start,
+            }),
+        }
+    }
+
+    // Given the number of workers that the operator has asked for, return the
+    // number that we will actually run so that, even with every worker busy
+    // hashing, the agent can not occupy more CPUs than its share allows.
+    pub fn max_workers(&self, requested: usize) -> usize {
+        let cap = (self.ncpus * self.limit).ceil() as usize;
+        max(1, min(requested, cap))
+    }
+
+    // Called by workers in between tasks.  If the agent has consumed more CPU
+    // time than it is entitled to over the current window, sleep for long
+    // enough that the average drops back down to the limit.
+    pub fn pace(&self) {
+        if let Some(delay) = self.delay(Instant::now(), process_cpu_time()) {
+            trace!("CPU ceiling reached, pacing worker for {:?}", delay);
+            thread::sleep(delay);
+        }
+    }
+
+    // How long a worker that finds the process to have used `cpu_now` of CPU
+    // time at `now` is to sleep, if at all.  The window starts over at `now`
+    // once it has run its length.
+    fn delay(&self, now: Instant, cpu_now: Duration) -> Option<Duration> {
+        let mut window = self.window.lock().unwrap();
+        let wall = now.duration_since(window.wall_start);
+        let used = cpu_now.checked_sub(window.cpu_start).unwrap_or_default();
+
+        if wall >= CPU_WINDOW {
+            window.wall_start = now;
+            window.cpu_start = cpu_now;
+        }
+
+        // The amount of wall clock time that would need to have passed
+        // for `used' to fall within our share of the machine.
+        let entitled = used.as_secs_f64() / (self.ncpus * self.limit);
+        let behind = entitled - wall.as_secs_f64();
+
+        if behind > 0.0 {
+            Some(min(Duration::from_secs_f64(behind), MAX_PACE_SLEEP))
+        } else {
+            None
+        }
+    }
+}
+
+fn online_cpus() -> usize {
+    let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if n < 1 {
+        1
+    } else {
+        n as usize
+    }
+}
+
+// Total user and system CPU time consumed by this process so far.
+fn process_cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Duration::default();
+    }
+
+    let to_duration = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64)
+            + Duration::from_micros(tv.tv_usec as u64)
+    };
+
+    to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_workers() {
+        let throttle =
+            CpuThrottle::with_cpus(50, 8, Instant::now(), Duration::default());
+
+        assert_eq!(throttle.max_workers(16), 4);
+        assert_eq!(throttle.max_workers(2), 2);
+        assert_eq!(throttle.max_workers(0), 1);
+
+        // A share of less than one CPU still allows one worker.
+        let throttle =
+            CpuThrottle::with_cpus(10, 2, Instant::now(), Duration::default());
+        assert_eq!(throttle.max_workers(4), 1);
+    }
+
+    #[test]
+    fn pace_delay() {
+        let start = Instant::now();
+        let secs = Duration::from_secs_f64;
+
+        // Half of 4 CPUs is 2 seconds of CPU time for every second.
+        let throttle = CpuThrottle::with_cpus(50, 4, start, secs(10.0));
+        let at = |s: f64| start + secs(s);
+
+        // Within the share, or using less than the window started with.
+        assert_eq!(throttle.delay(at(1.0), secs(11.0)), None);
+        assert_eq!(throttle.delay(at(1.0), secs(12.0)), None);
+        assert_eq!(throttle.delay(at(1.0), secs(5.0)), None);
+
+        // 3 seconds of CPU are owed 1.5 seconds, 0.5 of which are to come.
+        assert_eq!(throttle.delay(at(1.0), secs(13.0)), Some(secs(0.5)));
+
+        // No single sleep is longer than MAX_PACE_SLEEP.
+        assert_eq!(throttle.delay(at(1.0), secs(30.0)), Some(MAX_PACE_SLEEP));
+
+        // Once the window has run its length it starts over, so what was
+        // used before counts for nothing.
+        assert_eq!(throttle.delay(at(5.0), secs(40.0)), Some(MAX_PACE_SLEEP));
+        assert_eq!(throttle.delay(at(5.5), secs(40.5)), None);
+        assert_eq!(throttle.delay(at(5.5), secs(42.0)), Some(secs(0.5)));
+    }
+}
@@ -0,0 +1,904 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ */
+
+/*
+ * Copyright 2020 Joyent, Inc.
+ */
+
+// How the agent fetches objects from their sources.
+//
+// The download stage of the task pipeline only needs an object's bytes to end
+// up in its temporary location, along with what was learned of the transfer
+// for the task's autopsy.  How they get there is up to the `Transfer` that the
+// agent is configured with (`transfer.backend`), so that other ways of moving
+// objects can be tried without touching task processing:
+//
+//  * http (the default): each download worker has an HTTP client of its own,
+//    and makes one request at a time with it.
+//  * pipelined (experimental): the download workers share up to
+//    `transfer.connections_per_source` persistent connections to each
+//    source, and send their requests down them without waiting for the
+//    responses to the requests before theirs, up to
+//    `transfer.pipeline_depth` requests in flight on each connection.  The
+//    responses come back in the order that the requests were sent, so a
+//    large object holds up the objects requested after it on the same
+//    connection.  Any error on a connection fails every request in flight on
+//    it, and the connection is not used again.  The failed requests fail
+//    for a transient reason, so they may be retried (see the retry module).
+//
+// Either way, only a 200 response is taken to be the object, and whatever
+// the source sent is recorded in the task's autopsy.
+//
+// The manager may name content encodings for an assignment (see
+// common::ContentEncoding) that its objects may be sent in.  They are offered
+// to the source in an Accept-Encoding header, most preferred first, and a
+// response in one of them is decoded on its way in to the object's file.  A
+// source that does not compress the object sends it as it is.  The object is
+// verified against the checksum of its decoded bytes as before, so a body
+// that decodes to something else fails verification like any other
+// corruption in transit, and one that does not decode at all fails the
+// download for a transient reason.  Both the bytes that came from the source
+// and those that they were decoded to are recorded in the autopsy.
+
+use crate::common::{ContentEncoding, ObjectSkippedReason, TaskAutopsy};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use flate2::write::GzDecoder;
+use reqwest::header::ACCEPT_ENCODING;
+use reqwest::{Client, Url};
+use serde_derive::{Deserialize, Serialize};
+use zstd::stream::write::Decoder as ZstdDecoder;
+
+static DEFAULT_CONNECTIONS_PER_SOURCE: usize = 2;
+static DEFAULT_PIPELINE_DEPTH: usize = 4;
+static DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+// The size of the buffer that response bodies are copied through.
+const COPY_BUFFER_BYTES: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferBackend {
+    Http,
+    Pipelined,
+}
+
+impl Default for TransferBackend {
+    fn default() -> Self {
+        TransferBackend::Http
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConfigTransfer {
+    // How objects are fetched from their sources.
+    pub backend: TransferBackend,
+    // With the pipelined backend, the most connections kept open to any one
+    // source.
+    pub connections_per_source: usize,
+    // With the pipelined backend, the most requests in flight on any one
+    // connection.
+    pub pipeline_depth: usize,
+    // With the pipelined backend, the seconds to wait for a source to accept
+    // a connection, or to send more of a response.
+    pub timeout_secs: u64,
+}
+
+impl Default for ConfigTransfer {
+    fn default() -> Self {
+        Self {
+            backend: TransferBackend::default(),
+            connections_per_source: DEFAULT_CONNECTIONS_PER_SOURCE,
+            pipeline_depth: DEFAULT_PIPELINE_DEPTH,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// A way of fetching objects from their sources.
+pub trait Transfer: Send + Sync {
+    /// Fetch the object at `uri` in to a new file at `path`, offering the
+    /// source the content `encodings` to send it in, and recording what is
+    /// learned of the transfer along the way in `autopsy`.  The file is only
+    /// created if the source has the object.  Returns the number of bytes
+    /// written to it, which are those of the decoded object.
+    fn fetch(
+        &self,
+        uri: &str,
+        path: &str,
+        encodings: &[ContentEncoding],
+        autopsy: &mut TaskAutopsy,
+    ) -> Result<u64, ObjectSkippedReason>;
+}
+
+/// The transfer for each of `workers` download workers.  With the http
+/// backend each worker has a client of its own, whereas with the pipelined
+/// backend they all share the same connections, which is what lets their
+/// requests be pipelined.
+pub fn worker_transfers(
+    config: &ConfigTransfer,
+    workers: usize,
+) -> Vec<Arc<dyn Transfer>> {
+    match config.backend {
+        TransferBackend::Http => (0..workers)
+            .map(|_| Arc::new(HttpTransfer::new()) as Arc<dyn Transfer>)
+            .collect(),
+        TransferBackend::Pipelined => {
+            let shared: Arc<dyn Transfer> =
+                Arc::new(PipelinedTransfer::new(config));
+            (0..workers).map(|_| Arc::clone(&shared)).collect()
+        }
+    }
+}
+
+fn create_file(path: &str) -> Result<File, ObjectSkippedReason> {
+    File::create(path).map_err(|e| {
+        error!("Error creating file {}: {}", path, e);
+        ObjectSkippedReason::AgentFSError
+    })
+}
+
+// The Accept-Encoding header that offers `encodings`, if there are any.
+fn accept_encoding(encodings: &[ContentEncoding]) -> Option<String> {
+    if encodings.is_empty() {
+        return None;
+    }
+
+    let names: Vec<String> = encodings.iter().map(|e| e.to_string()).collect();
+    Some(names.join(", "))
+}
+
+// The encoding of the response for `uri`, from its Content-Encoding header.
+// A response in an encoding that can not be decoded fails the download.
+fn response_encoding(
+    uri: &str,
+    value: Option<&str>,
+) -> Result<Option<ContentEncoding>, ObjectSkippedReason> {
+    let value = value.map(|v| v.trim().to_lowercase()).unwrap_or_default();
+
+    match value.as_str() {
+        "" | "identity" => Ok(None),
+        "x-gzip" => Ok(Some(ContentEncoding::Gzip)),
+        v => ContentEncoding::from_str(v).map(Some).map_err(|_| {
+            error!("Unsupported content encoding for {}: {}", uri, v);
+            ObjectSkippedReason::SourceOtherError
+        }),
+    }
+}
+
+// The file that an object is written to, which remembers whether a write to
+// it has failed, so that a body that could not be decoded can be told apart
+// from a full or failing disk.
+struct ObjectFile {
+    file: File,
+    failed: bool,
+}
+
+impl ObjectFile {
+    fn check<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if res.is_err() {
+            self.failed = true;
+        }
+        res
+    }
+}
+
+impl Write for ObjectFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.file.write(buf);
+        self.check(res)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let res = self.file.flush();
+        self.check(res)
+    }
+}
+
+// Where the body of a response is written: straight in to the object's file,
+// or through the decoder for the response's content encoding.
+enum Body {
+    Identity(ObjectFile),
+    Gzip(GzDecoder<ObjectFile>),
+    Zstd(ZstdDecoder<ObjectFile>),
+}
+
+impl Body {
+    fn open(
+        path: &str,
+        encoding: Option<ContentEncoding>,
+    ) -> Result<Body, ObjectSkippedReason> {
+        let file = ObjectFile {
+            file: create_file(path)?,
+            failed: false,
+        };
+
+        match encoding {
+            None => Ok(Body::Identity(file)),
+            Some(ContentEncoding::Gzip) => Ok(Body::Gzip(GzDecoder::new(file))),
+            Some(ContentEncoding::Zstd) => {
+                ZstdDecoder::new(file).map(Body::Zstd).map_err(|e| {
+                    error!("Error creating decoder for {}: {}", path, e);
+                    ObjectSkippedReason::AgentFSError
+                })
+            }
+        }
+    }
+
+    fn file(&self) -> &ObjectFile {
+        match self {
+            Body::Identity(f) => f,
+            Body::Gzip(d) => d.get_ref(),
+            Body::Zstd(d) => d.get_ref(),
+        }
+    }
+
+    // Write out whatever the decoder is still holding on to.
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Body::Identity(_) => Ok(()),
+            Body::Gzip(d) => d.try_finish(),
+            Body::Zstd(d) => d.flush(),
+        }
+    }
+
+    // The bytes written to the object's file so far.
+    fn written(&self) -> u64 {
+        self.file().file.metadata().map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+impl Write for Body {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Body::Identity(f) => f.write(buf),
+            Body::Gzip(d) => d.write(buf),
+            Body::Zstd(d) => d.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Body::Identity(f) => f.flush(),
+            Body::Gzip(d) => d.flush(),
+            Body::Zstd(d) => d.flush(),
+        }
+    }
+}
+
+// Finish with the body of the response for `uri`, of which `copied` bytes
+// came from the source, and record what was received of it.  Returns the
+// bytes written to the object's file.
+fn finish_body(
+    uri: &str,
+    mut body: Body,
+    copied: Result<u64, BodyError>,
+    autopsy: &mut TaskAutopsy,
+) -> Result<u64, ObjectSkippedReason> {
+    let copied =
+        copied.and_then(|b| body.finish().map(|_| b).map_err(BodyError::Dest));
+    let written = body.written();
+
+    let e = match copied {
+        Ok(b) => {
+            autopsy.bytes = b;
+            if autopsy.content_encoding.is_some() {
+                autopsy.decoded_bytes = Some(written);
+            }
+            return Ok(written);
+        }
+        Err(e) => e,
+    };
+
+    autopsy.bytes = written;
+    match e {
+        BodyError::Source(e) => {
+            error!("Failed to complete object download: {}:{}", uri, e);
+            Err(ObjectSkippedReason::SourceOtherError)
+        }
+        BodyError::Dest(e) if !body.file().failed => {
+            error!("Failed to decode object download: {}:{}", uri, e);
+            Err(ObjectSkippedReason::SourceOtherError)
+        }
+        BodyError::Dest(e) => {
+            error!("Failed to complete object download: {}:{}", uri, e);
+            Err(ObjectSkippedReason::AgentFSError)
+        }
+    }
+}
+
+/// One request at a time, with a client of its own.
+pub struct HttpTransfer {
+    client: Client,
+}
+
+impl HttpTransfer {
+    /// The client would otherwise ask for gzip of its own accord, and decode
+    /// it out of sight, so that is left to fetch().
+    pub fn new() -> HttpTransfer {
+        HttpTransfer {
+            client: Client::builder()
+                .gzip(false)
+                .build()
+                .expect("failed to create download client"),
+        }
+    }
+}
+
+impl Default for HttpTransfer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transfer for HttpTransfer {
+    fn fetch(
+        &self,
+        uri: &str,
+        path: &str,
+        encodings: &[ContentEncoding],
+        autopsy: &mut TaskAutopsy,
+    ) -> Result<u64, ObjectSkippedReason> {
+        let mut request = self.client.get(uri);
+        if let Some(accept) = accept_encoding(encodings) {
+            request = request.header(ACCEPT_ENCODING, accept);
+        }
+
+        let start = Instant::now();
+        let mut response = match request.send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!("Request failed: {}", &e);
+                return Err(ObjectSkippedReason::SourceOtherError);
+            }
+        };
+
+        autopsy.first_byte_ms = Some(start.elapsed().as_millis() as u64);
+        autopsy.source_addr = response.remote_addr().map(|a| a.to_string());
+        autopsy.expected_bytes = response.content_length();
+        autopsy.source_headers = response
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                (name.to_string(), value.into_owned())
+            })
+            .collect();
+
+        let status = response.status();
+        let msg = format!("Download response for {} is {}", uri, status);
+        if status != reqwest::StatusCode::OK {
+            error!("{}", msg);
+            return Err(ObjectSkippedReason::HTTPStatusCode(status.into()));
+        }
+
+        trace!("{}", msg);
+
+        let encoding = response_encoding(
+            uri,
+            autopsy
+                .source_headers
+                .get("content-encoding")
+                .map(|v| v.as_str()),
+        )?;
+        autopsy.content_encoding = encoding;
+
+        let mut body = Body::open(path, encoding)?;
+        let copied = copy_exact(&mut response, None, &mut body);
+        finish_body(uri, body, copied, autopsy)
+    }
+}
+
+// How the end of a response's body is found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Framing {
+    Length(u64),
+    Chunked,
+    // The body runs until the source closes the connection.
+    Close,
+}
+
+// The status line and headers of a response.
+struct ResponseHead {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    framing: Framing,
+    // The source will close the connection after this response.
+    closing: bool,
+}
+
+// Where copying a body stopped, if it did not get to the end.
+enum BodyError {
+    Source(io::Error),
+    Dest(io::Error),
+}
+
+// The requests sent down a connection, and the responses read from it.  Each
+// request is given a ticket, and the response to it may only be read once
+// the responses to every earlier ticket have been.
+struct Turns {
+    sent: u64,
+    read: u64,
+    broken: bool,
+}
+
+impl Turns {
+    fn in_flight(&self) -> u64 {
+        self.sent - self.read
+    }
+}
+
+struct Connection {
+    peer: Option<String>,
+    writer: Mutex<TcpStream>,
+    reader: Mutex<BufReader<TcpStream>>,
+    turns: Mutex<Turns>,
+    turn_taken: Condvar,
+}
+
+impl Connection {
+    fn open(addr: &str, timeout: Duration) -> io::Result<Connection> {
+        let mut last_err = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no address for {}", addr),
+        );
+
+        for sockaddr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&sockaddr, timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(timeout))?;
+                    stream.set_write_timeout(Some(timeout))?;
+                    stream.set_nodelay(true)?;
+
+                    return Ok(Connection {
+                        peer: stream.peer_addr().ok().map(|a| a.to_string()),
+                        writer: Mutex::new(stream.try_clone()?),
+                        reader: Mutex::new(BufReader::new(stream)),
+                        turns: Mutex::new(Turns {
+                            sent: 0,
+                            read: 0,
+                            broken: false,
+                        }),
+                        turn_taken: Condvar::new(),
+                    });
+                }
+                Err(e) => last_err = e,
+            }
+        }
+
+        Err(last_err)
+    }
+
+    fn is_broken(&self) -> bool {
+        self.turns.lock().unwrap().broken
+    }
+
+    fn in_flight(&self) -> u64 {
+        self.turns.lock().unwrap().in_flight()
+    }
+
+    // Send a request, returning its ticket.
+    fn send(&self, request: &[u8]) -> io::Result<u64> {
+        let mut writer = self.writer.lock().unwrap();
+        let ticket = {
+            let mut turns = self.turns.lock().unwrap();
+            if turns.broken {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection is no longer in use",
+                ));
+            }
+            turns.sent += 1;
+            turns.sent - 1
+        };
+
+        if let Err(e) = writer.write_all(request).and_then(|_| writer.flush()) {
+            // The request may have been partly written, so nothing more can
+            // be sent down the connection, nor can the response to this
+            // request ever be read.
+            self.finish(ticket, false);
+            return Err(e);
+        }
+
+        Ok(ticket)
+    }
+
+    // Wait for the response to `ticket` to be next.  Returns false if the
+    // connection has broken in the meantime.
+    fn wait_turn(&self, ticket: u64) -> bool {
+        let mut turns = self.turns.lock().unwrap();
+        while !turns.broken && turns.read != ticket {
+            turns = self.turn_taken.wait(turns).unwrap();
+        }
+        !turns.broken
+    }
+
+    // The response to `ticket` has been read, or (if not `reusable`) could
+    // not be, and neither can those of the requests after it.
+    fn finish(&self, ticket: u64, reusable: bool) {
+        let mut turns = self.turns.lock().unwrap();
+        if ticket == turns.read {
+            turns.read += 1;
+        }
+        if !reusable {
+            turns.broken = true;
+        }
+        self.turn_taken.notify_all();
+    }
+}
+
+// The connections to one source.
+struct SourcePool {
+    connections: Mutex<Vec<Arc<Connection>>>,
+    freed: Condvar,
+}
+
+/// Pipelined requests on a few persistent connections to each source.  See
+/// the top of this module.
+pub struct PipelinedTransfer {
+    connections_per_source: usize,
+    pipeline_depth: u64,
+    timeout: Duration,
+    sources: Mutex<HashMap<String, Arc<SourcePool>>>,
+}
+
+impl PipelinedTransfer {
+    pub fn new(config: &ConfigTransfer) -> PipelinedTransfer {
+        PipelinedTransfer {
+            connections_per_source: config.connections_per_source.max(1),
+            pipeline_depth: config.pipeline_depth.max(1) as u64,
+            timeout: Duration::from_secs(config.timeout_secs.max(1)),
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn source_pool(&self, addr: &str) -> Arc<SourcePool> {
+        let mut sources = self.sources.lock().unwrap();
+        let pool = sources.entry(addr.to_string()).or_insert_with(|| {
+            Arc::new(SourcePool {
+                connections: Mutex::new(Vec::new()),
+                freed: Condvar::new(),
+            })
+        });
+        Arc::clone(pool)
+    }
+
+    // The connection to `addr` with the fewest requests in flight, opening a
+    // new one if every connection is at the pipeline depth and there is
+    // room for another, or else waiting for a request to finish.  New
+    // connections are opened with the source's pool locked, so that no more
+    // than `connections_per_source` are ever opened to it.
+    fn connection(
+        &self,
+        pool: &SourcePool,
+        addr: &str,
+    ) -> io::Result<Arc<Connection>> {
+        let mut connections = pool.connections.lock().unwrap();
+
+        loop {
+            connections.retain(|c| !c.is_broken());
+
+            let least_busy = connections
+                .iter()
+                .min_by_key(|c| c.in_flight())
+                .filter(|c| c.in_flight() < self.pipeline_depth)
+                .map(Arc::clone);
+
+            if let Some(c) = least_busy {
+                return Ok(c);
+            }
+
+            if connections.len() < self.connections_per_source {
+                let c = Arc::new(Connection::open(addr, self.timeout)?);
+                connections.push(Arc::clone(&c));
+                return Ok(c);
+            }
+
+            connections = pool
+                .freed
+                .wait_timeout(connections, self.timeout)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+impl Transfer for PipelinedTransfer {
+    fn fetch(
+        &self,
+        uri: &str,
+        path: &str,
+        encodings: &[ContentEncoding],
+        autopsy: &mut TaskAutopsy,
+    ) -> Result<u64, ObjectSkippedReason> {
+        let url = Url::parse(uri).map_err(|e| {
+            error!("Invalid download url {}: {}", uri, e);
+            ObjectSkippedReason::SourceOtherError
+        })?;
+        let host = url.host_str().unwrap_or_default();
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addr = format!("{}:{}", host, port);
+        let host_header = match url.port() {
+            Some(_) => addr.clone(),
+            None => host.to_string(),
+        };
+        let target = match url.query() {
+            Some(q) => format!("{}?{}", url.path(), q),
+            None => url.path().to_string(),
+        };
+        let accept = accept_encoding(encodings)
+            .map(|a| format!("Accept-Encoding: {}\r\n", a))
+            .unwrap_or_default();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nAccept: */*\r\n{}\r\n",
+            target, host_header, accept
+        );
+
+        let pool = self.source_pool(&addr);
+        let start = Instant::now();
+
+        let conn = self.connection(&pool, &addr).map_err(|e| {
+            error!("Request failed: unable to connect to {}: {}", addr, e);
+            ObjectSkippedReason::SourceOtherError
+        })?;
+
+        let res =
+            fetch_on(&conn, request.as_bytes(), uri, path, start, autopsy);
+        pool.freed.notify_all();
+        res
+    }
+}
+
+// Send the request for an object down `conn`, and read the response to it
+// once every response before it has been read.
+fn fetch_on(
+    conn: &Connection,
+    request: &[u8],
+    uri: &str,
+    path: &str,
+    start: Instant,
+    autopsy: &mut TaskAutopsy,
+) -> Result<u64, ObjectSkippedReason> {
+    let ticket = conn.send(request).map_err(|e| {
+        error!("Request failed: {}: {}", uri, e);
+        ObjectSkippedReason::SourceOtherError
+    })?;
+
+    if !conn.wait_turn(ticket) {
+        error!(
+            "Request failed: {}: the connection failed on an earlier request",
+            uri
+        );
+        return Err(ObjectSkippedReason::SourceOtherError);
+    }
+
+    let mut reader = conn.reader.lock().unwrap();
+
+    let head = match read_head(&mut *reader) {
+        Ok(head) => head,
+        Err(e) => {
+            drop(reader);
+            conn.finish(ticket, false);
+            error!("Request failed: {}: {}", uri, e);
+            return Err(ObjectSkippedReason::SourceOtherError);
+        }
+    };
+
+    autopsy.first_byte_ms = Some(start.elapsed().as_millis() as u64);
+    autopsy.source_addr = conn.peer.clone();
+    autopsy.expected_bytes = match head.framing {
+        Framing::Length(len) => Some(len),
+        _ => None,
+    };
+    autopsy.source_headers = head.headers.clone();
+
+    let reusable = !head.closing && head.framing != Framing::Close;
+    let msg = format!("Download response for {} is {}", uri, head.status);
+
+    // The body of any other response still has to be read past to get to
+    // the responses after it.
+    if head.status != 200 {
+        error!("{}", msg);
+        let drained = copy_body(&mut *reader, head.framing, &mut io::sink());
+        drop(reader);
+        conn.finish(ticket, reusable && drained.is_ok());
+        return Err(ObjectSkippedReason::HTTPStatusCode(head.status));
+    }
+
+    trace!("{}", msg);
+
+    let opened = response_encoding(
+        uri,
+        head.headers.get("content-encoding").map(|v| v.as_str()),
+    )
+    .and_then(|encoding| {
+        autopsy.content_encoding = encoding;
+        Body::open(path, encoding)
+    });
+
+    let mut body = match opened {
+        Ok(body) => body,
+        Err(reason) => {
+            let drained =
+                copy_body(&mut *reader, head.framing, &mut io::sink());
+            drop(reader);
+            conn.finish(ticket, reusable && drained.is_ok());
+            return Err(reason);
+        }
+    };
+
+    let copied = copy_body(&mut *reader, head.framing, &mut body);
+    drop(reader);
+    conn.finish(ticket, reusable && copied.is_ok());
+
+    finish_body(uri, body, copied, autopsy)
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Read a line of the response head, without its line ending.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by source",
+        ));
+    }
+    Ok(line
+        .trim_end_matches(|c| c == '\r' || c == '\n')
+        .to_string())
+}
+
+fn read_head<R: BufRead>(reader: &mut R) -> io::Result<ResponseHead> {
+    let status_line = read_line(reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts
+        .next()
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| invalid(format!("bad status line: {}", status_line)))?;
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        let name = header.next().unwrap_or_default().trim().to_lowercase();
+        let value = header.next().unwrap_or_default().trim().to_string();
+        headers.insert(name, value);
+    }
+
+    let chunked = headers
+        .get("transfer-encoding")
+        .map_or(false, |te| te.to_lowercase().contains("chunked"));
+    let length = headers.get("content-length").map(|cl| cl.parse::<u64>());
+
+    // Responses to GETs that have no body of their own still have no body.
+    let framing = if status == 204 || status == 304 || status < 200 {
+        Framing::Length(0)
+    } else if chunked {
+        Framing::Chunked
+    } else {
+        match length {
+            Some(Ok(len)) => Framing::Length(len),
+            Some(Err(_)) => {
+                return Err(invalid(String::from("bad content-length")))
+            }
+            None => Framing::Close,
+        }
+    };
+
+    let connection = headers
+        .get("connection")
+        .map(|c| c.to_lowercase())
+        .unwrap_or_default();
+    let closing = connection.contains("close")
+        || (version == "HTTP/1.0" && !connection.contains("keep-alive"));
+
+    Ok(ResponseHead {
+        status,
+        headers,
+        framing,
+        closing,
+    })
+}
+
+// Copy up to `len` bytes of the body (or all of it, if `len` is None) to
+// `dest`.  Returns the number of bytes copied, which is only short of `len`
+// if the source closed the connection.
+fn copy_exact<R: Read, W: Write>(
+    reader: &mut R,
+    len: Option<u64>,
+    dest: &mut W,
+) -> Result<u64, BodyError> {
+    let mut buf = vec![0; COPY_BUFFER_BYTES];
+    let mut copied = 0;
+
+    loop {
+        let want = match len {
+            Some(len) if copied >= len => break,
+            Some(len) => (len - copied).min(buf.len() as u64) as usize,
+            None => buf.len(),
+        };
+
+        let n = match reader.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(BodyError::Source(e)),
+        };
+
+        dest.write_all(&buf[..n]).map_err(BodyError::Dest)?;
+        copied += n as u64;
+    }
+
+    Ok(copied)
+}
+
+fn copy_body<R: BufRead, W: Write>(
+    reader: &mut R,
+    framing: Framing,
+    dest: &mut W,
+) -> Result<u64, BodyError> {
+    let short = || {
+        BodyError::Source(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by source",
+        ))
+    };
+
+    match framing {
+        Framing::Length(len) => {
+            let copied = copy_exact(reader, Some(len), dest)?;
+            if copied < len {
+                return Err(short());
+            }
+            Ok(copied)
+        }
+        Framing::Close => copy_exact(reader, None, dest),
+        Framing::Chunked => {
+            let mut copied = 0;
+            loop {
+                let line = read_line(reader).map_err(BodyError::Source)?;
+                let size = line.split(';').next().unwrap_or_default().trim();
+                let size = u64::from_str_radix(size, 16).map_err(|_| {
+                    BodyError::Source(invalid(format!("bad chunk: {}", line)))
+                })?;
+
+                // The last chunk is followed by any trailers, which are
+                // skipped.
+                if size == 0 {
+                    loop {
+                        let trailer =
+                            read_line(reader).map_err(BodyError::Source)?;
+                        if trailer.is_empty() {
+                            return Ok(copied);
+                        }
+                    }
+                }
+
+                if copy_exact(reader, Some(size), dest)? < size {
+                    return Err(short());
+                }
+                copied += size;
+                read_line(reader).map_err(BodyError::Source)?;
+            }
+        }
+    }
+}
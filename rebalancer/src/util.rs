@@ -9,15 +9,40 @@
  */
 
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use clap::{crate_name, crate_version};
-use slog::{o, Drain, Level, LevelFilter, Logger};
+use slog::{o, Drain, Level, LevelFilter, Logger, OwnedKVList, Record};
 
 pub static MIN_HTTP_STATUS_CODE: u16 = 100;
 pub static MAX_HTTP_STATUS_CODE: u16 = 600;
 
+// The level of the global logger, which can be changed while it is in use.
+static GLOBAL_LOG_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+// A LevelFilter whose level is GLOBAL_LOG_LEVEL at the time of each record.
+struct GlobalLevelFilter<D: Drain>(D);
+
+impl<D: Drain> Drain for GlobalLevelFilter<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(log_level()) {
+            self.0.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
 pub fn create_bunyan_logger<W>(io: W, level: Level) -> Logger
 where
     W: io::Write + std::marker::Send + 'static,
@@ -41,10 +66,39 @@ pub fn init_global_logger(
         level = l;
     }
 
-    let log = create_bunyan_logger(std::io::stdout(), level);
+    set_log_level(level);
+
+    let log = Logger::root(
+        Mutex::new(GlobalLevelFilter(
+            slog_bunyan::with_name(crate_name!(), std::io::stdout()).build(),
+        ))
+        .fuse(),
+        o!("build-id" => crate_version!()),
+    );
     slog_scope::set_global_logger(log)
 }
 
+/// The level of the global logger.
+pub fn log_level() -> Level {
+    Level::from_usize(GLOBAL_LOG_LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(Level::Trace)
+}
+
+/// Change the level of the global logger.  This takes effect immediately
+/// for every thread.
+pub fn set_log_level(level: Level) {
+    GLOBAL_LOG_LEVEL.store(level.as_usize(), Ordering::Relaxed);
+}
+
+/// Milliseconds since the epoch, as times are recorded in the databases and
+/// reports of the manager and agent.
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
 // If no name just print the crate name with the thread ID like so:
 //      "<crate_name>[ThreadId(<tid>)]: <log msg>"
 // Otherwise add the thread name as well.
@@ -110,7 +164,12 @@ macro_rules! crit(
     };
 );
 
+/// Returns the shard number that a shard host name starts with, e.g. 1 for
+/// "1.moray.us-east.joyent.us", if it starts with one.
+pub fn parse_shard_num(shard_host: &str) -> Option<u32> {
+    shard_host.split('.').next()?.parse().ok()
+}
+
 pub fn shard_host2num(shard_host: &str) -> u32 {
-    let shard_split: Vec<&str> = shard_host.split('.').collect();
-    shard_split[0].parse().unwrap()
+    parse_shard_num(shard_host).expect("shard number")
 }